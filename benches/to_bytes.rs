@@ -0,0 +1,125 @@
+//! Compare `measurement::to_bytes_pooled`, which reuses a thread local FlatBufferBuilder, with `Measurement::to_bytes`
+//!
+//! Run with `cargo bench --bench to_bytes`. Both serialize the same measurement with
+//! `FlatBufferMeasurement::serialize_into`, `to_bytes` into a new builder every time and `to_bytes_pooled` into the
+//! reused one.
+
+use chrono::{DateTime, Utc};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use flatbuffers::FlatBufferBuilder;
+
+use opensensor::measurement::{
+    to_bytes_pooled, ClockSkew, FlatBufferCodec, FlatBufferMeasurement, Measurement,
+    MeasurementError,
+};
+use opensensor::reflection_generated::reflection;
+
+/// Measurement with a payload about the size of a radar plot, stored as a reflection `KeyValue`
+#[derive(Clone)]
+struct BenchMeasurement {
+    source_id: String,
+    timestamp: DateTime<Utc>,
+    readings: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum BenchError {
+    #[error("Kafka payload was empty")]
+    EmptyPayload,
+    #[error("Expected schema version {expected}, got {found}")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("{0}")]
+    ClockSkew(ClockSkew),
+    #[error("Invalid flatbuffer {0}")]
+    Flatbuffer(#[from] flatbuffers::InvalidFlatbuffer),
+}
+
+impl MeasurementError for BenchError {
+    fn empty_payload_error() -> Self {
+        BenchError::EmptyPayload
+    }
+
+    fn version_mismatch_error(expected: u32, found: u32) -> Self {
+        BenchError::VersionMismatch { expected, found }
+    }
+
+    fn clock_skew_error(skew: ClockSkew) -> Self {
+        BenchError::ClockSkew(skew)
+    }
+}
+
+impl FlatBufferMeasurement for BenchMeasurement {
+    type Error = BenchError;
+
+    fn serialize_into(self, fbb: &mut FlatBufferBuilder) {
+        let key = fbb.create_string(&self.source_id);
+        let value = fbb.create_string(&self.readings);
+        let offset = reflection::KeyValue::create(
+            fbb,
+            &reflection::KeyValueArgs {
+                key: Some(key),
+                value: Some(value),
+            },
+        );
+        fbb.finish_minimal(offset);
+    }
+
+    fn from_flatbuffer(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let kv = flatbuffers::root::<reflection::KeyValue>(bytes)?;
+
+        Ok(BenchMeasurement {
+            source_id: kv.key().to_owned(),
+            timestamp: Utc::now(),
+            readings: kv.value().unwrap_or_default().to_owned(),
+        })
+    }
+}
+
+impl<'a> Measurement<'a> for BenchMeasurement {
+    type Error = BenchError;
+    type Codec = FlatBufferCodec;
+
+    const TOPIC_NAME: &'static str = "raw.bench.bench-measurement";
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn source_id(&self) -> &str {
+        &self.source_id
+    }
+}
+
+fn bench_to_bytes(c: &mut Criterion) {
+    let measurement = BenchMeasurement {
+        source_id: "radar-1".to_owned(),
+        timestamp: Utc::now(),
+        readings: (0..128)
+            .map(|i| format!("{:.3},", i as f64 * 0.125))
+            .collect(),
+    };
+    assert_eq!(
+        to_bytes_pooled(measurement.clone()),
+        measurement.clone().to_bytes()
+    );
+
+    let mut group = c.benchmark_group("to_bytes");
+    group.bench_function("to_bytes", |b| {
+        b.iter_batched(
+            || measurement.clone(),
+            |m| black_box(m.to_bytes()),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("to_bytes_pooled", |b| {
+        b.iter_batched(
+            || measurement.clone(),
+            |m| black_box(to_bytes_pooled(m)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_to_bytes);
+criterion_main!(benches);
@@ -1,7 +1,10 @@
 use std::path::Path;
 
 fn build_flatbuffers() {
-    let flatbuffer_paths = [Path::new("flatbuffers/reflection.fbs")];
+    let flatbuffer_paths = [
+        Path::new("flatbuffers/reflection.fbs"),
+        Path::new("flatbuffers/archive.fbs"),
+    ];
     flatc_rust::run(flatc_rust::Args {
         inputs: &flatbuffer_paths,
         out_dir: Path::new("src"),
@@ -0,0 +1,305 @@
+// automatically generated by the FlatBuffers compiler, do not modify
+
+
+// @generated
+
+use core::mem;
+use core::cmp::Ordering;
+
+extern crate flatbuffers;
+use self::flatbuffers::{EndianScalar, Follow};
+
+#[allow(unused_imports, dead_code)]
+pub mod archive {
+
+  use core::mem;
+  use core::cmp::Ordering;
+
+  extern crate flatbuffers;
+  use self::flatbuffers::{EndianScalar, Follow};
+
+pub enum ArchivedMeasurementOffset {}
+#[derive(Copy, Clone, PartialEq)]
+
+/// A single measurement, stored as the serialized flatbuffer produced by Measurement::to_bytes
+pub struct ArchivedMeasurement<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for ArchivedMeasurement<'a> {
+  type Inner = ArchivedMeasurement<'a>;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    Self { _tab: flatbuffers::Table::new(buf, loc) }
+  }
+}
+
+impl<'a> ArchivedMeasurement<'a> {
+  pub const VT_DATA: flatbuffers::VOffsetT = 4;
+
+  #[inline]
+  pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+    ArchivedMeasurement { _tab: table }
+  }
+  #[allow(unused_mut)]
+  pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+    _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+    args: &'args ArchivedMeasurementArgs<'args>
+  ) -> flatbuffers::WIPOffset<ArchivedMeasurement<'bldr>> {
+    let mut builder = ArchivedMeasurementBuilder::new(_fbb);
+    if let Some(x) = args.data { builder.add_data(x); }
+    builder.finish()
+  }
+
+
+  /// Finished flatbuffer bytes of the measurement
+  #[inline]
+  pub fn data(&self) -> flatbuffers::Vector<'a, u8> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, u8>>>(ArchivedMeasurement::VT_DATA, None).unwrap()}
+  }
+}
+
+impl flatbuffers::Verifiable for ArchivedMeasurement<'_> {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    v.visit_table(pos)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, u8>>>("data", Self::VT_DATA, true)?
+     .finish();
+    Ok(())
+  }
+}
+pub struct ArchivedMeasurementArgs<'a> {
+    pub data: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, u8>>>,
+}
+impl<'a> Default for ArchivedMeasurementArgs<'a> {
+  #[inline]
+  fn default() -> Self {
+    ArchivedMeasurementArgs {
+      data: None, // required field
+    }
+  }
+}
+
+pub struct ArchivedMeasurementBuilder<'a: 'b, 'b> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> ArchivedMeasurementBuilder<'a, 'b> {
+  #[inline]
+  pub fn add_data(&mut self, data: flatbuffers::WIPOffset<flatbuffers::Vector<'b , u8>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(ArchivedMeasurement::VT_DATA, data);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> ArchivedMeasurementBuilder<'a, 'b> {
+    let start = _fbb.start_table();
+    ArchivedMeasurementBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<ArchivedMeasurement<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    self.fbb_.required(o, ArchivedMeasurement::VT_DATA,"data");
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+impl core::fmt::Debug for ArchivedMeasurement<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let mut ds = f.debug_struct("ArchivedMeasurement");
+      ds.field("data", &self.data());
+      ds.finish()
+  }
+}
+pub enum ArchiveChunkOffset {}
+#[derive(Copy, Clone, PartialEq)]
+
+/// A chunk of measurements consumed from a single Redpanda topic
+pub struct ArchiveChunk<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for ArchiveChunk<'a> {
+  type Inner = ArchiveChunk<'a>;
+  #[inline]
+  unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+    Self { _tab: flatbuffers::Table::new(buf, loc) }
+  }
+}
+
+impl<'a> ArchiveChunk<'a> {
+  pub const VT_MEASUREMENTS: flatbuffers::VOffsetT = 4;
+
+  #[inline]
+  pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+    ArchiveChunk { _tab: table }
+  }
+  #[allow(unused_mut)]
+  pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+    _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+    args: &'args ArchiveChunkArgs<'args>
+  ) -> flatbuffers::WIPOffset<ArchiveChunk<'bldr>> {
+    let mut builder = ArchiveChunkBuilder::new(_fbb);
+    if let Some(x) = args.measurements { builder.add_measurements(x); }
+    builder.finish()
+  }
+
+
+  /// Measurements in the chunk
+  #[inline]
+  pub fn measurements(&self) -> flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<ArchivedMeasurement<'a>>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<ArchivedMeasurement>>>>(ArchiveChunk::VT_MEASUREMENTS, None).unwrap()}
+  }
+}
+
+impl flatbuffers::Verifiable for ArchiveChunk<'_> {
+  #[inline]
+  fn run_verifier(
+    v: &mut flatbuffers::Verifier, pos: usize
+  ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+    use self::flatbuffers::Verifiable;
+    v.visit_table(pos)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<ArchivedMeasurement>>>>("measurements", Self::VT_MEASUREMENTS, true)?
+     .finish();
+    Ok(())
+  }
+}
+pub struct ArchiveChunkArgs<'a> {
+    pub measurements: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<ArchivedMeasurement<'a>>>>>,
+}
+impl<'a> Default for ArchiveChunkArgs<'a> {
+  #[inline]
+  fn default() -> Self {
+    ArchiveChunkArgs {
+      measurements: None, // required field
+    }
+  }
+}
+
+pub struct ArchiveChunkBuilder<'a: 'b, 'b> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> ArchiveChunkBuilder<'a, 'b> {
+  #[inline]
+  pub fn add_measurements(&mut self, measurements: flatbuffers::WIPOffset<flatbuffers::Vector<'b , flatbuffers::ForwardsUOffset<ArchivedMeasurement<'b >>>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(ArchiveChunk::VT_MEASUREMENTS, measurements);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> ArchiveChunkBuilder<'a, 'b> {
+    let start = _fbb.start_table();
+    ArchiveChunkBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<ArchiveChunk<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    self.fbb_.required(o, ArchiveChunk::VT_MEASUREMENTS,"measurements");
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+impl core::fmt::Debug for ArchiveChunk<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let mut ds = f.debug_struct("ArchiveChunk");
+      ds.field("measurements", &self.measurements());
+      ds.finish()
+  }
+}
+#[inline]
+/// Verifies that a buffer of bytes contains a `ArchiveChunk`
+/// and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_archive_chunk_unchecked`.
+pub fn root_as_archive_chunk(buf: &[u8]) -> Result<ArchiveChunk, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::root::<ArchiveChunk>(buf)
+}
+#[inline]
+/// Verifies that a buffer of bytes contains a size prefixed
+/// `ArchiveChunk` and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `size_prefixed_root_as_archive_chunk_unchecked`.
+pub fn size_prefixed_root_as_archive_chunk(buf: &[u8]) -> Result<ArchiveChunk, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::size_prefixed_root::<ArchiveChunk>(buf)
+}
+#[inline]
+/// Verifies, with the given options, that a buffer of bytes
+/// contains a `ArchiveChunk` and returns it.
+/// Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_archive_chunk_unchecked`.
+pub fn root_as_archive_chunk_with_opts<'b, 'o>(
+  opts: &'o flatbuffers::VerifierOptions,
+  buf: &'b [u8],
+) -> Result<ArchiveChunk<'b>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::root_with_opts::<ArchiveChunk<'b>>(opts, buf)
+}
+#[inline]
+/// Verifies, with the given verifier options, that a buffer of
+/// bytes contains a size prefixed `ArchiveChunk` and returns
+/// it. Note that verification is still experimental and may not
+/// catch every error, or be maximally performant. For the
+/// previous, unchecked, behavior use
+/// `root_as_archive_chunk_unchecked`.
+pub fn size_prefixed_root_as_archive_chunk_with_opts<'b, 'o>(
+  opts: &'o flatbuffers::VerifierOptions,
+  buf: &'b [u8],
+) -> Result<ArchiveChunk<'b>, flatbuffers::InvalidFlatbuffer> {
+  flatbuffers::size_prefixed_root_with_opts::<ArchiveChunk<'b>>(opts, buf)
+}
+#[inline]
+/// Assumes, without verification, that a buffer of bytes contains a ArchiveChunk and returns it.
+/// # Safety
+/// Callers must trust the given bytes do indeed contain a valid `ArchiveChunk`.
+pub unsafe fn root_as_archive_chunk_unchecked(buf: &[u8]) -> ArchiveChunk {
+  flatbuffers::root_unchecked::<ArchiveChunk>(buf)
+}
+#[inline]
+/// Assumes, without verification, that a buffer of bytes contains a size prefixed ArchiveChunk and returns it.
+/// # Safety
+/// Callers must trust the given bytes do indeed contain a valid size prefixed `ArchiveChunk`.
+pub unsafe fn size_prefixed_root_as_archive_chunk_unchecked(buf: &[u8]) -> ArchiveChunk {
+  flatbuffers::size_prefixed_root_unchecked::<ArchiveChunk>(buf)
+}
+pub const ARCHIVE_CHUNK_IDENTIFIER: &str = "OSAR";
+
+#[inline]
+pub fn archive_chunk_buffer_has_identifier(buf: &[u8]) -> bool {
+  flatbuffers::buffer_has_identifier(buf, ARCHIVE_CHUNK_IDENTIFIER, false)
+}
+
+#[inline]
+pub fn archive_chunk_size_prefixed_buffer_has_identifier(buf: &[u8]) -> bool {
+  flatbuffers::buffer_has_identifier(buf, ARCHIVE_CHUNK_IDENTIFIER, true)
+}
+
+#[inline]
+pub fn finish_archive_chunk_buffer<'a, 'b>(
+    fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+    root: flatbuffers::WIPOffset<ArchiveChunk<'a>>) {
+  fbb.finish(root, Some(ARCHIVE_CHUNK_IDENTIFIER));
+}
+
+#[inline]
+pub fn finish_size_prefixed_archive_chunk_buffer<'a, 'b>(fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>, root: flatbuffers::WIPOffset<ArchiveChunk<'a>>) {
+  fbb.finish_size_prefixed(root, Some(ARCHIVE_CHUNK_IDENTIFIER));
+}
+}  // pub mod archive
+
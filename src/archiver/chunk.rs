@@ -0,0 +1,787 @@
+//! In-memory buffering of archive chunks
+//!
+//! The archiver either buffers every measurement on a topic into a single chunk, or (with `--split-by-source`)
+//! keeps a separate chunk per `source_id` so each sensor instance's data ends up under its own object key prefix.
+//!
+//! Chunks are serialized as a `batch::MeasurementBatch`, an `ArchiveChunk` flatbuffer (see `flatbuffers/archive.fbs`)
+//! which stores each measurement as the finished flatbuffer bytes from `Measurement::to_bytes`. Flatbuffers can't be
+//! larger than 2GB, so chunks are also flushed before their serialized size reaches `MAX_CHUNK_BYTES`, whatever the
+//! chunk size.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+use flatbuffers::FlatBufferBuilder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::archiver::error::ArchiveError;
+use crate::archiver::format::chunk_bytes;
+use crate::batch::{MeasurementBatch, RECORD_OVERHEAD};
+use crate::measurement::Measurement;
+
+/// Largest serialized chunk the archiver will build, in bytes
+///
+/// Flatbuffers use 32 bit offsets, so `FlatBufferBuilder` panics past 2GB (`batch::MAX_BATCH_BYTES`). Chunks are
+/// flushed well before that, leaving headroom for the `ArchiveChunk` tables wrapping each measurement.
+pub const MAX_CHUNK_BYTES: usize = 1_900_000_000;
+
+/// Running serialized size of a chunk as measurements are buffered, for flushing it before it's too large
+///
+/// Each measurement counts as its `Measurement::to_bytes` length plus the space the `ArchiveChunk` needs to store it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkBytes {
+    max_bytes: usize,
+    bytes: usize,
+}
+
+impl Default for ChunkBytes {
+    fn default() -> Self {
+        ChunkBytes::new(MAX_CHUNK_BYTES)
+    }
+}
+
+impl ChunkBytes {
+    /// Empty chunk that can hold up to `max_bytes`
+    pub fn new(max_bytes: usize) -> Self {
+        ChunkBytes {
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    /// Serialized size of the measurements counted so far
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether a measurement of `len` serialized bytes can be added without going over the limit
+    pub fn fits(&self, len: usize) -> bool {
+        self.bytes
+            .saturating_add(len)
+            .saturating_add(RECORD_OVERHEAD)
+            <= self.max_bytes
+    }
+
+    /// Count a measurement of `len` serialized bytes
+    pub fn add(&mut self, len: usize) {
+        self.bytes = self
+            .bytes
+            .saturating_add(len)
+            .saturating_add(RECORD_OVERHEAD);
+    }
+
+    /// Whether the measurements counted so far add up to at least `flush_bytes`, the size chunks are flushed at
+    /// with `--max-chunk-bytes`
+    pub fn reached(&self, flush_bytes: usize) -> bool {
+        self.bytes >= flush_bytes
+    }
+
+    /// Start counting a new, empty chunk
+    pub fn reset(&mut self) {
+        self.bytes = 0;
+    }
+
+    /// Check the chunk is still under the limit
+    ///
+    /// # Errors
+    ///
+    /// - ArchiveError::ChunkTooLarge: If the measurements counted are over the limit
+    pub fn check(&self) -> Result<(), ArchiveError> {
+        if self.bytes > self.max_bytes {
+            return Err(ArchiveError::ChunkTooLarge { bytes: self.bytes });
+        }
+        Ok(())
+    }
+}
+
+/// Serialize a chunk of measurements into a single `ArchiveChunk` flatbuffer
+///
+/// Measurements are stored in the order they're given, so a chunk preserves consumption order unless it was sorted
+/// with `sort_chunk` first. Chunks are `MeasurementBatch`es, see `batch` for the format.
+///
+/// # Errors
+///
+/// - ArchiveError::ChunkTooLarge: If the chunk would be over `MAX_CHUNK_BYTES`
+pub fn serialize_chunk<M>(measurements: Vec<M>) -> Result<FlatBufferBuilder<'static>, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let mut batch = MeasurementBatch::<M>::new();
+    let mut chunk_bytes = ChunkBytes::default();
+    for measurement in measurements {
+        let record = measurement.to_bytes();
+        chunk_bytes.add(record.len());
+        chunk_bytes.check()?;
+        batch.push_bytes(&record)?;
+    }
+
+    Ok(batch.into())
+}
+
+/// Serialize measurements that are already serialized with `Measurement::to_bytes` into an `ArchiveChunk` flatbuffer
+///
+/// Records are stored in the order they're given. Their total size is checked before anything is serialized.
+///
+/// # Errors
+///
+/// - ArchiveError::ChunkTooLarge: If the chunk would be over `MAX_CHUNK_BYTES`
+pub fn serialize_records<M, I>(records: I) -> Result<FlatBufferBuilder<'static>, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let records: Vec<I::Item> = records.into_iter().collect();
+    let mut chunk_bytes = ChunkBytes::default();
+    for record in &records {
+        chunk_bytes.add(record.as_ref().len());
+    }
+    chunk_bytes.check()?;
+
+    let mut batch = MeasurementBatch::<M>::new();
+    for record in &records {
+        batch.push_bytes(record.as_ref())?;
+    }
+
+    Ok(batch.into())
+}
+
+/// Deserialize the measurements stored in an `ArchiveChunk` flatbuffer, bare or in an archive container
+///
+/// # Errors
+///
+/// - ArchiveError::FormatError: If `data` is an archive container that can't be read (see `format::chunk_bytes`)
+/// - ArchiveError::InvalidChunk: If `data` isn't a valid `ArchiveChunk`
+/// - ArchiveError::DeserializeError: If any stored measurement fails `Measurement::from_bytes`
+pub fn deserialize_chunk<M>(data: &[u8]) -> Result<Vec<M>, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let data = chunk_bytes(data)?;
+    let measurements = MeasurementBatch::<M>::from_bytes(&data)?.collect::<Result<_, _>>()?;
+    Ok(measurements)
+}
+
+/// Collapse runs of equal-value records per key into the first record of each run
+///
+/// Meant for state (compacted) topics, where a record only matters if it changes the value for its key. A record is
+/// dropped when it's equal (by `content_eq`) to the previous kept record for the same key; records for other keys in
+/// between don't break the run. Returns the kept records, in their original order, and the number dropped.
+///
+/// This is value-based and independent of count/time chunking. See `compact_chunk` for compacting consumed
+/// Measurements by their `partition_key` and `content_eq`.
+pub fn compact_equal_runs<T, K, F, E>(
+    records: Vec<T>,
+    mut compaction_key: F,
+    mut content_eq: E,
+) -> (Vec<T>, usize)
+where
+    K: Eq + Hash,
+    F: FnMut(&T) -> K,
+    E: FnMut(&T, &T) -> bool,
+{
+    let mut kept: Vec<T> = Vec::with_capacity(records.len());
+    // Index into `kept` of the last record kept for each key
+    let mut last_kept: HashMap<K, usize> = HashMap::new();
+    let mut dropped = 0;
+
+    for record in records {
+        let key = compaction_key(&record);
+        if let Some(&i) = last_kept.get(&key) {
+            if content_eq(&kept[i], &record) {
+                dropped += 1;
+                continue;
+            }
+        }
+        last_kept.insert(key, kept.len());
+        kept.push(record);
+    }
+
+    (kept, dropped)
+}
+
+/// Allow-list of measurement `source_id`s to archive, set with the archiver's `--source-ids` option
+///
+/// The default filter allows every source.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceFilter {
+    /// Allowed source ids, None to allow every source
+    source_ids: Option<HashSet<String>>,
+}
+
+impl SourceFilter {
+    /// Filter that only allows the given source ids
+    pub fn only<I, S>(source_ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        SourceFilter {
+            source_ids: Some(source_ids.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// Whether measurements from `source_id` should be archived
+    pub fn allows(&self, source_id: &str) -> bool {
+        match &self.source_ids {
+            Some(source_ids) => source_ids.contains(source_id),
+            None => true,
+        }
+    }
+}
+
+/// A measurement along with where it was consumed from
+#[derive(Debug, PartialEq, Eq)]
+pub struct Consumed<M> {
+    /// The deserialized measurement
+    pub measurement: M,
+    /// Kafka partition the measurement was read from
+    pub partition: i32,
+    /// Offset of the measurement within its partition
+    pub offset: i64,
+}
+
+/// Range of Kafka offsets covered by a chunk within a single partition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct OffsetRange {
+    /// Kafka partition
+    pub partition: i32,
+    /// Lowest offset in the chunk for this partition
+    pub first_offset: i64,
+    /// Highest offset in the chunk for this partition
+    pub last_offset: i64,
+}
+
+/// Offset range covered by a chunk in each partition it has measurements from, ordered by partition
+pub fn offset_ranges<M>(items: &[Consumed<M>]) -> Vec<OffsetRange> {
+    let mut ranges: BTreeMap<i32, OffsetRange> = BTreeMap::new();
+    for item in items {
+        ranges
+            .entry(item.partition)
+            .and_modify(|range| {
+                range.first_offset = range.first_offset.min(item.offset);
+                range.last_offset = range.last_offset.max(item.offset);
+            })
+            .or_insert(OffsetRange {
+                partition: item.partition,
+                first_offset: item.offset,
+                last_offset: item.offset,
+            });
+    }
+
+    ranges.into_values().collect()
+}
+
+/// Offsets that are safe to commit in each partition of the archived topic
+///
+/// A partition can only be committed up to its earliest measurement still buffered in an open chunk. Committing
+/// the consumer's position instead would cover measurements buffered for other chunks (i.e. other sources' chunks
+/// with `--split-by-source`), which are lost if the archiver crashes or the partition is reassigned before they're
+/// uploaded. Partitions with nothing buffered are safe up to the last message consumed from them, so skipped and
+/// dead lettered messages are committed too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartitionOffsets {
+    /// One past the highest offset consumed in each partition
+    next: BTreeMap<i32, i64>,
+    /// Offsets of the measurements buffered in open chunks in each partition, with how many are buffered at each
+    buffered: BTreeMap<i32, BTreeMap<i64, usize>>,
+    /// Offset last committed in each partition
+    committed: BTreeMap<i32, i64>,
+}
+
+impl PartitionOffsets {
+    /// Tracker that hasn't seen any messages
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a message consumed from `partition`, whether it's buffered, skipped, or sent to the dead letter topic
+    pub fn consumed(&mut self, partition: i32, offset: i64) {
+        let next = self.next.entry(partition).or_insert(offset + 1);
+        *next = (*next).max(offset + 1);
+    }
+
+    /// Record a measurement buffered in an open chunk, holding back its partition's offset until it's archived
+    pub fn buffered(&mut self, partition: i32, offset: i64) {
+        self.consumed(partition, offset);
+        *self
+            .buffered
+            .entry(partition)
+            .or_default()
+            .entry(offset)
+            .or_insert(0) += 1;
+    }
+
+    /// Record the measurements of an uploaded chunk as archived
+    pub fn archived(&mut self, records: &[RecordOffset]) {
+        for item in records {
+            if let Some(buffered) = self.buffered.get_mut(&item.partition) {
+                if let Some(count) = buffered.get_mut(&item.offset) {
+                    *count -= 1;
+                    if *count == 0 {
+                        buffered.remove(&item.offset);
+                    }
+                }
+                if buffered.is_empty() {
+                    self.buffered.remove(&item.partition);
+                }
+            }
+        }
+    }
+
+    /// Offset that's safe to commit in `partition`, the next offset to consume from it once everything before is
+    /// archived, or None if nothing's been consumed from it
+    pub fn safe_offset(&self, partition: i32) -> Option<i64> {
+        let next = *self.next.get(&partition)?;
+        let earliest_buffered = self
+            .buffered
+            .get(&partition)
+            .and_then(|buffered| buffered.keys().next());
+        Some(earliest_buffered.map_or(next, |&offset| offset.min(next)))
+    }
+
+    /// (partition, offset) for every partition whose safe offset is past its last committed offset, ordered by
+    /// partition
+    pub fn uncommitted(&self) -> Vec<(i32, i64)> {
+        self.next
+            .keys()
+            .filter_map(|&partition| {
+                let offset = self.safe_offset(partition)?;
+                match self.committed.get(&partition) {
+                    Some(&committed) if committed >= offset => None,
+                    _ => Some((partition, offset)),
+                }
+            })
+            .collect()
+    }
+
+    /// Record offsets from `uncommitted` as committed
+    pub fn committed(&mut self, offsets: &[(i32, i64)]) {
+        for &(partition, offset) in offsets {
+            self.committed.insert(partition, offset);
+        }
+    }
+}
+
+/// How to order the measurements within a chunk before it's serialized, set with `--sort-chunk-by`
+///
+/// Chunks are stored in consumption order by default. Sorting groups similar measurements together, which can
+/// shrink the compressed chunk a lot, at the cost of losing consumption order within the chunk (see `SortedChunk`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[cfg_attr(
+    feature = "json",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum ChunkSort {
+    /// Sort by `Measurement::sort_key`, the `source_id` then timestamp by default
+    SortKey,
+    /// Sort by `Measurement::timestamp`
+    Timestamp,
+}
+
+/// Kafka partition and offset a single stored measurement was consumed from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordOffset {
+    /// Kafka partition
+    pub partition: i32,
+    /// Offset of the measurement within its partition
+    pub offset: i64,
+}
+
+/// How `sort_chunk` reordered a chunk, so consumption order can be recovered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortedChunk {
+    /// Order the chunk was sorted by
+    pub sort: ChunkSort,
+    /// Where each measurement was consumed from, in stored (sorted) order
+    pub record_offsets: Vec<RecordOffset>,
+    /// Stored position of each measurement, in consumption order
+    pub stored_positions: Vec<usize>,
+}
+
+/// Collapse runs of `Measurement::content_eq` measurements per `Measurement::partition_key` in a consumed chunk
+///
+/// Returns the kept measurements, in consumption order, and the number dropped (see `compact_equal_runs`).
+pub fn compact_chunk<M>(items: Vec<Consumed<M>>) -> (Vec<Consumed<M>>, usize)
+where
+    M: for<'a> Measurement<'a>,
+{
+    compact_equal_runs(
+        items,
+        |c| c.measurement.partition_key().into_owned(),
+        |kept, c| kept.measurement.content_eq(&c.measurement),
+    )
+}
+
+/// Sort a chunk of consumed measurements, recording where each one was consumed from
+///
+/// The sort is stable, so measurements with equal keys keep their consumption order.
+pub fn sort_chunk<M>(items: Vec<Consumed<M>>, sort: ChunkSort) -> (Vec<Consumed<M>>, SortedChunk)
+where
+    M: for<'a> Measurement<'a>,
+{
+    let mut indexed: Vec<(usize, Consumed<M>)> = items.into_iter().enumerate().collect();
+    match sort {
+        ChunkSort::SortKey => indexed.sort_by_cached_key(|(_, c)| c.measurement.sort_key()),
+        ChunkSort::Timestamp => indexed.sort_by_key(|(_, c)| c.measurement.timestamp()),
+    }
+
+    let mut stored_positions = vec![0; indexed.len()];
+    let mut record_offsets = Vec::with_capacity(indexed.len());
+    let mut sorted = Vec::with_capacity(indexed.len());
+    for (stored, (consumed, item)) in indexed.into_iter().enumerate() {
+        stored_positions[consumed] = stored;
+        record_offsets.push(RecordOffset {
+            partition: item.partition,
+            offset: item.offset,
+        });
+        sorted.push(item);
+    }
+
+    (
+        sorted,
+        SortedChunk {
+            sort,
+            record_offsets,
+            stored_positions,
+        },
+    )
+}
+
+/// Suffix of the sidecar object holding a sorted chunk's record offsets, appended to the chunk's key
+///
+/// Written alongside every sorted chunk, with or without the `json` feature, so consumption order can always be
+/// recovered (see `read_sorted_chunk`). The chunk's `Manifest::record_offsets` hold the same offsets.
+pub const RECORD_OFFSETS_SUFFIX: &str = ".offsets";
+
+/// Bytes each record offset takes in a record offsets sidecar
+const RECORD_OFFSET_LEN: usize = 12;
+
+/// Key of the record offsets sidecar of the chunk at `key`
+pub fn record_offsets_key(key: &str) -> String {
+    format!("{}{}", key, RECORD_OFFSETS_SUFFIX)
+}
+
+/// Encode record offsets for a record offsets sidecar, as a little-endian i32 partition and i64 offset per record
+pub fn encode_record_offsets(record_offsets: &[RecordOffset]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(record_offsets.len() * RECORD_OFFSET_LEN);
+    for record in record_offsets {
+        bytes.extend_from_slice(&record.partition.to_le_bytes());
+        bytes.extend_from_slice(&record.offset.to_le_bytes());
+    }
+    bytes
+}
+
+/// Decode the record offsets in a record offsets sidecar written with `encode_record_offsets`
+///
+/// # Errors
+///
+/// - ArchiveError::OrderError: If `bytes` isn't a whole number of record offsets
+pub fn decode_record_offsets(bytes: &[u8]) -> Result<Vec<RecordOffset>, ArchiveError> {
+    if bytes.len() % RECORD_OFFSET_LEN != 0 {
+        return Err(ArchiveError::OrderError(format!(
+            "{} byte record offsets sidecar isn't a multiple of {} bytes",
+            bytes.len(),
+            RECORD_OFFSET_LEN
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(RECORD_OFFSET_LEN)
+        .map(|record| RecordOffset {
+            partition: i32::from_le_bytes(record[..4].try_into().unwrap()),
+            offset: i64::from_le_bytes(record[4..].try_into().unwrap()),
+        })
+        .collect())
+}
+
+/// Put the measurements of a sorted chunk back in the order they were consumed from each partition
+///
+/// `record_offsets` are the offsets recorded for the chunk when it was sorted, in stored order (see
+/// `decode_record_offsets` and `Manifest::record_offsets`). Measurements are ordered by partition, then offset. Kafka only orders messages within
+/// a partition, so how partitions were interleaved when the chunk was consumed isn't recovered.
+///
+/// # Errors
+///
+/// - ArchiveError::OrderError: If there isn't exactly one offset per measurement
+pub fn restore_consumption_order<T>(
+    items: Vec<T>,
+    record_offsets: &[RecordOffset],
+) -> Result<Vec<T>, ArchiveError> {
+    if items.len() != record_offsets.len() {
+        return Err(ArchiveError::OrderError(format!(
+            "{} record offsets for {} measurements",
+            record_offsets.len(),
+            items.len()
+        )));
+    }
+
+    let mut items: Vec<(RecordOffset, T)> = record_offsets.iter().copied().zip(items).collect();
+    items.sort_by_key(|(offset, _)| *offset);
+
+    Ok(items.into_iter().map(|(_, item)| item).collect())
+}
+
+/// A per-source chunk that is ready to be serialized and uploaded
+#[derive(Debug, PartialEq, Eq)]
+pub struct FullChunk<T> {
+    /// Source the buffered measurements were read from
+    pub source_id: String,
+    /// Buffered measurements, in the order they were consumed
+    pub items: Vec<T>,
+}
+
+/// Number of messages the next archive chunk should hold, given the consumer lag when the last chunk was flushed
+///
+/// While the archiver is behind, chunks grow to cover the backlog (up to `max`), so a backfill is written as a few
+/// large objects rather than a flood of small ones. Once it has caught up, chunks shrink back to `min` so
+/// measurements are archived soon after they're produced. If `min` is larger than `max`, `min` wins, and the size
+/// is never less than 1.
+pub fn next_chunk_size(current_lag: u64, min: u64, max: u64) -> u64 {
+    current_lag.min(max).max(min).max(1)
+}
+
+/// Per-source archive chunks with a bound on how many sources can have an open chunk at once
+///
+/// Every source gets its own chunk that is flushed when it reaches `chunk_size` items, or when it would grow past
+/// `MAX_CHUNK_BYTES` if measurements are added with `push_sized`. With `with_flush_bytes`, sized chunks are also
+/// flushed once they reach that many bytes, whichever comes first. Because the set of sources on
+/// a topic isn't known ahead of time, the number of open chunks is capped at `max_open_sources`. When a measurement
+/// arrives for a new source and the cap has been reached, the least recently used source's chunk is flushed early
+/// to make room.
+///
+/// This trades more (and smaller) objects for better read locality: consumers can read a single sensor's data
+/// without scanning the data from every other sensor on the topic.
+pub struct SourceChunks<T> {
+    chunk_size: usize,
+    max_open_sources: usize,
+    max_chunk_bytes: usize,
+    flush_bytes: Option<usize>,
+    chunks: HashMap<String, Vec<T>>,
+    /// Serialized size of each open chunk
+    sizes: HashMap<String, ChunkBytes>,
+    /// Source ids with an open chunk, least recently used at the front
+    recency: VecDeque<String>,
+}
+
+impl<T> SourceChunks<T> {
+    /// Create an empty set of per-source chunks
+    ///
+    /// `chunk_size` and `max_open_sources` are clamped to be at least 1
+    pub fn new(chunk_size: usize, max_open_sources: usize) -> Self {
+        SourceChunks {
+            chunk_size: chunk_size.max(1),
+            max_open_sources: max_open_sources.max(1),
+            max_chunk_bytes: MAX_CHUNK_BYTES,
+            flush_bytes: None,
+            chunks: HashMap::new(),
+            sizes: HashMap::new(),
+            recency: VecDeque::new(),
+        }
+    }
+
+    /// Flush chunks before they grow past `max_bytes` instead of `MAX_CHUNK_BYTES`
+    pub fn with_max_chunk_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_chunk_bytes = max_bytes;
+        self
+    }
+
+    /// Also flush chunks added to with `push_sized` once they reach `flush_bytes`, if set
+    ///
+    /// Unlike the max chunk bytes, which a chunk is flushed before it would grow past, a chunk is flushed after the
+    /// measurement that takes it to `flush_bytes` is added, so chunks are at least this size.
+    pub fn with_flush_bytes(mut self, flush_bytes: Option<usize>) -> Self {
+        self.flush_bytes = flush_bytes;
+        self
+    }
+
+    /// Flush chunks once they reach `chunk_size` items from now on, clamped to be at least 1
+    ///
+    /// Chunks already at or past the new size are flushed on their source's next push.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    /// Number of sources that currently have a partially filled chunk
+    pub fn open_sources(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Number of measurements buffered across all open chunks
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(Vec::len).sum()
+    }
+
+    /// Whether there are no buffered measurements
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Buffer a measurement from `source_id`, returning any chunks that need to be flushed
+    ///
+    /// At most two chunks are returned: the least recently used chunk if it had to be evicted to make room for a
+    /// new source, and this source's chunk if it is now full.
+    pub fn push(&mut self, source_id: &str, item: T) -> Vec<FullChunk<T>> {
+        let mut full = Vec::new();
+
+        if !self.chunks.contains_key(source_id) && self.chunks.len() >= self.max_open_sources {
+            if let Some(lru) = self.recency.pop_front() {
+                self.sizes.remove(&lru);
+                if let Some(items) = self.chunks.remove(&lru) {
+                    full.push(FullChunk {
+                        source_id: lru,
+                        items,
+                    });
+                }
+            }
+        }
+
+        self.touch(source_id);
+        let chunk = self.chunks.entry(source_id.to_owned()).or_default();
+        chunk.push(item);
+
+        if chunk.len() >= self.chunk_size {
+            full.push(self.remove(source_id));
+        }
+
+        full
+    }
+
+    /// Buffer a measurement of `len` serialized bytes from `source_id`, returning any chunks that need to be flushed
+    ///
+    /// Like `push`, but if the measurement would take this source's chunk over the max chunk bytes, the chunk is
+    /// flushed first and the measurement starts a new one. A measurement that's too large for a chunk on its own is
+    /// still buffered, it's up to the caller to reject it. The chunk is then flushed if it has reached `chunk_size`
+    /// items or the flush bytes.
+    pub fn push_sized(&mut self, source_id: &str, item: T, len: usize) -> Vec<FullChunk<T>> {
+        let mut full = Vec::new();
+
+        if matches!(self.sizes.get(source_id), Some(chunk_bytes) if !chunk_bytes.fits(len)) {
+            full.push(self.remove(source_id));
+        }
+
+        let max_chunk_bytes = self.max_chunk_bytes;
+        self.sizes
+            .entry(source_id.to_owned())
+            .or_insert_with(|| ChunkBytes::new(max_chunk_bytes))
+            .add(len);
+        full.extend(self.push(source_id, item));
+
+        let reached = match (self.flush_bytes, self.sizes.get(source_id)) {
+            (Some(flush_bytes), Some(chunk_bytes)) => chunk_bytes.reached(flush_bytes),
+            _ => false,
+        };
+        if reached {
+            full.push(self.remove(source_id));
+        }
+
+        full
+    }
+
+    /// Flush every open chunk, least recently used first
+    pub fn drain(&mut self) -> Vec<FullChunk<T>> {
+        self.sizes.clear();
+        let mut full = Vec::with_capacity(self.chunks.len());
+        while let Some(source_id) = self.recency.pop_front() {
+            if let Some(items) = self.chunks.remove(&source_id) {
+                full.push(FullChunk { source_id, items });
+            }
+        }
+        full
+    }
+
+    /// Mark `source_id` as the most recently used source
+    fn touch(&mut self, source_id: &str) {
+        if let Some(i) = self.recency.iter().position(|s| s == source_id) {
+            self.recency.remove(i);
+        }
+        self.recency.push_back(source_id.to_owned());
+    }
+
+    /// Close the chunk for `source_id`
+    fn remove(&mut self, source_id: &str) -> FullChunk<T> {
+        if let Some(i) = self.recency.iter().position(|s| s == source_id) {
+            self.recency.remove(i);
+        }
+        self.sizes.remove(source_id);
+        FullChunk {
+            source_id: source_id.to_owned(),
+            items: self.chunks.remove(source_id).unwrap_or_default(),
+        }
+    }
+}
+
+/// Uniform random sample of up to `capacity` items from a stream of unknown length
+///
+/// Implements Algorithm R: the first `capacity` items are kept, then the n-th item replaces a random kept item with
+/// probability `capacity / n`. Every item offered since the last `take` is equally likely to be in the sample, no
+/// matter how many were offered, so the sample stays representative when the volume varies wildly.
+pub struct Reservoir<T, R = StdRng> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+    rng: R,
+}
+
+impl<T> Reservoir<T> {
+    /// Empty reservoir keeping up to `capacity` items
+    pub fn new(capacity: usize) -> Self {
+        Reservoir::with_rng(capacity, StdRng::from_entropy())
+    }
+}
+
+impl<T, R: Rng> Reservoir<T, R> {
+    /// Empty reservoir keeping up to `capacity` items, sampled with `rng`
+    pub fn with_rng(capacity: usize, rng: R) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            items: Vec::with_capacity(capacity),
+            rng,
+        }
+    }
+
+    /// Offer the next item in the stream to the sample
+    ///
+    /// `item` is only called if the item is kept, so items that are expensive to copy are only copied when sampled.
+    pub fn offer<F>(&mut self, item: F)
+    where
+        F: FnOnce() -> T,
+    {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item());
+            return;
+        }
+
+        let i = self.rng.gen_range(0..self.seen);
+        if i < self.capacity as u64 {
+            self.items[i as usize] = item();
+        }
+    }
+
+    /// Number of items offered since the last `take`
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// Number of items in the sample
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether the sample is empty
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Take the sample, starting a new one
+    pub fn take(&mut self) -> Vec<T> {
+        self.seen = 0;
+        std::mem::replace(&mut self.items, Vec::with_capacity(self.capacity))
+    }
+}
@@ -1,12 +1,22 @@
 //! Command Line Interface for an archiver
 
+use crate::archiver::chunk::{ChunkSort, SourceFilter, MAX_CHUNK_BYTES};
+use crate::archiver::codec::{Codec, CodecKind, ZstdDictionary};
+use crate::archiver::error::ConfigError;
+use crate::archiver::store::S3ObjectStore;
+use crate::archiver::{zstd_compression_level, Encryption, KeyLayout, StartFrom};
 use aws_sdk_s3::{Client, Config, Credentials, Endpoint, Region};
-use clap::Parser;
+use clap::{Args, Parser};
+use http::Uri;
+use redpanda::RedpandaBuilder;
+#[cfg(feature = "metrics")]
+use std::net::SocketAddr;
+use std::time::Duration;
 
-/// CLI for S3 archiver
-#[derive(Parser)]
-#[command(author, about, long_about = None)]
-pub struct Cli {
+/// S3 connection and bucket options, flatten into any CLI that reads or writes archives with
+/// `#[command(flatten)]`
+#[derive(Args, Clone, Debug, PartialEq, Eq)]
+pub struct S3Args {
     /// Sets a s3 access key (MinIO username)
     #[arg(short, long, value_name = "S3_ACCESS_KEY")]
     access_key: String,
@@ -26,15 +36,190 @@ pub struct Cli {
     #[arg(short, long, value_name = "S3_REGION")]
     region: String,
 
-    /// Sets the s3 bucket name to archive to
+    /// Sets the s3 bucket name of the archive
     /// Note: This should just be of the form "opensensor-archive" or any other valid s3 bucket name
     #[arg(short, long, value_name = "S3_BUCKET_NAME")]
     bucket_name: String,
+}
+
+impl S3Args {
+    /// Construct S3Args for mocking + testing
+    pub fn new(
+        access_key: &str,
+        secret_key: &str,
+        endpoint: &str,
+        region: &str,
+        bucket_name: &str,
+    ) -> Self {
+        S3Args {
+            access_key: access_key.to_owned(),
+            secret_key: secret_key.to_owned(),
+            endpoint: endpoint.to_owned(),
+            region: region.to_owned(),
+            bucket_name: bucket_name.to_owned(),
+        }
+    }
+
+    /// S3 access key accessor
+    pub fn access_key(&self) -> &str {
+        &self.access_key
+    }
+
+    /// S3 secret key accessor
+    pub fn secret_key(&self) -> &str {
+        &self.secret_key
+    }
+
+    /// S3 endpoint accessor
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// S3 region accessor
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// S3 bucket name accessor
+    pub fn bucket_name(&self) -> &str {
+        &self.bucket_name
+    }
+
+    /// Whether `other` connects to the same endpoint and region with the same credentials, so a client built for
+    /// either can be used for both (the buckets can differ)
+    pub fn same_connection(&self, other: &S3Args) -> bool {
+        self.access_key == other.access_key
+            && self.secret_key == other.secret_key
+            && self.endpoint == other.endpoint
+            && self.region == other.region
+    }
+
+    /// Build a S3 client for the endpoint with static credentials
+    ///
+    /// # Errors
+    ///
+    /// - ConfigError::InvalidEndpoint: If the endpoint isn't a URL with a scheme and host, i.e. "localhost:9000"
+    /// - ConfigError::EmptyRegion: If the region is empty
+    pub fn build_client(&self) -> Result<Client, ConfigError> {
+        let s3_endpoint = Endpoint::immutable(parse_endpoint(&self.endpoint)?);
+        if self.region.trim().is_empty() {
+            return Err(ConfigError::EmptyRegion);
+        }
+
+        // credential provider name is required, but the value doesn't seem to matter
+        let provider_name = "opensensor-credentials";
+        let creds = Credentials::new(
+            &self.access_key,
+            &self.secret_key,
+            None,
+            None,
+            provider_name,
+        );
+
+        let config = Config::builder()
+            .region(Region::new(self.region.clone()))
+            .endpoint_resolver(s3_endpoint)
+            .credentials_provider(creds)
+            .build();
+
+        Ok(Client::from_conf(config))
+    }
+}
+
+/// Kafka options for consuming a topic, flatten into any CLI that consumes measurements with `#[command(flatten)]`
+#[derive(Args, Clone, Debug, PartialEq, Eq)]
+pub struct KafkaArgs {
+    /// Addresses of the brokers to connect to, in kafka form
+    /// ex. 127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012
+    #[arg(short, long, value_name = "KAFKA_ADDRESSES")]
+    kafka_addresses: String,
+
+    /// Redpanda topic to consume
+    /// Defaults to the consumed Measurement's TOPIC_NAME
+    #[arg(long, value_name = "TOPIC")]
+    topic: Option<String>,
+
+    /// Max bytes fetched from a broker per request across every partition, i.e. "50MiB" (librdkafka fetch.max.bytes)
+    /// Defaults to librdkafka's 50MiB. Raise it (with max_partition_fetch_bytes) for high-rate topics so each fetch
+    /// brings back a larger batch, or lower it for many low-rate topics to bound the consumer's memory
+    #[arg(long, value_name = "FETCH_MAX_BYTES", value_parser = parse_fetch_max_bytes)]
+    fetch_max_bytes: Option<usize>,
+
+    /// Max bytes fetched per partition per request, i.e. "1MiB" (librdkafka max.partition.fetch.bytes)
+    /// Defaults to librdkafka's 1MiB, which is plenty for low-rate topics. High-rate topics with few partitions
+    /// fetch faster with 4-16MiB
+    #[arg(long, value_name = "MAX_PARTITION_FETCH_BYTES", value_parser = parse_max_partition_fetch_bytes)]
+    max_partition_fetch_bytes: Option<usize>,
+}
+
+impl KafkaArgs {
+    /// Construct KafkaArgs for mocking + testing, consuming the Measurement's TOPIC_NAME
+    pub fn new(kafka_addresses: &str) -> Self {
+        KafkaArgs {
+            kafka_addresses: kafka_addresses.to_owned(),
+            topic: None,
+            fetch_max_bytes: None,
+            max_partition_fetch_bytes: None,
+        }
+    }
+
+    /// Kafka addresses to consume from
+    pub fn kafka_addresses(&self) -> &str {
+        &self.kafka_addresses
+    }
+
+    /// Topic to consume, if overriding the Measurement's TOPIC_NAME
+    pub fn topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+
+    /// Max bytes fetched per request across partitions, if overriding librdkafka's default
+    pub fn fetch_max_bytes(&self) -> Option<usize> {
+        self.fetch_max_bytes
+    }
+
+    /// Max bytes fetched per partition per request, if overriding librdkafka's default
+    pub fn max_partition_fetch_bytes(&self) -> Option<usize> {
+        self.max_partition_fetch_bytes
+    }
+
+    /// librdkafka consumer settings for the fetch sizes that are set, as key and value
+    pub fn consumer_settings(&self) -> Vec<(&'static str, String)> {
+        let settings = [
+            ("fetch.max.bytes", self.fetch_max_bytes),
+            ("max.partition.fetch.bytes", self.max_partition_fetch_bytes),
+        ];
+        settings
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value.to_string())))
+            .collect()
+    }
+
+    /// Set the consumer settings on a consumer builder, before `build_consumer`
+    pub fn apply(&self, builder: &mut RedpandaBuilder) {
+        for (key, value) in self.consumer_settings() {
+            builder.set(key, &value);
+        }
+    }
+}
+
+/// CLI for S3 archiver
+///
+/// Also usable as part of a larger CLI (i.e. a multi-sensor tool) with `#[command(flatten)]`, or compose `S3Args` and
+/// `KafkaArgs` into a different set of options.
+#[derive(Parser)]
+#[command(author, about, long_about = None)]
+pub struct Cli {
+    #[command(flatten)]
+    s3: S3Args,
+
+    #[command(flatten)]
+    kafka: KafkaArgs,
 
     /// Sensor name to archive data from
     /// Several pieces of information are derived from this:
-    /// Redpanda topic name = sensor_name + "-measurements"
     /// Consumer group name = sensor_name + "-archiver"
+    /// Object key prefix = sensor_name
     #[arg(long, value_name = "SENSOR_NAME")]
     sensor_name: String,
 
@@ -42,10 +227,156 @@ pub struct Cli {
     #[arg(short, long, value_name = "MESSAGES_PER_CHUNK")]
     chunk_size: u64,
 
-    /// Addresses of the brokers to connect to, in kafka form
-    /// ex. 127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012
-    #[arg(short, long, value_name = "KAFKA_ADDRESSES")]
-    kafka_addresses: String,
+    /// Max time to buffer a partial chunk before flushing it, i.e. "30s", "5m", "1h"
+    /// Chunks are flushed at chunk_size messages or max_chunk_age, whichever comes first
+    /// If not set, chunks are only flushed once they reach chunk_size messages
+    #[arg(long, value_name = "MAX_CHUNK_AGE", value_parser = humantime::parse_duration)]
+    max_chunk_age: Option<Duration>,
+
+    /// Smallest chunk size to adapt down to once the archiver has caught up with the topic
+    /// With this or max_chunk_size, chunk_size is only the starting size and each chunk's size follows the
+    /// consumer lag between the two. Defaults to chunk_size
+    #[arg(long, value_name = "MESSAGES_PER_CHUNK")]
+    min_chunk_size: Option<u64>,
+
+    /// Largest chunk size to adapt up to while the archiver is lagging behind the topic
+    /// Defaults to chunk_size
+    #[arg(long, value_name = "MESSAGES_PER_CHUNK")]
+    max_chunk_size: Option<u64>,
+
+    /// Flush a chunk once its uncompressed size reaches this many bytes, i.e. "64MB"
+    /// Chunks are flushed at chunk_size messages or max_chunk_bytes, whichever comes first, so archive objects are
+    /// a similar size however large the measurements are. If not set, only chunk_size counts
+    #[arg(long, value_name = "MAX_CHUNK_BYTES", value_parser = parse_chunk_bytes)]
+    max_chunk_bytes: Option<usize>,
+
+    /// Where to start consuming the topic: "committed" resumes from the consumer group's committed offsets, or
+    /// start a backfill from "earliest", "latest", "offset:N" in every partition, or the first message at or after
+    /// "time:RFC3339". Starting anywhere but committed moves the group's offsets (unless dry_run), so stop other
+    /// archivers first
+    #[arg(long, value_name = "START_FROM", default_value = "committed")]
+    start_from: StartFrom,
+
+    /// Max time to wait for the next message before treating the topic as idle, i.e. "500ms", "10s"
+    /// If not set, the archiver waits for messages indefinitely. High-rate topics can use "500ms", low-rate topics
+    /// need longer than the usual gap between messages, i.e. "30s"
+    #[arg(long, value_name = "POLL_TIMEOUT", value_parser = humantime::parse_duration)]
+    poll_timeout: Option<Duration>,
+
+    /// Only archive measurements from these source_ids, comma separated, i.e. "radar-1,radar-2"
+    /// Measurements from other sources are skipped. If not set, every source is archived
+    #[arg(long, value_name = "SOURCE_IDS", value_delimiter = ',')]
+    source_ids: Vec<String>,
+
+    /// Keep a separate archive chunk per measurement source_id and write each source to its own key prefix
+    /// Object keys become sensor_name/source_id/timestamp instead of sensor_name/timestamp
+    #[arg(long)]
+    split_by_source: bool,
+
+    /// Max number of sources with an open chunk when splitting by source
+    /// When a new source arrives and this is exceeded, the least recently used source's chunk is flushed early
+    #[arg(long, value_name = "MAX_OPEN_SOURCES", default_value_t = 64)]
+    max_open_sources: u64,
+
+    /// zstd compression level for archive objects
+    /// Higher levels compress better at the cost of CPU. Use -1 for zstd's default level
+    #[arg(
+        long,
+        value_name = "COMPRESSION_LEVEL",
+        default_value_t = 0,
+        allow_negative_numbers = true,
+        value_parser = parse_compression_level
+    )]
+    compression_level: i32,
+
+    /// Compression codec for archive objects
+    /// lz4 and snappy compress worse than zstd but decompress much faster for downstream readers
+    #[arg(long, value_name = "CODEC", value_enum, default_value_t = CodecKind::Zstd)]
+    codec: CodecKind,
+
+    /// zstd dictionary file to compress archive objects with, trained with `codec::train_dictionary` or
+    /// `zstd --train` on sample measurements. Only used with the zstd codec
+    /// Readers need the same dictionary to decompress the archives, see `download_object_zstd_dict`
+    #[arg(long, value_name = "ZSTD_DICTIONARY", value_parser = parse_zstd_dictionary)]
+    zstd_dictionary: Option<ZstdDictionary>,
+
+    /// Sort each chunk before it's serialized, which usually compresses much better than consumption order
+    /// The manifest records every measurement's original offset so consumption order can be restored
+    #[arg(long, value_name = "SORT_CHUNK_BY", value_enum)]
+    sort_chunk_by: Option<ChunkSort>,
+
+    /// Drop measurements `content_eq` to the last one kept in their chunk with the same partition key
+    /// Meant for state topics, where only changes matter. The dropped measurements' offsets are still committed
+    #[arg(long)]
+    compact_equal_runs: bool,
+
+    /// Wrap each chunk in the versioned archive container (see `archiver::format`) instead of uploading it bare
+    /// The container is compressed with the codec like a bare chunk. Readers in this crate accept both
+    #[arg(long)]
+    container: bool,
+
+    /// Layout of archive object keys: "flat" ({sensor_name}/{rfc3339}) or "hive"
+    /// ({sensor_name}/year=YYYY/month=MM/day=DD/hour=HH/{rfc3339}) for querying archives as a partitioned dataset
+    #[arg(long, value_name = "KEY_LAYOUT", value_enum, default_value_t = KeyLayout::Flat)]
+    key_layout: KeyLayout,
+
+    /// Request SSE-S3 server-side encryption for every uploaded object
+    #[arg(long)]
+    sse: bool,
+
+    /// Request SSE-KMS server-side encryption with this KMS key id or ARN for every uploaded object
+    /// Takes precedence over --sse
+    #[arg(long, value_name = "SSE_KMS_KEY_ID")]
+    sse_kms_key_id: Option<String>,
+
+    /// Stamp every archived object with S3 user metadata identifying the archiver instance that wrote it
+    /// (hostname, process id, crate version, consumer group, and the partitions covered)
+    #[arg(long)]
+    provenance: bool,
+
+    /// Also archive a uniform random sample of this many measurements per reservoir window to the preview prefix
+    /// (sensor_name + "-preview"), for building representative preview datasets
+    #[arg(long, value_name = "RESERVOIR_SIZE")]
+    reservoir: Option<usize>,
+
+    /// Time window each reservoir sample covers, i.e. "10m", "1h"
+    #[arg(long, value_name = "RESERVOIR_WINDOW", default_value = "1h", value_parser = humantime::parse_duration)]
+    reservoir_window: Duration,
+
+    /// How many times to retry a failed S3 upload before giving up
+    /// Only transient failures (timeouts, throttling, 5xx) are retried
+    #[arg(long, value_name = "UPLOAD_RETRIES", default_value_t = 5)]
+    upload_retries: u32,
+
+    /// Delay before the first upload retry, in milliseconds
+    /// The delay doubles with every retry
+    #[arg(long, value_name = "UPLOAD_RETRY_DELAY_MS", default_value_t = 200)]
+    upload_retry_delay_ms: u64,
+
+    /// Topic to produce messages that fail to deserialize to, defaults to sensor_name + "-dead-letter"
+    #[arg(long, value_name = "DEAD_LETTER_TOPIC")]
+    dead_letter_topic: Option<String>,
+
+    /// Exit with an error once more than this many messages have been sent to the dead letter topic
+    /// If not set, the archiver keeps going no matter how many messages fail to deserialize
+    #[arg(long, value_name = "MAX_DEAD_LETTERS")]
+    max_dead_letters: Option<u64>,
+
+    /// How often to log the totals archived so far, i.e. "5m", "1h". "0s" only logs them when the archiver stops
+    #[arg(long, value_name = "STATS_INTERVAL", default_value = "15m", value_parser = humantime::parse_duration)]
+    stats_interval: Duration,
+
+    /// Consume and chunk the topic without uploading anything, committing offsets, or producing dead letters
+    /// Logs the key, record count, and size of each chunk that would be uploaded. The partitions are read directly
+    /// instead of joining the consumer group, so the group's committed offsets stay where they were and a running
+    /// archiver keeps its partitions. With poll_timeout, stops once the topic is idle
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Serve Prometheus metrics at http://METRICS_ADDRESS/metrics, i.e. "0.0.0.0:9898"
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "METRICS_ADDRESS")]
+    metrics_address: Option<SocketAddr>,
 }
 
 impl Cli {
@@ -61,40 +392,75 @@ impl Cli {
         kafka_addresses: &str,
     ) -> Self {
         Cli {
-            access_key: access_key.to_owned(),
-            secret_key: secret_key.to_owned(),
-            endpoint: endpoint.to_owned(),
-            region: region.to_owned(),
-            bucket_name: bucket_name.to_owned(),
+            s3: S3Args::new(access_key, secret_key, endpoint, region, bucket_name),
+            kafka: KafkaArgs::new(kafka_addresses),
             sensor_name: sensor_name.to_owned(),
             chunk_size: chunk_side,
-            kafka_addresses: kafka_addresses.to_owned(),
+            max_chunk_age: None,
+            min_chunk_size: None,
+            max_chunk_size: None,
+            max_chunk_bytes: None,
+            poll_timeout: None,
+            start_from: StartFrom::Committed,
+            source_ids: Vec::new(),
+            split_by_source: false,
+            max_open_sources: 64,
+            compression_level: 0,
+            codec: CodecKind::Zstd,
+            zstd_dictionary: None,
+            sort_chunk_by: None,
+            compact_equal_runs: false,
+            container: false,
+            key_layout: KeyLayout::Flat,
+            sse: false,
+            sse_kms_key_id: None,
+            provenance: false,
+            reservoir: None,
+            reservoir_window: Duration::from_secs(3600),
+            upload_retries: 5,
+            upload_retry_delay_ms: 200,
+            dead_letter_topic: None,
+            max_dead_letters: None,
+            stats_interval: Duration::from_secs(15 * 60),
+            dry_run: false,
+            #[cfg(feature = "metrics")]
+            metrics_address: None,
         }
     }
 
+    /// S3 connection and bucket options
+    pub fn s3(&self) -> &S3Args {
+        &self.s3
+    }
+
+    /// Kafka options
+    pub fn kafka(&self) -> &KafkaArgs {
+        &self.kafka
+    }
+
     /// S3 access key accessor
     pub fn access_key(&self) -> &str {
-        &self.access_key
+        self.s3.access_key()
     }
 
     /// S3 secret key accessor
     pub fn secret_key(&self) -> &str {
-        &self.secret_key
+        self.s3.secret_key()
     }
 
     /// S3 endpoint accessor
     pub fn endpoint(&self) -> &str {
-        &self.endpoint
+        self.s3.endpoint()
     }
 
     /// S3 region accessor
     pub fn region(&self) -> &str {
-        &self.region
+        self.s3.region()
     }
 
     /// S3 bucket name accessor
     pub fn bucket_name(&self) -> &str {
-        &self.bucket_name
+        self.s3.bucket_name()
     }
 
     /// sensor name accessor
@@ -102,36 +468,361 @@ impl Cli {
         &self.sensor_name
     }
 
+    /// Topic to archive, if overriding the Measurement's TOPIC_NAME
+    pub fn topic(&self) -> Option<&str> {
+        self.kafka.topic()
+    }
+
     /// Max number of records to put in a single archival chunk
     pub fn chunk_size(&self) -> u64 {
         self.chunk_size
     }
 
+    /// Range of chunk sizes to adapt between based on consumer lag, None for a fixed chunk_size
+    ///
+    /// Unset bounds default to chunk_size. If the min is larger than the max, the max is raised to match.
+    pub fn chunk_size_range(&self) -> Option<(u64, u64)> {
+        if self.min_chunk_size.is_none() && self.max_chunk_size.is_none() {
+            return None;
+        }
+
+        let min = self.min_chunk_size.unwrap_or(self.chunk_size);
+        let max = self.max_chunk_size.unwrap_or(self.chunk_size).max(min);
+        Some((min, max))
+    }
+
+    /// Uncompressed size in bytes a chunk is flushed at, as well as at chunk_size messages, if any
+    pub fn max_chunk_bytes(&self) -> Option<usize> {
+        self.max_chunk_bytes
+    }
+
+    /// Max time a partial chunk is buffered before it's flushed, if any
+    pub fn max_chunk_age(&self) -> Option<Duration> {
+        self.max_chunk_age
+    }
+
+    /// Max time to wait for a message before the topic is considered idle, if any
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        self.poll_timeout
+    }
+
+    /// Where to start consuming the topic
+    pub fn start_from(&self) -> StartFrom {
+        self.start_from
+    }
+
     /// Kafka addresses the archiver consumes from
     pub fn kafka_addresses(&self) -> &str {
-        &self.kafka_addresses
+        self.kafka.kafka_addresses()
+    }
+
+    /// Which measurement source_ids to archive
+    pub fn source_filter(&self) -> SourceFilter {
+        if self.source_ids.is_empty() {
+            SourceFilter::default()
+        } else {
+            SourceFilter::only(&self.source_ids)
+        }
+    }
+
+    /// Whether to archive each measurement source_id to its own key prefix
+    pub fn split_by_source(&self) -> bool {
+        self.split_by_source
+    }
+
+    /// Max number of per-source chunks to hold open when splitting by source
+    pub fn max_open_sources(&self) -> u64 {
+        self.max_open_sources
+    }
+
+    /// zstd compression level for archive objects
+    pub fn compression_level(&self) -> i32 {
+        self.compression_level
+    }
+
+    /// Codec archive objects are compressed with, zstd at `compression_level` by default
+    ///
+    /// zstd uses the `--zstd-dictionary`, if one was given.
+    pub fn codec(&self) -> Codec {
+        match (self.codec, &self.zstd_dictionary) {
+            (CodecKind::Zstd, Some(dictionary)) => Codec::ZstdDict {
+                level: self.compression_level,
+                dictionary: dictionary.clone(),
+            },
+            (codec, _) => codec.codec(self.compression_level),
+        }
+    }
+
+    /// zstd dictionary archive objects are compressed with, if any
+    pub fn zstd_dictionary(&self) -> Option<&ZstdDictionary> {
+        self.zstd_dictionary.as_ref()
+    }
+
+    /// How to sort each chunk before it's serialized, None keeps consumption order
+    pub fn sort_chunk_by(&self) -> Option<ChunkSort> {
+        self.sort_chunk_by
+    }
+
+    /// Whether runs of equal measurements per partition key are collapsed before archiving
+    pub fn compact_equal_runs(&self) -> bool {
+        self.compact_equal_runs
+    }
+
+    /// Whether chunks are wrapped in the archive container format
+    pub fn container(&self) -> bool {
+        self.container
+    }
+
+    /// Layout of archive object keys
+    pub fn key_layout(&self) -> KeyLayout {
+        self.key_layout
+    }
+
+    /// Server-side encryption to request for uploaded objects
+    pub fn encryption(&self) -> Encryption {
+        match &self.sse_kms_key_id {
+            Some(key_id) => Encryption::SseKms {
+                key_id: key_id.clone(),
+            },
+            None if self.sse => Encryption::Sse,
+            None => Encryption::None,
+        }
+    }
+
+    /// Kafka consumer group id the archiver commits its offsets to
+    pub fn group_id(&self) -> String {
+        format!("{}-archiver", self.sensor_name)
+    }
+
+    /// Whether to stamp archived objects with provenance metadata
+    pub fn provenance(&self) -> bool {
+        self.provenance
+    }
+
+    /// Number of measurements to reservoir sample per window, if sampling
+    pub fn reservoir(&self) -> Option<usize> {
+        self.reservoir.filter(|size| *size > 0)
+    }
+
+    /// Time window each reservoir sample covers
+    pub fn reservoir_window(&self) -> Duration {
+        self.reservoir_window
+    }
+
+    /// Object key prefix reservoir samples are archived to
+    pub fn preview_prefix(&self) -> String {
+        format!("{}-preview", self.sensor_name)
+    }
+
+    /// Max number of retries for a failed S3 upload
+    pub fn upload_retries(&self) -> u32 {
+        self.upload_retries
+    }
+
+    /// Base delay between S3 upload retries
+    pub fn upload_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.upload_retry_delay_ms)
+    }
+
+    /// Topic messages that fail to deserialize are produced to
+    pub fn dead_letter_topic(&self) -> String {
+        self.dead_letter_topic
+            .clone()
+            .unwrap_or_else(|| format!("{}-dead-letter", self.sensor_name))
+    }
+
+    /// Max number of dead letters before the archiver exits, None for no limit
+    pub fn max_dead_letters(&self) -> Option<u64> {
+        self.max_dead_letters
+    }
+
+    /// How often to log the totals archived so far, None to only log them when the archiver stops
+    pub fn stats_interval(&self) -> Option<Duration> {
+        Some(self.stats_interval).filter(|interval| !interval.is_zero())
+    }
+
+    /// Whether to consume and chunk without uploading or committing anything
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Address to serve Prometheus metrics on, None if metrics are off
+    #[cfg(feature = "metrics")]
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address
     }
 
     /// Build a S3 client from the CLI configuration
-    pub fn build_client(&self) -> Client {
-        // credential provider name is required, but the value doesn't seem to matter
-        let provider_name = "opensensor-credentials";
-        let creds = Credentials::new(
-            &self.access_key,
-            &self.secret_key,
-            None,
-            None,
-            provider_name,
-        );
+    ///
+    /// # Errors
+    ///
+    /// - Same as `S3Args::build_client`
+    #[deprecated(note = "use `cli.s3().build_client()`")]
+    pub fn build_client(&self) -> Result<Client, ConfigError> {
+        self.s3.build_client()
+    }
 
-        let s3_endpoint = Endpoint::immutable(self.endpoint.parse().unwrap());
+    /// Build the S3 object store for the configured bucket and encryption
+    ///
+    /// # Errors
+    ///
+    /// - Same as `S3Args::build_client`
+    pub fn build_object_store(&self) -> Result<S3ObjectStore, ConfigError> {
+        Ok(S3ObjectStore::new(
+            self.s3.build_client()?,
+            self.bucket_name(),
+            self.encryption(),
+        ))
+    }
+}
 
-        let config = Config::builder()
-            .region(Region::new(self.region.clone()))
-            .endpoint_resolver(s3_endpoint)
-            .credentials_provider(creds)
-            .build();
+/// CLI options for `scan_archive`, which checks every archive object in a bucket
+#[derive(Parser)]
+#[command(name = "scan", author, about, long_about = None)]
+pub struct ScanCli {
+    #[command(flatten)]
+    s3: S3Args,
+
+    /// Only scan objects whose keys start with this prefix, i.e. "radar-2d/"
+    /// Defaults to the whole bucket
+    #[arg(long, value_name = "PREFIX", default_value = "")]
+    prefix: String,
+
+    /// Only check each object's size against its manifest instead of downloading and decompressing it
+    #[arg(long)]
+    quick: bool,
+
+    /// Report a gap in an archive's time coverage when no object covers this long, i.e. "10m", "1h"
+    #[arg(long, value_name = "MAX_GAP", default_value = "1h", value_parser = humantime::parse_duration)]
+    max_gap: Duration,
+
+    /// zstd dictionary file the archives were compressed with (the archiver's `--zstd-dictionary`), repeat it for
+    /// archives written with more than one. Objects name the dictionary they need by id
+    #[arg(long, value_name = "ZSTD_DICTIONARY", value_parser = parse_zstd_dictionary)]
+    zstd_dictionary: Vec<ZstdDictionary>,
+}
+
+impl ScanCli {
+    /// S3 connection and bucket options
+    pub fn s3(&self) -> &S3Args {
+        &self.s3
+    }
+
+    /// S3 bucket name accessor
+    pub fn bucket_name(&self) -> &str {
+        self.s3.bucket_name()
+    }
 
-        Client::from_conf(config)
+    /// Key prefix of the objects to scan, empty for the whole bucket
+    pub fn prefix(&self) -> &str {
+        &self.prefix
     }
+
+    /// Whether to only check object sizes against their manifests
+    pub fn quick(&self) -> bool {
+        self.quick
+    }
+
+    /// Longest time without an archive object that isn't reported as a gap
+    pub fn max_gap(&self) -> Duration {
+        self.max_gap
+    }
+
+    /// zstd dictionaries to decompress archive objects with, empty if none were given
+    pub fn zstd_dictionaries(&self) -> &[ZstdDictionary] {
+        &self.zstd_dictionary
+    }
+
+    /// Build a S3 client from the CLI configuration
+    ///
+    /// # Errors
+    ///
+    /// - Same as `S3Args::build_client`
+    #[deprecated(note = "use `scan.s3().build_client()`")]
+    pub fn build_client(&self) -> Result<Client, ConfigError> {
+        self.s3.build_client()
+    }
+}
+
+/// Parse an S3 endpoint URL, which needs a scheme and host for the S3 client to send requests to
+fn parse_endpoint(endpoint: &str) -> Result<Uri, ConfigError> {
+    let invalid = |reason: String| ConfigError::InvalidEndpoint {
+        endpoint: endpoint.to_owned(),
+        reason,
+    };
+
+    let uri: Uri = endpoint.parse().map_err(|e| invalid(format!("{}", e)))?;
+    if uri.scheme().is_none() {
+        return Err(invalid("missing a scheme, i.e. http://".to_owned()));
+    }
+    if uri.host().is_none() {
+        return Err(invalid("missing a host".to_owned()));
+    }
+
+    Ok(uri)
+}
+
+/// Read a zstd dictionary file, rejecting files that aren't trained dictionaries
+fn parse_zstd_dictionary(s: &str) -> Result<ZstdDictionary, String> {
+    ZstdDictionary::from_file(s).map_err(|e| format!("can't read zstd dictionary {}: {}", s, e))
+}
+
+/// Parse a zstd compression level, rejecting levels zstd doesn't support
+fn parse_compression_level(s: &str) -> Result<i32, String> {
+    let compression_level: i32 = s.parse().map_err(|e| format!("{}", e))?;
+    zstd_compression_level(compression_level).map_err(|e| e.to_string())?;
+
+    Ok(compression_level)
+}
+
+/// Parse a chunk size in bytes, i.e. "1048576", "64MB", or "1GiB", rejecting sizes that are zero or over
+/// `MAX_CHUNK_BYTES`
+fn parse_chunk_bytes(s: &str) -> Result<usize, String> {
+    parse_bytes(s, "chunk bytes", MAX_CHUNK_BYTES)
+}
+
+/// Largest fetch.max.bytes librdkafka accepts
+const MAX_FETCH_MAX_BYTES: usize = 2_147_483_135;
+
+/// Largest max.partition.fetch.bytes librdkafka accepts
+const MAX_PARTITION_FETCH_BYTES: usize = 1_000_000_000;
+
+/// Parse fetch.max.bytes like a chunk size, rejecting sizes librdkafka doesn't accept
+fn parse_fetch_max_bytes(s: &str) -> Result<usize, String> {
+    parse_bytes(s, "fetch max bytes", MAX_FETCH_MAX_BYTES)
+}
+
+/// Parse max.partition.fetch.bytes like a chunk size, rejecting sizes librdkafka doesn't accept
+fn parse_max_partition_fetch_bytes(s: &str) -> Result<usize, String> {
+    parse_bytes(s, "max partition fetch bytes", MAX_PARTITION_FETCH_BYTES)
+}
+
+/// Parse a size in bytes with an optional unit, rejecting sizes that are zero or over `max`
+fn parse_bytes(s: &str, name: &str, max: usize) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let multiplier: usize = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        unit => {
+            return Err(format!(
+                "unknown unit {}, expected B, KB, MB, GB, KiB, MiB, or GiB",
+                unit
+            ))
+        }
+    };
+    let bytes = digits
+        .parse::<usize>()
+        .map_err(|e| format!("{}", e))?
+        .checked_mul(multiplier)
+        .filter(|bytes| (1..=max).contains(bytes))
+        .ok_or_else(|| format!("{} must be between 1 and {}", name, max))?;
+
+    Ok(bytes)
 }
@@ -0,0 +1,319 @@
+//! Compression codecs for archive objects
+//!
+//! Archive objects are compressed with a `Codec` and stored with its content encoding, so readers (i.e.
+//! `StoredObject::decompressed` and `archiver::download_object_zstd`) pick the matching decompressor from the object
+//! itself rather than assuming zstd.
+//!
+//! zstd compresses best and is the default. LZ4 and Snappy trade ratio for much cheaper decompression, which suits
+//! downstream jobs (i.e. Spark) that read archives far more often than they're written.
+//!
+//! Sensors producing many small, similar measurements compress much better with a zstd dictionary trained on
+//! samples of them (see `train_dictionary` and `Codec::ZstdDict`). Objects compressed with a dictionary record its
+//! id in their metadata, and need the same dictionary to be decompressed (see `StoredObject::decompressed_with`).
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use crate::archiver::chunk::MAX_CHUNK_BYTES;
+use crate::archiver::zstd_compression_level;
+
+/// Content encoding of zstd compressed objects, a single zstd frame (or a stream of them for multipart uploads)
+pub const ZSTD_CONTENT_ENCODING: &str = "zstd";
+
+/// Content encoding of LZ4 compressed objects, in the LZ4 frame format
+pub const LZ4_CONTENT_ENCODING: &str = "lz4";
+
+/// Content encoding of Snappy compressed objects, in the Snappy framing format
+pub const SNAPPY_CONTENT_ENCODING: &str = "snappy";
+
+/// Compression applied to an archive object before it's stored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// zstd at a compression level, see `zstd_compression_level`
+    Zstd {
+        /// zstd compression level, `ZSTD_DEFAULT_LEVEL` for zstd's default
+        level: i32,
+    },
+    /// zstd with a pre-trained dictionary, stored with the zstd content encoding and the dictionary's id
+    ZstdDict {
+        /// zstd compression level, `ZSTD_DEFAULT_LEVEL` for zstd's default
+        level: i32,
+        /// Dictionary to compress with, which readers need to decompress
+        dictionary: ZstdDictionary,
+    },
+    /// LZ4 frame format, much faster to decompress than zstd at a worse ratio
+    Lz4,
+    /// Snappy framing format, faster still with the worst ratio
+    Snappy,
+    /// Stored uncompressed, without a content encoding
+    None,
+}
+
+impl Default for Codec {
+    /// zstd at level 0, the historical default
+    fn default() -> Self {
+        Codec::Zstd { level: 0 }
+    }
+}
+
+impl Codec {
+    /// Content encoding objects compressed with this codec are stored with, None for uncompressed objects
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Codec::Zstd { .. } | Codec::ZstdDict { .. } => Some(ZSTD_CONTENT_ENCODING),
+            Codec::Lz4 => Some(LZ4_CONTENT_ENCODING),
+            Codec::Snappy => Some(SNAPPY_CONTENT_ENCODING),
+            Codec::None => None,
+        }
+    }
+
+    /// Kind of this codec, i.e. to record it without the dictionary's bytes
+    pub fn kind(&self) -> CodecKind {
+        match self {
+            Codec::Zstd { .. } | Codec::ZstdDict { .. } => CodecKind::Zstd,
+            Codec::Lz4 => CodecKind::Lz4,
+            Codec::Snappy => CodecKind::Snappy,
+            Codec::None => CodecKind::None,
+        }
+    }
+
+    /// zstd compression level objects are compressed at, None for codecs other than zstd
+    pub fn zstd_level(&self) -> Option<i32> {
+        match self {
+            Codec::Zstd { level } | Codec::ZstdDict { level, .. } => Some(*level),
+            _ => None,
+        }
+    }
+
+    /// Dictionary objects are compressed with, if any
+    pub fn dictionary(&self) -> Option<&ZstdDictionary> {
+        match self {
+            Codec::ZstdDict { dictionary, .. } => Some(dictionary),
+            _ => None,
+        }
+    }
+
+    /// Compress `data` with this codec
+    ///
+    /// # Errors
+    ///
+    /// - std::io::ErrorKind::InvalidInput: If the zstd compression level is out of range
+    /// - std::io::Error: If compression fails
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Codec::Zstd { level } => {
+                let level = zstd_compression_level(*level).map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+                })?;
+                zstd::bulk::compress(data, level)
+            }
+            Codec::ZstdDict { level, dictionary } => {
+                let level = zstd_compression_level(*level).map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+                })?;
+                zstd::bulk::Compressor::with_dictionary(level, dictionary.as_bytes())?
+                    .compress(data)
+            }
+            Codec::Lz4 => {
+                let mut encoder = lz4::EncoderBuilder::new().build(Vec::new())?;
+                encoder.write_all(data)?;
+                let (body, result) = encoder.finish();
+                result?;
+                Ok(body)
+            }
+            Codec::Snappy => {
+                let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder.into_inner().map_err(|e| e.into_error())
+            }
+            Codec::None => Ok(data.to_vec()),
+        }
+    }
+}
+
+/// Decompress the body of an object stored with `content_encoding`
+///
+/// Objects without a content encoding (or with `identity`) are returned as-is. zstd bodies are only decompressed in
+/// one call when their decompressed size is known, otherwise with the streaming decoder (see `decompress_zstd`).
+///
+/// # Errors
+///
+/// - std::io::ErrorKind::Unsupported: If the content encoding isn't one a `Codec` writes
+/// - std::io::Error: If the body isn't valid for its content encoding, or was compressed with a zstd dictionary
+pub fn decompress(content_encoding: Option<&str>, body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    decompress_with_dictionary(content_encoding, body, None)
+}
+
+/// Decompress the body of an object stored with `content_encoding` like `decompress`, with the zstd dictionary it
+/// was compressed with, if any
+///
+/// # Errors
+///
+/// - Same as `decompress`, including if the body was compressed with a different dictionary
+pub fn decompress_with_dictionary(
+    content_encoding: Option<&str>,
+    body: &[u8],
+    dictionary: Option<&ZstdDictionary>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let mut data = Vec::new();
+    match content_encoding {
+        None | Some("identity") => data.extend_from_slice(body),
+        Some(ZSTD_CONTENT_ENCODING) => match dictionary {
+            Some(dictionary) => {
+                zstd::stream::Decoder::with_dictionary(body, dictionary.as_bytes())?
+                    .read_to_end(&mut data)?;
+            }
+            None => data = decompress_zstd(body)?,
+        },
+        Some(LZ4_CONTENT_ENCODING) => {
+            let mut decoder = lz4::Decoder::new(body)?;
+            decoder.read_to_end(&mut data)?;
+            // The decoder stops quietly at the end of the body, even partway through the frame
+            let (_, result) = decoder.finish();
+            result.map_err(|e| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, e))?;
+        }
+        Some(SNAPPY_CONTENT_ENCODING) => {
+            snap::read::FrameDecoder::new(body).read_to_end(&mut data)?;
+        }
+        Some(content_encoding) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Unsupported content encoding {}", content_encoding),
+            ))
+        }
+    }
+
+    Ok(data)
+}
+
+/// Largest decompressed size `decompress_zstd` trusts a zstd frame header with, so a corrupt header can't make it
+/// allocate more than an archive chunk can hold
+const MAX_BULK_DECOMPRESS_BYTES: u64 = MAX_CHUNK_BYTES as u64;
+
+/// Decompress a zstd body compressed without a dictionary
+///
+/// A single frame recording its decompressed size (as `Codec::compress` writes) is decompressed in one call into a
+/// buffer of exactly that size. Bodies without a recorded size, like multipart uploads compressed as a stream, with
+/// several frames, or claiming more than `MAX_BULK_DECOMPRESS_BYTES`, use the streaming decoder rather than
+/// guessing a capacity.
+///
+/// # Errors
+///
+/// - std::io::ErrorKind::UnexpectedEof: If the body is truncated
+/// - std::io::Error: If the body isn't zstd
+fn decompress_zstd(body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let single_frame = zstd::zstd_safe::find_frame_compressed_size(body) == Ok(body.len());
+    match zstd::zstd_safe::get_frame_content_size(body) {
+        size if single_frame && size <= MAX_BULK_DECOMPRESS_BYTES => {
+            zstd::bulk::decompress(body, size as usize)
+        }
+        _ => zstd::stream::decode_all(body),
+    }
+}
+
+/// A zstd dictionary, trained on sample measurements with `train_dictionary`
+///
+/// Cheap to clone, the dictionary's bytes are shared.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ZstdDictionary {
+    id: u32,
+    bytes: Arc<[u8]>,
+}
+
+impl ZstdDictionary {
+    /// Dictionary from the bytes `train_dictionary` returned (or `zstd --train` wrote)
+    ///
+    /// # Errors
+    ///
+    /// - std::io::ErrorKind::InvalidData: If the bytes aren't a zstd dictionary with an id, i.e. raw content that
+    ///   wasn't trained
+    pub fn new(bytes: Vec<u8>) -> Result<Self, std::io::Error> {
+        match zstd::zstd_safe::get_dict_id(&bytes) {
+            Some(id) => Ok(ZstdDictionary {
+                id,
+                bytes: bytes.into(),
+            }),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a trained zstd dictionary, it has no dictionary id",
+            )),
+        }
+    }
+
+    /// Dictionary read from a file
+    ///
+    /// # Errors
+    ///
+    /// - std::io::Error: If the file can't be read
+    /// - Same as `ZstdDictionary::new`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        ZstdDictionary::new(std::fs::read(path)?)
+    }
+
+    /// Dictionary id, recorded in the zstd frames and metadata of objects compressed with it
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The dictionary's bytes, i.e. to save it next to the archives that need it
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Debug for ZstdDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdDictionary")
+            .field("id", &self.id)
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// Train a zstd dictionary of up to `dict_size` bytes on sample measurements (see `ZstdDictionary::new`)
+///
+/// Samples should be representative serialized measurements (or archive chunks) of a single sensor. zstd needs a lot
+/// of them to train on, roughly 100 times `dict_size` bytes in total. A few KB is usually enough for small
+/// measurements.
+///
+/// # Errors
+///
+/// - std::io::Error: If training fails, i.e. there are too few samples
+pub fn train_dictionary(samples: &[Vec<u8>], dict_size: usize) -> Result<Vec<u8>, std::io::Error> {
+    zstd::dict::from_samples(samples, dict_size)
+}
+
+/// Codec names for the archiver's `--codec` option
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+#[cfg_attr(
+    feature = "json",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum CodecKind {
+    /// zstd, at `--compression-level`
+    #[default]
+    Zstd,
+    /// LZ4 frame format
+    Lz4,
+    /// Snappy framing format
+    Snappy,
+    /// No compression
+    None,
+}
+
+impl CodecKind {
+    /// Codec of this kind, using `zstd_level` if it's zstd
+    pub fn codec(self, zstd_level: i32) -> Codec {
+        match self {
+            CodecKind::Zstd => Codec::Zstd { level: zstd_level },
+            CodecKind::Lz4 => Codec::Lz4,
+            CodecKind::Snappy => Codec::Snappy,
+            CodecKind::None => Codec::None,
+        }
+    }
+}
@@ -0,0 +1,578 @@
+//! Merge many small archive objects into fewer, larger ones
+//!
+//! Low-volume sensors, `--split-by-source`, and frequent flushes all leave behind many small archive objects, which
+//! are slow and costly to list and read back. `compact_archives` merges runs of consecutive small objects into one
+//! object of up to a target size, keeping the measurements in time order.
+//!
+//! A merged object is stored at the first merged object's timestamp with a `_{count}c-{hash}` suffix, where the hash
+//! is of the keys it was merged from, so it sorts where the objects it replaces did and `key_timestamp` still parses
+//! it. Before the merged object is written, the keys it's merged from are recorded next to it at
+//! `{key}.compacted-from`, and that record is only deleted once they have been. A compaction interrupted part way is
+//! finished (or, if the merged object was never written, abandoned) by the next run, so it's always safe to re-run.
+//!
+//! With the `json` feature, the merged object replaces the objects it was merged from in the sensor's daily indexes
+//! (see `index::remove_from_indexes`) before they're deleted.
+
+#[cfg(feature = "json")]
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tracing::{event, Level};
+
+use crate::archiver::chunk::record_offsets_key;
+use crate::archiver::chunk::{deserialize_chunk, serialize_chunk};
+use crate::archiver::cli::Cli;
+use crate::archiver::codec::{Codec, ZstdDictionary};
+use crate::archiver::error::ArchiveError;
+#[cfg(feature = "json")]
+use crate::archiver::index::{index_prefix, is_index_key, remove_from_indexes, IndexEntry};
+#[cfg(feature = "json")]
+use crate::archiver::manifest::{get_manifest, put_manifest, Manifest};
+use crate::archiver::store::{put_with_retry, ObjectStore, ObjectStoreError, StoredObject};
+#[cfg(feature = "json")]
+use crate::archiver::zstd_compression_level;
+use crate::archiver::{
+    decompress_object, key_timestamp, sha256_hex, sidecar_chunk_key, verify_checksum,
+    LAST_TIMESTAMP_METADATA_KEY, MANIFEST_KEY_SUFFIX, RECORD_COUNT_METADATA_KEY,
+};
+use crate::measurement::Measurement;
+
+/// Suffix appended to a merged object's key to get the key of the record of the objects it's merged from
+pub const COMPACTED_FROM_KEY_SUFFIX: &str = ".compacted-from";
+
+/// Metadata key under which merged objects record how many objects they were merged from
+pub const COMPACTED_OBJECTS_METADATA_KEY: &str = "compacted-objects";
+
+/// Times uploading a merged object is retried, as the archiver's `--upload-retries` default
+const COMPACT_UPLOAD_RETRIES: u32 = 5;
+
+/// Delay before the first upload retry, as the archiver's `--upload-retry-delay` default
+const COMPACT_UPLOAD_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// What a `compact_archives` run did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Merged objects written
+    pub merged: usize,
+    /// Small objects deleted after being merged, including those of compactions finished from an interrupted run
+    pub removed: usize,
+}
+
+impl std::ops::AddAssign for CompactionReport {
+    fn add_assign(&mut self, other: Self) {
+        self.merged += other.merged;
+        self.removed += other.removed;
+    }
+}
+
+/// Key of the record of the objects the merged object at `key` was merged from
+pub fn compacted_from_key(key: &str) -> String {
+    format!("{}{}", key, COMPACTED_FROM_KEY_SUFFIX)
+}
+
+/// Key of the object merged from the objects at `keys`, which are in time order
+///
+/// The first key's timestamp with a suffix of how many objects were merged and the first 16 hex digits of the SHA-256
+/// of their keys, i.e. `radar-2d/2022-10-26T07:00:00+00:00_3c-5d41402abc4b2a76`. Any Kafka offsets in the first key are
+/// dropped, the merged object's manifest records the offsets of every object merged into it.
+pub fn compacted_key(keys: &[String]) -> String {
+    let first = keys.first().map(String::as_str).unwrap_or_default();
+    let (parent, name) = match first.rsplit_once('/') {
+        Some((parent, name)) => (Some(parent), name),
+        None => (None, first),
+    };
+    let timestamp = name
+        .split_once('_')
+        .map_or(name, |(timestamp, _)| timestamp);
+    let hash = sha256_hex(keys.join("\n").as_bytes());
+    let name = format!("{}_{}c-{}", timestamp, keys.len(), &hash[..16]);
+    match parent {
+        Some(parent) => format!("{}/{}", parent, name),
+        None => name,
+    }
+}
+
+/// Merge runs of small archive objects of the archiver's sensor into objects of up to `target_size` bytes
+///
+/// Compacts the bucket and sensor `cli` archives to, writing merged objects with its `--codec` (and
+/// `--zstd-dictionary`, which objects compressed with it also need to be read) and server-side encryption. See
+/// `compact_archives_in_store`.
+///
+/// # Errors
+///
+/// - ArchiveError::ConfigError: If the S3 client can't be built from `cli`
+/// - Same as `compact_archives_in_store`
+///
+/// # Examples
+///
+/// ```no_run
+/// let cli = Cli::parse();
+/// let report = compact_archives::<RadarMeasurement2d>(&cli, 64 * 1024 * 1024).await?;
+/// println!("Merged {} objects into {}", report.removed, report.merged);
+/// ```
+pub async fn compact_archives<M>(
+    cli: &Cli,
+    target_size: usize,
+) -> Result<CompactionReport, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let store = cli.build_object_store()?;
+    compact_archives_in_store::<M, _>(&store, cli.sensor_name(), target_size, &cli.codec()).await
+}
+
+/// Merge runs of small archive objects of `sensor` in `store` into objects of up to `target_size` bytes
+///
+/// Archive objects are grouped in key (time) order, only with others under the same prefix (so `--split-by-source`
+/// sources and Hive partitions aren't mixed). Consecutive objects smaller than `target_size` as stored (see
+/// `ObjectStore::size`, so objects are only downloaded to be merged) are grouped while the group's total stored size
+/// is at most `target_size`; each group of two or more is downloaded,
+/// concatenated into one chunk in time order, compressed with `codec` and uploaded. The merged object is read back and
+/// its checksum verified before the objects it was merged from (and their manifests) are deleted, and with the `json`
+/// feature the sensor's indexes are updated to list it in their place. Chunks sorted with
+/// `--sort-chunk-by` are left as they are, since merging them would lose their record offsets.
+///
+/// Compactions an earlier run was interrupted in are finished first, see the module documentation.
+///
+/// A group with an object that can't be decompressed (i.e. it needs a dictionary other than `codec`'s) or isn't a
+/// valid `ArchiveChunk` of `M` is skipped with a WARN, leaving its objects as they are, and the run carries on with
+/// the next group.
+///
+/// # Errors
+///
+/// - ArchiveError::StoreError: If listing the sensor's objects fails
+/// - ArchiveError::StoreObjectError: If getting, putting or deleting an object fails
+/// - ArchiveError::DecompressError: If a merged object, including one an interrupted run wrote, can't be decompressed
+/// - ArchiveError::ChecksumMismatch: If the merged object doesn't read back as written
+pub async fn compact_archives_in_store<M, S>(
+    store: &S,
+    sensor: &str,
+    target_size: usize,
+    codec: &Codec,
+) -> Result<CompactionReport, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore + ?Sized,
+{
+    let prefix = format!("{}/", sensor);
+    let dictionaries: Vec<ZstdDictionary> = codec.dictionary().cloned().into_iter().collect();
+    let mut report = CompactionReport::default();
+
+    for key in store.list(&prefix).await? {
+        if let Some(merged_key) = key.strip_suffix(COMPACTED_FROM_KEY_SUFFIX) {
+            report.removed += resume_compaction(store, sensor, merged_key, &dictionaries).await?;
+        }
+    }
+
+    let keys = store.list(&prefix).await?;
+    let manifests: HashSet<&str> = keys
+        .iter()
+        .filter_map(|key| key.strip_suffix(MANIFEST_KEY_SUFFIX))
+        .collect();
+
+    let mut group: Vec<String> = Vec::new();
+    let mut group_size = 0;
+    for key in &keys {
+        #[cfg(feature = "json")]
+        if is_index_key(sensor, key) {
+            continue;
+        }
+        if key_timestamp(key).is_none() {
+            if sidecar_chunk_key(key).is_none() && !key.ends_with(COMPACTED_FROM_KEY_SUFFIX) {
+                event!(
+                    Level::WARN,
+                    "Skipping archive key {} without a timestamp suffix",
+                    key
+                );
+            }
+            continue;
+        }
+
+        let size = store
+            .size(key)
+            .await
+            .map_err(|source| store_object_error(store, key, source))? as usize;
+        let mergeable = size < target_size && !is_sorted(store, key, &manifests).await?;
+        let same_prefix = group
+            .first()
+            .map_or(false, |first| parent(first) == parent(key));
+        if !mergeable || !same_prefix || group_size + size > target_size {
+            report += merge_group::<M, S>(
+                store,
+                sensor,
+                std::mem::take(&mut group),
+                codec,
+                &dictionaries,
+            )
+            .await?;
+            group_size = 0;
+        }
+        if mergeable {
+            group_size += size;
+            group.push(key.clone());
+        }
+    }
+    report += merge_group::<M, S>(store, sensor, group, codec, &dictionaries).await?;
+
+    event!(
+        Level::INFO,
+        merged = report.merged,
+        removed = report.removed,
+        "Compacted archives for {} in {}",
+        sensor,
+        store.location()
+    );
+    Ok(report)
+}
+
+/// The prefix of `key` up to its last '/'
+fn parent(key: &str) -> &str {
+    key.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Whether the archive object at `key` is a chunk sorted with `--sort-chunk-by`, according to its manifest
+#[cfg(feature = "json")]
+async fn is_sorted<S>(store: &S, key: &str, manifests: &HashSet<&str>) -> Result<bool, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    if !manifests.contains(key) {
+        return Ok(false);
+    }
+    Ok(get_object_manifest(store, key)
+        .await?
+        .sort_chunk_by
+        .is_some())
+}
+
+/// Whether the archive object at `key` is a chunk sorted with `--sort-chunk-by`, unknown without manifests
+#[cfg(not(feature = "json"))]
+async fn is_sorted<S>(
+    _store: &S,
+    _key: &str,
+    _manifests: &HashSet<&str>,
+) -> Result<bool, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    Ok(false)
+}
+
+/// Merge the objects at the keys in `group` into one object and delete them, if there's more than one
+///
+/// Nothing is merged if any of them can't be read, see `compact_archives_in_store`.
+async fn merge_group<M, S>(
+    store: &S,
+    sensor: &str,
+    keys: Vec<String>,
+    codec: &Codec,
+    dictionaries: &[ZstdDictionary],
+) -> Result<CompactionReport, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore + ?Sized,
+{
+    if keys.len() < 2 {
+        return Ok(CompactionReport::default());
+    }
+
+    let mut measurements = Vec::new();
+    for key in &keys {
+        let object = get_object(store, key).await?;
+        let chunk = match decompress_object(key, &object, dictionaries) {
+            Ok(data) => deserialize_chunk::<M>(&data),
+            Err(e) => Err(e),
+        };
+        match chunk {
+            Ok(chunk) => measurements.extend(chunk),
+            // Only this group is left as it is, i.e. for an object compressed with another dictionary
+            Err(e) => {
+                event!(
+                    Level::WARN,
+                    "Skipping compaction of {} objects from {}, {} can't be read. {}",
+                    keys.len(),
+                    keys[0],
+                    key,
+                    e
+                );
+                return Ok(CompactionReport::default());
+            }
+        }
+    }
+    // Objects are in key order, so a stable sort only reorders measurements of objects that overlap in time
+    measurements.sort_by_key(|m| m.timestamp());
+    let record_count = measurements.len();
+    #[cfg(feature = "json")]
+    let timestamps = (
+        measurements.first().map(|m| m.timestamp()),
+        measurements.last().map(|m| m.timestamp()),
+    );
+
+    let merged_key = compacted_key(&keys);
+    let from_key = compacted_from_key(&merged_key);
+    let from = StoredObject {
+        body: keys.join("\n").into_bytes(),
+        content_type: Some("text/plain".to_owned()),
+        ..StoredObject::default()
+    };
+    put_object(store, &from_key, from).await?;
+
+    let last_timestamp = measurements.last().map(|m| m.timestamp());
+    let data = serialize_chunk(measurements)?.finished_data().to_vec();
+    let mut metadata = HashMap::from([
+        (
+            RECORD_COUNT_METADATA_KEY.to_owned(),
+            record_count.to_string(),
+        ),
+        (
+            COMPACTED_OBJECTS_METADATA_KEY.to_owned(),
+            keys.len().to_string(),
+        ),
+    ]);
+    if let Some(last_timestamp) = last_timestamp {
+        metadata.insert(
+            LAST_TIMESTAMP_METADATA_KEY.to_owned(),
+            last_timestamp.to_rfc3339(),
+        );
+    }
+    let compressed_bytes = put_with_retry(
+        store,
+        &merged_key,
+        &data,
+        codec,
+        COMPACT_UPLOAD_RETRIES,
+        COMPACT_UPLOAD_RETRY_DELAY,
+        Some(metadata),
+    )
+    .await
+    .map_err(|source| store_object_error(store, &merged_key, source))?;
+    let merged = verify_merged(store, &merged_key, dictionaries).await?;
+
+    #[cfg(feature = "json")]
+    if let (Some(first_timestamp), Some(last_timestamp)) = timestamps {
+        let mut offsets = Vec::new();
+        for key in &keys {
+            if let Ok(manifest) =
+                get_manifest(store, &format!("{}{}", key, MANIFEST_KEY_SUFFIX)).await
+            {
+                offsets.extend(manifest.offsets);
+            }
+        }
+        let manifest = Manifest {
+            record_count,
+            first_timestamp,
+            last_timestamp,
+            uncompressed_bytes: data.len(),
+            compressed_bytes,
+            offsets,
+            codec: codec.kind(),
+            compression_level: codec.zstd_level().map(zstd_compression_level).transpose()?,
+            dictionary_id: codec.dictionary().map(ZstdDictionary::id),
+            sort_chunk_by: None,
+            record_offsets: Vec::new(),
+        };
+        let manifest_key = format!("{}{}", merged_key, MANIFEST_KEY_SUFFIX);
+        put_manifest(store, &manifest_key, &manifest)
+            .await
+            .map_err(|source| store_object_error(store, &manifest_key, source))?;
+    }
+
+    reindex_merged(store, sensor, &merged_key, &merged, &keys).await?;
+    let removed = delete_sources(store, &keys).await?;
+    delete_object(store, &from_key).await?;
+    event!(
+        Level::INFO,
+        record_count,
+        compressed_bytes,
+        "Merged {} archive objects into {} in {}",
+        keys.len(),
+        merged_key,
+        store.location()
+    );
+
+    Ok(CompactionReport { merged: 1, removed })
+}
+
+/// Finish or abandon the compaction into `merged_key` an earlier run was interrupted in, returning the objects removed
+///
+/// If the merged object was written, the objects it was merged from are deleted. Otherwise nothing was deleted yet, so
+/// only the record of the objects it's merged from is.
+async fn resume_compaction<S>(
+    store: &S,
+    sensor: &str,
+    merged_key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<usize, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let from_key = compacted_from_key(merged_key);
+    let removed = match store.size(merged_key).await {
+        Ok(_) => {
+            let merged = verify_merged(store, merged_key, dictionaries).await?;
+            let from = get_object(store, &from_key).await?;
+            let keys: Vec<String> = String::from_utf8_lossy(&from.body)
+                .lines()
+                .map(str::to_owned)
+                .collect();
+            event!(
+                Level::INFO,
+                "Finishing interrupted compaction of {} archive objects into {}",
+                keys.len(),
+                merged_key
+            );
+            reindex_merged(store, sensor, merged_key, &merged, &keys).await?;
+            delete_sources(store, &keys).await?
+        }
+        Err(ObjectStoreError::NotFound(_)) => {
+            event!(
+                Level::WARN,
+                "Abandoning interrupted compaction into {}, the merged object was never written",
+                merged_key
+            );
+            0
+        }
+        Err(source) => return Err(store_object_error(store, merged_key, source)),
+    };
+    delete_object(store, &from_key).await?;
+
+    Ok(removed)
+}
+
+/// Read back the merged object at `key` and check it against its checksum, returning it
+async fn verify_merged<S>(
+    store: &S,
+    key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<StoredObject, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let object = get_object(store, key).await?;
+    let data = decompress_object(key, &object, dictionaries)?;
+    verify_checksum(key, Some(&object.metadata), &data)?;
+    Ok(object)
+}
+
+/// List the `merged` object at `merged_key` in `sensor`'s indexes in place of the objects at `keys` it was merged from
+///
+/// The entry's timestamps and record count come from the merged object's key and metadata, so an interrupted
+/// compaction can be reindexed when it's resumed.
+#[cfg(feature = "json")]
+async fn reindex_merged<S>(
+    store: &S,
+    sensor: &str,
+    merged_key: &str,
+    merged: &StoredObject,
+    keys: &[String],
+) -> Result<(), ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let first_timestamp = match key_timestamp(merged_key) {
+        Some(timestamp) => timestamp,
+        None => return Ok(()),
+    };
+    let last_timestamp = merged
+        .metadata
+        .get(LAST_TIMESTAMP_METADATA_KEY)
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map_or(first_timestamp, |timestamp| timestamp.with_timezone(&Utc));
+    let entry = IndexEntry {
+        key: merged_key.to_owned(),
+        first_timestamp,
+        last_timestamp,
+        record_count: merged
+            .metadata
+            .get(RECORD_COUNT_METADATA_KEY)
+            .and_then(|count| count.parse().ok())
+            .unwrap_or_default(),
+        compressed_bytes: merged.body.len(),
+    };
+
+    let keys: HashSet<&str> = keys.iter().map(String::as_str).collect();
+    remove_from_indexes(store, sensor, &keys, Some(&entry))
+        .await
+        .map_err(|source| store_object_error(store, &index_prefix(sensor), source))?;
+    Ok(())
+}
+
+/// Without the `json` feature there are no indexes to update
+#[cfg(not(feature = "json"))]
+async fn reindex_merged<S>(
+    _store: &S,
+    _sensor: &str,
+    _merged_key: &str,
+    _merged: &StoredObject,
+    _keys: &[String],
+) -> Result<(), ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    Ok(())
+}
+
+/// Delete the objects at `keys` and their manifests and record offsets, returning how many objects were deleted
+async fn delete_sources<S>(store: &S, keys: &[String]) -> Result<usize, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    for key in keys {
+        delete_object(store, key).await?;
+        delete_object(store, &format!("{}{}", key, MANIFEST_KEY_SUFFIX)).await?;
+        delete_object(store, &record_offsets_key(key)).await?;
+    }
+    Ok(keys.len())
+}
+
+#[cfg(feature = "json")]
+async fn get_object_manifest<S>(store: &S, key: &str) -> Result<Manifest, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let manifest_key = format!("{}{}", key, MANIFEST_KEY_SUFFIX);
+    get_manifest(store, &manifest_key)
+        .await
+        .map_err(|source| store_object_error(store, &manifest_key, source))
+}
+
+async fn get_object<S>(store: &S, key: &str) -> Result<StoredObject, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    store
+        .get(key)
+        .await
+        .map_err(|source| store_object_error(store, key, source))
+}
+
+async fn put_object<S>(store: &S, key: &str, object: StoredObject) -> Result<(), ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    store
+        .put(key, object)
+        .await
+        .map_err(|source| store_object_error(store, key, source))
+}
+
+async fn delete_object<S>(store: &S, key: &str) -> Result<(), ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    store
+        .delete(key)
+        .await
+        .map_err(|source| store_object_error(store, key, source))
+}
+
+fn store_object_error<S>(store: &S, key: &str, source: ObjectStoreError) -> ArchiveError
+where
+    S: ObjectStore + ?Sized,
+{
+    ArchiveError::StoreObjectError {
+        location: store.location().to_owned(),
+        key: key.to_owned(),
+        source,
+    }
+}
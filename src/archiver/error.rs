@@ -1,15 +1,228 @@
 //! Error type for archiving
 
 use aws_sdk_s3::Error;
+use flatbuffers::InvalidFlatbuffer;
 use redpanda::error::KafkaError;
 
+use crate::archiver::chunk::OffsetRange;
+use crate::archiver::store::ObjectStoreError;
+use crate::batch::BatchError;
+
 /// Error for all archiving-related issues
 #[derive(thiserror::Error, Debug)]
 pub enum ArchiveError {
     /// Wrap archiving-related Kafka errors
-    #[error("A Kafka error occurred")]
+    #[error("A Kafka error occurred: {0}")]
     KafkaError(KafkaError),
+    /// A Kafka operation on a consumed message failed, i.e. producing it to the dead letter topic
+    #[error(
+        "Kafka error for the message on {topic} partition {partition} at offset {offset}: {source}"
+    )]
+    KafkaMessageError {
+        /// Topic the message was consumed from
+        topic: String,
+        /// Partition the message was consumed from
+        partition: i32,
+        /// Offset of the message
+        offset: i64,
+        /// The Kafka error
+        source: KafkaError,
+    },
+    /// Committing the consumer offsets after archiving a chunk failed
+    #[error("Failed to commit offsets {} on {topic}: {source}", display_offset_ranges(.offsets))]
+    CommitError {
+        /// Topic the chunk was consumed from
+        topic: String,
+        /// Offsets covered by the chunk in each partition
+        offsets: Vec<OffsetRange>,
+        /// The Kafka error
+        source: KafkaError,
+    },
     /// Wrap archiving-related s3 errors
-    #[error("A S3 error occurred")]
+    #[error("A S3 error occurred: {0}")]
     S3Error(Error),
+    /// An S3 request for an object failed
+    #[error("S3 error for {key} in bucket {bucket}: {source}")]
+    S3ObjectError {
+        /// Bucket of the object
+        bucket: String,
+        /// Key of the object
+        key: String,
+        /// The S3 error
+        source: Error,
+    },
+    /// Wrap errors from the object store archives are written to
+    #[error("An object store error occurred: {0}")]
+    StoreError(ObjectStoreError),
+    /// Storing an object in the object store failed
+    #[error("Object store error for {key} at {location}: {source}")]
+    StoreObjectError {
+        /// Bucket name or root directory of the store (see `ObjectStore::location`)
+        location: String,
+        /// Key of the object
+        key: String,
+        /// The object store error
+        source: ObjectStoreError,
+    },
+    /// An archive object's body is truncated, isn't valid for its content encoding, or can't be decompressed
+    #[error("Failed to decompress {key}: {source}")]
+    DecompressError {
+        /// Key of the object
+        key: String,
+        /// The decompression error
+        source: std::io::Error,
+    },
+    /// An archived object isn't a valid `ArchiveChunk` flatbuffer
+    #[error("Invalid archive chunk")]
+    InvalidChunk(InvalidFlatbuffer),
+    /// An archived object is an archive container (see `archiver::format`) that can't be read
+    #[error("Invalid archive container: {0}")]
+    FormatError(FormatError),
+    /// A measurement stored in an archive chunk failed to deserialize
+    #[error("Failed to deserialize an archived measurement: {0}")]
+    DeserializeError(String),
+    /// A chunk would be larger than the archiver can safely serialize (see `chunk::MAX_CHUNK_BYTES`)
+    #[error("Archive chunk would be {bytes} bytes, too large for a 2GB flatbuffer")]
+    ChunkTooLarge {
+        /// Serialized size of the chunk, or of the single measurement that doesn't fit in a chunk
+        bytes: usize,
+    },
+    /// A sorted chunk's recorded offsets don't match its measurements
+    #[error("Can't restore the consumption order of a sorted chunk: {0}")]
+    OrderError(String),
+    /// An archive object's uncompressed bytes don't match the checksum stored with it
+    #[error("Checksum mismatch for {key}, expected sha256 {expected} but got {actual}")]
+    ChecksumMismatch {
+        /// Key of the object
+        key: String,
+        /// Checksum stored with the object
+        expected: String,
+        /// Checksum of the downloaded bytes
+        actual: String,
+    },
+    /// An archive object has no checksum to verify, i.e. it was uploaded before checksums were stored
+    #[error("Object {0} has no sha256 checksum")]
+    MissingChecksum(String),
+    /// More messages failed to deserialize than `--max-dead-letters` allows
+    #[error("{0} messages failed to deserialize, more than --max-dead-letters allows")]
+    TooManyDeadLetters(u64),
+    /// The Prometheus metrics endpoint couldn't be started
+    #[error("Failed to start the metrics endpoint: {0}")]
+    MetricsError(String),
+    /// The archiver's configuration is invalid
+    #[error("Invalid configuration: {0}")]
+    ConfigError(ConfigError),
+}
+
+/// Invalid archiver configuration, i.e. a typo on the command line
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The S3 endpoint isn't a URL the S3 client can send requests to
+    #[error(
+        "Invalid S3 endpoint {endpoint:?}: {reason}, expected a URL like http://localhost:9000"
+    )]
+    InvalidEndpoint {
+        /// Endpoint as configured
+        endpoint: String,
+        /// What's wrong with it
+        reason: String,
+    },
+    /// The S3 region is empty
+    #[error("The S3 region is empty, expected a region like us-east-1")]
+    EmptyRegion,
+}
+
+/// Kafka errors without a message or offsets to attribute them to, i.e. building or subscribing a consumer
+///
+/// Prefer `KafkaMessageError` or `CommitError` where the topic, partition, and offset are known.
+impl From<KafkaError> for ArchiveError {
+    fn from(e: KafkaError) -> Self {
+        ArchiveError::KafkaError(e)
+    }
+}
+
+/// S3 errors without an object to attribute them to, i.e. listing a bucket
+///
+/// Prefer `S3ObjectError` where the bucket and key are known.
+impl From<Error> for ArchiveError {
+    fn from(e: Error) -> Self {
+        ArchiveError::S3Error(e)
+    }
+}
+
+/// Object store errors without an object to attribute them to
+///
+/// Prefer `StoreObjectError` where the key is known.
+impl From<ObjectStoreError> for ArchiveError {
+    fn from(e: ObjectStoreError) -> Self {
+        ArchiveError::StoreError(e)
+    }
+}
+
+impl From<FormatError> for ArchiveError {
+    fn from(e: FormatError) -> Self {
+        ArchiveError::FormatError(e)
+    }
+}
+
+impl From<ConfigError> for ArchiveError {
+    fn from(e: ConfigError) -> Self {
+        ArchiveError::ConfigError(e)
+    }
+}
+
+/// Offset ranges as "partition:first-last", comma separated
+fn display_offset_ranges(offsets: &[OffsetRange]) -> String {
+    offsets
+        .iter()
+        .map(|range| {
+            format!(
+                "{}:{}-{}",
+                range.partition, range.first_offset, range.last_offset
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl From<BatchError> for ArchiveError {
+    fn from(e: BatchError) -> Self {
+        match e {
+            BatchError::TooLarge(bytes) => ArchiveError::ChunkTooLarge { bytes },
+            BatchError::InvalidBatch(e) => ArchiveError::InvalidChunk(e),
+            BatchError::DeserializeError(e) => ArchiveError::DeserializeError(e),
+        }
+    }
+}
+
+/// Error reading or writing the archive container format (see `archiver::format`)
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The data doesn't start with the archive magic bytes
+    #[error("Not an archive container, bad magic bytes")]
+    BadMagic,
+    /// The container was written with a format version this crate can't read
+    #[error("Unsupported archive format version {0}")]
+    UnsupportedVersion(u8),
+    /// The payload codec byte isn't a known codec
+    #[error("Unknown archive codec {0}")]
+    UnknownCodec(u8),
+    /// The flags byte has bits set that this version doesn't define
+    #[error("Unknown archive flags {0:#010b}")]
+    UnknownFlags(u8),
+    /// The reserved header byte isn't zero
+    #[error("Archive reserved header byte is {0:#04x}, expected 0")]
+    ReservedByte(u8),
+    /// The data ended before a length-prefixed field
+    #[error("Archive container is truncated")]
+    Truncated,
+    /// There is data after the payload
+    #[error("Archive container has {0} trailing bytes")]
+    TrailingBytes(usize),
+    /// A schema or payload doesn't fit in a u32 length prefix
+    #[error("Archive field of {0} bytes is too large")]
+    TooLarge(usize),
+    /// Compressing or decompressing the payload failed
+    #[error("Archive codec error: {0}")]
+    Codec(String),
 }
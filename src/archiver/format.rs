@@ -0,0 +1,240 @@
+//! Versioned archive container format
+//!
+//! A self-describing wrapper around an `ArchiveChunk` flatbuffer (see `flatbuffers/archive.fbs`) so archives can be
+//! read from any language with a flatbuffers implementation and a zstd decoder, without depending on this crate.
+//!
+//! # Byte layout (version 1)
+//!
+//! All integers are little endian.
+//!
+//! | Offset | Size | Field                                                               |
+//! |--------|------|---------------------------------------------------------------------|
+//! | 0      | 4    | Magic bytes, `OSAF`                                                 |
+//! | 4      | 1    | Format version, `1`                                                 |
+//! | 5      | 1    | Codec of the payload: `0` = none, `1` = zstd                        |
+//! | 6      | 1    | Flags: bit 0 set if a schema is embedded, other bits must be 0      |
+//! | 7      | 1    | Reserved, must be 0                                                 |
+//! | 8      | 4    | Schema length `S` (u32), only present if the schema flag is set     |
+//! | 12     | S    | Binary flatbuffers schema (`.bfbs`) of the archived measurement     |
+//! | ..     | 4    | Payload length `P` (u32), the length of the payload as stored       |
+//! | ..     | P    | `ArchiveChunk` flatbuffer, encoded with the codec                   |
+//!
+//! Nothing may follow the payload. Readers must reject unknown versions, codecs, and flags rather than guess.
+//!
+//! The schema is the measurement's schema, not `archive.fbs`: the `ArchiveChunk` stores each measurement as the
+//! bytes from `Measurement::to_bytes`, so embedding the schema lets a reader decode them with flatbuffers
+//! reflection.
+//!
+//! Any change to this layout must bump `FORMAT_VERSION`.
+//!
+//! The archiver uploads bare `ArchiveChunk`s unless it's run with `--container`, which wraps each chunk in a
+//! container with an uncompressed payload and stores it with the `--codec` content encoding as usual. The crate's
+//! readers accept both (see `chunk_bytes`), whatever codec a container was written with.
+
+use std::borrow::Cow;
+
+use crate::archiver::codec::{decompress, Codec};
+use crate::archiver::error::FormatError;
+use crate::archiver::ZSTD_DEFAULT_LEVEL;
+
+/// Magic bytes at the start of every archive container
+pub const MAGIC: [u8; 4] = *b"OSAF";
+
+/// Version of the container layout written by `write_archive`
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Flag bit set when a schema is embedded
+pub const FLAG_SCHEMA: u8 = 0b0000_0001;
+
+/// Size of the fixed header before the optional schema
+pub const HEADER_LEN: usize = 8;
+
+/// Codec byte of an uncompressed payload
+pub const CODEC_NONE: u8 = 0;
+
+/// Codec byte of a zstd compressed payload, a single zstd frame without a dictionary
+pub const CODEC_ZSTD: u8 = 1;
+
+/// Codec byte a container stores `codec` as
+///
+/// # Errors
+///
+/// - FormatError::Codec: If version 1 containers can't hold payloads compressed with the codec, i.e. LZ4, Snappy, or
+///   zstd with a dictionary
+fn codec_byte(codec: &Codec) -> Result<u8, FormatError> {
+    match codec {
+        Codec::None => Ok(CODEC_NONE),
+        Codec::Zstd { .. } => Ok(CODEC_ZSTD),
+        codec => Err(FormatError::Codec(format!(
+            "{:?} payloads can't be stored in a version {} container",
+            codec.kind(),
+            FORMAT_VERSION
+        ))),
+    }
+}
+
+/// Codec a container's codec byte stands for
+///
+/// The compression level isn't stored, so zstd payloads read back as `Codec::Zstd` at `ZSTD_DEFAULT_LEVEL`.
+///
+/// # Errors
+///
+/// - FormatError::UnknownCodec: If the byte isn't a codec this version defines
+fn codec_from_byte(value: u8) -> Result<Codec, FormatError> {
+    match value {
+        CODEC_NONE => Ok(Codec::None),
+        CODEC_ZSTD => Ok(Codec::Zstd {
+            level: ZSTD_DEFAULT_LEVEL,
+        }),
+        codec => Err(FormatError::UnknownCodec(codec)),
+    }
+}
+
+/// Contents of an archive container, with the payload decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveContents {
+    /// Codec the payload was stored with, see `codec_from_byte`
+    pub codec: Codec,
+    /// Embedded binary flatbuffers schema, if any
+    pub schema: Option<Vec<u8>>,
+    /// Decoded `ArchiveChunk` flatbuffer
+    pub chunk: Vec<u8>,
+}
+
+/// Write an `ArchiveChunk` flatbuffer into a version 1 archive container
+///
+/// Only `Codec::None` and `Codec::Zstd` payloads can be stored in a version 1 container.
+///
+/// # Errors
+///
+/// - FormatError::TooLarge: If the schema or the encoded payload is larger than u32::MAX bytes
+/// - FormatError::Codec: If the codec can't be stored in a container, the compression level is invalid, or
+///   compression fails
+pub fn write_archive(
+    chunk: &[u8],
+    codec: &Codec,
+    schema: Option<&[u8]>,
+) -> Result<Vec<u8>, FormatError> {
+    let codec_byte = codec_byte(codec)?;
+    let payload = codec
+        .compress(chunk)
+        .map_err(|e| FormatError::Codec(e.to_string()))?;
+
+    let schema_len = schema.map_or(0, |schema| 4 + schema.len());
+    let mut data = Vec::with_capacity(HEADER_LEN + schema_len + 4 + payload.len());
+    data.extend_from_slice(&MAGIC);
+    data.push(FORMAT_VERSION);
+    data.push(codec_byte);
+    data.push(if schema.is_some() { FLAG_SCHEMA } else { 0 });
+    data.push(0);
+    if let Some(schema) = schema {
+        write_length_prefixed(&mut data, schema)?;
+    }
+    write_length_prefixed(&mut data, &payload)?;
+
+    Ok(data)
+}
+
+/// Read a version 1 archive container, decoding the payload
+///
+/// # Errors
+///
+/// - FormatError::BadMagic: If the data doesn't start with `MAGIC`
+/// - FormatError::UnsupportedVersion: If the version isn't `FORMAT_VERSION`
+/// - FormatError::UnknownCodec, FormatError::UnknownFlags: If the header has values this version doesn't define
+/// - FormatError::ReservedByte: If the reserved header byte isn't zero
+/// - FormatError::Truncated, FormatError::TrailingBytes: If the lengths don't match the data
+/// - FormatError::Codec: If the payload fails to decompress
+pub fn read_archive(data: &[u8]) -> Result<ArchiveContents, FormatError> {
+    let (codec, schema, payload) = parse_archive(data)?;
+    let chunk = decode_payload(&codec, payload)?.into_owned();
+
+    Ok(ArchiveContents {
+        codec,
+        schema: schema.map(<[u8]>::to_vec),
+        chunk,
+    })
+}
+
+/// The `ArchiveChunk` flatbuffer in a decoded archive object, which is either a container or a bare `ArchiveChunk`
+///
+/// Data that starts with `MAGIC` is read as a container, and borrowed from when its payload is uncompressed (as the
+/// archiver writes it). Anything else is returned as-is, to be read as a bare `ArchiveChunk`.
+///
+/// # Errors
+///
+/// - Same as `read_archive`, for data that starts with `MAGIC`
+pub fn chunk_bytes(data: &[u8]) -> Result<Cow<'_, [u8]>, FormatError> {
+    if !data.starts_with(&MAGIC) {
+        return Ok(Cow::Borrowed(data));
+    }
+    let (codec, _, payload) = parse_archive(data)?;
+
+    decode_payload(&codec, payload)
+}
+
+/// Check a container's header and lengths, returning its codec, schema, and still encoded payload
+fn parse_archive(data: &[u8]) -> Result<(Codec, Option<&[u8]>, &[u8]), FormatError> {
+    if data.len() < HEADER_LEN {
+        return Err(FormatError::Truncated);
+    }
+    if data[0..4] != MAGIC {
+        return Err(FormatError::BadMagic);
+    }
+    if data[4] != FORMAT_VERSION {
+        return Err(FormatError::UnsupportedVersion(data[4]));
+    }
+    let codec = codec_from_byte(data[5])?;
+    let flags = data[6];
+    if flags & !FLAG_SCHEMA != 0 {
+        return Err(FormatError::UnknownFlags(flags));
+    }
+    if data[7] != 0 {
+        return Err(FormatError::ReservedByte(data[7]));
+    }
+
+    let mut rest = &data[HEADER_LEN..];
+    let schema = if flags & FLAG_SCHEMA != 0 {
+        Some(read_length_prefixed(&mut rest)?)
+    } else {
+        None
+    };
+    let payload = read_length_prefixed(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(FormatError::TrailingBytes(rest.len()));
+    }
+
+    Ok((codec, schema, payload))
+}
+
+fn decode_payload<'a>(codec: &Codec, payload: &'a [u8]) -> Result<Cow<'a, [u8]>, FormatError> {
+    match codec {
+        Codec::None => Ok(Cow::Borrowed(payload)),
+        codec => decompress(codec.content_encoding(), payload)
+            .map(Cow::Owned)
+            .map_err(|e| FormatError::Codec(e.to_string())),
+    }
+}
+
+fn write_length_prefixed(data: &mut Vec<u8>, bytes: &[u8]) -> Result<(), FormatError> {
+    let len = u32::try_from(bytes.len()).map_err(|_| FormatError::TooLarge(bytes.len()))?;
+    data.extend_from_slice(&len.to_le_bytes());
+    data.extend_from_slice(bytes);
+
+    Ok(())
+}
+
+fn read_length_prefixed<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], FormatError> {
+    if data.len() < 4 {
+        return Err(FormatError::Truncated);
+    }
+    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let end = 4usize.checked_add(len).ok_or(FormatError::Truncated)?;
+    if data.len() < end {
+        return Err(FormatError::Truncated);
+    }
+
+    let bytes = &data[4..end];
+    *data = &data[end..];
+    Ok(bytes)
+}
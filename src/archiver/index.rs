@@ -0,0 +1,290 @@
+//! Daily index objects listing the archive objects uploaded for a sensor
+//!
+//! Finding the archive objects for a time range means listing every key under the sensor's prefix, which gets slow
+//! with thousands of objects. `append_to_index` adds an entry per uploaded chunk to a small JSON index object at
+//! `{sensor}/index/YYYY-MM-DD.json` for the UTC day it was uploaded, so queries can read a day's index instead.
+//! The archiver appends every chunk it uploads with `append_to_index_in_store`, and compaction and expiry remove the
+//! objects they delete with `remove_from_indexes`. Every update is a conditional write against the index's tag (its
+//! ETag on S3), retried when another writer changed the index first.
+
+use aws_sdk_s3::{Client, Error};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::{event, Level};
+
+use crate::archiver::store::{ObjectStore, ObjectStoreError, S3ObjectStore, StoredObject};
+use crate::archiver::{retry_delay, Encryption};
+
+/// Prefix under a sensor's prefix that its daily index objects are stored at
+pub const INDEX_PREFIX: &str = "index";
+
+/// Times `update_index_in_store` rereads an index after another writer changed it, before giving up
+pub const INDEX_APPEND_RETRIES: u32 = 10;
+
+/// Delay before the first reread of an index another writer changed
+const INDEX_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// One archive object in a daily index
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Key of the archive object
+    pub key: String,
+    /// Earliest measurement timestamp in the object
+    pub first_timestamp: DateTime<Utc>,
+    /// Latest measurement timestamp in the object
+    pub last_timestamp: DateTime<Utc>,
+    /// Number of measurements in the object
+    pub record_count: usize,
+    /// Size of the object as stored
+    pub compressed_bytes: usize,
+}
+
+/// The archive objects uploaded for a sensor in one UTC day, in the order they were appended
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveIndex {
+    /// One entry per archive object
+    pub entries: Vec<IndexEntry>,
+}
+
+impl ArchiveIndex {
+    /// Add `entry`, replacing any entry for the same key so re-uploading a chunk doesn't list it twice
+    pub fn insert(&mut self, entry: IndexEntry) {
+        match self.entries.iter_mut().find(|e| e.key == entry.key) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Entries with measurements in the window from `start` (inclusive) to `end` (exclusive)
+    pub fn overlapping(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> impl Iterator<Item = &IndexEntry> {
+        self.entries
+            .iter()
+            .filter(move |e| e.first_timestamp < end && e.last_timestamp >= start)
+    }
+}
+
+/// Key of `sensor`'s index for the UTC day `date`, i.e. `radar-2d/index/2022-10-26.json`
+pub fn index_key(sensor: &str, date: NaiveDate) -> String {
+    format!("{}{}.json", index_prefix(sensor), date.format("%Y-%m-%d"))
+}
+
+/// Prefix of every one of `sensor`'s index keys, i.e. `radar-2d/index/`
+pub fn index_prefix(sensor: &str) -> String {
+    format!("{}/{}/", sensor, INDEX_PREFIX)
+}
+
+/// Whether `key` is one of `sensor`'s index objects rather than an archive object, so listings of the sensor's
+/// archives can skip it
+pub fn is_index_key(sensor: &str, key: &str) -> bool {
+    key.starts_with(&index_prefix(sensor))
+}
+
+/// Add an entry for an uploaded archive object to `sensor`'s index for the current UTC day
+///
+/// `append_to_index_in_store` on an `S3ObjectStore`, so concurrent writers are detected with the index's ETag.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error::Unhandled: If the index isn't valid JSON, or another writer kept changing it
+/// - aws_sdk_s3::Error: If reading or writing the index fails
+///
+/// # Examples
+///
+/// ```no_run
+/// let entry = IndexEntry {
+///     key: key.clone(),
+///     first_timestamp: uploaded.first_timestamp,
+///     last_timestamp: uploaded.last_timestamp,
+///     record_count: uploaded.count,
+///     compressed_bytes: uploaded.compressed_bytes,
+/// };
+/// append_to_index(&client, bucket_name, "radar-2d", entry).await?;
+/// ```
+pub async fn append_to_index(
+    client: &Client,
+    bucket_name: &str,
+    sensor: &str,
+    entry: IndexEntry,
+) -> Result<ArchiveIndex, Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket_name, Encryption::None);
+    Ok(append_to_index_in_store(&store, sensor, entry).await?)
+}
+
+/// Add an entry for an uploaded archive object to `sensor`'s index in `store` for the current UTC day
+///
+/// Reads, modifies, and writes the index like `update_index_in_store`, creating it if it's the day's first entry.
+/// Returns the index as written.
+///
+/// # Errors
+///
+/// - Same as `update_index_in_store`
+pub async fn append_to_index_in_store<S>(
+    store: &S,
+    sensor: &str,
+    entry: IndexEntry,
+) -> Result<ArchiveIndex, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let key = index_key(sensor, Utc::now().date_naive());
+    update_index_in_store(store, &key, |index| index.insert(entry.clone())).await
+}
+
+/// Apply `update` to the index at `key` in `store` and write it back, returning the index as written
+///
+/// Writers are optimistic: the index is written with `ObjectStore::put_if_match` against the tag it was read with,
+/// and if another writer changed it in between it's reread and `update` applied again, up to INDEX_APPEND_RETRIES
+/// times. How closely that guards against concurrent writers depends on the store, see `put_if_match`. An index
+/// `update` doesn't change isn't written, and one it leaves without entries is deleted.
+///
+/// # Errors
+///
+/// - ObjectStoreError::Other: If the index isn't valid JSON, or another writer kept changing it
+/// - ObjectStoreError: If reading, writing, or deleting the index fails
+pub async fn update_index_in_store<S, F>(
+    store: &S,
+    key: &str,
+    mut update: F,
+) -> Result<ArchiveIndex, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+    F: FnMut(&mut ArchiveIndex) + Send,
+{
+    for attempt in 0..=INDEX_APPEND_RETRIES {
+        let (mut index, tag) = get_index(store, key).await?;
+        let read = index.clone();
+        update(&mut index);
+        if index == read {
+            return Ok(index);
+        }
+        if index.entries.is_empty() {
+            store.delete(key).await?;
+            return Ok(index);
+        }
+
+        let object = StoredObject {
+            body: serde_json::to_vec(&index).map_err(|e| ObjectStoreError::Other(Box::new(e)))?,
+            content_type: Some("application/json".to_owned()),
+            ..StoredObject::default()
+        };
+        if store.put_if_match(key, object, tag.as_deref()).await? {
+            return Ok(index);
+        }
+
+        let delay = retry_delay(INDEX_RETRY_DELAY, attempt);
+        event!(
+            Level::DEBUG,
+            "Index {} changed while updating it, retrying ({}/{}) in {:?}",
+            key,
+            attempt + 1,
+            INDEX_APPEND_RETRIES,
+            delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+
+    Err(ObjectStoreError::Other(Box::from(format!(
+        "Index {} kept changing while updating it, gave up after {} retries",
+        key, INDEX_APPEND_RETRIES
+    ))))
+}
+
+/// Remove the entries for `keys` from every one of `sensor`'s indexes in `store`, returning how many were updated
+///
+/// For archive objects that were merged (`compact::compact_archives_in_store`) or expired (`expire_archives`). If
+/// there's a `replacement` (the merged object), it's added to the first index, by day, that listed one of `keys`.
+/// Each index is updated with `update_index_in_store`, so indexes left without entries are deleted.
+///
+/// # Errors
+///
+/// - ObjectStoreError: If listing the indexes fails, or the same as `update_index_in_store`
+pub async fn remove_from_indexes<S>(
+    store: &S,
+    sensor: &str,
+    keys: &HashSet<&str>,
+    replacement: Option<&IndexEntry>,
+) -> Result<usize, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let mut updated = 0;
+    let mut replaced = false;
+    for key in store.list(&index_prefix(sensor)).await? {
+        let mut removed = false;
+        update_index_in_store(store, &key, |index| {
+            let count = index.entries.len();
+            index.entries.retain(|e| !keys.contains(e.key.as_str()));
+            removed = index.entries.len() < count;
+            if let Some(replacement) = replacement.filter(|_| removed && !replaced) {
+                index.insert(replacement.clone());
+            }
+        })
+        .await?;
+
+        if removed {
+            updated += 1;
+            replaced = true;
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Read `sensor`'s index in `store` for the UTC day `date`, empty if nothing was indexed that day
+///
+/// # Errors
+///
+/// - ObjectStoreError::Other: If the index isn't valid JSON
+/// - ObjectStoreError: If the index can't be fetched
+pub async fn read_index_in_store<S>(
+    store: &S,
+    sensor: &str,
+    date: NaiveDate,
+) -> Result<ArchiveIndex, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let (index, _) = get_index(store, &index_key(sensor, date)).await?;
+    Ok(index)
+}
+
+/// Read `sensor`'s index for the UTC day `date`, empty if nothing was indexed that day
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error::Unhandled: If the body fails to download or isn't a valid index
+/// - aws_sdk_s3::Error: If the index can't be fetched
+pub async fn read_index(
+    client: &Client,
+    bucket_name: &str,
+    sensor: &str,
+    date: NaiveDate,
+) -> Result<ArchiveIndex, Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket_name, Encryption::None);
+    Ok(read_index_in_store(&store, sensor, date).await?)
+}
+
+/// The index at `key` and its tag, or an empty index and None if there's no index there yet
+async fn get_index<S>(
+    store: &S,
+    key: &str,
+) -> Result<(ArchiveIndex, Option<String>), ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    match store.get_tagged(key).await {
+        Ok((object, tag)) => {
+            let index = serde_json::from_slice(&object.body)
+                .map_err(|e| ObjectStoreError::Other(Box::new(e)))?;
+            Ok((index, tag))
+        }
+        Err(ObjectStoreError::NotFound(_)) => Ok((ArchiveIndex::default(), None)),
+        Err(e) => Err(e),
+    }
+}
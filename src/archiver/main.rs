@@ -9,17 +9,88 @@
 //!             docker compose with port 9000 exposed.
 //! - region: MINIO_REGION_NAME or AWS_DEFAULT_REGION. The s3 region to connect to.
 //! - bucket-name: Bucket to save sensor archive data to.
-//! - sensor-name: Name of the sensor to archive data from. This name is used to generate the Kafka topic name to subscribe to
-//!                ("{sensor-name}-measurements"), the Kafka group_id associated with the consumer ("{sensor-name}-archiver") and the tag to prepend all object names with ()
+//! - sensor-name: Name of the sensor to archive data from. This name is used to generate the Kafka group_id associated with
+//!                the consumer ("{sensor-name}-archiver") and the tag to prepend all object names with ("{sensor-name}/")
+//! - topic: Optional. Kafka topic to subscribe to, defaults to the archived Measurement's TOPIC_NAME.
 //! - chunk-size: How many sensor measurements to include in a single archive file. As long as the resulting file is <2gb
 //!               (the max size of a flatbuffer) and you have adequate system memory to store the flatbuffers before
 //!               they're constructed and written to s3. In practice, this should probably be in the low hundreds of mb, but depends
 //!               on the data production rate of the sensor.
+//! - min-chunk-size, max-chunk-size: Optional. Adapt the chunk size to the consumer lag between these bounds (each
+//!                                   defaults to chunk-size), which becomes the starting size. Chunks grow while the
+//!                                   archiver is catching up on a backlog and shrink once it's live.
+//! - max-chunk-bytes: Optional. Also flush a chunk once its uncompressed size reaches this many bytes, i.e. "64MB" or
+//!                    "256MiB", whichever of chunk-size and max-chunk-bytes is reached first. Keeps archive objects a
+//!                    similar size when measurements vary widely in size.
 //! - kafka-addresses: Hostname and ports, in Kafka form, of the brokers to connect to.
+//! - source-ids: Optional. Comma separated `source_id`s to archive, i.e. "radar-1,radar-2". Measurements from any
+//!               other source on the topic are skipped (and their offsets committed with the next chunk), so a subset
+//!               of sensors can be archived without a dedicated topic. If not set, every source is archived.
+//! - split-by-source: Optional. Keep a separate chunk per measurement `source_id` and write each source's chunks under
+//!                    their own key prefix ("{sensor-name}/{source-id}/{timestamp}") so a single sensor instance's
+//!                    data can be read without scanning every other source on the topic.
+//! - poll-timeout: Optional. Max time to wait for the next message before the topic is considered idle. Idle polls
+//!                 are logged at DEBUG. If not set, the archiver waits for messages indefinitely.
+//! - start-from: Optional, defaults to `committed`, resuming from the consumer group's committed offsets. For one-off
+//!               backfills, start every partition at `earliest`, `latest`, `offset:N`, or the first message at or
+//!               after `time:RFC3339` (by Kafka timestamp) instead. This moves the group's committed offsets before
+//!               subscribing, so stop any other archiver of the same sensor first.
+//! - sse: Optional. Request SSE-S3 server-side encryption for every uploaded object.
+//! - sse-kms-key-id: Optional. Request SSE-KMS server-side encryption with this KMS key for every uploaded object.
+//! - provenance: Optional. Stamp every archived object with S3 user metadata identifying the archiver instance
+//!               (hostname, process id, crate version, consumer group, and partitions covered).
+//! - max-open-sources: Optional, defaults to 64. Max number of per-source chunks held in memory when splitting by
+//!                     source. When exceeded, the least recently used source's chunk is flushed early.
+//! - codec: Optional, defaults to `zstd` (at `compression-level`). Compression for archive objects, one of `zstd`,
+//!          `lz4`, `snappy`, or `none`. lz4 and snappy compress worse but decompress much faster, for archives that
+//!          downstream jobs read far more often than the archiver writes them.
+//! - zstd-dictionary: Optional. Path of a zstd dictionary (trained with `opensensor::archiver::codec::train_dictionary`
+//!                    or `zstd --train` on sample measurements) to compress archive objects with when the codec is
+//!                    `zstd`, for sensors producing many small, similar measurements. The dictionary's id is stored in
+//!                    each object's `zstd-dictionary-id` metadata, and readers need the same dictionary to decompress
+//!                    them (`opensensor::archiver::download_object_zstd_dict`).
+//! - sort-chunk-by: Optional. Sort each chunk before it's serialized, by `sort-key` (`Measurement::sort_key`, the
+//!                  `source_id` then timestamp by default) or `timestamp`. Grouping similar measurements usually
+//!                  compresses much better, and the saving is logged per chunk. Sorted chunks aren't in consumption
+//!                  order, so with the `json` feature their manifests record every measurement's original offset
+//!                  (read them back with `opensensor::archiver::read_sorted_chunk`).
+//! - compact-equal-runs: Optional. For state topics, drop each measurement that's `Measurement::content_eq` to the
+//!                       last one kept in its chunk with the same partition key. Every consumed offset is still
+//!                       committed.
+//! - container: Optional. Wrap each chunk in the versioned archive container (`opensensor::archiver::format`), which
+//!              records the format version, before compressing it with the codec. The crate's readers accept archives
+//!              with and without it.
+//! - key-layout: Optional, defaults to `flat` ({sensor-name}/{rfc3339}). `hive` keys objects as
+//!               {sensor-name}/year=YYYY/month=MM/day=DD/hour=HH/{rfc3339} (UTC) so archives can be queried as a
+//!               Hive partitioned dataset by Athena or Spark. Either way, the chunk's offset ranges follow the
+//!               timestamp (i.e. {rfc3339}_p0-100-199), so a chunk re-archived after a crash replaces its object.
+//! - dead-letter-topic: Optional, defaults to "{sensor-name}-dead-letter". Messages that fail to deserialize are
+//!                      produced here with their raw key and payload, plus headers with the error and the topic,
+//!                      partition, and offset they were consumed from, instead of being dropped.
+//! - max-dead-letters: Optional. Exit with an error once more than this many messages have failed to deserialize.
+//! - stats-interval: Optional, defaults to 15m. How often to log the totals archived so far (messages, chunks, bytes,
+//!                   compression ratio, and dead letters). They're also logged whenever the archiver stops.
+//! - metrics-address: Optional, needs the `metrics` feature. Serve Prometheus metrics at
+//!                    `http://{metrics-address}/metrics`: `archiver_messages_consumed_total`,
+//!                    `archiver_chunks_uploaded_total`, `archiver_upload_bytes_total`,
+//!                    `archiver_upload_errors_total`, and `archiver_consumer_lag` per partition (updated at most every
+//!                    10 seconds).
 //!
-//! Data is archived as a vector of chunk-size flatbuffer records, zstd compressed per archival file. To parse, un-compress
-//! and use the readers provided in the messages crate. Readers can be generated for any of the programming languages
-//! supported by flatbuffers. Last archived offsets are saved automatically in the consumer group topic offsets.
+//! The archiver is generic over the Measurement it archives (see `opensensor::archiver::run_archiver`), so the same
+//! code archives any sensor in the ecosystem.
+//! Archives are written through the `opensensor::archiver::store::ObjectStore` trait: this binary archives to S3, and
+//! `run_archiver_with_store` with a `FileSystemObjectStore` archives to a local directory instead.
+//!
+//! Data is archived as an `ArchiveChunk` flatbuffer (`flatbuffers/archive.fbs`) holding a vector of chunk-size
+//! measurement flatbuffers, compressed per archival file with `codec` (named by the object's content encoding). To
+//! parse, un-compress (`opensensor::archiver::download_object_zstd` handles every codec), read the chunk with
+//! `root_as_archive_chunk` and read each measurement's bytes with the readers provided in the messages crate. Readers
+//! can be generated for any of the programming languages supported by flatbuffers. In Rust,
+//! `opensensor::batch::MeasurementBatch::from_bytes` does both. Last archived offsets are saved automatically in the
+//! consumer group topic offsets.
+//!
+//! With the `json` feature, every archive object also gets a JSON manifest at `{key}.manifest.json` with its record
+//! count, timestamp range, sizes, Kafka offset ranges, and zstd level, so catalogs can index archives cheaply.
 //!
 //! # Example
 //!
@@ -33,166 +104,31 @@
 //! --chunk-size 10000 \
 //! --kafka-addresses 127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012
 //! ```
+//!
+//! # Scan
+//!
+//! This binary only archives. To audit a bucket, run `opensensor::archiver::scan_archive` with the sensor's
+//! measurement type, parsing its options with `opensensor::archiver::cli::ScanCli`. It reports unreadable,
+//! truncated, and corrupt objects (including objects that don't match their stored checksum), the number of archived
+//! measurements, the time each key prefix covers, and any gaps longer than `--max-gap`. `--quick` only checks each
+//! object's size against its manifest instead of downloading it. `ScanCli` takes the same S3 options as archiving,
+//! plus:
+//! - prefix: Optional. Only scan keys starting with this prefix, defaults to the whole bucket.
+//! - quick: Optional. Check sizes against manifests without downloading objects.
+//! - max-gap: Optional, defaults to 1h. Report gaps in coverage longer than this.
+//! - zstd-dictionary: Optional, repeatable. Dictionary files the archives were compressed with, if any.
 
-// use archiver::cli::Cli;
-// use archiver::error::ArchiveError;
-// use chrono::Utc;
 // use clap::Parser;
-// use flatbuffers::FlatBufferBuilder;
-// use futures_util::StreamExt;
-// use messages::radar_2d::{
-//     root_as_radar_measurement_2d, RadarMeasurement2d, RadarMeasurement2dArgs,
-//     RadarMeasurement2dFlatBufferBuilder, RadarVector2DBuilder,
-// };
-// use redpanda::{consumer::CommitMode, consumer::Consumer, message::Message, RedpandaBuilder};
-// use tracing::{event, Level};
+// use messages::radar_2d::RadarMeasurement2d;
+// use opensensor::archiver::{cli::Cli, error::ArchiveError, run_archiver};
 
 // #[tokio::main]
 // async fn main() -> Result<(), ArchiveError> {
 //     utility::configure_tracing();
 //     let cli = Cli::parse();
 
-//     run_archiver(cli).await?;
-
-//     Ok(())
-// }
-
-// /// Run a kafka archiver, given a parsed command line configuration
-// ///
-// /// # Parameters
-// ///
-// /// - cli (archiver.cli.Cli): CLI configuration to run the archiver from
-// ///
-// /// # Examples
-// ///
-// /// ```no_run
-// /// let access_key = "USERNAME";
-// /// let secret_key = "SUPER_SECRET_PASSWORD";
-// /// let endpoint = "http://localhost:9000";
-// /// let region = "opensensor-region";
-// /// let bucket_name = "opensensor-archive";
-// /// let sensor_name = "radar-2d";
-// /// let chunk_size = 10000;
-// /// let kafka_addresses = "127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012";
-// ///
-// /// let cli = Cli::new(
-// ///     access_key,
-// ///     secret_key,
-// ///     endpoint,
-// ///     region,
-// ///     bucket_name,
-// ///     sensor_name,
-// ///     chunk_size,
-// ///     kafka_addresses,
-// /// );
-// ///
-// /// run_archiver(cli).await?;
-// /// ```
-// ///
-// /// TODO: Verify the ordering of these archived chunks is correct (does the archived data end up revered because of the push
-// /// and then pop?)
-// /// TODO: implement individual archiver for each message type because that's required to do the serialization and deserialization
-// async fn run_archiver(cli: Cli) -> Result<(), ArchiveError> {
-//     let client = cli.build_client();
-
-//     // Configure Redpanda, disabling auto-commit to ensure we only commit topics consumption offsets
-//     // for the "sensor_name-archiver" topics once the consumed records have been successfully
-//     // written to S3
-//     let mut builder = RedpandaBuilder::default();
-//     let group_id = format!("{}-archiver", cli.sensor_name());
-//     builder.set_group_id(&group_id);
-//     builder.set("enable.auto.commit", "false");
-//     builder.set_bootstrap_servers(cli.kafka_addresses());
-//     let topic = format!("{}-measurements", cli.sensor_name());
-//     let consumer = builder.build_consumer().unwrap();
-//     consumer.subscribe(&[&topic]).unwrap();
-//     let mut stream = consumer.stream();
-//     event!(Level::INFO, "{:?}", consumer.consumer.position().unwrap());
-
-//     // locals for archive chunk tracking
-//     let chunk_size = cli.chunk_size();
-//     let mut chunk_counter = 0;
-
-//     // Vector to save archive chunks to...hard limit of 2GB per buffer due to 32 bit flatbuffer address space.
-//     // The practical limit is somewhat less than this...current implementation relies on there being enough
-//     // RAM to store all in-progress archive chunks in memory.
-//     // The allocation for this seems unavoidable...there is no other way to save the intermediate references
-//     // to flatbuffer buffer data
-//     let mut archival_buffer: Vec<RadarMeasurement2dFlatBufferBuilder> = Vec::new();
-
-//     // Stream the topic, writing archives to S3 every chunk_size messages
-//     while let Some(m) = stream.next().await {
-//         chunk_counter += 1;
-//         let bytes = m.as_ref().unwrap().payload();
-//         // If there's no payload, continue to the next message
-//         if bytes.is_none() {
-//             event!(
-//                 Level::WARN,
-//                 "Got empty Redpanda message payload, continuing to next message"
-//             );
-//             continue;
-//         }
-//         // We've already handled the Option::None case above, so this won't panic
-//         let bytes = bytes.unwrap();
-//         // Try to map the bytes to a radar_measurement_2d
-//         let measurement = root_as_radar_measurement_2d(bytes).unwrap();
-//         let radar_builder = RadarMeasurement2dFlatBufferBuilder::new(
-//             measurement.measurement_strengths().unwrap().bytes(),
-//             measurement.theta_radians(),
-//         )
-//         .unwrap();
-//         archival_buffer.push(radar_builder);
-
-//         if chunk_counter == chunk_size {
-//             // Create a FlatBufferBuilder to construct the RadarVector2d from
-//             let mut fbb = FlatBufferBuilder::new();
-//             let mut offsets = Vec::new();
-
-//             while let Some(m) = archival_buffer.pop() {
-//                 let measurement_strengths_offset = fbb.create_vector(m.measurement_strengths());
-//                 let offset = RadarMeasurement2d::create(
-//                     &mut fbb,
-//                     &RadarMeasurement2dArgs {
-//                         theta_radians: *m.theta_radians(),
-//                         measurement_strengths: Some(measurement_strengths_offset),
-//                     },
-//                 );
-//                 offsets.push(offset);
-//             }
-
-//             let data_offsets = fbb.create_vector(&offsets);
-
-//             let mut radar_vector_builder = RadarVector2DBuilder::new(&mut fbb);
-//             radar_vector_builder.add_data(data_offsets);
-//             let radar_vector_buffer = radar_vector_builder.finish();
-//             fbb.finish_minimal(radar_vector_buffer);
-
-//             let now = Utc::now();
-//             let key = format!("{}/{}", cli.sensor_name(), now.to_rfc3339());
-//             let data_uncompressed = fbb.finished_data();
-
-//             // Try to upload (and compress) the data to s3. Return errors on upload failure or on offset commit failure
-//             match archiver::upload_object_zstd(data_uncompressed, &client, cli.bucket_name(), &key)
-//                 .await
-//             {
-//                 Ok(_) => event!(
-//                     Level::DEBUG,
-//                     "Uploaded key {} to bucket {}",
-//                     key,
-//                     cli.bucket_name()
-//                 ),
-//                 Err(e) => return Err(ArchiveError::S3Error(e)),
-//             };
-//             if let Err(e) = consumer.consumer.commit_consumer_state(CommitMode::Sync) {
-//                 event!(Level::ERROR, "Failed to commit consumer offset. This may result in duplicate archives in archival storage. {}", e);
-//                 return Err(ArchiveError::KafkaError(e));
-//             };
-//             // let timestamp = deserialize_key(m.unwrap().key().unwrap()).unwrap();
-//             event!(Level::INFO, count = chunk_counter, timestamp = ?now, position = ?consumer.consumer.position().unwrap());
-
-//             chunk_counter = 0;
-//         }
-//     }
+//     // The archiver is generic over the Measurement it archives, pick the sensor's measurement type here
+//     run_archiver::<RadarMeasurement2d>(cli).await?;
 
 //     Ok(())
 // }
@@ -0,0 +1,131 @@
+//! Per-chunk manifests describing archived objects
+//!
+//! Every archive object at `{prefix}/{rfc3339}` gets a small JSON manifest next to it at
+//! `{prefix}/{rfc3339}.manifest.json`, so catalogs can index archives without downloading and decompressing them.
+
+use aws_sdk_s3::{Client, Error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::archiver::chunk::{ChunkSort, OffsetRange, RecordOffset};
+use crate::archiver::codec::CodecKind;
+use crate::archiver::store::{ObjectStore, ObjectStoreError, S3ObjectStore, StoredObject};
+use crate::archiver::Encryption;
+
+/// Suffix appended to an archive object's key to get its manifest's key
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// Metadata about a single archive object
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Number of measurements in the chunk
+    pub record_count: usize,
+    /// Earliest measurement timestamp in the chunk
+    pub first_timestamp: DateTime<Utc>,
+    /// Latest measurement timestamp in the chunk
+    pub last_timestamp: DateTime<Utc>,
+    /// Size of the serialized `ArchiveChunk` before compression
+    pub uncompressed_bytes: usize,
+    /// Size of the object as stored
+    pub compressed_bytes: usize,
+    /// Kafka offsets covered by the chunk, per partition
+    pub offsets: Vec<OffsetRange>,
+    /// Codec the object was compressed with, zstd for manifests written before it was recorded
+    #[serde(default)]
+    pub codec: CodecKind,
+    /// zstd compression level the object was compressed with, None for other codecs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+    /// Id of the zstd dictionary the object was compressed with (see `codec::ZstdDictionary::id`), if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dictionary_id: Option<u32>,
+    /// Order the chunk was sorted by with `--sort-chunk-by`, None if it's in consumption order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_chunk_by: Option<ChunkSort>,
+    /// Where each measurement was consumed from, in stored order, only recorded for sorted chunks
+    ///
+    /// Pass these to `archiver::read_sorted_chunk` to read the chunk back in consumption order. The chunk's record
+    /// offsets sidecar (see `archiver::get_record_offsets`) holds the same offsets, and is written without the `json`
+    /// feature too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub record_offsets: Vec<RecordOffset>,
+}
+
+/// Key of the manifest for the archive object at `key`
+pub fn manifest_key(key: &str) -> String {
+    format!("{}{}", key, MANIFEST_SUFFIX)
+}
+
+/// Store a manifest as JSON at `key`
+///
+/// # Errors
+///
+/// - ObjectStoreError::Other: If the manifest fails to serialize
+/// - ObjectStoreError: If the put fails
+pub async fn put_manifest<S>(
+    store: &S,
+    key: &str,
+    manifest: &Manifest,
+) -> Result<(), ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let body = serde_json::to_vec(manifest).map_err(|e| ObjectStoreError::Other(Box::new(e)))?;
+    let object = StoredObject {
+        body,
+        content_type: Some("application/json".to_owned()),
+        ..StoredObject::default()
+    };
+
+    store.put(key, object).await
+}
+
+/// Get and parse the manifest at `key`
+///
+/// # Errors
+///
+/// - ObjectStoreError::NotFound: If there's no manifest at `key`
+/// - ObjectStoreError::Other: If the object isn't a valid manifest
+/// - ObjectStoreError: If the get fails
+pub async fn get_manifest<S>(store: &S, key: &str) -> Result<Manifest, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let object = store.get(key).await?;
+    serde_json::from_slice(&object.body).map_err(|e| ObjectStoreError::Other(Box::new(e)))
+}
+
+/// Upload a manifest as JSON to `key`, with the same server-side encryption as the archive it describes
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error::Unhandled: If the manifest fails to serialize
+/// - aws_sdk_s3::Error: If the upload fails
+pub async fn write_manifest(
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    manifest: &Manifest,
+    encryption: &Encryption,
+) -> Result<(), Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket_name, encryption.clone());
+    put_manifest(&store, key, manifest)
+        .await
+        .map_err(Error::from)
+}
+
+/// Download and parse the manifest at `key`
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error::NoSuchKey: If the manifest doesn't exist
+/// - aws_sdk_s3::Error: If the manifest can't be fetched
+/// - aws_sdk_s3::Error::Unhandled: If the body fails to download or isn't a valid manifest
+pub async fn read_manifest(
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+) -> Result<Manifest, Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket_name, Encryption::None);
+    get_manifest(&store, key).await.map_err(Error::from)
+}
@@ -0,0 +1,70 @@
+//! Prometheus metrics for the archiver, enabled with the `metrics` feature
+//!
+//! `install` serves the metrics over HTTP for Prometheus to scrape. `run_archiver` updates them at the same points
+//! it logs the matching events, so the metrics and logs always agree.
+
+use std::net::SocketAddr;
+
+use metrics::{describe_counter, describe_gauge, gauge};
+use metrics_exporter_prometheus::PrometheusBuilder;
+
+use crate::archiver::error::ArchiveError;
+
+/// Counter of messages read from the topic, including ones that failed to deserialize
+pub const MESSAGES_CONSUMED: &str = "archiver_messages_consumed_total";
+/// Counter of archive chunks uploaded and committed
+pub const CHUNKS_UPLOADED: &str = "archiver_chunks_uploaded_total";
+/// Counter of compressed bytes of the archive chunks uploaded
+pub const UPLOAD_BYTES: &str = "archiver_upload_bytes_total";
+/// Counter of failed S3 upload attempts, including ones that were retried
+pub const UPLOAD_ERRORS: &str = "archiver_upload_errors_total";
+/// Gauge of messages on a partition the archiver hasn't consumed yet, labeled by topic and partition
+pub const CONSUMER_LAG: &str = "archiver_consumer_lag";
+
+/// Serve the archiver's metrics at `http://address/metrics` and describe them
+///
+/// Must be called from within a tokio runtime, the endpoint runs as a task on it.
+///
+/// # Errors
+///
+/// - ArchiveError::MetricsError: If the address can't be bound or a metrics recorder is already installed
+pub fn install(address: SocketAddr) -> Result<(), ArchiveError> {
+    PrometheusBuilder::new()
+        .with_http_listener(address)
+        .install()
+        .map_err(|e| ArchiveError::MetricsError(e.to_string()))?;
+
+    describe_counter!(
+        MESSAGES_CONSUMED,
+        "Messages read from the topic, including ones that failed to deserialize"
+    );
+    describe_counter!(CHUNKS_UPLOADED, "Archive chunks uploaded and committed");
+    describe_counter!(
+        UPLOAD_BYTES,
+        "Compressed bytes of the archive chunks uploaded"
+    );
+    describe_counter!(
+        UPLOAD_ERRORS,
+        "Failed S3 upload attempts, including ones that were retried"
+    );
+    describe_gauge!(
+        CONSUMER_LAG,
+        "Messages on a partition the archiver hasn't consumed yet"
+    );
+
+    Ok(())
+}
+
+/// Set `CONSUMER_LAG` for every partition measured by `partition_lags`
+///
+/// Partitions whose watermarks couldn't be fetched keep their last lag.
+pub fn record_consumer_lag(lags: &[(String, i32, u64)]) {
+    for (topic, partition, lag) in lags {
+        gauge!(
+            CONSUMER_LAG,
+            *lag as f64,
+            "topic" => topic.clone(),
+            "partition" => partition.to_string()
+        );
+    }
+}
@@ -1,106 +1,3402 @@
 //! Archive Measurements to S3 from Redpanda
 
+pub mod chunk;
 #[allow(clippy::too_many_arguments)]
 pub mod cli;
+pub mod codec;
+pub mod compact;
 pub mod error;
+pub mod format;
+#[cfg(feature = "json")]
+pub mod index;
+#[cfg(feature = "json")]
+pub mod manifest;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod multi;
+pub mod stats;
+pub mod store;
 
 #[cfg(test)]
 mod tests;
 
+use crate::archive_generated::archive::root_as_archive_chunk;
+use crate::archiver::chunk::{
+    compact_chunk, decode_record_offsets, deserialize_chunk, encode_record_offsets,
+    next_chunk_size, offset_ranges, record_offsets_key, restore_consumption_order, serialize_chunk,
+    serialize_records, sort_chunk, ChunkBytes, Consumed, FullChunk, OffsetRange, PartitionOffsets,
+    RecordOffset, Reservoir, SortedChunk, SourceChunks, RECORD_OFFSETS_SUFFIX,
+};
+use crate::archiver::cli::Cli;
+use crate::archiver::codec::{Codec, ZstdDictionary};
+use crate::archiver::error::{ArchiveError, ConfigError, FormatError};
+#[cfg(feature = "json")]
+use crate::archiver::manifest::{
+    manifest_key, put_manifest, read_manifest, Manifest, MANIFEST_SUFFIX,
+};
+#[cfg(feature = "metrics")]
+use crate::archiver::metrics::{CHUNKS_UPLOADED, MESSAGES_CONSUMED, UPLOAD_BYTES};
+use crate::archiver::stats::{ArchiveStats, SummaryOnDrop};
+use crate::archiver::store::{
+    metadata_dictionary, put_with_retry, ObjectStore, S3ObjectStore, StoredObject,
+};
+use crate::measurement::{from_bytes_versioned, schema_version, Measurement, RecordMeta};
+use crate::SensorSink;
+#[cfg(feature = "metrics")]
+use ::metrics::{counter, increment_counter};
+use async_stream::try_stream;
 use aws_sdk_s3::model::{
-    BucketLocationConstraint, CreateBucketConfiguration, Delete, ObjectIdentifier,
+    BucketLocationConstraint, CompletedMultipartUpload, CompletedPart, CreateBucketConfiguration,
+    Delete, ObjectIdentifier, ServerSideEncryption,
 };
 use aws_sdk_s3::output::ListObjectsV2Output;
-use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::types::{ByteStream, SdkError};
 use aws_sdk_s3::{Client, Error};
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use futures_util::StreamExt;
+use rdkafka::{Offset, TopicPartitionList};
+use redpanda::{
+    consumer::CommitMode, consumer::Consumer, consumer::RedpandaConsumer, error::KafkaError,
+    message::Header, message::Message, message::OwnedHeaders, producer::RedpandaProducer,
+    producer::RedpandaRecord, RedpandaBuilder,
+};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::marker::PhantomData;
 use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
 use tracing::{event, Level};
 
-/// Delete a bucket, assuming all objects have already been removed from the bucket
-pub async fn delete_bucket(client: &Client, bucket_name: &str) -> Result<(), Error> {
-    client.delete_bucket().bucket(bucket_name).send().await?;
-    event!(Level::INFO, "Deleted bucket {}", bucket_name);
+/// Run a kafka archiver for a Measurement type, given a parsed command line configuration
+///
+/// Consumes the Measurement's topic (or the topic given on the CLI), deserializes each message with
+/// `Measurement::from_bytes` (or `Measurement::migrate` for other schema versions), and uploads every chunk-size
+/// measurements to S3 as a zstd compressed `ArchiveChunk`. Chunks are uploaded early if the next measurement would take
+/// them over `chunk::MAX_CHUNK_BYTES`, so large measurements can't overflow the 2GB flatbuffer limit. Consumer offsets
+/// are only committed once a chunk has been uploaded, and only per partition up to the earliest measurement still
+/// buffered in any open chunk (see `chunk::PartitionOffsets`), so a chunk mixing partitions never commits past
+/// measurements that aren't archived yet. Chunks are keyed by the offsets they cover (see `archive_key_with_offsets`),
+/// so a chunk re-archived after a crash between its upload and commit replaces its object instead of duplicating it.
+///
+/// With `--start-from`, the consumer group's offsets are moved to the earliest or latest offsets, an offset, or a time
+/// before subscribing (see `seek_start`), for backfills. Otherwise the archiver resumes from the committed offsets.
+///
+/// With `--max-chunk-bytes`, chunks are also flushed once their uncompressed size reaches that many bytes, whichever
+/// of the message count and byte size is reached first, so archive objects are a similar size even when measurements
+/// vary widely in size.
+///
+/// With `--min-chunk-size` or `--max-chunk-size`, `--chunk-size` is only the starting size: every time a chunk is
+/// flushed, the next chunk's size is set from the consumer lag with `chunk::next_chunk_size`, so the archiver writes
+/// large chunks while it's catching up and small ones once it's live. The lag is measured at most every 10 seconds
+/// (see `LagMeter`), since fetching it takes a broker round trip per partition.
+///
+/// With `--source-ids`, measurements from any other `source_id` are skipped without being archived. Skipped
+/// messages' offsets are committed along with the next chunk, so they aren't reprocessed.
+///
+/// Messages with empty payloads are skipped with a WARN. Messages that fail to deserialize are also skipped: each
+/// one is produced to the dead letter topic (see `dead_letter_record`) before moving on, so a poison message never
+/// halts archival, and counted so the number of dead letters is visible in the per-chunk logs.
+///
+/// With `--dry-run`, the topic is consumed and chunked as usual, but each chunk is only compressed and logged (key,
+/// record count, and size) instead of uploaded, and nothing is committed or produced to the dead letter topic. The
+/// partitions are assigned at the start offsets (see `assign_start`) instead of subscribing, so the consumer group's
+/// committed offsets are left as they were. With `--poll-timeout`, a dry run stops once the topic is idle.
+///
+/// With the `metrics` feature and `--metrics-address`, throughput, upload failures, and consumer lag are served for
+/// Prometheus to scrape (see `metrics`).
+///
+/// Every `--stats-interval`, and whenever the archiver stops (the stream ending, an error, or its future being
+/// dropped), the totals archived so far (messages, chunks, uncompressed and compressed bytes, compression ratio, and
+/// chunks per minute) are logged as a single INFO event (see `stats::ArchiveStats`).
+///
+/// # Parameters
+///
+/// - cli (archiver.cli.Cli): CLI configuration to run the archiver from
+///
+/// # Errors
+///
+/// - ArchiveError::KafkaError: If the consumer or producer can't be built, or the consumer can't seek to
+///   `--start-from`, be subscribed, or read from
+/// - ArchiveError::KafkaMessageError: If a dead letter can't be delivered, with the topic, partition, and offset of
+///   the message it holds
+/// - ArchiveError::CommitError: If the consumer offsets can't be committed, with the offsets of the archived chunk
+/// - ArchiveError::StoreObjectError: If a chunk, manifest, or preview fails to upload, with its key
+/// - ArchiveError::TooManyDeadLetters: If more messages failed to deserialize than `--max-dead-letters`
+/// - ArchiveError::ChunkTooLarge: If a single measurement is too large to archive (see `chunk::MAX_CHUNK_BYTES`)
+/// - ArchiveError::MetricsError: If the metrics endpoint can't be started
+/// - ArchiveError::ConfigError: If the S3 endpoint or region is invalid
+///
+/// # Examples
+///
+/// ```no_run
+/// let access_key = "USERNAME";
+/// let secret_key = "SUPER_SECRET_PASSWORD";
+/// let endpoint = "http://localhost:9000";
+/// let region = "opensensor-region";
+/// let bucket_name = "opensensor-archive";
+/// let sensor_name = "radar-2d";
+/// let chunk_size = 10000;
+/// let kafka_addresses = "127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012";
+///
+/// let cli = Cli::new(
+///     access_key,
+///     secret_key,
+///     endpoint,
+///     region,
+///     bucket_name,
+///     sensor_name,
+///     chunk_size,
+///     kafka_addresses,
+/// );
+///
+/// run_archiver::<RadarMeasurement2d>(cli).await?;
+/// ```
+pub async fn run_archiver<M>(cli: Cli) -> Result<(), ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let store = cli.build_object_store()?;
+    run_archiver_with_store::<M, _>(cli, store).await
+}
+
+/// Run a kafka archiver like `run_archiver`, writing archives to any object store instead of the S3 bucket in `cli`
+///
+/// The S3 settings in `cli` (credentials, endpoint, bucket, and encryption) are ignored, everything else applies as
+/// in `run_archiver`. Pass a `store::FileSystemObjectStore` to archive to a local directory, i.e. for development
+/// without MinIO.
+///
+/// # Errors
+///
+/// - Same as `run_archiver`
+///
+/// # Examples
+///
+/// ```no_run
+/// let store = FileSystemObjectStore::new("/var/lib/opensensor/archive");
+/// run_archiver_with_store::<RadarMeasurement2d, _>(cli, store).await?;
+/// ```
+pub async fn run_archiver_with_store<M, S>(cli: Cli, store: S) -> Result<(), ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore,
+{
+    #[cfg(feature = "metrics")]
+    if let Some(address) = cli.metrics_address() {
+        metrics::install(address)?;
+        event!(
+            Level::INFO,
+            "Serving Prometheus metrics at http://{}/metrics",
+            address
+        );
+    }
+
+    // Configure Redpanda, disabling auto-commit to ensure we only commit topics consumption offsets
+    // for the "sensor_name-archiver" topics once the consumed records have been successfully
+    // written to S3
+    let mut builder = RedpandaBuilder::default();
+    let group_id = cli.group_id();
+    builder.set_group_id(&group_id);
+    builder.set("enable.auto.commit", "false");
+    builder.set_bootstrap_servers(cli.kafka_addresses());
+    cli.kafka().apply(&mut builder);
+    let topic = cli.topic().unwrap_or(M::TOPIC_NAME);
+    // Shared with the blocking thread pool for broker round trips: seeking, commits, and lag measurements
+    let consumer = Arc::new(builder.build_consumer()?);
+    let dry_run = cli.dry_run();
+    let start_from = cli.start_from();
+    let start_topic = topic.to_owned();
+    blocking(&consumer, move |consumer| {
+        if dry_run {
+            assign_start(consumer, &start_topic, start_from)
+        } else {
+            seek_start(consumer, &start_topic, start_from)
+        }
+    })
+    .await?;
+    if !dry_run {
+        consumer.subscribe(&[topic])?;
+    }
+    let mut stream = consumer.stream();
+    event!(
+        Level::INFO,
+        "Archiving topic {} with group id {}",
+        topic,
+        group_id
+    );
+
+    // Messages that fail to deserialize are produced to the dead letter topic instead of being dropped
+    let mut producer_builder = RedpandaBuilder::default();
+    producer_builder.set_bootstrap_servers(cli.kafka_addresses());
+    let dead_letter_producer = producer_builder.build_producer()?;
+    let dead_letter_topic = cli.dead_letter_topic();
+
+    // locals for archive chunk tracking
+    // With --min-chunk-size/--max-chunk-size, chunk_size adapts to the consumer lag every time a chunk is flushed
+    let chunk_size_range = cli.chunk_size_range();
+    let mut chunk_size = match chunk_size_range {
+        Some((min, max)) => cli.chunk_size().clamp(min, max) as usize,
+        None => cli.chunk_size() as usize,
+    };
+    // The lag is only measured when something uses it, it takes a broker round trip per partition
+    #[cfg(feature = "metrics")]
+    let measure_lag = chunk_size_range.is_some() || cli.metrics_address().is_some();
+    #[cfg(not(feature = "metrics"))]
+    let measure_lag = chunk_size_range.is_some();
+    let mut lag_meter = LagMeter::new(measure_lag);
+    // Only measurements from these sources are archived, with --source-ids
+    let source_filter = cli.source_filter();
+    // Totals logged every stats interval and when the archiver stops, including the count of messages that couldn't
+    // be deserialized into a Measurement and were sent to the dead letter topic
+    let mut stats = SummaryOnDrop::new();
+    let mut stats_interval = cli.stats_interval().map(|stats_interval| {
+        let mut interval =
+            tokio::time::interval_at(Instant::now() + stats_interval, stats_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    });
+    // Offsets consumed and still buffered in each partition, so commits never cover unarchived measurements
+    let mut partition_offsets = PartitionOffsets::new();
+
+    // Measurements waiting to be archived. The current implementation relies on there being enough RAM to store
+    // all in-progress archive chunks in memory.
+    let mut archival_buffer: Vec<Consumed<M>> = Vec::with_capacity(chunk_size);
+    // Serialized size of archival_buffer, so it's flushed before it's too large for a flatbuffer, and once it
+    // reaches max_chunk_bytes with --max-chunk-bytes
+    let mut archival_bytes = ChunkBytes::default();
+    let max_chunk_bytes = cli.max_chunk_bytes();
+
+    // Per-source chunks, only used with --split-by-source
+    let mut source_chunks: SourceChunks<Consumed<M>> =
+        SourceChunks::new(chunk_size, cli.max_open_sources() as usize)
+            .with_flush_bytes(max_chunk_bytes);
+
+    // Flush partial chunks every max_chunk_age so data from low-rate sensors doesn't sit unarchived for hours.
+    // The interval is reset whenever a full chunk is flushed, so a tick means nothing has been flushed for
+    // max_chunk_age.
+    let max_chunk_age = cli.max_chunk_age().filter(|age| !age.is_zero());
+    let mut chunk_age_interval = max_chunk_age.map(|max_chunk_age| {
+        let mut interval = tokio::time::interval_at(Instant::now() + max_chunk_age, max_chunk_age);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    });
+
+    // Uniform sample of the serialized measurements consumed in each reservoir window, along with where they were
+    // consumed from, only used with --reservoir. A zero window samples the whole run.
+    let mut reservoir: Option<Reservoir<Consumed<Vec<u8>>>> = cli.reservoir().map(Reservoir::new);
+    let reservoir_window = Some(cli.reservoir_window()).filter(|window| !window.is_zero());
+    let mut reservoir_interval = reservoir.as_ref().and(reservoir_window).map(|window| {
+        let mut interval = tokio::time::interval_at(Instant::now() + window, window);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    });
+
+    // Wait at most poll_timeout for each message so an idle topic can be told apart from the stream ending
+    let poll_timeout = cli.poll_timeout().filter(|timeout| !timeout.is_zero());
+    // Consecutive polls that timed out without a message
+    let mut idle_polls: u32 = 0;
+
+    // Stream the topic, writing archives to S3 every chunk_size messages, every max_chunk_bytes, every
+    // max_chunk_age, or before a chunk grows past MAX_CHUNK_BYTES, whichever comes first
+    loop {
+        let polled = tokio::select! {
+            polled = poll_next(&mut stream, poll_timeout) => polled,
+            _ = tick(&mut chunk_age_interval), if chunk_age_interval.is_some() => {
+                let items = std::mem::replace(&mut archival_buffer, Vec::with_capacity(chunk_size));
+                archival_bytes.reset();
+                let prefix = cli.sensor_name();
+                archive_chunk(&cli, &store, &consumer, &mut partition_offsets, prefix, items, &mut stats).await?;
+                for FullChunk { source_id, items } in source_chunks.drain() {
+                    let prefix = format!("{}/{}", cli.sensor_name(), source_id);
+                    archive_chunk(&cli, &store, &consumer, &mut partition_offsets, &prefix, items, &mut stats).await?;
+                }
+                chunk_size =
+                    adapt_chunk_size(lag_meter.measure(&consumer).await, chunk_size_range, chunk_size);
+                source_chunks.set_chunk_size(chunk_size);
+                continue;
+            }
+            _ = tick(&mut reservoir_interval), if reservoir_interval.is_some() => {
+                if let Some(reservoir) = reservoir.as_mut() {
+                    let seen = reservoir.seen();
+                    archive_preview::<M, _>(&cli, &store, reservoir.take(), seen).await?;
+                }
+                continue;
+            }
+            _ = tick(&mut stats_interval), if stats_interval.is_some() => {
+                stats.log_summary();
+                continue;
+            }
+        };
+        let message = match polled {
+            Polled::Message(message) => {
+                idle_polls = 0;
+                #[cfg(feature = "metrics")]
+                increment_counter!(MESSAGES_CONSUMED);
+                let message = message?;
+                partition_offsets.consumed(message.partition(), message.offset());
+                message
+            }
+            Polled::Idle => {
+                idle_polls += 1;
+                event!(
+                    Level::DEBUG,
+                    idle_polls,
+                    "No messages on topic {} within the poll timeout",
+                    topic
+                );
+                if cli.dry_run() {
+                    break;
+                }
+                continue;
+            }
+            Polled::Ended => break,
+        };
+        // If there's no payload, continue to the next message
+        let bytes = match message.payload() {
+            Some(bytes) => bytes,
+            None => {
+                event!(
+                    Level::WARN,
+                    "Got empty Redpanda message payload, continuing to next message"
+                );
+                continue;
+            }
+        };
+        // Measurements serialized with another schema version are migrated, so chunks are archived in the current
+        // layout
+        let measurement = match from_bytes_versioned::<M>(bytes, schema_version(&message)) {
+            Ok(measurement) => measurement,
+            Err(e) => {
+                stats.record_dead_letter();
+                let failed_count = stats.dead_letters();
+                event!(
+                    Level::WARN,
+                    failed_count,
+                    "Failed to deserialize message at offset {}, sending it to dead letter topic {}. {}",
+                    message.offset(),
+                    dead_letter_topic,
+                    e
+                );
+                if !cli.dry_run() {
+                    let record = dead_letter_record(&dead_letter_topic, &message, &e.to_string());
+                    produce_dead_letter(&dead_letter_producer, &record)
+                        .await
+                        .map_err(|source| ArchiveError::KafkaMessageError {
+                            topic: message.topic().to_owned(),
+                            partition: message.partition(),
+                            offset: message.offset(),
+                            source,
+                        })?;
+                }
+                if cli
+                    .max_dead_letters()
+                    .map_or(false, |max| failed_count > max)
+                {
+                    return Err(ArchiveError::TooManyDeadLetters(failed_count));
+                }
+                continue;
+            }
+        };
+        // Measurements from sources that aren't archived are dropped here. Their offsets are still committed with the
+        // next chunk, so they aren't consumed again.
+        if !source_filter.allows(measurement.source_id()) {
+            continue;
+        }
+        if let Some(reservoir) = reservoir.as_mut() {
+            reservoir.offer(|| Consumed {
+                measurement: bytes.to_vec(),
+                partition: message.partition(),
+                offset: message.offset(),
+            });
+        }
+
+        // A measurement too large for a chunk of its own can never be archived. Flush everything buffered first so
+        // only the measurement itself is left unarchived.
+        if !ChunkBytes::default().fits(bytes.len()) {
+            let items = std::mem::take(&mut archival_buffer);
+            archival_bytes.reset();
+            let prefix = cli.sensor_name();
+            archive_chunk(
+                &cli,
+                &store,
+                &consumer,
+                &mut partition_offsets,
+                prefix,
+                items,
+                &mut stats,
+            )
+            .await?;
+            for FullChunk { source_id, items } in source_chunks.drain() {
+                let prefix = format!("{}/{}", cli.sensor_name(), source_id);
+                archive_chunk(
+                    &cli,
+                    &store,
+                    &consumer,
+                    &mut partition_offsets,
+                    &prefix,
+                    items,
+                    &mut stats,
+                )
+                .await?;
+            }
+            event!(
+                Level::ERROR,
+                "Measurement at offset {} is {} bytes, too large to archive",
+                message.offset(),
+                bytes.len()
+            );
+            return Err(ArchiveError::ChunkTooLarge { bytes: bytes.len() });
+        }
+
+        if cli.split_by_source() {
+            // Each source's chunk is flushed when it fills up, or early if it's evicted to bound memory usage
+            let source_id = measurement.source_id().to_owned();
+            let consumed = Consumed {
+                measurement,
+                partition: message.partition(),
+                offset: message.offset(),
+            };
+            partition_offsets.buffered(message.partition(), message.offset());
+            let full = source_chunks.push_sized(&source_id, consumed, bytes.len());
+            if !full.is_empty() {
+                for FullChunk { source_id, items } in full {
+                    let prefix = format!("{}/{}", cli.sensor_name(), source_id);
+                    archive_chunk(
+                        &cli,
+                        &store,
+                        &consumer,
+                        &mut partition_offsets,
+                        &prefix,
+                        items,
+                        &mut stats,
+                    )
+                    .await?;
+                }
+                chunk_size = adapt_chunk_size(
+                    lag_meter.measure(&consumer).await,
+                    chunk_size_range,
+                    chunk_size,
+                );
+                source_chunks.set_chunk_size(chunk_size);
+            }
+            continue;
+        }
+
+        // Flush what fits before the chunk would grow too large to serialize
+        if !archival_bytes.fits(bytes.len()) {
+            let items = std::mem::replace(&mut archival_buffer, Vec::with_capacity(chunk_size));
+            archival_bytes.reset();
+            archive_chunk(
+                &cli,
+                &store,
+                &consumer,
+                &mut partition_offsets,
+                cli.sensor_name(),
+                items,
+                &mut stats,
+            )
+            .await?;
+        }
+        archival_bytes.add(bytes.len());
+        partition_offsets.buffered(message.partition(), message.offset());
+        archival_buffer.push(Consumed {
+            measurement,
+            partition: message.partition(),
+            offset: message.offset(),
+        });
+
+        let reached_max_bytes = max_chunk_bytes.map_or(false, |b| archival_bytes.reached(b));
+        if archival_buffer.len() >= chunk_size || reached_max_bytes {
+            let items = std::mem::replace(&mut archival_buffer, Vec::with_capacity(chunk_size));
+            archival_bytes.reset();
+            archive_chunk(
+                &cli,
+                &store,
+                &consumer,
+                &mut partition_offsets,
+                cli.sensor_name(),
+                items,
+                &mut stats,
+            )
+            .await?;
+            if let Some(interval) = chunk_age_interval.as_mut() {
+                interval.reset();
+            }
+            chunk_size = adapt_chunk_size(
+                lag_meter.measure(&consumer).await,
+                chunk_size_range,
+                chunk_size,
+            );
+        }
+    }
+
+    // Don't drop partially filled chunks on the floor when the stream ends
+    archive_chunk(
+        &cli,
+        &store,
+        &consumer,
+        &mut partition_offsets,
+        cli.sensor_name(),
+        archival_buffer,
+        &mut stats,
+    )
+    .await?;
+    for FullChunk { source_id, items } in source_chunks.drain() {
+        let prefix = format!("{}/{}", cli.sensor_name(), source_id);
+        archive_chunk(
+            &cli,
+            &store,
+            &consumer,
+            &mut partition_offsets,
+            &prefix,
+            items,
+            &mut stats,
+        )
+        .await?;
+    }
+    if let Some(reservoir) = reservoir.as_mut() {
+        let seen = reservoir.seen();
+        archive_preview::<M, _>(&cli, &store, reservoir.take(), seen).await?;
+    }
+
+    // stats logs its summary as it's dropped
+    Ok(())
+}
+
+/// Header of a dead letter holding the error the message failed to deserialize with
+pub const DEAD_LETTER_ERROR_HEADER: &str = "opensensor-dead-letter-error";
+/// Header of a dead letter holding the topic the message was consumed from
+pub const DEAD_LETTER_TOPIC_HEADER: &str = "opensensor-dead-letter-topic";
+/// Header of a dead letter holding the partition the message was consumed from
+pub const DEAD_LETTER_PARTITION_HEADER: &str = "opensensor-dead-letter-partition";
+/// Header of a dead letter holding the offset the message was consumed from
+pub const DEAD_LETTER_OFFSET_HEADER: &str = "opensensor-dead-letter-offset";
+
+/// Record for producing a message that failed to deserialize to `dead_letter_topic`
+///
+/// The record has the message's raw key and payload, so it can be inspected or replayed as is once the measurement
+/// can be read again, and headers with the deserialization error (`DEAD_LETTER_ERROR_HEADER`) and where the message
+/// was consumed from (see `RecordMeta`).
+pub fn dead_letter_record<T>(dead_letter_topic: &str, message: &T, error: &str) -> RedpandaRecord
+where
+    T: Message,
+{
+    let meta = RecordMeta::from_message(message);
+    let partition = meta.partition.to_string();
+    let offset = meta.offset.to_string();
+    let headers = OwnedHeaders::new()
+        .insert(Header {
+            key: DEAD_LETTER_ERROR_HEADER,
+            value: Some(error),
+        })
+        .insert(Header {
+            key: DEAD_LETTER_TOPIC_HEADER,
+            value: Some(meta.topic.as_str()),
+        })
+        .insert(Header {
+            key: DEAD_LETTER_PARTITION_HEADER,
+            value: Some(partition.as_str()),
+        })
+        .insert(Header {
+            key: DEAD_LETTER_OFFSET_HEADER,
+            value: Some(offset.as_str()),
+        });
+
+    RedpandaRecord::new(
+        dead_letter_topic,
+        meta.key,
+        message.payload().unwrap_or_default().to_vec(),
+        Some(headers),
+    )
+}
+
+/// Produce a dead letter and wait for it to be delivered
+///
+/// Waiting means the dead letter is stored before the offsets of the message it holds can be committed.
+async fn produce_dead_letter(
+    producer: &RedpandaProducer,
+    record: &RedpandaRecord,
+) -> Result<(), KafkaError> {
+    let delivery = producer.send_result(record).map_err(|(e, _)| e)?;
+    match delivery.await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err((e, _))) => Err(e),
+        Err(_) => Err(KafkaError::Canceled),
+    }
+}
+
+/// Result of waiting for the next item of a stream with an optional timeout
+#[derive(Debug, PartialEq, Eq)]
+pub enum Polled<T> {
+    /// The stream produced an item
+    Message(T),
+    /// No item arrived within the timeout, but the stream may still produce more
+    Idle,
+    /// The stream has ended and won't produce any more items
+    Ended,
+}
+
+/// Wait for the next item of a stream, giving up after `timeout` if one is set
+///
+/// Unlike `stream.next()`, this distinguishes "no data right now" (`Polled::Idle`) from "stream ended"
+/// (`Polled::Ended`), so a consume loop can exit on an idle topic instead of blocking forever. Dropping the pending
+/// poll on timeout is safe, the next call picks up where it left off.
+pub async fn poll_next<S>(stream: &mut S, timeout: Option<Duration>) -> Polled<S::Item>
+where
+    S: Stream + Unpin,
+{
+    let next = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(next) => next,
+            Err(_) => return Polled::Idle,
+        },
+        None => stream.next().await,
+    };
+
+    match next {
+        Some(item) => Polled::Message(item),
+        None => Polled::Ended,
+    }
+}
+
+/// Wait for the next tick of an optional interval
+///
+/// Only poll this when the interval is Some, it never completes otherwise
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Run a blocking consumer call (commits and metadata, watermark, and offset lookups wait for the brokers to respond)
+/// off the async executor
+async fn blocking<T, F>(consumer: &Arc<RedpandaConsumer>, f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(&RedpandaConsumer) -> T + Send + 'static,
+{
+    let consumer = Arc::clone(consumer);
+    match tokio::task::spawn_blocking(move || f(&consumer)).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// Commit the offsets that are safe to commit in each partition of `topic` (see `PartitionOffsets`)
+///
+/// Only partitions whose safe offset moved since the last commit are committed, and nothing is committed if none
+/// did. The commit waits for the broker to acknowledge it, on tokio's blocking thread pool.
+///
+/// # Errors
+///
+/// - KafkaError: If the commit fails, in which case nothing is recorded as committed
+pub async fn commit_offsets(
+    consumer: &Arc<RedpandaConsumer>,
+    topic: &str,
+    partition_offsets: &mut PartitionOffsets,
+) -> Result<(), KafkaError> {
+    let uncommitted = partition_offsets.uncommitted();
+    if uncommitted.is_empty() {
+        return Ok(());
+    }
+
+    let mut list = TopicPartitionList::new();
+    for &(partition, offset) in &uncommitted {
+        list.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+    }
+    blocking(consumer, move |consumer| {
+        consumer.consumer.commit(&list, CommitMode::Sync)
+    })
+    .await?;
+    partition_offsets.committed(&uncommitted);
+    Ok(())
+}
+
+/// Serialize a chunk of measurements, upload it to the object store under `prefix`, and commit the consumer offsets
+///
+/// Offsets are committed per partition (see `commit_offsets`), up to the earliest measurement still buffered in
+/// another open chunk, so a crash or rebalance never drops buffered (but not yet uploaded) measurements.
+///
+/// With the `json` feature, a manifest describing the chunk is uploaded next to it (see `manifest`) before the
+/// offsets are committed, and the chunk is appended to the sensor's daily index (see `index`). Failing to update the
+/// index is only logged, since the chunk is archived either way.
+///
+/// With `--sort-chunk-by`, the chunk is sorted before it's serialized (see `chunk::sort_chunk`), and the manifest
+/// records every measurement's original offset.
+///
+/// With `--compact-equal-runs`, runs of equal measurements per partition key are collapsed (see
+/// `chunk::compact_chunk`) before sorting, but every consumed offset is still committed.
+///
+/// With `--dry-run`, the chunk is only logged (see `upload_chunk`) and nothing is indexed or committed.
+///
+/// Archiving an empty chunk is a no-op, nothing is uploaded or committed.
+async fn archive_chunk<M, S>(
+    cli: &Cli,
+    store: &S,
+    consumer: &Arc<RedpandaConsumer>,
+    partition_offsets: &mut PartitionOffsets,
+    prefix: &str,
+    items: Vec<Consumed<M>>,
+    stats: &mut ArchiveStats,
+) -> Result<(), ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore,
+{
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let offsets = offset_ranges(&items);
+    let records: Vec<RecordOffset> = items
+        .iter()
+        .map(|item| RecordOffset {
+            partition: item.partition,
+            offset: item.offset,
+        })
+        .collect();
+    let items = if cli.compact_equal_runs() {
+        let (kept, dropped) = compact_chunk(items);
+        event!(
+            Level::DEBUG,
+            "Compacted {} equal measurements out of the chunk",
+            dropped
+        );
+        kept
+    } else {
+        items
+    };
+    let (items, sorted) = match cli.sort_chunk_by() {
+        Some(sort) => {
+            let (items, sorted) = sort_chunk(items, sort);
+            (items, Some(sorted))
+        }
+        None => (items, None),
+    };
+    let measurements = items.into_iter().map(|c| c.measurement).collect();
+    let uploaded = upload_chunk(
+        cli,
+        store,
+        prefix,
+        measurements,
+        &offsets,
+        sorted.as_ref(),
+        cli.dry_run(),
+    )
+    .await?;
+    #[cfg(feature = "json")]
+    if !cli.dry_run() {
+        let entry = index::IndexEntry {
+            key: uploaded.key.clone(),
+            first_timestamp: uploaded.first_timestamp,
+            last_timestamp: uploaded.last_timestamp,
+            record_count: uploaded.count,
+            compressed_bytes: uploaded.compressed_bytes,
+        };
+        if let Err(e) = index::append_to_index_in_store(store, cli.sensor_name(), entry).await {
+            event!(
+                Level::WARN,
+                "Failed to add {} to the index of {}. {}",
+                uploaded.key,
+                cli.sensor_name(),
+                e
+            );
+        }
+    }
+
+    partition_offsets.archived(&records);
+    let topic = cli.topic().unwrap_or(M::TOPIC_NAME);
+    let committed = if cli.dry_run() {
+        Ok(())
+    } else {
+        commit_offsets(consumer, topic, partition_offsets).await
+    };
+    if let Err(e) = committed {
+        event!(Level::ERROR, "Failed to commit consumer offset. This may result in duplicate archives in archival storage. {}", e);
+        return Err(ArchiveError::CommitError {
+            topic: topic.to_owned(),
+            offsets,
+            source: e,
+        });
+    };
+    stats.record_chunk(
+        uploaded.count,
+        uploaded.uncompressed_bytes,
+        uploaded.compressed_bytes,
+    );
+    event!(
+        Level::INFO,
+        key = %uploaded.key,
+        count = uploaded.count,
+        failed_count = stats.dead_letters(),
+        compressed_bytes = uploaded.compressed_bytes,
+        unsorted_compressed_bytes = ?uploaded.unsorted_compressed_bytes,
+        timestamp = ?uploaded.uploaded,
+        first_timestamp = ?uploaded.first_timestamp,
+        last_timestamp = ?uploaded.last_timestamp,
+        offsets = ?offsets,
+        position = ?consumer.consumer.position()
+    );
+    #[cfg(feature = "metrics")]
+    {
+        increment_counter!(CHUNKS_UPLOADED);
+        counter!(UPLOAD_BYTES, uploaded.compressed_bytes as u64);
+    }
+
+    Ok(())
+}
+
+/// How long to wait for a partition's watermarks when measuring consumer lag
+const WATERMARK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages the consumer hasn't consumed yet on each partition it has a position on, as (topic, partition, lag)
+///
+/// Lag is measured from each partition's high watermark. Fetching watermarks is a broker round trip per partition
+/// that blocks until it returns (or times out), so call this from tokio's blocking thread pool like `LagMeter` does.
+/// Partitions that haven't been consumed from yet, or whose watermarks can't be fetched, are left out.
+pub fn partition_lags(consumer: &RedpandaConsumer) -> Vec<(String, i32, u64)> {
+    let position = match consumer.consumer.position() {
+        Ok(position) => position,
+        Err(e) => {
+            event!(Level::DEBUG, "Can't measure consumer lag. {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut lags = Vec::new();
+    for elem in position.elements() {
+        // Partitions that haven't been consumed from yet have no offset
+        let offset = match elem.offset().to_raw() {
+            Some(offset) if offset >= 0 => offset,
+            _ => continue,
+        };
+        match consumer
+            .consumer
+            .fetch_watermarks(elem.topic(), elem.partition(), WATERMARK_TIMEOUT)
+        {
+            Ok((_, high)) => lags.push((
+                elem.topic().to_owned(),
+                elem.partition(),
+                (high - offset).max(0) as u64,
+            )),
+            Err(e) => event!(
+                Level::DEBUG,
+                "Can't fetch watermarks for {}/{}. {}",
+                elem.topic(),
+                elem.partition(),
+                e
+            ),
+        }
+    }
+
+    lags
+}
+
+/// Minimum time between two consumer lag measurements (see `LagMeter`)
+const LAG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Consumer lag, measured with `partition_lags` at most once per `LAG_INTERVAL`
+///
+/// `partition_lags` blocks for a broker round trip per partition, so it runs on tokio's blocking thread pool instead
+/// of a worker thread, and measuring after every chunk would still stall the archiver whenever chunks are small.
+/// Between measurements the last total is reused. With the `metrics` feature, every measurement also updates the
+/// `CONSUMER_LAG` gauges.
+struct LagMeter {
+    /// Whether anything uses the lag (adaptive chunk sizes or metrics), nothing is measured otherwise
+    enabled: bool,
+    measured: Option<Instant>,
+    total: Option<u64>,
+}
+
+impl LagMeter {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            measured: None,
+            total: None,
+        }
+    }
+
+    /// Total lag across the consumer's partitions, re-measured if the last measurement is older than `LAG_INTERVAL`
+    ///
+    /// None if the meter is disabled or the lag of no partition could be measured.
+    async fn measure(&mut self, consumer: &Arc<RedpandaConsumer>) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+        if let Some(measured) = self.measured {
+            if measured.elapsed() < LAG_INTERVAL {
+                return self.total;
+            }
+        }
+
+        let lags = blocking(consumer, partition_lags).await;
+        #[cfg(feature = "metrics")]
+        metrics::record_consumer_lag(&lags);
+        self.total = if lags.is_empty() {
+            None
+        } else {
+            Some(lags.iter().map(|(_, _, lag)| lag).sum())
+        };
+        self.measured = Some(Instant::now());
+        self.total
+    }
+}
+
+/// Total messages the consumer hasn't consumed yet across its partitions (see `partition_lags`)
+///
+/// None if the lag of no partition could be measured.
+pub fn consumer_lag(consumer: &RedpandaConsumer) -> Option<u64> {
+    let lags = partition_lags(consumer);
+    if lags.is_empty() {
+        return None;
+    }
+
+    Some(lags.iter().map(|(_, _, lag)| lag).sum())
+}
+
+/// Where the archiver starts consuming its topic (see `--start-from`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StartFrom {
+    /// Resume from the consumer group's committed offsets
+    #[default]
+    Committed,
+    /// The earliest offset still retained in each partition
+    Earliest,
+    /// The end of each partition, only archiving measurements produced from now on
+    Latest,
+    /// This offset in every partition
+    Offset(i64),
+    /// The first message in each partition whose Kafka timestamp is at or after this time
+    Time(DateTime<Utc>),
+}
+
+impl StartFrom {
+    /// Offset to start a partition at given its (low, high) watermarks, None to resume from the committed offset
+    ///
+    /// `resolved` is the offset `offsets_for_times` resolved `StartFrom::Time` to in the partition, `Offset::End` if
+    /// no message is that recent, in which case the partition starts at its high watermark. Start offsets are clamped
+    /// to the watermarks, so offsets that have been deleted by retention start at the earliest one retained.
+    pub fn start_offset(&self, watermarks: (i64, i64), resolved: Option<Offset>) -> Option<i64> {
+        let (low, high) = watermarks;
+        let clamp = |offset: i64| offset.max(low).min(high);
+        match self {
+            StartFrom::Committed => None,
+            StartFrom::Earliest => Some(low),
+            StartFrom::Latest => Some(high),
+            StartFrom::Offset(offset) => Some(clamp(*offset)),
+            StartFrom::Time(_) => match resolved {
+                Some(Offset::Offset(offset)) => Some(clamp(offset)),
+                _ => Some(high),
+            },
+        }
+    }
+}
+
+impl str::FromStr for StartFrom {
+    type Err = String;
+
+    /// Parse `committed`, `earliest`, `latest`, `offset:N`, or `time:RFC3339` (i.e. `time:2022-10-01T00:00:00Z`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None => match s {
+                "committed" => Ok(StartFrom::Committed),
+                "earliest" => Ok(StartFrom::Earliest),
+                "latest" => Ok(StartFrom::Latest),
+                _ => Err(format!(
+                    "unknown start {}, expected committed, earliest, latest, offset:N, or time:RFC3339",
+                    s
+                )),
+            },
+            Some(("offset", offset)) => match offset.parse() {
+                Ok(offset) if offset >= 0 => Ok(StartFrom::Offset(offset)),
+                _ => Err(format!("invalid start offset {}, expected a non-negative integer", offset)),
+            },
+            Some(("time", time)) => DateTime::parse_from_rfc3339(time)
+                .map(|time| StartFrom::Time(time.with_timezone(&Utc)))
+                .map_err(|e| format!("invalid start time {}: {}", time, e)),
+            Some(_) => Err(format!(
+                "unknown start {}, expected committed, earliest, latest, offset:N, or time:RFC3339",
+                s
+            )),
+        }
+    }
+}
+
+/// How long to wait for the topic's metadata, watermarks, and timestamp offsets when seeking to `--start-from`
+const START_FROM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Move the consumer group's offset on every partition of `topic` to where `start_from` says to start
+///
+/// Call this before subscribing. A subscribed consumer isn't assigned any partitions to `seek` until its stream is
+/// polled, so the start offsets are committed for the group instead, and the subscription picks them up whichever
+/// archiver in the group is assigned each partition. Times are resolved to offsets with `offsets_for_times` (see
+/// `StartFrom::start_offset`). `StartFrom::Committed` doesn't change anything.
+///
+/// Fetching the metadata, watermarks, timestamp offsets, and committing all block until the brokers respond, so call
+/// this from tokio's blocking thread pool like `run_archiver` does.
+///
+/// The group's offsets can only be moved while no other consumer in the group is running, so stop other archivers
+/// of the same sensor first. Restarting with the same `--start-from` seeks again, so only use it for one-off runs.
+///
+/// # Errors
+///
+/// - KafkaError: If the topic's metadata, watermarks, or timestamp offsets can't be fetched, or the offsets can't
+///   be committed
+pub fn seek_start(
+    consumer: &RedpandaConsumer,
+    topic: &str,
+    start_from: StartFrom,
+) -> Result<(), KafkaError> {
+    if start_from == StartFrom::Committed {
+        return Ok(());
+    }
+
+    let offsets = start_offsets(consumer, topic, start_from)?;
+    if offsets.is_empty() {
+        event!(
+            Level::WARN,
+            "Topic {} has no partitions to start from {:?}",
+            topic,
+            start_from
+        );
+        return Ok(());
+    }
+
+    let mut list = TopicPartitionList::new();
+    for &(partition, offset) in &offsets {
+        list.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+    }
+    consumer.consumer.commit(&list, CommitMode::Sync)?;
+    event!(
+        Level::INFO,
+        offsets = ?offsets,
+        "Starting topic {} from {:?}",
+        topic,
+        start_from
+    );
+    Ok(())
+}
+
+/// Assign every partition of `topic` to the consumer at the offset `start_from` says to start at, without joining the
+/// consumer group or committing anything
+///
+/// Like `seek_start`, but for consumers that mustn't change the group's offsets or take partitions from the group's
+/// other consumers, i.e. `--dry-run`. Use this instead of subscribing. `StartFrom::Committed` starts each partition at
+/// the group's committed offset, or where `auto.offset.reset` says if there isn't one.
+///
+/// Blocks on the same broker round trips as `seek_start`, so call it from tokio's blocking thread pool too.
+///
+/// # Errors
+///
+/// - KafkaError: If the topic's metadata, watermarks, or timestamp offsets can't be fetched, or the partitions can't
+///   be assigned
+pub fn assign_start(
+    consumer: &RedpandaConsumer,
+    topic: &str,
+    start_from: StartFrom,
+) -> Result<(), KafkaError> {
+    let offsets: Vec<(i32, Offset)> = match start_from {
+        StartFrom::Committed => topic_partitions(consumer, topic)?
+            .into_iter()
+            .map(|partition| (partition, Offset::Stored))
+            .collect(),
+        _ => start_offsets(consumer, topic, start_from)?
+            .into_iter()
+            .map(|(partition, offset)| (partition, Offset::Offset(offset)))
+            .collect(),
+    };
+
+    let mut list = TopicPartitionList::new();
+    for &(partition, offset) in &offsets {
+        list.add_partition_offset(topic, partition, offset)?;
+    }
+    consumer.consumer.assign(&list)?;
+    event!(
+        Level::INFO,
+        offsets = ?offsets,
+        "Assigned topic {} from {:?} without joining the consumer group",
+        topic,
+        start_from
+    );
+    Ok(())
+}
+
+/// Ids of every partition of `topic`
+fn topic_partitions(consumer: &RedpandaConsumer, topic: &str) -> Result<Vec<i32>, KafkaError> {
+    let metadata = consumer
+        .consumer
+        .fetch_metadata(Some(topic), START_FROM_TIMEOUT)?;
+    Ok(metadata
+        .topics()
+        .iter()
+        .filter(|t| t.name() == topic)
+        .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+        .collect())
+}
+
+/// Offset to start each partition of `topic` at for `start_from`, leaving out partitions that resume from the
+/// committed offset (see `StartFrom::start_offset`)
+fn start_offsets(
+    consumer: &RedpandaConsumer,
+    topic: &str,
+    start_from: StartFrom,
+) -> Result<Vec<(i32, i64)>, KafkaError> {
+    let partitions = topic_partitions(consumer, topic)?;
+
+    let resolved = match start_from {
+        StartFrom::Time(time) => {
+            let mut timestamps = TopicPartitionList::new();
+            for &partition in &partitions {
+                timestamps.add_partition_offset(
+                    topic,
+                    partition,
+                    Offset::Offset(time.timestamp_millis()),
+                )?;
+            }
+            Some(
+                consumer
+                    .consumer
+                    .offsets_for_times(timestamps, START_FROM_TIMEOUT)?,
+            )
+        }
+        _ => None,
+    };
+
+    let mut offsets = Vec::new();
+    for &partition in &partitions {
+        let watermarks =
+            consumer
+                .consumer
+                .fetch_watermarks(topic, partition, START_FROM_TIMEOUT)?;
+        let found = resolved
+            .as_ref()
+            .and_then(|resolved| resolved.find_partition(topic, partition))
+            .map(|elem| elem.offset());
+        if let Some(offset) = start_from.start_offset(watermarks, found) {
+            offsets.push((partition, offset));
+        }
+    }
+
+    Ok(offsets)
+}
+
+/// Size of the next archive chunk from the consumer's lag (see `LagMeter`), with `--min-chunk-size`/`--max-chunk-size`
+///
+/// Keeps `current` if the chunk size is fixed (`range` is None) or the lag couldn't be measured.
+fn adapt_chunk_size(lag: Option<u64>, range: Option<(u64, u64)>, current: usize) -> usize {
+    let (min, max) = match range {
+        Some(range) => range,
+        None => return current,
+    };
+    let lag = match lag {
+        Some(lag) => lag,
+        None => return current,
+    };
+
+    let next = next_chunk_size(lag, min, max) as usize;
+    if next != current {
+        event!(
+            Level::DEBUG,
+            lag,
+            "Adapting chunk size from {} to {} messages",
+            current,
+            next
+        );
+    }
+    next
+}
+
+/// Summary of a chunk uploaded by `upload_chunk`, for logging
+struct UploadedChunk {
+    key: String,
+    count: usize,
+    uncompressed_bytes: usize,
+    compressed_bytes: usize,
+    /// Compressed size the chunk would have had in consumption order, only measured for sorted chunks
+    unsorted_compressed_bytes: Option<usize>,
+    uploaded: DateTime<Utc>,
+    first_timestamp: DateTime<Utc>,
+    last_timestamp: DateTime<Utc>,
+}
+
+/// Serialize a non-empty chunk of measurements and upload it (and its manifest) to the object store under `prefix`
+///
+/// `offsets` are the Kafka offsets the chunk covers, recorded in the manifest and provenance metadata. `sorted` is how
+/// the measurements were sorted, if they were. Sorted chunks get a record offsets sidecar (see
+/// `chunk::record_offsets_key`), and are also compressed in consumption order to measure and log how much sorting
+/// saved, which costs a second compression per chunk.
+///
+/// With `dry_run`, the chunk is compressed to measure it and its key, record count, and size are logged, but nothing
+/// is uploaded.
+async fn upload_chunk<M, S>(
+    cli: &Cli,
+    store: &S,
+    prefix: &str,
+    measurements: Vec<M>,
+    offsets: &[OffsetRange],
+    sorted: Option<&SortedChunk>,
+    dry_run: bool,
+) -> Result<UploadedChunk, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore,
+{
+    let count = measurements.len();
+    // Objects are keyed by the earliest partition timestamp in the chunk so archives are partitioned by measurement
+    // time rather than upload time
+    let partition_time = measurements
+        .iter()
+        .map(|m| m.partition_timestamp())
+        .min()
+        .unwrap_or_else(Utc::now);
+    let first_timestamp = measurements
+        .iter()
+        .map(|m| m.timestamp())
+        .min()
+        .unwrap_or(partition_time);
+    let last_timestamp = measurements
+        .iter()
+        .map(|m| m.timestamp())
+        .max()
+        .unwrap_or(partition_time);
+    let (fbb, unsorted_compressed_bytes) = match sorted {
+        Some(sorted) => {
+            let records: Vec<Vec<u8>> = measurements.into_iter().map(|m| m.to_bytes()).collect();
+            let unsorted =
+                serialize_records::<M, _>(sorted.stored_positions.iter().map(|&i| &records[i]))?;
+            let unsorted_compressed_bytes = cli
+                .codec()
+                .compress(unsorted.finished_data())
+                .map_err(|e| ArchiveError::StoreError(e.into()))?
+                .len();
+            (
+                serialize_records::<M, _>(&records)?,
+                Some(unsorted_compressed_bytes),
+            )
+        }
+        None => (serialize_chunk(measurements)?, None),
+    };
+
+    let now = Utc::now();
+    let key = archive_key_with_offsets(prefix, cli.key_layout(), partition_time, offsets);
+    // With --container, the codec still compresses the container as a whole, so its payload is left uncompressed
+    let container;
+    let data_uncompressed = if cli.container() {
+        container = format::write_archive(fbb.finished_data(), &Codec::None, None)?;
+        &container[..]
+    } else {
+        fbb.finished_data()
+    };
+
+    let mut metadata = if cli.provenance() {
+        provenance_metadata(&cli.group_id(), offsets)
+    } else {
+        HashMap::new()
+    };
+    metadata.insert(RECORD_COUNT_METADATA_KEY.to_owned(), count.to_string());
+    metadata.insert(
+        LAST_TIMESTAMP_METADATA_KEY.to_owned(),
+        last_timestamp.to_rfc3339(),
+    );
+
+    // Try to upload (and compress) the data to the object store. Return errors on upload failure
+    let compressed_bytes = if dry_run {
+        let compressed_bytes = cli
+            .codec()
+            .compress(data_uncompressed)
+            .map_err(|e| ArchiveError::StoreError(e.into()))?
+            .len();
+        event!(
+            Level::INFO,
+            count,
+            uncompressed_bytes = data_uncompressed.len(),
+            compressed_bytes,
+            "Dry run, would upload key {} to {}",
+            key,
+            store.location()
+        );
+        compressed_bytes
+    } else {
+        match put_with_retry(
+            store,
+            &key,
+            data_uncompressed,
+            &cli.codec(),
+            cli.upload_retries(),
+            cli.upload_retry_delay(),
+            Some(metadata),
+        )
+        .await
+        {
+            Ok(compressed_bytes) => {
+                event!(Level::DEBUG, "Uploaded key {} to {}", key, store.location());
+                compressed_bytes
+            }
+            Err(source) => {
+                return Err(ArchiveError::StoreObjectError {
+                    location: store.location().to_owned(),
+                    key,
+                    source,
+                })
+            }
+        }
+    };
+    if let (Some(sorted), Some(unsorted_compressed_bytes)) = (sorted, unsorted_compressed_bytes) {
+        event!(
+            Level::INFO,
+            sort = ?sorted.sort,
+            compressed_bytes,
+            unsorted_compressed_bytes,
+            "Sorting chunk {} saved {:.1}% of its compressed size",
+            key,
+            100.0 * (1.0 - compressed_bytes as f64 / unsorted_compressed_bytes.max(1) as f64)
+        );
+    }
+
+    if let (Some(sorted), false) = (sorted, dry_run) {
+        let offsets_key = record_offsets_key(&key);
+        let object = StoredObject {
+            body: encode_record_offsets(&sorted.record_offsets),
+            content_type: Some("application/octet-stream".to_owned()),
+            ..StoredObject::default()
+        };
+        store
+            .put(&offsets_key, object)
+            .await
+            .map_err(|source| ArchiveError::StoreObjectError {
+                location: store.location().to_owned(),
+                key: offsets_key,
+                source,
+            })?;
+    }
+
+    #[cfg(feature = "json")]
+    {
+        let codec = cli.codec();
+        let manifest = Manifest {
+            record_count: count,
+            first_timestamp,
+            last_timestamp,
+            uncompressed_bytes: data_uncompressed.len(),
+            compressed_bytes,
+            offsets: offsets.to_vec(),
+            codec: codec.kind(),
+            compression_level: codec.zstd_level().map(zstd_compression_level).transpose()?,
+            dictionary_id: codec.dictionary().map(ZstdDictionary::id),
+            sort_chunk_by: sorted.map(|sorted| sorted.sort),
+            record_offsets: sorted
+                .map(|sorted| sorted.record_offsets.clone())
+                .unwrap_or_default(),
+        };
+        let manifest_key = manifest_key(&key);
+        if !dry_run {
+            put_manifest(store, &manifest_key, &manifest)
+                .await
+                .map_err(|source| ArchiveError::StoreObjectError {
+                    location: store.location().to_owned(),
+                    key: manifest_key.clone(),
+                    source,
+                })?;
+        }
+    }
+
+    Ok(UploadedChunk {
+        key,
+        count,
+        uncompressed_bytes: data_uncompressed.len(),
+        compressed_bytes,
+        unsorted_compressed_bytes,
+        uploaded: now,
+        first_timestamp,
+        last_timestamp,
+    })
+}
+
+/// The archiver's upload as a `SensorSink`, for running with `SensorSink::run`
+///
+/// Each batch of `--chunk-size` measurements is uploaded as one archive chunk under the sensor name prefix, exactly
+/// like `run_archiver` uploads a chunk. `run_archiver` is still the full archiver: `SensorSink::run` only flushes on
+/// batch size, so `--max-chunk-age`, `--split-by-source`, `--reservoir`, and `--poll-timeout` aren't supported here.
+/// `SensorSink::sink_batch` doesn't see Kafka offsets, so manifests and provenance metadata have no offset ranges,
+/// and `--sort-chunk-by` isn't supported either since sorted chunks need their original offsets recorded.
+/// `--dry-run` is ignored, since `SensorSink::run` commits offsets itself.
+///
+/// Chunks are uploaded to the S3 bucket configured in `cli`, or to any object store with `ArchiveSink::with_store`.
+///
+/// # Examples
+///
+/// ```no_run
+/// let sink = ArchiveSink::<RadarMeasurement2d>::new(cli)?;
+/// let consumer = sink.consumer()?;
+/// sink.run(consumer).await?;
+/// ```
+pub struct ArchiveSink<M, S = S3ObjectStore> {
+    cli: Cli,
+    store: S,
+    measurement: PhantomData<fn(M)>,
+}
+
+impl<M> ArchiveSink<M>
+where
+    M: for<'a> Measurement<'a>,
+{
+    /// Archive sink uploading to the bucket configured in `cli`
+    ///
+    /// # Errors
+    ///
+    /// - ConfigError: If the S3 endpoint or region is invalid (see `S3Args::build_client`)
+    pub fn new(cli: Cli) -> Result<Self, ConfigError> {
+        let store = cli.build_object_store()?;
+        Ok(ArchiveSink::with_store(cli, store))
+    }
+}
+
+impl<M, S> ArchiveSink<M, S>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore,
+{
+    /// Archive sink uploading to `store`, ignoring the S3 settings in `cli`
+    pub fn with_store(cli: Cli, store: S) -> Self {
+        ArchiveSink {
+            cli,
+            store,
+            measurement: PhantomData,
+        }
+    }
+
+    /// Consumer in the archiver's consumer group with auto-commit disabled, to pass to `SensorSink::run`
+    ///
+    /// # Errors
+    ///
+    /// - ArchiveError::KafkaError: If the consumer can't be built
+    pub fn consumer(&self) -> Result<RedpandaConsumer, ArchiveError> {
+        let mut builder = RedpandaBuilder::default();
+        builder.set_group_id(&self.cli.group_id());
+        builder.set("enable.auto.commit", "false");
+        builder.set_bootstrap_servers(self.cli.kafka_addresses());
+        self.cli.kafka().apply(&mut builder);
+        builder.build_consumer().map_err(ArchiveError::KafkaError)
+    }
+}
+
+#[async_trait::async_trait]
+impl<M, S> SensorSink for ArchiveSink<M, S>
+where
+    M: for<'a> Measurement<'a> + Send,
+    S: ObjectStore,
+{
+    type Measurement = M;
+    type Error = ArchiveError;
+
+    fn topic(&self) -> &str {
+        self.cli.topic().unwrap_or(M::TOPIC_NAME)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.cli.chunk_size() as usize
+    }
+
+    async fn sink_batch(&mut self, batch: Vec<M>) -> Result<(), ArchiveError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let uploaded = upload_chunk(
+            &self.cli,
+            &self.store,
+            self.cli.sensor_name(),
+            batch,
+            &[],
+            None,
+            false,
+        )
+        .await?;
+        event!(
+            Level::INFO,
+            count = uploaded.count,
+            compressed_bytes = uploaded.compressed_bytes,
+            timestamp = ?uploaded.uploaded,
+            first_timestamp = ?uploaded.first_timestamp,
+            last_timestamp = ?uploaded.last_timestamp
+        );
+
+        Ok(())
+    }
+}
+
+/// Upload a reservoir sample of serialized measurements as an `ArchiveChunk` under the preview prefix
+///
+/// The sample is sorted by timestamp and keyed by its earliest partition timestamp and the offset ranges it was
+/// sampled from, like a regular chunk, so samples from different windows or runs don't overwrite each other. Offsets
+/// aren't committed: previews are best effort, and a commit here would cover measurements still buffered for the
+/// next regular chunk.
+///
+/// Uploading an empty sample is a no-op. With `--dry-run`, the sample is only logged.
+async fn archive_preview<M, S>(
+    cli: &Cli,
+    store: &S,
+    sample: Vec<Consumed<Vec<u8>>>,
+    seen: u64,
+) -> Result<(), ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+    S: ObjectStore,
+{
+    // Every sampled measurement already deserialized once when it was consumed
+    let offsets = offset_ranges(&sample);
+    let mut measurements: Vec<M> = sample
+        .iter()
+        .filter_map(|item| M::from_bytes(&item.measurement).ok())
+        .collect();
+    if measurements.is_empty() {
+        return Ok(());
+    }
+    measurements.sort_by_key(|m| m.timestamp());
+
+    let count = measurements.len();
+    let partition_time = measurements
+        .iter()
+        .map(|m| m.partition_timestamp())
+        .min()
+        .unwrap_or_else(Utc::now);
+    let key = archive_key_with_offsets(
+        &cli.preview_prefix(),
+        cli.key_layout(),
+        partition_time,
+        &offsets,
+    );
+    if cli.dry_run() {
+        event!(
+            Level::INFO,
+            count,
+            seen,
+            "Dry run, would upload reservoir sample {} to {}",
+            key,
+            store.location()
+        );
+        return Ok(());
+    }
+    let fbb = serialize_chunk(measurements)?;
+
+    let compressed_bytes = put_with_retry(
+        store,
+        &key,
+        fbb.finished_data(),
+        &cli.codec(),
+        cli.upload_retries(),
+        cli.upload_retry_delay(),
+        Some(HashMap::from([(
+            RECORD_COUNT_METADATA_KEY.to_owned(),
+            count.to_string(),
+        )])),
+    )
+    .await
+    .map_err(|source| ArchiveError::StoreObjectError {
+        location: store.location().to_owned(),
+        key: key.clone(),
+        source,
+    })?;
+    event!(
+        Level::INFO,
+        count,
+        seen,
+        compressed_bytes,
+        "Uploaded reservoir sample {} to {}",
+        key,
+        store.location()
+    );
+
+    Ok(())
+}
+
+/// S3 user metadata identifying the archiver instance that wrote an object
+///
+/// Lets operators trace which archiver produced an object when debugging duplicates or gaps in multi-archiver
+/// deployments. Keys are stored by S3 as `x-amz-meta-{key}`:
+///
+/// - archiver-hostname: `HOSTNAME` or the contents of `/etc/hostname`, "unknown" if neither is available
+/// - archiver-pid: process id
+/// - archiver-version: version of this crate
+/// - consumer-group: Kafka consumer group id
+/// - partitions: comma separated Kafka partitions covered by the object
+pub fn provenance_metadata(group_id: &str, offsets: &[OffsetRange]) -> HashMap<String, String> {
+    let hostname = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+    let partitions: Vec<String> = offsets.iter().map(|r| r.partition.to_string()).collect();
+
+    HashMap::from([
+        ("archiver-hostname".to_owned(), hostname),
+        ("archiver-pid".to_owned(), std::process::id().to_string()),
+        (
+            "archiver-version".to_owned(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+        ),
+        ("consumer-group".to_owned(), group_id.to_owned()),
+        ("partitions".to_owned(), partitions.join(",")),
+    ])
+}
+
+/// How to repair timestamps that jump backwards when reading an archive
+///
+/// Archives from sensors with unstable clocks can contain non-monotonic timestamps. A backwards jump starts a run of
+/// out of order timestamps that ends at the first timestamp at or after the last good one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampRepair {
+    /// Replace every timestamp in the run with the last good timestamp
+    Clamp,
+    /// Spread the run evenly between the last good timestamp and the timestamp that ends the run
+    ///
+    /// Falls back to clamping when the run reaches the end of the chunk
+    Interpolate,
+}
+
+/// Measurements read back from an archive chunk
+#[derive(Debug)]
+pub struct ReadChunk<M> {
+    /// Measurements, in the order they're stored in the chunk
+    pub measurements: Vec<M>,
+    /// Timestamp of each measurement, after any repair
+    ///
+    /// Measurements are immutable once built, so repaired timestamps are returned alongside them instead of being
+    /// written back into the measurements
+    pub timestamps: Vec<DateTime<Utc>>,
+    /// Number of timestamps that were repaired
+    pub repaired: usize,
+}
+
+/// Read the measurements out of an uncompressed `ArchiveChunk`, optionally repairing non-monotonic timestamps
+///
+/// # Parameters
+///
+/// - data: uncompressed `ArchiveChunk` flatbuffer bytes
+/// - repair: how to repair timestamps that jump backwards, None leaves them untouched
+///
+/// # Errors
+///
+/// - ArchiveError::InvalidChunk: If `data` isn't a valid `ArchiveChunk`
+/// - ArchiveError::DeserializeError: If any stored measurement fails to deserialize
+pub fn read_chunk<M>(
+    data: &[u8],
+    repair: Option<TimestampRepair>,
+) -> Result<ReadChunk<M>, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let measurements: Vec<M> = deserialize_chunk(data)?;
+
+    Ok(repair_chunk(measurements, None, repair))
+}
+
+/// The raw flatbuffer bytes of each record in an uncompressed `ArchiveChunk`, without deserializing them
+///
+/// Archives only store finished flatbuffers, so chunks written by another language's flatbuffers library (against
+/// `flatbuffers/archive.fbs`) read back the same way as ones written by this crate. Records can be of a type with no
+/// `Measurement` implementation here, and be read with that type's generated accessors or checked with
+/// `reflection::validate_against_schema`. The records borrow from `data`, so archive objects must be decompressed first
+/// (see `decompress_object`). `data` can be an archive container with an uncompressed payload, as the archiver
+/// writes with `--container`.
+///
+/// # Errors
+///
+/// - ArchiveError::FormatError: If `data` is an archive container that can't be read, or whose payload is compressed
+///   (read those with `format::read_archive`)
+/// - ArchiveError::InvalidChunk: If `data` isn't a valid `ArchiveChunk`
+///
+/// # Examples
+///
+/// ```no_run
+/// let data = decompress_object(key, &store.get(key).await?, &[])?;
+/// for record in read_archive_raw(&data)? {
+///     let reading = flatbuffers::root::<Reading>(record)?;
+/// }
+/// ```
+pub fn read_archive_raw(data: &[u8]) -> Result<Vec<&[u8]>, ArchiveError> {
+    let data = match format::chunk_bytes(data)? {
+        Cow::Borrowed(data) => data,
+        Cow::Owned(_) => {
+            return Err(ArchiveError::FormatError(FormatError::Codec(
+                "can't borrow records from a compressed container payload".to_owned(),
+            )))
+        }
+    };
+    let chunk = root_as_archive_chunk(data).map_err(ArchiveError::InvalidChunk)?;
+
+    Ok(chunk
+        .measurements()
+        .iter()
+        .map(|archived| archived.data().bytes())
+        .collect())
+}
+
+/// Read the measurements out of an uncompressed `ArchiveChunk` archived with `--sort-chunk-by`, in consumption order
+///
+/// Sorting a chunk hides the backwards timestamp jumps `TimestampRepair` looks for, so the measurements are put back
+/// in the order they were consumed from each partition (see `chunk::restore_consumption_order`) before any repair.
+/// Each partition's timestamps are repaired separately, since Kafka doesn't order messages across partitions.
+///
+/// # Parameters
+///
+/// - data: uncompressed `ArchiveChunk` flatbuffer bytes
+/// - record_offsets: the chunk's record offsets, from `get_record_offsets` or `Manifest::record_offsets`
+/// - repair: how to repair timestamps that jump backwards, None leaves them untouched
+///
+/// # Errors
+///
+/// - ArchiveError::InvalidChunk: If `data` isn't a valid `ArchiveChunk`
+/// - ArchiveError::DeserializeError: If any stored measurement fails to deserialize
+/// - ArchiveError::OrderError: If there isn't exactly one record offset per measurement
+pub fn read_sorted_chunk<M>(
+    data: &[u8],
+    record_offsets: &[RecordOffset],
+    repair: Option<TimestampRepair>,
+) -> Result<ReadChunk<M>, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let measurements: Vec<M> = deserialize_chunk(data)?;
+    let measurements = restore_consumption_order(measurements, record_offsets)?;
+    let mut partitions: Vec<i32> = record_offsets.iter().map(|o| o.partition).collect();
+    partitions.sort_unstable();
+
+    Ok(repair_chunk(measurements, Some(&partitions), repair))
+}
+
+/// Get the record offsets `upload_chunk` recorded for the sorted chunk at `key`, for `read_sorted_chunk`
+///
+/// # Errors
+///
+/// - ArchiveError::StoreObjectError: If the record offsets sidecar can't be fetched, i.e. the chunk wasn't sorted
+/// - ArchiveError::OrderError: If the sidecar isn't a whole number of record offsets
+pub async fn get_record_offsets<S>(store: &S, key: &str) -> Result<Vec<RecordOffset>, ArchiveError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let offsets_key = record_offsets_key(key);
+    let object =
+        store
+            .get(&offsets_key)
+            .await
+            .map_err(|source| ArchiveError::StoreObjectError {
+                location: store.location().to_owned(),
+                key: offsets_key,
+                source,
+            })?;
+    decode_record_offsets(&object.body)
+}
+
+/// Timestamps of measurements read from a chunk, repaired with `repair`
+///
+/// `partitions` is the partition of each measurement when they're grouped by partition, so each partition is
+/// repaired on its own. None repairs the whole chunk as one sequence.
+fn repair_chunk<M>(
+    measurements: Vec<M>,
+    partitions: Option<&[i32]>,
+    repair: Option<TimestampRepair>,
+) -> ReadChunk<M>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let mut timestamps: Vec<DateTime<Utc>> = measurements.iter().map(|m| m.timestamp()).collect();
+
+    let repaired = match (repair, partitions) {
+        (Some(repair), Some(partitions)) => {
+            let mut repaired = 0;
+            let mut start = 0;
+            while start < timestamps.len() {
+                let end = partitions[start..]
+                    .iter()
+                    .position(|p| *p != partitions[start])
+                    .map_or(timestamps.len(), |p| start + p);
+                repaired += repair_timestamps(&mut timestamps[start..end], repair);
+                start = end;
+            }
+            repaired
+        }
+        (Some(repair), None) => repair_timestamps(&mut timestamps, repair),
+        (None, _) => 0,
+    };
+    if repaired > 0 {
+        event!(
+            Level::WARN,
+            repaired,
+            "Repaired non-monotonic timestamps in archive chunk"
+        );
+    }
+
+    ReadChunk {
+        measurements,
+        timestamps,
+        repaired,
+    }
+}
+
+/// Make a sequence of timestamps monotonically non-decreasing, returning how many were changed
+pub fn repair_timestamps(timestamps: &mut [DateTime<Utc>], repair: TimestampRepair) -> usize {
+    let mut repaired = 0;
+    let mut i = 1;
+
+    while i < timestamps.len() {
+        let previous = timestamps[i - 1];
+        if timestamps[i] >= previous {
+            i += 1;
+            continue;
+        }
+
+        // The run of backwards timestamps ends at the first timestamp at or after the last good one
+        let end = timestamps[i..]
+            .iter()
+            .position(|t| *t >= previous)
+            .map(|p| p + i);
+
+        match (repair, end) {
+            (TimestampRepair::Interpolate, Some(end)) => {
+                let step = (timestamps[end] - previous) / (end - i + 1) as i32;
+                for (k, t) in timestamps[i..end].iter_mut().enumerate() {
+                    *t = previous + step * (k + 1) as i32;
+                }
+                repaired += end - i;
+                i = end;
+            }
+            _ => {
+                let end = end.unwrap_or(timestamps.len());
+                for t in &mut timestamps[i..end] {
+                    *t = previous;
+                }
+                repaired += end - i;
+                i = end;
+            }
+        }
+    }
+
+    repaired
+}
+
+/// Re-publish archived measurements under `prefix` to Redpanda, oldest chunk first
+///
+/// Lists every object under `prefix`, downloads and decompresses each one with `download_object_zstd_dict` (so
+/// archives compressed with one of `dictionaries` can be replayed), and produces its measurements with
+/// `Measurement::to_message`. Objects are replayed in the order of the RFC 3339 timestamp at the end of their key (the
+/// earliest partition timestamp in the chunk). The indexes under `prefix` are skipped, and so are other keys without a
+/// timestamp suffix, with a WARN.
+///
+/// Only measurements with a `partition_timestamp` in `[start, end)` are produced. Either bound can be None to
+/// replay from the beginning or to the end of the archive. Chunks keyed at or after `end` aren't downloaded.
+///
+/// Waits for every measurement in a chunk to be delivered before moving on to the next chunk, and returns the number
+/// of measurements produced.
+///
+/// # Errors
+///
+/// - ArchiveError::S3Error: If listing objects fails
+/// - ArchiveError::S3ObjectError: If downloading an object fails
+/// - ArchiveError::InvalidChunk, ArchiveError::DeserializeError: If an object isn't a readable `ArchiveChunk`
+/// - ArchiveError::KafkaError: If a measurement fails to be queued or delivered
+pub async fn replay_archive<M>(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    producer: &RedpandaProducer,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    dictionaries: &[ZstdDictionary],
+) -> Result<u64, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let keys = list_object_keys(client, bucket, &format!("{}/", prefix)).await?;
+
+    #[cfg(feature = "json")]
+    let keys = keys
+        .into_iter()
+        .filter(|key| !index::is_index_key(prefix, key));
+    let mut chunks: Vec<(DateTime<Utc>, String)> = keys
+        .into_iter()
+        .filter_map(|key| match key_timestamp(&key) {
+            Some(timestamp) => Some((timestamp, key)),
+            None => {
+                event!(Level::WARN, "Skipping {} without a timestamp suffix", key);
+                None
+            }
+        })
+        .filter(|(timestamp, _)| end.map_or(true, |end| *timestamp < end))
+        .collect();
+    chunks.sort();
+
+    let in_range = |t: DateTime<Utc>| start.map_or(true, |s| t >= s) && end.map_or(true, |e| t < e);
+    let mut replayed = 0;
+    for (_, key) in chunks {
+        let data = download_object_zstd_dict(client, bucket, &key, dictionaries)
+            .await
+            .map_err(|source| ArchiveError::S3ObjectError {
+                bucket: bucket.to_owned(),
+                key: key.clone(),
+                source,
+            })?;
+        let chunk: ReadChunk<M> = read_chunk(&data, None)?;
+
+        let mut deliveries = Vec::with_capacity(chunk.measurements.len());
+        for measurement in chunk.measurements {
+            if !in_range(measurement.partition_timestamp()) {
+                continue;
+            }
+            let delivery = producer
+                .send_result(&measurement.to_message())
+                .map_err(|(e, _)| e)?;
+            deliveries.push(delivery);
+        }
+
+        let count = deliveries.len() as u64;
+        for delivery in deliveries {
+            match delivery.await {
+                Ok(Ok(_)) => {}
+                Ok(Err((e, _))) => return Err(e.into()),
+                Err(_) => return Err(KafkaError::Canceled.into()),
+            }
+        }
+        replayed += count;
+        event!(
+            Level::INFO,
+            count,
+            "Replayed {} from bucket {}",
+            key,
+            bucket
+        );
+    }
+
+    Ok(replayed)
+}
+
+/// How archive object keys are laid out under their prefix
+///
+/// Either way, `run_archiver` appends the chunk's Kafka offsets to the timestamp (see `archive_key_with_offsets`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyLayout {
+    /// `{prefix}/{rfc3339}`, every object directly under the prefix
+    #[default]
+    Flat,
+    /// `{prefix}/year=YYYY/month=MM/day=DD/hour=HH/{rfc3339}`, Hive partitioned by UTC hour so Athena and Spark can
+    /// prune partitions and lifecycle rules can target date prefixes
+    Hive,
+}
+
+/// Key of the archive object for a chunk keyed by timestamp `ts` under `prefix`, i.e. the sensor name
+///
+/// Keys always end with the RFC 3339 timestamp, so `key_timestamp` parses keys of either layout.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ts = Utc.with_ymd_and_hms(2022, 10, 26, 7, 0, 0).unwrap();
+/// assert_eq!(
+///     archive_key("radar-2d", KeyLayout::Hive, ts),
+///     "radar-2d/year=2022/month=10/day=26/hour=07/2022-10-26T07:00:00+00:00"
+/// );
+/// ```
+pub fn archive_key(prefix: &str, layout: KeyLayout, ts: DateTime<Utc>) -> String {
+    match layout {
+        KeyLayout::Flat => format!("{}/{}", prefix, ts.to_rfc3339()),
+        KeyLayout::Hive => format!(
+            "{}/{}/{}",
+            prefix,
+            ts.format("year=%Y/month=%m/day=%d/hour=%H"),
+            ts.to_rfc3339()
+        ),
+    }
+}
+
+/// Most offset ranges spelled out in an archive key, chunks from more partitions are keyed by a hash of their ranges
+///
+/// Keeps keys well under S3's 1024 byte limit however many partitions a chunk spans.
+pub const MAX_KEY_OFFSET_RANGES: usize = 8;
+
+/// Key of the archive object for a chunk covering the Kafka `offsets`, keyed by timestamp `ts` under `prefix`
+///
+/// Like `archive_key`, with each partition's offset range appended to the timestamp as `_p{partition}-{first}-{last}`.
+/// A chunk re-archived after a crash between its upload and offset commit covers the same offsets and has the same
+/// earliest partition timestamp, so its upload replaces the object instead of duplicating it, and different chunks
+/// with the same timestamp can't overwrite each other. Chunks spanning more than `MAX_KEY_OFFSET_RANGES` partitions
+/// are suffixed `_{partitions}p-{hash}` instead, with the first 16 hex digits of the ranges' SHA-256.
+///
+/// Without offsets (i.e. `ArchiveSink` chunks), this is `archive_key`.
+///
+/// # Examples
+///
+/// ```no_run
+/// let ts = Utc.with_ymd_and_hms(2022, 10, 26, 7, 0, 0).unwrap();
+/// let offsets = [OffsetRange { partition: 0, first_offset: 100, last_offset: 199 }];
+/// assert_eq!(
+///     archive_key_with_offsets("radar-2d", KeyLayout::Flat, ts, &offsets),
+///     "radar-2d/2022-10-26T07:00:00+00:00_p0-100-199"
+/// );
+/// ```
+pub fn archive_key_with_offsets(
+    prefix: &str,
+    layout: KeyLayout,
+    ts: DateTime<Utc>,
+    offsets: &[OffsetRange],
+) -> String {
+    let key = archive_key(prefix, layout, ts);
+    if offsets.is_empty() {
+        return key;
+    }
+
+    let ranges: Vec<String> = offsets
+        .iter()
+        .map(|range| {
+            format!(
+                "p{}-{}-{}",
+                range.partition, range.first_offset, range.last_offset
+            )
+        })
+        .collect();
+    if ranges.len() > MAX_KEY_OFFSET_RANGES {
+        let hash = sha256_hex(ranges.join("_").as_bytes());
+        return format!("{}_{}p-{}", key, ranges.len(), &hash[..16]);
+    }
+
+    format!("{}_{}", key, ranges.join("_"))
+}
+
+/// Whether `suffix` is the offsets `archive_key_with_offsets` appends after the timestamp, or the suffix of a
+/// `compact::compacted_key`, without the leading `_`
+fn is_key_offsets(suffix: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let is_range = |s: &str| match s.strip_prefix('p') {
+        Some(range) => {
+            let parts: Vec<&str> = range.split('-').collect();
+            parts.len() == 3 && parts.iter().all(|part| is_number(part))
+        }
+        None => false,
+    };
+    let is_hash = |s: &str| match s.split_once("p-") {
+        Some((partitions, hash)) => {
+            is_number(partitions) && hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        None => false,
+    };
+
+    let is_compacted = |s: &str| match s.split_once("c-") {
+        Some((objects, hash)) => {
+            is_number(objects) && hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        None => false,
+    };
+
+    is_hash(suffix) || is_compacted(suffix) || suffix.split('_').all(is_range)
+}
+
+/// Stream every archived measurement under `prefix`, one object at a time
+///
+/// Objects are listed a page at a time as the stream is consumed, in S3 key order, which is chronological for the
+/// archiver's keys (RFC 3339 timestamps in UTC, with either `KeyLayout`). Each object is downloaded and decompressed
+/// with `download_object_zstd_dict`, picking the zstd dictionary it was compressed with from `dictionaries`, and its
+/// measurements are yielded in stored order. Keys without a timestamp suffix,
+/// i.e. manifests, are skipped.
+///
+/// The next object is downloaded in a background task while the current one's measurements are being consumed, so at
+/// most two objects and one page of keys are held in memory. The stream ends after yielding the first error.
+///
+/// # Errors
+///
+/// - ArchiveError::S3Error: If listing objects fails
+/// - ArchiveError::S3ObjectError: If downloading an object fails
+/// - ArchiveError::InvalidChunk, ArchiveError::DeserializeError: If an object isn't a readable `ArchiveChunk`
+///
+/// # Examples
+///
+/// ```no_run
+/// let client = cli.s3().build_client()?;
+/// let measurements = archive_stream::<RadarMeasurement2d>(&client, "opensensor-archive", "radar-2d", &[]);
+/// pin_mut!(measurements);
+/// while let Some(measurement) = measurements.next().await {
+///     println!("{:?}", measurement?.timestamp());
+/// }
+/// ```
+pub fn archive_stream<M>(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    dictionaries: &[ZstdDictionary],
+) -> impl Stream<Item = Result<M, ArchiveError>>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let client = client.clone();
+    let bucket = bucket.to_owned();
+    let prefix = format!("{}/", prefix);
+    let dictionaries = dictionaries.to_vec();
+
+    try_stream! {
+        let mut keys = ArchiveKeys::default();
+        let mut next = keys
+            .next(&client, &bucket, &prefix)
+            .await?
+            .map(|key| prefetch_object(&client, &bucket, key, &dictionaries));
+
+        while let Some(download) = next.take() {
+            let data = download
+                .await
+                .map_err(|e| ArchiveError::S3Error(Error::Unhandled(Box::new(e))))??;
+            // Start downloading the next object before handing out this one's measurements
+            next = keys
+                .next(&client, &bucket, &prefix)
+                .await?
+                .map(|key| prefetch_object(&client, &bucket, key, &dictionaries));
+
+            let measurements: Vec<M> = deserialize_chunk(&data)?;
+            drop(data);
+            for measurement in measurements {
+                yield measurement;
+            }
+        }
+    }
+}
+
+/// Archive object keys under a prefix, listed a page at a time for `archive_stream`
+#[derive(Default)]
+struct ArchiveKeys {
+    page: VecDeque<String>,
+    continuation_token: Option<String>,
+    listed_all: bool,
+}
+
+impl ArchiveKeys {
+    /// The next key with a timestamp suffix, listing another page when the current one runs out
+    async fn next(
+        &mut self,
+        client: &Client,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Option<String>, ArchiveError> {
+        loop {
+            if let Some(key) = self.page.pop_front() {
+                if key_timestamp(&key).is_some() {
+                    return Ok(Some(key));
+                }
+                event!(Level::DEBUG, "Skipping {} without a timestamp suffix", key);
+                continue;
+            }
+            if self.listed_all {
+                return Ok(None);
+            }
+
+            let (page, next_token) =
+                list_object_keys_page(client, bucket, prefix, self.continuation_token.take())
+                    .await?;
+            self.page.extend(page);
+            self.listed_all = next_token.is_none();
+            self.continuation_token = next_token;
+        }
+    }
+}
+
+/// Download and decompress an object in a background task
+fn prefetch_object(
+    client: &Client,
+    bucket: &str,
+    key: String,
+    dictionaries: &[ZstdDictionary],
+) -> JoinHandle<Result<Vec<u8>, ArchiveError>> {
+    let client = client.clone();
+    let bucket = bucket.to_owned();
+    let dictionaries = dictionaries.to_vec();
+    tokio::spawn(async move {
+        download_object_zstd_dict(&client, &bucket, &key, &dictionaries)
+            .await
+            .map_err(|source| ArchiveError::S3ObjectError {
+                bucket,
+                key,
+                source,
+            })
+    })
+}
+
+/// Keys of the archive objects for `sensor` whose time range overlaps the window `[start, end)`
+///
+/// Every key under `{sensor}/` is listed, so keys of either `KeyLayout` and split by source are included, in key
+/// order. With the `json` feature, an object's time range is its first to last measurement timestamp, read from its
+/// manifest. Objects without a readable manifest (or without the feature) are assumed to cover just the timestamp at
+/// the end of their key. Manifests and the sensor's indexes are skipped, and so are malformed keys that don't end with
+/// an RFC 3339 timestamp, with a WARN.
+///
+/// Reading manifests costs a GET per object that has one, so narrow `sensor` to a source (`{sensor}/{source_id}`)
+/// where possible.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If the bucket can't be listed
+///
+/// # Examples
+///
+/// ```no_run
+/// let start = Utc.with_ymd_and_hms(2022, 10, 26, 0, 0, 0).unwrap();
+/// let keys = list_archives_in_range(&client, "opensensor-archive", "radar-2d", start, start + Duration::days(1))
+///     .await?;
+/// ```
+pub async fn list_archives_in_range(
+    client: &Client,
+    bucket: &str,
+    sensor: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let keys = list_object_keys(client, bucket, &format!("{}/", sensor)).await?;
+    #[cfg(feature = "json")]
+    let manifests: std::collections::HashSet<&str> = keys
+        .iter()
+        .filter_map(|key| key.strip_suffix(MANIFEST_SUFFIX))
+        .collect();
+
+    let mut in_range = Vec::new();
+    for key in &keys {
+        if sidecar_chunk_key(key).is_some() {
+            continue;
+        }
+        #[cfg(feature = "json")]
+        if index::is_index_key(sensor, key) {
+            continue;
+        }
+        let timestamp = match key_timestamp(key) {
+            Some(timestamp) => timestamp,
+            None => {
+                event!(
+                    Level::WARN,
+                    "Skipping archive key {} without a timestamp suffix",
+                    key
+                );
+                continue;
+            }
+        };
+
+        #[cfg(feature = "json")]
+        let (first, last) = if manifests.contains(key.as_str()) {
+            match read_manifest(client, bucket, &manifest_key(key)).await {
+                Ok(manifest) => (manifest.first_timestamp, manifest.last_timestamp),
+                Err(e) => {
+                    event!(
+                        Level::WARN,
+                        "Can't read the manifest of {}, using its key timestamp. {}",
+                        key,
+                        e
+                    );
+                    (timestamp, timestamp)
+                }
+            }
+        } else {
+            (timestamp, timestamp)
+        };
+        #[cfg(not(feature = "json"))]
+        let (first, last) = (timestamp, timestamp);
+
+        if overlaps_window(first, last, start, end) {
+            in_range.push(key.clone());
+        }
+    }
+
+    Ok(in_range)
+}
+
+/// Suffix of manifest keys (`manifest::MANIFEST_SUFFIX`), for recognizing manifests without the `json` feature
+///
+/// Buckets can hold manifests written by an archiver built with the feature.
+const MANIFEST_KEY_SUFFIX: &str = ".manifest.json";
+
+/// Key of the chunk a sidecar object (a manifest or record offsets) belongs to, None if `key` isn't a sidecar
+fn sidecar_chunk_key(key: &str) -> Option<&str> {
+    key.strip_suffix(MANIFEST_KEY_SUFFIX)
+        .or_else(|| key.strip_suffix(RECORD_OFFSETS_SUFFIX))
+}
+
+/// Whether an archive covering `first` to `last` (inclusive) overlaps the window `[start, end)`
+fn overlaps_window(
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> bool {
+    first < end && last >= start
+}
+
+/// Parse the RFC 3339 timestamp at the end of an archive object key, before any offsets (see
+/// `archive_key_with_offsets`)
+fn key_timestamp(key: &str) -> Option<DateTime<Utc>> {
+    let suffix = key.rsplit('/').next()?;
+    let timestamp = match suffix.split_once('_') {
+        Some((timestamp, offsets)) if is_key_offsets(offsets) => timestamp,
+        Some(_) => return None,
+        None => suffix,
+    };
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Something wrong with an archive object, found by `scan_archive`
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScanProblem {
+    /// The object couldn't be fetched
+    #[error("Unreadable: {0}")]
+    Unreadable(String),
+    /// The object ends early: its zstd stream is incomplete, or it's smaller than S3 or its manifest says
+    #[error("Truncated: {0}")]
+    Truncated(String),
+    /// The object isn't valid for its codec, doesn't match its checksum, or isn't a readable `ArchiveChunk` of the
+    /// scanned measurement
+    #[error("Corrupt: {0}")]
+    Corrupt(String),
+}
+
+/// An archive object that failed its check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanIssue {
+    /// Key of the object
+    pub key: String,
+    /// What's wrong with it
+    pub problem: ScanProblem,
+}
+
+/// What a readable archive object holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectSummary {
+    /// Number of measurements in the object, None if it wasn't read and has no manifest
+    pub measurements: Option<usize>,
+    /// Earliest measurement timestamp in the object
+    pub first_timestamp: DateTime<Utc>,
+    /// Latest measurement timestamp in the object
+    pub last_timestamp: DateTime<Utc>,
+    /// Size of the object as stored
+    pub bytes: u64,
+}
+
+/// Time between two archive objects under the same key prefix that no object covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    /// Latest timestamp covered before the gap
+    pub start: DateTime<Utc>,
+    /// Earliest timestamp covered after the gap
+    pub end: DateTime<Utc>,
+}
+
+/// Time covered by the readable archive objects under one key prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coverage {
+    /// Number of readable objects under the prefix
+    pub objects: usize,
+    /// Earliest timestamp covered
+    pub first_timestamp: DateTime<Utc>,
+    /// Latest timestamp covered
+    pub last_timestamp: DateTime<Utc>,
+    /// Gaps longer than the scan's max gap, oldest first
+    pub gaps: Vec<Gap>,
+}
+
+/// Result of checking every archive object in a bucket with `scan_archive`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    /// Number of archive objects checked
+    pub objects: usize,
+    /// Number of measurements in the readable objects
+    ///
+    /// Quick scans count measurements from manifests, so objects without a manifest aren't counted.
+    pub measurements: u64,
+    /// Total stored size of the readable objects
+    pub bytes: u64,
+    /// Time covered under each key prefix, i.e. `radar-2d` or `radar-2d/{source_id}` when split by source
+    pub coverage: BTreeMap<String, Coverage>,
+    /// Objects that failed their check, in key order
+    pub issues: Vec<ScanIssue>,
+}
+
+impl ScanReport {
+    /// Whether every archive object passed its check
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Check every archive object under `prefix` in a bucket, reporting unreadable, truncated, and corrupt objects along
+/// with how many measurements the bucket holds and the time it covers
+///
+/// A full scan downloads and decompresses every object, verifies it against the SHA-256 stored under
+/// CHECKSUM_METADATA_KEY, and reads it as an `ArchiveChunk` of `M`, with whichever of `dictionaries` an object records
+/// it was compressed with. A `quick` scan
+/// only fetches each object's size with a HEAD request, and with the `json` feature compares it to the
+/// `compressed_bytes` in the object's manifest, taking the measurement count and timestamps from the manifest too.
+/// Checksums cover the uncompressed bytes, so a quick scan catches missing, empty, and truncated objects but not
+/// corrupted bytes.
+///
+/// Objects without an RFC 3339 timestamp at the end of their key (i.e. manifests) aren't archive objects and are
+/// skipped. Coverage is tracked per key prefix, and a gap is reported wherever more than `max_gap` passes between
+/// the objects under a prefix. Without manifests, a quick scan only knows when each object starts (from its key), so
+/// objects spanning more than `max_gap` are reported as gaps.
+///
+/// Problems with individual objects are logged at WARN and collected in the report rather than stopping the scan.
+///
+/// # Errors
+///
+/// - ArchiveError::S3Error: If the bucket can't be listed
+pub async fn scan_archive<M>(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    quick: bool,
+    max_gap: Duration,
+    dictionaries: &[ZstdDictionary],
+) -> Result<ScanReport, ArchiveError>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let keys = list_object_keys(client, bucket, prefix).await?;
+
+    let mut report = ScanReport::default();
+    let mut spans = Vec::new();
+    for key in keys {
+        let timestamp = match key_timestamp(&key) {
+            Some(timestamp) => timestamp,
+            None => {
+                event!(Level::DEBUG, "Skipping {} without a timestamp suffix", key);
+                continue;
+            }
+        };
+        report.objects += 1;
+
+        let summary = if quick {
+            quick_check_object(client, bucket, &key, timestamp).await
+        } else {
+            check_object::<M>(client, bucket, &key, dictionaries).await
+        };
+        match summary {
+            Ok(summary) => {
+                report.measurements += summary.measurements.unwrap_or_default() as u64;
+                report.bytes += summary.bytes;
+                let prefix = key.rsplit_once('/').map_or("", |(prefix, _)| prefix);
+                spans.push((
+                    prefix.to_owned(),
+                    summary.first_timestamp,
+                    summary.last_timestamp,
+                ));
+            }
+            Err(problem) => {
+                event!(
+                    Level::WARN,
+                    "Archive object {} failed its check. {}",
+                    key,
+                    problem
+                );
+                report.issues.push(ScanIssue { key, problem });
+            }
+        }
+    }
+    report.coverage = coverage(spans, max_gap);
+
+    event!(
+        Level::INFO,
+        objects = report.objects,
+        measurements = report.measurements,
+        bytes = report.bytes,
+        issues = report.issues.len(),
+        gaps = report
+            .coverage
+            .values()
+            .map(|c| c.gaps.len())
+            .sum::<usize>(),
+        "Scanned bucket {}",
+        bucket
+    );
+
+    Ok(report)
+}
+
+/// Download an archive object, verify its checksum, and read it as an `ArchiveChunk` of `M`, decompressed with the
+/// zstd dictionary its metadata names, if any
+async fn check_object<M>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<ObjectSummary, ScanProblem>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let object = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| ScanProblem::Unreadable(Error::from(e).to_string()))?;
+    let content_encoding = object.content_encoding().map(str::to_owned);
+    let content_length = object.content_length().max(0) as usize;
+    let metadata = object.metadata().cloned();
+    let dictionary = match &metadata {
+        Some(metadata) => metadata_dictionary(metadata, dictionaries)
+            .map_err(|e| ScanProblem::Unreadable(e.to_string()))?,
+        None => None,
+    };
+    let body = object
+        .body
+        .collect()
+        .await
+        .map_err(|e| ScanProblem::Unreadable(e.to_string()))?
+        .into_bytes();
+    if body.len() < content_length {
+        return Err(ScanProblem::Truncated(format!(
+            "downloaded {} of {} bytes",
+            body.len(),
+            content_length
+        )));
+    }
+
+    check_chunk::<M>(
+        key,
+        &body,
+        content_encoding.as_deref(),
+        dictionary,
+        metadata.as_ref(),
+    )
+}
+
+/// Check the size of an archive object against its manifest, without downloading it
+///
+/// Without a manifest, the object is assumed to cover just `timestamp`, the timestamp in its key.
+async fn quick_check_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    timestamp: DateTime<Utc>,
+) -> Result<ObjectSummary, ScanProblem> {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| ScanProblem::Unreadable(Error::from(e).to_string()))?;
+    let bytes = head.content_length().max(0) as u64;
+    if bytes == 0 {
+        return Err(ScanProblem::Truncated("empty object".to_owned()));
+    }
+
+    #[cfg(feature = "json")]
+    match read_manifest(client, bucket, &manifest_key(key)).await {
+        Ok(manifest) if manifest.compressed_bytes as u64 > bytes => {
+            return Err(ScanProblem::Truncated(format!(
+                "{} bytes, manifest says {}",
+                bytes, manifest.compressed_bytes
+            )))
+        }
+        Ok(manifest) if manifest.compressed_bytes as u64 != bytes => {
+            return Err(ScanProblem::Corrupt(format!(
+                "{} bytes, manifest says {}",
+                bytes, manifest.compressed_bytes
+            )))
+        }
+        Ok(manifest) => {
+            return Ok(ObjectSummary {
+                measurements: Some(manifest.record_count),
+                first_timestamp: manifest.first_timestamp,
+                last_timestamp: manifest.last_timestamp,
+                bytes,
+            })
+        }
+        Err(e) => event!(Level::DEBUG, "No readable manifest for {}. {}", key, e),
+    }
+
+    Ok(ObjectSummary {
+        measurements: None,
+        first_timestamp: timestamp,
+        last_timestamp: timestamp,
+        bytes,
+    })
+}
+
+/// Decompress (with the codec `content_encoding` names, and `dictionary` if the object was compressed with one) and
+/// read the body of archive object `key` as an `ArchiveChunk` of `M`
+///
+/// The decompressed bytes are verified against the checksum in the object's user `metadata`, like
+/// `verify_checksum`. Objects uploaded without one are only checked for being readable.
+///
+/// # Errors
+///
+/// - ScanProblem::Truncated: If the compressed stream ends early
+/// - ScanProblem::Corrupt: If the body isn't valid for its content encoding, the content encoding is unknown, the
+///   decompressed bytes don't match the stored checksum, or the body isn't an `ArchiveChunk` of `M` or holds no
+///   measurements
+pub fn check_chunk<M>(
+    key: &str,
+    body: &[u8],
+    content_encoding: Option<&str>,
+    dictionary: Option<&ZstdDictionary>,
+    metadata: Option<&HashMap<String, String>>,
+) -> Result<ObjectSummary, ScanProblem>
+where
+    M: for<'a> Measurement<'a>,
+{
+    let data = codec::decompress_with_dictionary(content_encoding, body, dictionary).map_err(
+        |e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ScanProblem::Truncated(e.to_string()),
+            _ => ScanProblem::Corrupt(e.to_string()),
+        },
+    )?;
+
+    match verify_checksum(key, metadata, &data) {
+        Ok(()) => {}
+        Err(ArchiveError::MissingChecksum(_)) => {
+            event!(Level::DEBUG, "{} has no checksum to verify", key)
+        }
+        Err(e) => return Err(ScanProblem::Corrupt(e.to_string())),
+    }
+
+    let chunk: ReadChunk<M> =
+        read_chunk(&data, None).map_err(|e| ScanProblem::Corrupt(e.to_string()))?;
+    let (first_timestamp, last_timestamp) =
+        match (chunk.timestamps.iter().min(), chunk.timestamps.iter().max()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => {
+                return Err(ScanProblem::Corrupt(
+                    "archive chunk has no measurements".to_owned(),
+                ))
+            }
+        };
+
+    Ok(ObjectSummary {
+        measurements: Some(chunk.measurements.len()),
+        first_timestamp,
+        last_timestamp,
+        bytes: body.len() as u64,
+    })
+}
+
+/// Time covered by the objects under each key prefix, given each object's prefix and first and last timestamps
+///
+/// A gap is recorded wherever an object starts more than `max_gap` after every earlier object under the same prefix
+/// ended.
+pub fn coverage(
+    mut spans: Vec<(String, DateTime<Utc>, DateTime<Utc>)>,
+    max_gap: Duration,
+) -> BTreeMap<String, Coverage> {
+    let max_gap =
+        chrono::Duration::from_std(max_gap).unwrap_or_else(|_| chrono::Duration::max_value());
+    spans.sort();
+
+    let mut coverage: BTreeMap<String, Coverage> = BTreeMap::new();
+    for (prefix, first, last) in spans {
+        match coverage.get_mut(&prefix) {
+            Some(c) => {
+                if first - c.last_timestamp > max_gap {
+                    c.gaps.push(Gap {
+                        start: c.last_timestamp,
+                        end: first,
+                    });
+                }
+                c.objects += 1;
+                c.last_timestamp = c.last_timestamp.max(last);
+            }
+            None => {
+                coverage.insert(
+                    prefix,
+                    Coverage {
+                        objects: 1,
+                        first_timestamp: first,
+                        last_timestamp: last,
+                        gaps: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+
+    coverage
+}
+
+/// Delete a bucket, assuming all objects have already been removed from the bucket
+pub async fn delete_bucket(client: &Client, bucket_name: &str) -> Result<(), Error> {
+    client.delete_bucket().bucket(bucket_name).send().await?;
+    event!(Level::INFO, "Deleted bucket {}", bucket_name);
+    Ok(())
+}
+
+/// Most keys a single S3 `DeleteObjects` request accepts
+pub const MAX_DELETE_BATCH: usize = 1000;
+
+/// Delete all objects within a bucket, allowing the bucket to be deleted without forcing
+///
+/// Lists every object (following continuation tokens past the 1000 keys a single listing returns) and deletes them
+/// in batches of MAX_DELETE_BATCH.
+pub async fn delete_objects(client: &Client, bucket_name: &str) -> Result<(), Error> {
+    let keys = list_object_keys(client, bucket_name, "").await?;
+    delete_keys(client, bucket_name, &keys).await?;
+
+    let objects: ListObjectsV2Output = client.list_objects_v2().bucket(bucket_name).send().await?;
+    match objects.key_count {
+        0 => Ok(()),
+        _ => Err(Error::Unhandled(Box::from(
+            "There were still objects left in the bucket.",
+        ))),
+    }
+}
+
+/// Delete `keys` from a bucket in batches of MAX_DELETE_BATCH, returning how many were deleted
+///
+/// Keys S3 reports it couldn't delete are logged at WARN and left out of the count.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If a `DeleteObjects` request fails
+pub async fn delete_keys(
+    client: &Client,
+    bucket_name: &str,
+    keys: &[String],
+) -> Result<usize, Error> {
+    let mut deleted = 0;
+    for batch in keys.chunks(MAX_DELETE_BATCH) {
+        let delete_objects: Vec<ObjectIdentifier> = batch
+            .iter()
+            .map(|key| ObjectIdentifier::builder().key(key).build())
+            .collect();
+        let output = client
+            .delete_objects()
+            .bucket(bucket_name)
+            .delete(Delete::builder().set_objects(Some(delete_objects)).build())
+            .send()
+            .await?;
+
+        let errors = output.errors().unwrap_or_default();
+        for error in errors {
+            event!(
+                Level::WARN,
+                "Failed to delete {} from bucket {}. {}",
+                error.key().unwrap_or_default(),
+                bucket_name,
+                error.message().unwrap_or_default()
+            );
+        }
+        deleted += batch.len().saturating_sub(errors.len());
+    }
+
+    Ok(deleted)
+}
+
+/// Delete the archive objects for `sensor` that are older than `older_than`, returning how many objects were deleted
+///
+/// Enforces retention without S3 lifecycle rules, which not every S3-compatible store supports. Every key under
+/// `{sensor}/` is listed (with either `KeyLayout`, and split by source), and a chunk is expired once every measurement
+/// in it is before `older_than`. Keys are timestamped with the chunk's earliest measurement, so a chunk is only
+/// expired when the next chunk of the same series (the same sensor or source, whatever the key layout) starts by
+/// `older_than`, or, for the latest chunk before the cutoff, when its `LAST_TIMESTAMP_METADATA_KEY` (read with a HEAD
+/// request) is before `older_than`. Chunks straddling the cutoff, and chunks archived before that metadata was
+/// recorded with no later chunk to go by, are kept. Sidecars (manifests and record offsets) expire with the chunk they
+/// describe and are included in the count. Malformed keys without a timestamp are skipped with a WARN.
+///
+/// With the `json` feature, expired chunks are removed from the sensor's daily indexes (see
+/// `index::remove_from_indexes`) before they're deleted, and indexes left empty are deleted. Index objects aren't
+/// included in the count.
+///
+/// With `dry_run`, the objects that would be deleted are logged at INFO and counted, but nothing is deleted and the
+/// indexes aren't changed.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If the bucket can't be listed, a chunk's metadata can't be read, or a `DeleteObjects` request
+///   fails
+///
+/// # Examples
+///
+/// ```no_run
+/// let cutoff = Utc::now() - chrono::Duration::days(90);
+/// let expired = expire_archives(&client, "opensensor-archive", "radar-2d", cutoff, true).await?;
+/// println!("{} objects are past retention", expired);
+/// ```
+pub async fn expire_archives(
+    client: &Client,
+    bucket_name: &str,
+    sensor: &str,
+    older_than: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<usize, Error> {
+    let keys = list_object_keys(client, bucket_name, &format!("{}/", sensor)).await?;
+
+    // Chunks started before the cutoff, by series
+    let mut series: HashMap<&str, Vec<(DateTime<Utc>, &str)>> = HashMap::new();
+    let mut sidecars = Vec::new();
+    for key in &keys {
+        #[cfg(feature = "json")]
+        if index::is_index_key(sensor, key) {
+            continue;
+        }
+        if let Some(chunk_key) = sidecar_chunk_key(key) {
+            sidecars.push((key, chunk_key));
+            continue;
+        }
+        match key_timestamp(key) {
+            Some(timestamp) => series
+                .entry(key_series(key))
+                .or_default()
+                .push((timestamp, key)),
+            None => event!(
+                Level::WARN,
+                "Skipping archive key {} without a timestamp suffix",
+                key
+            ),
+        }
+    }
+
+    let mut expired_chunks = HashSet::new();
+    for chunks in series.values_mut() {
+        chunks.sort();
+        for (i, (timestamp, key)) in chunks.iter().enumerate() {
+            if *timestamp >= older_than {
+                break;
+            }
+            let ended = match chunks.get(i + 1) {
+                Some((next, _)) if *next <= older_than => true,
+                _ => chunk_last_timestamp(client, bucket_name, key)
+                    .await?
+                    .map_or(false, |last| last < older_than),
+            };
+            if ended {
+                expired_chunks.insert(*key);
+            }
+        }
+    }
+
+    let listed: HashSet<&str> = keys.iter().map(String::as_str).collect();
+    let mut expired: Vec<&String> = keys
+        .iter()
+        .filter(|key| expired_chunks.contains(key.as_str()))
+        .collect();
+    for (key, chunk_key) in sidecars {
+        // Sidecars whose chunk is already gone expire by their own key
+        let orphan_expired = !listed.contains(chunk_key)
+            && key_timestamp(chunk_key).map_or(false, |timestamp| timestamp < older_than);
+        if expired_chunks.contains(chunk_key) || orphan_expired {
+            expired.push(key);
+        }
+    }
+
+    if dry_run {
+        for key in &expired {
+            event!(
+                Level::INFO,
+                "Would delete {} from bucket {}",
+                key,
+                bucket_name
+            );
+        }
+        return Ok(expired.len());
+    }
+
+    // Drop the expired chunks from the sensor's indexes first, so an index never lists a deleted chunk
+    #[cfg(feature = "json")]
+    {
+        let store = S3ObjectStore::new(client.clone(), bucket_name, Encryption::None);
+        let updated = index::remove_from_indexes(&store, sensor, &expired_chunks, None).await?;
+        event!(
+            Level::DEBUG,
+            "Removed expired archives from {} indexes of {}",
+            updated,
+            sensor
+        );
+    }
+
+    let expired: Vec<String> = expired.into_iter().cloned().collect();
+    let deleted = delete_keys(client, bucket_name, &expired).await?;
+    event!(
+        Level::INFO,
+        deleted,
+        "Expired archives for {} older than {} from bucket {}",
+        sensor,
+        older_than.to_rfc3339(),
+        bucket_name
+    );
+
+    Ok(deleted)
+}
+
+/// Series of chunks an archive key belongs to: the key without its timestamp or `KeyLayout::Hive` partitions
+///
+/// i.e. `radar-2d/radar-1` for `radar-2d/radar-1/year=2022/month=10/day=26/hour=07/2022-10-26T07:00:00+00:00`.
+fn key_series(key: &str) -> &str {
+    let mut series = key.rsplit_once('/').map_or("", |(parent, _)| parent);
+    while let Some((parent, partition)) = series.rsplit_once('/') {
+        if !["year=", "month=", "day=", "hour="]
+            .iter()
+            .any(|name| partition.starts_with(name))
+        {
+            break;
+        }
+        series = parent;
+    }
+    series
+}
+
+/// Latest measurement timestamp of a chunk from its `LAST_TIMESTAMP_METADATA_KEY`, None if it wasn't recorded
+async fn chunk_last_timestamp(
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let metadata = head_object_metadata(client, bucket_name, key).await?;
+    Ok(metadata
+        .get(LAST_TIMESTAMP_METADATA_KEY)
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.with_timezone(&Utc)))
+}
+
+/// Print a list of the objects within a bucket
+pub async fn list_objects(client: &Client, bucket_name: &str) -> Result<(), Error> {
+    let keys = list_object_keys(client, bucket_name, "").await?;
+    println!("Objects in bucket:");
+    for key in keys {
+        println!("{:?}", key);
+    }
+
+    Ok(())
+}
+
+/// List the keys of every object in a bucket that starts with `prefix`, following continuation tokens
+pub async fn list_object_keys(
+    client: &Client,
+    bucket_name: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Error> {
+    let mut keys = Vec::new();
+    let mut continuation_token = None;
+
+    loop {
+        let (page, next_token) =
+            list_object_keys_page(client, bucket_name, prefix, continuation_token).await?;
+        keys.extend(page);
+
+        match next_token {
+            Some(token) => continuation_token = Some(token),
+            None => break,
+        }
+    }
+
+    Ok(keys)
+}
+
+/// List one page (up to 1000 keys) of the objects in a bucket that start with `prefix`
+///
+/// Returns the keys and the continuation token for the next page, None on the last page.
+async fn list_object_keys_page(
+    client: &Client,
+    bucket_name: &str,
+    prefix: &str,
+    continuation_token: Option<String>,
+) -> Result<(Vec<String>, Option<String>), Error> {
+    let objects = client
+        .list_objects_v2()
+        .bucket(bucket_name)
+        .prefix(prefix)
+        .set_continuation_token(continuation_token)
+        .send()
+        .await?;
+    let keys = objects
+        .contents()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|obj| obj.key().map(str::to_owned))
+        .collect();
+    let next_token = match objects.next_continuation_token() {
+        Some(token) if objects.is_truncated() => Some(token.to_owned()),
+        _ => None,
+    };
+
+    Ok((keys, next_token))
+}
+
+/// Copy an S3 object within a bucket
+pub async fn copy_object(
+    client: &Client,
+    bucket_name: &str,
+    object_key: &str,
+    target_key: &str,
+) -> Result<(), Error> {
+    let mut source_bucket_and_object: String = "".to_owned();
+    source_bucket_and_object.push_str(bucket_name);
+    source_bucket_and_object.push('/');
+    source_bucket_and_object.push_str(object_key);
+
+    client
+        .copy_object()
+        .copy_source(source_bucket_and_object)
+        .bucket(bucket_name)
+        .key(target_key)
+        .send()
+        .await?;
+
+    event!(
+        Level::INFO,
+        "Copied {}/{} to {}/{}",
+        bucket_name,
+        object_key,
+        bucket_name,
+        target_key
+    );
+
+    Ok(())
+}
+
+/// Download a specific object from a bucket, given the S3 client configuration
+///
+/// # Parameters
+///
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If we fail to get the requested object, NoSuchKey if it doesn't exist
+///
+/// # Examples
+///
+/// ```no_run
+/// let access_key = "USERNAME";
+/// let secret_key = "SUPER_SECRET_PASSWORD";
+/// let endpoint = "http://localhost:9000";
+/// let region = "opensensor-region";
+/// let bucket_name = "opensensor-archive";
+/// let sensor_name = "radar-2d";
+/// let chunk_size = 10000;
+/// let kafka_addresses = "127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012";
+///
+/// let cli = Cli::new(
+///     access_key,
+///     secret_key,
+///     endpoint,
+///     region,
+///     bucket_name,
+///     sensor_name,
+///     chunk_size,
+///     kafka_addresses,
+/// );
+///
+/// let client = cli.s3().build_client()?;
+///
+/// let bucket = "models"
+/// let key = "simple/config.pbtxt"
+/// download_object(&client, bucket, key).await;
+/// ```
+pub async fn download_object(client: &Client, bucket: &str, key: &str) -> Result<(), Error> {
+    client.get_object().bucket(bucket).key(key).send().await?;
+
+    Ok(())
+}
+
+/// Download an S3 object and decompress it, the counterpart to `upload_object`
+///
+/// Objects are decompressed with the codec their stored content encoding names (`zstd`, `lz4`, or `snappy`, see
+/// `codec::decompress`), whichever `Codec` they were uploaded with. Objects stored without one are returned as-is,
+/// so this also reads uncompressed objects.
+///
+/// zstd decompression uses the streaming decoder rather than `zstd::bulk::decompress` because multipart uploads are
+/// compressed as a stream and don't record their decompressed size in the zstd frame header.
+///
+/// # Parameters
+///
+/// - client: the s3 client you want to use for downloading
+/// - bucket: the bucket to download from
+/// - key: key within the bucket to download
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If we fail to get the requested object, NoSuchKey if it doesn't exist
+/// - aws_sdk_s3::Error::Unhandled: If the body fails to download, isn't valid for its content encoding, or the content
+///   encoding is unknown
+pub async fn download_object_zstd(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Vec<u8>, Error> {
+    let (data, _) = get_object_zstd(client, bucket, key, &[]).await?;
+    Ok(data)
+}
+
+/// Download an S3 object and decompress it like `download_object_zstd`, with the zstd dictionary it was compressed
+/// with, the counterpart to `upload_object_zstd_dict`
+///
+/// The dictionary is picked from `dictionaries` by the id stored under ZSTD_DICTIONARY_METADATA_KEY. Objects
+/// compressed without a dictionary are decompressed as in `download_object_zstd`.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If we fail to get the requested object, NoSuchKey if it doesn't exist
+/// - aws_sdk_s3::Error::Unhandled: If none of `dictionaries` is the one the object was compressed with, or the body
+///   fails to download or decompress
+///
+/// # Examples
+///
+/// ```no_run
+/// let dictionary = ZstdDictionary::from_file("radar-2d.dict")?;
+/// let data = download_object_zstd_dict(&client, "opensensor-archive", key, &[dictionary]).await?;
+/// ```
+pub async fn download_object_zstd_dict(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<Vec<u8>, Error> {
+    let (data, _) = get_object_zstd(client, bucket, key, dictionaries).await?;
+    Ok(data)
+}
+
+/// Download and decompress an S3 object like `download_object_zstd_dict`, verifying it against its stored checksum
+///
+/// Every upload function in this module stores the SHA-256 of the uncompressed bytes in the object's
+/// `CHECKSUM_METADATA_KEY` user metadata. Recomputing it after decompression catches bit rot in storage and
+/// truncated or corrupted uploads that still decompress.
+///
+/// # Errors
+///
+/// - ArchiveError::S3ObjectError: If the object can't be downloaded
+/// - ArchiveError::DecompressError: If the object is truncated or corrupt, or none of `dictionaries` is the one it was
+///   compressed with (see `decompress_object`)
+/// - ArchiveError::ChecksumMismatch: If the decompressed bytes don't match the stored checksum
+/// - ArchiveError::MissingChecksum: If the object has no stored checksum
+pub async fn download_object_verified(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<Vec<u8>, ArchiveError> {
+    let store = S3ObjectStore::new(client.clone(), bucket, Encryption::None);
+    let object = store
+        .get(key)
+        .await
+        .map_err(|e| ArchiveError::S3ObjectError {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            source: e.into(),
+        })?;
+    let data = decompress_object(key, &object, dictionaries)?;
+    verify_checksum(key, Some(&object.metadata), &data)?;
+    Ok(data)
+}
+
+/// Decompress the archive object stored at `key` with the codec its content encoding names, and the zstd
+/// dictionary it was compressed with, if any (see `StoredObject::decompressed_with`)
+///
+/// Truncated or corrupt objects are an error naming the object, rather than a bare zstd (or lz4, or snappy) error.
+///
+/// # Errors
+///
+/// - ArchiveError::DecompressError: If the body is truncated, isn't valid for its content encoding, the content
+///   encoding is unknown, or none of `dictionaries` is the one it was compressed with
+///
+/// # Examples
+///
+/// ```no_run
+/// let object = store.get(key).await?;
+/// match decompress_object(key, &object, &[]) {
+///     Ok(data) => read_chunk::<RadarMeasurement>(&data, None)?,
+///     Err(ArchiveError::DecompressError { key, source }) => println!("{} is corrupt: {}", key, source),
+///     Err(e) => return Err(e),
+/// }
+/// ```
+pub fn decompress_object(
+    key: &str,
+    object: &StoredObject,
+    dictionaries: &[ZstdDictionary],
+) -> Result<Vec<u8>, ArchiveError> {
+    object
+        .decompressed_with(dictionaries)
+        .map_err(|source| ArchiveError::DecompressError {
+            key: key.to_owned(),
+            source,
+        })
+}
+
+/// Check an S3 object against its stored checksum without returning its contents
+///
+/// The object is still downloaded and decompressed in full, since the checksum covers the uncompressed bytes.
+///
+/// # Errors
+///
+/// - Same as `download_object_verified`
+///
+/// # Examples
+///
+/// ```no_run
+/// let client = cli.s3().build_client()?;
+/// match verify_object(&client, "opensensor-archive", "radar-2d/2022-10-26T00:00:00+00:00", &[]).await {
+///     Ok(()) => {}
+///     Err(ArchiveError::ChecksumMismatch { key, .. }) => println!("{} is corrupt", key),
+///     Err(e) => return Err(e),
+/// }
+/// ```
+pub async fn verify_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<(), ArchiveError> {
+    download_object_verified(client, bucket, key, dictionaries).await?;
     Ok(())
 }
 
-/// Delete all objects within a bucket, allowing the bucket to be deleted without forcing
-pub async fn delete_objects(client: &Client, bucket_name: &str) -> Result<(), Error> {
-    let objects = client.list_objects_v2().bucket(bucket_name).send().await?;
+/// Download an S3 object, decompressed with the codec its content encoding names and whichever of `dictionaries` it
+/// was compressed with, along with its user metadata
+async fn get_object_zstd(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &[ZstdDictionary],
+) -> Result<(Vec<u8>, Option<HashMap<String, String>>), Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket, Encryption::None);
+    let object = store.get(key).await?;
+    let data = object
+        .decompressed_with(dictionaries)
+        .map_err(|e| Error::Unhandled(Box::new(e)))?;
 
-    let mut delete_objects: Vec<ObjectIdentifier> = vec![];
-    for obj in objects.contents().unwrap_or_default() {
-        let obj_id = ObjectIdentifier::builder()
-            .set_key(Some(obj.key().unwrap().to_string()))
-            .build();
-        delete_objects.push(obj_id);
-    }
-    client
-        .delete_objects()
-        .bucket(bucket_name)
-        .delete(Delete::builder().set_objects(Some(delete_objects)).build())
-        .send()
-        .await?;
+    Ok((data, Some(object.metadata)))
+}
 
-    let objects: ListObjectsV2Output = client.list_objects_v2().bucket(bucket_name).send().await?;
-    match objects.key_count {
-        0 => Ok(()),
-        _ => Err(Error::Unhandled(Box::from(
-            "There were still objects left in the bucket.",
-        ))),
-    }
+/// S3 user metadata key of the SHA-256 of an object's uncompressed bytes, stored as `x-amz-meta-sha256`
+pub const CHECKSUM_METADATA_KEY: &str = "sha256";
+
+/// S3 user metadata key of the size of an object's uncompressed bytes, stored as `x-amz-meta-uncompressed-length`
+pub const UNCOMPRESSED_LENGTH_METADATA_KEY: &str = "uncompressed-length";
+
+/// S3 user metadata key of the number of measurements in an archive chunk, stored as `x-amz-meta-record-count`
+pub const RECORD_COUNT_METADATA_KEY: &str = "record-count";
+
+/// S3 user metadata key of the latest measurement timestamp in an archive chunk (RFC 3339), stored as
+/// `x-amz-meta-last-timestamp`
+///
+/// Keys only record a chunk's earliest timestamp, `expire_archives` reads this to tell when a chunk ends.
+pub const LAST_TIMESTAMP_METADATA_KEY: &str = "last-timestamp";
+
+/// S3 user metadata key of the id of the zstd dictionary an object was compressed with (see `codec::ZstdDictionary`),
+/// stored as `x-amz-meta-zstd-dictionary-id`
+pub const ZSTD_DICTIONARY_METADATA_KEY: &str = "zstd-dictionary-id";
+
+/// User metadata of an S3 object, fetched with a HEAD request so the body isn't downloaded
+///
+/// Archive chunks carry RECORD_COUNT_METADATA_KEY, UNCOMPRESSED_LENGTH_METADATA_KEY, and CHECKSUM_METADATA_KEY, so
+/// catalogs can index a bucket from HEAD requests alone. Keys are returned without the `x-amz-meta-` prefix. Objects
+/// without user metadata return an empty map.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: If the object doesn't exist or the HEAD request fails
+///
+/// # Examples
+///
+/// ```no_run
+/// let client = cli.s3().build_client()?;
+/// let metadata = head_object_metadata(&client, "opensensor-archive", "radar-2d/2022-10-26T00:00:00+00:00").await?;
+/// let record_count: usize = metadata[RECORD_COUNT_METADATA_KEY].parse()?;
+/// ```
+pub async fn head_object_metadata(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<HashMap<String, String>, Error> {
+    let head = client.head_object().bucket(bucket).key(key).send().await?;
+    Ok(head.metadata().cloned().unwrap_or_default())
 }
 
-/// Print a list of the objects within a bucket
-pub async fn list_objects(client: &Client, bucket_name: &str) -> Result<(), Error> {
-    let objects = client.list_objects_v2().bucket(bucket_name).send().await?;
-    println!("Objects in bucket:");
-    for obj in objects.contents().unwrap_or_default() {
-        println!("{:?}", obj.key().unwrap());
+/// SHA-256 of `data` as lowercase hex, the checksum stored under CHECKSUM_METADATA_KEY
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Check an object's uncompressed bytes against the checksum in its user metadata
+///
+/// # Errors
+///
+/// - ArchiveError::ChecksumMismatch: If the bytes don't match the stored checksum
+/// - ArchiveError::MissingChecksum: If there's no metadata or it has no CHECKSUM_METADATA_KEY
+pub fn verify_checksum(
+    key: &str,
+    metadata: Option<&HashMap<String, String>>,
+    data: &[u8],
+) -> Result<(), ArchiveError> {
+    let expected = match metadata.and_then(|metadata| metadata.get(CHECKSUM_METADATA_KEY)) {
+        Some(expected) => expected,
+        None => return Err(ArchiveError::MissingChecksum(key.to_owned())),
+    };
+    let actual = sha256_hex(data);
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(ArchiveError::ChecksumMismatch {
+            key: key.to_owned(),
+            expected: expected.clone(),
+            actual,
+        });
     }
 
     Ok(())
 }
 
-/// Copy an S3 object within a bucket
-pub async fn copy_object(
-    client: &Client,
-    bucket_name: &str,
-    object_key: &str,
-    target_key: &str,
-) -> Result<(), Error> {
-    let mut source_bucket_and_object: String = "".to_owned();
-    source_bucket_and_object.push_str(bucket_name);
-    source_bucket_and_object.push('/');
-    source_bucket_and_object.push_str(object_key);
-
-    client
-        .copy_object()
-        .copy_source(source_bucket_and_object)
-        .bucket(bucket_name)
-        .key(target_key)
-        .send()
-        .await?;
+/// Server-side encryption to request for uploaded objects
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encryption {
+    /// Leave encryption up to the bucket's default
+    #[default]
+    None,
+    /// SSE-S3, encrypted with keys managed by the object store (`AES256`)
+    Sse,
+    /// SSE-KMS, encrypted with a KMS key
+    SseKms {
+        /// KMS key id or ARN to encrypt with
+        key_id: String,
+    },
+}
 
-    event!(
-        Level::INFO,
-        "Copied {}/{} to {}/{}",
-        bucket_name,
-        object_key,
-        bucket_name,
-        target_key
-    );
+impl Encryption {
+    /// Value for the `x-amz-server-side-encryption` header, if any
+    pub fn server_side_encryption(&self) -> Option<ServerSideEncryption> {
+        match self {
+            Encryption::None => None,
+            Encryption::Sse => Some(ServerSideEncryption::Aes256),
+            Encryption::SseKms { .. } => Some(ServerSideEncryption::AwsKms),
+        }
+    }
 
-    Ok(())
+    /// Value for the `x-amz-server-side-encryption-aws-kms-key-id` header, if any
+    pub fn kms_key_id(&self) -> Option<String> {
+        match self {
+            Encryption::SseKms { key_id } => Some(key_id.clone()),
+            _ => None,
+        }
+    }
 }
 
-/// Download a specific object from a bucket, given the S3 client configuration
+/// Compresses and uploads an S3 object, given a client and bucket name
+///
+/// The object is compressed with `codec` and stored with its content encoding, so `download_object_zstd` picks the
+/// matching decompressor. The SHA-256 and size of the uncompressed bytes are added to the object's user metadata
+/// under CHECKSUM_METADATA_KEY (see `download_object_verified`) and UNCOMPRESSED_LENGTH_METADATA_KEY (see
+/// `head_object_metadata`).
 ///
 /// # Parameters
 ///
+/// - data_uncompressed: reference to a byte array, the uncompressed data you want to upload
+/// - client: the s3 client you want to use for uploading
+/// - bucket_name: the bucket to upload to
+/// - key: key within bucket bucket_name to upload to
+/// - codec: compression to apply, `Codec::default()` is zstd at level 0, the historical default
+/// - encryption: server-side encryption to request for the object
+/// - metadata: S3 user metadata to store with the object, i.e. RECORD_COUNT_METADATA_KEY for archive chunks
 ///
 /// # Errors
 ///
-/// - aws_sdk_s3::types::SdkError<aws_sdk_s3::error::GetObjectError>: If we fail to get the requested object
+/// - aws_sdk_s3::Error: catch-all error for all the reasons the upload could fail (data fails to upload,
+/// bucket name wrong, invalid key, compression level out of range, etc)
 ///
 /// # Examples
 ///
@@ -125,19 +3421,152 @@ pub async fn copy_object(
 ///     kafka_addresses,
 /// );
 ///
-/// let client = cli.build_client();
+/// let client = cli.s3().build_client()?;
 ///
-/// let bucket = "models"
-/// let key = "simple/config.pbtxt"
-/// download_object(&client, bucket, key).await;
+/// let data_uncompressed: [u8] = [1, 2, 3, 4, 5, 6];
+/// let key = "test_key"
+/// upload_object(&data_uncompressed, &client, bucket_name, key, &Codec::Lz4, &Encryption::None, None)
+///     .await
+///     .unwrap()
 /// ```
-pub async fn download_object(client: &Client, bucket: &str, key: &str) -> Result<(), Error> {
-    client.get_object().bucket(bucket).key(key).send().await?;
+pub async fn upload_object(
+    data_uncompressed: &[u8],
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    codec: &Codec,
+    encryption: &Encryption,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<(), Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket_name, encryption.clone());
+    put_with_retry(
+        &store,
+        key,
+        data_uncompressed,
+        codec,
+        0,
+        Duration::ZERO,
+        metadata,
+    )
+    .await?;
 
     Ok(())
 }
 
-/// Compresses and uploads an S3 object, given a client and bucket name
+/// Compresses with zstd and uploads an S3 object, given a client and bucket name
+///
+/// `upload_object` with `Codec::Zstd` at `compression_level` (see `zstd_compression_level`).
+///
+/// # Errors
+///
+/// - Same as `upload_object`
+#[deprecated(note = "use upload_object with Codec::Zstd")]
+pub async fn upload_object_zstd(
+    data_uncompressed: &[u8],
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    compression_level: i32,
+    encryption: &Encryption,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<(), Error> {
+    upload_object(
+        data_uncompressed,
+        client,
+        bucket_name,
+        key,
+        &Codec::Zstd {
+            level: compression_level,
+        },
+        encryption,
+        metadata,
+    )
+    .await
+}
+
+/// Compresses with zstd and a pre-trained dictionary and uploads an S3 object, given a client and bucket name
+///
+/// `upload_object` with `Codec::ZstdDict` at `compression_level` (see `zstd_compression_level`). The dictionary's id
+/// is stored under ZSTD_DICTIONARY_METADATA_KEY, so `download_object_zstd_dict` can pick it to decompress the object.
+///
+/// # Errors
+///
+/// - Same as `upload_object`
+///
+/// # Examples
+///
+/// ```no_run
+/// let dictionary = ZstdDictionary::new(train_dictionary(&samples, 4096)?)?;
+/// upload_object_zstd_dict(&data, &client, bucket_name, key, &dictionary, 3, &Encryption::None, None).await?;
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_object_zstd_dict(
+    data_uncompressed: &[u8],
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    dictionary: &ZstdDictionary,
+    compression_level: i32,
+    encryption: &Encryption,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<(), Error> {
+    upload_object(
+        data_uncompressed,
+        client,
+        bucket_name,
+        key,
+        &Codec::ZstdDict {
+            level: compression_level,
+            dictionary: dictionary.clone(),
+        },
+        encryption,
+        metadata,
+    )
+    .await
+}
+
+/// Compression level that means "use zstd's default compression level"
+pub const ZSTD_DEFAULT_LEVEL: i32 = -1;
+
+/// Validate a zstd compression level, resolving ZSTD_DEFAULT_LEVEL to zstd's default level
+///
+/// Levels must be within `zstd::compression_level_range()`. Higher levels trade CPU for smaller archives, which is
+/// usually the right trade for archival data where storage and transfer are the expensive part.
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error::Unhandled: If the level is outside the range zstd supports
+pub fn zstd_compression_level(compression_level: i32) -> Result<i32, Error> {
+    if compression_level == ZSTD_DEFAULT_LEVEL {
+        return Ok(zstd::DEFAULT_COMPRESSION_LEVEL);
+    }
+
+    let range = zstd::compression_level_range();
+    if range.contains(&compression_level) {
+        Ok(compression_level)
+    } else {
+        Err(Error::Unhandled(Box::from(format!(
+            "zstd compression level {} is out of range, expected {} to {} or {} for the default level",
+            compression_level,
+            range.start(),
+            range.end(),
+            ZSTD_DEFAULT_LEVEL
+        ))))
+    }
+}
+
+/// Longest delay between upload attempts, regardless of how many retries have been made
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Compresses and uploads an S3 object, retrying transient failures with exponential backoff
+///
+/// The data is compressed once and the upload is retried up to `max_retries` times on retryable errors (timeouts,
+/// connection failures, throttling, and 5xx responses). The delay before retry `n` is `base_delay * 2^n`, capped at
+/// MAX_RETRY_DELAY, with jitter so a fleet of archivers doesn't retry in lockstep. Non-retryable errors (bad
+/// credentials, invalid bucket, etc) are returned immediately.
+///
+/// The SHA-256 and size of the uncompressed bytes are added to `metadata` under CHECKSUM_METADATA_KEY (see
+/// `download_object_verified`) and UNCOMPRESSED_LENGTH_METADATA_KEY.
 ///
 /// # Parameters
 ///
@@ -145,11 +3574,111 @@ pub async fn download_object(client: &Client, bucket: &str, key: &str) -> Result
 /// - client: the s3 client you want to use for uploading
 /// - bucket_name: the bucket to upload to
 /// - key: key within bucket bucket_name to upload to
+/// - compression_level: zstd compression level, see `zstd_compression_level`
+/// - max_retries: how many times to retry after the first attempt fails
+/// - base_delay: delay before the first retry
+/// - encryption: server-side encryption to request for the object
+/// - metadata: S3 user metadata to store with the object, see `provenance_metadata`
+///
+/// Returns the size of the uploaded (compressed) object in bytes.
 ///
 /// # Errors
 ///
-/// - aws_sdk_s3::Error: catch-all error for all the reasons the upload could fail (data fails to upload,
-/// bucket name wrong, invalid key, etc)
+/// - aws_sdk_s3::Error: the last error if the upload still failed after max_retries, or the first non-retryable
+/// error
+///
+/// # Examples
+///
+/// ```no_run
+/// let client = cli.s3().build_client()?;
+///
+/// let data_uncompressed: [u8] = [1, 2, 3, 4, 5, 6];
+/// let key = "test_key"
+/// let retry_delay = Duration::from_millis(100);
+/// let encryption = Encryption::None;
+/// upload_with_retry(&data_uncompressed, &client, bucket_name, key, 0, 5, retry_delay, &encryption, None)
+///     .await
+///     .unwrap()
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_with_retry(
+    data_uncompressed: &[u8],
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    compression_level: i32,
+    max_retries: u32,
+    base_delay: Duration,
+    encryption: &Encryption,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<usize, Error> {
+    let store = S3ObjectStore::new(client.clone(), bucket_name, encryption.clone());
+    let compressed_bytes = put_with_retry(
+        &store,
+        key,
+        data_uncompressed,
+        &Codec::Zstd {
+            level: compression_level,
+        },
+        max_retries,
+        base_delay,
+        metadata,
+    )
+    .await?;
+
+    Ok(compressed_bytes)
+}
+
+/// Exponential backoff delay (without jitter) before retry number `attempt`, counting from 0
+pub fn retry_delay(base_delay: Duration, attempt: u32) -> Duration {
+    base_delay
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Whether a failed S3 request is worth retrying
+///
+/// Timeouts, dispatch (connection) failures, and unparseable responses are assumed transient. Service errors are
+/// only retried when S3 is throttling (429) or had an internal error (5xx).
+fn is_retryable<E>(error: &SdkError<E>) -> bool {
+    match error {
+        SdkError::TimeoutError(_)
+        | SdkError::DispatchFailure(_)
+        | SdkError::ResponseError { .. } => true,
+        SdkError::ServiceError { raw, .. } => {
+            let status = raw.http().status();
+            status.is_server_error() || status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+/// Smallest part size S3 accepts for every part of a multipart upload except the last one (5 MiB)
+pub const MULTIPART_MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Compresses and uploads an S3 object in parts, given a client and bucket name
+///
+/// Drop-in replacement for `upload_object` with `Codec::Zstd` for large chunks. The data is compressed incrementally
+/// and each compressed part is uploaded as soon as it reaches `part_size`, so the whole zstd buffer never has to be
+/// resident in memory at once and the object isn't subject to the 5GB single PUT limit. If any part fails to upload,
+/// the multipart upload is aborted so S3 doesn't keep the orphaned parts around. Like `upload_object`, the SHA-256
+/// and size of the uncompressed bytes are stored under CHECKSUM_METADATA_KEY and UNCOMPRESSED_LENGTH_METADATA_KEY.
+///
+/// # Parameters
+///
+/// - data_uncompressed: reference to a byte array, the uncompressed data you want to upload
+/// - client: the s3 client you want to use for uploading
+/// - bucket_name: the bucket to upload to
+/// - key: key within bucket bucket_name to upload to
+/// - part_size: target size in bytes of each compressed part, raised to MULTIPART_MIN_PART_SIZE if smaller
+/// - compression_level: zstd compression level, see `zstd_compression_level`
+/// - encryption: server-side encryption to request for the object
+///
+/// # Errors
+///
+/// - aws_sdk_s3::Error: catch-all error for all the reasons the upload could fail (a part fails to upload,
+/// bucket name wrong, invalid key, compression failure, etc)
 ///
 /// # Examples
 ///
@@ -174,38 +3703,186 @@ pub async fn download_object(client: &Client, bucket: &str, key: &str) -> Result
 ///     kafka_addresses,
 /// );
 ///
-/// let client = cli.build_client();
+/// let client = cli.s3().build_client()?;
 ///
-/// let data_uncompressed: [u8] = [1, 2, 3, 4, 5, 6];
+/// let data_uncompressed = vec![0u8; 64 * 1024 * 1024];
 /// let key = "test_key"
-/// upload_object_zstd(&data_uncompressed, &client, bucket_name, key).await.unwrap()
+/// let part_size = MULTIPART_MIN_PART_SIZE;
+/// upload_object_zstd_multipart(&data_uncompressed, &client, bucket_name, key, part_size, 0, &Encryption::None)
+///     .await
+///     .unwrap()
 /// ```
-pub async fn upload_object_zstd(
+pub async fn upload_object_zstd_multipart(
     data_uncompressed: &[u8],
     client: &Client,
     bucket_name: &str,
     key: &str,
+    part_size: usize,
+    compression_level: i32,
+    encryption: &Encryption,
 ) -> Result<(), Error> {
-    let body_compressed = ByteStream::from(zstd::bulk::compress(data_uncompressed, 0).unwrap());
-    client
-        .put_object()
+    // Fail before starting the upload rather than leaving an upload to abort
+    let compression_level = zstd_compression_level(compression_level)?;
+    let upload = client
+        .create_multipart_upload()
         .bucket(bucket_name)
         .key(key)
-        .body(body_compressed)
         .content_type("application/octet-stream")
         .content_encoding("zstd")
+        .set_server_side_encryption(encryption.server_side_encryption())
+        .set_ssekms_key_id(encryption.kms_key_id())
+        .metadata(CHECKSUM_METADATA_KEY, sha256_hex(data_uncompressed))
+        .metadata(
+            UNCOMPRESSED_LENGTH_METADATA_KEY,
+            data_uncompressed.len().to_string(),
+        )
+        .send()
+        .await?;
+    let upload_id = match upload.upload_id() {
+        Some(id) => id.to_owned(),
+        None => {
+            return Err(Error::Unhandled(Box::from(
+                "S3 didn't return an upload id for the multipart upload.",
+            )))
+        }
+    };
+
+    let parts = match upload_parts_zstd(
+        data_uncompressed,
+        client,
+        bucket_name,
+        key,
+        &upload_id,
+        part_size,
+        compression_level,
+    )
+    .await
+    {
+        Ok(parts) => parts,
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                "Aborting multipart upload of key {} to bucket {}. {}",
+                key,
+                bucket_name,
+                e
+            );
+            if let Err(abort_error) = client
+                .abort_multipart_upload()
+                .bucket(bucket_name)
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await
+            {
+                event!(
+                    Level::ERROR,
+                    "Failed to abort multipart upload {}, its parts may need to be cleaned up manually. {}",
+                    upload_id,
+                    abort_error
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    let part_count = parts.len();
+    client
+        .complete_multipart_upload()
+        .bucket(bucket_name)
+        .key(key)
+        .upload_id(&upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
         .send()
         .await?;
 
     event!(
         Level::INFO,
-        "Uploaded zstd compressed object at key {} to bucket {}",
+        "Uploaded zstd compressed object at key {} to bucket {} in {} parts",
         key,
         bucket_name,
+        part_count,
     );
     Ok(())
 }
 
+/// Incrementally compress `data_uncompressed`, uploading a part to an in-progress multipart upload every time the
+/// compressed output reaches `part_size`
+async fn upload_parts_zstd(
+    data_uncompressed: &[u8],
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    upload_id: &str,
+    part_size: usize,
+    compression_level: i32,
+) -> Result<Vec<CompletedPart>, Error> {
+    let part_size = part_size.max(MULTIPART_MIN_PART_SIZE);
+    let mut encoder =
+        zstd::stream::write::Encoder::new(Vec::with_capacity(part_size), compression_level)
+            .map_err(|e| Error::Unhandled(Box::new(e)))?;
+    let mut parts = Vec::new();
+
+    for block in data_uncompressed.chunks(part_size) {
+        encoder
+            .write_all(block)
+            .map_err(|e| Error::Unhandled(Box::new(e)))?;
+
+        // Every part but the last has to be at least MULTIPART_MIN_PART_SIZE
+        if encoder.get_ref().len() >= part_size {
+            let body = std::mem::take(encoder.get_mut());
+            let part_number = parts.len() as i32 + 1;
+            parts.push(upload_part(client, bucket_name, key, upload_id, part_number, body).await?);
+        }
+    }
+
+    let body = encoder
+        .finish()
+        .map_err(|e| Error::Unhandled(Box::new(e)))?;
+    if !body.is_empty() || parts.is_empty() {
+        let part_number = parts.len() as i32 + 1;
+        parts.push(upload_part(client, bucket_name, key, upload_id, part_number, body).await?);
+    }
+
+    Ok(parts)
+}
+
+/// Upload a single part of a multipart upload, returning the part's ETag for completing the upload
+async fn upload_part(
+    client: &Client,
+    bucket_name: &str,
+    key: &str,
+    upload_id: &str,
+    part_number: i32,
+    body: Vec<u8>,
+) -> Result<CompletedPart, Error> {
+    let part = client
+        .upload_part()
+        .bucket(bucket_name)
+        .key(key)
+        .upload_id(upload_id)
+        .part_number(part_number)
+        .body(ByteStream::from(body))
+        .send()
+        .await?;
+
+    event!(
+        Level::DEBUG,
+        "Uploaded part {} of key {} to bucket {}",
+        part_number,
+        key,
+        bucket_name
+    );
+    Ok(CompletedPart::builder()
+        .set_e_tag(part.e_tag().map(str::to_owned))
+        .part_number(part_number)
+        .build())
+}
+
 /// Create a s3 bucket given a region and s3 client configuration
 ///
 /// # Parameters:
@@ -241,7 +3918,7 @@ pub async fn upload_object_zstd(
 ///     kafka_addresses,
 /// );
 ///
-/// let client = cli.build_client();
+/// let client = cli.s3().build_client()?;
 ///
 /// create_bucket(&client, bucket_name, region).await.unwrap()
 /// ```
@@ -0,0 +1,160 @@
+//! Archive many sensors' topics from one process
+//!
+//! `run_multi_archiver` runs an archiver per sensor as a task on the current runtime, instead of deploying an
+//! archiver process per topic. Each archiver keeps its own consumer group, so they commit and fail independently,
+//! while sharing S3 clients and a cap on concurrent uploads across all of them.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use aws_sdk_s3::Client;
+use futures_util::future::BoxFuture;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{event, Level};
+
+use crate::archiver::cli::{Cli, S3Args};
+use crate::archiver::error::{ArchiveError, ConfigError};
+use crate::archiver::run_archiver_with_store;
+use crate::archiver::store::{LimitedObjectStore, ObjectStore, S3ObjectStore};
+use crate::measurement::Measurement;
+
+/// Runs one archiver to completion, given its configuration and the store to archive to
+type Runner<S> = Box<
+    dyn FnOnce(Cli, LimitedObjectStore<S>) -> BoxFuture<'static, Result<(), ArchiveError>> + Send,
+>;
+
+/// One sensor's archiver in a multi-archiver (see `run_multi_archiver`)
+///
+/// Built for the Measurement type the sensor's topic carries, so archivers of different sensors can run together.
+pub struct ArchiverConfig<S = S3ObjectStore> {
+    cli: Cli,
+    run: Runner<S>,
+}
+
+impl<S> ArchiverConfig<S>
+where
+    S: ObjectStore + 'static,
+{
+    /// Archive the sensor configured by `cli` with `run_archiver_with_store`, consuming Measurements of type `M`
+    ///
+    /// Only set `--metrics-address` on one of the configs run together, metrics can only be served once per process.
+    pub fn new<M>(cli: Cli) -> Self
+    where
+        M: for<'a> Measurement<'a> + Send + 'static,
+        for<'a> <M as Measurement<'a>>::Error: Send,
+    {
+        ArchiverConfig::with_runner(cli, run_archiver_with_store::<M, _>)
+    }
+
+    /// Archive the sensor configured by `cli` with `run` instead of `run_archiver_with_store`, i.e. to wrap it or
+    /// mock it in tests
+    pub fn with_runner<F, Fut>(cli: Cli, run: F) -> Self
+    where
+        F: FnOnce(Cli, LimitedObjectStore<S>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), ArchiveError>> + Send + 'static,
+    {
+        ArchiverConfig {
+            cli,
+            run: Box::new(move |cli, store| Box::pin(run(cli, store))),
+        }
+    }
+
+    /// Configuration of the sensor's archiver
+    pub fn cli(&self) -> &Cli {
+        &self.cli
+    }
+}
+
+/// How one archiver in a multi-archiver finished, with the name of the sensor it archived
+pub type ArchiverExit = (String, Result<(), ArchiveError>);
+
+/// Run an archiver per config as tasks on the current runtime, sharing S3 clients and a cap on concurrent uploads
+///
+/// Each archiver consumes with its own consumer group (`Cli::group_id`) and archives to its own bucket, but configs
+/// connecting to the same S3 endpoint with the same credentials share one client (see `S3Args::same_connection`),
+/// and every archiver's uploads share `max_concurrent_uploads` permits (at least 1, see
+/// `store::LimitedObjectStore`).
+///
+/// Archivers fail independently: when one stops with an error, the others keep running. Await the returned
+/// `JoinSet` to find out when and how each one stopped, i.e. to restart it or to exit once any has failed. Dropping
+/// it aborts every archiver. Must be called from within a tokio runtime.
+///
+/// # Errors
+///
+/// - ConfigError: If any config's S3 endpoint or region is invalid, in which case no archiver is started
+///
+/// # Examples
+///
+/// ```no_run
+/// let configs = vec![
+///     ArchiverConfig::new::<RadarMeasurement2d>(radar_cli),
+///     ArchiverConfig::new::<AisMeasurement>(ais_cli),
+/// ];
+/// let mut archivers = run_multi_archiver(configs, 8)?;
+/// while let Some(exit) = archivers.join_next().await {
+///     let (sensor_name, result) = exit?;
+///     if let Err(e) = result {
+///         event!(Level::ERROR, "Archiver for {} failed. {}", sensor_name, e);
+///     }
+/// }
+/// ```
+pub fn run_multi_archiver(
+    configs: Vec<ArchiverConfig>,
+    max_concurrent_uploads: usize,
+) -> Result<JoinSet<ArchiverExit>, ConfigError> {
+    let mut clients: Vec<(S3Args, Client)> = Vec::new();
+    let mut archivers = Vec::with_capacity(configs.len());
+    for config in configs {
+        let s3 = config.cli.s3();
+        let client = match clients.iter().find(|(args, _)| args.same_connection(s3)) {
+            Some((_, client)) => client.clone(),
+            None => {
+                let client = s3.build_client()?;
+                clients.push((s3.clone(), client.clone()));
+                client
+            }
+        };
+        let store = S3ObjectStore::new(client, config.cli.bucket_name(), config.cli.encryption());
+        archivers.push((config, store));
+    }
+
+    Ok(run_multi_archiver_with_stores(
+        archivers,
+        max_concurrent_uploads,
+    ))
+}
+
+/// Run an archiver per config like `run_multi_archiver`, each archiving to the store paired with it
+///
+/// Uploads to every store share `max_concurrent_uploads` permits (at least 1). Must be called from within a tokio
+/// runtime.
+pub fn run_multi_archiver_with_stores<S>(
+    archivers: Vec<(ArchiverConfig<S>, S)>,
+    max_concurrent_uploads: usize,
+) -> JoinSet<ArchiverExit>
+where
+    S: ObjectStore + 'static,
+{
+    let uploads = Arc::new(Semaphore::new(max_concurrent_uploads.max(1)));
+    let mut tasks = JoinSet::new();
+    for (config, store) in archivers {
+        let sensor_name = config.cli.sensor_name().to_owned();
+        let store = LimitedObjectStore::new(store, uploads.clone());
+        event!(
+            Level::INFO,
+            "Starting archiver for {} with group id {}",
+            sensor_name,
+            config.cli.group_id()
+        );
+        tasks.spawn(async move {
+            let result = (config.run)(config.cli, store).await;
+            if let Err(e) = &result {
+                event!(Level::ERROR, "Archiver for {} failed. {}", sensor_name, e);
+            }
+            (sensor_name, result)
+        });
+    }
+
+    tasks
+}
@@ -0,0 +1,159 @@
+//! Aggregate archiver statistics for capacity planning
+//!
+//! `run_archiver` accumulates an `ArchiveStats` as it uploads chunks and logs a single summary event every stats
+//! interval and whenever it stops, instead of leaving totals to be summed from the per-chunk logs.
+
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+
+use tracing::{event, Level};
+
+/// Totals of what an archiver has uploaded since it started
+#[derive(Clone, Copy, Debug)]
+pub struct ArchiveStats {
+    started: Instant,
+    messages: u64,
+    chunks: u64,
+    uncompressed_bytes: u64,
+    compressed_bytes: u64,
+    dead_letters: u64,
+}
+
+impl Default for ArchiveStats {
+    fn default() -> Self {
+        ArchiveStats::new()
+    }
+}
+
+impl ArchiveStats {
+    /// Stats starting now, with nothing uploaded yet
+    pub fn new() -> Self {
+        ArchiveStats {
+            started: Instant::now(),
+            messages: 0,
+            chunks: 0,
+            uncompressed_bytes: 0,
+            compressed_bytes: 0,
+            dead_letters: 0,
+        }
+    }
+
+    /// Record an uploaded chunk of `messages` measurements, serialized to `uncompressed_bytes` and uploaded as
+    /// `compressed_bytes`
+    pub fn record_chunk(
+        &mut self,
+        messages: usize,
+        uncompressed_bytes: usize,
+        compressed_bytes: usize,
+    ) {
+        self.messages += messages as u64;
+        self.chunks += 1;
+        self.uncompressed_bytes += uncompressed_bytes as u64;
+        self.compressed_bytes += compressed_bytes as u64;
+    }
+
+    /// Record a message that couldn't be deserialized and was sent to the dead letter topic
+    pub fn record_dead_letter(&mut self) {
+        self.dead_letters += 1;
+    }
+
+    /// Measurements archived
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// Chunks uploaded
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+
+    /// Serialized size of the chunks uploaded, before compression
+    pub fn uncompressed_bytes(&self) -> u64 {
+        self.uncompressed_bytes
+    }
+
+    /// Size of the chunks uploaded, after compression
+    pub fn compressed_bytes(&self) -> u64 {
+        self.compressed_bytes
+    }
+
+    /// Messages sent to the dead letter topic
+    pub fn dead_letters(&self) -> u64 {
+        self.dead_letters
+    }
+
+    /// Time since the stats started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Uncompressed bytes per compressed byte over every chunk uploaded (i.e. 4.0 for chunks compressed to a
+    /// quarter of their size), None if nothing has been uploaded
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.compressed_bytes == 0 {
+            return None;
+        }
+        Some(self.uncompressed_bytes as f64 / self.compressed_bytes as f64)
+    }
+
+    /// Average chunks uploaded per minute over `elapsed`, 0 if no time has passed
+    pub fn chunks_per_minute(&self, elapsed: Duration) -> f64 {
+        if elapsed.is_zero() {
+            return 0.0;
+        }
+        self.chunks as f64 / (elapsed.as_secs_f64() / 60.0)
+    }
+
+    /// Log the totals as a single structured event
+    pub fn log_summary(&self) {
+        let elapsed = self.elapsed();
+        event!(
+            Level::INFO,
+            messages = self.messages,
+            chunks = self.chunks,
+            uncompressed_bytes = self.uncompressed_bytes,
+            compressed_bytes = self.compressed_bytes,
+            dead_letters = self.dead_letters,
+            compression_ratio = self.compression_ratio().unwrap_or(0.0),
+            chunks_per_minute = self.chunks_per_minute(elapsed),
+            elapsed = ?elapsed,
+            "Archived {} measurements in {} chunks",
+            self.messages,
+            self.chunks
+        );
+    }
+}
+
+/// `ArchiveStats` that log their summary when dropped
+///
+/// `run_archiver` keeps its stats in one of these, so the summary is logged however it stops: when the topic's stream
+/// ends, when it returns an error, or when its future is dropped on shutdown.
+#[derive(Debug, Default)]
+pub struct SummaryOnDrop(ArchiveStats);
+
+impl SummaryOnDrop {
+    /// Stats starting now, with nothing uploaded yet
+    pub fn new() -> Self {
+        SummaryOnDrop(ArchiveStats::new())
+    }
+}
+
+impl Deref for SummaryOnDrop {
+    type Target = ArchiveStats;
+
+    fn deref(&self) -> &ArchiveStats {
+        &self.0
+    }
+}
+
+impl DerefMut for SummaryOnDrop {
+    fn deref_mut(&mut self) -> &mut ArchiveStats {
+        &mut self.0
+    }
+}
+
+impl Drop for SummaryOnDrop {
+    fn drop(&mut self) {
+        self.0.log_summary();
+    }
+}
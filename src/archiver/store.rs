@@ -0,0 +1,847 @@
+//! Pluggable object storage for archives
+//!
+//! The archiver writes chunks, manifests, and previews through the `ObjectStore` trait, so the same archiving logic
+//! runs against S3 (`S3ObjectStore`), the local filesystem (`FileSystemObjectStore`, for development and CI without
+//! MinIO), or any other backend that implements it.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use ::metrics::increment_counter;
+use async_trait::async_trait;
+use aws_sdk_s3::error::NoSuchKey;
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::{Client, Error};
+use rand::Rng;
+use tokio::sync::Semaphore;
+use tracing::{event, Level};
+
+use crate::archiver::codec::{self, Codec, ZstdDictionary};
+#[cfg(feature = "metrics")]
+use crate::archiver::metrics::UPLOAD_ERRORS;
+use crate::archiver::{
+    is_retryable, list_object_keys, retry_delay, sha256_hex, Encryption, CHECKSUM_METADATA_KEY,
+    UNCOMPRESSED_LENGTH_METADATA_KEY, ZSTD_DICTIONARY_METADATA_KEY,
+};
+
+/// Error for all object store operations
+#[derive(thiserror::Error, Debug)]
+pub enum ObjectStoreError {
+    /// An S3 request failed
+    #[error("S3 error: {source}")]
+    S3 {
+        /// The S3 error
+        source: Error,
+        /// Whether the request failed transiently (timeout, connection failure, throttling, or 5xx)
+        retryable: bool,
+    },
+    /// A filesystem operation failed
+    #[error("Object store I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// There's no object at the key
+    #[error("No object at {0}")]
+    NotFound(String),
+    /// The backend can't store an object at the key, i.e. it would escape a filesystem store's root
+    #[error("Invalid object key {0}")]
+    InvalidKey(String),
+    /// Any other error, for backends outside this crate
+    #[error("Object store error: {0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl ObjectStoreError {
+    /// Whether the operation might succeed if it's retried
+    ///
+    /// S3 errors are retryable when they're transient, filesystem errors when they were interrupted or timed out.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ObjectStoreError::S3 { retryable, .. } => *retryable,
+            ObjectStoreError::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::Interrupted | std::io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Wrap a failed S3 request, recording whether it's worth retrying
+fn s3_error<E>(error: SdkError<E>) -> ObjectStoreError
+where
+    Error: From<SdkError<E>>,
+{
+    ObjectStoreError::S3 {
+        retryable: is_retryable(&error),
+        source: error.into(),
+    }
+}
+
+/// Convert back to an S3 error, for the S3 free functions built on `S3ObjectStore`
+impl From<ObjectStoreError> for Error {
+    fn from(error: ObjectStoreError) -> Self {
+        match error {
+            ObjectStoreError::S3 { source, .. } => source,
+            ObjectStoreError::NotFound(key) => {
+                Error::NoSuchKey(NoSuchKey::builder().message(key).build())
+            }
+            ObjectStoreError::Other(e) => match e.downcast::<Error>() {
+                Ok(e) => *e,
+                Err(e) => Error::Unhandled(e),
+            },
+            e => Error::Unhandled(Box::new(e)),
+        }
+    }
+}
+
+/// An object's contents and the attributes the archiver stores with it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoredObject {
+    /// Object contents, as stored
+    pub body: Vec<u8>,
+    /// MIME type, i.e. "application/octet-stream" for archive chunks and "application/json" for manifests
+    pub content_type: Option<String>,
+    /// Content encoding, the `Codec` compressed archive chunks were compressed with (i.e. "zstd")
+    pub content_encoding: Option<String>,
+    /// User metadata (S3 `x-amz-meta-*`), i.e. the checksum and provenance metadata
+    pub metadata: HashMap<String, String>,
+}
+
+impl StoredObject {
+    /// An archive object compressed with `codec`
+    ///
+    /// The id of the codec's zstd dictionary, if any, is added to `metadata` under ZSTD_DICTIONARY_METADATA_KEY.
+    pub fn compressed(
+        body_compressed: Vec<u8>,
+        codec: &Codec,
+        mut metadata: HashMap<String, String>,
+    ) -> Self {
+        if let Some(dictionary) = codec.dictionary() {
+            metadata.insert(
+                ZSTD_DICTIONARY_METADATA_KEY.to_owned(),
+                dictionary.id().to_string(),
+            );
+        }
+        StoredObject {
+            body: body_compressed,
+            content_type: Some("application/octet-stream".to_owned()),
+            content_encoding: codec.content_encoding().map(str::to_owned),
+            metadata,
+        }
+    }
+
+    /// A zstd compressed archive object
+    pub fn zstd(body_compressed: Vec<u8>, metadata: HashMap<String, String>) -> Self {
+        StoredObject::compressed(body_compressed, &Codec::default(), metadata)
+    }
+
+    /// The body, decompressed with the codec its content encoding names (see `codec::decompress`)
+    ///
+    /// # Errors
+    ///
+    /// - std::io::ErrorKind::NotFound: If the object was compressed with a zstd dictionary, use `decompressed_with`
+    /// - std::io::Error: If the body isn't valid for its content encoding, or the content encoding is unknown
+    pub fn decompressed(&self) -> Result<Vec<u8>, std::io::Error> {
+        self.decompressed_with(&[])
+    }
+
+    /// The body, decompressed like `decompressed` with whichever of `dictionaries` has the id recorded under
+    /// ZSTD_DICTIONARY_METADATA_KEY, if the object was compressed with one
+    ///
+    /// # Errors
+    ///
+    /// - std::io::ErrorKind::NotFound: If none of `dictionaries` is the one the object was compressed with
+    /// - std::io::ErrorKind::InvalidData: If the recorded dictionary id isn't a number
+    /// - Same as `decompressed`
+    pub fn decompressed_with(
+        &self,
+        dictionaries: &[ZstdDictionary],
+    ) -> Result<Vec<u8>, std::io::Error> {
+        let dictionary = metadata_dictionary(&self.metadata, dictionaries)?;
+        codec::decompress_with_dictionary(self.content_encoding.as_deref(), &self.body, dictionary)
+    }
+}
+
+/// Whichever of `dictionaries` has the id an object's `metadata` records under ZSTD_DICTIONARY_METADATA_KEY, None
+/// if the object wasn't compressed with a dictionary
+///
+/// # Errors
+///
+/// - std::io::ErrorKind::NotFound: If none of `dictionaries` is the one the object was compressed with
+/// - std::io::ErrorKind::InvalidData: If the recorded dictionary id isn't a number
+pub(crate) fn metadata_dictionary<'a>(
+    metadata: &HashMap<String, String>,
+    dictionaries: &'a [ZstdDictionary],
+) -> Result<Option<&'a ZstdDictionary>, std::io::Error> {
+    let id = match metadata.get(ZSTD_DICTIONARY_METADATA_KEY) {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let id: u32 = id.parse().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid zstd dictionary id {}: {}", id, e),
+        )
+    })?;
+    match dictionaries.iter().find(|d| d.id() == id) {
+        Some(dictionary) => Ok(Some(dictionary)),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("compressed with zstd dictionary {}, which wasn't given", id),
+        )),
+    }
+}
+
+/// Storage the archiver writes archive objects to
+///
+/// Keys are `/` separated paths, i.e. "radar-2d/2022-10-26T00:00:00+00:00".
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Where objects are stored, for logs: the bucket name or root directory
+    fn location(&self) -> &str;
+
+    /// Store an object at `key`, replacing any object already there
+    async fn put(&self, key: &str, object: StoredObject) -> Result<(), ObjectStoreError>;
+
+    /// The object at `key`
+    ///
+    /// # Errors
+    ///
+    /// - ObjectStoreError::NotFound: If there's no object at `key`
+    async fn get(&self, key: &str) -> Result<StoredObject, ObjectStoreError>;
+
+    /// Stored (compressed) size in bytes of the object at `key`
+    ///
+    /// The default implementation gets the whole object, stores that can look up the size without downloading the
+    /// object should override it.
+    ///
+    /// # Errors
+    ///
+    /// - ObjectStoreError::NotFound: If there's no object at `key`
+    async fn size(&self, key: &str) -> Result<u64, ObjectStoreError> {
+        Ok(self.get(key).await?.body.len() as u64)
+    }
+
+    /// Keys of every object starting with `prefix`, sorted
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, ObjectStoreError>;
+
+    /// Delete the object at `key`, succeeding if there's no object there
+    async fn delete(&self, key: &str) -> Result<(), ObjectStoreError>;
+
+    /// The object at `key` and a tag that changes whenever it's replaced (its ETag), for `put_if_match`
+    ///
+    /// The default implementation gets the object without a tag, for stores that can't tell when an object changed.
+    ///
+    /// # Errors
+    ///
+    /// - ObjectStoreError::NotFound: If there's no object at `key`
+    async fn get_tagged(
+        &self,
+        key: &str,
+    ) -> Result<(StoredObject, Option<String>), ObjectStoreError> {
+        Ok((self.get(key).await?, None))
+    }
+
+    /// Store an object at `key` if the object there still has the tag `get_tagged` returned, or if there's still no
+    /// object there when `tag` is None, returning whether it was stored
+    ///
+    /// Used for read-modify-write updates (i.e. `index::append_to_index_in_store`) that retry when another writer got
+    /// there first. The default implementation stores the object unconditionally and returns true, for stores that
+    /// can't tell when an object changed.
+    async fn put_if_match(
+        &self,
+        key: &str,
+        object: StoredObject,
+        _tag: Option<&str>,
+    ) -> Result<bool, ObjectStoreError> {
+        self.put(key, object).await?;
+        Ok(true)
+    }
+}
+
+/// An S3 bucket, the object store the archiver uses by default
+#[derive(Clone)]
+pub struct S3ObjectStore {
+    client: Client,
+    bucket: String,
+    encryption: Encryption,
+}
+
+impl S3ObjectStore {
+    /// Store objects in `bucket`, requesting `encryption` for every object put
+    pub fn new(client: Client, bucket: &str, encryption: Encryption) -> Self {
+        S3ObjectStore {
+            client,
+            bucket: bucket.to_owned(),
+            encryption,
+        }
+    }
+
+    /// Client the store makes requests with
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Bucket objects are stored in
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3ObjectStore {
+    fn location(&self) -> &str {
+        &self.bucket
+    }
+
+    async fn put(&self, key: &str, object: StoredObject) -> Result<(), ObjectStoreError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(object.body))
+            .set_content_type(object.content_type)
+            .set_content_encoding(object.content_encoding)
+            .set_server_side_encryption(self.encryption.server_side_encryption())
+            .set_ssekms_key_id(self.encryption.kms_key_id())
+            .set_metadata(Some(object.metadata).filter(|metadata| !metadata.is_empty()))
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, ObjectStoreError> {
+        let (object, _) = self.get_tagged(key).await?;
+        Ok(object)
+    }
+
+    /// The object and its ETag
+    async fn get_tagged(
+        &self,
+        key: &str,
+    ) -> Result<(StoredObject, Option<String>), ObjectStoreError> {
+        let output = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(SdkError::ServiceError { err, .. }) if err.is_no_such_key() => {
+                return Err(ObjectStoreError::NotFound(key.to_owned()))
+            }
+            Err(e) => return Err(s3_error(e)),
+        };
+        let e_tag = output.e_tag().map(str::to_owned);
+        let content_type = output.content_type().map(str::to_owned);
+        let content_encoding = output.content_encoding().map(str::to_owned);
+        let metadata = output.metadata().cloned().unwrap_or_default();
+        let body = output
+            .body
+            .collect()
+            .await
+            .map_err(|e| ObjectStoreError::Other(Box::new(e)))?
+            .into_bytes()
+            .to_vec();
+
+        let object = StoredObject {
+            body,
+            content_type,
+            content_encoding,
+            metadata,
+        };
+        Ok((object, e_tag))
+    }
+
+    /// The object's content length, from a HEAD request
+    async fn size(&self, key: &str) -> Result<u64, ObjectStoreError> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(output.content_length().max(0) as u64),
+            Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => {
+                Err(ObjectStoreError::NotFound(key.to_owned()))
+            }
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, ObjectStoreError> {
+        list_object_keys(&self.client, &self.bucket, prefix)
+            .await
+            .map_err(|source| ObjectStoreError::S3 {
+                source,
+                retryable: false,
+            })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ObjectStoreError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        Ok(())
+    }
+
+    /// Check the object's ETag with a conditional (`If-Match`) HEAD request just before putting it
+    ///
+    /// S3 has no conditional PUT, so two writers that check within the same instant can still both put and one
+    /// update is lost: the check narrows that window rather than closing it.
+    async fn put_if_match(
+        &self,
+        key: &str,
+        object: StoredObject,
+        tag: Option<&str>,
+    ) -> Result<bool, ObjectStoreError> {
+        let mut head = self.client.head_object().bucket(&self.bucket).key(key);
+        if let Some(tag) = tag {
+            head = head.if_match(tag);
+        }
+        let unchanged = match head.send().await {
+            Ok(_) => tag.is_some(),
+            Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => tag.is_none(),
+            // If-Match failed, another writer replaced the object
+            Err(SdkError::ServiceError { raw, .. }) if raw.http().status().as_u16() == 412 => false,
+            Err(e) => return Err(s3_error(e)),
+        };
+        if !unchanged {
+            return Ok(false);
+        }
+
+        self.put(key, object).await?;
+        Ok(true)
+    }
+}
+
+/// An object store whose puts each hold a permit from a semaphore, capping how many run at once
+///
+/// Stores sharing the semaphore share the cap, i.e. every archiver run by `multi::run_multi_archiver`. Gets, lists,
+/// and deletes aren't limited.
+#[derive(Clone, Debug)]
+pub struct LimitedObjectStore<S> {
+    inner: S,
+    uploads: Arc<Semaphore>,
+}
+
+impl<S> LimitedObjectStore<S> {
+    /// Limit puts to `inner` to the permits available from `uploads`
+    pub fn new(inner: S, uploads: Arc<Semaphore>) -> Self {
+        LimitedObjectStore { inner, uploads }
+    }
+
+    /// Store puts are made to once they have a permit
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<S: ObjectStore> ObjectStore for LimitedObjectStore<S> {
+    fn location(&self) -> &str {
+        self.inner.location()
+    }
+
+    async fn put(&self, key: &str, object: StoredObject) -> Result<(), ObjectStoreError> {
+        let _permit = self
+            .uploads
+            .acquire()
+            .await
+            .map_err(|e| ObjectStoreError::Other(Box::new(e)))?;
+        self.inner.put(key, object).await
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, ObjectStoreError> {
+        self.inner.get(key).await
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, ObjectStoreError> {
+        self.inner.size(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, ObjectStoreError> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ObjectStoreError> {
+        self.inner.delete(key).await
+    }
+
+    async fn get_tagged(
+        &self,
+        key: &str,
+    ) -> Result<(StoredObject, Option<String>), ObjectStoreError> {
+        self.inner.get_tagged(key).await
+    }
+
+    async fn put_if_match(
+        &self,
+        key: &str,
+        object: StoredObject,
+        tag: Option<&str>,
+    ) -> Result<bool, ObjectStoreError> {
+        let _permit = self
+            .uploads
+            .acquire()
+            .await
+            .map_err(|e| ObjectStoreError::Other(Box::new(e)))?;
+        self.inner.put_if_match(key, object, tag).await
+    }
+}
+
+/// Directory under a `FileSystemObjectStore`'s root holding object contents
+const OBJECTS_DIR: &str = "objects";
+/// Directory under a `FileSystemObjectStore`'s root holding object attributes
+const ATTRIBUTES_DIR: &str = "attributes";
+
+/// A local directory, for developing and testing archivers without object storage
+///
+/// Objects are stored at `{root}/objects/{key}`, with `/` in keys mapping to subdirectories. Each object's content
+/// type, content encoding, and metadata are stored next to it in `{root}/attributes/{key}`, one `name=value` per
+/// line, so metadata values can't contain newlines.
+#[derive(Clone, Debug)]
+pub struct FileSystemObjectStore {
+    root: PathBuf,
+    location: String,
+}
+
+impl FileSystemObjectStore {
+    /// Store objects under `root`, which is created on the first put if it doesn't exist
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let location = root.display().to_string();
+        FileSystemObjectStore { root, location }
+    }
+
+    /// Directory objects are stored under
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Paths of the contents and attributes of the object at `key`
+    ///
+    /// # Errors
+    ///
+    /// - ObjectStoreError::InvalidKey: If the key has an empty, `.`, or `..` segment (including a leading or trailing
+    ///   `/`), which could escape the root or alias another key, or ends with `.tmp`, which is reserved for writes in
+    ///   progress
+    fn paths(&self, key: &str) -> Result<(PathBuf, PathBuf), ObjectStoreError> {
+        let is_valid = key
+            .split('/')
+            .all(|segment| !matches!(segment, "" | "." | ".."));
+        if !is_valid || key.ends_with(".tmp") {
+            return Err(ObjectStoreError::InvalidKey(key.to_owned()));
+        }
+        let relative = Path::new(key);
+
+        Ok((
+            self.root.join(OBJECTS_DIR).join(relative),
+            self.root.join(ATTRIBUTES_DIR).join(relative),
+        ))
+    }
+}
+
+/// Attributes of a `StoredObject` in the `FileSystemObjectStore` attributes file format
+fn format_attributes(object: &StoredObject) -> String {
+    let mut attributes = String::new();
+    if let Some(content_type) = &object.content_type {
+        attributes.push_str(&format!("content-type={}\n", content_type));
+    }
+    if let Some(content_encoding) = &object.content_encoding {
+        attributes.push_str(&format!("content-encoding={}\n", content_encoding));
+    }
+    let mut metadata: Vec<_> = object.metadata.iter().collect();
+    metadata.sort();
+    for (name, value) in metadata {
+        attributes.push_str(&format!("meta-{}={}\n", name, value));
+    }
+    attributes
+}
+
+/// Parse a `FileSystemObjectStore` attributes file into `object`, ignoring lines it doesn't recognize
+fn parse_attributes(attributes: &str, object: &mut StoredObject) {
+    for line in attributes.lines() {
+        let (name, value) = match line.split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        match name {
+            "content-type" => object.content_type = Some(value.to_owned()),
+            "content-encoding" => object.content_encoding = Some(value.to_owned()),
+            _ => {
+                if let Some(name) = name.strip_prefix("meta-") {
+                    object.metadata.insert(name.to_owned(), value.to_owned());
+                }
+            }
+        }
+    }
+}
+
+/// Write `contents` to `path` through a temporary file, so readers never see a partially written file
+async fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    tokio::fs::write(&temporary, contents).await?;
+    tokio::fs::rename(&temporary, path).await
+}
+
+#[async_trait]
+impl ObjectStore for FileSystemObjectStore {
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    async fn put(&self, key: &str, object: StoredObject) -> Result<(), ObjectStoreError> {
+        let (object_path, attributes_path) = self.paths(key)?;
+        if object.metadata.values().any(|value| value.contains('\n')) {
+            return Err(ObjectStoreError::Other(Box::from(format!(
+                "metadata of {} has a value with a newline, which the filesystem store can't keep",
+                key
+            ))));
+        }
+
+        // Attributes first, so an object is never visible without them
+        write_atomic(&attributes_path, format_attributes(&object).as_bytes()).await?;
+        write_atomic(&object_path, &object.body).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, ObjectStoreError> {
+        let (object_path, attributes_path) = self.paths(key)?;
+        let body = match tokio::fs::read(&object_path).await {
+            Ok(body) => body,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ObjectStoreError::NotFound(key.to_owned()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut object = StoredObject {
+            body,
+            ..StoredObject::default()
+        };
+        match tokio::fs::read_to_string(&attributes_path).await {
+            Ok(attributes) => parse_attributes(&attributes, &mut object),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(object)
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, ObjectStoreError> {
+        let (object_path, _) = self.paths(key)?;
+        match tokio::fs::metadata(&object_path).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(ObjectStoreError::NotFound(key.to_owned()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, ObjectStoreError> {
+        let objects = self.root.join(OBJECTS_DIR);
+        let mut keys = Vec::new();
+        let mut directories = vec![(objects, String::new())];
+
+        while let Some((directory, key_prefix)) = directories.pop() {
+            let mut entries = match tokio::fs::read_dir(&directory).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let key = format!("{}{}", key_prefix, name);
+                if entry.file_type().await?.is_dir() {
+                    directories.push((entry.path(), format!("{}/", key)));
+                } else if key.starts_with(prefix) && !name.ends_with(".tmp") {
+                    keys.push(key);
+                }
+            }
+        }
+
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ObjectStoreError> {
+        let (object_path, attributes_path) = self.paths(key)?;
+        for path in [object_path, attributes_path] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The object and the SHA-256 of its contents as its tag
+    async fn get_tagged(
+        &self,
+        key: &str,
+    ) -> Result<(StoredObject, Option<String>), ObjectStoreError> {
+        let object = self.get(key).await?;
+        let tag = sha256_hex(&object.body);
+        Ok((object, Some(tag)))
+    }
+
+    /// Compare the SHA-256 of the object's contents with `tag` before putting it
+    ///
+    /// The check and the put aren't atomic, so this only guards against writers in other processes that aren't
+    /// writing at the same instant.
+    async fn put_if_match(
+        &self,
+        key: &str,
+        object: StoredObject,
+        tag: Option<&str>,
+    ) -> Result<bool, ObjectStoreError> {
+        let current = match self.get_tagged(key).await {
+            Ok((_, current)) => current,
+            Err(ObjectStoreError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        if current.as_deref() != tag {
+            return Ok(false);
+        }
+
+        self.put(key, object).await?;
+        Ok(true)
+    }
+}
+
+/// Compress and store a zstd archive object on any object store, retrying transient failures
+///
+/// `put_with_retry` with `Codec::Zstd` at `compression_level`.
+///
+/// # Errors
+///
+/// - Same as `put_with_retry`
+pub async fn put_zstd_with_retry<S>(
+    store: &S,
+    key: &str,
+    data_uncompressed: &[u8],
+    compression_level: i32,
+    max_retries: u32,
+    base_delay: Duration,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<usize, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    put_with_retry(
+        store,
+        key,
+        data_uncompressed,
+        &Codec::Zstd {
+            level: compression_level,
+        },
+        max_retries,
+        base_delay,
+        metadata,
+    )
+    .await
+}
+
+/// Compress and store an archive object on any object store, retrying transient failures with exponential backoff
+///
+/// The object store counterpart to `archiver::upload_with_retry`, with the same retry schedule: the SHA-256 and size
+/// of the uncompressed bytes are added to `metadata` under CHECKSUM_METADATA_KEY and UNCOMPRESSED_LENGTH_METADATA_KEY,
+/// and errors that `ObjectStoreError::is_retryable` are retried up to `max_retries` times. The object is stored with
+/// `codec`'s content encoding so it can be decompressed with `StoredObject::decompressed`. Returns the size of the
+/// stored (compressed) object in bytes.
+///
+/// # Errors
+///
+/// - ObjectStoreError::Io: If the zstd compression level is out of range or compression fails
+/// - ObjectStoreError: the last error if the put still failed after max_retries, or the first non-retryable error
+pub async fn put_with_retry<S>(
+    store: &S,
+    key: &str,
+    data_uncompressed: &[u8],
+    codec: &Codec,
+    max_retries: u32,
+    base_delay: Duration,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<usize, ObjectStoreError>
+where
+    S: ObjectStore + ?Sized,
+{
+    let body_compressed = codec.compress(data_uncompressed)?;
+    let compressed_bytes = body_compressed.len();
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.insert(
+        CHECKSUM_METADATA_KEY.to_owned(),
+        sha256_hex(data_uncompressed),
+    );
+    metadata.insert(
+        UNCOMPRESSED_LENGTH_METADATA_KEY.to_owned(),
+        data_uncompressed.len().to_string(),
+    );
+    let object = StoredObject::compressed(body_compressed, codec, metadata);
+
+    let mut attempt = 0;
+    loop {
+        match store.put(key, object.clone()).await {
+            Ok(()) => break,
+            Err(e) if attempt < max_retries && e.is_retryable() => {
+                #[cfg(feature = "metrics")]
+                increment_counter!(UPLOAD_ERRORS);
+                // Equal jitter: wait somewhere between half and all of the backoff delay
+                let delay = retry_delay(base_delay, attempt)
+                    .mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+                attempt += 1;
+                event!(
+                    Level::WARN,
+                    "Failed to upload key {} to {}, retrying ({}/{}) in {:?}. {}",
+                    key,
+                    store.location(),
+                    attempt,
+                    max_retries,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                #[cfg(feature = "metrics")]
+                increment_counter!(UPLOAD_ERRORS);
+                return Err(e);
+            }
+        }
+    }
+
+    let compression = match codec.content_encoding() {
+        Some(encoding) => format!("{} compressed", encoding),
+        None => "uncompressed".to_owned(),
+    };
+    event!(
+        Level::INFO,
+        "Uploaded {} object at key {} to {}",
+        compression,
+        key,
+        store.location(),
+    );
+    Ok(compressed_bytes)
+}
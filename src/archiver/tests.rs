@@ -1,5 +1,49 @@
-use crate::archiver::cli::Cli;
-use crate::archiver::{create_bucket, delete_bucket};
+use crate::archive_generated::archive::root_as_archive_chunk;
+use crate::archiver::chunk::{
+    compact_chunk, compact_equal_runs, decode_record_offsets, deserialize_chunk,
+    encode_record_offsets, next_chunk_size, offset_ranges, restore_consumption_order,
+    serialize_chunk, serialize_records, sort_chunk, ChunkBytes, ChunkSort, Consumed, FullChunk,
+    OffsetRange, PartitionOffsets, RecordOffset, Reservoir, SourceChunks, SourceFilter,
+    MAX_CHUNK_BYTES,
+};
+use crate::archiver::cli::{Cli, KafkaArgs, S3Args, ScanCli};
+use crate::archiver::codec::{self, CodecKind};
+use crate::archiver::compact::{
+    compact_archives_in_store, compacted_from_key, compacted_key, CompactionReport,
+    COMPACTED_OBJECTS_METADATA_KEY,
+};
+use crate::archiver::error::{ArchiveError, ConfigError, FormatError};
+use crate::archiver::format::{read_archive, write_archive, ArchiveContents};
+use crate::archiver::multi::{run_multi_archiver_with_stores, ArchiverConfig};
+use crate::archiver::stats::{ArchiveStats, SummaryOnDrop};
+use crate::archiver::store::{
+    put_with_retry, put_zstd_with_retry, FileSystemObjectStore, LimitedObjectStore, ObjectStore,
+    ObjectStoreError, StoredObject,
+};
+use crate::archiver::{
+    archive_key, archive_key_with_offsets, archive_preview, archive_stream, check_chunk, coverage,
+    create_bucket, dead_letter_record, decompress_object, delete_bucket, delete_objects,
+    download_object_verified, download_object_zstd, expire_archives, get_record_offsets,
+    head_object_metadata, key_series, key_timestamp, list_archives_in_range, list_object_keys,
+    overlaps_window, poll_next, provenance_metadata, read_archive_raw, read_chunk,
+    read_sorted_chunk, repair_timestamps, retry_delay, run_archiver_with_store, scan_archive,
+    seek_start, sha256_hex, upload_chunk, upload_object, upload_object_zstd_multipart,
+    verify_checksum, verify_object, zstd_compression_level, ArchiveSink, Encryption, Gap,
+    KeyLayout, LagMeter, Polled, ScanProblem, StartFrom, TimestampRepair, CHECKSUM_METADATA_KEY,
+    DEAD_LETTER_ERROR_HEADER, DEAD_LETTER_OFFSET_HEADER, DEAD_LETTER_PARTITION_HEADER,
+    DEAD_LETTER_TOPIC_HEADER, LAG_INTERVAL, LAST_TIMESTAMP_METADATA_KEY, MAX_KEY_OFFSET_RANGES,
+    MAX_RETRY_DELAY, MULTIPART_MIN_PART_SIZE, RECORD_COUNT_METADATA_KEY,
+    UNCOMPRESSED_LENGTH_METADATA_KEY, ZSTD_DEFAULT_LEVEL, ZSTD_DICTIONARY_METADATA_KEY,
+};
+use crate::batch::{MAX_BATCH_BYTES, RECORD_OVERHEAD};
+use crate::measurement::Measurement;
+use crate::reflection_generated::reflection::KeyValue;
+use crate::tests::TestMeasurement;
+use crate::SensorSink;
+use clap::Parser;
+use redpanda::error::KafkaError;
+use std::collections::HashMap;
+use std::time::Duration;
 
 /// Create a test CLI that can be used for testing against the OpenSensor docker-compose
 pub fn create_test_cli() -> Cli {
@@ -24,34 +68,3763 @@ pub fn create_test_cli() -> Cli {
     )
 }
 
+/// Required archiver arguments, for CLI tests to parse with extra options chained on
+fn base_args() -> [&'static str; 17] {
+    [
+        "archiver",
+        "--access-key",
+        "user",
+        "--secret-key",
+        "user123456",
+        "--endpoint",
+        "http://localhost:9000",
+        "--region",
+        "opensensor-region",
+        "--bucket-name",
+        "opensensor-archive",
+        "--sensor-name",
+        "radar-2d",
+        "--chunk-size",
+        "10000",
+        "--kafka-addresses",
+        "127.0.0.1:9010",
+    ]
+}
+
+#[tokio::test]
+pub async fn test_create_delete_bucket() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+
+    // Valid inputs
+    let bucket_name = "test-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+
+    // Bad bucket name
+    let invalid_bucket_name = "test_bucket";
+    let create_result = create_bucket(&client, invalid_bucket_name, cli.region()).await;
+    assert!(create_result.is_err());
+
+    // Double create raises an Error
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+    let create_result = create_bucket(&client, bucket_name, cli.region()).await;
+    assert!(create_result.is_err());
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_upload() {}
+
+#[tokio::test]
+pub async fn test_list_and_delete_paginated() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-pagination-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    // More than one listing page and more than one delete batch
+    let count = 1500;
+    for i in 0..count {
+        client
+            .put_object()
+            .bucket(bucket_name)
+            .key(format!("dummy/{:04}", i))
+            .body(Vec::new().into())
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let keys = list_object_keys(&client, bucket_name, "").await.unwrap();
+    assert_eq!(keys.len(), count);
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    let keys = list_object_keys(&client, bucket_name, "").await.unwrap();
+    assert!(keys.is_empty());
+
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_upload_multipart() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-multipart-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    // Incompressible data so the upload spans several parts
+    let data: Vec<u8> = (0..3 * MULTIPART_MIN_PART_SIZE as u64)
+        .map(|i| (i.wrapping_mul(2654435761) >> 7) as u8)
+        .collect();
+    upload_object_zstd_multipart(
+        &data,
+        &client,
+        bucket_name,
+        "multipart",
+        0,
+        0,
+        &Encryption::None,
+    )
+    .await
+    .unwrap();
+
+    let object = client
+        .get_object()
+        .bucket(bucket_name)
+        .key("multipart")
+        .send()
+        .await
+        .unwrap();
+    let compressed = object.body.collect().await.unwrap().into_bytes();
+    assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data);
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_download_object_zstd() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-download-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let data = b"archived measurements".repeat(100);
+    upload_object(
+        &data,
+        &client,
+        bucket_name,
+        "compressed",
+        &codec::Codec::default(),
+        &Encryption::None,
+        None,
+    )
+    .await
+    .unwrap();
+    let downloaded = download_object_zstd(&client, bucket_name, "compressed")
+        .await
+        .unwrap();
+    assert_eq!(downloaded, data);
+
+    // Objects stored without a zstd content encoding are passed through unchanged
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key("uncompressed")
+        .body(data.clone().into())
+        .send()
+        .await
+        .unwrap();
+    let downloaded = download_object_zstd(&client, bucket_name, "uncompressed")
+        .await
+        .unwrap();
+    assert_eq!(downloaded, data);
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_upload_object_codecs() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-codecs-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let data = b"archived measurements".repeat(100);
+    for codec in [
+        codec::Codec::Zstd { level: 3 },
+        codec::Codec::Lz4,
+        codec::Codec::Snappy,
+        codec::Codec::None,
+    ] {
+        let key = format!("{:?}", codec);
+        upload_object(
+            &data,
+            &client,
+            bucket_name,
+            &key,
+            &codec,
+            &Encryption::None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let object = client
+            .get_object()
+            .bucket(bucket_name)
+            .key(&key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(object.content_encoding(), codec.content_encoding());
+        let downloaded = download_object_zstd(&client, bucket_name, &key)
+            .await
+            .unwrap();
+        assert_eq!(downloaded, data);
+    }
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[test]
+fn test_codec() {
+    let data = b"archived measurements".repeat(100);
+    for codec in [
+        codec::Codec::default(),
+        codec::Codec::Zstd {
+            level: ZSTD_DEFAULT_LEVEL,
+        },
+        codec::Codec::Lz4,
+        codec::Codec::Snappy,
+        codec::Codec::None,
+    ] {
+        let compressed = codec.compress(&data).unwrap();
+        assert_eq!(
+            codec::decompress(codec.content_encoding(), &compressed).unwrap(),
+            data
+        );
+        if codec != codec::Codec::None {
+            assert!(compressed.len() < data.len());
+            let truncated = &compressed[..compressed.len() / 2];
+            assert_eq!(
+                codec::decompress(codec.content_encoding(), truncated)
+                    .unwrap_err()
+                    .kind(),
+                std::io::ErrorKind::UnexpectedEof
+            );
+        }
+    }
+
+    assert_eq!(codec::Codec::default(), codec::Codec::Zstd { level: 0 });
+    assert!(codec::Codec::Zstd { level: 100 }.compress(&data).is_err());
+    assert_eq!(
+        codec::decompress(Some("br"), &data).unwrap_err().kind(),
+        std::io::ErrorKind::Unsupported
+    );
+    assert_eq!(CodecKind::Lz4.codec(19), codec::Codec::Lz4);
+    assert_eq!(CodecKind::Zstd.codec(19), codec::Codec::Zstd { level: 19 });
+    assert_eq!(codec::Codec::Snappy.kind(), CodecKind::Snappy);
+    assert_eq!(codec::Codec::Zstd { level: 19 }.zstd_level(), Some(19));
+    assert_eq!(codec::Codec::Lz4.zstd_level(), None);
+}
+
+#[test]
+fn test_decompress_object() {
+    let data = b"archived measurements".repeat(100);
+    let object = |body: Vec<u8>| StoredObject::zstd(body, HashMap::new());
+
+    // Bodies with their decompressed size in the frame header, without it (as streamed multipart uploads are
+    // written), and with several frames all decompress
+    let bulk = codec::Codec::default().compress(&data).unwrap();
+    let mut streamed = zstd::stream::Encoder::new(Vec::new(), 0).unwrap();
+    streamed.include_contentsize(false).unwrap();
+    std::io::Write::write_all(&mut streamed, &data).unwrap();
+    let streamed = streamed.finish().unwrap();
+    assert_ne!(bulk, streamed);
+    let frames = [bulk.clone(), streamed.clone()].concat();
+    for body in [bulk.clone(), streamed] {
+        assert_eq!(
+            decompress_object("radar-2d/a", &object(body), &[]).unwrap(),
+            data
+        );
+    }
+    assert_eq!(
+        decompress_object("radar-2d/a", &object(frames), &[]).unwrap(),
+        [data.clone(), data].concat()
+    );
+
+    // Garbage, truncated, and uncompressed bodies are an error naming the object, not a panic
+    let garbage: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+    let truncated = bulk[..bulk.len() / 2].to_vec();
+    for body in [garbage, truncated, b"not zstd".to_vec(), Vec::new()] {
+        match decompress_object("radar-2d/corrupt", &object(body), &[]) {
+            Err(ArchiveError::DecompressError { key, .. }) => assert_eq!(key, "radar-2d/corrupt"),
+            other => panic!("expected a decompress error, got {:?}", other),
+        }
+    }
+    let unsupported = StoredObject {
+        content_encoding: Some("br".to_owned()),
+        ..object(bulk)
+    };
+    assert!(matches!(
+        decompress_object("radar-2d/br", &unsupported, &[]),
+        Err(ArchiveError::DecompressError { source, .. }) if source.kind() == std::io::ErrorKind::Unsupported
+    ));
+}
+
+/// Small, similar serialized measurements, the kind a zstd dictionary compresses well
+fn dictionary_samples(count: usize) -> Vec<Vec<u8>> {
+    (0..count)
+        .map(|i| {
+            format!(
+                r#"{{"source_id":"radar-{}","timestamp":"2022-10-26T07:{:02}:{:02}Z","range":{},"bearing":{}}}"#,
+                i % 4,
+                i / 60 % 60,
+                i % 60,
+                i * 7 % 5000,
+                i * 13 % 360
+            )
+            .into_bytes()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_zstd_dictionary() {
+    let samples = dictionary_samples(1000);
+    let dictionary =
+        codec::ZstdDictionary::new(codec::train_dictionary(&samples, 4096).unwrap()).unwrap();
+    assert_eq!(
+        dictionary,
+        codec::ZstdDictionary::new(dictionary.as_bytes().to_vec()).unwrap()
+    );
+    assert!(codec::ZstdDictionary::new(b"raw content".to_vec()).is_err());
+    assert!(codec::train_dictionary(&samples[..3], 4096).is_err());
+
+    // A measurement the dictionary wasn't trained on still compresses much better with it
+    let plain = codec::Codec::Zstd { level: 3 };
+    let dict = codec::Codec::ZstdDict {
+        level: 3,
+        dictionary: dictionary.clone(),
+    };
+    let data =
+        br#"{"source_id":"radar-2","timestamp":"2022-10-26T08:00:00Z","range":1234,"bearing":90}"#;
+    let plain_compressed = plain.compress(data).unwrap();
+    let dict_compressed = dict.compress(data).unwrap();
+    assert!(dict_compressed.len() < plain_compressed.len());
+    assert!(codec::decompress(Some("zstd"), &dict_compressed).is_err());
+    assert_eq!(
+        codec::decompress_with_dictionary(Some("zstd"), &dict_compressed, Some(&dictionary))
+            .unwrap(),
+        data
+    );
+
+    let store = test_file_store("zstd-dictionary");
+    put_with_retry(&store, "dict", data, &dict, 0, Duration::ZERO, None)
+        .await
+        .unwrap();
+    let object = store.get("dict").await.unwrap();
+    assert_eq!(object.content_encoding.as_deref(), Some("zstd"));
+    assert_eq!(
+        object.metadata[ZSTD_DICTIONARY_METADATA_KEY],
+        dictionary.id().to_string()
+    );
+    assert_eq!(
+        object.decompressed().unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+    let other = codec::ZstdDictionary::new(
+        codec::train_dictionary(&dictionary_samples(2000)[1000..], 2048).unwrap(),
+    )
+    .unwrap();
+    let decompressed = object
+        .decompressed_with(&[other, dictionary.clone()])
+        .unwrap();
+    assert_eq!(decompressed, data);
+    verify_checksum("dict", Some(&object.metadata), &decompressed).unwrap();
+
+    // --zstd-dictionary reads the dictionary when the CLI is parsed
+    let path = store.root().join("radar-2d.dict");
+    std::fs::write(&path, dictionary.as_bytes()).unwrap();
+    let path = path.to_str().unwrap();
+    let args = base_args();
+    let cli = Cli::try_parse_from(args.iter().chain(&["--zstd-dictionary", path])).unwrap();
+    assert_eq!(cli.codec(), dict);
+    assert_eq!(cli.zstd_dictionary(), Some(&dictionary));
+    let cli =
+        Cli::try_parse_from(
+            args.iter()
+                .chain(&["--zstd-dictionary", path, "--codec", "lz4"]),
+        )
+        .unwrap();
+    assert_eq!(cli.codec(), codec::Codec::Lz4);
+    assert!(Cli::try_parse_from(
+        args.iter()
+            .chain(&["--zstd-dictionary", "/nonexistent/radar-2d.dict"])
+    )
+    .is_err());
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[test]
+fn test_verify_checksum() {
+    // SHA-256 test vector
+    assert_eq!(
+        sha256_hex(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+
+    let mut data = b"archived measurements".repeat(100);
+    let metadata = HashMap::from([(CHECKSUM_METADATA_KEY.to_owned(), sha256_hex(&data))]);
+    verify_checksum("chunk", Some(&metadata), &data).unwrap();
+
+    data[42] ^= 0x01;
+    match verify_checksum("chunk", Some(&metadata), &data) {
+        Err(ArchiveError::ChecksumMismatch {
+            key,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(key, "chunk");
+            assert_eq!(&expected, &metadata[CHECKSUM_METADATA_KEY]);
+            assert_eq!(actual, sha256_hex(&data));
+        }
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+
+    assert!(matches!(
+        verify_checksum("chunk", None, &data),
+        Err(ArchiveError::MissingChecksum(key)) if key == "chunk"
+    ));
+    assert!(matches!(
+        verify_checksum("chunk", Some(&HashMap::new()), &data),
+        Err(ArchiveError::MissingChecksum(_))
+    ));
+}
+
+#[test]
+fn test_archive_error_context() {
+    let error = ArchiveError::KafkaMessageError {
+        topic: "radar-2d".to_owned(),
+        partition: 3,
+        offset: 1042,
+        source: KafkaError::Canceled,
+    };
+    assert!(error
+        .to_string()
+        .starts_with("Kafka error for the message on radar-2d partition 3 at offset 1042: "));
+
+    let error = ArchiveError::CommitError {
+        topic: "radar-2d".to_owned(),
+        offsets: vec![
+            OffsetRange {
+                partition: 0,
+                first_offset: 10,
+                last_offset: 19,
+            },
+            OffsetRange {
+                partition: 1,
+                first_offset: 5,
+                last_offset: 7,
+            },
+        ],
+        source: KafkaError::Canceled,
+    };
+    assert!(error
+        .to_string()
+        .starts_with("Failed to commit offsets 0:10-19, 1:5-7 on radar-2d: "));
+
+    let error = ArchiveError::StoreObjectError {
+        location: "opensensor-archive".to_owned(),
+        key: "radar-2d/2022-10-26T00:00:00+00:00".to_owned(),
+        source: ObjectStoreError::InvalidKey("radar-2d/2022-10-26T00:00:00+00:00".to_owned()),
+    };
+    assert_eq!(
+        error.to_string(),
+        "Object store error for radar-2d/2022-10-26T00:00:00+00:00 at opensensor-archive: \
+         Invalid object key radar-2d/2022-10-26T00:00:00+00:00"
+    );
+    assert!(std::error::Error::source(&error).is_some());
+
+    // Errors without context convert to the low-context variants
+    assert!(matches!(
+        ArchiveError::from(KafkaError::Canceled),
+        ArchiveError::KafkaError(KafkaError::Canceled)
+    ));
+    assert!(matches!(
+        ArchiveError::from(ObjectStoreError::NotFound("chunk".to_owned())),
+        ArchiveError::StoreError(ObjectStoreError::NotFound(key)) if key == "chunk"
+    ));
+}
+
+#[tokio::test]
+pub async fn test_verify_object() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-verify-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let data = b"archived measurements".repeat(100);
+    upload_object(
+        &data,
+        &client,
+        bucket_name,
+        "chunk",
+        &codec::Codec::default(),
+        &Encryption::None,
+        None,
+    )
+    .await
+    .unwrap();
+    verify_object(&client, bucket_name, "chunk", &[])
+        .await
+        .unwrap();
+    let downloaded = download_object_verified(&client, bucket_name, "chunk", &[])
+        .await
+        .unwrap();
+    assert_eq!(downloaded, data);
+
+    // Overwrite the object with a corrupted copy that still decompresses, keeping the original checksum
+    let mut corrupted = data.clone();
+    corrupted[42] ^= 0x01;
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key("chunk")
+        .body(zstd::bulk::compress(&corrupted, 0).unwrap().into())
+        .content_encoding("zstd")
+        .metadata(CHECKSUM_METADATA_KEY, sha256_hex(&data))
+        .send()
+        .await
+        .unwrap();
+    assert!(matches!(
+        verify_object(&client, bucket_name, "chunk", &[]).await,
+        Err(ArchiveError::ChecksumMismatch { .. })
+    ));
+
+    // Objects uploaded without a checksum can't be verified
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key("unchecked")
+        .body(data.clone().into())
+        .send()
+        .await
+        .unwrap();
+    assert!(matches!(
+        verify_object(&client, bucket_name, "unchecked", &[]).await,
+        Err(ArchiveError::MissingChecksum(_))
+    ));
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_head_object_metadata() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-head-metadata-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let data = b"archived measurements".repeat(100);
+    let metadata = HashMap::from([(RECORD_COUNT_METADATA_KEY.to_owned(), "100".to_owned())]);
+    upload_object(
+        &data,
+        &client,
+        bucket_name,
+        "chunk",
+        &codec::Codec::default(),
+        &Encryption::None,
+        Some(metadata),
+    )
+    .await
+    .unwrap();
+
+    let metadata = head_object_metadata(&client, bucket_name, "chunk")
+        .await
+        .unwrap();
+    assert_eq!(metadata[RECORD_COUNT_METADATA_KEY], "100");
+    assert_eq!(
+        metadata[UNCOMPRESSED_LENGTH_METADATA_KEY],
+        data.len().to_string()
+    );
+    assert_eq!(metadata[CHECKSUM_METADATA_KEY], sha256_hex(&data));
+
+    assert!(head_object_metadata(&client, bucket_name, "missing")
+        .await
+        .is_err());
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+/// Filesystem object store in a fresh directory under the system temp dir
+fn test_file_store(name: &str) -> FileSystemObjectStore {
+    let root = std::env::temp_dir().join(format!("opensensor-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    FileSystemObjectStore::new(root)
+}
+
+#[tokio::test]
+async fn test_file_system_object_store() {
+    let store = test_file_store("store");
+    let object = StoredObject::zstd(
+        vec![1, 2, 3],
+        HashMap::from([("sha256".to_owned(), "abc".to_owned())]),
+    );
+
+    store.put("radar-2d/b", object.clone()).await.unwrap();
+    store
+        .put("radar-2d/a", StoredObject::default())
+        .await
+        .unwrap();
+    store.put("lidar/a", StoredObject::default()).await.unwrap();
+    assert_eq!(store.get("radar-2d/b").await.unwrap(), object);
+    assert_eq!(
+        store.list("radar-2d/").await.unwrap(),
+        vec!["radar-2d/a", "radar-2d/b"]
+    );
+    assert_eq!(store.list("").await.unwrap().len(), 3);
+
+    store.delete("radar-2d/b").await.unwrap();
+    // Deleting a missing object succeeds
+    store.delete("radar-2d/b").await.unwrap();
+    assert!(matches!(
+        store.get("radar-2d/b").await,
+        Err(ObjectStoreError::NotFound(_))
+    ));
+    assert_eq!(store.list("radar-2d/").await.unwrap(), vec!["radar-2d/a"]);
+
+    for key in ["", "../escape", "/absolute", "radar-2d/./a", "radar-2d/"] {
+        assert!(matches!(
+            store.put(key, StoredObject::default()).await,
+            Err(ObjectStoreError::InvalidKey(_))
+        ));
+    }
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[tokio::test]
+async fn test_file_system_object_store_put_if_match() {
+    let store = test_file_store("put-if-match");
+    let object = |body: &[u8]| StoredObject {
+        body: body.to_vec(),
+        ..StoredObject::default()
+    };
+
+    // No tag only matches a missing object
+    assert!(store.put_if_match("a", object(b"1"), None).await.unwrap());
+    assert!(!store.put_if_match("a", object(b"2"), None).await.unwrap());
+
+    let (_, tag) = store.get_tagged("a").await.unwrap();
+    assert!(store
+        .put_if_match("a", object(b"2"), tag.as_deref())
+        .await
+        .unwrap());
+    // Another writer replaced the object since the tag was read
+    assert!(!store
+        .put_if_match("a", object(b"3"), tag.as_deref())
+        .await
+        .unwrap());
+    assert_eq!(store.get("a").await.unwrap().body, b"2");
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[tokio::test]
+async fn test_put_zstd_with_retry() {
+    let store = test_file_store("put-zstd");
+    let data = b"archived measurements".repeat(100);
+
+    let compressed_bytes = put_zstd_with_retry(
+        &store,
+        "chunk",
+        &data,
+        0,
+        0,
+        Duration::ZERO,
+        Some(HashMap::from([(
+            "consumer-group".to_owned(),
+            "radar-2d-archiver".to_owned(),
+        )])),
+    )
+    .await
+    .unwrap();
+
+    let object = store.get("chunk").await.unwrap();
+    assert_eq!(object.body.len(), compressed_bytes);
+    assert_eq!(object.content_encoding.as_deref(), Some("zstd"));
+    assert_eq!(object.metadata["consumer-group"], "radar-2d-archiver");
+    assert_eq!(
+        object.metadata[UNCOMPRESSED_LENGTH_METADATA_KEY],
+        data.len().to_string()
+    );
+    let decompressed = object.decompressed().unwrap();
+    assert_eq!(decompressed, data);
+    verify_checksum("chunk", Some(&object.metadata), &decompressed).unwrap();
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[tokio::test]
+async fn test_put_with_retry_codecs() {
+    let store = test_file_store("put-codecs");
+    let data = b"archived measurements".repeat(100);
+
+    for (key, codec) in [
+        ("lz4", codec::Codec::Lz4),
+        ("snappy", codec::Codec::Snappy),
+        ("none", codec::Codec::None),
+    ] {
+        let compressed_bytes = put_with_retry(&store, key, &data, &codec, 0, Duration::ZERO, None)
+            .await
+            .unwrap();
+
+        let object = store.get(key).await.unwrap();
+        assert_eq!(object.body.len(), compressed_bytes);
+        assert_eq!(object.content_encoding.as_deref(), codec.content_encoding());
+        let decompressed = object.decompressed().unwrap();
+        assert_eq!(decompressed, data);
+        verify_checksum(key, Some(&object.metadata), &decompressed).unwrap();
+    }
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[tokio::test]
+async fn test_compact_archives() {
+    let store = test_file_store("compact");
+    let mut keys = Vec::new();
+    let mut measurements = Vec::new();
+    for (i, ts) in seconds(&[0, 10, 20]).into_iter().enumerate() {
+        let chunk: Vec<TestMeasurement> = (0..2)
+            .map(|s| TestMeasurement::new("source", ts + chrono::Duration::seconds(s)))
+            .collect();
+        let key = archive_key_with_offsets(
+            "radar-2d",
+            KeyLayout::Flat,
+            ts,
+            &[OffsetRange {
+                partition: 0,
+                first_offset: 2 * i as i64,
+                last_offset: 2 * i as i64 + 1,
+            }],
+        );
+        let fbb = serialize_chunk(chunk.clone()).unwrap();
+        put_with_retry(
+            &store,
+            &key,
+            fbb.finished_data(),
+            &codec::Codec::default(),
+            0,
+            Duration::ZERO,
+            None,
+        )
+        .await
+        .unwrap();
+        keys.push(key);
+        measurements.extend(chunk);
+    }
+
+    let report = compact_archives_in_store::<TestMeasurement, _>(
+        &store,
+        "radar-2d",
+        1024 * 1024,
+        &codec::Codec::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        report,
+        CompactionReport {
+            merged: 1,
+            removed: 3
+        }
+    );
+
+    let merged_key = compacted_key(&keys);
+    assert!(merged_key.starts_with("radar-2d/1970-01-01T00:00:00+00:00_3c-"));
+    assert_eq!(key_timestamp(&merged_key), key_timestamp(&keys[0]));
+    let remaining: Vec<String> = store
+        .list("radar-2d/")
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|key| !key.ends_with(".manifest.json"))
+        .collect();
+    assert_eq!(remaining, vec![merged_key.clone()]);
+    let object = store.get(&merged_key).await.unwrap();
+    assert_eq!(object.metadata[COMPACTED_OBJECTS_METADATA_KEY], "3");
+    assert_eq!(object.metadata[RECORD_COUNT_METADATA_KEY], "6");
+    let data = decompress_object(&merged_key, &object, &[]).unwrap();
+    verify_checksum(&merged_key, Some(&object.metadata), &data).unwrap();
+    let read: Vec<TestMeasurement> = deserialize_chunk(&data).unwrap();
+    assert_eq!(read, measurements);
+    assert_eq!(
+        store.size(&merged_key).await.unwrap(),
+        object.body.len() as u64
+    );
+    assert!(matches!(
+        store.size(&keys[0]).await,
+        Err(ObjectStoreError::NotFound(_))
+    ));
+
+    // Nothing left to merge, so no archive object is downloaded
+    let counting = GetCountingStore {
+        inner: store.clone(),
+        gets: Default::default(),
+    };
+    let report = compact_archives_in_store::<TestMeasurement, _>(
+        &counting,
+        "radar-2d",
+        1024 * 1024,
+        &codec::Codec::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report, CompactionReport::default());
+    let gets = counting.gets.into_inner().unwrap();
+    assert!(
+        gets.iter().all(|key| key_timestamp(key).is_none()),
+        "{:?}",
+        gets
+    );
+
+    // A run interrupted after writing the merged object left a source behind
+    store
+        .put(&keys[1], StoredObject::zstd(Vec::new(), HashMap::new()))
+        .await
+        .unwrap();
+    store
+        .put(
+            &compacted_from_key(&merged_key),
+            StoredObject {
+                body: keys.join("\n").into_bytes(),
+                ..StoredObject::default()
+            },
+        )
+        .await
+        .unwrap();
+    let report = compact_archives_in_store::<TestMeasurement, _>(
+        &store,
+        "radar-2d",
+        1024 * 1024,
+        &codec::Codec::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.merged, 0);
+    assert!(matches!(
+        store.get(&keys[1]).await,
+        Err(ObjectStoreError::NotFound(_))
+    ));
+    assert!(matches!(
+        store.get(&compacted_from_key(&merged_key)).await,
+        Err(ObjectStoreError::NotFound(_))
+    ));
+    assert!(store.get(&merged_key).await.is_ok());
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+/// A group with an object that can't be read is left as it is, without stopping the other groups' compaction
+#[tokio::test]
+async fn test_compact_archives_skips_unreadable() {
+    let store = test_file_store("compact-unreadable");
+    let mut keys = HashMap::new();
+    for prefix in ["radar-2d/a", "radar-2d/b"] {
+        for (i, ts) in seconds(&[0, 10]).into_iter().enumerate() {
+            let key = archive_key_with_offsets(
+                prefix,
+                KeyLayout::Flat,
+                ts,
+                &[OffsetRange {
+                    partition: 0,
+                    first_offset: i as i64,
+                    last_offset: i as i64,
+                }],
+            );
+            let data = if prefix == "radar-2d/a" && i == 1 {
+                b"not an archive chunk".to_vec()
+            } else {
+                let chunk = vec![TestMeasurement::new("source", ts)];
+                serialize_chunk(chunk).unwrap().finished_data().to_vec()
+            };
+            put_with_retry(
+                &store,
+                &key,
+                &data,
+                &codec::Codec::default(),
+                0,
+                Duration::ZERO,
+                None,
+            )
+            .await
+            .unwrap();
+            keys.entry(prefix).or_insert_with(Vec::new).push(key);
+        }
+    }
+
+    let report = compact_archives_in_store::<TestMeasurement, _>(
+        &store,
+        "radar-2d",
+        1024 * 1024,
+        &codec::Codec::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        report,
+        CompactionReport {
+            merged: 1,
+            removed: 2
+        }
+    );
+    for key in &keys["radar-2d/a"] {
+        assert!(store.get(key).await.is_ok());
+    }
+    assert!(store.get(&compacted_key(&keys["radar-2d/b"])).await.is_ok());
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+/// Compaction lists the merged object in the sensor's index in place of the objects it was merged from
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn test_compact_archives_reindexes() {
+    use crate::archiver::index::{
+        append_to_index_in_store, index_key, read_index_in_store, IndexEntry,
+    };
+
+    let store = test_file_store("compact-index");
+    let mut keys = Vec::new();
+    for ts in seconds(&[0, 10, 20]) {
+        let key = archive_key("radar-2d", KeyLayout::Flat, ts);
+        let chunk = vec![TestMeasurement::new("source", ts)];
+        let fbb = serialize_chunk(chunk).unwrap();
+        let compressed_bytes = put_with_retry(
+            &store,
+            &key,
+            fbb.finished_data(),
+            &codec::Codec::default(),
+            0,
+            Duration::ZERO,
+            None,
+        )
+        .await
+        .unwrap();
+        let entry = IndexEntry {
+            key: key.clone(),
+            first_timestamp: ts,
+            last_timestamp: ts,
+            record_count: 1,
+            compressed_bytes,
+        };
+        append_to_index_in_store(&store, "radar-2d", entry)
+            .await
+            .unwrap();
+        keys.push(key);
+    }
+
+    // Objects uploaded yesterday are listed in yesterday's index
+    let today = chrono::Utc::now().date_naive();
+    let yesterday = index_key("radar-2d", today.pred_opt().unwrap());
+    let today_index = store.get(&index_key("radar-2d", today)).await.unwrap();
+    store.put(&yesterday, today_index).await.unwrap();
+
+    let report = compact_archives_in_store::<TestMeasurement, _>(
+        &store,
+        "radar-2d",
+        1024 * 1024,
+        &codec::Codec::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.merged, 1);
+
+    // The merged object is listed once, in the first index that listed its sources
+    let merged_key = compacted_key(&keys);
+    let index = read_index_in_store(&store, "radar-2d", today.pred_opt().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(index.entries.len(), 1);
+    assert_eq!(index.entries[0].key, merged_key);
+    assert_eq!(index.entries[0].record_count, 3);
+    assert_eq!(index.entries[0].last_timestamp, seconds(&[20])[0]);
+    // Today's index only listed merged sources, so it's deleted
+    assert!(matches!(
+        store.get(&index_key("radar-2d", today)).await,
+        Err(ObjectStoreError::NotFound(_))
+    ));
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[tokio::test]
+async fn test_archive_sink_with_store() {
+    let store = test_file_store("sink");
+    let root = store.root().to_owned();
+    let mut sink = ArchiveSink::<TestMeasurement, _>::with_store(create_test_cli(), store);
+    let measurements: Vec<TestMeasurement> = seconds(&[2, 0, 1])
+        .into_iter()
+        .map(|t| TestMeasurement::new("source", t))
+        .collect();
+
+    sink.sink_batch(measurements).await.unwrap();
+
+    let store = FileSystemObjectStore::new(&root);
+    let keys = store.list("radar-2d/").await.unwrap();
+    let chunk_key = keys
+        .iter()
+        .find(|key| !key.ends_with(".manifest.json"))
+        .unwrap();
+    let data = store.get(chunk_key).await.unwrap().decompressed().unwrap();
+    let read = read_chunk::<TestMeasurement>(&data, None).unwrap();
+    assert_eq!(read.measurements.len(), 3);
+    let metadata = store.get(chunk_key).await.unwrap().metadata;
+    assert_eq!(metadata[RECORD_COUNT_METADATA_KEY], "3");
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_poll_next() {
+    let timeout = Some(Duration::from_millis(10));
+
+    let mut idle = futures_util::stream::pending::<u32>();
+    assert_eq!(poll_next(&mut idle, timeout).await, Polled::Idle);
+
+    let mut stream = futures_util::stream::iter(vec![1]);
+    assert_eq!(poll_next(&mut stream, timeout).await, Polled::Message(1));
+    assert_eq!(poll_next(&mut stream, timeout).await, Polled::Ended);
+    assert_eq!(poll_next(&mut stream, None).await, Polled::Ended);
+}
+
+#[tokio::test]
+pub async fn test_upload_sse() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-sse-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let data = b"archived measurements".repeat(100);
+    upload_object(
+        &data,
+        &client,
+        bucket_name,
+        "encrypted",
+        &codec::Codec::default(),
+        &Encryption::Sse,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let head = client
+        .head_object()
+        .bucket(bucket_name)
+        .key("encrypted")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        head.server_side_encryption(),
+        Encryption::Sse.server_side_encryption().as_ref()
+    );
+    let downloaded = download_object_zstd(&client, bucket_name, "encrypted")
+        .await
+        .unwrap();
+    assert_eq!(downloaded, data);
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[test]
+fn test_cli_encryption() {
+    let base = base_args();
+
+    let cli = Cli::try_parse_from(base).unwrap();
+    assert_eq!(cli.encryption(), Encryption::None);
+
+    let cli = Cli::try_parse_from(base.iter().chain(&["--sse"])).unwrap();
+    assert_eq!(cli.encryption(), Encryption::Sse);
+
+    let cli = Cli::try_parse_from(base.iter().chain(&["--sse-kms-key-id", "archive-key"])).unwrap();
+    assert_eq!(
+        cli.encryption(),
+        Encryption::SseKms {
+            key_id: "archive-key".to_owned()
+        }
+    );
+}
+
+#[test]
+fn test_cli_max_chunk_age() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.max_chunk_age(), None);
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--max-chunk-age", "5m"])).unwrap();
+    assert_eq!(cli.max_chunk_age(), Some(Duration::from_secs(300)));
+
+    assert!(Cli::try_parse_from(args.iter().chain(&["--max-chunk-age", "soon"])).is_err());
+}
+
+#[test]
+fn test_consumer_fetch_settings() {
+    let args = base_args();
+
+    // Unset fetch sizes leave librdkafka's defaults alone
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.kafka().fetch_max_bytes(), None);
+    assert!(cli.kafka().consumer_settings().is_empty());
+
+    let cli = Cli::try_parse_from(args.iter().chain(&[
+        "--fetch-max-bytes",
+        "64MiB",
+        "--max-partition-fetch-bytes",
+        "4MB",
+        "--poll-timeout",
+        "500ms",
+    ]))
+    .unwrap();
+    assert_eq!(cli.kafka().fetch_max_bytes(), Some(64 << 20));
+    assert_eq!(cli.kafka().max_partition_fetch_bytes(), Some(4_000_000));
+    assert_eq!(cli.poll_timeout(), Some(Duration::from_millis(500)));
+    assert_eq!(
+        cli.kafka().consumer_settings(),
+        vec![
+            ("fetch.max.bytes", "67108864".to_owned()),
+            ("max.partition.fetch.bytes", "4000000".to_owned()),
+        ]
+    );
+
+    // RedpandaBuilder doesn't expose its settings, but its Debug output shows the client config
+    let mut builder = redpanda::RedpandaBuilder::default();
+    cli.kafka().apply(&mut builder);
+    let builder = format!("{:?}", builder);
+    assert!(builder.contains(r#""fetch.max.bytes": "67108864""#));
+    assert!(builder.contains(r#""max.partition.fetch.bytes": "4000000""#));
+
+    // Sizes librdkafka would reject at build_consumer
+    for (arg, value) in [
+        ("--fetch-max-bytes", "0"),
+        ("--fetch-max-bytes", "3GiB"),
+        ("--max-partition-fetch-bytes", "2GB"),
+        ("--max-partition-fetch-bytes", "lots"),
+    ] {
+        assert!(Cli::try_parse_from(args.iter().chain(&[arg, value])).is_err());
+    }
+}
+
+/// A dry run consumes and chunks the topic, but uploads (and indexes) nothing and leaves the group's committed offsets
+/// alone, even starting from `earliest`. Needs the Redpanda cluster from docker-compose.yaml
+#[tokio::test]
+async fn test_dry_run() {
+    use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+    use rdkafka::client::DefaultClientContext;
+    use rdkafka::consumer::{CommitMode, Consumer};
+    use rdkafka::{ClientConfig, Offset, TopicPartitionList};
+    use redpanda::producer::RedpandaRecord;
+
+    let kafka_addresses = "127.0.0.1:9010";
+    let name = format!(
+        "dry-run-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_millis()
+    );
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set("bootstrap.servers", kafka_addresses)
+        .create()
+        .unwrap();
+    let created = admin
+        .create_topics(
+            [&NewTopic::new(&name, 2, TopicReplication::Fixed(1))],
+            &AdminOptions::new(),
+        )
+        .await
+        .unwrap();
+    assert!(created.iter().all(|r| r.is_ok()));
+
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    let producer = builder.build_producer().unwrap();
+    let now = chrono::Utc::now();
+    for i in 0..25 {
+        let measurement = TestMeasurement::new(&i.to_string(), now);
+        let record = RedpandaRecord::new(&name, None, measurement.to_bytes(), None);
+        let delivery = producer.send_result(&record).map_err(|(e, _)| e).unwrap();
+        assert!(matches!(delivery.await, Ok(Ok(_))));
+    }
+
+    // Archive the new topic as its own sensor, in chunks small enough for 25 records to fill
+    let mut args: Vec<&str> = base_args().to_vec();
+    args[12] = &name;
+    args[14] = "10";
+    let cli = Cli::try_parse_from(args.iter().chain(&[
+        "--topic",
+        name.as_str(),
+        "--start-from",
+        "earliest",
+        "--poll-timeout",
+        "5s",
+        "--dry-run",
+    ]))
+    .unwrap();
+    assert!(cli.dry_run());
+
+    // Partition 0 was archived up to offset 1 before, partition 1 never was
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    builder.set_group_id(&cli.group_id());
+    let consumer = builder.build_consumer().unwrap();
+    let mut committed = TopicPartitionList::new();
+    committed
+        .add_partition_offset(&name, 0, Offset::Offset(1))
+        .unwrap();
+    consumer
+        .consumer
+        .commit(&committed, CommitMode::Sync)
+        .unwrap();
+
+    let store = test_file_store("dry-run");
+    run_archiver_with_store::<TestMeasurement, _>(cli, store.clone())
+        .await
+        .unwrap();
+    assert!(store.list("").await.unwrap().is_empty());
+
+    let mut partitions = TopicPartitionList::new();
+    partitions.add_partition(&name, 0);
+    partitions.add_partition(&name, 1);
+    let committed = consumer
+        .consumer
+        .committed_offsets(partitions, Duration::from_secs(10))
+        .unwrap();
+    let offsets: Vec<Offset> = committed.elements().iter().map(|e| e.offset()).collect();
+    assert_eq!(offsets, vec![Offset::Offset(1), Offset::Invalid]);
+
+    admin
+        .delete_topics(&[&name], &AdminOptions::new())
+        .await
+        .unwrap();
+}
+
+/// `seek_start` commits the offsets `--start-from` resolves to for every partition of the topic, and leaves them
+/// alone for `committed`. Needs the Redpanda cluster from docker-compose.yaml
+#[tokio::test]
+async fn test_seek_start() {
+    use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+    use rdkafka::client::DefaultClientContext;
+    use rdkafka::consumer::Consumer;
+    use rdkafka::{ClientConfig, Offset, TopicPartitionList};
+    use redpanda::producer::RedpandaRecord;
+
+    let kafka_addresses = "127.0.0.1:9010";
+    let name = format!(
+        "seek-start-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_millis()
+    );
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set("bootstrap.servers", kafka_addresses)
+        .create()
+        .unwrap();
+    let created = admin
+        .create_topics(
+            [&NewTopic::new(&name, 2, TopicReplication::Fixed(1))],
+            &AdminOptions::new(),
+        )
+        .await
+        .unwrap();
+    assert!(created.iter().all(|r| r.is_ok()));
+
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    let producer = builder.build_producer().unwrap();
+    let now = chrono::Utc::now();
+    for i in 0..25 {
+        let measurement = TestMeasurement::new(&i.to_string(), now);
+        let record = RedpandaRecord::new(&name, None, measurement.to_bytes(), None);
+        let delivery = producer.send_result(&record).map_err(|(e, _)| e).unwrap();
+        assert!(matches!(delivery.await, Ok(Ok(_))));
+    }
+
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    builder.set_group_id(&format!("{}-group", name));
+    let consumer = builder.build_consumer().unwrap();
+    let watermarks: Vec<(i64, i64)> = (0..2)
+        .map(|partition| {
+            consumer
+                .consumer
+                .fetch_watermarks(&name, partition, Duration::from_secs(10))
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(
+        watermarks.iter().map(|(low, high)| high - low).sum::<i64>(),
+        25
+    );
+    let committed = || -> Vec<Offset> {
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition(&name, 0);
+        partitions.add_partition(&name, 1);
+        let committed = consumer
+            .consumer
+            .committed_offsets(partitions, Duration::from_secs(10))
+            .unwrap();
+        committed.elements().iter().map(|e| e.offset()).collect()
+    };
+
+    // Nothing committed yet, and `committed` doesn't commit anything either
+    seek_start(&consumer, &name, StartFrom::Committed).unwrap();
+    assert_eq!(committed(), vec![Offset::Invalid, Offset::Invalid]);
+
+    seek_start(&consumer, &name, StartFrom::Latest).unwrap();
+    let highs: Vec<Offset> = watermarks
+        .iter()
+        .map(|&(_, high)| Offset::Offset(high))
+        .collect();
+    assert_eq!(committed(), highs);
+
+    seek_start(&consumer, &name, StartFrom::Earliest).unwrap();
+    let lows: Vec<Offset> = watermarks
+        .iter()
+        .map(|&(low, _)| Offset::Offset(low))
+        .collect();
+    assert_eq!(committed(), lows);
+
+    // Offsets past the end of a partition are clamped to its high watermark
+    seek_start(&consumer, &name, StartFrom::Offset(3)).unwrap();
+    let clamped: Vec<Offset> = watermarks
+        .iter()
+        .map(|&(low, high)| Offset::Offset(3.max(low).min(high)))
+        .collect();
+    assert_eq!(committed(), clamped);
+
+    // No measurement is that recent, so every partition starts at its end
+    seek_start(
+        &consumer,
+        &name,
+        StartFrom::Time(now + chrono::Duration::hours(1)),
+    )
+    .unwrap();
+    assert_eq!(committed(), highs);
+
+    // Resuming from the committed offsets keeps the last seek
+    seek_start(&consumer, &name, StartFrom::Committed).unwrap();
+    assert_eq!(committed(), highs);
+
+    admin
+        .delete_topics(&[&name], &AdminOptions::new())
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_start_from() {
+    use chrono::TimeZone;
+    use rdkafka::Offset;
+
+    assert_eq!("committed".parse(), Ok(StartFrom::Committed));
+    assert_eq!("earliest".parse(), Ok(StartFrom::Earliest));
+    assert_eq!("latest".parse(), Ok(StartFrom::Latest));
+    assert_eq!("offset:42".parse(), Ok(StartFrom::Offset(42)));
+    let time = chrono::Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+    assert_eq!(
+        "time:2022-10-01T14:00:00+02:00".parse(),
+        Ok(StartFrom::Time(time))
+    );
+    for invalid in [
+        "beginning",
+        "offset:-1",
+        "offset:next",
+        "time:yesterday",
+        "partition:1",
+    ] {
+        assert!(invalid.parse::<StartFrom>().is_err(), "{}", invalid);
+    }
+
+    // Times resolve to the offset offsets_for_times found, or the end of a partition with no message that recent
+    let watermarks = (100, 200);
+    let start = StartFrom::Time(time);
+    assert_eq!(
+        start.start_offset(watermarks, Some(Offset::Offset(150))),
+        Some(150)
+    );
+    assert_eq!(start.start_offset(watermarks, Some(Offset::End)), Some(200));
+    assert_eq!(start.start_offset(watermarks, None), Some(200));
+    // Offsets deleted by retention start at the earliest retained
+    assert_eq!(
+        start.start_offset(watermarks, Some(Offset::Offset(50))),
+        Some(100)
+    );
+    assert_eq!(
+        StartFrom::Offset(250).start_offset(watermarks, None),
+        Some(200)
+    );
+    assert_eq!(
+        StartFrom::Earliest.start_offset(watermarks, None),
+        Some(100)
+    );
+    assert_eq!(StartFrom::Latest.start_offset(watermarks, None), Some(200));
+    assert_eq!(StartFrom::Committed.start_offset(watermarks, None), None);
+}
+
+#[test]
+fn test_cli_start_from() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.start_from(), StartFrom::Committed);
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--start-from", "offset:10"])).unwrap();
+    assert_eq!(cli.start_from(), StartFrom::Offset(10));
+
+    assert!(Cli::try_parse_from(args.iter().chain(&["--start-from", "tomorrow"])).is_err());
+}
+
+#[test]
+fn test_cli_reservoir() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.reservoir(), None);
+    assert_eq!(cli.reservoir_window(), Duration::from_secs(3600));
+    assert_eq!(cli.preview_prefix(), "radar-2d-preview");
+
+    let cli = Cli::try_parse_from(args.iter().chain(&[
+        "--reservoir",
+        "100",
+        "--reservoir-window",
+        "10m",
+    ]))
+    .unwrap();
+    assert_eq!(cli.reservoir(), Some(100));
+    assert_eq!(cli.reservoir_window(), Duration::from_secs(600));
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--reservoir", "0"])).unwrap();
+    assert_eq!(cli.reservoir(), None);
+}
+
+#[test]
+fn test_cli_sort_chunk_by() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.sort_chunk_by(), None);
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--sort-chunk-by", "sort-key"])).unwrap();
+    assert_eq!(cli.sort_chunk_by(), Some(ChunkSort::SortKey));
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--sort-chunk-by", "timestamp"])).unwrap();
+    assert_eq!(cli.sort_chunk_by(), Some(ChunkSort::Timestamp));
+
+    assert!(Cli::try_parse_from(args.iter().chain(&["--sort-chunk-by", "size"])).is_err());
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_cli_metrics_address() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.metrics_address(), None);
+
+    let cli =
+        Cli::try_parse_from(args.iter().chain(&["--metrics-address", "0.0.0.0:9898"])).unwrap();
+    assert_eq!(cli.metrics_address(), Some("0.0.0.0:9898".parse().unwrap()));
+
+    assert!(Cli::try_parse_from(args.iter().chain(&["--metrics-address", "localhost"])).is_err());
+}
+
+#[test]
+fn test_dead_letter_record() {
+    use redpanda::message::{Headers, OwnedMessage, Timestamp};
+    use redpanda::producer::FutureRecord;
+
+    let cli = create_test_cli();
+    assert_eq!(cli.dead_letter_topic(), "radar-2d-dead-letter");
+    assert_eq!(cli.max_dead_letters(), None);
+    let cli = Cli::try_parse_from(base_args().iter().chain(&[
+        "--dead-letter-topic",
+        "poison",
+        "--max-dead-letters",
+        "10",
+    ]))
+    .unwrap();
+    assert_eq!(cli.dead_letter_topic(), "poison");
+    assert_eq!(cli.max_dead_letters(), Some(10));
+
+    let message = OwnedMessage::new(
+        Some(vec![0xde, 0xad]),
+        Some(b"radar-1".to_vec()),
+        "radar_2d".to_owned(),
+        Timestamp::NotAvailable,
+        3,
+        42,
+        None,
+    );
+    let record = dead_letter_record("poison", &message, "invalid flatbuffer");
+    let record = FutureRecord::from(&record);
+
+    // The raw message is kept as is, with where it came from and why it failed in the headers
+    assert_eq!(record.topic, "poison");
+    assert_eq!(record.key, Some(&b"radar-1".to_vec()));
+    assert_eq!(record.payload, Some(&vec![0xde, 0xad]));
+    let headers = record.headers.unwrap();
+    let expected = [
+        (DEAD_LETTER_ERROR_HEADER, "invalid flatbuffer"),
+        (DEAD_LETTER_TOPIC_HEADER, "radar_2d"),
+        (DEAD_LETTER_PARTITION_HEADER, "3"),
+        (DEAD_LETTER_OFFSET_HEADER, "42"),
+    ];
+    assert_eq!(headers.count(), expected.len());
+    for (i, (key, value)) in expected.into_iter().enumerate() {
+        let header = headers.get(i);
+        assert_eq!(header.key, key);
+        assert_eq!(header.value, Some(value.as_bytes()));
+    }
+}
+
+#[test]
+fn test_reservoir() {
+    use rand::SeedableRng;
+
+    let mut reservoir = Reservoir::with_rng(10, rand::rngs::StdRng::seed_from_u64(7));
+    for i in 0..5 {
+        reservoir.offer(|| i);
+    }
+    assert_eq!(reservoir.take(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(reservoir.seen(), 0);
+
+    let mut copied = 0;
+    for i in 0..10_000 {
+        reservoir.offer(|| {
+            copied += 1;
+            i
+        });
+    }
+    assert_eq!(reservoir.seen(), 10_000);
+    assert_eq!(reservoir.len(), 10);
+    // Items are only copied when they're kept, which is ~ capacity * ln(n / capacity) times
+    assert!(copied < 200);
+
+    let mut empty: Reservoir<u32> = Reservoir::new(0);
+    empty.offer(|| 1);
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn test_reservoir_uniform() {
+    use rand::SeedableRng;
+
+    // Every item should be sampled about capacity / n of the time, no matter where it is in the stream
+    let mut counts = [0u32; 100];
+    let mut reservoir = Reservoir::with_rng(10, rand::rngs::StdRng::seed_from_u64(42));
+    for _ in 0..2000 {
+        for i in 0..counts.len() {
+            reservoir.offer(|| i);
+        }
+        for i in reservoir.take() {
+            counts[i] += 1;
+        }
+    }
+
+    // Expected 200 each
+    assert!(
+        counts.iter().all(|&c| (140..=260).contains(&c)),
+        "{:?}",
+        counts
+    );
+    assert!(counts[..10].iter().sum::<u32>() < 2300);
+    assert!(counts[90..].iter().sum::<u32>() > 1700);
+}
+
+#[tokio::test]
+async fn test_archive_preview_keys() {
+    let store = test_file_store("preview");
+    let cli = Cli::try_parse_from(base_args().iter().chain(&["--reservoir", "2"])).unwrap();
+    let sample = |first_offset: i64| -> Vec<Consumed<Vec<u8>>> {
+        seconds(&[0, 1])
+            .into_iter()
+            .zip(first_offset..)
+            .map(|(t, offset)| Consumed {
+                measurement: TestMeasurement::new("source", t).to_bytes(),
+                partition: 0,
+                offset,
+            })
+            .collect()
+    };
+
+    // Samples with the same timestamps from different windows (or runs) don't overwrite each other
+    archive_preview::<TestMeasurement, _>(&cli, &store, sample(0), 10)
+        .await
+        .unwrap();
+    archive_preview::<TestMeasurement, _>(&cli, &store, sample(100), 10)
+        .await
+        .unwrap();
+    for key in [
+        "radar-2d-preview/1970-01-01T00:00:00+00:00_p0-0-1",
+        "radar-2d-preview/1970-01-01T00:00:00+00:00_p0-100-101",
+    ] {
+        let object = store.get(key).await.unwrap();
+        let data = decompress_object(key, &object, &[]).unwrap();
+        assert_eq!(
+            read_chunk::<TestMeasurement>(&data, None)
+                .unwrap()
+                .measurements
+                .len(),
+            2
+        );
+    }
+}
+
+#[test]
+fn test_zstd_compression_level() {
+    assert_eq!(zstd_compression_level(0).unwrap(), 0);
+    assert_eq!(zstd_compression_level(19).unwrap(), 19);
+    assert_eq!(
+        zstd_compression_level(ZSTD_DEFAULT_LEVEL).unwrap(),
+        zstd::DEFAULT_COMPRESSION_LEVEL
+    );
+    assert!(zstd_compression_level(*zstd::compression_level_range().end() + 1).is_err());
+}
+
+#[test]
+fn test_retry_delay() {
+    let base = Duration::from_millis(100);
+
+    assert_eq!(retry_delay(base, 0), base);
+    assert_eq!(retry_delay(base, 3), Duration::from_millis(800));
+    assert_eq!(retry_delay(base, 20), MAX_RETRY_DELAY);
+    assert_eq!(retry_delay(base, u32::MAX), MAX_RETRY_DELAY);
+}
+
+#[test]
+fn test_serialize_chunk() {
+    let now = chrono::Utc::now();
+    let measurements: Vec<TestMeasurement> = (0..3)
+        .map(|i| TestMeasurement::new("source", now + chrono::Duration::seconds(i)))
+        .collect();
+
+    let fbb = serialize_chunk(measurements.clone()).unwrap();
+    let chunk = root_as_archive_chunk(fbb.finished_data()).unwrap();
+    assert_eq!(chunk.measurements().len(), measurements.len());
+
+    // Measurements come back in the order they were consumed
+    let archived: Vec<TestMeasurement> = chunk
+        .measurements()
+        .iter()
+        .map(|archived| TestMeasurement::from_bytes(archived.data().bytes()).unwrap())
+        .collect();
+    assert_eq!(archived, measurements);
+}
+
+#[test]
+fn test_chunk_bytes() {
+    assert!(MAX_CHUNK_BYTES < MAX_BATCH_BYTES);
+
+    let mut chunk_bytes = ChunkBytes::new(100 + 2 * RECORD_OVERHEAD);
+    assert!(chunk_bytes.fits(60));
+    chunk_bytes.add(60);
+    assert_eq!(chunk_bytes.bytes(), 60 + RECORD_OVERHEAD);
+    assert!(chunk_bytes.fits(40));
+    assert!(!chunk_bytes.fits(41));
+    assert!(chunk_bytes.check().is_ok());
+    assert!(chunk_bytes.reached(60 + RECORD_OVERHEAD));
+    assert!(!chunk_bytes.reached(61 + RECORD_OVERHEAD));
+
+    chunk_bytes.add(41);
+    assert!(matches!(
+        chunk_bytes.check(),
+        Err(ArchiveError::ChunkTooLarge { bytes }) if bytes == 101 + 2 * RECORD_OVERHEAD
+    ));
+
+    chunk_bytes.reset();
+    assert_eq!(chunk_bytes.bytes(), 0);
+    assert!(!chunk_bytes.fits(usize::MAX));
+}
+
+#[test]
+fn test_serialize_records_too_large() {
+    // 30 references to one 64MB record add up to a chunk over MAX_CHUNK_BYTES without allocating it
+    let record = vec![0u8; 64 * 1024 * 1024];
+    let records = vec![record.as_slice(); 30];
+
+    match serialize_records::<TestMeasurement, _>(records) {
+        Err(ArchiveError::ChunkTooLarge { bytes }) => {
+            assert_eq!(bytes, 30 * (record.len() + RECORD_OVERHEAD));
+            assert!(bytes > MAX_CHUNK_BYTES);
+        }
+        Err(e) => panic!("Expected ChunkTooLarge, got {}", e),
+        Ok(_) => panic!(
+            "Expected ChunkTooLarge for a {} byte chunk",
+            30 * record.len()
+        ),
+    }
+}
+
+#[test]
+fn test_deserialize_chunk() {
+    let now = chrono::Utc::now();
+    let measurements: Vec<TestMeasurement> = (0..3)
+        .map(|i| TestMeasurement::new(&format!("source-{}", i), now))
+        .collect();
+
+    let fbb = serialize_chunk(measurements.clone()).unwrap();
+    let read: Vec<TestMeasurement> = deserialize_chunk(fbb.finished_data()).unwrap();
+    assert_eq!(read.len(), measurements.len());
+    for m in read {
+        assert!(measurements.contains(&m));
+    }
+
+    assert!(deserialize_chunk::<TestMeasurement>(b"not a chunk").is_err());
+}
+
+fn seconds(s: &[i64]) -> Vec<chrono::DateTime<chrono::Utc>> {
+    s.iter()
+        .map(|s| chrono::TimeZone::timestamp_opt(&chrono::Utc, *s, 0).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_repair_timestamps_clamp() {
+    let mut timestamps = seconds(&[0, 10, 5, 6, 20, 15]);
+    let repaired = repair_timestamps(&mut timestamps, TimestampRepair::Clamp);
+
+    assert_eq!(repaired, 3);
+    assert_eq!(timestamps, seconds(&[0, 10, 10, 10, 20, 20]));
+}
+
+#[test]
+fn test_repair_timestamps_interpolate() {
+    let mut timestamps = seconds(&[0, 10, 5, 6, 40, 15]);
+    let repaired = repair_timestamps(&mut timestamps, TimestampRepair::Interpolate);
+
+    // The run at the end of the sequence has nothing to interpolate towards, so it is clamped
+    assert_eq!(repaired, 3);
+    assert_eq!(timestamps, seconds(&[0, 10, 20, 30, 40, 40]));
+}
+
+#[test]
+fn test_repair_timestamps_monotonic() {
+    let mut timestamps = seconds(&[0, 0, 1, 2]);
+    let repaired = repair_timestamps(&mut timestamps, TimestampRepair::Interpolate);
+
+    assert_eq!(repaired, 0);
+    assert_eq!(timestamps, seconds(&[0, 0, 1, 2]));
+}
+
+#[test]
+fn test_read_chunk_repair() {
+    let timestamps = seconds(&[0, 10, 5]);
+    let measurements: Vec<TestMeasurement> = timestamps
+        .iter()
+        .map(|t| TestMeasurement::new("source", *t))
+        .collect();
+    let fbb = serialize_chunk(measurements).unwrap();
+
+    let read = read_chunk::<TestMeasurement>(fbb.finished_data(), None).unwrap();
+    assert_eq!(read.repaired, 0);
+    assert_eq!(read.measurements.len(), 3);
+
+    let read =
+        read_chunk::<TestMeasurement>(fbb.finished_data(), Some(TimestampRepair::Clamp)).unwrap();
+    assert!(read.repaired > 0);
+    assert!(read.timestamps.windows(2).all(|w| w[0] <= w[1]));
+}
+
+fn consumed(items: &[(&str, i64)]) -> Vec<Consumed<TestMeasurement>> {
+    items
+        .iter()
+        .zip(seconds(&items.iter().map(|(_, s)| *s).collect::<Vec<_>>()))
+        .enumerate()
+        .map(|(i, ((source_id, _), timestamp))| Consumed {
+            measurement: TestMeasurement::new(source_id, timestamp),
+            partition: (i % 2) as i32,
+            offset: 100 + i as i64,
+        })
+        .collect()
+}
+
+#[test]
+fn test_sort_chunk() {
+    let items = consumed(&[("b", 1), ("a", 3), ("b", 0), ("a", 2)]);
+
+    let (sorted, chunk) = sort_chunk(items, ChunkSort::SortKey);
+    let keys: Vec<(&str, i64)> = sorted
+        .iter()
+        .map(|c| {
+            (
+                c.measurement.source_id(),
+                c.measurement.timestamp().timestamp(),
+            )
+        })
+        .collect();
+    assert_eq!(keys, vec![("a", 2), ("a", 3), ("b", 0), ("b", 1)]);
+    assert_eq!(chunk.sort, ChunkSort::SortKey);
+    assert_eq!(chunk.stored_positions, vec![3, 1, 2, 0]);
+    assert_eq!(
+        chunk.record_offsets,
+        vec![
+            RecordOffset {
+                partition: 1,
+                offset: 103
+            },
+            RecordOffset {
+                partition: 1,
+                offset: 101
+            },
+            RecordOffset {
+                partition: 0,
+                offset: 102
+            },
+            RecordOffset {
+                partition: 0,
+                offset: 100
+            },
+        ]
+    );
+
+    // Equal timestamps keep their consumption order
+    let items = consumed(&[("b", 1), ("a", 0), ("c", 1)]);
+    let (sorted, chunk) = sort_chunk(items, ChunkSort::Timestamp);
+    let sources: Vec<&str> = sorted.iter().map(|c| c.measurement.source_id()).collect();
+    assert_eq!(sources, vec!["a", "b", "c"]);
+    assert_eq!(chunk.stored_positions, vec![1, 0, 2]);
+}
+
+#[test]
+fn test_restore_consumption_order() {
+    let items = consumed(&[("b", 1), ("a", 3), ("b", 0), ("a", 2)]);
+    let (sorted, chunk) = sort_chunk(items, ChunkSort::SortKey);
+    let offsets: Vec<i64> = sorted.iter().map(|c| c.offset).collect();
+
+    let restored = restore_consumption_order(offsets, &chunk.record_offsets).unwrap();
+    assert_eq!(restored, vec![100, 102, 101, 103]);
+
+    assert!(restore_consumption_order(vec![1, 2], &chunk.record_offsets).is_err());
+}
+
+#[test]
+fn test_read_sorted_chunk() {
+    // Source "a" has a clock that jumps backwards, which sorting hides
+    let items = consumed(&[("a", 10), ("b", 0), ("a", 5), ("b", 1)]);
+    let (sorted, chunk) = sort_chunk(items, ChunkSort::SortKey);
+    let fbb = serialize_chunk(sorted.into_iter().map(|c| c.measurement).collect()).unwrap();
+
+    let read = read_chunk::<TestMeasurement>(fbb.finished_data(), None).unwrap();
+    assert_eq!(read.timestamps, seconds(&[5, 10, 0, 1]));
+
+    let read = read_sorted_chunk::<TestMeasurement>(
+        fbb.finished_data(),
+        &chunk.record_offsets,
+        Some(TimestampRepair::Clamp),
+    )
+    .unwrap();
+    // Source "b" is on another partition, so its timestamps aren't clamped to source "a"'s
+    assert_eq!(read.timestamps, seconds(&[10, 10, 0, 1]));
+    assert_eq!(read.repaired, 1);
+}
+
+#[tokio::test]
+async fn test_upload_sorted_chunk_record_offsets() {
+    let store = test_file_store("record-offsets");
+    let cli =
+        Cli::try_parse_from(base_args().iter().chain(&["--sort-chunk-by", "sort-key"])).unwrap();
+    let items = consumed(&[("a", 10), ("b", 0), ("a", 5), ("b", 1)]);
+    let offsets = offset_ranges(&items);
+    let (sorted, chunk) = sort_chunk(items, ChunkSort::SortKey);
+    let measurements: Vec<TestMeasurement> = sorted.into_iter().map(|c| c.measurement).collect();
+
+    upload_chunk(
+        &cli,
+        &store,
+        "radar-2d",
+        measurements,
+        &offsets,
+        Some(&chunk),
+        false,
+    )
+    .await
+    .unwrap();
+    let key = archive_key_with_offsets("radar-2d", KeyLayout::Flat, seconds(&[0])[0], &offsets);
+
+    // The sidecar is written whatever features are enabled, and restores consumption order
+    let record_offsets = get_record_offsets(&store, &key).await.unwrap();
+    assert_eq!(record_offsets, chunk.record_offsets);
+    let object = store.get(&key).await.unwrap();
+    let data = decompress_object(&key, &object, &[]).unwrap();
+    let read = read_sorted_chunk::<TestMeasurement>(&data, &record_offsets, None).unwrap();
+    assert_eq!(read.timestamps, seconds(&[10, 5, 0, 1]));
+
+    assert_eq!(
+        decode_record_offsets(&encode_record_offsets(&record_offsets)).unwrap(),
+        record_offsets
+    );
+    assert!(matches!(
+        decode_record_offsets(&[0; 13]),
+        Err(ArchiveError::OrderError(_))
+    ));
+}
+
+#[test]
+fn test_key_timestamp() {
+    let mut keys = vec![
+        "radar-2d/2022-11-02T10:00:00+00:00",
+        "radar-2d/source-a/2022-11-01T10:00:00+00:00",
+        "radar-2d/2022-11-01T09:00:00-02:00",
+    ];
+    keys.sort_by_key(|k| key_timestamp(k).unwrap());
+    assert_eq!(
+        keys,
+        vec![
+            "radar-2d/source-a/2022-11-01T10:00:00+00:00",
+            "radar-2d/2022-11-01T09:00:00-02:00",
+            "radar-2d/2022-11-02T10:00:00+00:00",
+        ]
+    );
+
+    assert!(key_timestamp("radar-2d/not-a-timestamp").is_none());
+}
+
+fn utc(rfc3339: &str) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339(rfc3339)
+        .unwrap()
+        .with_timezone(&chrono::Utc)
+}
+
+#[test]
+fn test_archive_key_flat() {
+    assert_eq!(
+        archive_key("radar-2d", KeyLayout::Flat, utc("2022-10-26T07:30:00Z")),
+        "radar-2d/2022-10-26T07:30:00+00:00"
+    );
+    assert_eq!(
+        archive_key(
+            "radar-2d/source-a",
+            KeyLayout::Flat,
+            utc("2023-01-01T00:00:00Z")
+        ),
+        "radar-2d/source-a/2023-01-01T00:00:00+00:00"
+    );
+}
+
+#[test]
+fn test_archive_key_hive() {
+    assert_eq!(
+        archive_key("radar-2d", KeyLayout::Hive, utc("2022-10-26T07:30:00Z")),
+        "radar-2d/year=2022/month=10/day=26/hour=07/2022-10-26T07:30:00+00:00"
+    );
+    // Midnight starts a new day partition at hour 00
+    assert_eq!(
+        archive_key("radar-2d", KeyLayout::Hive, utc("2022-03-05T00:00:00Z")),
+        "radar-2d/year=2022/month=03/day=05/hour=00/2022-03-05T00:00:00+00:00"
+    );
+    // The last instant of a year stays in its year, the next second starts the next one
+    assert_eq!(
+        archive_key("radar-2d", KeyLayout::Hive, utc("2022-12-31T23:59:59.999Z")),
+        "radar-2d/year=2022/month=12/day=31/hour=23/2022-12-31T23:59:59.999+00:00"
+    );
+    assert_eq!(
+        archive_key("radar-2d", KeyLayout::Hive, utc("2023-01-01T00:00:00Z")),
+        "radar-2d/year=2023/month=01/day=01/hour=00/2023-01-01T00:00:00+00:00"
+    );
+    // Partitions are by UTC time, not the timestamp's original offset
+    assert_eq!(
+        archive_key(
+            "radar-2d",
+            KeyLayout::Hive,
+            utc("2022-12-31T22:00:00-02:00")
+        ),
+        "radar-2d/year=2023/month=01/day=01/hour=00/2023-01-01T00:00:00+00:00"
+    );
+}
+
+#[test]
+fn test_key_timestamp_hive() {
+    let ts = utc("2022-12-31T23:59:59Z");
+    assert_eq!(
+        key_timestamp(&archive_key("radar-2d", KeyLayout::Hive, ts)),
+        Some(ts)
+    );
+}
+
+#[test]
+fn test_archive_key_with_offsets() {
+    let ts = utc("2022-10-26T07:00:00Z");
+    let range = |partition, first_offset, last_offset| OffsetRange {
+        partition,
+        first_offset,
+        last_offset,
+    };
+
+    assert_eq!(
+        archive_key_with_offsets("radar-2d", KeyLayout::Flat, ts, &[]),
+        archive_key("radar-2d", KeyLayout::Flat, ts)
+    );
+    let key = archive_key_with_offsets(
+        "radar-2d",
+        KeyLayout::Flat,
+        ts,
+        &[range(0, 100, 199), range(3, 50, 80)],
+    );
+    assert_eq!(
+        key,
+        "radar-2d/2022-10-26T07:00:00+00:00_p0-100-199_p3-50-80"
+    );
+    assert_eq!(key_timestamp(&key), Some(ts));
+    let key = archive_key_with_offsets("radar-2d", KeyLayout::Hive, ts, &[range(1, 0, 9)]);
+    assert_eq!(
+        key,
+        "radar-2d/year=2022/month=10/day=26/hour=07/2022-10-26T07:00:00+00:00_p1-0-9"
+    );
+    assert_eq!(key_timestamp(&key), Some(ts));
+
+    // Manifests of keys with offsets still aren't archive objects
+    assert_eq!(key_timestamp(&format!("{}.manifest.json", key)), None);
+    assert_eq!(
+        key_timestamp("radar-2d/2022-10-26T07:00:00+00:00_backup"),
+        None
+    );
+
+    // Chunks from many partitions are keyed by a hash of their ranges
+    let ranges: Vec<OffsetRange> = (0..=MAX_KEY_OFFSET_RANGES as i32)
+        .map(|partition| range(partition, 0, 9))
+        .collect();
+    let key = archive_key_with_offsets("radar-2d", KeyLayout::Flat, ts, &ranges);
+    let (_, suffix) = key.split_once('_').unwrap();
+    assert_eq!(suffix.len(), "9p-".len() + 16);
+    assert!(suffix.starts_with("9p-"));
+    assert_eq!(key_timestamp(&key), Some(ts));
+    assert_eq!(
+        archive_key_with_offsets("radar-2d", KeyLayout::Flat, ts, &ranges),
+        key
+    );
+    let mut other = ranges.clone();
+    other[0].last_offset = 10;
+    assert_ne!(
+        archive_key_with_offsets("radar-2d", KeyLayout::Flat, ts, &other),
+        key
+    );
+}
+
+#[tokio::test]
+async fn test_rearchive_same_offsets() {
+    let store = test_file_store("rearchive");
+    let cli = create_test_cli();
+    let measurements: Vec<TestMeasurement> = seconds(&[2, 0, 1])
+        .into_iter()
+        .map(|t| TestMeasurement::new("source", t))
+        .collect();
+    let offsets = [OffsetRange {
+        partition: 0,
+        first_offset: 100,
+        last_offset: 102,
+    }];
+    let chunk_keys = |keys: Vec<String>| -> Vec<String> {
+        keys.into_iter()
+            .filter(|key| !key.ends_with(".manifest.json"))
+            .collect()
+    };
+
+    // The archiver crashed after uploading the chunk but before committing its offsets, so the next run consumes
+    // and archives the same offsets again
+    for _ in 0..2 {
+        upload_chunk(
+            &cli,
+            &store,
+            "radar-2d",
+            measurements.clone(),
+            &offsets,
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    let keys = chunk_keys(store.list("radar-2d/").await.unwrap());
+    assert_eq!(keys, vec!["radar-2d/1970-01-01T00:00:00+00:00_p0-100-102"]);
+
+    // A different chunk with the same earliest timestamp gets its own object
+    let offsets = [OffsetRange {
+        partition: 1,
+        first_offset: 7,
+        last_offset: 9,
+    }];
+    upload_chunk(
+        &cli,
+        &store,
+        "radar-2d",
+        measurements,
+        &offsets,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(chunk_keys(store.list("radar-2d/").await.unwrap()).len(), 2);
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_chunk_container() {
+    let store = test_file_store("container");
+    let cli = Cli::try_parse_from(base_args().iter().chain(&["--container"])).unwrap();
+    assert!(cli.container());
+    let measurements: Vec<TestMeasurement> = seconds(&[0, 1, 2])
+        .into_iter()
+        .map(|t| TestMeasurement::new("source", t))
+        .collect();
+    let offsets = [OffsetRange {
+        partition: 0,
+        first_offset: 0,
+        last_offset: 2,
+    }];
+
+    upload_chunk(
+        &cli,
+        &store,
+        "radar-2d",
+        measurements.clone(),
+        &offsets,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+    let key = "radar-2d/1970-01-01T00:00:00+00:00_p0-0-2";
+    let object = store.get(key).await.unwrap();
+    let data = decompress_object(key, &object, &[]).unwrap();
+    let contents = read_archive(&data).unwrap();
+    assert_eq!(contents.codec, codec::Codec::None);
+    assert_eq!(contents.schema, None);
+
+    // Every reader unwraps the container
+    let chunk = read_chunk::<TestMeasurement>(&data, None).unwrap();
+    assert_eq!(chunk.measurements, measurements);
+    assert_eq!(read_archive_raw(&data).unwrap().len(), 3);
+    let summary = check_chunk::<TestMeasurement>(
+        key,
+        &object.body,
+        object.content_encoding.as_deref(),
+        None,
+        Some(&object.metadata),
+    )
+    .unwrap();
+    assert_eq!(summary.measurements, Some(3));
+    assert_eq!(summary.last_timestamp, seconds(&[2])[0]);
+
+    // Containers with a compressed payload are read too, except by read_archive_raw which borrows the records
+    let zstd = write_archive(&contents.chunk, &codec::Codec::default(), None).unwrap();
+    let chunk = read_chunk::<TestMeasurement>(&zstd, None).unwrap();
+    assert_eq!(chunk.measurements, measurements);
+    assert!(matches!(
+        read_archive_raw(&zstd),
+        Err(ArchiveError::FormatError(FormatError::Codec(_)))
+    ));
+
+    // A corrupt container is an error rather than being read as a bare chunk
+    assert!(matches!(
+        read_chunk::<TestMeasurement>(&data[..data.len() - 1], None),
+        Err(ArchiveError::FormatError(FormatError::Truncated))
+    ));
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+#[test]
+fn test_cli_key_layout() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.key_layout(), KeyLayout::Flat);
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--key-layout", "hive"])).unwrap();
+    assert_eq!(cli.key_layout(), KeyLayout::Hive);
+
+    assert!(Cli::try_parse_from(args.iter().chain(&["--key-layout", "daily"])).is_err());
+}
+
+#[test]
+fn test_check_chunk() {
+    let timestamps = seconds(&[10, 0, 5]);
+    let measurements: Vec<TestMeasurement> = timestamps
+        .iter()
+        .map(|t| TestMeasurement::new("source", *t))
+        .collect();
+    let fbb = serialize_chunk(measurements).unwrap();
+    let compressed = zstd::encode_all(fbb.finished_data(), 0).unwrap();
+
+    let summary =
+        check_chunk::<TestMeasurement>("key", &compressed, Some("zstd"), None, None).unwrap();
+    assert_eq!(summary.measurements, Some(3));
+    assert_eq!(summary.first_timestamp, timestamps[1]);
+    assert_eq!(summary.last_timestamp, timestamps[0]);
+    assert_eq!(summary.bytes, compressed.len() as u64);
+    assert!(check_chunk::<TestMeasurement>("key", fbb.finished_data(), None, None, None).is_ok());
+    let lz4 = codec::Codec::Lz4.compress(fbb.finished_data()).unwrap();
+    assert!(check_chunk::<TestMeasurement>("key", &lz4, Some("lz4"), None, None).is_ok());
+
+    assert!(matches!(
+        check_chunk::<TestMeasurement>(
+            "key",
+            &compressed[..compressed.len() / 2],
+            Some("zstd"),
+            None,
+            None
+        ),
+        Err(ScanProblem::Truncated(_))
+    ));
+    assert!(matches!(
+        check_chunk::<TestMeasurement>("key", b"not zstd", Some("zstd"), None, None),
+        Err(ScanProblem::Corrupt(_))
+    ));
+    assert!(matches!(
+        check_chunk::<TestMeasurement>("key", b"not a chunk", None, None, None),
+        Err(ScanProblem::Corrupt(_))
+    ));
+    // Bytes that don't match the stored checksum are corrupt even if they read as a chunk
+    let checksum = HashMap::from([(
+        CHECKSUM_METADATA_KEY.to_owned(),
+        sha256_hex(fbb.finished_data()),
+    )]);
+    assert!(check_chunk::<TestMeasurement>(
+        "key",
+        &compressed,
+        Some("zstd"),
+        None,
+        Some(&checksum)
+    )
+    .is_ok());
+    let other = serialize_chunk(vec![TestMeasurement::new("other", timestamps[0])]).unwrap();
+    assert!(matches!(
+        check_chunk::<TestMeasurement>("key", other.finished_data(), None, None, Some(&checksum)),
+        Err(ScanProblem::Corrupt(_))
+    ));
+
+    let empty = serialize_chunk(Vec::<TestMeasurement>::new()).unwrap();
+    assert!(matches!(
+        check_chunk::<TestMeasurement>("key", empty.finished_data(), None, None, None),
+        Err(ScanProblem::Corrupt(_))
+    ));
+}
+
+#[test]
+fn test_coverage() {
+    let t = seconds(&[0, 60, 120, 1000, 1060, 50]);
+    let spans = vec![
+        ("radar-2d".to_owned(), t[3], t[4]),
+        ("radar-2d".to_owned(), t[0], t[1]),
+        ("radar-2d".to_owned(), t[1], t[2]),
+        // Overlaps the first object, so doesn't end the coverage early
+        ("radar-2d".to_owned(), t[5], t[5]),
+        ("radar-2d/source-a".to_owned(), t[3], t[3]),
+    ];
+
+    let coverage = coverage(spans, Duration::from_secs(300));
+    assert_eq!(coverage.len(), 2);
+    let radar = &coverage["radar-2d"];
+    assert_eq!(radar.objects, 4);
+    assert_eq!(radar.first_timestamp, t[0]);
+    assert_eq!(radar.last_timestamp, t[4]);
+    assert_eq!(
+        radar.gaps,
+        vec![Gap {
+            start: t[2],
+            end: t[3]
+        }]
+    );
+    assert!(coverage["radar-2d/source-a"].gaps.is_empty());
+}
+
+#[test]
+fn test_scan_cli() {
+    let args = [
+        "scan",
+        "--access-key",
+        "user",
+        "--secret-key",
+        "user123456",
+        "--endpoint",
+        "http://localhost:9000",
+        "--region",
+        "opensensor-region",
+        "--bucket-name",
+        "opensensor-archive",
+    ];
+
+    let scan = ScanCli::try_parse_from(args).unwrap();
+    assert_eq!(scan.prefix(), "");
+    assert!(!scan.quick());
+    assert_eq!(scan.max_gap(), Duration::from_secs(3600));
+    assert!(scan.zstd_dictionaries().is_empty());
+
+    let scan = ScanCli::try_parse_from(args.iter().chain(&[
+        "--prefix",
+        "radar-2d/",
+        "--quick",
+        "--max-gap",
+        "10m",
+    ]))
+    .unwrap();
+    assert_eq!(scan.prefix(), "radar-2d/");
+    assert!(scan.quick());
+    assert_eq!(scan.max_gap(), Duration::from_secs(600));
+    assert_eq!(
+        scan.s3(),
+        &S3Args::new(
+            "user",
+            "user123456",
+            "http://localhost:9000",
+            "opensensor-region",
+            "opensensor-archive"
+        )
+    );
+}
+
+#[test]
+fn test_flatten_cli_args() {
+    /// Multi-sensor tool reusing the archiver's S3 and Kafka options
+    #[derive(Parser)]
+    struct ToolCli {
+        #[command(flatten)]
+        s3: S3Args,
+
+        #[command(flatten)]
+        kafka: KafkaArgs,
+
+        #[arg(long)]
+        sensors: Vec<String>,
+    }
+
+    let s3_args = [
+        "--access-key",
+        "user",
+        "--secret-key",
+        "user123456",
+        "--endpoint",
+        "http://localhost:9000",
+        "--region",
+        "opensensor-region",
+        "--bucket-name",
+        "opensensor-archive",
+    ];
+    let s3 = S3Args::new(
+        "user",
+        "user123456",
+        "http://localhost:9000",
+        "opensensor-region",
+        "opensensor-archive",
+    );
+
+    let tool = ToolCli::try_parse_from(
+        [
+            "tool",
+            "--kafka-addresses",
+            "127.0.0.1:9010",
+            "--sensors",
+            "radar",
+        ]
+        .iter()
+        .chain(&s3_args),
+    )
+    .unwrap();
+    assert_eq!(tool.s3, s3);
+    assert_eq!(tool.kafka, KafkaArgs::new("127.0.0.1:9010"));
+    assert_eq!(tool.kafka.topic(), None);
+    assert_eq!(tool.sensors, vec!["radar".to_owned()]);
+
+    // The whole archiver CLI can be flattened into a larger one too
+    #[derive(Parser)]
+    struct ArchiveToolCli {
+        #[command(flatten)]
+        archive: Cli,
+
+        #[arg(long)]
+        dry_run: bool,
+    }
+
+    let tool = ArchiveToolCli::try_parse_from(
+        [
+            "tool",
+            "--sensor-name",
+            "radar-2d",
+            "--chunk-size",
+            "10",
+            "--kafka-addresses",
+            "127.0.0.1:9010",
+            "--topic",
+            "raw.surface.radar",
+            "--dry-run",
+        ]
+        .iter()
+        .chain(&s3_args),
+    )
+    .unwrap();
+    assert!(tool.dry_run);
+    let cli = tool.archive;
+    assert_eq!(cli.s3(), &s3);
+    assert_eq!(cli.kafka().topic(), Some("raw.surface.radar"));
+    assert_eq!(cli.topic(), Some("raw.surface.radar"));
+    assert_eq!(cli.bucket_name(), "opensensor-archive");
+    assert_eq!(cli.kafka_addresses(), "127.0.0.1:9010");
+}
+
+#[test]
+fn test_s3_config_errors() {
+    let s3 = |endpoint: &str, region: &str| {
+        S3Args::new("user", "user123456", endpoint, region, "opensensor-archive")
+    };
+
+    assert!(s3("http://localhost:9000", "opensensor-region")
+        .build_client()
+        .is_ok());
+
+    for endpoint in ["localhost:9000", "/path", "http://local host:9000", ""] {
+        match s3(endpoint, "opensensor-region").build_client() {
+            Err(ConfigError::InvalidEndpoint { endpoint: e, .. }) => assert_eq!(e, endpoint),
+            other => panic!(
+                "{:?} should be an invalid endpoint, got {:?}",
+                endpoint,
+                other.err()
+            ),
+        }
+    }
+
+    assert_eq!(
+        s3("http://localhost:9000", " ").build_client().err(),
+        Some(ConfigError::EmptyRegion)
+    );
+
+    let cli = Cli::new(
+        "user",
+        "user123456",
+        "localhost:9000",
+        "opensensor-region",
+        "opensensor-archive",
+        "radar-2d",
+        10000,
+        "127.0.0.1:9010",
+    );
+    assert!(matches!(
+        cli.build_object_store().err(),
+        Some(ConfigError::InvalidEndpoint { .. })
+    ));
+}
+
+#[tokio::test]
+pub async fn test_scan_archive() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-scan-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let timestamps = seconds(&[0, 10]);
+    let measurements: Vec<TestMeasurement> = timestamps
+        .iter()
+        .map(|t| TestMeasurement::new("source", *t))
+        .collect();
+    let fbb = serialize_chunk(measurements).unwrap();
+    let key = |t: chrono::DateTime<chrono::Utc>| format!("radar-2d/{}", t.to_rfc3339());
+    for t in &timestamps {
+        upload_object(
+            fbb.finished_data(),
+            &client,
+            bucket_name,
+            &key(*t),
+            &codec::Codec::default(),
+            &Encryption::None,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+    let corrupt = key(seconds(&[20])[0]);
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key(&corrupt)
+        .body(b"not an archive".to_vec().into())
+        .send()
+        .await
+        .unwrap();
+
+    let report = scan_archive::<TestMeasurement>(
+        &client,
+        bucket_name,
+        "",
+        false,
+        Duration::from_secs(3600),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.objects, 3);
+    assert_eq!(report.measurements, 4);
+    assert!(!report.is_healthy());
+    assert_eq!(report.issues.len(), 1);
+    assert_eq!(report.issues[0].key, corrupt);
+    assert!(matches!(report.issues[0].problem, ScanProblem::Corrupt(_)));
+    assert_eq!(report.coverage["radar-2d"].objects, 2);
+
+    // A quick scan doesn't download the objects, so only catches problems with their size
+    let report = scan_archive::<TestMeasurement>(
+        &client,
+        bucket_name,
+        "",
+        true,
+        Duration::from_secs(3600),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.objects, 3);
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_archive_stream() {
+    use futures_util::StreamExt;
+
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-stream-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    // Three chunks of two measurements, uploaded out of order, plus a manifest-like key to skip
+    for chunk in [2, 0, 1] {
+        let timestamps = seconds(&[chunk * 10, chunk * 10 + 1]);
+        let measurements: Vec<TestMeasurement> = timestamps
+            .iter()
+            .map(|t| TestMeasurement::new("source", *t))
+            .collect();
+        let fbb = serialize_chunk(measurements).unwrap();
+        upload_object(
+            fbb.finished_data(),
+            &client,
+            bucket_name,
+            &archive_key("radar-2d", KeyLayout::Hive, timestamps[0]),
+            &codec::Codec::default(),
+            &Encryption::None,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key("radar-2d/index.json")
+        .body(b"{}".to_vec().into())
+        .send()
+        .await
+        .unwrap();
+
+    let measurements: Vec<TestMeasurement> =
+        archive_stream::<TestMeasurement>(&client, bucket_name, "radar-2d", &[])
+            .map(|m| m.unwrap())
+            .collect()
+            .await;
+    let timestamps: Vec<_> = measurements.iter().map(|m| m.timestamp()).collect();
+    assert_eq!(timestamps, seconds(&[0, 1, 10, 11, 20, 21]));
+
+    // A corrupt object ends the stream with an error
+    client
+        .put_object()
+        .bucket(bucket_name)
+        .key(archive_key("radar-2d", KeyLayout::Hive, seconds(&[30])[0]))
+        .body(b"not an archive".to_vec().into())
+        .send()
+        .await
+        .unwrap();
+    let results: Vec<_> = archive_stream::<TestMeasurement>(&client, bucket_name, "radar-2d", &[])
+        .collect()
+        .await;
+    assert_eq!(results.len(), 7);
+    assert!(results[6].is_err());
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+/// Archives written with `--zstd-dictionary` read back through `scan_archive`, `archive_stream`, and
+/// `download_object_verified` given the dictionary, and fail to without it
+#[tokio::test]
+pub async fn test_read_dictionary_archive() {
+    use futures_util::StreamExt;
+
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-dictionary-archive-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
+
+    let dictionary = codec::ZstdDictionary::new(
+        codec::train_dictionary(&dictionary_samples(1000), 4096).unwrap(),
+    )
+    .unwrap();
+    let dict = codec::Codec::ZstdDict {
+        level: 3,
+        dictionary: dictionary.clone(),
+    };
+    let mut keys = Vec::new();
+    for chunk in 0..2 {
+        let timestamps = seconds(&[chunk * 10, chunk * 10 + 1]);
+        let measurements: Vec<TestMeasurement> = timestamps
+            .iter()
+            .map(|t| TestMeasurement::new("source", *t))
+            .collect();
+        let fbb = serialize_chunk(measurements).unwrap();
+        let key = archive_key("radar-2d", KeyLayout::Hive, timestamps[0]);
+        upload_object(
+            fbb.finished_data(),
+            &client,
+            bucket_name,
+            &key,
+            &dict,
+            &Encryption::None,
+            None,
+        )
+        .await
+        .unwrap();
+        keys.push(key);
+    }
+
+    // The scan CLI reads the dictionaries to decompress with
+    let path = std::env::temp_dir().join(format!("scan-{}.dict", std::process::id()));
+    std::fs::write(&path, dictionary.as_bytes()).unwrap();
+    let scan = ScanCli::try_parse_from([
+        "scan",
+        "--access-key",
+        "user",
+        "--secret-key",
+        "user123456",
+        "--endpoint",
+        "http://localhost:9000",
+        "--region",
+        "opensensor-region",
+        "--bucket-name",
+        bucket_name,
+        "--zstd-dictionary",
+        path.to_str().unwrap(),
+    ])
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(scan.zstd_dictionaries(), &[dictionary.clone()]);
+
+    let report = scan_archive::<TestMeasurement>(
+        &client,
+        bucket_name,
+        "",
+        false,
+        Duration::from_secs(3600),
+        scan.zstd_dictionaries(),
+    )
+    .await
+    .unwrap();
+    assert!(report.is_healthy());
+    assert_eq!(report.objects, 2);
+    assert_eq!(report.measurements, 4);
+
+    // Without the dictionary every object is reported unreadable
+    let report = scan_archive::<TestMeasurement>(
+        &client,
+        bucket_name,
+        "",
+        false,
+        Duration::from_secs(3600),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.issues.len(), 2);
+    assert!(report
+        .issues
+        .iter()
+        .all(|issue| matches!(issue.problem, ScanProblem::Unreadable(_))));
+
+    let measurements: Vec<TestMeasurement> =
+        archive_stream::<TestMeasurement>(&client, bucket_name, "radar-2d", &[dictionary.clone()])
+            .map(|m| m.unwrap())
+            .collect()
+            .await;
+    let timestamps: Vec<_> = measurements.iter().map(|m| m.timestamp()).collect();
+    assert_eq!(timestamps, seconds(&[0, 1, 10, 11]));
+    let results: Vec<_> = archive_stream::<TestMeasurement>(&client, bucket_name, "radar-2d", &[])
+        .collect()
+        .await;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+
+    let data = download_object_verified(&client, bucket_name, &keys[0], &[dictionary.clone()])
+        .await
+        .unwrap();
+    assert_eq!(
+        deserialize_chunk::<TestMeasurement>(&data).unwrap().len(),
+        2
+    );
+    assert!(matches!(
+        verify_object(&client, bucket_name, &keys[0], &[]).await,
+        Err(ArchiveError::DecompressError { .. })
+    ));
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[test]
+fn test_compact_equal_runs() {
+    let records = vec![
+        ("a", 1),
+        ("b", 1),
+        ("a", 1),
+        ("b", 2),
+        ("a", 1),
+        ("a", 2),
+        ("a", 1),
+    ];
+
+    let (kept, dropped) = compact_equal_runs(records, |r| r.0, |x, y| x.1 == y.1);
+    assert_eq!(dropped, 2);
+    assert_eq!(kept, vec![("a", 1), ("b", 1), ("b", 2), ("a", 2), ("a", 1)]);
+}
+
+#[test]
+fn test_compact_chunk() {
+    let items: Vec<Consumed<TestMeasurement>> = ["a", "a", "b", "a", "b"]
+        .into_iter()
+        .zip(seconds(&[0, 1, 2, 3, 4]))
+        .enumerate()
+        .map(|(i, (source_id, timestamp))| Consumed {
+            measurement: TestMeasurement::new(source_id, timestamp),
+            partition: 0,
+            offset: i as i64,
+        })
+        .collect();
+
+    let (kept, dropped) = compact_chunk(items);
+    assert_eq!(dropped, 3);
+    let kept: Vec<(&str, i64)> = kept
+        .iter()
+        .map(|c| (c.measurement.source_id(), c.offset))
+        .collect();
+    assert_eq!(kept, vec![("a", 0), ("b", 2)]);
+
+    // Off by default
+    assert!(!create_test_cli().compact_equal_runs());
+    let cli = Cli::try_parse_from(base_args().iter().chain(&["--compact-equal-runs"])).unwrap();
+    assert!(cli.compact_equal_runs());
+}
+
+#[test]
+fn test_archive_stats() {
+    let mut stats = ArchiveStats::new();
+    assert_eq!(stats.compression_ratio(), None);
+    assert_eq!(stats.chunks_per_minute(Duration::from_secs(60)), 0.0);
+
+    stats.record_chunk(100, 4000, 1000);
+    stats.record_chunk(50, 2000, 1000);
+    stats.record_dead_letter();
+    assert_eq!(stats.messages(), 150);
+    assert_eq!(stats.chunks(), 2);
+    assert_eq!(stats.uncompressed_bytes(), 6000);
+    assert_eq!(stats.compressed_bytes(), 2000);
+    assert_eq!(stats.dead_letters(), 1);
+    assert_eq!(stats.compression_ratio(), Some(3.0));
+    assert_eq!(stats.chunks_per_minute(Duration::from_secs(30)), 4.0);
+    assert_eq!(stats.chunks_per_minute(Duration::ZERO), 0.0);
+
+    // The summary guard records into the stats it wraps
+    let mut guarded = SummaryOnDrop::new();
+    guarded.record_chunk(100, 4000, 1000);
+    assert_eq!(guarded.messages(), 100);
+    assert_eq!(guarded.compression_ratio(), Some(4.0));
+}
+
+#[test]
+fn test_cli_stats_interval() {
+    let default = Some(Duration::from_secs(15 * 60));
+    assert_eq!(
+        Cli::try_parse_from(base_args()).unwrap().stats_interval(),
+        default
+    );
+    assert_eq!(create_test_cli().stats_interval(), default);
+
+    let cli = Cli::try_parse_from(base_args().iter().chain(&["--stats-interval", "5m"])).unwrap();
+    assert_eq!(cli.stats_interval(), Some(Duration::from_secs(300)));
+    let cli = Cli::try_parse_from(base_args().iter().chain(&["--stats-interval", "0s"])).unwrap();
+    assert_eq!(cli.stats_interval(), None);
+}
+
+#[test]
+fn test_offset_ranges() {
+    let items: Vec<Consumed<()>> = [(1, 7), (0, 3), (1, 5), (0, 4), (1, 6)]
+        .into_iter()
+        .map(|(partition, offset)| Consumed {
+            measurement: (),
+            partition,
+            offset,
+        })
+        .collect();
+
+    assert_eq!(
+        offset_ranges(&items),
+        vec![
+            OffsetRange {
+                partition: 0,
+                first_offset: 3,
+                last_offset: 4
+            },
+            OffsetRange {
+                partition: 1,
+                first_offset: 5,
+                last_offset: 7
+            },
+        ]
+    );
+}
+
 #[tokio::test]
-pub async fn test_create_delete_bucket() {
+pub async fn test_expire_archives() {
     let cli = create_test_cli();
-    let client = cli.build_client();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-expire-bucket";
+    create_bucket(&client, bucket_name, cli.region())
+        .await
+        .unwrap();
 
-    // Valid inputs
-    let bucket_name = "test-bucket";
+    let t = seconds(&[0, 10, 20, 30]);
+    // Keys and the last timestamp recorded in their metadata, if any
+    let objects = [
+        // Ends before the next chunk of its series starts at the cutoff
+        (archive_key("radar-2d", KeyLayout::Flat, t[0]), None),
+        (
+            format!(
+                "{}.manifest.json",
+                archive_key("radar-2d", KeyLayout::Flat, t[0])
+            ),
+            None,
+        ),
+        // Ends before the cutoff
+        (
+            archive_key("radar-2d/radar-1", KeyLayout::Flat, t[0]),
+            Some(t[1]),
+        ),
+        // Straddles the cutoff
+        (
+            archive_key("radar-2d/radar-2", KeyLayout::Flat, t[1]),
+            Some(t[3]),
+        ),
+        // Can't tell where it ends
+        (archive_key("radar-2d/radar-3", KeyLayout::Flat, t[0]), None),
+        (archive_key("radar-2d", KeyLayout::Hive, t[2]), None),
+        (archive_key("radar-2d", KeyLayout::Flat, t[3]), None),
+        ("radar-2d/index.json".to_owned(), None),
+        (archive_key("radar-3d", KeyLayout::Flat, t[0]), None),
+    ];
+    for (key, last_timestamp) in &objects {
+        client
+            .put_object()
+            .bucket(bucket_name)
+            .key(key)
+            .body(b"archive".to_vec().into())
+            .set_metadata(last_timestamp.map(|last_timestamp| {
+                HashMap::from([(
+                    LAST_TIMESTAMP_METADATA_KEY.to_owned(),
+                    last_timestamp.to_rfc3339(),
+                )])
+            }))
+            .send()
+            .await
+            .unwrap();
+    }
+    #[cfg(feature = "json")]
+    for key in [
+        archive_key("radar-2d", KeyLayout::Flat, t[0]),
+        archive_key("radar-2d/radar-2", KeyLayout::Flat, t[1]),
+    ] {
+        let entry = crate::archiver::index::IndexEntry {
+            key,
+            first_timestamp: t[0],
+            last_timestamp: t[3],
+            record_count: 1,
+            compressed_bytes: 7,
+        };
+        crate::archiver::index::append_to_index(&client, bucket_name, "radar-2d", entry)
+            .await
+            .unwrap();
+    }
+    let mut keys = list_object_keys(&client, bucket_name, "").await.unwrap();
+    keys.sort();
+
+    // A dry run counts the objects that ended before the cutoff without deleting them
+    let expired = expire_archives(&client, bucket_name, "radar-2d", t[2], true)
+        .await
+        .unwrap();
+    assert_eq!(expired, 3);
+    assert_eq!(
+        list_object_keys(&client, bucket_name, "").await.unwrap(),
+        keys
+    );
+
+    // Objects that straddle or start after the cutoff, malformed keys, and other sensors are kept
+    let expired = expire_archives(&client, bucket_name, "radar-2d", t[2], false)
+        .await
+        .unwrap();
+    assert_eq!(expired, 3);
+    let mut remaining = vec![
+        archive_key("radar-2d/radar-2", KeyLayout::Flat, t[1]),
+        archive_key("radar-2d/radar-3", KeyLayout::Flat, t[0]),
+        archive_key("radar-2d", KeyLayout::Hive, t[2]),
+        archive_key("radar-2d", KeyLayout::Flat, t[3]),
+        "radar-2d/index.json".to_owned(),
+        archive_key("radar-3d", KeyLayout::Flat, t[0]),
+    ];
+    // The index only lists the chunk that's kept
+    #[cfg(feature = "json")]
+    {
+        let today = chrono::Utc::now().date_naive();
+        let index = crate::archiver::index::read_index(&client, bucket_name, "radar-2d", today)
+            .await
+            .unwrap();
+        let indexed: Vec<&str> = index.entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(indexed, [remaining[0].as_str()]);
+        remaining.push(crate::archiver::index::index_key("radar-2d", today));
+    }
+    remaining.sort();
+    assert_eq!(
+        list_object_keys(&client, bucket_name, "").await.unwrap(),
+        remaining
+    );
+
+    delete_objects(&client, bucket_name).await.unwrap();
+    delete_bucket(&client, bucket_name).await.unwrap();
+}
+
+#[test]
+fn test_key_series() {
+    let t = seconds(&[0]);
+    assert_eq!(
+        key_series(&archive_key("radar-2d", KeyLayout::Flat, t[0])),
+        "radar-2d"
+    );
+    assert_eq!(
+        key_series(&archive_key("radar-2d/radar-1", KeyLayout::Hive, t[0])),
+        "radar-2d/radar-1"
+    );
+    assert_eq!(key_series("2022-10-26T07:00:00+00:00"), "");
+}
+
+#[test]
+fn test_overlaps_window() {
+    let t = seconds(&[0, 10, 20, 30]);
+
+    assert!(overlaps_window(t[1], t[1], t[0], t[2]));
+    assert!(overlaps_window(t[0], t[2], t[1], t[3]));
+    assert!(overlaps_window(t[0], t[3], t[1], t[2]));
+    // The window includes its start but not its end
+    assert!(overlaps_window(t[0], t[1], t[1], t[2]));
+    assert!(!overlaps_window(t[2], t[3], t[1], t[2]));
+    assert!(!overlaps_window(t[0], t[0], t[1], t[2]));
+}
+
+#[tokio::test]
+pub async fn test_list_archives_in_range() {
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-range-bucket";
     create_bucket(&client, bucket_name, cli.region())
         .await
         .unwrap();
+
+    let t = seconds(&[0, 10, 20, 30]);
+    let keys = [
+        archive_key("radar-2d", KeyLayout::Flat, t[0]),
+        archive_key("radar-2d", KeyLayout::Flat, t[1]),
+        archive_key("radar-2d/radar-1", KeyLayout::Flat, t[2]),
+        archive_key("radar-2d", KeyLayout::Hive, t[3]),
+        "radar-2d/index.json".to_owned(),
+        archive_key("radar-3d", KeyLayout::Flat, t[1]),
+    ];
+    for key in &keys {
+        client
+            .put_object()
+            .bucket(bucket_name)
+            .key(key)
+            .body(b"archive".to_vec().into())
+            .send()
+            .await
+            .unwrap();
+    }
+
+    // Malformed keys are skipped, and objects without manifests cover just their key timestamp
+    let in_range = list_archives_in_range(&client, bucket_name, "radar-2d", seconds(&[5])[0], t[3])
+        .await
+        .unwrap();
+    assert_eq!(in_range, [keys[1].clone(), keys[2].clone()]);
+
+    // With a manifest, the object's whole time range counts
+    #[cfg(feature = "json")]
+    {
+        use crate::archiver::manifest::{manifest_key, write_manifest, Manifest};
+
+        let manifest = Manifest {
+            record_count: 2,
+            first_timestamp: t[0],
+            last_timestamp: seconds(&[6])[0],
+            uncompressed_bytes: 100,
+            compressed_bytes: 40,
+            offsets: vec![],
+            codec: CodecKind::Zstd,
+            compression_level: Some(3),
+            dictionary_id: None,
+            sort_chunk_by: None,
+            record_offsets: vec![],
+        };
+        write_manifest(
+            &client,
+            bucket_name,
+            &manifest_key(&keys[0]),
+            &manifest,
+            &Encryption::None,
+        )
+        .await
+        .unwrap();
+        let in_range =
+            list_archives_in_range(&client, bucket_name, "radar-2d", seconds(&[5])[0], t[3])
+                .await
+                .unwrap();
+        assert_eq!(
+            in_range,
+            [keys[0].clone(), keys[1].clone(), keys[2].clone()]
+        );
+    }
+
+    delete_objects(&client, bucket_name).await.unwrap();
     delete_bucket(&client, bucket_name).await.unwrap();
+}
 
-    // Bad bucket name
-    let invalid_bucket_name = "test_bucket";
-    let create_result = create_bucket(&client, invalid_bucket_name, cli.region()).await;
-    assert!(create_result.is_err());
+#[cfg(feature = "json")]
+#[test]
+fn test_manifest_json() {
+    use crate::archiver::manifest::{manifest_key, Manifest};
 
-    // Double create raises an Error
+    let timestamp = chrono::TimeZone::timestamp_opt(&chrono::Utc, 0, 0).unwrap();
+    let manifest = Manifest {
+        record_count: 2,
+        first_timestamp: timestamp,
+        last_timestamp: timestamp,
+        uncompressed_bytes: 100,
+        compressed_bytes: 40,
+        offsets: vec![OffsetRange {
+            partition: 0,
+            first_offset: 10,
+            last_offset: 11,
+        }],
+        codec: CodecKind::Zstd,
+        compression_level: Some(3),
+        dictionary_id: Some(7),
+        sort_chunk_by: None,
+        record_offsets: vec![],
+    };
+
+    let json: serde_json::Value = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(json["record_count"], 2);
+    assert_eq!(json["codec"], "zstd");
+    assert_eq!(json["compression_level"], 3);
+    assert_eq!(json["dictionary_id"], 7);
+    // Unsorted chunks leave the sort fields out, so older manifests still parse
+    assert!(json.get("sort_chunk_by").is_none());
+    assert!(json.get("record_offsets").is_none());
+    assert_eq!(json["first_timestamp"], "1970-01-01T00:00:00Z");
+    assert_eq!(json["offsets"][0]["last_offset"], 11);
+    assert_eq!(serde_json::from_value::<Manifest>(json).unwrap(), manifest);
+
+    // Only zstd manifests record a level
+    let lz4 = Manifest {
+        codec: CodecKind::Lz4,
+        compression_level: None,
+        dictionary_id: None,
+        ..manifest
+    };
+    let json: serde_json::Value = serde_json::to_value(&lz4).unwrap();
+    assert_eq!(json["codec"], "lz4");
+    assert!(json.get("compression_level").is_none());
+    assert!(json.get("dictionary_id").is_none());
+    assert_eq!(serde_json::from_value::<Manifest>(json).unwrap(), lz4);
+
+    assert_eq!(
+        manifest_key("radar-2d/1970-01-01T00:00:00+00:00"),
+        "radar-2d/1970-01-01T00:00:00+00:00.manifest.json"
+    );
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+pub async fn test_append_to_index() {
+    use crate::archiver::index::{append_to_index, index_key, read_index, IndexEntry};
+
+    let cli = create_test_cli();
+    let client = cli.s3().build_client().unwrap();
+    let bucket_name = "test-index-bucket";
     create_bucket(&client, bucket_name, cli.region())
         .await
         .unwrap();
-    let create_result = create_bucket(&client, bucket_name, cli.region()).await;
-    assert!(create_result.is_err());
+
+    let t = seconds(&[0, 10, 20, 30]);
+    let entry = |first: usize, last: usize| IndexEntry {
+        key: archive_key("radar-2d", KeyLayout::Flat, t[first]),
+        first_timestamp: t[first],
+        last_timestamp: t[last],
+        record_count: 100,
+        compressed_bytes: 2048,
+    };
+
+    append_to_index(&client, bucket_name, "radar-2d", entry(0, 1))
+        .await
+        .unwrap();
+    let index = append_to_index(&client, bucket_name, "radar-2d", entry(2, 3))
+        .await
+        .unwrap();
+    assert_eq!(index.entries, vec![entry(0, 1), entry(2, 3)]);
+
+    // Both appends landed in today's index object
+    let today = chrono::Utc::now().date_naive();
+    let index = read_index(&client, bucket_name, "radar-2d", today)
+        .await
+        .unwrap();
+    assert_eq!(index.entries, vec![entry(0, 1), entry(2, 3)]);
+    assert_eq!(
+        list_object_keys(&client, bucket_name, "radar-2d/")
+            .await
+            .unwrap(),
+        vec![index_key("radar-2d", today)]
+    );
+    let overlapping: Vec<&IndexEntry> = index.overlapping(t[2], t[3]).collect();
+    assert_eq!(overlapping, vec![&entry(2, 3)]);
+
+    // Appending the same object again replaces its entry
+    let index = append_to_index(&client, bucket_name, "radar-2d", entry(0, 1))
+        .await
+        .unwrap();
+    assert_eq!(index.entries.len(), 2);
+    // Days without uploads have an empty index
+    let yesterday = today.pred_opt().unwrap();
+    assert!(read_index(&client, bucket_name, "radar-2d", yesterday)
+        .await
+        .unwrap()
+        .entries
+        .is_empty());
+
+    delete_objects(&client, bucket_name).await.unwrap();
     delete_bucket(&client, bucket_name).await.unwrap();
 }
 
+#[cfg(feature = "json")]
 #[tokio::test]
-pub async fn test_upload() {}
+async fn test_append_to_index_in_store() {
+    use crate::archiver::index::{append_to_index_in_store, read_index_in_store, IndexEntry};
+
+    let store = test_file_store("index");
+    let t = seconds(&[0, 10, 20, 30]);
+    let entry = |first: usize, last: usize| IndexEntry {
+        key: archive_key("radar-2d", KeyLayout::Flat, t[first]),
+        first_timestamp: t[first],
+        last_timestamp: t[last],
+        record_count: 100,
+        compressed_bytes: 2048,
+    };
+
+    append_to_index_in_store(&store, "radar-2d", entry(0, 1))
+        .await
+        .unwrap();
+    append_to_index_in_store(&store, "radar-2d", entry(2, 3))
+        .await
+        .unwrap();
+    let index = append_to_index_in_store(&store, "radar-2d", entry(0, 1))
+        .await
+        .unwrap();
+    assert_eq!(index.entries, vec![entry(0, 1), entry(2, 3)]);
+
+    let today = chrono::Utc::now().date_naive();
+    let index = read_index_in_store(&store, "radar-2d", today)
+        .await
+        .unwrap();
+    assert_eq!(index.entries, vec![entry(0, 1), entry(2, 3)]);
+    assert!(
+        read_index_in_store(&store, "radar-2d", today.pred_opt().unwrap())
+            .await
+            .unwrap()
+            .entries
+            .is_empty()
+    );
+
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+/// The archiver appends every chunk it uploads to the sensor's index (a dry run appends nothing, see `test_dry_run`).
+/// Needs the Redpanda cluster from docker-compose.yaml
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn test_archiver_appends_to_index() {
+    use crate::archiver::index::read_index_in_store;
+    use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+    use rdkafka::client::DefaultClientContext;
+    use rdkafka::ClientConfig;
+    use redpanda::producer::RedpandaRecord;
+
+    let kafka_addresses = "127.0.0.1:9010";
+    let name = format!(
+        "index-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_millis()
+    );
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set("bootstrap.servers", kafka_addresses)
+        .create()
+        .unwrap();
+    let created = admin
+        .create_topics(
+            [&NewTopic::new(&name, 1, TopicReplication::Fixed(1))],
+            &AdminOptions::new(),
+        )
+        .await
+        .unwrap();
+    assert!(created.iter().all(|r| r.is_ok()));
+
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    let producer = builder.build_producer().unwrap();
+    let now = chrono::Utc::now();
+    for i in 0..20 {
+        let measurement = TestMeasurement::new(&i.to_string(), now);
+        let record = RedpandaRecord::new(&name, None, measurement.to_bytes(), None);
+        let delivery = producer.send_result(&record).map_err(|(e, _)| e).unwrap();
+        assert!(matches!(delivery.await, Ok(Ok(_))));
+    }
+
+    let mut args: Vec<&str> = base_args().to_vec();
+    args[12] = &name;
+    args[14] = "10";
+    let cli = Cli::try_parse_from(args.iter().chain(&[
+        "--topic",
+        name.as_str(),
+        "--start-from",
+        "earliest",
+    ]))
+    .unwrap();
+
+    // The archiver runs until it's stopped, so stop it once both chunks are indexed
+    let store = test_file_store("archiver-index");
+    let archiver = tokio::spawn(run_archiver_with_store::<TestMeasurement, _>(
+        cli,
+        store.clone(),
+    ));
+    let today = chrono::Utc::now().date_naive();
+    let mut index = Default::default();
+    for _ in 0..60 {
+        index = read_index_in_store(&store, &name, today).await.unwrap();
+        if index.entries.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    archiver.abort();
+
+    assert_eq!(index.entries.len(), 2);
+    for entry in &index.entries {
+        assert_eq!(entry.record_count, 10);
+        let object = store.get(&entry.key).await.unwrap();
+        assert_eq!(entry.compressed_bytes, object.body.len());
+    }
+
+    admin
+        .delete_topics(&[&name], &AdminOptions::new())
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(store.root()).unwrap();
+}
+
+/// Version 1 container with an embedded schema and an uncompressed payload. If this test fails, the on-disk format
+/// changed and FORMAT_VERSION needs to be bumped
+#[test]
+fn test_archive_format_golden() {
+    let golden =
+        std::fs::read("flatbuffers/archive-v1.golden").expect("Failed to read golden file");
+
+    let written = write_archive(b"archive chunk", &codec::Codec::None, Some(b"schema")).unwrap();
+    assert_eq!(written, golden);
+
+    assert_eq!(
+        read_archive(&golden).unwrap(),
+        ArchiveContents {
+            codec: codec::Codec::None,
+            schema: Some(b"schema".to_vec()),
+            chunk: b"archive chunk".to_vec(),
+        }
+    );
+}
+
+/// Zstd compressed `ArchiveChunk` of `reflection::KeyValue` records, encoded by hand from the flatbuffers binary format
+/// rather than with this crate's builder, like an archive written by another language
+#[test]
+fn test_read_archive_raw_foreign() {
+    let compressed =
+        std::fs::read("flatbuffers/archive-chunk-foreign.zst").expect("Failed to read fixture");
+    let data = codec::decompress(Some("zstd"), &compressed).unwrap();
+
+    let records = read_archive_raw(&data).unwrap();
+    let key_values: Vec<(&str, Option<&str>)> = records
+        .iter()
+        .map(|record| {
+            let key_value = flatbuffers::root::<KeyValue>(record).unwrap();
+            (key_value.key(), key_value.value())
+        })
+        .collect();
+    assert_eq!(
+        key_values,
+        vec![
+            ("temperature", Some("21.5")),
+            ("humidity", Some("40")),
+            ("unit", Some("celsius")),
+        ]
+    );
+
+    assert!(matches!(
+        read_archive_raw(&compressed),
+        Err(ArchiveError::InvalidChunk(_))
+    ));
+}
+
+#[test]
+fn test_archive_format_zstd_round_trip() {
+    let now = chrono::Utc::now();
+    let measurements: Vec<TestMeasurement> = (0..3)
+        .map(|i| TestMeasurement::new("source", now + chrono::Duration::seconds(i)))
+        .collect();
+    let fbb = serialize_chunk(measurements.clone()).unwrap();
+
+    let data = write_archive(fbb.finished_data(), &codec::Codec::Zstd { level: 3 }, None).unwrap();
+    let contents = read_archive(&data).unwrap();
+    // The level isn't stored in the container
+    assert_eq!(
+        contents.codec,
+        codec::Codec::Zstd {
+            level: ZSTD_DEFAULT_LEVEL
+        }
+    );
+    assert_eq!(contents.schema, None);
+
+    let read: Vec<TestMeasurement> = deserialize_chunk(&contents.chunk).unwrap();
+    assert_eq!(read, measurements);
+}
+
+#[test]
+fn test_archive_format_rejects_invalid() {
+    let data = write_archive(b"archive chunk", &codec::Codec::None, None).unwrap();
+
+    let mut bad_magic = data.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(read_archive(&bad_magic), Err(FormatError::BadMagic));
+
+    let mut bad_version = data.clone();
+    bad_version[4] = 2;
+    assert_eq!(
+        read_archive(&bad_version),
+        Err(FormatError::UnsupportedVersion(2))
+    );
+
+    let mut bad_codec = data.clone();
+    bad_codec[5] = 9;
+    assert_eq!(read_archive(&bad_codec), Err(FormatError::UnknownCodec(9)));
+
+    let mut bad_flags = data.clone();
+    bad_flags[6] = 0b10;
+    assert_eq!(read_archive(&bad_flags), Err(FormatError::UnknownFlags(0b10)));
+
+    let mut bad_reserved = data.clone();
+    bad_reserved[7] = 1;
+    assert_eq!(read_archive(&bad_reserved), Err(FormatError::ReservedByte(1)));
+
+    assert_eq!(
+        read_archive(&data[..data.len() - 1]),
+        Err(FormatError::Truncated)
+    );
+
+    let mut trailing = data;
+    trailing.push(0);
+    assert_eq!(read_archive(&trailing), Err(FormatError::TrailingBytes(1)));
+
+    // Version 1 has no codec byte for LZ4 or Snappy
+    assert!(matches!(
+        write_archive(b"archive chunk", &codec::Codec::Lz4, None),
+        Err(FormatError::Codec(_))
+    ));
+}
+
+#[test]
+fn test_provenance_metadata() {
+    let offsets = [
+        OffsetRange {
+            partition: 0,
+            first_offset: 1,
+            last_offset: 2,
+        },
+        OffsetRange {
+            partition: 2,
+            first_offset: 5,
+            last_offset: 9,
+        },
+    ];
+    let metadata = provenance_metadata("radar-2d-archiver", &offsets);
+
+    assert_eq!(metadata["consumer-group"], "radar-2d-archiver");
+    assert_eq!(metadata["partitions"], "0,2");
+    assert_eq!(metadata["archiver-pid"], std::process::id().to_string());
+    assert_eq!(metadata["archiver-version"], env!("CARGO_PKG_VERSION"));
+    assert!(!metadata["archiver-hostname"].is_empty());
+}
+
+#[test]
+fn test_source_filter() {
+    let filter = SourceFilter::only(["radar-1", "radar-2"]);
+    assert!(filter.allows("radar-1"));
+    assert!(filter.allows("radar-2"));
+    assert!(!filter.allows("radar-3"));
+    assert!(!filter.allows("radar"));
+    assert!(!filter.allows(""));
+
+    // Every source is allowed by default
+    let filter = SourceFilter::default();
+    assert!(filter.allows("radar-3"));
+    assert!(filter.allows(""));
+
+    // An empty allow-list allows nothing
+    assert!(!SourceFilter::only(Vec::<String>::new()).allows("radar-1"));
+}
+
+#[test]
+fn test_cli_source_ids() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.source_filter(), SourceFilter::default());
+    assert_eq!(create_test_cli().source_filter(), SourceFilter::default());
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--source-ids", "radar-1,radar-2"])).unwrap();
+    assert_eq!(
+        cli.source_filter(),
+        SourceFilter::only(["radar-1", "radar-2"])
+    );
+    assert!(!cli.source_filter().allows("radar-3"));
+}
+
+#[test]
+fn test_cli_codec() {
+    let args = base_args();
+
+    let cli = Cli::try_parse_from(args).unwrap();
+    assert_eq!(cli.codec(), codec::Codec::default());
+    assert_eq!(create_test_cli().codec(), codec::Codec::default());
+
+    let cli = Cli::try_parse_from(args.iter().chain(&["--compression-level", "19"])).unwrap();
+    assert_eq!(cli.codec(), codec::Codec::Zstd { level: 19 });
+    let cli = Cli::try_parse_from(args.iter().chain(&["--codec", "lz4"])).unwrap();
+    assert_eq!(cli.codec(), codec::Codec::Lz4);
+    let cli = Cli::try_parse_from(args.iter().chain(&["--codec", "snappy"])).unwrap();
+    assert_eq!(cli.codec(), codec::Codec::Snappy);
+    assert!(Cli::try_parse_from(args.iter().chain(&["--codec", "gzip"])).is_err());
+}
+
+#[test]
+fn test_cli_chunk_size_range() {
+    let args = base_args();
+
+    assert_eq!(Cli::try_parse_from(args).unwrap().chunk_size_range(), None);
+    assert_eq!(create_test_cli().chunk_size_range(), None);
+
+    let cli = Cli::try_parse_from(args.iter().chain(&[
+        "--min-chunk-size",
+        "100",
+        "--max-chunk-size",
+        "100000",
+    ]))
+    .unwrap();
+    assert_eq!(cli.chunk_size_range(), Some((100, 100_000)));
+    let cli = Cli::try_parse_from(args.iter().chain(&["--max-chunk-size", "100000"])).unwrap();
+    assert_eq!(cli.chunk_size_range(), Some((10000, 100_000)));
+    let cli = Cli::try_parse_from(args.iter().chain(&["--min-chunk-size", "5000"])).unwrap();
+    assert_eq!(cli.chunk_size_range(), Some((5000, 10000)));
+    let cli = Cli::try_parse_from(args.iter().chain(&["--min-chunk-size", "50000"])).unwrap();
+    assert_eq!(cli.chunk_size_range(), Some((50000, 50000)));
+}
+
+#[test]
+fn test_cli_max_chunk_bytes() {
+    let args = base_args();
+
+    assert_eq!(Cli::try_parse_from(args).unwrap().max_chunk_bytes(), None);
+    assert_eq!(create_test_cli().max_chunk_bytes(), None);
+
+    for (bytes, expected) in [
+        ("1048576", 1_048_576),
+        ("64MB", 64_000_000),
+        ("64 MB", 64_000_000),
+        ("256MiB", 256 * 1024 * 1024),
+        ("1gib", 1024 * 1024 * 1024),
+        ("500B", 500),
+    ] {
+        let cli = Cli::try_parse_from(args.iter().chain(&["--max-chunk-bytes", bytes])).unwrap();
+        assert_eq!(cli.max_chunk_bytes(), Some(expected));
+    }
+
+    // Zero, over MAX_CHUNK_BYTES, and unknown units are rejected
+    for bytes in ["0", "2GB", "64TB", "MB", "-1"] {
+        assert!(Cli::try_parse_from(args.iter().chain(&["--max-chunk-bytes", bytes])).is_err());
+    }
+}
+
+#[test]
+fn test_source_chunks_flush_full() {
+    let mut chunks = SourceChunks::new(2, 4);
+
+    assert!(chunks.push("a", 1).is_empty());
+    assert!(chunks.push("b", 2).is_empty());
+    assert_eq!(chunks.open_sources(), 2);
+
+    let full = chunks.push("a", 3);
+    assert_eq!(
+        full,
+        vec![FullChunk {
+            source_id: "a".to_owned(),
+            items: vec![1, 3]
+        }]
+    );
+    assert_eq!(chunks.open_sources(), 1);
+    assert_eq!(chunks.len(), 1);
+}
+
+#[test]
+fn test_next_chunk_size() {
+    // Caught up: the smallest chunks, for low latency
+    assert_eq!(next_chunk_size(0, 100, 10_000), 100);
+    assert_eq!(next_chunk_size(50, 100, 10_000), 100);
+    // Behind: chunks grow to cover the backlog
+    assert_eq!(next_chunk_size(2_500, 100, 10_000), 2_500);
+    // Far behind: the largest chunks
+    assert_eq!(next_chunk_size(u64::MAX, 100, 10_000), 10_000);
+    // min wins over max, and chunks always hold at least one message
+    assert_eq!(next_chunk_size(2_500, 500, 200), 500);
+    assert_eq!(next_chunk_size(0, 0, 10), 1);
+}
+
+#[tokio::test]
+async fn test_lag_meter() {
+    // Building a consumer doesn't connect to the brokers, and it has no position on any partition yet
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers("localhost:9092");
+    builder.set_group_id("test-lag-meter");
+    let consumer = std::sync::Arc::new(builder.build_consumer().unwrap());
+
+    assert_eq!(LagMeter::new(false).measure(&consumer).await, None);
+
+    // A recent measurement is reused without asking the brokers again
+    let mut meter = LagMeter::new(true);
+    meter.measured = Some(tokio::time::Instant::now());
+    meter.total = Some(42);
+    assert_eq!(meter.measure(&consumer).await, Some(42));
+
+    // A stale one is measured again
+    meter.measured = Some(tokio::time::Instant::now() - LAG_INTERVAL);
+    assert_eq!(meter.measure(&consumer).await, None);
+    assert!(meter.measured.unwrap().elapsed() < LAG_INTERVAL);
+}
+
+#[test]
+fn test_partition_offsets() {
+    let record = |partition, offset| RecordOffset { partition, offset };
+    let mut offsets = PartitionOffsets::new();
+    assert_eq!(offsets.safe_offset(0), None);
+    assert!(offsets.uncommitted().is_empty());
+
+    // Two partitions split by source: "a" on partition 0, and "b" on both partitions. A filtered out message on
+    // partition 1 is consumed without being buffered.
+    offsets.buffered(0, 10);
+    offsets.buffered(1, 20);
+    offsets.buffered(0, 11);
+    offsets.consumed(1, 21);
+    offsets.buffered(1, 22);
+    offsets.buffered(0, 12);
+    // Nothing is archived yet, so nothing is safe to commit past what's buffered
+    assert_eq!(offsets.uncommitted(), vec![(0, 10), (1, 20)]);
+
+    // "b" (0:11, 1:20, 1:22) is uploaded. Partition 1 is fully archived, but "a" still holds back partition 0.
+    offsets.archived(&[record(1, 20), record(0, 11), record(1, 22)]);
+    assert_eq!(offsets.safe_offset(0), Some(10));
+    assert_eq!(offsets.safe_offset(1), Some(23));
+    let uncommitted = offsets.uncommitted();
+    assert_eq!(uncommitted, vec![(0, 10), (1, 23)]);
+    offsets.committed(&uncommitted);
+    assert!(offsets.uncommitted().is_empty());
+
+    // "a" (0:10, 0:12) is uploaded, only partition 0 moves
+    offsets.archived(&[record(0, 10), record(0, 12)]);
+    assert_eq!(offsets.uncommitted(), vec![(0, 13)]);
+    offsets.committed(&[(0, 13)]);
+
+    // A measurement consumed again after a rebalance is held back until its last copy is archived
+    offsets.buffered(1, 23);
+    offsets.buffered(1, 23);
+    offsets.archived(&[record(1, 23)]);
+    assert!(offsets.uncommitted().is_empty());
+    offsets.archived(&[record(1, 23)]);
+    assert_eq!(offsets.uncommitted(), vec![(1, 24)]);
+}
+
+#[test]
+fn test_source_chunks_set_chunk_size() {
+    let mut chunks = SourceChunks::new(4, 4);
+
+    assert!(chunks.push("a", 1).is_empty());
+    assert!(chunks.push("a", 2).is_empty());
+    chunks.set_chunk_size(2);
+    assert_eq!(
+        chunks.push("a", 3),
+        vec![FullChunk {
+            source_id: "a".to_owned(),
+            items: vec![1, 2, 3]
+        }]
+    );
+
+    chunks.set_chunk_size(0);
+    assert_eq!(chunks.push("b", 4).len(), 1);
+}
+
+#[test]
+fn test_source_chunks_flush_bytes() {
+    let mut chunks = SourceChunks::new(10, 4).with_max_chunk_bytes(100 + 2 * RECORD_OVERHEAD);
+
+    assert!(chunks.push_sized("a", 1, 60).is_empty());
+    assert!(chunks.push_sized("b", 2, 90).is_empty());
+    assert!(chunks.push_sized("a", 3, 40).is_empty());
+
+    // "a" is full, so it's flushed before 4 starts a new chunk
+    let full = chunks.push_sized("a", 4, 1);
+    assert_eq!(
+        full,
+        vec![FullChunk {
+            source_id: "a".to_owned(),
+            items: vec![1, 3]
+        }]
+    );
+    assert_eq!(chunks.open_sources(), 2);
+    assert_eq!(chunks.len(), 2);
+
+    // Sizes are tracked per chunk, so the new chunk for "a" has room again
+    assert!(chunks.push_sized("a", 5, 90).is_empty());
+}
+
+#[test]
+fn test_source_chunks_max_chunk_bytes() {
+    let mut chunks = SourceChunks::new(10, 4).with_flush_bytes(Some(100 + 2 * RECORD_OVERHEAD));
+
+    assert!(chunks.push_sized("a", 1, 60).is_empty());
+    assert!(chunks.push_sized("b", 2, 90).is_empty());
+    assert!(chunks.push_sized("a", 3, 39).is_empty());
+
+    // 4 takes "a" to the flush bytes, so it's flushed with 4 in it, long before 10 items
+    let full = chunks.push_sized("a", 4, 1);
+    assert_eq!(
+        full,
+        vec![FullChunk {
+            source_id: "a".to_owned(),
+            items: vec![1, 3, 4]
+        }]
+    );
+    assert_eq!(chunks.open_sources(), 1);
+
+    // A measurement over the flush bytes on its own is flushed as a chunk of one
+    assert_eq!(chunks.push_sized("c", 5, 1000).len(), 1);
+    assert_eq!(chunks.len(), 1);
+}
+
+#[test]
+fn test_source_chunks_max_chunk_bytes_and_size() {
+    let mut chunks = SourceChunks::new(2, 4).with_flush_bytes(Some(100 + 2 * RECORD_OVERHEAD));
+
+    // Small measurements reach the chunk size first
+    assert!(chunks.push_sized("a", 1, 1).is_empty());
+    let full = chunks.push_sized("a", 2, 1);
+    assert_eq!(
+        full,
+        vec![FullChunk {
+            source_id: "a".to_owned(),
+            items: vec![1, 2]
+        }]
+    );
+
+    // Large ones reach the flush bytes first
+    assert_eq!(
+        chunks.push_sized("b", 3, 200),
+        vec![FullChunk {
+            source_id: "b".to_owned(),
+            items: vec![3]
+        }]
+    );
+
+    // Reaching both at once flushes the chunk once
+    assert!(chunks.push_sized("c", 4, 50).is_empty());
+    assert_eq!(chunks.push_sized("c", 5, 50).len(), 1);
+    assert!(chunks.is_empty());
+
+    // Without flush bytes only the chunk size counts
+    let mut chunks = SourceChunks::new(2, 4).with_flush_bytes(None);
+    assert!(chunks.push_sized("b", 3, 200).is_empty());
+}
+
+#[test]
+fn test_source_chunks_evict_lru() {
+    let mut chunks = SourceChunks::new(10, 2);
+
+    assert!(chunks.push("a", 1).is_empty());
+    assert!(chunks.push("b", 2).is_empty());
+    // Touch "a" so "b" becomes the least recently used source
+    assert!(chunks.push("a", 3).is_empty());
+
+    let full = chunks.push("c", 4);
+    assert_eq!(
+        full,
+        vec![FullChunk {
+            source_id: "b".to_owned(),
+            items: vec![2]
+        }]
+    );
+    assert_eq!(chunks.open_sources(), 2);
+
+    let drained = chunks.drain();
+    assert_eq!(drained.len(), 2);
+    assert_eq!(drained[0].source_id, "a");
+    assert_eq!(drained[0].items, vec![1, 3]);
+    assert_eq!(drained[1].source_id, "c");
+    assert!(chunks.is_empty());
+}
 
 use arrow2::array::*;
 use arrow2::chunk::Chunk;
@@ -172,3 +3945,161 @@ struct ArrowTest {
 //     println!("{:?}", buffer);
 //     Ok(())
 // }
+
+/// Object store that records the most puts it has had in flight at once
+struct ConcurrencyStore {
+    inner: FileSystemObjectStore,
+    active: std::sync::atomic::AtomicUsize,
+    max_active: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl ObjectStore for ConcurrencyStore {
+    fn location(&self) -> &str {
+        self.inner.location()
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        object: StoredObject,
+    ) -> std::result::Result<(), ObjectStoreError> {
+        use std::sync::atomic::Ordering;
+
+        let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_active.fetch_max(active, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let result = self.inner.put(key, object).await;
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    async fn get(&self, key: &str) -> std::result::Result<StoredObject, ObjectStoreError> {
+        self.inner.get(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> std::result::Result<Vec<String>, ObjectStoreError> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, key: &str) -> std::result::Result<(), ObjectStoreError> {
+        self.inner.delete(key).await
+    }
+}
+
+/// Object store that records the keys of every object it's asked to get
+struct GetCountingStore {
+    inner: FileSystemObjectStore,
+    gets: std::sync::Mutex<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl ObjectStore for GetCountingStore {
+    fn location(&self) -> &str {
+        self.inner.location()
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        object: StoredObject,
+    ) -> std::result::Result<(), ObjectStoreError> {
+        self.inner.put(key, object).await
+    }
+
+    async fn get(&self, key: &str) -> std::result::Result<StoredObject, ObjectStoreError> {
+        self.gets.lock().unwrap().push(key.to_owned());
+        self.inner.get(key).await
+    }
+
+    async fn size(&self, key: &str) -> std::result::Result<u64, ObjectStoreError> {
+        self.inner.size(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> std::result::Result<Vec<String>, ObjectStoreError> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, key: &str) -> std::result::Result<(), ObjectStoreError> {
+        self.inner.delete(key).await
+    }
+}
+
+/// Mock archiver for a topic that puts `chunks` objects at once, then fails if `fail` is set
+async fn archive_mock_topic(
+    cli: Cli,
+    store: LimitedObjectStore<ConcurrencyStore>,
+    chunks: usize,
+    fail: bool,
+) -> std::result::Result<(), ArchiveError> {
+    let puts = (0..chunks).map(|i| {
+        let key = format!("{}/{}", cli.sensor_name(), i);
+        let store = &store;
+        async move { store.put(&key, StoredObject::default()).await }
+    });
+    for result in futures_util::future::join_all(puts).await {
+        result?;
+    }
+
+    if fail {
+        return Err(ArchiveError::TooManyDeadLetters(1));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_run_multi_archiver() {
+    let test_cli = |sensor_name| {
+        Cli::new(
+            "user",
+            "user123456",
+            "http://localhost:9000",
+            "opensensor-region",
+            "opensensor-archive",
+            sensor_name,
+            10,
+            "127.0.0.1:9010",
+        )
+    };
+    let root = test_file_store("multi-archiver");
+    let max_active = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let store = || ConcurrencyStore {
+        inner: root.clone(),
+        active: std::sync::atomic::AtomicUsize::new(0),
+        max_active: max_active.clone(),
+    };
+
+    // One archiver fails after uploading, which mustn't stop the other
+    let archivers = vec![
+        (
+            ArchiverConfig::with_runner(test_cli("radar-a"), |cli, store| {
+                archive_mock_topic(cli, store, 4, false)
+            }),
+            store(),
+        ),
+        (
+            ArchiverConfig::with_runner(test_cli("radar-b"), |cli, store| {
+                archive_mock_topic(cli, store, 2, true)
+            }),
+            store(),
+        ),
+    ];
+    let mut tasks = run_multi_archiver_with_stores(archivers, 1);
+    let mut exits = HashMap::new();
+    while let Some(exit) = tasks.join_next().await {
+        let (sensor_name, result) = exit.unwrap();
+        exits.insert(sensor_name, result);
+    }
+
+    assert!(exits["radar-a"].is_ok());
+    assert!(matches!(
+        exits["radar-b"],
+        Err(ArchiveError::TooManyDeadLetters(1))
+    ));
+    assert_eq!(root.list("radar-a/").await.unwrap().len(), 4);
+    assert_eq!(root.list("radar-b/").await.unwrap().len(), 2);
+    // Uploads of both archivers shared a single permit
+    assert_eq!(max_active.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    std::fs::remove_dir_all(root.root()).unwrap();
+}
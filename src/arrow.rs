@@ -1,3 +1,22 @@
+//! Arrow serialization of sensors and arrow2_convert field types
+//!
+//! `arrow_chunks` batches a stream of measurements into arrow `Chunk`s, the bridge from live sensor streams to
+//! arrow-based analytics such as DataFusion.
+
+use std::sync::Arc;
+
+use arrow2::array::{Array, MutablePrimitiveArray, PrimitiveArray, StructArray, TryPush};
+use arrow2::chunk::Chunk;
+use arrow2::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow2_convert::deserialize::ArrowDeserialize;
+use arrow2_convert::field::{ArrowEnableVecForType, ArrowField};
+use arrow2_convert::serialize::{ArrowSerialize, TryIntoArrow};
+use async_stream::stream;
+use chrono::{DateTime, TimeZone, Utc};
+use futures_core::Stream;
+
+use crate::parquet_io::STRUCT_COLUMN;
+
 /// Sensors should implement this trait for Apache Arrow in-memory serialization and deserialization
 pub trait ArrowSerializable {
     /// This should be the error type of the implementing sensor
@@ -11,3 +30,135 @@ pub trait ArrowSerializable {
     where
         Self: Sized;
 }
+
+/// Arrow field for `DateTime<Utc>` values, written as `timestamp(ns, "UTC")` columns
+///
+/// arrow2_convert maps `chrono::NaiveDateTime` to `timestamp(ns, None)`, which parquet records as a local time that
+/// isn't adjusted to UTC, so readers like pyarrow and Spark can't tell what instant it is. Use this type for
+/// `DateTime<Utc>` fields instead, so the timezone is part of the parquet schema. `DateTime<Utc>` can't implement
+/// arrow2_convert's traits itself, so like `arrow2_convert::field::LargeString` this is only named in the field
+/// attribute.
+///
+/// # Examples
+///
+/// ```no_run
+/// #[derive(ArrowField, ArrowSerialize, ArrowDeserialize)]
+/// struct Reading {
+///     #[arrow_field(type = "opensensor::arrow::UtcTimestamp")]
+///     timestamp: DateTime<Utc>,
+///     #[arrow_field(type = "Option<opensensor::arrow::UtcTimestamp>")]
+///     calibrated_at: Option<DateTime<Utc>>,
+/// }
+/// ```
+pub struct UtcTimestamp {}
+
+impl ArrowField for UtcTimestamp {
+    type Type = DateTime<Utc>;
+
+    #[inline]
+    fn data_type() -> DataType {
+        DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".to_owned()))
+    }
+}
+
+impl ArrowSerialize for UtcTimestamp {
+    type MutableArrayType = MutablePrimitiveArray<i64>;
+
+    #[inline]
+    fn new_array() -> Self::MutableArrayType {
+        Self::MutableArrayType::from(<Self as ArrowField>::data_type())
+    }
+
+    #[inline]
+    fn arrow_serialize(
+        v: &DateTime<Utc>,
+        array: &mut Self::MutableArrayType,
+    ) -> arrow2::error::Result<()> {
+        array.try_push(Some(v.timestamp_nanos()))
+    }
+}
+
+impl ArrowDeserialize for UtcTimestamp {
+    type ArrayType = PrimitiveArray<i64>;
+
+    #[inline]
+    fn arrow_deserialize(v: Option<&i64>) -> Option<DateTime<Utc>> {
+        v.map(|t| Utc.timestamp_nanos(*t))
+    }
+}
+
+impl ArrowEnableVecForType for UtcTimestamp {}
+
+/// Schema of the chunks `arrow_chunks` yields for structs of type `T`, a column per field of `T`
+///
+/// Types that aren't structs get a single column named `parquet_io::STRUCT_COLUMN`.
+pub fn arrow_schema<T>() -> Schema
+where
+    T: ArrowField<Type = T>,
+{
+    match T::data_type() {
+        DataType::Struct(fields) => Schema::from(fields),
+        data_type => Schema::from(vec![Field::new(STRUCT_COLUMN, data_type, false)]),
+    }
+}
+
+/// Batch a stream of structs into arrow chunks of `batch_size` rows, i.e. to query a live sensor stream with DataFusion
+///
+/// Each chunk has a column per field of `T`, as described by `arrow_schema`, serialized with arrow2_convert. The last
+/// chunk holds whatever is left when the stream ends, so it can be smaller than `batch_size`; nothing is yielded for
+/// an empty stream. `batch_size` is clamped to be at least 1.
+///
+/// A batch that fails to serialize is yielded as an error, and the stream carries on with the next batch.
+///
+/// # Examples
+///
+/// ```no_run
+/// let schema = arrow_schema::<RadarReading>();
+/// let mut chunks = Box::pin(arrow_chunks(readings, 8192));
+/// while let Some(chunk) = chunks.next().await {
+///     let chunk = chunk?;
+///     // Hand `schema` and `chunk` to DataFusion
+/// }
+/// ```
+pub fn arrow_chunks<S, T>(
+    items: S,
+    batch_size: usize,
+) -> impl Stream<Item = Result<Chunk<Arc<dyn Array>>, arrow2::error::Error>>
+where
+    S: Stream<Item = T>,
+    T: ArrowField<Type = T> + ArrowSerialize + 'static,
+{
+    let batch_size = batch_size.max(1);
+
+    stream! {
+        let mut batch = Vec::with_capacity(batch_size);
+
+        for await item in items {
+            batch.push(item);
+            if batch.len() >= batch_size {
+                yield to_chunk(&batch);
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            yield to_chunk(&batch);
+        }
+    }
+}
+
+/// Serialize `items` to a chunk with a column per struct field
+fn to_chunk<T>(items: &[T]) -> Result<Chunk<Arc<dyn Array>>, arrow2::error::Error>
+where
+    T: ArrowField<Type = T> + ArrowSerialize + 'static,
+{
+    let array: Box<dyn Array> = items.try_into_arrow()?;
+    let columns = match array.as_any().downcast_ref::<StructArray>() {
+        Some(array) => array
+            .values()
+            .iter()
+            .map(|column| Arc::from(column.to_boxed()))
+            .collect(),
+        None => vec![Arc::from(array)],
+    };
+    Chunk::try_new(columns)
+}
@@ -0,0 +1,54 @@
+//! Avro serialization for consumers that expect Avro, i.e. Kafka Connect and Flink
+//!
+//! Measurements are serialized as a single Avro binary datum, without the object container file header, which is what
+//! Avro tooling reading from Kafka expects. Combine with `registry::encode` and an Avro schema registered with
+//! `SchemaType::Avro` (the schema's `canonical_form`) to produce the Confluent wire format.
+
+use apache_avro::{from_avro_datum, from_value, to_avro_datum, to_value, Schema};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Sensors should implement this trait for Avro serialization and deserialization
+///
+/// `to_datum` and `from_datum` implement the methods for any measurement that implements serde's `Serialize` and
+/// `Deserialize` in the shape of its Avro schema.
+pub trait AvroSerializable {
+    /// This should be the error type of the implementing sensor
+    type Error;
+
+    /// Serialize this implementing sensor to an Avro binary datum written with `avro_schema`
+    fn avro_serialize(self) -> Vec<u8>;
+
+    /// Static method to construct sensor type from an Avro binary datum written with `avro_schema`
+    fn avro_deserialize(bytes: &[u8]) -> Result<Self, Self::Error>
+    where
+        Self: Sized;
+
+    /// Avro schema the sensor is serialized with
+    fn avro_schema() -> Schema;
+}
+
+/// Serialize a value to an Avro binary datum with `schema`
+///
+/// # Errors
+///
+/// - apache_avro::Error: If the value doesn't match the schema
+pub fn to_datum<T>(value: &T, schema: &Schema) -> Result<Vec<u8>, apache_avro::Error>
+where
+    T: Serialize,
+{
+    to_avro_datum(schema, to_value(value)?)
+}
+
+/// Deserialize a value from an Avro binary datum written with `schema`
+///
+/// # Errors
+///
+/// - apache_avro::Error: If the bytes aren't a datum of the schema, or it doesn't deserialize into `T`
+pub fn from_datum<T>(mut bytes: &[u8], schema: &Schema) -> Result<T, apache_avro::Error>
+where
+    T: DeserializeOwned,
+{
+    let value = from_avro_datum(schema, &mut bytes, None)?;
+    from_value(&value)
+}
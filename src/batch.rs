@@ -0,0 +1,202 @@
+//! Batches of measurements serialized as a single flatbuffer
+//!
+//! A `MeasurementBatch` stores many measurements as one `ArchiveChunk` flatbuffer (see `flatbuffers/archive.fbs`), a
+//! vector of tables holding each measurement's finished `Measurement::to_bytes` flatbuffer. This is the chunk format
+//! the archiver writes, so batches can be sent as a single Kafka message or written straight to archival storage,
+//! and any archive chunk can be read back with `MeasurementBatch::from_bytes`.
+//!
+//! Flatbuffers use 32 bit offsets, so a finished buffer can be at most 2GB (`MAX_BATCH_BYTES`). Instead of letting
+//! `FlatBufferBuilder` panic, `MeasurementBatch::push` returns `BatchError::TooLarge` for a measurement that would
+//! take the batch over the limit, leaving the batch as it was so it can still be finished and a new one started.
+
+use std::marker::PhantomData;
+
+use flatbuffers::{FlatBufferBuilder, InvalidFlatbuffer, WIPOffset, FLATBUFFERS_MAX_BUFFER_SIZE};
+
+use crate::archive_generated::archive::{
+    finish_archive_chunk_buffer, root_as_archive_chunk, ArchiveChunk, ArchiveChunkArgs,
+    ArchivedMeasurement, ArchivedMeasurementArgs,
+};
+use crate::measurement::Measurement;
+
+/// Largest finished batch in bytes, the 2GB limit of the 32 bit flatbuffer address space
+pub const MAX_BATCH_BYTES: usize = FLATBUFFERS_MAX_BUFFER_SIZE;
+
+/// Upper bound on the bytes a record adds to a batch on top of its own length: the vector length prefix, alignment
+/// padding, the `ArchivedMeasurement` table and vtable, and its offset in the measurements vector
+pub(crate) const RECORD_OVERHEAD: usize = 32;
+
+/// Upper bound on the bytes finishing a batch adds: the root offset, file identifier, measurements vector length,
+/// `ArchiveChunk` table and vtable, and alignment padding
+const FINISH_OVERHEAD: usize = 64;
+
+/// Error for all issues building or reading measurement batches
+#[derive(thiserror::Error, Debug)]
+pub enum BatchError {
+    /// Adding a record would take the batch over MAX_BATCH_BYTES
+    #[error("Measurement batch would be {0} bytes, over the 2GB flatbuffer limit")]
+    TooLarge(usize),
+    /// The bytes aren't a valid `ArchiveChunk` flatbuffer
+    #[error("Invalid measurement batch {0}")]
+    InvalidBatch(#[from] InvalidFlatbuffer),
+    /// A measurement stored in the batch failed `Measurement::from_bytes`
+    #[error("Failed to deserialize a batched measurement: {0}")]
+    DeserializeError(String),
+}
+
+/// Many measurements of one type, serialized into a single vector-of-tables flatbuffer
+///
+/// Measurements are serialized as they're pushed, so the batch only holds the flatbuffer being built. Convert the
+/// batch into a `FlatBufferBuilder` to finish it.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut batch = MeasurementBatch::new();
+/// for measurement in measurements {
+///     batch.push(measurement)?;
+/// }
+/// let fbb: FlatBufferBuilder = batch.into();
+///
+/// for measurement in MeasurementBatch::<RadarMeasurement2d>::from_bytes(fbb.finished_data())? {
+///     println!("{:?}", measurement?.timestamp());
+/// }
+/// ```
+pub struct MeasurementBatch<M> {
+    fbb: FlatBufferBuilder<'static>,
+    offsets: Vec<WIPOffset<ArchivedMeasurement<'static>>>,
+    measurement: PhantomData<fn(M)>,
+}
+
+impl<M> Default for MeasurementBatch<M> {
+    fn default() -> Self {
+        MeasurementBatch {
+            fbb: FlatBufferBuilder::new(),
+            offsets: Vec::new(),
+            measurement: PhantomData,
+        }
+    }
+}
+
+impl<M> MeasurementBatch<M>
+where
+    M: for<'a> Measurement<'a>,
+{
+    /// An empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Batch of every measurement in `measurements`, in order
+    ///
+    /// # Errors
+    ///
+    /// - BatchError::TooLarge: If the measurements don't fit in one flatbuffer
+    pub fn from_measurements<I>(measurements: I) -> Result<Self, BatchError>
+    where
+        I: IntoIterator<Item = M>,
+    {
+        let mut batch = Self::new();
+        for measurement in measurements {
+            batch.push(measurement)?;
+        }
+        Ok(batch)
+    }
+
+    /// Serialize a measurement with `Measurement::to_bytes` and add it to the end of the batch
+    ///
+    /// # Errors
+    ///
+    /// - BatchError::TooLarge: If the measurement would take the batch over MAX_BATCH_BYTES. The batch is unchanged.
+    pub fn push(&mut self, measurement: M) -> Result<(), BatchError> {
+        self.push_bytes(&measurement.to_bytes())
+    }
+
+    /// Add a measurement that's already serialized with `Measurement::to_bytes` to the end of the batch
+    ///
+    /// The bytes aren't checked, pass only the output of `Measurement::to_bytes` for `M`.
+    ///
+    /// # Errors
+    ///
+    /// - BatchError::TooLarge: If the record would take the batch over MAX_BATCH_BYTES. The batch is unchanged.
+    pub fn push_bytes(&mut self, record: &[u8]) -> Result<(), BatchError> {
+        if !self.fits(record.len()) {
+            return Err(BatchError::TooLarge(
+                self.serialized_size()
+                    .saturating_add(record.len())
+                    .saturating_add(RECORD_OVERHEAD),
+            ));
+        }
+
+        let data = self.fbb.create_vector(record);
+        let offset = ArchivedMeasurement::create(
+            &mut self.fbb,
+            &ArchivedMeasurementArgs { data: Some(data) },
+        );
+        self.offsets.push(offset);
+        Ok(())
+    }
+
+    /// Whether a serialized measurement of `record_len` bytes can be pushed without going over MAX_BATCH_BYTES
+    pub fn fits(&self, record_len: usize) -> bool {
+        let size = self
+            .serialized_size()
+            .checked_add(record_len)
+            .and_then(|size| size.checked_add(RECORD_OVERHEAD));
+        matches!(size, Some(size) if size <= MAX_BATCH_BYTES)
+    }
+
+    /// Number of measurements in the batch
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Whether the batch has no measurements
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Upper bound on the size of the finished batch in bytes
+    pub fn serialized_size(&self) -> usize {
+        // Finishing also writes the measurements vector, one offset per measurement
+        self.fbb.unfinished_data().len() + 4 * self.offsets.len() + FINISH_OVERHEAD
+    }
+
+    /// Read the measurements in a batch, i.e. a finished `MeasurementBatch` or an archive chunk, in stored order
+    ///
+    /// The flatbuffer is verified up front, then each measurement is deserialized with `Measurement::from_bytes` as
+    /// the iterator reaches it.
+    ///
+    /// # Errors
+    ///
+    /// - BatchError::InvalidBatch: If `data` isn't a valid `ArchiveChunk` flatbuffer
+    /// - BatchError::DeserializeError: For each stored measurement that fails `Measurement::from_bytes`
+    pub fn from_bytes(
+        data: &[u8],
+    ) -> Result<impl Iterator<Item = Result<M, BatchError>> + '_, BatchError> {
+        let chunk = root_as_archive_chunk(data)?;
+
+        Ok(chunk.measurements().iter().map(|archived| {
+            M::from_bytes(archived.data().bytes())
+                .map_err(|e| BatchError::DeserializeError(e.to_string()))
+        }))
+    }
+}
+
+impl<M> From<MeasurementBatch<M>> for FlatBufferBuilder<'static> {
+    fn from(batch: MeasurementBatch<M>) -> Self {
+        let MeasurementBatch {
+            mut fbb, offsets, ..
+        } = batch;
+        let measurements = fbb.create_vector(&offsets);
+        let chunk = ArchiveChunk::create(
+            &mut fbb,
+            &ArchiveChunkArgs {
+                measurements: Some(measurements),
+            },
+        );
+        finish_archive_chunk_buffer(&mut fbb, chunk);
+
+        fbb
+    }
+}
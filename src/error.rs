@@ -1,5 +1,6 @@
 //! Error types for use by all Sensors
 use redpanda::error::KafkaError;
+use std::time::Duration;
 
 /// Error type used by simple sensor
 /// TODO: This will probably get deleted and turned into a Trait similar to the MeasurementError that
@@ -18,4 +19,16 @@ pub enum SensorError {
     /// If there is an error in the message's timestamp
     #[error("Invalid timestamp value {0}")]
     TimestampError(i64),
+    /// If a producer configuration is invalid
+    #[error("Invalid producer configuration: {0}")]
+    ConfigError(String),
+    /// If a sensor's saved state can't be read or written
+    #[error("Sensor state error: {0}")]
+    StateError(String),
+    /// If the health probe endpoint can't be served
+    #[error("Failed to serve health probes: {0}")]
+    HealthServerError(String),
+    /// If a Sensor produced faster than its `RateLimiter` allows, holding how long until it can produce again
+    #[error("Rate limit exceeded, next measurement can be produced in {0:?}")]
+    RateLimited(Duration),
 }
@@ -9,17 +9,30 @@
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_doc_code_examples)]
 
+#[allow(dead_code, unused_imports, missing_docs)]
+#[allow(clippy::all)]
+pub mod archive_generated;
 pub mod archiver;
 /// Trait for arrow serialization
 pub mod arrow;
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod batch;
 pub mod error;
 pub mod measurement;
+pub mod mock;
 /// Trait that sensors should implement to produce parquet archives
 pub mod parquet;
+pub mod parquet_io;
+pub mod reflection;
 #[allow(dead_code, unused_imports, missing_docs)]
 #[allow(clippy::all)]
 pub mod reflection_generated;
+#[cfg(feature = "schema-registry")]
+pub mod registry;
 pub mod sensor;
+pub mod sink;
+pub mod stream_ext;
 
 #[cfg(test)]
 mod test_arrow;
@@ -28,10 +41,22 @@ mod tests;
 
 pub mod transducer;
 
+/// Reexports used by `impl_measurement_enum!`, not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    pub use chrono;
+    pub use redpanda;
+}
+
 pub use sensor::Sensor;
 /// Reexports
 pub use transducer::Transducer;
 
+use futures_util::StreamExt;
+use redpanda::consumer::{CommitMode, Consumer, RedpandaConsumer};
+use redpanda::error::KafkaError;
+use tracing::{event, Level};
+
 /// A sink for sensor data stored in Redpanda into various downstream data systems
 ///
 /// Use for implementing an S3 Parquet sink (also the Archiver trait), MyCelial (SQLite), and OLTP (Scylladb)
@@ -44,5 +69,159 @@ pub use transducer::Transducer;
 /// It might make more sense to separate these out by the type of sink (have a separate Archiver, SQLite, and ScyllaDB trait)
 /// that can also be implemented on AlgorithmResult/InferenceResults vs a single SensorSink trait (and have to also write a
 /// ModelSink + other types of traits)
+///
+/// ## Delivery guarantees
+///
+/// Committing offsets after a confirmed write gives at-least-once delivery: a crash between the write and the commit
+/// replays the batch. Exactly-once requires the offset commit to be part of the same atomic unit as the sink write,
+/// which depends on the backend:
+///
+/// - Redpanda/Kafka topics: can participate in a Kafka transaction directly (produce the output and send the consumer
+///   offsets to the same transaction). `sink::ExactlyOnceSink` does this with a `sink::transactional_producer`.
+/// - SQLite/ScyllaDB and other databases: can't join a Kafka transaction. Store the consumed offsets in the same
+///   database transaction (SQLite) or make writes idempotent by keying rows on topic/partition/offset (ScyllaDB).
+/// - S3 archives: object writes are atomic but not transactional. Embed offsets in the object key so a replayed
+///   batch overwrites the same object instead of duplicating it.
+///
+/// ## Implementing
+///
+/// Implement `sink_batch` to write a whole batch atomically where the backend allows it (i.e. one SQLite
+/// transaction), then hand the sink a consumer with `SensorSink::run`. `run` owns the Kafka consume + commit loop:
+/// it subscribes to the sink's topic, accumulates `batch_size` measurements, and only commits the consumer offsets
+/// once `sink_batch` succeeds. See `sink::sqlite::SqliteSink` and `archiver::ArchiveSink` for examples.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut builder = RedpandaBuilder::default();
+/// builder.set_bootstrap_servers("127.0.0.1:9010");
+/// builder.set_group_id("radar-2d-sqlite");
+/// builder.set("enable.auto.commit", "false");
+/// let consumer = builder.build_consumer().unwrap();
+///
+/// let sink = SqliteSink::<RadarMeasurement2d>::open("radar-2d.db")?.with_batch_size(1000);
+/// sink.run(consumer).await?;
+/// ```
 #[async_trait::async_trait]
-pub trait SensorSink {}
+pub trait SensorSink: Send + Sized {
+    /// Type of the measurements the sink writes
+    type Measurement: for<'a> measurement::Measurement<'a> + Send;
+
+    /// Error the sink returns, which must be able to wrap the Kafka errors from `run`
+    type Error: From<KafkaError> + std::fmt::Display + Send;
+
+    /// Topic `run` subscribes to
+    ///
+    /// ## Default Implementation
+    ///
+    /// The measurement's `TOPIC_NAME`
+    fn topic(&self) -> &str {
+        <Self::Measurement as measurement::Measurement>::TOPIC_NAME
+    }
+
+    /// Number of measurements `run` accumulates before calling `sink_batch`
+    ///
+    /// ## Default Implementation
+    ///
+    /// 1000 measurements
+    fn batch_size(&self) -> usize {
+        1000
+    }
+
+    /// Write a batch of measurements to the sink, returning once they're durably stored
+    ///
+    /// # Errors
+    ///
+    /// - Any error from the sink's backend. Nothing in a failed batch should be considered written
+    async fn sink_batch(&mut self, batch: Vec<Self::Measurement>) -> Result<(), Self::Error>;
+
+    /// Write a batch of measurements along with the record each was consumed from, returning once they're durably
+    /// stored
+    ///
+    /// `run` writes its batches with this. Override it for sinks that key rows on the record's topic, partition, and
+    /// offset, so a batch replayed after a crash between the write and the offset commit isn't stored twice.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Drops the records' metadata and calls `sink_batch`
+    ///
+    /// # Errors
+    ///
+    /// - Same as `sink_batch`
+    async fn sink_records(
+        &mut self,
+        batch: Vec<(Self::Measurement, measurement::RecordMeta)>,
+    ) -> Result<(), Self::Error> {
+        let batch = batch.into_iter().map(|(measurement, _)| measurement).collect();
+        self.sink_batch(batch).await
+    }
+
+    /// Commit `consumer`'s offsets once `sink_batch` has written everything consumed so far
+    ///
+    /// Override for sinks that commit offsets as part of their write, i.e. in the same Kafka transaction.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Commits the consumer's current position with `commit_consumer_state(CommitMode::Sync)`
+    ///
+    /// # Errors
+    ///
+    /// - KafkaError: If committing fails
+    async fn commit_offsets(&mut self, consumer: &RedpandaConsumer) -> Result<(), Self::Error> {
+        consumer.consumer.commit_consumer_state(CommitMode::Sync)?;
+        Ok(())
+    }
+
+    /// Consume the sink's topic into the sink in batches, committing offsets after each written batch
+    ///
+    /// Offsets are only committed (with `commit_offsets`) once `sink_batch` succeeds, so a crash replays at most the
+    /// batch in flight. The
+    /// consumer should be built with `enable.auto.commit=false` and a consumer group dedicated to this sink. A partial
+    /// batch left when the stream ends is written and committed too.
+    ///
+    /// Messages that fail to deserialize are skipped with a WARN. Batches are written with `sink_records`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Override only to change how batches are accumulated (i.e. to flush on a timer as well as on size)
+    ///
+    /// # Errors
+    ///
+    /// - KafkaError: If subscribing or reading from the consumer fails
+    /// - Any error returned by `sink_batch` or `commit_offsets`
+    async fn run(mut self, consumer: RedpandaConsumer) -> Result<(), Self::Error> {
+        consumer.subscribe(&[self.topic()])?;
+
+        let batch_size = self.batch_size().max(1);
+        let mut batch: Vec<(Self::Measurement, measurement::RecordMeta)> =
+            Vec::with_capacity(batch_size);
+        let mut stream = consumer.stream();
+
+        while let Some(message) = stream.next().await {
+            match <Self::Measurement as measurement::Measurement>::from_message_with_meta(message?) {
+                Ok(record) => batch.push(record),
+                Err(e) => {
+                    event!(
+                        Level::WARN,
+                        "Failed to deserialize measurement, continuing to next message. {}",
+                        e
+                    );
+                    continue;
+                }
+            }
+
+            if batch.len() >= batch_size {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                self.sink_records(full).await?;
+                self.commit_offsets(&consumer).await?;
+            }
+        }
+
+        if !batch.is_empty() {
+            self.sink_records(batch).await?;
+            self.commit_offsets(&consumer).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,59 +1,529 @@
 //! Measurement trait for raw sensor measurements and derived data streams
 
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 
 use chrono::{DateTime, LocalResult, TimeZone, Utc};
 use flatbuffers::FlatBufferBuilder;
 use futures_core::Stream;
 use redpanda::{
-    message::{BorrowedMessage, Message},
+    message::{BorrowedMessage, Header, Headers, Message, OwnedHeaders, Timestamp},
     producer::RedpandaRecord,
 };
+use tracing::{event, Level};
 
 /// Convert nanoseconds since unix epoch (in UTC) to a UTC datetime
 pub fn nanos_to_date_time(unix_ns: i64) -> LocalResult<DateTime<Utc>> {
     Utc.timestamp_opt(unix_ns / 1_000_000_000, (unix_ns % 1_000_000_000) as u32)
 }
 
+thread_local! {
+    /// Builder reused by `to_bytes_pooled` on each thread
+    static POOLED_BUILDER: RefCell<FlatBufferBuilder<'static>> = RefCell::new(FlatBufferBuilder::new());
+}
+
+/// Serialize a Measurement like `Measurement::to_bytes`, with its codec's `MeasurementCodec::encode_pooled`
+///
+/// For `FlatBufferCodec` measurements this reuses a thread local FlatBufferBuilder. The builder is reset rather than
+/// reallocated, so in a hot loop its buffer is only allocated once per thread and grown to fit the largest
+/// measurement serialized on that thread. The only allocation left per measurement is the returned Vec.
+pub fn to_bytes_pooled<M>(measurement: M) -> Vec<u8>
+where
+    M: Measurement<'static>,
+{
+    M::Codec::encode_pooled(measurement)
+}
+
+/// How a Measurement is encoded to and decoded from bytes, chosen with `Measurement::Codec`
+///
+/// `FlatBufferCodec` encodes measurements as FlatBuffers. Implement this to encode them with something else, i.e.
+/// Protobuf or Cap'n Proto.
+pub trait MeasurementCodec<M> {
+    /// Error decoding fails with, the Measurement's own `Measurement::Error`
+    type Error;
+
+    /// Encode a Measurement into bytes, suitable for network transfer, consuming the Measurement
+    fn encode(measurement: M) -> Vec<u8>;
+
+    /// Decode a Measurement from bytes written by `encode`
+    fn decode(bytes: &[u8]) -> Result<M, Self::Error>;
+
+    /// Encode a Measurement like `encode`, reusing this thread's buffers if the codec has any (see `to_bytes_pooled`)
+    ///
+    /// ## Default Implementation
+    ///
+    /// Calls `encode`
+    fn encode_pooled(measurement: M) -> Vec<u8> {
+        Self::encode(measurement)
+    }
+}
+
+/// A Measurement encoded as a flatbuffer by `FlatBufferCodec`
+pub trait FlatBufferMeasurement: Sized {
+    /// Error reading the flatbuffer fails with, the Measurement's own `Measurement::Error`
+    type Error;
+
+    /// Serialize a Measurement into an existing FlatBufferBuilder, consuming the Measurement
+    ///
+    /// `fbb` has been reset and must hold the finished flatbuffer when this returns. Build straight into `fbb` (and
+    /// never replace it) so `to_bytes_pooled` reuses its buffer.
+    fn serialize_into(self, fbb: &mut FlatBufferBuilder);
+
+    /// Deserialize a Measurement from a flatbuffer built by `serialize_into`
+    fn from_flatbuffer(bytes: &[u8]) -> Result<Self, Self::Error>;
+}
+
+/// `MeasurementCodec` for FlatBuffers measurements, see `FlatBufferMeasurement`
+///
+/// Encodes with `FlatBufferMeasurement::serialize_into`, into a new builder or with `encode_pooled` into this
+/// thread's pooled builder, and decodes with `FlatBufferMeasurement::from_flatbuffer`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlatBufferCodec;
+
+impl<M> MeasurementCodec<M> for FlatBufferCodec
+where
+    M: FlatBufferMeasurement,
+{
+    type Error = M::Error;
+
+    fn encode(measurement: M) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+        measurement.serialize_into(&mut fbb);
+
+        fbb.finished_data().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<M, Self::Error> {
+        M::from_flatbuffer(bytes)
+    }
+
+    fn encode_pooled(measurement: M) -> Vec<u8> {
+        POOLED_BUILDER.with(|fbb| match fbb.try_borrow_mut() {
+            Ok(mut fbb) => {
+                fbb.reset();
+                measurement.serialize_into(&mut fbb);
+                fbb.finished_data().to_vec()
+            }
+            // serialize_into is already using this thread's builder, i.e. for a nested measurement
+            Err(_) => Self::encode(measurement),
+        })
+    }
+}
+
+/// Serialize a Measurement to a Kafka message like the default `Measurement::to_message`, using `to_bytes_pooled`
+///
+/// Measurements that override `Measurement::to_message` should not use this.
+pub fn to_message_pooled<M>(measurement: M) -> RedpandaRecord
+where
+    M: Measurement<'static>,
+{
+    let key = measurement.message_key();
+    let version = measurement.record_schema_version().to_string();
+    let payload = to_bytes_pooled(measurement);
+    let headers = OwnedHeaders::new().insert(Header {
+        key: SCHEMA_VERSION_HEADER,
+        value: Some(version.as_str()),
+    });
+    RedpandaRecord::new(M::TOPIC_NAME, key, payload, Some(headers))
+}
+
+/// Kafka header recording the `Measurement::SCHEMA_VERSION` a measurement was serialized with, as a decimal string
+pub const SCHEMA_VERSION_HEADER: &str = "schema-version";
+
+/// Schema version recorded in a consumed message's `SCHEMA_VERSION_HEADER` header
+///
+/// None if the message has no such header (i.e. it was produced before schema versions were recorded) or the
+/// header isn't a version number.
+pub fn schema_version(message: &BorrowedMessage) -> Option<u32> {
+    let headers = message.headers()?;
+    headers
+        .iter()
+        .find(|header| header.key == SCHEMA_VERSION_HEADER)
+        .and_then(|header| std::str::from_utf8(header.value?).ok())
+        .and_then(|version| version.parse().ok())
+}
+
+/// Deserialize and validate a Measurement serialized with schema `version`, like the default
+/// `Measurement::from_message`
+///
+/// Bytes from the current `Measurement::SCHEMA_VERSION`, or with no known version, are read with
+/// `Measurement::from_bytes`, which validates them. Bytes from any other version are passed to `Measurement::migrate`
+/// and then checked with `Measurement::validate`.
+pub fn from_bytes_versioned<'a, M>(bytes: &[u8], version: Option<u32>) -> Result<M, M::Error>
+where
+    M: Measurement<'a>,
+{
+    match version {
+        Some(version) if version != M::SCHEMA_VERSION => {
+            let measurement = M::migrate(bytes, version)?;
+            measurement.validate()?;
+            Ok(measurement)
+        }
+        _ => M::from_bytes(bytes),
+    }
+}
+
+/// Kafka header recording which clock a measurement's timestamp came from (see `TimestampClock`)
+pub const TIMESTAMP_SOURCE_HEADER: &str = "opensensor-timestamp-source";
+
+/// Skew beyond which `TimestampSource::ProducerIfSkewed` uses the producer clock when none is given
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(1);
+
+/// Which clock a Sensor stamps its measurements with
+///
+/// Measurements usually carry the sensor hardware's timestamp, but hardware clocks in the field are often not
+/// synchronized. Operators can choose to trust the producer host's (NTP synchronized) clock instead, always or only
+/// when the sensor clock has drifted too far from it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// Keep the sensor's own timestamp
+    #[default]
+    Sensor,
+    /// Replace the sensor's timestamp with the producer host's clock
+    Producer,
+    /// Keep the sensor's timestamp unless it's more than this far from the producer host's clock
+    ProducerIfSkewed(Duration),
+}
+
+impl TimestampSource {
+    /// Clock to stamp a measurement with, given its sensor timestamp and the producer clock's current time
+    pub fn select(&self, sensor: DateTime<Utc>, producer: DateTime<Utc>) -> TimestampClock {
+        match self {
+            TimestampSource::Sensor => TimestampClock::Sensor,
+            TimestampSource::Producer => TimestampClock::Producer,
+            TimestampSource::ProducerIfSkewed(max_skew) => {
+                let skew = (producer.max(sensor) - producer.min(sensor))
+                    .to_std()
+                    .unwrap_or(Duration::MAX);
+                if skew > *max_skew {
+                    TimestampClock::Producer
+                } else {
+                    TimestampClock::Sensor
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for TimestampSource {
+    type Err = String;
+
+    /// Parse `sensor`, `producer`, or `producer_if_skewed`, optionally with a max skew (i.e.
+    /// `producer_if_skewed=500ms`, defaulting to `DEFAULT_MAX_CLOCK_SKEW`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None => match s {
+                "sensor" => Ok(TimestampSource::Sensor),
+                "producer" => Ok(TimestampSource::Producer),
+                "producer_if_skewed" => {
+                    Ok(TimestampSource::ProducerIfSkewed(DEFAULT_MAX_CLOCK_SKEW))
+                }
+                _ => Err(format!(
+                    "unknown timestamp source {}, expected sensor, producer, or producer_if_skewed",
+                    s
+                )),
+            },
+            Some(("producer_if_skewed", max_skew)) => humantime::parse_duration(max_skew)
+                .map(TimestampSource::ProducerIfSkewed)
+                .map_err(|e| format!("invalid max clock skew {}: {}", max_skew, e)),
+            Some(_) => Err(format!(
+                "only producer_if_skewed takes a max clock skew, not {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Clock a measurement's timestamp was taken from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampClock {
+    /// The sensor hardware's clock
+    Sensor,
+    /// The producer host's clock
+    Producer,
+}
+
+impl TimestampClock {
+    /// Value of the `TIMESTAMP_SOURCE_HEADER` header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimestampClock::Sensor => "sensor",
+            TimestampClock::Producer => "producer",
+        }
+    }
+
+    /// Clock recorded in a consumed message's `TIMESTAMP_SOURCE_HEADER` header, None if it has no such header
+    pub fn from_message(message: &BorrowedMessage) -> Option<Self> {
+        let headers = message.headers()?;
+        headers
+            .iter()
+            .find(|header| header.key == TIMESTAMP_SOURCE_HEADER)
+            .and_then(|header| match header.value? {
+                b"sensor" => Some(TimestampClock::Sensor),
+                b"producer" => Some(TimestampClock::Producer),
+                _ => None,
+            })
+    }
+}
+
+impl fmt::Display for TimestampClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Stamp a Measurement with the clock `source` selects, returning the clock its timestamp now comes from
+///
+/// Measurements that don't override `Measurement::set_timestamp` keep their sensor timestamp.
+pub fn stamp_timestamp<'a, M>(measurement: &mut M, source: TimestampSource) -> TimestampClock
+where
+    M: Measurement<'a>,
+{
+    let now = Utc::now();
+    match source.select(measurement.timestamp(), now) {
+        TimestampClock::Producer if measurement.set_timestamp(now) => TimestampClock::Producer,
+        _ => TimestampClock::Sensor,
+    }
+}
+
+/// Serialize a Measurement to a Kafka message like `to_message_pooled`, stamped with the clock `source` selects
+///
+/// The clock used is recorded in the `TIMESTAMP_SOURCE_HEADER` header (alongside `SCHEMA_VERSION_HEADER`), so
+/// consumers can tell measurements stamped by the sensor from those stamped by the producer. Measurements that
+/// override `Measurement::to_message` should not use this.
+pub fn to_message_stamped<M>(mut measurement: M, source: TimestampSource) -> RedpandaRecord
+where
+    M: Measurement<'static>,
+{
+    let clock = stamp_timestamp(&mut measurement, source);
+    let version = measurement.record_schema_version().to_string();
+    let headers = OwnedHeaders::new()
+        .insert(Header {
+            key: TIMESTAMP_SOURCE_HEADER,
+            value: Some(clock.as_str()),
+        })
+        .insert(Header {
+            key: SCHEMA_VERSION_HEADER,
+            value: Some(version.as_str()),
+        });
+    let key = measurement.message_key();
+    let payload = to_bytes_pooled(measurement);
+    RedpandaRecord::new(M::TOPIC_NAME, key, payload, Some(headers))
+}
+
+/// What the default `Measurement::from_message` does with a measurement whose timestamp is further than
+/// `Measurement::MAX_CLOCK_SKEW` from its Kafka record's
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClockSkewAction {
+    /// Return the error from `MeasurementError::clock_skew_error`
+    #[default]
+    Reject,
+    /// Log a warning and keep the measurement
+    Warn,
+}
+
+/// A measurement timestamped further from the Kafka record it arrived in than `Measurement::MAX_CLOCK_SKEW` allows,
+/// i.e. by a device whose clock isn't synchronized
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// The measurement's `timestamp`
+    pub measured: DateTime<Utc>,
+    /// The record's timestamp: its log append time, or its create time on topics that keep that instead
+    pub record: DateTime<Utc>,
+    /// The `MAX_CLOCK_SKEW` it exceeded
+    pub max_skew: Duration,
+}
+
+impl ClockSkew {
+    /// How far the measurement's timestamp is from the record's, in either direction
+    pub fn skew(&self) -> Duration {
+        (self.record.max(self.measured) - self.record.min(self.measured))
+            .to_std()
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl fmt::Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "measurement timestamp {} is {:?} from its record timestamp {}, more than the max clock skew {:?}",
+            self.measured,
+            self.skew(),
+            self.record,
+            self.max_skew
+        )
+    }
+}
+
+/// Check a measurement's timestamp is within `M::MAX_CLOCK_SKEW` of its Kafka record's timestamp, as the default
+/// `Measurement::from_message` does
+///
+/// Measurements without a `MAX_CLOCK_SKEW`, and records without a timestamp, always pass. With
+/// `ClockSkewAction::Warn` a skewed measurement is logged and passes.
+///
+/// # Errors
+///
+/// - M::Error: From `MeasurementError::clock_skew_error`, if the skew is over `MAX_CLOCK_SKEW` and
+///   `M::CLOCK_SKEW_ACTION` is `ClockSkewAction::Reject`
+pub fn check_clock_skew<'a, M>(measurement: &M, record_timestamp: Timestamp) -> Result<(), M::Error>
+where
+    M: Measurement<'a>,
+{
+    let max_skew = match M::MAX_CLOCK_SKEW {
+        Some(max_skew) => max_skew,
+        None => return Ok(()),
+    };
+    let record = match record_timestamp
+        .to_millis()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+    {
+        Some(record) => record,
+        None => return Ok(()),
+    };
+
+    let skew = ClockSkew {
+        measured: measurement.timestamp(),
+        record,
+        max_skew,
+    };
+    if skew.skew() <= max_skew {
+        return Ok(());
+    }
+    match M::CLOCK_SKEW_ACTION {
+        ClockSkewAction::Reject => Err(M::Error::clock_skew_error(skew)),
+        ClockSkewAction::Warn => {
+            event!(
+                Level::WARN,
+                source_id = measurement.source_id(),
+                topic = M::TOPIC_NAME,
+                "{}",
+                skew
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Where a consumed Measurement came from: its Kafka record's topic, partition, offset, timestamp, and key
+///
+/// Returned alongside the measurement by `Measurement::from_message_with_meta`, for sinks to track offsets and
+/// detect late data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordMeta {
+    /// Topic the record was consumed from
+    pub topic: String,
+    /// Partition the record was consumed from
+    pub partition: i32,
+    /// Offset of the record within its partition
+    pub offset: i64,
+    /// Record timestamp, set by the producer (`Timestamp::CreateTime`) or the broker (`Timestamp::LogAppendTime`)
+    pub timestamp: Timestamp,
+    /// Record key, None if the record was produced without one
+    pub key: Option<Vec<u8>>,
+}
+
+impl RecordMeta {
+    /// Metadata of a consumed message
+    pub fn from_message<M>(message: &M) -> Self
+    where
+        M: Message,
+    {
+        RecordMeta {
+            topic: message.topic().to_owned(),
+            partition: message.partition(),
+            offset: message.offset(),
+            timestamp: message.timestamp(),
+            key: message.key().map(<[u8]>::to_vec),
+        }
+    }
+
+    /// Record timestamp in UTC, None if the record has no timestamp
+    ///
+    /// This is when the record was produced or appended, not `Measurement::timestamp`.
+    pub fn timestamp_utc(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+            .to_millis()
+            .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+    }
+}
+
 /// Measurement error
 ///
 /// Enforce that this can only be implemented for errors with the std::error::Error trait bound
 ///
 /// Since there is no way to enforce that an enum contains a variant, this trait requires the enum to return
-/// its empty payload error variant
+/// its empty payload, version mismatch, and clock skew error variants
 pub trait MeasurementError: Error {
     /// Return the empty payload variant here
     fn empty_payload_error() -> Self;
+
+    /// Return the variant for a message serialized with a schema version the Measurement can't read
+    ///
+    /// `expected` is the Measurement's `SCHEMA_VERSION` and `found` is the version the message was serialized with.
+    /// Returned by the default `Measurement::migrate`.
+    fn version_mismatch_error(expected: u32, found: u32) -> Self
+    where
+        Self: Sized;
+
+    /// Return the variant for a measurement whose clock is skewed more than `Measurement::MAX_CLOCK_SKEW` from its
+    /// Kafka record
+    ///
+    /// Returned by the default `Measurement::from_message` with `ClockSkewAction::Reject`.
+    fn clock_skew_error(skew: ClockSkew) -> Self
+    where
+        Self: Sized;
 }
 
 /// Raw measurement from a Sensor or derived data from a computation (i.e. tracking algorithm or ML model)
 ///
 /// ## Implementing
 ///
-/// Satisfy the trait boundary `Into<FlatBufferBuilder<'a>>` by implementing
-/// `From<YourMeasurementStruct> for for FlatBufferBuilder<'_>`
+/// Choose how the Measurement is encoded with `Codec`. Measurements encoded as FlatBuffers use `FlatBufferCodec` and
+/// implement `FlatBufferMeasurement` to build and read their flatbuffer. To encode with something else, i.e. Protobuf
+/// or Cap'n Proto, implement `MeasurementCodec` and use that.
 ///
 /// ### Implementers are responsible for
 ///
 /// - `Error` : Error type used in the Measurement's constructor and field validation methods
+/// - `Codec` : How to encode your Measurement to bytes and decode it back, usually `FlatBufferCodec`
 /// - `TOPIC_NAME` : Topic to store this measurement to in Redpanda
-/// - `from_bytes` : How to deserialize from bytes to your Measurement
 /// - `timestamp` : Return your Measurement's internal representation of the UTC time is was measured
-/// - `Into<FlatBufferBuilder<'a>> : How to serialize your Measurement to a Flatbuffer
+/// - `source_id` : Return the sensor or algorithm the Measurement came from
 ///
 /// ### Default implementations are provided for
 ///
 /// - `to_bytes`
+/// - `from_bytes`
+/// - `SCHEMA_VERSION`
+/// - `record_schema_version`
 /// - `to_message`
+/// - `message_key`
+/// - `partition_key`
+/// - `content_eq`
 /// - `from_message`
+/// - `migrate`
+/// - `validate`
 /// - `timestamp_nanos`
-pub trait Measurement<'a>: Into<FlatBufferBuilder<'a>> {
+/// - `partition_timestamp`
+/// - `sort_key`
+/// - `to_json` and `from_json`, with the `json` feature
+pub trait Measurement<'a>: Sized {
     /// Associated type for the measurement's specific error
     ///
     /// We need this to be separate from the MeasurementError defined above because there are measurement-specific
     /// field validations that need to be applied & can't be expressed adequately in a generic MeasurementError type
     type Error: MeasurementError;
 
+    /// How the Measurement is encoded to and decoded from bytes
+    ///
+    /// `FlatBufferCodec` for measurements that implement `FlatBufferMeasurement`, or a `MeasurementCodec` of your own.
+    type Codec: MeasurementCodec<Self, Error = Self::Error>;
+
     /// Topic to produce this measurement to
     ///
     /// OpenSensor follows the following Kafka topic naming convention:
@@ -83,16 +553,52 @@ pub trait Measurement<'a>: Into<FlatBufferBuilder<'a>> {
     /// - https://www.conduktor.io/kafka/kafka-topics-naming-convention
     const TOPIC_NAME: &'static str;
 
+    /// Version of the Measurement's serialized (flatbuffer) layout
+    ///
+    /// Bump this whenever the layout changes in a way old readers can't handle, i.e. when sensor firmware changes
+    /// what's measured. `to_message` records it in the `SCHEMA_VERSION_HEADER` header, and `from_message` hands
+    /// messages from any other version to `migrate`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Version 1
+    const SCHEMA_VERSION: u32 = 1;
+
+    /// Furthest a measurement's `timestamp` may be from its Kafka record's timestamp, None to not check
+    ///
+    /// The default `from_message` compares the two (see `check_clock_skew`), catching devices with unsynchronized
+    /// clocks at ingestion before their wildly wrong timestamps poison time-windowed aggregations. Leave room for
+    /// the time measurements spend buffered in the Sensor and producer.
+    ///
+    /// ## Default Implementation
+    ///
+    /// None, timestamps aren't checked
+    const MAX_CLOCK_SKEW: Option<Duration> = None;
+
+    /// What the default `from_message` does with a measurement skewed more than `MAX_CLOCK_SKEW`
+    ///
+    /// ## Default Implementation
+    ///
+    /// `ClockSkewAction::Reject`
+    const CLOCK_SKEW_ACTION: ClockSkewAction = ClockSkewAction::Reject;
+
     /// Serialize a Measurement into a vec of bytes, suitable for network transfer, consuming the Measurement
     ///
     /// ## Default Implementation
     ///
-    /// Notionally, this should be using FlatBuffers, but technically this isn't specific
-    /// and it's probably better to avoid being overly proscriptive.
+    /// Encodes with `Codec`
     fn to_bytes(self) -> Vec<u8> {
-        let fbb: FlatBufferBuilder = self.into();
+        Self::Codec::encode(self)
+    }
 
-        fbb.finished_data().to_vec()
+    /// Schema version recorded in the `SCHEMA_VERSION_HEADER` header of this Measurement's Kafka record
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns `SCHEMA_VERSION`. `impl_measurement_enum!` returns the wrapped measurement's, since each variant is
+    /// versioned separately.
+    fn record_schema_version(&self) -> u32 {
+        Self::SCHEMA_VERSION
     }
 
     /// Serialize a Measurement to a Kafka message
@@ -102,21 +608,101 @@ pub trait Measurement<'a>: Into<FlatBufferBuilder<'a>> {
     /// This default implementation can be overridden if a specific measurement needs different Kafka
     /// message serialization semantics. If you override Measurement::to_message, you MUST also override the
     /// Measurement::from_message method. Otherwise your custom message serialization won't be undone correctly.
+    ///
+    /// The record is keyed by `message_key` (by default, the `partition_key`), so every measurement with the same
+    /// partition key lands on the same partition, and `record_schema_version` is recorded in the
+    /// `SCHEMA_VERSION_HEADER` header.
     fn to_message(self) -> RedpandaRecord
     where
         Self: Sized,
     {
+        let key = self.message_key();
+        let version = self.record_schema_version().to_string();
         let payload: Vec<u8> = self.to_bytes();
-        RedpandaRecord::new(Self::TOPIC_NAME, None, payload, None)
+        let headers = OwnedHeaders::new().insert(Header {
+            key: SCHEMA_VERSION_HEADER,
+            value: Some(version.as_str()),
+        });
+        RedpandaRecord::new(Self::TOPIC_NAME, key, payload, Some(headers))
     }
 
-    /// Deserialize a Measurement from a vec of bytes off the network
+    /// Key for the Kafka record wrapping this Measurement
+    ///
+    /// Records with the same key hash to the same partition, which is what keeps a source's measurements ordered.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns the `partition_key` bytes. Override `partition_key` to partition by something else, or this to
+    /// return None and spread records across partitions.
+    fn message_key(&self) -> Option<Vec<u8>> {
+        Some(self.partition_key().as_bytes().to_vec())
+    }
+
+    /// What the Kafka records wrapping this Measurement are partitioned by, i.e. a spatial cell or a vessel's MMSI
+    ///
+    /// Measurements are only ordered relative to others with the same partition key. This is independent of
+    /// `source_id`, so a deployment can change how it partitions without changing how sources are identified.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns the `source_id`, so measurements from the same physical sensor stay ordered
+    fn partition_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.source_id())
+    }
+
+    /// Whether this Measurement carries the same state as `other`, ignoring when it was measured
+    ///
+    /// The archiver's `--compact-equal-runs` drops a Measurement that's `content_eq` to the last one kept with the
+    /// same `partition_key`, so override this for state topics (i.e. comparing every field but the timestamp).
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns false, so nothing is compacted
+    fn content_eq(&self, _other: &Self) -> bool {
+        false
+    }
+
+    /// Serialize a Measurement to JSON for debugging and interop with tools that don't speak FlatBuffers
     ///
-    /// Notionally, this should be implemented using the FlatBuffers to read a struct
-    /// from serialized data
-    fn from_bytes(bytes: &[u8]) -> Result<Self, Self::Error>
+    /// Not meant for the hot path, which should keep using `to_bytes`/`to_message`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Serializes with the Measurement's `serde::Serialize` implementation
+    #[cfg(feature = "json")]
+    fn to_json(&self) -> Result<String, serde_json::Error>
     where
-        Self: Sized;
+        Self: serde::Serialize,
+    {
+        serde_json::to_string(self)
+    }
+
+    /// Deserialize a Measurement from JSON written by `to_json`
+    ///
+    /// ## Default Implementation
+    ///
+    /// Deserializes with the Measurement's `serde::Deserialize` implementation, converting JSON errors into the
+    /// Measurement's error type
+    #[cfg(feature = "json")]
+    fn from_json(s: &str) -> Result<Self, Self::Error>
+    where
+        Self: Sized + serde::de::DeserializeOwned,
+        Self::Error: From<serde_json::Error>,
+    {
+        serde_json::from_str(s).map_err(Self::Error::from)
+    }
+
+    /// Deserialize a Measurement from a vec of bytes off the network
+    ///
+    /// ## Default Implementation
+    ///
+    /// Decodes with `Codec`, then checks the result with `validate`. Overrides should validate too, since archive
+    /// reads and `from_bytes_versioned` rely on it.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let measurement = Self::Codec::decode(bytes)?;
+        measurement.validate()?;
+        Ok(measurement)
+    }
 
     /// Deserialize a Measurement from a Kafka message
     ///
@@ -133,6 +719,11 @@ pub trait Measurement<'a>: Into<FlatBufferBuilder<'a>> {
     /// message headers vs in the payload if they choose to implement a message-specific
     /// version of this method. We only care that you can deserialize a
     /// Measurement from a kafka message, not the specifics of how.
+    ///
+    /// Messages whose `SCHEMA_VERSION_HEADER` header doesn't match `SCHEMA_VERSION` are deserialized with `migrate`
+    /// (see `from_bytes_versioned`). Messages without the header are assumed to be the current version. The
+    /// deserialized measurement is checked with `validate`, so invalid measurements are rejected here, and its
+    /// timestamp against the record's with `check_clock_skew` if `MAX_CLOCK_SKEW` is set.
     fn from_message(message: BorrowedMessage) -> Result<Self, Self::Error>
     where
         Self: Sized,
@@ -142,7 +733,60 @@ pub trait Measurement<'a>: Into<FlatBufferBuilder<'a>> {
             None => return Err(Self::Error::empty_payload_error()),
         };
 
-        Self::from_bytes(bytes)
+        let measurement = from_bytes_versioned(bytes, schema_version(&message))?;
+        check_clock_skew(&measurement, message.timestamp())?;
+        Ok(measurement)
+    }
+
+    /// Deserialize a Measurement from a Kafka message, along with the record's topic, partition, offset, timestamp,
+    /// and key
+    ///
+    /// `from_message` only keeps the payload and headers. Sinks need where each measurement came from for offset
+    /// bookkeeping and late data detection.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Reads the metadata with `RecordMeta::from_message`, then deserializes with `from_message`, so measurements
+    /// that override `from_message` don't need to override this too.
+    fn from_message_with_meta(message: BorrowedMessage) -> Result<(Self, RecordMeta), Self::Error>
+    where
+        Self: Sized,
+    {
+        let meta = RecordMeta::from_message(&message);
+        let measurement = Self::from_message(message)?;
+        Ok((measurement, meta))
+    }
+
+    /// Deserialize a Measurement from bytes serialized with an older (or newer) `SCHEMA_VERSION`
+    ///
+    /// Override this to read previous layouts, i.e. by parsing `bytes` with the readers generated for
+    /// `from_version`'s schema and filling in fields that didn't exist yet.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns the error from `MeasurementError::version_mismatch_error`
+    fn migrate(_bytes: &[u8], from_version: u32) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Err(Self::Error::version_mismatch_error(
+            Self::SCHEMA_VERSION,
+            from_version,
+        ))
+    }
+
+    /// Check the measurement's fields are valid, i.e. angles and ranges within their physical limits
+    ///
+    /// Called after deserializing by the default `from_bytes` (and so by `from_message`, `from_bytes_versioned` and
+    /// archive reads) and after `migrate`, so invalid measurements are rejected at the boundary instead of
+    /// propagating bad data downstream. Return
+    /// the Measurement's own error type, i.e. a variant naming the field and value out of range.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Accepts every measurement
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
     }
 
     /// Getter for the measurement's timestamp in UTC
@@ -171,12 +815,69 @@ pub trait Measurement<'a>: Into<FlatBufferBuilder<'a>> {
         self.timestamp().timestamp_nanos()
     }
 
+    /// Replace the measurement's timestamp, returning whether it was replaced
+    ///
+    /// Used by `stamp_timestamp` to stamp measurements with the producer clock (see `TimestampSource`).
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns false, the measurement always keeps its sensor timestamp. Override this to support
+    /// `TimestampSource::Producer` and `TimestampSource::ProducerIfSkewed`.
+    fn set_timestamp(&mut self, _timestamp: DateTime<Utc>) -> bool {
+        false
+    }
+
+    /// Getter for the time used to partition the measurement in archives
+    ///
+    /// Some measurements carry more than one timestamp (i.e. the time an AIS report was received vs the event time
+    /// reported inside it). Override this to archive by a time other than `timestamp`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns `timestamp`
+    fn partition_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp()
+    }
+
+    /// Getter for the key the archiver sorts chunks by with `--sort-chunk-by sort-key`
+    ///
+    /// Override this to group measurements by a different low-cardinality field (i.e. an AIS message type).
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns the `source_id` then `timestamp_nanos`
+    fn sort_key(&self) -> SortKey {
+        SortKey::new(self.source_id(), self.timestamp_nanos())
+    }
+
     /// Getter for the identify of the sensor or algorithm source that generated the measurement
     ///
     /// The source_id is an identifier that specifies what sensor the measurement was read from
     fn source_id(&self) -> &str;
 }
 
+/// Key measurements are ordered by within a sorted archive chunk, see `Measurement::sort_key`
+///
+/// Orders by `group` then `timestamp_ns`. Grouping by a low-cardinality field puts runs of similar measurements next
+/// to each other, which zstd compresses much better than interleaved consumption order.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey {
+    /// Low-cardinality field to group measurements by, i.e. the `source_id`
+    pub group: String,
+    /// Order of measurements within a group, in UTC nanoseconds since Unix epoch
+    pub timestamp_ns: i64,
+}
+
+impl SortKey {
+    /// Sort key grouping by `group`, then ordering by `timestamp_ns`
+    pub fn new(group: impl Into<String>, timestamp_ns: i64) -> Self {
+        SortKey {
+            group: group.into(),
+            timestamp_ns,
+        }
+    }
+}
+
 /// Steam of sensor measurements, either from raw or derived data
 pub trait MeasurementStream {
     /// Type of the individual sensor measurement in the stream
@@ -185,3 +886,229 @@ pub trait MeasurementStream {
     /// A measurement stream from a sensor or derived data stream
     fn stream(&self) -> dyn Stream<Item = Self::Item>;
 }
+
+/// Payload of an `impl_measurement_enum!` measurement whose leading variant tag isn't one of the enum's
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownVariant {
+    /// Name of the enum being deserialized
+    pub measurement: &'static str,
+    /// The tag that matched none of its variants
+    pub tag: u8,
+}
+
+impl fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} has no variant tagged {}", self.measurement, self.tag)
+    }
+}
+
+impl Error for UnknownVariant {}
+
+/// Implement `Measurement` for an enum whose variants each wrap a Measurement, i.e. one variant per AIS message type
+///
+/// Generates a `Measurement` implementation that forwards every method to the wrapped measurement, with the enum as
+/// its own `MeasurementCodec`. The enum's `Error` must implement `From` for each variant's error and for
+/// `UnknownVariant`.
+///
+/// Each variant is given a tag, which the codec writes as a leading byte before the wrapped measurement's bytes and
+/// dispatches on to decode them, so variants with compatible layouts can't be mistaken for each other. Bytes with a
+/// tag none of the variants have return `UnknownVariant`. Tags are part of the serialized format: never reuse or
+/// change one once measurements have been written with it.
+///
+/// `to_message` produces each variant to its own `TOPIC_NAME`, recording the variant's `SCHEMA_VERSION` (see
+/// `Measurement::record_schema_version`), and `from_message` and `migrate` read the variant with its own `migrate`,
+/// `validate`, and clock skew check. Versions are per variant, so the enum's `SCHEMA_VERSION` is 0, which no
+/// record is written with.
+///
+/// # Examples
+///
+/// ```no_run
+/// pub enum AisMeasurement {
+///     PositionReport(PositionReport),
+///     StaticData(StaticData),
+/// }
+///
+/// impl_measurement_enum!(AisMeasurement, topic = "raw.surface.ais", error = AisError, {
+///     PositionReport(PositionReport) = 1,
+///     StaticData(StaticData) = 5,
+/// });
+/// ```
+#[macro_export]
+macro_rules! impl_measurement_enum {
+    ($name:ident, topic = $topic:expr, error = $error:ty, { $($variant:ident($inner:ty) = $tag:literal),+ $(,)? }) => {
+        impl $crate::measurement::MeasurementCodec<$name> for $name {
+            type Error = $error;
+
+            fn encode(measurement: $name) -> Vec<u8> {
+                let (tag, bytes): (u8, Vec<u8>) = match measurement {
+                    $($name::$variant(m) => {
+                        ($tag, <$inner as $crate::measurement::Measurement<'static>>::to_bytes(m))
+                    })+
+                };
+                let mut encoded = Vec::with_capacity(bytes.len() + 1);
+                encoded.push(tag);
+                encoded.extend_from_slice(&bytes);
+                encoded
+            }
+
+            fn decode(bytes: &[u8]) -> Result<$name, $error> {
+                match bytes.split_first() {
+                    $(Some((&$tag, bytes)) => {
+                        <$inner as $crate::measurement::Measurement<'static>>::from_bytes(bytes)
+                            .map($name::$variant)
+                            .map_err(Into::into)
+                    })+
+                    Some((&tag, _)) => Err($crate::measurement::UnknownVariant {
+                        measurement: stringify!($name),
+                        tag,
+                    }
+                    .into()),
+                    None => Err(<$error as $crate::measurement::MeasurementError>::empty_payload_error()),
+                }
+            }
+        }
+
+        impl<'a> $crate::measurement::Measurement<'a> for $name {
+            type Error = $error;
+            type Codec = $name;
+
+            const TOPIC_NAME: &'static str = $topic;
+
+            const SCHEMA_VERSION: u32 = 0;
+
+            fn record_schema_version(&self) -> u32 {
+                match self {
+                    $($name::$variant(_) => <$inner as $crate::measurement::Measurement<'a>>::SCHEMA_VERSION,)+
+                }
+            }
+
+            fn to_message(self) -> $crate::__private::redpanda::producer::RedpandaRecord {
+                use $crate::__private::redpanda::message::{Header, OwnedHeaders};
+
+                let topic = match &self {
+                    $($name::$variant(_) => <$inner as $crate::measurement::Measurement<'a>>::TOPIC_NAME,)+
+                };
+                let key = self.message_key();
+                let version = self.record_schema_version().to_string();
+                let headers = OwnedHeaders::new().insert(Header {
+                    key: $crate::measurement::SCHEMA_VERSION_HEADER,
+                    value: Some(version.as_str()),
+                });
+                let payload = <Self as $crate::measurement::Measurement<'a>>::to_bytes(self);
+                $crate::__private::redpanda::producer::RedpandaRecord::new(topic, key, payload, Some(headers))
+            }
+
+            fn message_key(&self) -> Option<Vec<u8>> {
+                match self {
+                    $($name::$variant(m) => <$inner as $crate::measurement::Measurement<'a>>::message_key(m),)+
+                }
+            }
+
+            fn partition_key(&self) -> ::std::borrow::Cow<'_, str> {
+                match self {
+                    $($name::$variant(m) => <$inner as $crate::measurement::Measurement<'a>>::partition_key(m),)+
+                }
+            }
+
+            fn content_eq(&self, other: &Self) -> bool {
+                match (self, other) {
+                    $(($name::$variant(m), $name::$variant(o)) => {
+                        <$inner as $crate::measurement::Measurement<'a>>::content_eq(m, o)
+                    })+
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+
+            fn from_message(
+                message: $crate::__private::redpanda::message::BorrowedMessage,
+            ) -> Result<Self, Self::Error> {
+                use $crate::__private::redpanda::message::Message;
+
+                let bytes = match message.payload() {
+                    Some(bytes) => bytes,
+                    None => {
+                        return Err(<$error as $crate::measurement::MeasurementError>::empty_payload_error())
+                    }
+                };
+                let version = $crate::measurement::schema_version(&message);
+                match bytes.split_first() {
+                    $(Some((&$tag, bytes)) => {
+                        let m = $crate::measurement::from_bytes_versioned::<$inner>(bytes, version)?;
+                        $crate::measurement::check_clock_skew(&m, message.timestamp())?;
+                        Ok($name::$variant(m))
+                    })+
+                    Some((&tag, _)) => Err($crate::measurement::UnknownVariant {
+                        measurement: stringify!($name),
+                        tag,
+                    }
+                    .into()),
+                    None => Err(<$error as $crate::measurement::MeasurementError>::empty_payload_error()),
+                }
+            }
+
+            fn migrate(bytes: &[u8], from_version: u32) -> Result<Self, Self::Error> {
+                match bytes.split_first() {
+                    $(Some((&$tag, bytes)) => {
+                        $crate::measurement::from_bytes_versioned::<$inner>(bytes, Some(from_version))
+                            .map($name::$variant)
+                            .map_err(Into::into)
+                    })+
+                    Some((&tag, _)) => Err($crate::measurement::UnknownVariant {
+                        measurement: stringify!($name),
+                        tag,
+                    }
+                    .into()),
+                    None => Err(<$error as $crate::measurement::MeasurementError>::empty_payload_error()),
+                }
+            }
+
+            fn validate(&self) -> Result<(), Self::Error> {
+                match self {
+                    $($name::$variant(m) => {
+                        <$inner as $crate::measurement::Measurement<'a>>::validate(m).map_err(Into::into)
+                    })+
+                }
+            }
+
+            fn timestamp(&self) -> $crate::__private::chrono::DateTime<$crate::__private::chrono::Utc> {
+                match self {
+                    $($name::$variant(m) => <$inner as $crate::measurement::Measurement<'a>>::timestamp(m),)+
+                }
+            }
+
+            fn set_timestamp(
+                &mut self,
+                timestamp: $crate::__private::chrono::DateTime<$crate::__private::chrono::Utc>,
+            ) -> bool {
+                match self {
+                    $($name::$variant(m) => {
+                        <$inner as $crate::measurement::Measurement<'a>>::set_timestamp(m, timestamp)
+                    })+
+                }
+            }
+
+            fn partition_timestamp(
+                &self,
+            ) -> $crate::__private::chrono::DateTime<$crate::__private::chrono::Utc> {
+                match self {
+                    $($name::$variant(m) => {
+                        <$inner as $crate::measurement::Measurement<'a>>::partition_timestamp(m)
+                    })+
+                }
+            }
+
+            fn sort_key(&self) -> $crate::measurement::SortKey {
+                match self {
+                    $($name::$variant(m) => <$inner as $crate::measurement::Measurement<'a>>::sort_key(m),)+
+                }
+            }
+
+            fn source_id(&self) -> &str {
+                match self {
+                    $($name::$variant(m) => <$inner as $crate::measurement::Measurement<'a>>::source_id(m),)+
+                }
+            }
+        }
+    };
+}
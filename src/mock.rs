@@ -0,0 +1,164 @@
+//! Test Sensors without a Redpanda cluster
+//!
+//! `Sensor::produce_measurement` returns the producer's `DeliveryFuture`, which can only come from a real Kafka
+//! producer. `MockProducer` builds one against librdkafka's in-process mock cluster (`test.mock.num.brokers`), so
+//! deliveries still resolve like they would against Redpanda, and records every measurement it produces so tests
+//! can assert on exactly what a Sensor would have published.
+//!
+//! Write the Sensor generic over a `MeasurementProducer`, hold a `RedpandaProducer` in production and a
+//! `MockProducer` in tests, and implement `MockSensor` for the test version to drive it with `collect_n`.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use redpanda::error::KafkaError;
+use redpanda::producer::DeliveryFuture;
+use redpanda::{RedpandaBuilder, RedpandaProducer};
+use tokio::sync::Notify;
+
+use crate::error::SensorError;
+use crate::measurement::{to_message_pooled, Measurement};
+use crate::sensor::Sensor;
+
+/// Producer a Sensor sends its measurements with
+pub trait MeasurementProducer<M> {
+    /// Queue `measurement` for delivery, returning its delivery future
+    ///
+    /// # Errors
+    ///
+    /// - KafkaError: If the measurement couldn't be queued, i.e. the producer queue is full
+    fn produce(&self, measurement: M) -> Result<DeliveryFuture, KafkaError>;
+}
+
+impl<M> MeasurementProducer<M> for RedpandaProducer
+where
+    M: Measurement<'static>,
+{
+    /// Produce the measurement with `to_message_pooled`
+    fn produce(&self, measurement: M) -> Result<DeliveryFuture, KafkaError> {
+        self.send_result(&to_message_pooled(measurement))
+            .map_err(|(e, _)| e)
+    }
+}
+
+/// Producer that records every measurement produced with it, backed by librdkafka's in-process mock cluster
+///
+/// Cheap to clone, and every clone shares the same recorded measurements, so give one clone to the Sensor and keep
+/// another to assert on.
+///
+/// # Examples
+///
+/// ```no_run
+/// let producer = MockProducer::<RadarMeasurement2d>::new()?;
+/// let sensor = RadarSensor::new(transducer, producer.clone());
+/// let measurements = collect_n(sensor, 10).await?;
+/// assert_eq!(measurements.len(), 10);
+/// ```
+pub struct MockProducer<M> {
+    producer: RedpandaProducer,
+    produced: Arc<Mutex<Vec<M>>>,
+    notify: Arc<Notify>,
+}
+
+impl<M> Clone for MockProducer<M> {
+    fn clone(&self) -> Self {
+        MockProducer {
+            producer: self.producer.clone(),
+            produced: self.produced.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+}
+
+impl<M> MockProducer<M> {
+    /// Producer connected to a new single broker mock cluster
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::KafkaError: If the mock cluster or producer can't be created
+    pub fn new() -> Result<Self, SensorError> {
+        let mut builder = RedpandaBuilder::default();
+        builder.set("test.mock.num.brokers", "1");
+
+        Ok(MockProducer {
+            producer: builder.build_producer().map_err(SensorError::KafkaError)?,
+            produced: Arc::new(Mutex::new(Vec::new())),
+            notify: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Underlying producer, i.e. to return from `Sensor::producer` so `Sensor::flush` waits on it
+    pub fn producer(&self) -> &RedpandaProducer {
+        &self.producer
+    }
+
+    /// Lock the measurements produced so far, in the order they were produced
+    pub fn produced(&self) -> MutexGuard<'_, Vec<M>> {
+        self.produced.lock().unwrap()
+    }
+
+    /// Remove and return the measurements produced so far
+    pub fn take(&self) -> Vec<M> {
+        std::mem::take(&mut *self.produced())
+    }
+
+    /// Number of measurements produced so far
+    pub fn len(&self) -> usize {
+        self.produced().len()
+    }
+
+    /// Whether nothing has been produced yet
+    pub fn is_empty(&self) -> bool {
+        self.produced().is_empty()
+    }
+
+    /// Wait until at least `n` measurements have been produced
+    pub async fn wait_for(&self, n: usize) {
+        loop {
+            // Register for the next notification before checking, so a measurement produced in between isn't missed
+            let notified = self.notify.notified();
+            if self.len() >= n {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl<M> MeasurementProducer<M> for MockProducer<M>
+where
+    M: Measurement<'static> + Clone,
+{
+    /// Record the measurement, then produce it to the mock cluster
+    fn produce(&self, measurement: M) -> Result<DeliveryFuture, KafkaError> {
+        self.produced().push(measurement.clone());
+        self.notify.notify_waiters();
+        self.producer.produce(measurement)
+    }
+}
+
+/// Sensor that produces its measurements with a `MockProducer`
+pub trait MockSensor: Sensor {
+    /// The producer `produce_measurement` sends measurements with
+    fn mock_producer(&self) -> &MockProducer<Self::SensorMeasurement>;
+}
+
+/// Run `sensor` until it has produced `n` measurements, and return them
+///
+/// The sensor is stopped with `Sensor::run_until` once the `n`th measurement is produced. If it runs out of
+/// measurements first, the ones it produced are returned, so there may be fewer than `n`. Any measurements past the `n`th stay
+/// recorded on the `MockProducer`.
+///
+/// # Errors
+///
+/// - Any error returned by the sensor's `run_until`
+pub async fn collect_n<S>(sensor: S, n: usize) -> Result<Vec<S::SensorMeasurement>, SensorError>
+where
+    S: MockSensor + Send,
+{
+    let producer = sensor.mock_producer().clone();
+    sensor.run_until(producer.wait_for(n)).await?;
+
+    let mut produced = producer.produced();
+    let count = n.min(produced.len());
+    Ok(produced.drain(..count).collect())
+}
@@ -1,6 +1,30 @@
 use parquet;
 
+use arrow2::array::{
+    Array, BinaryArray, FixedSizeBinaryArray, FixedSizeListArray, ListArray, PrimitiveArray,
+    StructArray,
+};
+use arrow2::chunk::Chunk;
+use arrow2::compute::cast::{can_cast_types, cast, CastOptions};
+use arrow2::compute::take::take;
+use arrow2::datatypes::{DataType, Field, Schema};
+use arrow2::io::parquet::read;
+use arrow2::io::parquet::write::{
+    array_to_columns, to_parquet_leaves, to_parquet_type, transverse, CompressionOptions,
+    Compressor, Descriptor, DynIter, DynStreamingIterator, Encoding, FallibleStreamingIterator,
+    FileWriter, Page, Version, WriteOptions,
+};
+use arrow2::offset::{Offset, OffsetsBuffer};
+use arrow2_convert::deserialize::{ArrowDeserialize, TryIntoCollection};
+use arrow2_convert::field::ArrowField;
+use arrow2_convert::serialize::{ArrowSerialize, TryIntoArrow};
 use parquet::schema::types::Type;
+use parquet2::encoding::hybrid_rle::encode_u32;
+use parquet2::page::{split_buffer, DataPage, DataPageHeader, DataPageHeaderV1, DataPageHeaderV2};
+use parquet2::read::levels::get_bit_width;
+use parquet2::schema::types::PrimitiveType as ParquetPrimitiveType;
+use parquet2::statistics::ParquetStatistics;
+use std::io::{Cursor, Write};
 use std::sync::Arc;
 
 ///  This purpose of this trait is to facilitate code reuse for sensor data serialization and archiving.  Sensors should implement this trait.
@@ -19,3 +43,839 @@ pub trait ParquetArchivable {
     /// The output of this is a parquet schema.
     fn schema(&self) -> Arc<Type>;
 }
+
+/// One `encoding` per parquet leaf column of each field in `schema`, in the shape `RowGroupIterator` expects
+///
+/// A struct or list field is written as one parquet column per primitive field nested in it, and arrow2 needs an
+/// encoding for each of them. Use this instead of counting leaf columns by hand, which panics or silently mismatches
+/// as soon as a struct gains a field.
+pub fn leaf_encodings(schema: &Schema, encoding: Encoding) -> Vec<Vec<Encoding>> {
+    schema
+        .fields
+        .iter()
+        .map(|field| transverse(&field.data_type, |_| encoding))
+        .collect()
+}
+
+/// Write a chunk of arrays to `writer` as a parquet file with one row group, one column per field of `schema`, Plain
+/// encoded, returning the number of bytes written
+///
+/// See `write_row_group` for how columns are encoded. Use `parquet_io::ParquetStreamWriter` to write more than one
+/// row group.
+///
+/// # Errors
+///
+/// - Same as `write_row_group`
+/// - arrow2::error::Error::Io: If writing to `writer` fails
+pub fn write_parquet_chunk<A, W>(
+    chunk: &Chunk<A>,
+    schema: &Schema,
+    writer: W,
+    options: WriteOptions,
+) -> Result<u64, arrow2::error::Error>
+where
+    A: AsRef<dyn Array>,
+    W: Write,
+{
+    let mut writer = FileWriter::try_new(writer, parquet_schema(schema), options)?;
+    write_row_group(&mut writer, chunk, schema, options)?;
+    writer.end(None)
+}
+
+/// Schema of the parquet file written for `schema`, with every field's type converted by `parquet_data_type`
+///
+/// Create the `FileWriter` passed to `write_row_group` with this schema.
+pub fn parquet_schema(schema: &Schema) -> Schema {
+    Schema {
+        fields: schema
+            .fields
+            .iter()
+            .map(|field| Field {
+                data_type: parquet_data_type(&field.data_type),
+                ..field.clone()
+            })
+            .collect(),
+        metadata: schema.metadata.clone(),
+    }
+}
+
+/// Write a chunk of arrays as a row group of `writer`, one column per field of `schema`, Plain encoded
+///
+/// arrow2 0.16 computes wrong repetition and definition levels for lists inside a struct column, and for empty
+/// lists: a `Vec<Vec<T>>` field gets repetition levels one higher than the schema allows, which pyarrow rejects with
+/// "Malformed levels", and rows after an empty list can start mid-record or lose values. So arrow2 only encodes each
+/// leaf column's values here, and the levels are recomputed from the arrays (see `leaf_levels`). Every leaf column
+/// is written as a single page, since arrow2's page splits can also fall mid-record.
+///
+/// Fixed size lists and fixed size binary are written as lists and binary (see `parquet_data_type`).
+///
+/// # Errors
+///
+/// - arrow2::error::Error::InvalidArgumentError: If the chunk doesn't have one array per field of `schema`
+/// - arrow2::error::Error: If arrow2 can't encode a column (i.e. a map, which isn't supported)
+/// - arrow2::error::Error::Io: If writing to `writer` fails
+pub fn write_row_group<A, W>(
+    writer: &mut FileWriter<W>,
+    chunk: &Chunk<A>,
+    schema: &Schema,
+    options: WriteOptions,
+) -> Result<(), arrow2::error::Error>
+where
+    A: AsRef<dyn Array>,
+    W: Write,
+{
+    if chunk.arrays().len() != schema.fields.len() {
+        return Err(arrow2::error::Error::InvalidArgumentError(format!(
+            "chunk has {} arrays but the schema has {} fields",
+            chunk.arrays().len(),
+            schema.fields.len()
+        )));
+    }
+
+    let single_page = WriteOptions {
+        data_pagesize_limit: Some(usize::MAX),
+        ..options
+    };
+    let schema = parquet_schema(schema);
+    let mut pages = Vec::new();
+    let fields = chunk.arrays().iter().zip(&schema.fields);
+    for ((array, field), encodings) in fields.zip(leaf_encodings(&schema, Encoding::Plain)) {
+        let array = to_parquet_array(array.as_ref())?;
+        let array = array.as_ref();
+        let levels = leaf_levels(array, field.is_nullable)?;
+        let parquet_type = to_parquet_type(field)?;
+        let leaves = to_parquet_leaves(parquet_type.clone());
+        let columns = array_to_columns(array, parquet_type, single_page, &encodings)?;
+        pages.extend(column_pages(columns, levels, leaves, chunk.len(), options)?);
+    }
+
+    let row_group = DynIter::new(pages.into_iter().map(move |page| {
+        let pages = DynIter::new(std::iter::once(Ok(page)));
+        let compressed =
+            Compressor::new(pages, options.compression, vec![]).map_err(arrow2::error::Error::from);
+        Ok(DynStreamingIterator::new(compressed))
+    }));
+    writer.write(row_group)
+}
+
+/// Replace the levels of each leaf column's page encoded by arrow2, or write a page of just levels for leaves
+/// without values
+fn column_pages(
+    columns: Vec<DynIter<'static, Result<Page, arrow2::error::Error>>>,
+    levels: Vec<Levels>,
+    leaves: Vec<ParquetPrimitiveType>,
+    num_rows: usize,
+    options: WriteOptions,
+) -> Result<Vec<Page>, arrow2::error::Error> {
+    let mut pages = Vec::with_capacity(columns.len());
+    for ((column, levels), leaf) in columns.into_iter().zip(levels).zip(leaves) {
+        let descriptor = Descriptor {
+            primitive_type: leaf,
+            max_def_level: levels.max_def,
+            max_rep_level: levels.max_rep,
+        };
+        let mut column = column.collect::<Result<Vec<_>, _>>()?;
+        let page = match (column.pop(), column.is_empty()) {
+            (Some(page), true) => with_levels(page, descriptor, &levels, num_rows)?,
+            // arrow2 writes no pages for a leaf without values, i.e. when every list in the batch is empty
+            (None, _) => empty_page(descriptor, &levels, num_rows, options)?,
+            (Some(_), false) => {
+                return Err(arrow2::error::Error::InvalidArgumentError(format!(
+                    "column {} doesn't fit in a single page, write smaller batches",
+                    descriptor.primitive_type.field_info.name
+                )))
+            }
+        };
+        pages.push(page);
+    }
+
+    Ok(pages)
+}
+
+/// Data type a column of `data_type` is written to parquet as by `write_parquet_chunk`
+///
+/// arrow2 0.16 can't write fixed size lists at all, and can't write or read fixed size binary nested in a struct or
+/// list, which is every arrow2_convert column. So fixed size lists are written as lists, and fixed size binary as
+/// binary, at any depth. `from_parquet_array` restores them when reading. Every other type is written as-is.
+pub fn parquet_data_type(data_type: &DataType) -> DataType {
+    let field = |field: &Field| Field {
+        data_type: parquet_data_type(&field.data_type),
+        ..field.clone()
+    };
+    match data_type {
+        DataType::FixedSizeBinary(_) => DataType::Binary,
+        DataType::FixedSizeList(inner, _) | DataType::List(inner) => {
+            DataType::List(Box::new(field(inner)))
+        }
+        DataType::LargeList(inner) => DataType::LargeList(Box::new(field(inner))),
+        DataType::Struct(fields) => DataType::Struct(fields.iter().map(field).collect()),
+        DataType::Extension(name, inner, metadata) => DataType::Extension(
+            name.clone(),
+            Box::new(parquet_data_type(inner)),
+            metadata.clone(),
+        ),
+        data_type => data_type.clone(),
+    }
+}
+
+/// Convert the fixed size lists and fixed size binary in `array` to the lists and binary they're written as (see
+/// `parquet_data_type`)
+///
+/// # Errors
+///
+/// - arrow2::error::Error::Overflow: If a converted column has more than `i32::MAX` values or bytes
+pub fn to_parquet_array(array: &dyn Array) -> Result<Box<dyn Array>, arrow2::error::Error> {
+    let data_type = parquet_data_type(array.data_type());
+    if &data_type == array.data_type() {
+        return Ok(array.to_boxed());
+    }
+
+    let validity = array.validity().cloned();
+    Ok(match array.data_type().to_logical_type() {
+        DataType::FixedSizeBinary(_) => {
+            let array = array
+                .as_any()
+                .downcast_ref::<FixedSizeBinaryArray>()
+                .unwrap();
+            let offsets = fixed_size_offsets(array, array.size())?;
+            let values: Vec<u8> = array.iter().flatten().flatten().copied().collect();
+            BinaryArray::<i32>::try_new(data_type, offsets, values.into(), validity)?.boxed()
+        }
+        DataType::FixedSizeList(_, size) => {
+            let array = array.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+            let offsets = fixed_size_offsets(array, *size)?;
+            // Null lists still hold `size` items, which would be written as values of the next valid list
+            let values = if array.null_count() > 0 {
+                let indices: Vec<i32> = (0..array.len())
+                    .filter(|&i| array.is_valid(i))
+                    .flat_map(|i| i * size..(i + 1) * size)
+                    .map(|index| index as i32)
+                    .collect();
+                take(array.values().as_ref(), &PrimitiveArray::from_vec(indices))?
+            } else {
+                array.values().clone()
+            };
+            let values = to_parquet_array(values.as_ref())?;
+            ListArray::<i32>::try_new(data_type, offsets, values, validity)?.boxed()
+        }
+        DataType::List(_) => {
+            let array = array.as_any().downcast_ref::<ListArray<i32>>().unwrap();
+            let values = to_parquet_array(array.values().as_ref())?;
+            ListArray::<i32>::try_new(data_type, array.offsets().clone(), values, validity)?.boxed()
+        }
+        DataType::LargeList(_) => {
+            let array = array.as_any().downcast_ref::<ListArray<i64>>().unwrap();
+            let values = to_parquet_array(array.values().as_ref())?;
+            ListArray::<i64>::try_new(data_type, array.offsets().clone(), values, validity)?.boxed()
+        }
+        DataType::Struct(_) => {
+            let array = array.as_any().downcast_ref::<StructArray>().unwrap();
+            let values = array
+                .values()
+                .iter()
+                .map(|values| to_parquet_array(values.as_ref()))
+                .collect::<Result<_, _>>()?;
+            StructArray::try_new(data_type, values, validity)?.boxed()
+        }
+        _ => array.to_boxed(),
+    })
+}
+
+/// Offsets of the values of a fixed size `array` as variable size values, `size` long except for nulls, which are
+/// empty
+fn fixed_size_offsets(
+    array: &dyn Array,
+    size: usize,
+) -> Result<OffsetsBuffer<i32>, arrow2::error::Error> {
+    let size = i32::try_from(size).map_err(|_| arrow2::error::Error::Overflow)?;
+    let mut offsets = Vec::with_capacity(array.len() + 1);
+    let mut offset = 0i32;
+    offsets.push(offset);
+    for i in 0..array.len() {
+        if array.is_valid(i) {
+            offset = offset
+                .checked_add(size)
+                .ok_or(arrow2::error::Error::Overflow)?;
+        }
+        offsets.push(offset);
+    }
+    offsets.try_into()
+}
+
+/// Restore the fixed size lists and fixed size binary of `data_type` in `array`, read from a column written by
+/// `write_parquet_chunk` (see `parquet_data_type`)
+///
+/// Null fixed size values are read back zeroed (binary) or null (list items).
+///
+/// # Errors
+///
+/// - arrow2::error::Error::InvalidArgumentError: If a valid list or binary value doesn't have its fixed size, or
+///   `array` doesn't have the type `data_type` is written as
+pub fn from_parquet_array(
+    array: &dyn Array,
+    data_type: &DataType,
+) -> Result<Box<dyn Array>, arrow2::error::Error> {
+    if array.data_type() == data_type {
+        return Ok(array.to_boxed());
+    }
+    if array.data_type() != &parquet_data_type(data_type) {
+        return Err(arrow2::error::Error::InvalidArgumentError(format!(
+            "can't read a {:?} column as {:?}",
+            array.data_type(),
+            data_type
+        )));
+    }
+
+    let validity = array.validity().cloned();
+    Ok(match data_type.to_logical_type() {
+        DataType::FixedSizeBinary(size) => {
+            let array = array.as_any().downcast_ref::<BinaryArray<i32>>().unwrap();
+            let mut values = Vec::with_capacity(array.len() * size);
+            for i in 0..array.len() {
+                if !array.is_valid(i) {
+                    values.resize(values.len() + size, 0);
+                    continue;
+                }
+                let value = array.value(i);
+                if value.len() != *size {
+                    return Err(fixed_size_error(value.len(), *size));
+                }
+                values.extend_from_slice(value);
+            }
+            FixedSizeBinaryArray::try_new(data_type.clone(), values.into(), validity)?.boxed()
+        }
+        DataType::FixedSizeList(inner, size) => {
+            let array = array.as_any().downcast_ref::<ListArray<i32>>().unwrap();
+            // Null lists are read back empty, but still need `size` (null) items in a fixed size list
+            let mut indices = Vec::with_capacity(array.len() * size);
+            for i in 0..array.len() {
+                let (start, end) = array.offsets().start_end(i);
+                if !array.is_valid(i) {
+                    indices.resize(indices.len() + size, None);
+                    continue;
+                }
+                if end - start != *size {
+                    return Err(fixed_size_error(end - start, *size));
+                }
+                indices.extend((start..end).map(|index| Some(index as i32)));
+            }
+            let values = take(array.values().as_ref(), &PrimitiveArray::from(indices))?;
+            let values = from_parquet_array(values.as_ref(), &inner.data_type)?;
+            FixedSizeListArray::try_new(data_type.clone(), values, validity)?.boxed()
+        }
+        DataType::List(inner) => {
+            let array = array.as_any().downcast_ref::<ListArray<i32>>().unwrap();
+            let values = from_parquet_array(array.values().as_ref(), &inner.data_type)?;
+            ListArray::<i32>::try_new(data_type.clone(), array.offsets().clone(), values, validity)?
+                .boxed()
+        }
+        DataType::LargeList(inner) => {
+            let array = array.as_any().downcast_ref::<ListArray<i64>>().unwrap();
+            let values = from_parquet_array(array.values().as_ref(), &inner.data_type)?;
+            ListArray::<i64>::try_new(data_type.clone(), array.offsets().clone(), values, validity)?
+                .boxed()
+        }
+        DataType::Struct(fields) => {
+            let array = array.as_any().downcast_ref::<StructArray>().unwrap();
+            let values = array
+                .values()
+                .iter()
+                .zip(fields)
+                .map(|(values, field)| from_parquet_array(values.as_ref(), &field.data_type))
+                .collect::<Result<_, _>>()?;
+            StructArray::try_new(data_type.clone(), values, validity)?.boxed()
+        }
+        _ => array.to_boxed(),
+    })
+}
+
+fn fixed_size_error(len: usize, size: usize) -> arrow2::error::Error {
+    arrow2::error::Error::InvalidArgumentError(format!(
+        "value of length {} in a column of fixed size {}",
+        len, size
+    ))
+}
+
+/// Repetition and definition levels of a leaf column
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Levels {
+    /// Repetition level of each value
+    pub rep: Vec<u32>,
+    /// Definition level of each value
+    pub def: Vec<u32>,
+    /// Highest repetition level the column's schema allows
+    pub max_rep: i16,
+    /// Highest definition level the column's schema allows, reached by non-null values
+    pub max_def: i16,
+}
+
+impl Levels {
+    fn push(&mut self, rep: u32, def: u32) {
+        self.rep.push(rep);
+        self.def.push(def);
+    }
+}
+
+/// Dremel repetition and definition levels of every leaf column of `array`, in parquet column order
+///
+/// Lists add a repetition level, and a definition level for being non-empty. Nullable arrays add a definition level
+/// for being valid.
+///
+/// # Errors
+///
+/// - arrow2::error::Error::NotYetImplemented: If the array contains fixed size lists or maps
+pub fn leaf_levels(
+    array: &dyn Array,
+    is_nullable: bool,
+) -> Result<Vec<Levels>, arrow2::error::Error> {
+    let mut paths = Vec::new();
+    leaf_paths(array, is_nullable, Vec::new(), &mut paths)?;
+
+    Ok(paths
+        .iter()
+        .map(|path| {
+            let lists = path.iter().filter(|(array, _)| is_list(*array)).count();
+            let nullable = path.iter().filter(|(_, is_nullable)| *is_nullable).count();
+            let mut levels = Levels {
+                max_rep: lists as i16,
+                max_def: (lists + nullable) as i16,
+                ..Default::default()
+            };
+            for row in 0..array.len() {
+                push_levels(path, row, 0, 0, 0, &mut levels);
+            }
+            levels
+        })
+        .collect())
+}
+
+/// Arrays (and their nullability) from a column down to each of its leaves
+fn leaf_paths<'a>(
+    array: &'a dyn Array,
+    is_nullable: bool,
+    mut path: Vec<(&'a dyn Array, bool)>,
+    paths: &mut Vec<Vec<(&'a dyn Array, bool)>>,
+) -> Result<(), arrow2::error::Error> {
+    path.push((array, is_nullable));
+    match array.data_type().to_logical_type() {
+        DataType::Struct(fields) => {
+            let array = array.as_any().downcast_ref::<StructArray>().unwrap();
+            for (field, values) in fields.iter().zip(array.values()) {
+                leaf_paths(values.as_ref(), field.is_nullable, path.clone(), paths)?;
+            }
+        }
+        DataType::List(field) => {
+            let array = array.as_any().downcast_ref::<ListArray<i32>>().unwrap();
+            leaf_paths(array.values().as_ref(), field.is_nullable, path, paths)?;
+        }
+        DataType::LargeList(field) => {
+            let array = array.as_any().downcast_ref::<ListArray<i64>>().unwrap();
+            leaf_paths(array.values().as_ref(), field.is_nullable, path, paths)?;
+        }
+        DataType::FixedSizeList(_, _) | DataType::Map(_, _) => {
+            return Err(arrow2::error::Error::NotYetImplemented(format!(
+                "parquet levels for {:?}",
+                array.data_type()
+            )))
+        }
+        _ => paths.push(path),
+    }
+
+    Ok(())
+}
+
+/// Push the levels of element `index` of `path[0]`, which starts at repetition level `rep` and is defined up to
+/// `def`, under `depth` enclosing lists
+fn push_levels(
+    path: &[(&dyn Array, bool)],
+    index: usize,
+    rep: u32,
+    mut def: u32,
+    depth: u32,
+    levels: &mut Levels,
+) {
+    let (array, is_nullable) = path[0];
+    if is_nullable {
+        if !array.is_valid(index) {
+            return levels.push(rep, def);
+        }
+        def += 1;
+    }
+
+    let children = &path[1..];
+    if children.is_empty() {
+        return levels.push(rep, def);
+    }
+
+    let (start, end) = match array.data_type().to_logical_type() {
+        DataType::List(_) => list_range::<i32>(array, index),
+        DataType::LargeList(_) => list_range::<i64>(array, index),
+        // Structs add no levels of their own beyond being valid
+        _ => return push_levels(children, index, rep, def, depth, levels),
+    };
+    if start == end {
+        return levels.push(rep, def);
+    }
+    for (i, child) in (start..end).enumerate() {
+        let child_rep = if i == 0 { rep } else { depth + 1 };
+        push_levels(children, child, child_rep, def + 1, depth + 1, levels);
+    }
+}
+
+fn is_list(array: &dyn Array) -> bool {
+    matches!(
+        array.data_type().to_logical_type(),
+        DataType::List(_) | DataType::LargeList(_)
+    )
+}
+
+/// Range of child indices of list `index`
+fn list_range<O: Offset>(array: &dyn Array, index: usize) -> (usize, usize) {
+    let array = array.as_any().downcast_ref::<ListArray<O>>().unwrap();
+    array.offsets().start_end(index)
+}
+
+/// Replace the levels of a page encoded by arrow2 with `levels`, keeping its values
+fn with_levels(
+    page: Page,
+    descriptor: Descriptor,
+    levels: &Levels,
+    num_rows: usize,
+) -> Result<Page, arrow2::error::Error> {
+    let mut page = match page {
+        Page::Data(page) => page,
+        Page::Dict(_) => {
+            return Err(arrow2::error::Error::NotYetImplemented(
+                "dictionary encoded nested columns".to_owned(),
+            ))
+        }
+    };
+    // arrow2 leaves the descriptor's max levels at 0 (the file writer fills them in from the schema), but wrote
+    // levels for the real max levels, which are needed to find where its values start
+    page.descriptor = descriptor;
+    let (_, _, values) = split_buffer(&page)?;
+
+    levels_page(
+        page.header().clone(),
+        page.descriptor.clone(),
+        levels,
+        values,
+        num_rows,
+    )
+}
+
+/// Page with only `levels`, for a leaf column without any values
+fn empty_page(
+    descriptor: Descriptor,
+    levels: &Levels,
+    num_rows: usize,
+    options: WriteOptions,
+) -> Result<Page, arrow2::error::Error> {
+    // Every value is null or an empty list
+    let statistics = options.write_statistics.then(|| ParquetStatistics {
+        max: None,
+        min: None,
+        null_count: Some(levels.def.len() as i64),
+        distinct_count: None,
+        max_value: None,
+        min_value: None,
+    });
+    let header = match options.version {
+        Version::V1 => DataPageHeader::V1(DataPageHeaderV1 {
+            num_values: 0,
+            encoding: Encoding::Plain.into(),
+            definition_level_encoding: Encoding::Rle.into(),
+            repetition_level_encoding: Encoding::Rle.into(),
+            statistics: statistics.clone(),
+        }),
+        Version::V2 => DataPageHeader::V2(DataPageHeaderV2 {
+            num_values: 0,
+            encoding: Encoding::Plain.into(),
+            num_nulls: 0,
+            num_rows: 0,
+            definition_levels_byte_length: 0,
+            repetition_levels_byte_length: 0,
+            is_compressed: Some(options.compression != CompressionOptions::Uncompressed),
+            statistics,
+        }),
+    };
+
+    levels_page(header, descriptor, levels, &[], num_rows)
+}
+
+/// Page of `values` prefixed with `levels`, with `header`'s counts set to match
+fn levels_page(
+    header: DataPageHeader,
+    descriptor: Descriptor,
+    levels: &Levels,
+    values: &[u8],
+    num_rows: usize,
+) -> Result<Page, arrow2::error::Error> {
+    let version = match header {
+        DataPageHeader::V1(_) => Version::V1,
+        DataPageHeader::V2(_) => Version::V2,
+    };
+    let mut buffer = Vec::with_capacity(values.len());
+    let rep_len = write_levels(&mut buffer, &levels.rep, levels.max_rep, version)?;
+    let def_len = write_levels(&mut buffer, &levels.def, levels.max_def, version)?;
+    buffer.extend_from_slice(values);
+
+    let num_values = levels.def.len() as i32;
+    let header = match header {
+        DataPageHeader::V1(mut header) => {
+            header.num_values = num_values;
+            DataPageHeader::V1(header)
+        }
+        DataPageHeader::V2(mut header) => {
+            header.num_values = num_values;
+            header.num_nulls = levels
+                .def
+                .iter()
+                .filter(|def| **def < levels.max_def as u32)
+                .count() as i32;
+            header.num_rows = num_rows as i32;
+            header.repetition_levels_byte_length = rep_len as i32;
+            header.definition_levels_byte_length = def_len as i32;
+            DataPageHeader::V2(header)
+        }
+    };
+
+    Ok(Page::Data(DataPage::new(
+        header,
+        buffer,
+        descriptor,
+        Some(num_rows),
+    )))
+}
+
+/// RLE encode levels into `buffer` (length prefixed for V1 pages), returning the number of bytes written
+///
+/// Nothing is written for a max level of 0.
+fn write_levels(
+    buffer: &mut Vec<u8>,
+    levels: &[u32],
+    max_level: i16,
+    version: Version,
+) -> Result<usize, arrow2::error::Error> {
+    if max_level == 0 {
+        return Ok(0);
+    }
+
+    let mut encoded = vec![];
+    encode_u32(
+        &mut encoded,
+        levels.iter().copied(),
+        get_bit_width(max_level),
+    )?;
+    if let Version::V1 = version {
+        buffer.extend_from_slice(&(encoded.len() as i32).to_le_bytes());
+    }
+    buffer.extend_from_slice(&encoded);
+
+    Ok(encoded.len())
+}
+
+/// Something `read_parquet_tolerant` had to adapt to read a file written with a different schema
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemaWarning {
+    /// The file has no column for this field, so every row got the field's default value
+    MissingField(String),
+    /// The file has a column the struct has no field for, so it was ignored
+    ExtraColumn(String),
+    /// The column's type differs from the field's, but every value converted losslessly
+    CastField {
+        /// Field name
+        name: String,
+        /// Type of the column in the file
+        from: DataType,
+        /// Type of the field
+        to: DataType,
+    },
+    /// The column's type differs from the field's and the values don't convert, so every row got the field's default
+    /// value
+    IncompatibleField {
+        /// Field name
+        name: String,
+        /// Type of the column in the file
+        found: DataType,
+        /// Type of the field
+        expected: DataType,
+    },
+}
+
+/// Structs read by `read_parquet_tolerant`, with every schema difference it had to work around
+#[derive(Clone, Debug, PartialEq)]
+pub struct TolerantRead<T> {
+    /// Structs in file order
+    pub items: Vec<T>,
+    /// Schema differences, each reported once per file
+    pub warnings: Vec<SchemaWarning>,
+}
+
+/// Read structs from parquet bytes written with an older or newer version of the struct
+///
+/// The file's first column must be a struct column, as written by arrow2_convert. Its children are matched to the
+/// struct's fields by name, so reordered fields read normally. Fields the file doesn't have are filled with the
+/// value from `T::default()`, columns the struct doesn't have are ignored, and columns whose type changed are cast
+/// when every value converts losslessly, and otherwise also filled with the default. Each of these is reported as a
+/// `SchemaWarning` instead of failing the read.
+///
+/// # Errors
+///
+/// - arrow2::error::Error: If the bytes aren't a parquet file, its first column isn't a struct, or the adapted
+///   columns still fail to deserialize into `T`
+pub fn read_parquet_tolerant<T>(bytes: &[u8]) -> Result<TolerantRead<T>, arrow2::error::Error>
+where
+    T: ArrowDeserialize + ArrowSerialize + ArrowField<Type = T> + Default + Clone + 'static,
+    for<'a> &'a <T as ArrowDeserialize>::ArrayType: IntoIterator,
+{
+    let mut reader = Cursor::new(bytes);
+    let metadata = read::read_metadata(&mut reader)?;
+    let schema = read::infer_schema(&metadata)?;
+    let chunks = read::FileReader::new(reader, metadata.row_groups, schema, None, None, None);
+
+    let data_type = T::data_type();
+    let fields = StructArray::get_fields(&data_type);
+
+    let mut items = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, chunk) in chunks.enumerate() {
+        let chunk = chunk?;
+        let array = chunk.arrays().first().ok_or_else(|| {
+            arrow2::error::Error::InvalidArgumentError("parquet file has no columns".to_owned())
+        })?;
+        let file_struct = array
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .ok_or_else(|| {
+                arrow2::error::Error::InvalidArgumentError(format!(
+                    "first column is {:?}, not a struct",
+                    array.data_type()
+                ))
+            })?;
+        let len = file_struct.len();
+
+        // Warnings only depend on the schema, which is the same for every chunk in the file
+        let mut chunk_warnings = Vec::new();
+        let mut defaults: Option<Box<dyn Array>> = None;
+        let mut values = Vec::with_capacity(fields.len());
+        for (j, field) in fields.iter().enumerate() {
+            let column = file_struct
+                .fields()
+                .iter()
+                .position(|f| f.name == field.name)
+                .map(|k| &file_struct.values()[k]);
+
+            let value = match column {
+                Some(column) if column.data_type() == &field.data_type => Some(column.clone()),
+                // Fixed size lists and binary are written as lists and binary
+                Some(column) if column.data_type() == &parquet_data_type(&field.data_type) => {
+                    Some(from_parquet_array(column.as_ref(), &field.data_type)?)
+                }
+                Some(column) => match cast_lossless(column.as_ref(), &field.data_type) {
+                    Some(cast) => {
+                        chunk_warnings.push(SchemaWarning::CastField {
+                            name: field.name.clone(),
+                            from: column.data_type().clone(),
+                            to: field.data_type.clone(),
+                        });
+                        Some(cast)
+                    }
+                    None => {
+                        chunk_warnings.push(SchemaWarning::IncompatibleField {
+                            name: field.name.clone(),
+                            found: column.data_type().clone(),
+                            expected: field.data_type.clone(),
+                        });
+                        None
+                    }
+                },
+                None => {
+                    chunk_warnings.push(SchemaWarning::MissingField(field.name.clone()));
+                    None
+                }
+            };
+
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    if defaults.is_none() {
+                        defaults = Some(vec![T::default(); len].try_into_arrow()?);
+                    }
+                    let defaults = defaults.as_ref().unwrap();
+                    let defaults = defaults
+                        .as_any()
+                        .downcast_ref::<StructArray>()
+                        .expect("arrow2_convert serializes structs to a StructArray");
+                    defaults.values()[j].clone()
+                }
+            };
+            values.push(value);
+        }
+        for field in file_struct.fields() {
+            if !fields.iter().any(|f| f.name == field.name) {
+                chunk_warnings.push(SchemaWarning::ExtraColumn(field.name.clone()));
+            }
+        }
+        if i == 0 {
+            warnings = chunk_warnings;
+        }
+
+        let adapted =
+            StructArray::try_new(data_type.clone(), values, file_struct.validity().cloned())?;
+        let chunk_items: Vec<T> = (&adapted as &dyn Array).try_into_collection()?;
+        items.extend(chunk_items);
+    }
+
+    Ok(TolerantRead { items, warnings })
+}
+
+/// Cast `array` to `to_type`, only if no value is lost (turned into a null) along the way
+///
+/// Floats cast to integers are truncated, so they're only lossless if every value casts back to the same float.
+fn cast_lossless(array: &dyn Array, to_type: &DataType) -> Option<Box<dyn Array>> {
+    if !can_cast_types(array.data_type(), to_type) {
+        return None;
+    }
+
+    let converted = cast(array, to_type, CastOptions::default()).ok()?;
+    if converted.null_count() != array.null_count() {
+        return None;
+    }
+    if is_float(array.data_type()) && is_integer(to_type) {
+        let round_trip = cast(
+            converted.as_ref(),
+            array.data_type(),
+            CastOptions::default(),
+        )
+        .ok()?;
+        if round_trip.as_ref() != array {
+            return None;
+        }
+    }
+
+    Some(converted)
+}
+
+/// Whether `data_type` is a floating point type
+fn is_float(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Float16 | DataType::Float32 | DataType::Float64
+    )
+}
+
+/// Whether `data_type` is a signed or unsigned integer type
+fn is_integer(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+    )
+}
@@ -0,0 +1,427 @@
+//! Write and read slices of structs as parquet files with arrow2_convert
+//!
+//! The canonical way for sensors to serialize measurements to parquet. Structs are stored as a single struct column
+//! named `STRUCT_COLUMN` in one row group, with the schema built from `ArrowField::data_type`, so callers never build
+//! schemas or count leaf column encodings by hand. Streams too long to hold in memory can be written a row group at a
+//! time with `ParquetStreamWriter`, and read back selectively with `read_parquet_filtered`.
+//!
+//! Fixed width fields, i.e. a 3-axis accelerometer sample, can be declared with
+//! `#[arrow_field(type = "arrow2_convert::field::FixedSizeVec<f32, 3>")]` or `FixedSizeBinary<N>`, plain or wrapped
+//! in an `Option`, in any struct (including structs in a `Vec`). Any size `N` of at least 1 works (arrow2 rejects 0),
+//! as long as a batch's column has fewer than `i32::MAX` items or bytes. arrow2_convert 0.4 can't serialize a `Vec`
+//! of them directly, i.e. `Vec<FixedSizeVec<f32, 3>>`, so wrap each one in a struct. They're written as parquet lists
+//! and binary (see `parquet::parquet_data_type`), and every value is checked to have its fixed size when read back.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use arrow2::array::{Array, StructArray};
+use arrow2::chunk::Chunk;
+use arrow2::datatypes::{DataType, Field, Schema};
+use arrow2::io::parquet::read::{self, ColumnChunkMetaData, RowGroupMetaData};
+use arrow2::io::parquet::write::{FileWriter, WriteOptions};
+use arrow2_convert::deserialize::{ArrowDeserialize, TryIntoCollection};
+use arrow2_convert::field::ArrowField;
+use arrow2_convert::serialize::{ArrowSerialize, TryIntoArrow};
+use parquet2::statistics::PrimitiveStatistics;
+
+use crate::parquet::{
+    from_parquet_array, parquet_data_type, parquet_schema, write_parquet_chunk, write_row_group,
+};
+
+/// Name of the struct column `write_parquet` stores structs in
+pub const STRUCT_COLUMN: &str = "items";
+
+/// Write structs to `writer` as a parquet file, returning the number of bytes written
+///
+/// Nested lists are written with correct repetition and definition levels (see `parquet::write_parquet_chunk`).
+///
+/// # Errors
+///
+/// - arrow2::error::Error: If the structs can't be serialized to arrow, or can't be written (see
+///   `parquet::write_parquet_chunk`)
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut file = File::create("radar.parquet")?;
+/// let options = WriteOptions {
+///     write_statistics: true,
+///     compression: CompressionOptions::Zstd(None),
+///     version: Version::V2,
+///     data_pagesize_limit: None,
+/// };
+/// write_parquet(&measurements, &mut file, options)?;
+/// ```
+pub fn write_parquet<T, W>(
+    items: &[T],
+    writer: W,
+    options: WriteOptions,
+) -> Result<u64, arrow2::error::Error>
+where
+    T: ArrowField<Type = T> + ArrowSerialize + 'static,
+    W: Write,
+{
+    let schema = Schema::from(vec![Field::new(STRUCT_COLUMN, T::data_type(), true)]);
+    let array: Box<dyn Array> = items.try_into_arrow()?;
+    write_parquet_chunk(&Chunk::new(vec![array]), &schema, writer, options)
+}
+
+/// Write structs to a parquet file as they arrive, a row group at a time, for unbounded streams of measurements
+///
+/// Structs are buffered until `row_group_size` of them have been pushed, then written as a row group the same way
+/// `write_parquet` writes its only one, so memory use is bounded by the row group size however long the stream is.
+/// `finish` writes the last, partial row group and the file footer: a file that isn't finished can't be read.
+///
+/// # Examples
+///
+/// ```no_run
+/// let file = File::create("radar.parquet")?;
+/// let mut writer = ParquetStreamWriter::new(file, options, 10_000)?;
+/// while let Some(measurement) = stream.next().await {
+///     writer.push(measurement)?;
+/// }
+/// writer.finish()?;
+/// ```
+pub struct ParquetStreamWriter<T, W: Write> {
+    writer: FileWriter<W>,
+    schema: Schema,
+    options: WriteOptions,
+    row_group_size: usize,
+    buffer: Vec<T>,
+    rows: u64,
+}
+
+impl<T, W> ParquetStreamWriter<T, W>
+where
+    T: ArrowField<Type = T> + ArrowSerialize + 'static,
+    W: Write,
+{
+    /// Writer to `writer` that writes a row group every `row_group_size` structs
+    ///
+    /// # Errors
+    ///
+    /// - arrow2::error::Error::InvalidArgumentError: If `row_group_size` is 0
+    /// - arrow2::error::Error: If `T`'s schema can't be written as parquet
+    pub fn new(
+        writer: W,
+        options: WriteOptions,
+        row_group_size: usize,
+    ) -> Result<Self, arrow2::error::Error> {
+        if row_group_size == 0 {
+            return Err(arrow2::error::Error::InvalidArgumentError(
+                "row group size must be at least 1".to_owned(),
+            ));
+        }
+        let schema = Schema::from(vec![Field::new(STRUCT_COLUMN, T::data_type(), true)]);
+
+        Ok(ParquetStreamWriter {
+            writer: FileWriter::try_new(writer, parquet_schema(&schema), options)?,
+            schema,
+            options,
+            row_group_size,
+            buffer: Vec::with_capacity(row_group_size),
+            rows: 0,
+        })
+    }
+
+    /// Buffer a struct, writing the buffered structs as a row group once there are `row_group_size` of them
+    ///
+    /// # Errors
+    ///
+    /// - Same as `flush`
+    pub fn push(&mut self, item: T) -> Result<(), arrow2::error::Error> {
+        self.buffer.push(item);
+        self.rows += 1;
+        if self.buffer.len() >= self.row_group_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered structs as a row group now, even if there are fewer than `row_group_size`
+    ///
+    /// Does nothing if no structs are buffered.
+    ///
+    /// # Errors
+    ///
+    /// - arrow2::error::Error: If the structs can't be serialized to arrow, or can't be written (see
+    ///   `parquet::write_row_group`)
+    pub fn flush(&mut self) -> Result<(), arrow2::error::Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let array: Box<dyn Array> = self.buffer.as_slice().try_into_arrow()?;
+        self.buffer.clear();
+        write_row_group(
+            &mut self.writer,
+            &Chunk::new(vec![array]),
+            &self.schema,
+            self.options,
+        )
+    }
+
+    /// Number of structs pushed so far, written or not
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Write the buffered structs and the file footer, returning the number of bytes written
+    ///
+    /// # Errors
+    ///
+    /// - Same as `flush`
+    /// - arrow2::error::Error::Io: If writing the footer fails
+    pub fn finish(mut self) -> Result<u64, arrow2::error::Error> {
+        self.flush()?;
+        self.writer.end(None)
+    }
+}
+
+/// Read every struct in the first column of a parquet file, i.e. one written by `write_parquet`
+///
+/// The file must have been written with the same struct schema. Use `parquet::read_parquet_tolerant` to read files
+/// written with an older or newer version of the struct.
+///
+/// # Errors
+///
+/// - arrow2::error::Error: If the file isn't parquet, has no columns, or its first column doesn't deserialize into
+///   `T`
+pub fn read_parquet<T, R>(mut reader: R) -> Result<Vec<T>, arrow2::error::Error>
+where
+    T: ArrowDeserialize + ArrowField<Type = T> + 'static,
+    for<'a> &'a <T as ArrowDeserialize>::ArrayType: IntoIterator,
+    R: Read + Seek,
+{
+    let metadata = read::read_metadata(&mut reader)?;
+    let mut schema = read::infer_schema(&metadata)?;
+    // Extension types are inferred with their name in the field metadata, which arrow2_convert doesn't expect, so
+    // read the first column with T's own data type, as it was written
+    match schema.fields.first_mut() {
+        Some(field) => field.data_type = parquet_data_type(&T::data_type()),
+        None => {
+            return Err(arrow2::error::Error::InvalidArgumentError(
+                "parquet file has no columns".to_owned(),
+            ))
+        }
+    }
+    let chunks = read::FileReader::new(reader, metadata.row_groups, schema, None, None, None);
+
+    let mut items = Vec::new();
+    for chunk in chunks {
+        let chunk = chunk?;
+        let array = from_parquet_array(chunk.arrays()[0].as_ref(), &T::data_type())?;
+        let array: Vec<T> = array.as_ref().try_into_collection()?;
+        items.extend(array);
+    }
+
+    Ok(items)
+}
+
+/// Predicate `read_parquet_filtered` keeps decoded structs by
+pub type RowFilter<T> = Box<dyn Fn(&T) -> bool>;
+
+/// Timestamps `read_parquet_filtered` reads row groups for, from `start` up to (not including) `end`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeRange {
+    /// Name of the struct field with each row's timestamp, which must be stored as 64 bit integers, i.e. an `i64`
+    /// or a `chrono::NaiveDateTime`
+    pub column: String,
+    /// First timestamp in the range
+    pub start: i64,
+    /// Timestamp just past the end of the range
+    pub end: i64,
+}
+
+impl TimeRange {
+    /// Whether any timestamp from `min` to `max` (inclusive) is in the range
+    fn overlaps(&self, min: i64, max: i64) -> bool {
+        min < self.end && max >= self.start
+    }
+}
+
+/// Read only some fields of the structs in a parquet file written by `write_parquet`, skipping whole row groups
+/// outside a time range
+///
+/// Only the leaf columns of the fields named in `columns` are read and decoded (every field if it's empty). The
+/// other fields get their value from `T::default()`, like fields missing from the file in
+/// `parquet::read_parquet_tolerant`. Row groups whose min/max statistics for `time_range.column` fall entirely
+/// outside `time_range` aren't read at all, which is what makes time range queries on time-partitioned archives
+/// cheap. Row groups without statistics are always read. `row_filter` is applied to each decoded struct, so rows
+/// of a partly overlapping row group can be dropped by checking their timestamp there.
+///
+/// Like `read_parquet`, the file must have been written with the same struct schema.
+///
+/// # Errors
+///
+/// - arrow2::error::Error::InvalidArgumentError: If `columns` names a field `T` doesn't have, or the file has no
+///   `time_range.column` column of 64 bit integers
+/// - arrow2::error::Error: If the file isn't parquet, or the columns read don't deserialize into `T`
+///
+/// # Examples
+///
+/// ```no_run
+/// let range = TimeRange {
+///     column: "timestamp".to_owned(),
+///     start,
+///     end,
+/// };
+/// let in_range = Box::new(move |m: &Measurement| (start..end).contains(&m.timestamp));
+/// let measurements: Vec<Measurement> =
+///     read_parquet_filtered(file, &["timestamp", "range"], Some(&range), Some(in_range))?;
+/// ```
+pub fn read_parquet_filtered<T, R>(
+    mut reader: R,
+    columns: &[&str],
+    time_range: Option<&TimeRange>,
+    row_filter: Option<RowFilter<T>>,
+) -> Result<Vec<T>, arrow2::error::Error>
+where
+    T: ArrowDeserialize + ArrowSerialize + ArrowField<Type = T> + Default + Clone + 'static,
+    for<'a> &'a <T as ArrowDeserialize>::ArrayType: IntoIterator,
+    R: Read + Seek,
+{
+    let data_type = T::data_type();
+    let fields = StructArray::get_fields(&data_type);
+    if let Some(column) = columns
+        .iter()
+        .find(|column| !fields.iter().any(|field| field.name == **column))
+    {
+        return Err(arrow2::error::Error::InvalidArgumentError(format!(
+            "struct has no field {column}"
+        )));
+    }
+    let selected: Vec<&Field> = fields
+        .iter()
+        .filter(|field| columns.is_empty() || columns.contains(&field.name.as_str()))
+        .collect();
+    let projected = Field::new(
+        STRUCT_COLUMN,
+        DataType::Struct(
+            selected
+                .iter()
+                .map(|field| Field {
+                    data_type: parquet_data_type(&field.data_type),
+                    ..(*field).clone()
+                })
+                .collect(),
+        ),
+        true,
+    );
+
+    let metadata = read::read_metadata(&mut reader)?;
+    let mut items = Vec::new();
+    for row_group in &metadata.row_groups {
+        if let Some(time_range) = time_range {
+            if !overlaps_time_range(row_group, time_range)? {
+                continue;
+            }
+        }
+
+        let mut column_chunks = Vec::new();
+        for column in row_group.columns() {
+            let path = &column.descriptor().path_in_schema;
+            if path.len() > 1
+                && path[0] == STRUCT_COLUMN
+                && selected.iter().any(|field| field.name == path[1])
+            {
+                column_chunks.push(read_column_chunk(&mut reader, column)?);
+            }
+        }
+
+        let arrays = read::to_deserializer(
+            column_chunks,
+            projected.clone(),
+            row_group.num_rows(),
+            None,
+            None,
+        )?;
+        for array in arrays {
+            let array = array?;
+            let array = array
+                .as_any()
+                .downcast_ref::<StructArray>()
+                .expect("a struct field is read as a StructArray");
+
+            let mut defaults: Option<Box<dyn Array>> = None;
+            let mut values = Vec::with_capacity(fields.len());
+            for (i, field) in fields.iter().enumerate() {
+                match selected.iter().position(|f| f.name == field.name) {
+                    Some(j) => values.push(from_parquet_array(
+                        array.values()[j].as_ref(),
+                        &field.data_type,
+                    )?),
+                    None => {
+                        if defaults.is_none() {
+                            defaults = Some(vec![T::default(); array.len()].try_into_arrow()?);
+                        }
+                        let defaults = defaults.as_ref().unwrap();
+                        let defaults = defaults
+                            .as_any()
+                            .downcast_ref::<StructArray>()
+                            .expect("arrow2_convert serializes structs to a StructArray");
+                        values.push(defaults.values()[i].clone());
+                    }
+                }
+            }
+
+            let array = StructArray::try_new(data_type.clone(), values, array.validity().cloned())?;
+            let array: Vec<T> = (&array as &dyn Array).try_into_collection()?;
+            match &row_filter {
+                Some(row_filter) => items.extend(array.into_iter().filter(|item| row_filter(item))),
+                None => items.extend(array),
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Whether a row group's statistics for the time range's column don't rule out timestamps in the range
+fn overlaps_time_range(
+    row_group: &RowGroupMetaData,
+    time_range: &TimeRange,
+) -> Result<bool, arrow2::error::Error> {
+    let column = row_group
+        .columns()
+        .iter()
+        .find(|column| column.descriptor().path_in_schema == [STRUCT_COLUMN, &time_range.column])
+        .ok_or_else(|| {
+            arrow2::error::Error::InvalidArgumentError(format!(
+                "no column {} to filter by time",
+                time_range.column
+            ))
+        })?;
+    let statistics = match column.statistics() {
+        Some(statistics) => statistics?,
+        None => return Ok(true),
+    };
+    let statistics = statistics
+        .as_any()
+        .downcast_ref::<PrimitiveStatistics<i64>>()
+        .ok_or_else(|| {
+            arrow2::error::Error::InvalidArgumentError(format!(
+                "time column {} isn't 64 bit integers",
+                time_range.column
+            ))
+        })?;
+
+    Ok(match (statistics.min_value, statistics.max_value) {
+        (Some(min), Some(max)) => time_range.overlaps(min, max),
+        // Every timestamp is null, or the writer left them out
+        _ => true,
+    })
+}
+
+/// Read one column chunk's bytes, for `read::to_deserializer`
+fn read_column_chunk<'a, R: Read + Seek>(
+    reader: &mut R,
+    column: &'a ColumnChunkMetaData,
+) -> Result<(&'a ColumnChunkMetaData, Vec<u8>), arrow2::error::Error> {
+    let (start, length) = column.byte_range();
+    reader.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::with_capacity(length as usize);
+    reader.by_ref().take(length).read_to_end(&mut bytes)?;
+
+    Ok((column, bytes))
+}
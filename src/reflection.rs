@@ -0,0 +1,530 @@
+//! Arrow schemas generated from flatbuffer reflection schemas
+//!
+//! `flatc --schema --binary` compiles a measurement's `.fbs` schema to a binary `.bfbs` schema (itself a flatbuffer,
+//! see `flatbuffers/reflection.fbs`). `schema_from_bfbs` walks the `.bfbs` root table and maps every field to an
+//! arrow field, so archived flatbuffer measurements can be converted to parquet without hand-writing an
+//! `ArrowField` implementation per sensor.
+//!
+//! `validate_against_schema` checks a measurement's flatbuffer against the same `.bfbs` schema, i.e. to catch a
+//! producer writing an unexpected schema version before its measurements are archived.
+
+use arrow2::datatypes::{DataType, Field, Schema};
+use flatbuffers::InvalidFlatbuffer;
+
+use crate::reflection_generated::reflection::{self, BaseType, Object, Type};
+
+/// Name of the item field of the arrow lists that flatbuffer vectors and arrays map to
+pub const LIST_ITEM: &str = "item";
+
+/// Error for all issues converting flatbuffer reflection schemas to arrow schemas
+#[derive(thiserror::Error, Debug)]
+pub enum ReflectionError {
+    /// The bytes aren't a valid `.bfbs` reflection schema
+    #[error("Invalid flatbuffer reflection schema {0}")]
+    InvalidSchema(#[from] InvalidFlatbuffer),
+    /// The schema has no `root_type`, so there's no table to build the arrow schema from
+    #[error("Flatbuffer schema has no root table")]
+    NoRootTable,
+    /// A field refers to a table or struct that isn't in the schema
+    #[error("Field {field} refers to missing object {index}")]
+    MissingObject {
+        /// Name of the field
+        field: String,
+        /// Index of the object in the schema's objects
+        index: i32,
+    },
+    /// A field's type has no arrow equivalent, i.e. a union
+    #[error("Field {field} has unsupported flatbuffer type {base_type}")]
+    UnsupportedType {
+        /// Name of the field
+        field: String,
+        /// Name of the flatbuffer base type
+        base_type: String,
+    },
+    /// A table contains itself, directly or through other tables, which arrow schemas can't express
+    #[error("Table {0} is recursive")]
+    RecursiveTable(String),
+}
+
+/// Error for flatbuffers that don't match a reflection schema, see `validate_against_schema`
+#[derive(thiserror::Error, Debug)]
+pub enum SchemaError {
+    /// The schema bytes aren't a valid `.bfbs` reflection schema
+    #[error("Invalid flatbuffer reflection schema {0}")]
+    InvalidSchema(#[from] InvalidFlatbuffer),
+    /// The schema has no `root_type`, so there's no table to check the buffer against
+    #[error("Flatbuffer schema has no root table")]
+    NoRootTable,
+    /// A field refers to a table or struct that isn't in the schema
+    #[error("Field {field} refers to missing object {index}")]
+    MissingObject {
+        /// Path of the field
+        field: String,
+        /// Index of the object in the schema's objects
+        index: i32,
+    },
+    /// The buffer's file identifier isn't the one the schema declares
+    #[error("Flatbuffer file identifier {actual:?} doesn't match the schema's {expected:?}")]
+    FileIdentifierMismatch {
+        /// The schema's `file_identifier`
+        expected: String,
+        /// The buffer's 4 identifier bytes
+        actual: String,
+    },
+    /// A field of the buffer diverges from the schema
+    #[error("Field {field} doesn't match the schema: {reason}")]
+    FieldMismatch {
+        /// Path of the field from the root table, i.e. `Track.history[2].x`, or `Track.#5` for a field id the schema
+        /// doesn't have
+        field: String,
+        /// How the field diverges
+        reason: String,
+    },
+}
+
+/// Tables nested deeper than this in a buffer are rejected rather than checked, like flatbuffers' own verifier
+const MAX_TABLE_DEPTH: usize = 64;
+
+/// Arrow schema for the root table of a binary flatbuffer schema (`.bfbs`)
+///
+/// Every non-deprecated field of the root table becomes a top level arrow field, in declaration order. Flatbuffer
+/// types map to arrow types as follows:
+///
+/// - bool, byte, ubyte, short, ushort, int, uint, long, ulong, float, double: the arrow type of the same width.
+///   Enums map to their underlying integer type
+/// - string: `Utf8`
+/// - vector: `List` with non-nullable items named `LIST_ITEM`
+/// - fixed length array (in structs): `FixedSizeList` with non-nullable items
+/// - table and struct: `Struct`, recursing into its fields
+///
+/// Fields are nullable when the schema marks them optional: scalars declared with `= null`, and strings, vectors,
+/// and tables that aren't `(required)`. Scalars with a default and fields of structs are never null.
+///
+/// # Errors
+///
+/// - ReflectionError::InvalidSchema: If `bytes` isn't a reflection schema flatbuffer
+/// - ReflectionError::NoRootTable: If the schema has no root table
+/// - ReflectionError::MissingObject: If a field refers to an object that isn't in the schema
+/// - ReflectionError::UnsupportedType: If a field is a union
+/// - ReflectionError::RecursiveTable: If a table contains itself
+///
+/// # Examples
+///
+/// ```no_run
+/// let bfbs = std::fs::read("flatbuffers/radar_2d.bfbs")?;
+/// let schema = schema_from_bfbs(&bfbs)?;
+/// ```
+pub fn schema_from_bfbs(bytes: &[u8]) -> Result<Schema, ReflectionError> {
+    let schema = reflection::root_as_schema(bytes)?;
+    let root = schema.root_table().ok_or(ReflectionError::NoRootTable)?;
+
+    Ok(Schema::from(object_fields(&schema, &root, &mut vec![])?))
+}
+
+/// Arrow fields for an object's non-deprecated fields, in declaration order
+///
+/// `parents` are the names of the tables being converted that contain this object, to detect recursion.
+fn object_fields<'a>(
+    schema: &reflection::Schema<'a>,
+    object: &Object<'a>,
+    parents: &mut Vec<&'a str>,
+) -> Result<Vec<Field>, ReflectionError> {
+    if parents.contains(&object.name()) {
+        return Err(ReflectionError::RecursiveTable(object.name().to_owned()));
+    }
+    parents.push(object.name());
+
+    // Reflection schemas store fields sorted by name, ids are in declaration order
+    let mut fields: Vec<reflection::Field> =
+        object.fields().iter().filter(|f| !f.deprecated()).collect();
+    fields.sort_by_key(|f| f.id());
+
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let data_type = field_type(schema, field, &field.type_(), parents)?;
+            let nullable = !object.is_struct() && field.optional();
+            Ok(Field::new(field.name(), data_type, nullable))
+        })
+        .collect::<Result<Vec<_>, ReflectionError>>()?;
+
+    parents.pop();
+    Ok(fields)
+}
+
+/// Arrow type of a field, including vectors and arrays
+fn field_type<'a>(
+    schema: &reflection::Schema<'a>,
+    field: &reflection::Field<'a>,
+    type_: &Type<'a>,
+    parents: &mut Vec<&'a str>,
+) -> Result<DataType, ReflectionError> {
+    match type_.base_type() {
+        BaseType::Vector => {
+            let item = element_type(schema, field, type_.element(), type_.index(), parents)?;
+            Ok(DataType::List(Box::new(Field::new(LIST_ITEM, item, false))))
+        }
+        BaseType::Array => {
+            let item = element_type(schema, field, type_.element(), type_.index(), parents)?;
+            Ok(DataType::FixedSizeList(
+                Box::new(Field::new(LIST_ITEM, item, false)),
+                type_.fixed_length() as usize,
+            ))
+        }
+        base_type => element_type(schema, field, base_type, type_.index(), parents),
+    }
+}
+
+/// Arrow type of a single (non-vector) flatbuffer value
+///
+/// `index` is the object index for tables and structs, or the enum index for enums
+fn element_type<'a>(
+    schema: &reflection::Schema<'a>,
+    field: &reflection::Field<'a>,
+    base_type: BaseType,
+    index: i32,
+    parents: &mut Vec<&'a str>,
+) -> Result<DataType, ReflectionError> {
+    let data_type = match base_type {
+        BaseType::Bool => DataType::Boolean,
+        BaseType::Byte => DataType::Int8,
+        BaseType::UByte => DataType::UInt8,
+        BaseType::Short => DataType::Int16,
+        BaseType::UShort => DataType::UInt16,
+        BaseType::Int => DataType::Int32,
+        BaseType::UInt => DataType::UInt32,
+        BaseType::Long => DataType::Int64,
+        BaseType::ULong => DataType::UInt64,
+        BaseType::Float => DataType::Float32,
+        BaseType::Double => DataType::Float64,
+        BaseType::String => DataType::Utf8,
+        BaseType::Obj => {
+            let objects = schema.objects();
+            if index < 0 || index as usize >= objects.len() {
+                return Err(ReflectionError::MissingObject {
+                    field: field.name().to_owned(),
+                    index,
+                });
+            }
+            DataType::Struct(object_fields(
+                schema,
+                &objects.get(index as usize),
+                parents,
+            )?)
+        }
+        base_type => {
+            return Err(ReflectionError::UnsupportedType {
+                field: field.name().to_owned(),
+                base_type: base_type.variant_name().unwrap_or("unknown").to_owned(),
+            })
+        }
+    };
+
+    Ok(data_type)
+}
+
+/// Check that a flatbuffer is a root table of a binary flatbuffer schema (`.bfbs`)
+///
+/// Flatbuffers aren't self-describing, so this checks that the buffer's layout is one the schema allows: if the
+/// schema declares a `file_identifier`, the buffer has it; every field present in the buffer is in the schema;
+/// `(required)` fields are present; every scalar and struct fits in its table and is aligned for its type; and every
+/// string, vector, and table a field points to is in the buffer, with strings valid UTF-8 and tables checked
+/// recursively. Deprecated fields and the contents of unions aren't checked. A value of the wrong type but the same
+/// size, i.e. an int where the schema has a float, can't be told apart.
+///
+/// # Errors
+///
+/// - SchemaError::InvalidSchema: If `schema_bfbs` isn't a reflection schema flatbuffer
+/// - SchemaError::NoRootTable: If the schema has no root table
+/// - SchemaError::MissingObject: If a field refers to an object that isn't in the schema
+/// - SchemaError::FileIdentifierMismatch: If the buffer doesn't have the schema's file identifier
+/// - SchemaError::FieldMismatch: For the first field of the buffer that diverges from the schema
+///
+/// # Examples
+///
+/// ```no_run
+/// let bfbs = std::fs::read("flatbuffers/radar_2d.bfbs")?;
+/// validate_against_schema(message.payload().unwrap_or_default(), &bfbs)?;
+/// ```
+pub fn validate_against_schema(bytes: &[u8], schema_bfbs: &[u8]) -> Result<(), SchemaError> {
+    let schema = reflection::root_as_schema(schema_bfbs)?;
+    let root = schema.root_table().ok_or(SchemaError::NoRootTable)?;
+
+    if let Some(expected) = schema.file_ident().filter(|ident| !ident.is_empty()) {
+        let actual = bytes.get(4..8).unwrap_or_default();
+        if actual != expected.as_bytes() {
+            return Err(SchemaError::FileIdentifierMismatch {
+                expected: expected.to_owned(),
+                actual: String::from_utf8_lossy(actual).into_owned(),
+            });
+        }
+    }
+
+    let table = follow_offset(bytes, 0, root.name())?;
+    check_table(&schema, bytes, &root, table, root.name(), 0)
+}
+
+/// Check the table at `pos` has only fields of `object`, and that each of them matches its type
+fn check_table(
+    schema: &reflection::Schema,
+    bytes: &[u8],
+    object: &Object,
+    pos: usize,
+    path: &str,
+    depth: usize,
+) -> Result<(), SchemaError> {
+    if depth >= MAX_TABLE_DEPTH {
+        return Err(mismatch(
+            path,
+            format!("tables are nested more than {} deep", MAX_TABLE_DEPTH),
+        ));
+    }
+    let vtable = read_i32(bytes, pos)
+        .and_then(|soffset| usize::try_from(pos as i64 - soffset as i64).ok())
+        .ok_or_else(|| mismatch(path, "table is out of the buffer"))?;
+    let (vtable_len, table_len) = match (read_u16(bytes, vtable), read_u16(bytes, vtable + 2)) {
+        (Some(vtable_len), Some(table_len))
+            if vtable_len >= 4
+                && vtable + vtable_len as usize <= bytes.len()
+                && pos + table_len as usize <= bytes.len() =>
+        {
+            (vtable_len as usize, table_len as usize)
+        }
+        _ => return Err(mismatch(path, "table's vtable is out of the buffer")),
+    };
+    // Offset of each field from the start of the table, 0 for fields that aren't present
+    let slots: Vec<usize> = (vtable + 4..vtable + vtable_len)
+        .step_by(2)
+        .map(|slot| read_u16(bytes, slot).unwrap_or_default() as usize)
+        .collect();
+
+    let fields = object.fields();
+    for (id, offset) in slots.iter().enumerate() {
+        if *offset != 0 && !fields.iter().any(|field| field.id() as usize == id) {
+            return Err(mismatch(
+                &format!("{}.#{}", path, id),
+                "the buffer has a field the schema doesn't",
+            ));
+        }
+    }
+
+    for field in fields.iter().filter(|field| !field.deprecated()) {
+        let field_path = format!("{}.{}", path, field.name());
+        let offset = slots.get(field.id() as usize).copied().unwrap_or_default();
+        if offset == 0 {
+            if field.required() {
+                return Err(mismatch(&field_path, "required field is missing"));
+            }
+            continue;
+        }
+        if offset >= table_len {
+            return Err(mismatch(&field_path, "field is past the end of its table"));
+        }
+        check_field(
+            schema,
+            bytes,
+            &field.type_(),
+            pos + offset,
+            pos + table_len,
+            &field_path,
+            depth,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check the value of a table field at `pos`, which must end by `table_end`
+fn check_field(
+    schema: &reflection::Schema,
+    bytes: &[u8],
+    type_: &Type,
+    pos: usize,
+    table_end: usize,
+    path: &str,
+    depth: usize,
+) -> Result<(), SchemaError> {
+    let (size, align) = inline_layout(schema, type_.base_type(), type_.index(), path)?;
+    if pos + size > table_end {
+        return Err(mismatch(
+            path,
+            format!("{} byte value extends past the end of its table", size),
+        ));
+    }
+    if pos % align != 0 {
+        return Err(mismatch(
+            path,
+            format!("value isn't aligned to {} bytes", align),
+        ));
+    }
+
+    match type_.base_type() {
+        BaseType::String => check_string(bytes, follow_offset(bytes, pos, path)?, path),
+        BaseType::Vector => {
+            let vector = follow_offset(bytes, pos, path)?;
+            check_vector(schema, bytes, type_, vector, path, depth)
+        }
+        BaseType::Obj => {
+            let object = schema_object(schema, type_.index(), path)?;
+            if object.is_struct() {
+                return Ok(());
+            }
+            let table = follow_offset(bytes, pos, path)?;
+            check_table(schema, bytes, &object, table, path, depth + 1)
+        }
+        BaseType::Union => follow_offset(bytes, pos, path).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// Check the vector at `pos` is in the buffer, and each of its strings and tables matches the schema
+fn check_vector(
+    schema: &reflection::Schema,
+    bytes: &[u8],
+    type_: &Type,
+    pos: usize,
+    path: &str,
+    depth: usize,
+) -> Result<(), SchemaError> {
+    let (size, align) = inline_layout(schema, type_.element(), type_.index(), path)?;
+    let len = read_u32(bytes, pos).ok_or_else(|| mismatch(path, "vector is out of the buffer"))?;
+    let start = pos + 4;
+    match (len as usize).checked_mul(size) {
+        Some(vector_size) if start + vector_size <= bytes.len() => {}
+        _ => return Err(mismatch(path, "vector is out of the buffer")),
+    }
+    if start % align != 0 {
+        return Err(mismatch(
+            path,
+            format!("vector isn't aligned to {} bytes", align),
+        ));
+    }
+
+    for i in 0..len as usize {
+        let element = start + i * size;
+        let element_path = format!("{}[{}]", path, i);
+        match type_.element() {
+            BaseType::String => check_string(
+                bytes,
+                follow_offset(bytes, element, &element_path)?,
+                &element_path,
+            )?,
+            BaseType::Obj => {
+                let object = schema_object(schema, type_.index(), path)?;
+                if object.is_struct() {
+                    break;
+                }
+                let table = follow_offset(bytes, element, &element_path)?;
+                check_table(schema, bytes, &object, table, &element_path, depth + 1)?;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the string at `pos` is in the buffer, null terminated, and valid UTF-8
+fn check_string(bytes: &[u8], pos: usize, path: &str) -> Result<(), SchemaError> {
+    let start = pos + 4;
+    let end = read_u32(bytes, pos)
+        .map(|len| start + len as usize)
+        .filter(|end| *end < bytes.len())
+        .ok_or_else(|| mismatch(path, "string is out of the buffer"))?;
+    if bytes[end] != 0 {
+        return Err(mismatch(path, "string is missing its null terminator"));
+    }
+    std::str::from_utf8(&bytes[start..end])
+        .map_err(|e| mismatch(path, format!("string isn't valid UTF-8: {}", e)))?;
+
+    Ok(())
+}
+
+/// Size and alignment of a value of `base_type` stored inline in a table, vector, or struct
+///
+/// Strings, vectors, unions, and tables are stored as a 4 byte offset to the value. `index` is the object index of
+/// tables and structs.
+fn inline_layout(
+    schema: &reflection::Schema,
+    base_type: BaseType,
+    index: i32,
+    path: &str,
+) -> Result<(usize, usize), SchemaError> {
+    let size = match base_type {
+        BaseType::Bool | BaseType::Byte | BaseType::UByte | BaseType::UType => 1,
+        BaseType::Short | BaseType::UShort => 2,
+        BaseType::Int | BaseType::UInt | BaseType::Float => 4,
+        BaseType::Long | BaseType::ULong | BaseType::Double => 8,
+        BaseType::String | BaseType::Vector | BaseType::Union => 4,
+        BaseType::Obj => {
+            let object = schema_object(schema, index, path)?;
+            if object.is_struct() {
+                return Ok((
+                    object.bytesize().max(0) as usize,
+                    object.minalign().max(1) as usize,
+                ));
+            }
+            4
+        }
+        base_type => {
+            return Err(mismatch(
+                path,
+                format!(
+                    "unsupported flatbuffer type {}",
+                    base_type.variant_name().unwrap_or("unknown")
+                ),
+            ))
+        }
+    };
+
+    Ok((size, size))
+}
+
+/// The schema's object at `index`, the table or struct a field refers to
+fn schema_object<'a>(
+    schema: &reflection::Schema<'a>,
+    index: i32,
+    path: &str,
+) -> Result<Object<'a>, SchemaError> {
+    let objects = schema.objects();
+    if index < 0 || index as usize >= objects.len() {
+        return Err(SchemaError::MissingObject {
+            field: path.to_owned(),
+            index,
+        });
+    }
+    Ok(objects.get(index as usize))
+}
+
+/// Position the offset at `pos` points to
+fn follow_offset(bytes: &[u8], pos: usize, path: &str) -> Result<usize, SchemaError> {
+    read_u32(bytes, pos)
+        .map(|offset| pos + offset as usize)
+        .filter(|target| *target < bytes.len())
+        .ok_or_else(|| mismatch(path, "offset points out of the buffer"))
+}
+
+fn mismatch(field: &str, reason: impl Into<String>) -> SchemaError {
+    SchemaError::FieldMismatch {
+        field: field.to_owned(),
+        reason: reason.into(),
+    }
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(pos..pos + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(pos..pos + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_i32(bytes: &[u8], pos: usize) -> Option<i32> {
+    Some(i32::from_le_bytes(
+        bytes.get(pos..pos + 4)?.try_into().ok()?,
+    ))
+}
@@ -0,0 +1,485 @@
+//! Client for Confluent-compatible schema registries (Confluent Schema Registry, Apicurio, Redpanda)
+//!
+//! Measurements are registered under the `{TOPIC_NAME}-value` subject (the registry's default topic name
+//! strategy), and produced in the Confluent wire format: a zero magic byte, the 4 byte big-endian schema ID, then the
+//! serialized measurement. Schema IDs and schemas are cached, so the registry is only hit once per schema and ID.
+//!
+//! `SchemaRegistry` pairs a client with the schema each measurement is registered with, and `RegistryMeasurement`
+//! adds `to_message_with_registry`/`from_message_with_registry` to every Measurement.
+//!
+//! The payload after the header is whatever the Measurement's `Codec` writes, FlatBuffers for `FlatBufferCodec`. The
+//! registry doesn't convert it, so the standard Confluent deserializers can only decode it if the Codec writes the
+//! registered schema type, i.e. a `MeasurementCodec` built on `avro::to_datum` for a `SchemaType::Avro` schema.
+//!
+//! Confluent's registry only accepts Avro, Protobuf, and JSON schemas. Register flatbuffer schemas with a registry
+//! that supports arbitrary schema types (i.e. Apicurio), or register an equivalent Avro schema.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::measurement::Measurement;
+use redpanda::message::{BorrowedMessage, Message};
+use redpanda::producer::RedpandaRecord;
+
+/// First byte of every message in the Confluent wire format
+pub const MAGIC_BYTE: u8 = 0;
+
+/// Length of the Confluent wire format header (magic byte + schema ID)
+pub const HEADER_LEN: usize = 5;
+
+/// Error for all schema registry issues
+#[derive(thiserror::Error, Debug)]
+pub enum RegistryError {
+    /// Wrap HTTP errors talking to the registry
+    #[error("Schema registry request failed {0}")]
+    HttpError(#[from] reqwest::Error),
+    /// The registry answered with an error status
+    #[error("Schema registry returned {status}: {message}")]
+    RegistryError {
+        /// HTTP status code
+        status: u16,
+        /// Error message from the registry
+        message: String,
+    },
+    /// A message isn't in the Confluent wire format
+    #[error("Message is not in the Confluent wire format: {0}")]
+    WireFormatError(String),
+    /// The payload after the wire format header isn't a valid measurement
+    #[error("Failed to deserialize registered measurement: {0}")]
+    DeserializeError(String),
+}
+
+/// Type of a registered schema, as named by the registry's `schemaType`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SchemaType {
+    /// Apache Avro schema
+    Avro,
+    /// Protocol Buffers schema
+    Protobuf,
+    /// JSON schema
+    Json,
+    /// FlatBuffers schema (`.fbs`), not supported by Confluent's registry
+    Flatbuffers,
+}
+
+/// Subject a Measurement's schema is registered under
+pub fn subject<M>() -> String
+where
+    M: for<'a> Measurement<'a>,
+{
+    format!("{}-value", M::TOPIC_NAME)
+}
+
+/// Prefix a serialized measurement with the Confluent wire format header for `schema_id`
+pub fn encode(schema_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.push(MAGIC_BYTE);
+    bytes.extend_from_slice(&schema_id.to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Split a Confluent wire format message into its schema ID and serialized measurement
+///
+/// # Errors
+///
+/// - RegistryError::WireFormatError: If the message is shorter than the header or doesn't start with the magic byte
+pub fn decode(bytes: &[u8]) -> Result<(u32, &[u8]), RegistryError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(RegistryError::WireFormatError(format!(
+            "{} bytes is shorter than the {} byte header",
+            bytes.len(),
+            HEADER_LEN
+        )));
+    }
+    if bytes[0] != MAGIC_BYTE {
+        return Err(RegistryError::WireFormatError(format!(
+            "unknown magic byte {}",
+            bytes[0]
+        )));
+    }
+
+    let schema_id = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+    Ok((schema_id, &bytes[HEADER_LEN..]))
+}
+
+/// Serialize a Measurement to a Kafka message like `Measurement::to_message`, prefixed with `schema_id`
+pub fn to_message_registered<M>(measurement: M, schema_id: u32) -> RedpandaRecord
+where
+    M: for<'a> Measurement<'a>,
+{
+    let key = measurement.message_key();
+    let payload = encode(schema_id, &measurement.to_bytes());
+    RedpandaRecord::new(M::TOPIC_NAME, key, payload, None)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RegisterRequest<'a> {
+    schema: &'a str,
+    schema_type: SchemaType,
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+}
+
+#[derive(Deserialize)]
+struct SubjectVersionResponse {
+    id: u32,
+    schema: String,
+}
+
+#[derive(Deserialize)]
+struct SubjectVersion {
+    subject: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Client for a Confluent-compatible schema registry's REST API that caches schema IDs and schemas
+pub struct SchemaRegistryClient {
+    url: String,
+    http: reqwest::Client,
+    /// IDs returned by `register`, keyed by subject and schema
+    registered: Mutex<HashMap<(String, String), u32>>,
+    /// IDs returned by `latest_id`, keyed by subject
+    latest: Mutex<HashMap<String, u32>>,
+    schemas: Mutex<HashMap<u32, String>>,
+    /// Subject and schema ID pairs known to be registered
+    versions: Mutex<HashSet<(String, u32)>>,
+}
+
+impl SchemaRegistryClient {
+    /// Client for the registry at `url`, i.e. `http://localhost:8081`
+    pub fn new(url: &str) -> Self {
+        SchemaRegistryClient {
+            url: url.trim_end_matches('/').to_owned(),
+            http: reqwest::Client::new(),
+            registered: Mutex::new(HashMap::new()),
+            latest: Mutex::new(HashMap::new()),
+            schemas: Mutex::new(HashMap::new()),
+            versions: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Register `schema` under `subject`, returning its schema ID
+    ///
+    /// Registering a schema that's already registered returns the existing ID. IDs are cached per subject and
+    /// schema, so only the first call for a schema goes to the registry, and registering a new version of the
+    /// schema under the same subject returns the new version's ID.
+    ///
+    /// # Errors
+    ///
+    /// - RegistryError::HttpError: If the registry can't be reached or its response can't be parsed
+    /// - RegistryError::RegistryError: If the registry rejects the schema, i.e. it's incompatible with the
+    ///   subject's previous version
+    pub async fn register(
+        &self,
+        subject: &str,
+        schema: &str,
+        schema_type: SchemaType,
+    ) -> Result<u32, RegistryError> {
+        let key = (subject.to_owned(), schema.to_owned());
+        if let Some(id) = self.registered.lock().unwrap().get(&key) {
+            return Ok(*id);
+        }
+
+        let response = self
+            .http
+            .post(format!("{}/subjects/{}/versions", self.url, subject))
+            .header("Content-Type", "application/vnd.schemaregistry.v1+json")
+            .json(&RegisterRequest {
+                schema,
+                schema_type,
+            })
+            .send()
+            .await?;
+        let id = Self::parse::<RegisterResponse>(response).await?.id;
+
+        self.registered.lock().unwrap().insert(key, id);
+        self.schemas.lock().unwrap().insert(id, schema.to_owned());
+        self.versions
+            .lock()
+            .unwrap()
+            .insert((subject.to_owned(), id));
+
+        Ok(id)
+    }
+
+    /// Register a Measurement's schema under its subject (see `subject`), returning its schema ID
+    ///
+    /// # Errors
+    ///
+    /// - Any error returned by `SchemaRegistryClient::register`
+    pub async fn register_measurement<M>(
+        &self,
+        schema: &str,
+        schema_type: SchemaType,
+    ) -> Result<u32, RegistryError>
+    where
+        M: for<'a> Measurement<'a>,
+    {
+        self.register(&subject::<M>(), schema, schema_type).await
+    }
+
+    /// Fetch the schema registered with `schema_id`, i.e. the ID decoded from a consumed message
+    ///
+    /// # Errors
+    ///
+    /// - RegistryError::HttpError: If the registry can't be reached or its response can't be parsed
+    /// - RegistryError::RegistryError: If no schema has that ID
+    pub async fn schema(&self, schema_id: u32) -> Result<String, RegistryError> {
+        if let Some(schema) = self.schemas.lock().unwrap().get(&schema_id) {
+            return Ok(schema.clone());
+        }
+
+        let response = self
+            .http
+            .get(format!("{}/schemas/ids/{}", self.url, schema_id))
+            .send()
+            .await?;
+        let schema = Self::parse::<SchemaResponse>(response).await?.schema;
+
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert(schema_id, schema.clone());
+
+        Ok(schema)
+    }
+
+    /// ID of the latest schema registered under `subject`
+    ///
+    /// IDs are cached per subject, so a schema registered under the subject after the first call isn't picked up
+    /// until the client is recreated.
+    ///
+    /// # Errors
+    ///
+    /// - RegistryError::HttpError: If the registry can't be reached or its response can't be parsed
+    /// - RegistryError::RegistryError: If nothing is registered under the subject
+    pub async fn latest_id(&self, subject: &str) -> Result<u32, RegistryError> {
+        if let Some(id) = self.latest.lock().unwrap().get(subject) {
+            return Ok(*id);
+        }
+
+        let response = self
+            .http
+            .get(format!("{}/subjects/{}/versions/latest", self.url, subject))
+            .send()
+            .await?;
+        let latest = Self::parse::<SubjectVersionResponse>(response).await?;
+
+        self.latest
+            .lock()
+            .unwrap()
+            .insert(subject.to_owned(), latest.id);
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert(latest.id, latest.schema);
+        self.versions
+            .lock()
+            .unwrap()
+            .insert((subject.to_owned(), latest.id));
+
+        Ok(latest.id)
+    }
+
+    /// Whether `schema_id` is one of the versions registered under `subject`
+    ///
+    /// Only matches are cached, so an ID that's registered under the subject later is picked up on the next call.
+    ///
+    /// # Errors
+    ///
+    /// - RegistryError::HttpError: If the registry can't be reached or its response can't be parsed
+    /// - RegistryError::RegistryError: If no schema has that ID
+    pub async fn is_version_of(
+        &self,
+        subject: &str,
+        schema_id: u32,
+    ) -> Result<bool, RegistryError> {
+        let key = (subject.to_owned(), schema_id);
+        if self.versions.lock().unwrap().contains(&key) {
+            return Ok(true);
+        }
+
+        let response = self
+            .http
+            .get(format!("{}/schemas/ids/{}/versions", self.url, schema_id))
+            .send()
+            .await?;
+        let registered = Self::parse::<Vec<SubjectVersion>>(response)
+            .await?
+            .iter()
+            .any(|v| v.subject == subject);
+
+        if registered {
+            self.versions.lock().unwrap().insert(key);
+        }
+
+        Ok(registered)
+    }
+
+    async fn parse<T>(response: reqwest::Response) -> Result<T, RegistryError>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+
+        let message = match response.json::<ErrorResponse>().await {
+            Ok(e) => e.message,
+            Err(_) => status.canonical_reason().unwrap_or_default().to_owned(),
+        };
+        Err(RegistryError::RegistryError {
+            status: status.as_u16(),
+            message,
+        })
+    }
+}
+
+/// Schema registry client along with the schema each Measurement type is registered with
+///
+/// Measurements with a schema (see `with_schema`) have it registered the first time they're produced. Measurements
+/// without one use the latest schema already registered under their subject, i.e. by a deployment pipeline.
+///
+/// # Examples
+///
+/// ```no_run
+/// let registry = SchemaRegistry::new(SchemaRegistryClient::new("http://localhost:8081"))
+///     .with_schema::<RadarMeasurement2d>(RADAR_AVRO_SCHEMA, SchemaType::Avro);
+/// let record = measurement.to_message_with_registry(&registry).await?;
+/// ```
+pub struct SchemaRegistry {
+    client: SchemaRegistryClient,
+    /// Schema to register, keyed by subject
+    schemas: HashMap<String, (String, SchemaType)>,
+}
+
+impl SchemaRegistry {
+    /// Registry wrapper around `client` without any schemas to register
+    pub fn new(client: SchemaRegistryClient) -> Self {
+        SchemaRegistry {
+            client,
+            schemas: HashMap::new(),
+        }
+    }
+
+    /// Register `schema` under a Measurement's subject the first time the Measurement is produced
+    pub fn with_schema<M>(mut self, schema: &str, schema_type: SchemaType) -> Self
+    where
+        M: for<'a> Measurement<'a>,
+    {
+        self.schemas
+            .insert(subject::<M>(), (schema.to_owned(), schema_type));
+        self
+    }
+
+    /// Underlying client, i.e. to fetch schemas by ID
+    pub fn client(&self) -> &SchemaRegistryClient {
+        &self.client
+    }
+
+    /// Schema ID to produce a Measurement with
+    ///
+    /// Registers the Measurement's schema if one was given with `with_schema`, otherwise looks up the latest schema
+    /// registered under its subject. Either way the ID is cached after the first call.
+    ///
+    /// # Errors
+    ///
+    /// - Any error returned by `SchemaRegistryClient::register` or `SchemaRegistryClient::latest_id`
+    pub async fn schema_id<M>(&self) -> Result<u32, RegistryError>
+    where
+        M: for<'a> Measurement<'a>,
+    {
+        let subject = subject::<M>();
+        match self.schemas.get(&subject) {
+            Some((schema, schema_type)) => {
+                self.client.register(&subject, schema, *schema_type).await
+            }
+            None => self.client.latest_id(&subject).await,
+        }
+    }
+}
+
+/// Produce and consume Measurements in the Confluent wire format through a `SchemaRegistry`
+///
+/// Implemented for every Measurement.
+#[async_trait::async_trait]
+pub trait RegistryMeasurement: for<'a> Measurement<'a> + Send + Sized {
+    /// Serialize to a Kafka message like `Measurement::to_message`, prefixed with the Measurement's schema ID
+    ///
+    /// # Errors
+    ///
+    /// - Any error returned by `SchemaRegistry::schema_id`
+    async fn to_message_with_registry(
+        self,
+        registry: &SchemaRegistry,
+    ) -> Result<RedpandaRecord, RegistryError> {
+        let schema_id = registry.schema_id::<Self>().await?;
+        Ok(to_message_registered(self, schema_id))
+    }
+
+    /// Deserialize a Confluent wire format payload, validating its header and schema ID
+    ///
+    /// The schema ID must be registered under the Measurement's subject (see `subject`), but can be any version of
+    /// the schema, not just the latest.
+    ///
+    /// # Errors
+    ///
+    /// - RegistryError::WireFormatError: If the payload isn't in the Confluent wire format, or its schema ID isn't
+    ///   registered under the Measurement's subject
+    /// - RegistryError::RegistryError: If the registry doesn't know the payload's schema ID
+    /// - RegistryError::HttpError: If the registry can't be reached
+    /// - RegistryError::DeserializeError: If the payload isn't a valid measurement or fails `Measurement::validate`
+    async fn from_bytes_with_registry(
+        bytes: &[u8],
+        registry: &SchemaRegistry,
+    ) -> Result<Self, RegistryError> {
+        let (schema_id, payload) = decode(bytes)?;
+        let subject = subject::<Self>();
+        if !registry.client().is_version_of(&subject, schema_id).await? {
+            return Err(RegistryError::WireFormatError(format!(
+                "schema ID {} isn't registered under {}",
+                schema_id, subject
+            )));
+        }
+
+        Self::from_bytes(payload).map_err(|e| RegistryError::DeserializeError(e.to_string()))
+    }
+
+    /// Deserialize a consumed Kafka message produced with `to_message_with_registry`
+    ///
+    /// # Errors
+    ///
+    /// - RegistryError::WireFormatError: If the message has no payload
+    /// - Any error returned by `RegistryMeasurement::from_bytes_with_registry`
+    async fn from_message_with_registry(
+        message: BorrowedMessage<'_>,
+        registry: &SchemaRegistry,
+    ) -> Result<Self, RegistryError> {
+        match message.payload() {
+            Some(bytes) => Self::from_bytes_with_registry(bytes, registry).await,
+            None => Err(RegistryError::WireFormatError(
+                "message has no payload".to_owned(),
+            )),
+        }
+    }
+}
+
+impl<M> RegistryMeasurement for M where M: for<'a> Measurement<'a> + Send {}
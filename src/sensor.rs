@@ -1,8 +1,242 @@
 //! Generic OpenSensor Sensor for producing sensor measurements from a Transducer to the OpenSensor stack
 
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use crate::error::SensorError;
-use crate::measurement::Measurement;
-use redpanda::{error::KafkaError, producer::DeliveryFuture};
+use crate::measurement::{Measurement, TimestampSource};
+use crate::transducer::{ConnectionStatus, FrameBuffer, TransducerHealth};
+use redpanda::{
+    error::KafkaError,
+    producer::{DeliveryFuture, Producer, RedpandaRecord},
+    RedpandaBuilder, RedpandaProducer,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{event, Level};
+
+/// How long the default `Sensor::run_until` waits for queued measurements to be delivered after shutdown
+pub const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many in-flight requests librdkafka allows per connection with idempotence enabled
+const MAX_IDEMPOTENT_IN_FLIGHT: u32 = 5;
+
+/// Number of broker acknowledgements a produce request waits for (`acks`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Acks {
+    /// Don't wait for the broker at all (`acks=0`)
+    None,
+    /// Wait for the partition leader only (`acks=1`)
+    Leader,
+    /// Wait for every in-sync replica (`acks=all`)
+    All,
+}
+
+impl Acks {
+    /// Value of the librdkafka `acks` setting
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Acks::None => "0",
+            Acks::Leader => "1",
+            Acks::All => "all",
+        }
+    }
+}
+
+/// Delivery guarantee a Sensor needs for its measurements
+///
+/// `acks`, `retries`, `enable.idempotence`, and `max.in.flight` depend on each other, so sensors pick one of these
+/// levels instead of setting them by hand.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeliveryGuarantee {
+    /// Fire and forget: no acks and no retries. Fine for high rate telemetry where a dropped measurement is
+    /// replaced by the next one
+    BestEffort,
+    /// Wait for every in-sync replica and retry until delivered. A retry can duplicate or reorder measurements
+    #[default]
+    AtLeastOnce,
+    /// At-least-once plus the idempotent producer, so retries never duplicate or reorder measurements within a
+    /// partition
+    Strongest,
+}
+
+impl DeliveryGuarantee {
+    /// Producer settings for this delivery guarantee
+    pub fn producer_settings(&self) -> ProducerSettings {
+        match self {
+            DeliveryGuarantee::BestEffort => ProducerSettings {
+                acks: Acks::None,
+                retries: 0,
+                enable_idempotence: false,
+                max_in_flight: MAX_IDEMPOTENT_IN_FLIGHT,
+            },
+            DeliveryGuarantee::AtLeastOnce => ProducerSettings {
+                acks: Acks::All,
+                retries: i32::MAX as u32,
+                enable_idempotence: false,
+                max_in_flight: MAX_IDEMPOTENT_IN_FLIGHT,
+            },
+            DeliveryGuarantee::Strongest => ProducerSettings {
+                acks: Acks::All,
+                retries: i32::MAX as u32,
+                enable_idempotence: true,
+                max_in_flight: MAX_IDEMPOTENT_IN_FLIGHT,
+            },
+        }
+    }
+}
+
+/// Delivery related librdkafka producer settings
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProducerSettings {
+    /// `acks`
+    pub acks: Acks,
+    /// `retries`
+    pub retries: u32,
+    /// `enable.idempotence`
+    pub enable_idempotence: bool,
+    /// `max.in.flight`
+    pub max_in_flight: u32,
+}
+
+impl ProducerSettings {
+    /// Check the settings are a combination librdkafka accepts and that actually gives the expected guarantee
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::ConfigError: If `max_in_flight` is 0, `retries` doesn't fit in librdkafka's i32, or
+    ///   idempotence is enabled without `acks=all`, at least one retry, and at most 5 in-flight requests
+    pub fn validate(&self) -> Result<(), SensorError> {
+        if self.max_in_flight == 0 {
+            return Err(SensorError::ConfigError(
+                "max.in.flight must be at least 1".to_owned(),
+            ));
+        }
+        if self.retries > i32::MAX as u32 {
+            return Err(SensorError::ConfigError(format!(
+                "retries must be at most {}",
+                i32::MAX
+            )));
+        }
+        if self.enable_idempotence {
+            if self.acks != Acks::All {
+                return Err(SensorError::ConfigError(format!(
+                    "enable.idempotence requires acks=all, not acks={}",
+                    self.acks.as_str()
+                )));
+            }
+            if self.retries == 0 {
+                return Err(SensorError::ConfigError(
+                    "enable.idempotence requires retries > 0".to_owned(),
+                ));
+            }
+            if self.max_in_flight > MAX_IDEMPOTENT_IN_FLIGHT {
+                return Err(SensorError::ConfigError(format!(
+                    "enable.idempotence requires max.in.flight <= {}",
+                    MAX_IDEMPOTENT_IN_FLIGHT
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the settings and set them on a producer builder
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::ConfigError: If the settings fail `ProducerSettings::validate`
+    pub fn apply(&self, builder: &mut RedpandaBuilder) -> Result<(), SensorError> {
+        self.validate()?;
+
+        builder.set("acks", self.acks.as_str());
+        builder.set("retries", &self.retries.to_string());
+        builder.set("enable.idempotence", &self.enable_idempotence.to_string());
+        builder.set("max.in.flight", &self.max_in_flight.to_string());
+
+        Ok(())
+    }
+}
+
+/// Token bucket limiting how fast a Sensor produces measurements, see `Sensor::rate_limiter`
+///
+/// The bucket holds up to one second of measurements and refills continuously, so a Sensor can burst after being
+/// idle but never averages more than `max_per_sec`. A runaway read loop is held to the cap instead of flooding
+/// Redpanda and starving other producers.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_per_sec: u32,
+    bucket: Mutex<TokenBucket>,
+}
+
+/// Tokens left in a `RateLimiter`, as of the last refill
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Rate limiter allowing `max_per_sec` measurements per second, starting with a full bucket
+    ///
+    /// Sensors configured with `max_measurements_per_sec: Option<u32>` build theirs with
+    /// `max_measurements_per_sec.map(RateLimiter::new).transpose()?`.
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::ConfigError: If `max_per_sec` is 0
+    pub fn new(max_per_sec: u32) -> Result<Self, SensorError> {
+        if max_per_sec == 0 {
+            return Err(SensorError::ConfigError(
+                "max measurements per second must be at least 1".to_owned(),
+            ));
+        }
+
+        Ok(RateLimiter {
+            max_per_sec,
+            bucket: Mutex::new(TokenBucket {
+                tokens: max_per_sec as f64,
+                refilled: Instant::now(),
+            }),
+        })
+    }
+
+    /// Most measurements allowed per second
+    pub fn max_per_sec(&self) -> u32 {
+        self.max_per_sec
+    }
+
+    /// Take a token for one measurement, or return how long until the next token is available if the bucket is empty
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let rate = self.max_per_sec as f64;
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    /// Wait until a token is available and take it
+    pub async fn acquire(&self) {
+        while let Err(retry_after) = self.try_acquire() {
+            tokio::time::sleep(retry_after).await;
+        }
+    }
+}
 
 /// Sensor that produces a stream of measurements
 #[async_trait::async_trait]
@@ -14,23 +248,759 @@ pub trait Sensor {
     /// each struct. Then the SensorMeasurement associated type here is just an enum of those structs,
     /// and the enum also implements measurement::Measurement. The top-level enum Measurement implementation
     /// just matches self & calls the correct variant's implementation of the respective Measurement
-    /// trait method.
+    /// trait method. `impl_measurement_enum!` generates that implementation from a list of the variants.
     ///
     /// Send bound is required for this type to be used for async functions
     type SensorMeasurement: for<'a> Measurement<'a> + Send;
 
+    /// Read the Sensor's next measurement from its Transducer, or None once the Transducer has no more
+    ///
+    /// This is the body of the Sensor's run loop, `run_until` produces whatever it returns. It's cancelled at its
+    /// next await point when `run_until`'s shutdown future resolves, so only await on reading here, never on
+    /// delivering a measurement. Sensors that loop forever can return `std::future::pending().await` once they're
+    /// out of measurements and leave stopping them to `run_until`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, so `run_until` stops straight away. Sensors that still override `run` instead keep compiling,
+    /// but need to implement this to be run with `run_until` or `run_resumable`.
+    async fn next_measurement(&mut self) -> Result<Option<Self::SensorMeasurement>, SensorError> {
+        Ok(None)
+    }
+
     /// Start collecting measurements, return an error if we hit something unrecoverable
     /// It's fine that this function is async because we're only calling it one (so one heap allocation)
-    /// The function should call produce_measurement
-    async fn run(mut self) -> Result<(), SensorError>;
+    ///
+    /// ## Default Implementation
+    ///
+    /// Calls `run_until` with a shutdown future that never resolves, so it runs until `next_measurement` returns
+    /// None or an error
+    async fn run(mut self) -> Result<(), SensorError>
+    where
+        Self: Sized + Send,
+    {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Collect measurements until `shutdown` resolves, then flush the producer and return `Ok(())`
+    ///
+    /// Use this to drain cleanly on SIGTERM when running as a container, i.e. pass `tokio::signal::ctrl_c()` mapped
+    /// to `()`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Selects between `shutdown` and `next_measurement` on every iteration, producing each measurement read with
+    /// `produce_measurement` outside the select so a produce is never dropped part way. Once `shutdown` resolves or
+    /// `next_measurement` returns None it waits up to `SHUTDOWN_FLUSH_TIMEOUT` for the queued measurements to be
+    /// delivered, on tokio's blocking thread pool (see `flush_sensor`). A measurement `next_measurement` was in the
+    /// middle of reading when `shutdown` resolved is lost.
+    ///
+    /// # Errors
+    ///
+    /// - Any error returned by `next_measurement` before `shutdown` resolves
+    /// - SensorError::KafkaError: If a measurement can't be queued, or the producer's queue didn't drain before
+    ///   `SHUTDOWN_FLUSH_TIMEOUT`
+    async fn run_until<F>(mut self, shutdown: F) -> Result<(), SensorError>
+    where
+        Self: Sized + Send,
+        F: Future<Output = ()> + Send,
+    {
+        tokio::pin!(shutdown);
+        loop {
+            let measurement = tokio::select! {
+                biased;
+                _ = &mut shutdown => break,
+                next = self.next_measurement() => match next? {
+                    Some(measurement) => measurement,
+                    None => break,
+                },
+            };
+
+            // Queued measurements are delivered by the flush below, so their delivery futures aren't awaited
+            self.produce_measurement(measurement)
+                .map_err(SensorError::KafkaError)?;
+        }
+
+        flush_sensor(&self, SHUTDOWN_FLUSH_TIMEOUT).await
+    }
+
+    /// Delivery guarantee the Sensor's producer is built with
+    ///
+    /// Apply it with `self.delivery_guarantee().producer_settings().apply(&mut builder)?` before building the
+    /// producer.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns `DeliveryGuarantee::AtLeastOnce`
+    fn delivery_guarantee(&self) -> DeliveryGuarantee {
+        DeliveryGuarantee::AtLeastOnce
+    }
+
+    /// Clock the Sensor stamps its measurements with
+    ///
+    /// Build records with `measurement::to_message_stamped(measurement, self.timestamp_source())` in
+    /// `produce_measurement` to apply it, which also records the clock used in a header. Sensors whose hardware clock
+    /// may be unsynchronized should make this configurable (`TimestampSource` parses from `sensor`, `producer`, or
+    /// `producer_if_skewed=<max skew>`).
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns `TimestampSource::Sensor`
+    fn timestamp_source(&self) -> TimestampSource {
+        TimestampSource::Sensor
+    }
+
+    /// Snapshot of any state the Sensor needs to resume cleanly after a restart
+    ///
+    /// i.e. a decoder's partial-message buffer or the last sequence number seen. Call `StateFile::persist` with this
+    /// on graceful shutdown, or run the Sensor with `run_resumable`, which saves it periodically and on shutdown and
+    /// restores it on the next start.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, the Sensor is stateless
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restore state previously returned by `save_state`
+    ///
+    /// Called before `run`. Sensors should tolerate state from an older version of themselves, or ignore it.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Ignores the state
+    fn restore_state(&mut self, _state: &[u8]) {}
+
+    /// Whether the Sensor is fit to serve, for Kubernetes readiness and liveness probes (see `serve_health`)
+    ///
+    /// Override this to report whether the Transducer is connected and producing (`SensorHealth::from_transducer`
+    /// with `Transducer::health`) and whether the producer can reach Redpanda (`producer_reachable`), combining them
+    /// with `SensorHealth::and`.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns `SensorHealth::Healthy`
+    async fn health_check(&self) -> SensorHealth
+    where
+        Self: Sync,
+    {
+        SensorHealth::Healthy
+    }
+
+    /// Most recent raw frames the Sensor's Transducer read, served at `/debug/frames` by `serve_health`
+    ///
+    /// Override this to return the Transducer's `Transducer::debug_frames`, so operators can see the bytes a
+    /// suspicious measurement was parsed from.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, there are no raw frames to serve
+    fn debug_frames(&self) -> Option<FrameBuffer> {
+        None
+    }
 
     /// Produce a measurement to Redpanda
     /// Don't use async_trait here because each function call results in a heap allocation...we expect this
     /// function to be called in a hot loop and we don't want a separate heap allocation every time we call it...
     ///
+    /// For the same reason, prefer building the record with `measurement::to_message_pooled`, which reuses a
+    /// FlatBufferBuilder instead of allocating one per measurement.
+    ///
     /// TODO: We should register the failures to queue or deliver measurements somewhere...probably in traces that go to Loki
     fn produce_measurement(
         &self,
         measurement: Self::SensorMeasurement,
     ) -> Result<DeliveryFuture, KafkaError>;
+
+    /// Produce a batch of measurements to Redpanda, returning every delivery future so they can be awaited together
+    ///
+    /// Stops at the first measurement that fails to queue and returns its error. Measurements queued before it are
+    /// still delivered, but their delivery futures are dropped, and the rest of the batch isn't produced.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Calls `produce_measurement` for each measurement in order. Override it if the Sensor can serialize or queue a
+    /// batch more cheaply than one measurement at a time.
+    fn produce_measurements(
+        &self,
+        measurements: Vec<Self::SensorMeasurement>,
+    ) -> Result<Vec<DeliveryFuture>, KafkaError> {
+        measurements
+            .into_iter()
+            .map(|measurement| self.produce_measurement(measurement))
+            .collect()
+    }
+
+    /// Rate limiter `produce_measurement_throttled` consults before producing
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, so measurements aren't throttled. Override it to return a `RateLimiter` built from the Sensor's
+    /// `max_measurements_per_sec` setting, if it has one.
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        None
+    }
+
+    /// Produce a measurement to Redpanda like `produce_measurement`, unless it would exceed the Sensor's rate limit
+    ///
+    /// Over the limit, the measurement is dropped and the error says how long until the next one can be produced.
+    /// Sensors that would rather wait than drop measurements can `rate_limiter().acquire().await` before calling
+    /// `produce_measurement` instead. This isn't async for the same reason `produce_measurement` isn't.
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::RateLimited: If `rate_limiter` has no tokens left
+    /// - SensorError::KafkaError: If `produce_measurement` fails
+    ///
+    /// ## Default Implementation
+    ///
+    /// Takes a token from `rate_limiter`, if the Sensor has one, then calls `produce_measurement`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// match sensor.produce_measurement_throttled(measurement) {
+    ///     Ok(delivery) => deliveries.push(delivery),
+    ///     Err(SensorError::RateLimited(retry_after)) => tokio::time::sleep(retry_after).await,
+    ///     Err(e) => return Err(e),
+    /// }
+    /// ```
+    fn produce_measurement_throttled(
+        &self,
+        measurement: Self::SensorMeasurement,
+    ) -> Result<DeliveryFuture, SensorError> {
+        if let Some(limiter) = self.rate_limiter() {
+            limiter.try_acquire().map_err(SensorError::RateLimited)?;
+        }
+
+        self.produce_measurement(measurement)
+            .map_err(SensorError::KafkaError)
+    }
+
+    /// Producer `produce_measurement` queues measurements on, which `flush` waits on
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, so `flush` has nothing to wait on and logs a WARN that nothing was flushed. Override it to return
+    /// the Sensor's producer (i.e. `MockProducer::producer` in tests).
+    fn producer(&self) -> Option<&RedpandaProducer> {
+        None
+    }
+
+    /// Wait up to `timeout` for every measurement queued by `produce_measurement` to be delivered
+    ///
+    /// Producing only queues a measurement, so a Sensor that stops without flushing drops whatever is still queued.
+    /// The default `run_until` flushes before returning with `flush_sensor`, which only calls this for Sensors without
+    /// a `producer`, and Sensors that override it must flush too, i.e. `flush_sensor(&self, timeout).await?`.
+    ///
+    /// # Errors
+    ///
+    /// - KafkaError: If the queue didn't drain before `timeout`
+    ///
+    /// ## Default Implementation
+    ///
+    /// Flushes `producer` like `flush_producer`. Sensors without one have nothing to flush, which is logged at WARN
+    /// since whatever they queued on a producer of their own may still be undelivered.
+    fn flush(&self, timeout: Duration) -> Result<(), KafkaError> {
+        match self.producer() {
+            Some(producer) => producer.producer.flush(timeout),
+            None => {
+                event!(
+                    Level::WARN,
+                    "Sensor has no producer to flush, override Sensor::producer or call flush_producer. Queued \
+                     measurements may not have been delivered"
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Health of a Sensor, reported by `Sensor::health_check`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SensorHealth {
+    /// Connected and producing, ready for traffic
+    Healthy,
+    /// Running but not ready, i.e. the Transducer is reconnecting or Redpanda is unreachable. Still live, so it isn't
+    /// restarted while it recovers.
+    Degraded(String),
+    /// Broken in a way it won't recover from, neither ready nor live, so it's restarted
+    Unhealthy(String),
+}
+
+impl SensorHealth {
+    /// Whether the Sensor is running, `/healthz` for liveness probes. Only `Unhealthy` Sensors aren't.
+    pub fn is_live(&self) -> bool {
+        !matches!(self, SensorHealth::Unhealthy(_))
+    }
+
+    /// Whether the Sensor is connected and producing, `/readyz` for readiness probes. Only `Healthy` Sensors are.
+    pub fn is_ready(&self) -> bool {
+        matches!(self, SensorHealth::Healthy)
+    }
+
+    /// The worse of two healths, i.e. to combine the Transducer's with the producer's
+    pub fn and(self, other: SensorHealth) -> SensorHealth {
+        match (self, other) {
+            (SensorHealth::Unhealthy(reason), _) | (_, SensorHealth::Unhealthy(reason)) => {
+                SensorHealth::Unhealthy(reason)
+            }
+            (SensorHealth::Degraded(reason), _) | (_, SensorHealth::Degraded(reason)) => {
+                SensorHealth::Degraded(reason)
+            }
+            (SensorHealth::Healthy, SensorHealth::Healthy) => SensorHealth::Healthy,
+        }
+    }
+
+    /// Health of a Sensor whose Transducer reports `health`
+    ///
+    /// Transducers that don't report their connection status (`ConnectionStatus::Unknown`) are assumed healthy.
+    pub fn from_transducer(health: &TransducerHealth) -> SensorHealth {
+        match health.status {
+            ConnectionStatus::Unknown | ConnectionStatus::Connected => SensorHealth::Healthy,
+            ConnectionStatus::Reconnecting => {
+                SensorHealth::Degraded("Transducer is reconnecting".to_owned())
+            }
+            ConnectionStatus::Disconnected => {
+                SensorHealth::Unhealthy("Transducer is disconnected".to_owned())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SensorHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SensorHealth::Healthy => write!(f, "healthy"),
+            SensorHealth::Degraded(reason) => write!(f, "degraded: {}", reason),
+            SensorHealth::Unhealthy(reason) => write!(f, "unhealthy: {}", reason),
+        }
+    }
+}
+
+/// Whether `producer` can reach a Redpanda broker within `timeout`, by fetching the cluster metadata
+///
+/// Fetching metadata blocks until it returns (or times out), so it runs on tokio's blocking thread pool.
+pub async fn producer_reachable(producer: &RedpandaProducer, timeout: Duration) -> bool {
+    let producer = producer.clone();
+    tokio::task::spawn_blocking(move || {
+        producer
+            .producer
+            .client()
+            .fetch_metadata(None, timeout)
+            .is_ok()
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Serve a Sensor's `health_check` over HTTP at `address`, for Kubernetes probes
+///
+/// `GET /healthz` is the liveness probe and `GET /readyz` the readiness probe: each responds 200 OK if the Sensor is
+/// live (or ready), and 503 Service Unavailable if not, with the health as a plain text body.
+///
+/// With `--debug-frames` on (see `Sensor::debug_frames`), `GET /debug/frames` lists the kept raw frames oldest first
+/// and `GET /debug/frames/{sequence}` the frame with that sequence number, one `RawFrame` per line. Any other path,
+/// or a frame that has been dropped, is 404. Spawn this next to the Sensor's `run`, it only returns if the address
+/// can't be bound.
+///
+/// # Errors
+///
+/// - SensorError::HealthServerError: If `address` can't be bound
+///
+/// # Examples
+///
+/// ```no_run
+/// let sensor = Arc::new(RadarSensor::new(transducer, producer));
+/// tokio::spawn(serve_health(sensor.clone(), "0.0.0.0:8080".parse()?));
+/// ```
+pub async fn serve_health<S>(sensor: Arc<S>, address: SocketAddr) -> Result<(), SensorError>
+where
+    S: Sensor + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|e| SensorError::HealthServerError(format!("Can't bind {}: {}", address, e)))?;
+    serve_health_on(sensor, listener).await
+}
+
+/// Serve a Sensor's `health_check` over HTTP like `serve_health`, on a listener that's already bound
+///
+/// i.e. to an ephemeral port in tests.
+///
+/// # Errors
+///
+/// - SensorError::HealthServerError: If accepting connections fails
+pub async fn serve_health_on<S>(sensor: Arc<S>, listener: TcpListener) -> Result<(), SensorError>
+where
+    S: Sensor + Send + Sync + 'static,
+{
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| SensorError::HealthServerError(e.to_string()))?;
+        let sensor = sensor.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond_health(stream, sensor.as_ref()).await {
+                event!(Level::DEBUG, "Failed to respond to a health probe. {}", e);
+            }
+        });
+    }
+}
+
+/// Largest probe request read, probes only send a request line and a few headers
+const MAX_PROBE_REQUEST_BYTES: usize = 8192;
+
+/// Longest to wait for a probe's request, so a client that connects and sends nothing doesn't hold its connection
+const PROBE_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answer one HTTP health probe, closing the connection after the response
+///
+/// Gives up with `io::ErrorKind::TimedOut` if the request doesn't arrive within `PROBE_READ_TIMEOUT`.
+async fn respond_health<S>(mut stream: TcpStream, sensor: &S) -> io::Result<()>
+where
+    S: Sensor + Sync,
+{
+    // Only the request line matters, but read the whole head so the client isn't reset before it reads the response
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    let read = async {
+        while !request.windows(4).any(|w| w == b"\r\n\r\n")
+            && request.len() < MAX_PROBE_REQUEST_BYTES
+        {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, io::Error>(())
+    };
+    tokio::time::timeout(PROBE_READ_TIMEOUT, read)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "probe sent no request"))??;
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/healthz") | ("GET", "/readyz") => {
+            let health = sensor.health_check().await;
+            let ok = if path == "/healthz" {
+                health.is_live()
+            } else {
+                health.is_ready()
+            };
+            let status = if ok {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, health.to_string())
+        }
+        ("GET", "/debug/frames") => match sensor.debug_frames() {
+            Some(frames) => {
+                let body = frames
+                    .recent(frames.capacity())
+                    .iter()
+                    .map(|frame| format!("{}\n", frame))
+                    .collect();
+                ("200 OK", body)
+            }
+            None => ("404 Not Found", "debug frames are off".to_owned()),
+        },
+        ("GET", _) if path.starts_with("/debug/frames/") => {
+            let frame = path["/debug/frames/".len()..]
+                .parse()
+                .ok()
+                .and_then(|sequence| sensor.debug_frames()?.get(sequence));
+            match frame {
+                Some(frame) => ("200 OK", format!("{}\n", frame)),
+                None => ("404 Not Found", "not found".to_owned()),
+            }
+        }
+        _ => ("404 Not Found", "not found".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Wait up to `timeout` for every measurement queued on `producer` to be delivered
+///
+/// Call this from an overridden `Sensor::run_until` once the shutdown future resolves, or return the producer from
+/// `Sensor::producer` and call `Sensor::flush`.
+///
+/// # Errors
+///
+/// - SensorError::KafkaError: If the queue didn't drain before `timeout`
+pub fn flush_producer(producer: &RedpandaProducer, timeout: Duration) -> Result<(), SensorError> {
+    producer
+        .producer
+        .flush(timeout)
+        .map_err(SensorError::KafkaError)
+}
+
+/// Wait up to `timeout` for every measurement `sensor` queued to be delivered, without blocking the runtime
+///
+/// Flushing blocks until the queue drains, so `Sensor::producer` is flushed on tokio's blocking thread pool like
+/// `flush_producer`. The returned future doesn't borrow `sensor`, so it can be awaited from a Sensor that isn't
+/// `Sync`. Sensors without a producer are flushed with `Sensor::flush` when this is called, which by default only
+/// logs that there was nothing to flush.
+///
+/// # Errors
+///
+/// - SensorError::KafkaError: If the queue didn't drain before `timeout`
+pub fn flush_sensor<S: Sensor>(
+    sensor: &S,
+    timeout: Duration,
+) -> impl Future<Output = Result<(), SensorError>> + Send + 'static {
+    let producer = sensor.producer().cloned();
+    let flushed = match producer {
+        Some(_) => Ok(()),
+        None => sensor.flush(timeout).map_err(SensorError::KafkaError),
+    };
+
+    async move {
+        flushed?;
+        let producer = match producer {
+            Some(producer) => producer,
+            None => return Ok(()),
+        };
+        match tokio::task::spawn_blocking(move || flush_producer(&producer, timeout)).await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// Topic a Sensor forwards its Transducer's health to, `derived.<domain>...<data-name>.health` for measurements
+/// produced to `raw.<domain>...<data-name>`
+///
+/// i.e. `derived.surface.ais.health` for `raw.surface.ais`. Derived measurement topics just get `.health` appended.
+pub fn health_topic(measurement_topic: &str) -> String {
+    let name = measurement_topic
+        .strip_prefix("raw.")
+        .or_else(|| measurement_topic.strip_prefix("derived."))
+        .unwrap_or(measurement_topic);
+    format!("derived.{name}.health")
+}
+
+/// Produce a Transducer's health to `topic` every `interval` until the Transducer is dropped
+///
+/// Each report is `TransducerHealth::to_json` of the latest health, keyed by `source_id` so one Transducer's reports
+/// stay in order. Reports are produced on an interval rather than on every update, since reads update the health
+/// on every measurement, and a heartbeat whose `last_read` stops advancing shows a stuck Transducer even while it's
+/// still connected. Spawn this with the receiver from `Transducer::health_stream` and `health_topic`. The last
+/// health is reported once more after the Transducer is dropped, then this returns.
+///
+/// # Errors
+///
+/// - SensorError::KafkaError: If a report can't be queued or delivered
+///
+/// # Panics
+///
+/// - If `interval` is zero, like `tokio::time::interval`
+pub async fn forward_health(
+    producer: &RedpandaProducer,
+    topic: &str,
+    source_id: &str,
+    mut health: watch::Receiver<TransducerHealth>,
+    interval: Duration,
+) -> Result<(), SensorError> {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        // Checked before reading the health, so the final update is still reported
+        let closed = health.has_changed().is_err();
+        let payload = health.borrow_and_update().to_json();
+
+        let record = RedpandaRecord::new(
+            topic,
+            Some(source_id.as_bytes().to_vec()),
+            payload.into_bytes(),
+            None,
+        );
+        let delivery = producer
+            .send_result(&record)
+            .map_err(|(e, _)| SensorError::KafkaError(e))?;
+        match delivery.await {
+            Ok(Ok(_)) => {}
+            Ok(Err((e, _))) => return Err(SensorError::KafkaError(e)),
+            Err(_) => return Err(SensorError::KafkaError(KafkaError::Canceled)),
+        }
+
+        if closed {
+            return Ok(());
+        }
+    }
+}
+
+/// File that a Sensor's `save_state` snapshot is persisted to between restarts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    /// State file at `path`. The file doesn't need to exist yet
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        StateFile { path: path.into() }
+    }
+
+    /// Path the state is stored at
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the saved state, None if nothing has been saved yet
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::StateError: If the file exists but can't be read
+    pub fn load(&self) -> Result<Option<Vec<u8>>, SensorError> {
+        match fs::read(&self.path) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.error("read", e)),
+        }
+    }
+
+    /// Save state, replacing anything saved before
+    ///
+    /// The state is written to a temporary file and renamed over the old one, so a crash mid-write never leaves a
+    /// partially written state file behind.
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::StateError: If the state can't be written
+    pub fn save(&self, state: &[u8]) -> Result<(), SensorError> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        fs::write(&tmp, state).map_err(|e| self.error("write", e))?;
+        fs::rename(&tmp, &self.path).map_err(|e| self.error("write", e))
+    }
+
+    /// Restore a Sensor from the saved state, returning whether there was any state to restore
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::StateError: If the file exists but can't be read
+    pub fn restore<S: Sensor>(&self, sensor: &mut S) -> Result<bool, SensorError> {
+        match self.load()? {
+            Some(state) => {
+                sensor.restore_state(&state);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Save a Sensor's state, returning whether it had any state to save
+    ///
+    /// `run_resumable` calls this periodically and on graceful shutdown.
+    ///
+    /// # Errors
+    ///
+    /// - SensorError::StateError: If the state can't be written
+    pub fn persist<S: Sensor>(&self, sensor: &S) -> Result<bool, SensorError> {
+        match sensor.save_state() {
+            Some(state) => {
+                self.save(&state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn error(&self, action: &str, e: io::Error) -> SensorError {
+        SensorError::StateError(format!(
+            "Failed to {} sensor state at {}: {}",
+            action,
+            self.path.display(),
+            e
+        ))
+    }
+}
+
+/// Restore a Sensor's state from `state_file` and run it until `shutdown` resolves, saving its state every
+/// `save_interval` and once it stops
+///
+/// Runs the same loop as the default `Sensor::run_until`, and saves the state between measurements once
+/// `save_interval` has passed since the last save, so a measurement `next_measurement` is in the middle of reading is
+/// never dropped for a save. Each save flushes the producer first (see `flush_sensor`), so the saved state never runs
+/// ahead of the measurements that were delivered. A Sensor waiting on `next_measurement` isn't saved until it returns
+/// its next measurement. The state is saved on graceful shutdown and when the Sensor runs out of measurements, but
+/// not when it fails.
+///
+/// # Errors
+///
+/// - SensorError::StateError: If the state file exists but can't be read, or the state can't be written
+/// - SensorError::KafkaError: If a measurement can't be queued, or the producer's queue didn't drain before
+///   `SHUTDOWN_FLUSH_TIMEOUT` at a save
+/// - Any error returned by `Sensor::next_measurement`
+pub async fn run_resumable<S, F>(
+    mut sensor: S,
+    state_file: &StateFile,
+    save_interval: Duration,
+    shutdown: F,
+) -> Result<(), SensorError>
+where
+    S: Sensor + Send,
+    F: Future<Output = ()> + Send,
+{
+    if state_file.restore(&mut sensor)? {
+        event!(
+            Level::INFO,
+            "Restored sensor state from {}",
+            state_file.path().display()
+        );
+    }
+
+    tokio::pin!(shutdown);
+    let mut saved = Instant::now();
+    loop {
+        let measurement = tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            next = sensor.next_measurement() => match next? {
+                Some(measurement) => measurement,
+                None => break,
+            },
+        };
+        sensor
+            .produce_measurement(measurement)
+            .map_err(SensorError::KafkaError)?;
+
+        if saved.elapsed() >= save_interval {
+            save_flushed(&sensor, state_file).await?;
+            saved = Instant::now();
+        }
+    }
+
+    save_flushed(&sensor, state_file).await
+}
+
+/// Flush `sensor` and then save its state to `state_file`, for `run_resumable`
+async fn save_flushed<S: Sensor>(sensor: &S, state_file: &StateFile) -> Result<(), SensorError> {
+    flush_sensor(sensor, SHUTDOWN_FLUSH_TIMEOUT).await?;
+    if state_file.persist(sensor)? {
+        event!(
+            Level::DEBUG,
+            "Saved sensor state to {}",
+            state_file.path().display()
+        );
+    }
+    Ok(())
 }
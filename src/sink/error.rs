@@ -0,0 +1,48 @@
+//! Error type for sinks
+
+use redpanda::error::KafkaError;
+
+/// Error for all sink-related issues
+#[derive(thiserror::Error, Debug)]
+pub enum SinkError {
+    /// The downstream reader went away (i.e. a closed pipe), so there's nowhere left to write to
+    #[error("The sink's output was closed")]
+    Closed,
+    /// The consumer has no consumer group to commit offsets to, which exactly-once sinks need
+    #[error("The sink's consumer has no consumer group")]
+    NoConsumerGroup,
+    /// Wrap sink-related IO errors
+    #[error("An IO error occurred {0}")]
+    IoError(std::io::Error),
+    /// Wrap sink-related Kafka errors
+    #[error("A Kafka error occurred {0}")]
+    KafkaError(KafkaError),
+    /// Wrap JSON serialization errors
+    #[cfg(feature = "json")]
+    #[error("Failed to serialize measurement to JSON {0}")]
+    JsonError(serde_json::Error),
+    /// Wrap SQLite errors
+    #[cfg(feature = "sqlite")]
+    #[error("A SQLite error occurred {0}")]
+    SqliteError(rusqlite::Error),
+    /// Wrap ScyllaDB errors
+    #[cfg(feature = "scylla")]
+    #[error("A ScyllaDB error occurred {0}")]
+    ScyllaError(scylla::transport::errors::QueryError),
+}
+
+impl From<KafkaError> for SinkError {
+    fn from(e: KafkaError) -> Self {
+        SinkError::KafkaError(e)
+    }
+}
+
+impl From<std::io::Error> for SinkError {
+    /// Broken pipes mean the reader on the other end is done, which isn't an error for a streaming sink
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::BrokenPipe => SinkError::Closed,
+            _ => SinkError::IoError(e),
+        }
+    }
+}
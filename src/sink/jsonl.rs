@@ -0,0 +1,164 @@
+//! Stream measurements as JSON lines for composing with shell tools
+//!
+//! Each measurement is written as a single line of JSON, so a topic can be piped straight into `jq`, `duckdb`, or
+//! a file:
+//!
+//! ```text
+//! sink --format jsonl | jq '.source_id'
+//! ```
+
+use std::io::{Stdout, Write};
+use std::marker::PhantomData;
+
+use redpanda::consumer::{CommitMode, Consumer, RedpandaConsumer};
+use serde::Serialize;
+use tracing::{event, Level};
+
+use crate::measurement::Measurement;
+use crate::sink::error::SinkError;
+use crate::SensorSink;
+
+/// Writes measurements as newline delimited JSON to stdout or any other `Write`
+pub struct JsonlSink<M, W: Write> {
+    writer: W,
+    written: u64,
+    batch_size: usize,
+    commit_offsets: bool,
+    measurement: PhantomData<fn(M)>,
+}
+
+impl<M> JsonlSink<M, Stdout> {
+    /// JSON lines sink that writes to stdout
+    pub fn stdout() -> Self {
+        JsonlSink::new(std::io::stdout())
+    }
+}
+
+impl<M, W: Write> JsonlSink<M, W> {
+    /// JSON lines sink that writes to `writer`, in batches of 100 measurements that commit their offsets
+    pub fn new(writer: W) -> Self {
+        JsonlSink {
+            writer,
+            written: 0,
+            batch_size: 100,
+            commit_offsets: true,
+            measurement: PhantomData,
+        }
+    }
+
+    /// Write (and flush) batches of `batch_size` measurements (100 by default) when run with `SensorSink::run`
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Whether `SensorSink::run` commits offsets after each flushed batch (the default), so a restarted sink picks
+    /// up where it left off. Without it the consumer's offsets are left untouched, i.e. for a one-off dump.
+    pub fn with_commit_offsets(mut self, commit_offsets: bool) -> Self {
+        self.commit_offsets = commit_offsets;
+        self
+    }
+
+    /// Number of measurements written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Consume the sink, returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<M, W> JsonlSink<M, W>
+where
+    M: for<'a> Measurement<'a> + Serialize,
+    W: Write,
+{
+    /// Write a batch of measurements, one JSON object per line, and flush the writer
+    ///
+    /// Each measurement is serialized with `Measurement::to_json`, so a Measurement that overrides it controls its
+    /// JSON lines too.
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::Closed: If the downstream reader closed the pipe
+    /// - SinkError::IoError: If writing or flushing failed for any other reason
+    /// - SinkError::JsonError: If a measurement couldn't be serialized
+    pub fn write_batch(&mut self, measurements: &[M]) -> Result<(), SinkError> {
+        for measurement in measurements {
+            let line = measurement.to_json().map_err(SinkError::JsonError)?;
+            self.writer.write_all(line.as_bytes())?;
+            self.writer.write_all(b"\n")?;
+            self.written += 1;
+        }
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl<M, W> SensorSink for JsonlSink<M, W>
+where
+    M: for<'a> Measurement<'a> + Serialize + Send,
+    W: Write + Send,
+{
+    type Measurement = M;
+    type Error = SinkError;
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Write the batch with `write_batch`
+    async fn sink_batch(&mut self, batch: Vec<M>) -> Result<(), SinkError> {
+        self.write_batch(&batch)
+    }
+
+    /// Commit the consumer's position, unless the sink was built `with_commit_offsets(false)`
+    async fn commit_offsets(&mut self, consumer: &RedpandaConsumer) -> Result<(), SinkError> {
+        if self.commit_offsets {
+            consumer.consumer.commit_consumer_state(CommitMode::Sync)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stream the sink's topic into a JSON lines sink with `SensorSink::run` until the stream ends or the sink's output
+/// is closed
+///
+/// A closed output (i.e. `jq` exiting) ends the sink cleanly with `Ok(())`.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut builder = RedpandaBuilder::default();
+/// builder.set_bootstrap_servers("127.0.0.1:9010");
+/// builder.set_group_id("radar-2d-jsonl");
+/// builder.set("enable.auto.commit", "false");
+/// let consumer = builder.build_consumer().unwrap();
+///
+/// let sink = JsonlSink::<RadarMeasurement2d, _>::stdout().with_commit_offsets(false);
+/// run_jsonl_sink(consumer, sink).await?;
+/// ```
+///
+/// # Errors
+///
+/// - Any error returned by `SensorSink::run`, other than SinkError::Closed
+pub async fn run_jsonl_sink<M, W>(
+    consumer: RedpandaConsumer,
+    sink: JsonlSink<M, W>,
+) -> Result<(), SinkError>
+where
+    M: for<'a> Measurement<'a> + Serialize + Send,
+    W: Write + Send,
+{
+    match sink.run(consumer).await {
+        Err(SinkError::Closed) => {
+            event!(Level::INFO, "JSON lines output closed, stopping");
+            Ok(())
+        }
+        result => result,
+    }
+}
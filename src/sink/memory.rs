@@ -0,0 +1,109 @@
+//! Keep sunk measurements in memory, for testing sink pipelines and sensors without a database
+//!
+//! A `MemorySink` is cheap to clone and every clone shares the same measurements, so hand one clone to the code
+//! under test and assert on another.
+
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::measurement::Measurement;
+use crate::sink::error::SinkError;
+use crate::SensorSink;
+
+/// Appends every batch of measurements to a shared `Vec`
+///
+/// # Examples
+///
+/// ```no_run
+/// let sink = MemorySink::<RadarMeasurement2d>::new().with_batch_size(10);
+/// let written = sink.clone();
+/// sink.run(consumer).await?;
+/// assert_eq!(written.len(), 100);
+/// ```
+pub struct MemorySink<M> {
+    measurements: Arc<Mutex<Vec<M>>>,
+    batches: Arc<Mutex<usize>>,
+    batch_size: usize,
+    measurement: PhantomData<fn(M)>,
+}
+
+impl<M> Clone for MemorySink<M> {
+    fn clone(&self) -> Self {
+        MemorySink {
+            measurements: self.measurements.clone(),
+            batches: self.batches.clone(),
+            batch_size: self.batch_size,
+            measurement: PhantomData,
+        }
+    }
+}
+
+impl<M> Default for MemorySink<M> {
+    fn default() -> Self {
+        MemorySink {
+            measurements: Arc::new(Mutex::new(Vec::new())),
+            batches: Arc::new(Mutex::new(0)),
+            batch_size: 1000,
+            measurement: PhantomData,
+        }
+    }
+}
+
+impl<M> MemorySink<M> {
+    /// Empty sink writing batches of 1000 measurements
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write batches of `batch_size` measurements (1000 by default) when run with `SensorSink::run`
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Lock the measurements written so far, in the order they were written
+    pub fn measurements(&self) -> MutexGuard<'_, Vec<M>> {
+        self.measurements.lock().unwrap()
+    }
+
+    /// Remove and return the measurements written so far
+    pub fn take(&self) -> Vec<M> {
+        std::mem::take(&mut *self.measurements())
+    }
+
+    /// Number of measurements written so far
+    pub fn len(&self) -> usize {
+        self.measurements().len()
+    }
+
+    /// Whether nothing has been written yet
+    pub fn is_empty(&self) -> bool {
+        self.measurements().is_empty()
+    }
+
+    /// Number of batches written so far
+    pub fn batches(&self) -> usize {
+        *self.batches.lock().unwrap()
+    }
+}
+
+#[async_trait::async_trait]
+impl<M> SensorSink for MemorySink<M>
+where
+    M: for<'a> Measurement<'a> + Send,
+{
+    type Measurement = M;
+    type Error = SinkError;
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Append the batch, which can't fail
+    async fn sink_batch(&mut self, batch: Vec<M>) -> Result<(), SinkError> {
+        self.measurements().extend(batch);
+        *self.batches.lock().unwrap() += 1;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! Sinks that stream measurements out of Redpanda into downstream systems and tools
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use rdkafka::TopicPartitionList;
+use redpanda::consumer::Consumer;
+use redpanda::error::KafkaError;
+use redpanda::producer::{Producer, RedpandaProducer, RedpandaRecord};
+use redpanda::{RedpandaBuilder, RedpandaConsumer};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{event, Level};
+
+use crate::measurement::{Measurement, RecordMeta};
+use crate::sink::error::SinkError;
+use crate::SensorSink;
+
+pub mod error;
+#[cfg(feature = "json")]
+pub mod jsonl;
+pub mod memory;
+#[cfg(feature = "scylla")]
+pub mod scylla;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[cfg(test)]
+mod tests;
+
+/// Sink hosted by a SinkHost, whose batches each hold a permit from the host's semaphore while they're written
+struct Limited<S> {
+    sink: S,
+    writes: Arc<Semaphore>,
+}
+
+#[async_trait::async_trait]
+impl<S: SensorSink> SensorSink for Limited<S> {
+    type Measurement = S::Measurement;
+    type Error = S::Error;
+
+    fn topic(&self) -> &str {
+        self.sink.topic()
+    }
+
+    fn batch_size(&self) -> usize {
+        self.sink.batch_size()
+    }
+
+    async fn sink_batch(&mut self, batch: Vec<S::Measurement>) -> Result<(), S::Error> {
+        // The host never closes the semaphore
+        let _permit = self.writes.acquire().await.ok();
+        self.sink.sink_batch(batch).await
+    }
+
+    async fn sink_records(
+        &mut self,
+        batch: Vec<(S::Measurement, RecordMeta)>,
+    ) -> Result<(), S::Error> {
+        let _permit = self.writes.acquire().await.ok();
+        self.sink.sink_records(batch).await
+    }
+
+    async fn commit_offsets(&mut self, consumer: &RedpandaConsumer) -> Result<(), S::Error> {
+        self.sink.commit_offsets(consumer).await
+    }
+}
+
+/// Runs a hosted sink on its consumer, waiting for the host's semaphore before each batch
+type RunSink = Box<
+    dyn FnOnce(RedpandaConsumer, Arc<Semaphore>) -> BoxFuture<'static, Result<(), SinkError>>
+        + Send,
+>;
+
+/// Runs several sinks in one process, each on its own consumer
+///
+/// Every hosted sink is run with `SensorSink::run` as a task on the same runtime, on its own consumer in the
+/// consumer group `{group_prefix}-{name}` with auto commit disabled. Each sink reads, batches (see
+/// `SensorSink::batch_size`), and commits offsets on its own, so a slow or failing sink never holds back another
+/// sink's commits, and a restart replays to each sink only what it hadn't stored yet. At most `max_concurrency`
+/// sinks write a batch at once.
+///
+/// The sinks share the runtime and the write limit, not a Kafka client: a librdkafka consumer belongs to exactly
+/// one consumer group, so per-sink offsets take a consumer (and its broker connections) per sink, and each sink
+/// consumes the topic separately. Sinks that can share offsets are cheaper to run behind one sink that writes to
+/// all of them.
+///
+/// A sink that fails stops with an ERROR log while the others keep running. A sink's own `SensorSink::run` override
+/// isn't used, only its `sink_records`, `sink_batch`, and `commit_offsets`.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut host = SinkHost::new("127.0.0.1:9010", "radar-2d-sinks", 2);
+/// host.add("sqlite", SqliteSink::<RadarMeasurement2d>::open("radar-2d.db")?);
+/// let recent = MemorySink::<RadarMeasurement2d>::new();
+/// host.add("recent", recent.clone());
+/// host.run().await?;
+/// ```
+pub struct SinkHost {
+    kafka_addresses: String,
+    group_prefix: String,
+    settings: Vec<(String, String)>,
+    max_concurrency: usize,
+    sinks: Vec<(String, RunSink)>,
+}
+
+impl SinkHost {
+    /// Host without any sinks consuming from the brokers at `kafka_addresses` with consumer groups prefixed by
+    /// `group_prefix`, writing at most `max_concurrency` sinks' batches at once
+    pub fn new(kafka_addresses: &str, group_prefix: &str, max_concurrency: usize) -> Self {
+        SinkHost {
+            kafka_addresses: kafka_addresses.to_owned(),
+            group_prefix: group_prefix.to_owned(),
+            settings: Vec::new(),
+            max_concurrency: max_concurrency.max(1),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Set a librdkafka configuration parameter on every sink's consumer
+    ///
+    /// `enable.auto.commit` is always overridden to false.
+    pub fn set(&mut self, key: &str, value: &str) -> &mut Self {
+        self.settings.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Host a sink, which consumes its `SensorSink::topic` in the consumer group `{group_prefix}-{name}`
+    ///
+    /// # Panics
+    ///
+    /// - If the host already runs a sink named `name`, which would split the topic's partitions between them
+    pub fn add<S>(&mut self, name: &str, sink: S) -> &mut Self
+    where
+        S: SensorSink + 'static,
+        SinkError: From<S::Error>,
+    {
+        assert!(
+            !self.sinks.iter().any(|(n, _)| n == name),
+            "sink host {} already runs a sink named {}",
+            self.group_prefix,
+            name
+        );
+        let run: RunSink = Box::new(move |consumer, writes| {
+            Box::pin(async move {
+                Limited { sink, writes }
+                    .run(consumer)
+                    .await
+                    .map_err(SinkError::from)
+            })
+        });
+        self.sinks.push((name.to_owned(), run));
+        self
+    }
+
+    /// Number of hosted sinks
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Whether the host runs no sinks
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Consumer group of the sink named `name`
+    pub fn group_id(&self, name: &str) -> String {
+        format!("{}-{}", self.group_prefix, name)
+    }
+
+    /// Run every sink until its stream ends, each committing offsets after its own batches
+    ///
+    /// Every consumer is built before any sink starts. Messages that fail to deserialize for a sink are skipped
+    /// with a WARN, like `SensorSink::run`.
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::KafkaError: If a consumer can't be built, before any sink starts
+    /// - The first error a sink failed with, once every sink has stopped. Only that sink's uncommitted batch is
+    ///   replayed, to it alone, when the host restarts.
+    pub async fn run(self) -> Result<(), SinkError> {
+        let mut consumers = Vec::with_capacity(self.sinks.len());
+        for (name, _) in &self.sinks {
+            let group_id = self.group_id(name);
+            let mut builder = RedpandaBuilder::default();
+            builder.set_bootstrap_servers(&self.kafka_addresses);
+            for (key, value) in &self.settings {
+                builder.set(key, value);
+            }
+            builder.set_group_id(&group_id);
+            builder.set("enable.auto.commit", "false");
+            consumers.push((group_id, builder.build_consumer()?));
+        }
+
+        let writes = Arc::new(Semaphore::new(self.max_concurrency));
+        let mut tasks = JoinSet::new();
+        for ((name, run), (group_id, consumer)) in self.sinks.into_iter().zip(consumers) {
+            event!(
+                Level::INFO,
+                "Starting sink {} with group id {}",
+                name,
+                group_id
+            );
+            let run = run(consumer, writes.clone());
+            tasks.spawn(async move {
+                let result = run.await;
+                if let Err(e) = &result {
+                    event!(Level::ERROR, "Sink {} failed. {}", name, e);
+                }
+                result
+            });
+        }
+
+        let mut first_error = None;
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Default timeout for the blocking transaction calls an ExactlyOnceSink makes
+pub const DEFAULT_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Build a producer with transactions initialized, for use with an ExactlyOnceSink
+///
+/// `transactional_id` must be unique per sink instance and stable across its restarts: initializing transactions
+/// fences off any older producer with the same id (i.e. a zombie from before a crash) and aborts its open
+/// transaction. Idempotence is enabled, which transactions require.
+///
+/// # Errors
+///
+/// - KafkaError: If the producer can't be built or the brokers can't initialize transactions within `timeout`
+pub async fn transactional_producer(
+    builder: &mut RedpandaBuilder,
+    transactional_id: &str,
+    timeout: Duration,
+) -> Result<RedpandaProducer, KafkaError> {
+    builder.set("transactional.id", transactional_id);
+    builder.enable_idempotence();
+    let producer = builder.build_producer()?;
+    blocking(&producer, move |p| p.producer.init_transactions(timeout)).await?;
+    Ok(producer)
+}
+
+/// Run a blocking producer call (librdkafka's transaction API blocks until the brokers respond) off the async
+/// executor
+async fn blocking<F>(producer: &RedpandaProducer, f: F) -> Result<(), KafkaError>
+where
+    F: FnOnce(&RedpandaProducer) -> Result<(), KafkaError> + Send + 'static,
+{
+    let producer = producer.clone();
+    match tokio::task::spawn_blocking(move || f(&producer)).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// Sink that writes measurements to Redpanda topics with exactly-once delivery
+///
+/// Each batch is written in one Kafka transaction: `sink_batch` begins it and produces the records `to_records`
+/// builds for the batch, then `commit_offsets` sends the consumer's offsets to the same transaction with
+/// `send_offsets_to_transaction` and commits it. Either the batch's output and its offset commit both become visible,
+/// or (after an abort) neither does, so a crash at any point neither drops nor duplicates output for
+/// `read_committed` readers.
+///
+/// Only backends that can join a Kafka transaction participate, which means Redpanda/Kafka topics (derived topics,
+/// mirrors, filtered or re-keyed copies of a topic). Databases and object stores can't; see `SensorSink` for how to
+/// make their writes idempotent instead.
+///
+/// The consumer must have a group id, `enable.auto.commit=false` (offsets are only ever committed by the
+/// transaction), and `isolation.level=read_committed` if its input is itself written transactionally.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut builder = RedpandaBuilder::default();
+/// builder.set_bootstrap_servers("127.0.0.1:9010");
+/// let producer = transactional_producer(&mut builder, "radar-2d-mirror-0", DEFAULT_TRANSACTION_TIMEOUT).await?;
+///
+/// builder.set_group_id("radar-2d-mirror");
+/// builder.set("enable.auto.commit", "false");
+/// builder.set("isolation.level", "read_committed");
+/// let consumer = builder.build_consumer()?;
+///
+/// let sink = ExactlyOnceSink::<RadarMeasurement2d, _>::new(producer, |m| {
+///     let key = Some(m.source_id().as_bytes().to_vec());
+///     Ok(vec![RedpandaRecord::new("radar-2d-mirror", key, m.clone().to_bytes(), None)])
+/// });
+/// sink.run(consumer).await?;
+/// ```
+pub struct ExactlyOnceSink<M, F> {
+    producer: RedpandaProducer,
+    to_records: F,
+    batch_size: usize,
+    timeout: Duration,
+    _measurement: PhantomData<M>,
+}
+
+impl<M, F> ExactlyOnceSink<M, F>
+where
+    M: for<'a> Measurement<'a>,
+    F: FnMut(&M) -> Result<Vec<RedpandaRecord>, SinkError>,
+{
+    /// Exactly-once sink producing the records `to_records` builds for each measurement with `producer`
+    ///
+    /// `producer` must come from `transactional_producer`. An error from `to_records` fails the batch before its
+    /// transaction begins, so nothing in it is produced.
+    pub fn new(producer: RedpandaProducer, to_records: F) -> Self {
+        ExactlyOnceSink {
+            producer,
+            to_records,
+            batch_size: 1000,
+            timeout: DEFAULT_TRANSACTION_TIMEOUT,
+            _measurement: PhantomData,
+        }
+    }
+
+    /// Number of measurements written per transaction, 1000 by default
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Timeout for sending offsets to, committing, and aborting a transaction, `DEFAULT_TRANSACTION_TIMEOUT` by
+    /// default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Write `batch`, everything `consumer` has consumed so far, in one transaction
+    ///
+    /// `sink_batch` followed by `commit_offsets`, for driving the sink without `SensorSink::run`. The consumer's
+    /// current position is committed with the batch, so call this right after consuming the batch's last message.
+    /// On any error the transaction is aborted and nothing in the batch is written or committed. The consumer's
+    /// position is then past the aborted batch, so it should be rebuilt (or its partitions re-assigned) to resume
+    /// from the last committed transaction.
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::KafkaError: If producing, sending the offsets, or committing failed (after aborting)
+    /// - SinkError::NoConsumerGroup: If `consumer` wasn't built with a group id (after aborting)
+    /// - Any error returned by `to_records`
+    pub async fn write_transaction(
+        &mut self,
+        consumer: &RedpandaConsumer,
+        batch: &[M],
+    ) -> Result<(), SinkError> {
+        let records = self.records(batch)?;
+        self.produce(records).await?;
+        self.commit_transaction(consumer).await
+    }
+
+    /// Records `to_records` builds for every measurement in the batch
+    fn records(&mut self, batch: &[M]) -> Result<Vec<RedpandaRecord>, SinkError> {
+        let mut records = Vec::with_capacity(batch.len());
+        for measurement in batch {
+            records.extend((self.to_records)(measurement)?);
+        }
+        Ok(records)
+    }
+
+    /// Begin a transaction and produce `records` in it, aborting it if any record fails
+    async fn produce(&mut self, records: Vec<RedpandaRecord>) -> Result<(), SinkError> {
+        blocking(&self.producer, |p| p.producer.begin_transaction())
+            .await
+            .map_err(SinkError::KafkaError)?;
+
+        match self.send_records(&records).await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.abort(e).await),
+        }
+    }
+
+    async fn send_records(&mut self, records: &[RedpandaRecord]) -> Result<(), SinkError> {
+        let mut deliveries = Vec::with_capacity(records.len());
+        for record in records {
+            let delivery = self
+                .producer
+                .send_result(record)
+                .map_err(|(e, _)| SinkError::KafkaError(e))?;
+            deliveries.push(delivery);
+        }
+        for delivery in deliveries {
+            match delivery.await {
+                Ok(Ok(_)) => {}
+                Ok(Err((e, _))) => return Err(SinkError::KafkaError(e)),
+                Err(_) => {
+                    return Err(SinkError::KafkaError(KafkaError::Canceled));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Commit the open transaction with the consumer's offsets, aborting it if that fails
+    async fn commit_transaction(&mut self, consumer: &RedpandaConsumer) -> Result<(), SinkError> {
+        match self.send_offsets_and_commit(consumer).await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.abort(e).await),
+        }
+    }
+
+    async fn send_offsets_and_commit(
+        &mut self,
+        consumer: &RedpandaConsumer,
+    ) -> Result<(), SinkError> {
+        let offsets: TopicPartitionList = consumer.consumer.position()?;
+        let group_metadata = consumer
+            .consumer
+            .group_metadata()
+            .ok_or(SinkError::NoConsumerGroup)?;
+        let timeout = self.timeout;
+        blocking(&self.producer, move |p| {
+            p.producer
+                .send_offsets_to_transaction(&offsets, &group_metadata, timeout)?;
+            p.producer.commit_transaction(timeout)
+        })
+        .await
+        .map_err(SinkError::KafkaError)
+    }
+
+    /// Abort the open transaction after `error`, returning the error to report: `error`, or the abort's own error if
+    /// aborting failed too
+    async fn abort(&mut self, error: SinkError) -> SinkError {
+        event!(Level::WARN, "Aborting transaction. {}", error);
+        let timeout = self.timeout;
+        match blocking(&self.producer, move |p| {
+            p.producer.abort_transaction(timeout)
+        })
+        .await
+        {
+            Ok(()) => error,
+            Err(e) => SinkError::KafkaError(e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<M, F> SensorSink for ExactlyOnceSink<M, F>
+where
+    M: for<'a> Measurement<'a> + Send,
+    F: FnMut(&M) -> Result<Vec<RedpandaRecord>, SinkError> + Send,
+{
+    type Measurement = M;
+    type Error = SinkError;
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Begin the batch's transaction and produce its records, aborting the transaction on any error
+    async fn sink_batch(&mut self, batch: Vec<M>) -> Result<(), SinkError> {
+        let records = self.records(&batch)?;
+        self.produce(records).await
+    }
+
+    /// Send the consumer's offsets to the batch's transaction and commit it, aborting the transaction on any error
+    async fn commit_offsets(&mut self, consumer: &RedpandaConsumer) -> Result<(), SinkError> {
+        self.commit_transaction(consumer).await
+    }
+}
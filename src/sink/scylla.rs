@@ -0,0 +1,183 @@
+//! Store measurements in ScyllaDB for OLTP queries
+//!
+//! Measurements are partitioned by `source_id` and clustered by `timestamp_ns`, so a single sensor's measurements
+//! over a time range can be read from one partition in order. The remaining columns come from a user-supplied
+//! closure, so the sink works for any measurement.
+
+use std::sync::Arc;
+
+use futures_util::future::try_join_all;
+use scylla::frame::response::result::CqlValue;
+use scylla::frame::value::SerializedValues;
+use scylla::prepared_statement::PreparedStatement;
+use scylla::transport::errors::{BadQuery, QueryError};
+use scylla::Session;
+
+use crate::measurement::Measurement;
+use crate::sink::error::SinkError;
+use crate::SensorSink;
+
+/// A column of a measurement's ScyllaDB table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScyllaColumn {
+    /// Column name
+    pub name: &'static str,
+    /// CQL type, i.e. "bigint", "double", "text", or "blob"
+    pub cql_type: &'static str,
+}
+
+impl ScyllaColumn {
+    /// Column named `name` of type `cql_type`
+    pub const fn new(name: &'static str, cql_type: &'static str) -> Self {
+        ScyllaColumn { name, cql_type }
+    }
+}
+
+/// Maps a measurement to the values of its columns, in the order the columns were given
+type ColumnValues<M> = Box<dyn Fn(&M) -> Vec<CqlValue> + Send + Sync>;
+
+/// Writes measurements to a ScyllaDB table keyed by `source_id` and `timestamp_ns`
+pub struct ScyllaSink<M> {
+    session: Arc<Session>,
+    insert: PreparedStatement,
+    values: ColumnValues<M>,
+    batch_size: usize,
+}
+
+impl<M> ScyllaSink<M>
+where
+    M: for<'a> Measurement<'a>,
+{
+    /// ScyllaDB sink writing to `keyspace.table`, creating the table if it doesn't exist yet
+    ///
+    /// The table has a `source_id text` partition key and a `timestamp_ns bigint` clustering key, followed by
+    /// `columns`. `values` returns the value of each of `columns` for a measurement. The keyspace must already exist.
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::ScyllaError: If the table can't be created or the insert can't be prepared
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let session = SessionBuilder::new().known_node("127.0.0.1:9042").build().await?;
+    /// let sink = ScyllaSink::<RadarMeasurement2d>::new(
+    ///     Arc::new(session),
+    ///     "opensensor",
+    ///     "radar_2d",
+    ///     &[ScyllaColumn::new("range_m", "double"), ScyllaColumn::new("bearing_deg", "double")],
+    ///     |m| vec![CqlValue::Double(m.range_m), CqlValue::Double(m.bearing_deg)],
+    /// )
+    /// .await?;
+    /// ```
+    pub async fn new<F>(
+        session: Arc<Session>,
+        keyspace: &str,
+        table: &str,
+        columns: &[ScyllaColumn],
+        values: F,
+    ) -> Result<Self, SinkError>
+    where
+        F: Fn(&M) -> Vec<CqlValue> + Send + Sync + 'static,
+    {
+        let mut definitions = vec![
+            "source_id text".to_owned(),
+            "timestamp_ns bigint".to_owned(),
+        ];
+        definitions.extend(columns.iter().map(|c| format!("{} {}", c.name, c.cql_type)));
+        session
+            .query(
+                format!(
+                    "CREATE TABLE IF NOT EXISTS {}.{} ({}, PRIMARY KEY ((source_id), timestamp_ns))",
+                    keyspace,
+                    table,
+                    definitions.join(", ")
+                ),
+                &[],
+            )
+            .await
+            .map_err(SinkError::ScyllaError)?;
+
+        let mut names = vec!["source_id", "timestamp_ns"];
+        names.extend(columns.iter().map(|c| c.name));
+        let placeholders = vec!["?"; names.len()];
+        let insert = session
+            .prepare(format!(
+                "INSERT INTO {}.{} ({}) VALUES ({})",
+                keyspace,
+                table,
+                names.join(", "),
+                placeholders.join(", ")
+            ))
+            .await
+            .map_err(SinkError::ScyllaError)?;
+
+        Ok(ScyllaSink {
+            session,
+            insert,
+            values: Box::new(values),
+            batch_size: 1000,
+        })
+    }
+
+    /// Write batches of `batch_size` measurements (1000 by default) when run with `SensorSink::run`
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Session the sink writes with
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Bound values for inserting a measurement
+    fn row(&self, measurement: &M) -> Result<SerializedValues, SinkError> {
+        let mut row = SerializedValues::new();
+        let key = [
+            CqlValue::Text(measurement.source_id().to_owned()),
+            CqlValue::BigInt(measurement.timestamp_nanos()),
+        ];
+        for value in key.iter().chain((self.values)(measurement).iter()) {
+            row.add_value(value).map_err(|e| {
+                SinkError::ScyllaError(QueryError::BadQuery(BadQuery::SerializeValuesError(e)))
+            })?;
+        }
+
+        Ok(row)
+    }
+}
+
+#[async_trait::async_trait]
+impl<M> SensorSink for ScyllaSink<M>
+where
+    M: for<'a> Measurement<'a> + Send,
+{
+    type Measurement = M;
+    type Error = SinkError;
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Insert every measurement in the batch, returning once Scylla has acknowledged them all
+    ///
+    /// Rows are inserted concurrently rather than in a CQL batch, since a batch spanning many partitions makes a
+    /// single coordinator do all the work. Inserts are idempotent (same key, same row), so a failed batch can be
+    /// retried as a whole.
+    async fn sink_batch(&mut self, batch: Vec<M>) -> Result<(), SinkError> {
+        let rows = batch
+            .iter()
+            .map(|m| self.row(m))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        try_join_all(
+            rows.iter()
+                .map(|row| self.session.execute(&self.insert, row)),
+        )
+        .await
+        .map_err(SinkError::ScyllaError)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+//! Store measurements in SQLite for edge deployments (i.e. MyCelial)
+//!
+//! Each measurement topic gets its own table, named after the topic. Every table has `source_id` and `timestamp_ns`
+//! columns, then the `kafka_topic`, `kafka_partition`, and `kafka_offset` of the record each row was consumed from,
+//! followed by the columns the measurement declares with `SqliteRow`.
+//!
+//! The record columns are unique together, and rows are inserted with `INSERT OR IGNORE`, so a batch that `run`
+//! replays after a crash between writing it and committing its offsets isn't stored twice. Rows written with
+//! `sink_batch` directly have no record, and are always inserted.
+
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+
+use crate::measurement::{Measurement, RecordMeta};
+use crate::sink::error::SinkError;
+use crate::SensorSink;
+
+/// A column of a measurement's SQLite table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SqliteColumn {
+    /// Column name
+    pub name: &'static str,
+    /// SQLite type affinity, i.e. "INTEGER", "REAL", "TEXT", or "BLOB"
+    pub sql_type: &'static str,
+}
+
+impl SqliteColumn {
+    /// Column named `name` with type affinity `sql_type`
+    pub const fn new(name: &'static str, sql_type: &'static str) -> Self {
+        SqliteColumn { name, sql_type }
+    }
+}
+
+/// Measurement that can be stored as a row of a SQLite table
+pub trait SqliteRow {
+    /// Columns for the measurement's fields, in addition to `source_id`, `timestamp_ns`, and the record columns
+    fn columns() -> Vec<SqliteColumn>;
+
+    /// Value of each column returned by `columns`, in the same order
+    fn values(&self) -> Vec<Value>;
+}
+
+/// Writes measurements to a SQLite table named after the measurement's topic
+pub struct SqliteSink<M> {
+    connection: Mutex<Connection>,
+    insert: String,
+    batch_size: usize,
+    measurement: PhantomData<fn(M)>,
+}
+
+impl<M> SqliteSink<M>
+where
+    M: for<'a> Measurement<'a> + SqliteRow,
+{
+    /// SQLite sink writing to the database file at `path`, creating it if needed
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::SqliteError: If the database can't be opened or the table can't be created
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SinkError> {
+        Self::new(Connection::open(path).map_err(SinkError::SqliteError)?)
+    }
+
+    /// SQLite sink writing to a new in-memory database
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::SqliteError: If the table can't be created
+    pub fn open_in_memory() -> Result<Self, SinkError> {
+        Self::new(Connection::open_in_memory().map_err(SinkError::SqliteError)?)
+    }
+
+    /// SQLite sink writing with `connection`, creating the measurement's table if it doesn't exist yet
+    ///
+    /// # Errors
+    ///
+    /// - SinkError::SqliteError: If the table can't be created
+    pub fn new(connection: Connection) -> Result<Self, SinkError> {
+        let table = table_name::<M>();
+        let columns = M::columns();
+
+        let mut definitions = vec![
+            "source_id TEXT NOT NULL".to_owned(),
+            "timestamp_ns INTEGER NOT NULL".to_owned(),
+            "kafka_topic TEXT".to_owned(),
+            "kafka_partition INTEGER".to_owned(),
+            "kafka_offset INTEGER".to_owned(),
+        ];
+        definitions.extend(
+            columns
+                .iter()
+                .map(|c| format!("{} {}", quote(c.name), c.sql_type)),
+        );
+        // NULLs never conflict, so rows without a record are never ignored
+        definitions.push("UNIQUE (kafka_topic, kafka_partition, kafka_offset)".to_owned());
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} ({})",
+                    table,
+                    definitions.join(", ")
+                ),
+                [],
+            )
+            .map_err(SinkError::SqliteError)?;
+
+        let mut names = vec![
+            "source_id".to_owned(),
+            "timestamp_ns".to_owned(),
+            "kafka_topic".to_owned(),
+            "kafka_partition".to_owned(),
+            "kafka_offset".to_owned(),
+        ];
+        names.extend(columns.iter().map(|c| quote(c.name)));
+        let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{}", i)).collect();
+        let insert = format!(
+            "INSERT OR IGNORE INTO {} ({}) VALUES ({})",
+            table,
+            names.join(", "),
+            placeholders.join(", ")
+        );
+
+        Ok(SqliteSink {
+            connection: Mutex::new(connection),
+            insert,
+            batch_size: 1000,
+            measurement: PhantomData,
+        })
+    }
+
+    /// Write batches of `batch_size` measurements (1000 by default) when run with `SensorSink::run`
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Lock the underlying connection, i.e. to query what's been written
+    pub fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
+
+    /// Insert measurements and the records they were consumed from in a single transaction, ignoring any whose
+    /// record is already stored
+    fn insert<'m>(
+        &mut self,
+        rows: impl Iterator<Item = (&'m M, Option<&'m RecordMeta>)>,
+    ) -> Result<(), SinkError>
+    where
+        M: 'm,
+    {
+        let connection = self.connection.get_mut().unwrap();
+        let transaction = connection.transaction().map_err(SinkError::SqliteError)?;
+        {
+            let mut statement = transaction
+                .prepare_cached(&self.insert)
+                .map_err(SinkError::SqliteError)?;
+            for (measurement, meta) in rows {
+                let mut values = vec![
+                    Value::Text(measurement.source_id().to_owned()),
+                    Value::Integer(measurement.timestamp_nanos()),
+                ];
+                match meta {
+                    Some(meta) => values.extend([
+                        Value::Text(meta.topic.clone()),
+                        Value::Integer(meta.partition.into()),
+                        Value::Integer(meta.offset),
+                    ]),
+                    None => values.extend([Value::Null, Value::Null, Value::Null]),
+                }
+                values.extend(measurement.values());
+                statement
+                    .execute(params_from_iter(values))
+                    .map_err(SinkError::SqliteError)?;
+            }
+        }
+
+        transaction.commit().map_err(SinkError::SqliteError)
+    }
+}
+
+#[async_trait::async_trait]
+impl<M> SensorSink for SqliteSink<M>
+where
+    M: for<'a> Measurement<'a> + SqliteRow + Send,
+{
+    type Measurement = M;
+    type Error = SinkError;
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Insert the batch in a single transaction, so either every measurement is written or none are
+    ///
+    /// SQLite calls block, so keep batches small enough that a write doesn't stall the runtime.
+    async fn sink_batch(&mut self, batch: Vec<M>) -> Result<(), SinkError> {
+        self.insert(batch.iter().map(|measurement| (measurement, None)))
+    }
+
+    /// Insert the batch in a single transaction like `sink_batch`, skipping records that are already stored
+    async fn sink_records(&mut self, batch: Vec<(M, RecordMeta)>) -> Result<(), SinkError> {
+        self.insert(
+            batch
+                .iter()
+                .map(|(measurement, meta)| (measurement, Some(meta))),
+        )
+    }
+}
+
+/// Quoted name of the table a Measurement is stored in
+pub fn table_name<M>() -> String
+where
+    M: for<'a> Measurement<'a>,
+{
+    quote(M::TOPIC_NAME)
+}
+
+/// Quote an SQL identifier, since topic names contain `.` and `-`
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
@@ -0,0 +1,488 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use rdkafka::Offset;
+use redpanda::consumer::Consumer;
+use redpanda::producer::RedpandaRecord;
+use redpanda::RedpandaBuilder;
+
+use crate::measurement::Measurement;
+use crate::sink::error::SinkError;
+use crate::sink::memory::MemorySink;
+use crate::sink::{transactional_producer, ExactlyOnceSink, SinkHost, DEFAULT_TRANSACTION_TIMEOUT};
+use crate::tests::TestMeasurement;
+use crate::SensorSink;
+
+/// Kafka brokers from the OpenSensor docker-compose
+const KAFKA_ADDRESSES: &str = "127.0.0.1:9010,127.0.0.1:9011,127.0.0.1:9012";
+
+/// Sink whose every batch fails
+struct FailingSink;
+
+#[async_trait::async_trait]
+impl SensorSink for FailingSink {
+    type Measurement = TestMeasurement;
+    type Error = SinkError;
+
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    async fn sink_batch(&mut self, _batch: Vec<TestMeasurement>) -> Result<(), SinkError> {
+        Err(SinkError::IoError(std::io::ErrorKind::Other.into()))
+    }
+}
+
+/// Whether `group_id` has committed an offset for any partition of the TestMeasurement topic
+fn has_committed(group_id: &str) -> bool {
+    let mut builder = RedpandaBuilder::default();
+    builder.set_bootstrap_servers(KAFKA_ADDRESSES);
+    builder.set_group_id(group_id);
+    let consumer = builder.build_consumer().unwrap();
+    let metadata = consumer
+        .consumer
+        .fetch_metadata(Some(TestMeasurement::TOPIC_NAME), Duration::from_secs(10))
+        .unwrap();
+    let mut partitions = rdkafka::TopicPartitionList::new();
+    for partition in metadata.topics()[0].partitions() {
+        partitions.add_partition(TestMeasurement::TOPIC_NAME, partition.id());
+    }
+    let committed = consumer
+        .consumer
+        .committed_offsets(partitions, Duration::from_secs(10))
+        .unwrap();
+    committed
+        .elements()
+        .iter()
+        .any(|e| matches!(e.offset(), Offset::Offset(_)))
+}
+
+#[tokio::test]
+async fn test_sink_host_independent_sinks() {
+    let source_id = format!("sink-host-{}", std::process::id());
+    let count = 10;
+
+    let mut builder = RedpandaBuilder::default();
+    builder.set_bootstrap_servers(KAFKA_ADDRESSES);
+    let producer = builder.build_producer().unwrap();
+    for _ in 0..count {
+        let record = TestMeasurement::new(&source_id, chrono::Utc::now()).to_message();
+        producer
+            .send_result(&record)
+            .map_err(|(e, _)| e)
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    // Every sink has its own consumer group, so each sees every measurement and commits after its own batches,
+    // whatever the others do
+    let group_prefix = format!("sink-host-{}", std::process::id());
+    let batched = MemorySink::<TestMeasurement>::new().with_batch_size(4);
+    let single = MemorySink::<TestMeasurement>::new().with_batch_size(1);
+    let mut host = SinkHost::new(KAFKA_ADDRESSES, &group_prefix, 2);
+    host.set("auto.offset.reset", "earliest");
+    host.add("batched", batched.clone())
+        .add("single", single.clone())
+        .add("failing", FailingSink);
+    assert_eq!(host.len(), 3);
+    let group_ids = ["batched", "single", "failing"].map(|name| host.group_id(name));
+
+    let run = tokio::spawn(host.run());
+    let seen = |sink: &MemorySink<TestMeasurement>| {
+        sink.measurements()
+            .iter()
+            .filter(|m| m.source_id == source_id)
+            .count()
+    };
+    tokio::time::timeout(Duration::from_secs(60), async {
+        while seen(&batched) < count || seen(&single) < count {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
+    run.abort();
+
+    assert!(has_committed(&group_ids[0]));
+    assert!(has_committed(&group_ids[1]));
+    // The failing sink never wrote a batch, so it replays from the start on restart
+    assert!(!has_committed(&group_ids[2]));
+}
+
+#[test]
+fn test_sink_host_names() {
+    let mut host = SinkHost::new(KAFKA_ADDRESSES, "radar-2d-sinks", 0);
+    assert!(host.is_empty());
+    host.add("recent", MemorySink::<TestMeasurement>::new());
+    assert_eq!(host.len(), 1);
+    assert_eq!(host.group_id("recent"), "radar-2d-sinks-recent");
+
+    // Sinks sharing a name would share a consumer group and split the topic between them
+    let duplicate = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        host.add("recent", MemorySink::<TestMeasurement>::new());
+    }));
+    assert!(duplicate.is_err());
+}
+
+#[tokio::test]
+async fn test_exactly_once_sink() {
+    let id = std::process::id();
+    let input_topic = format!("raw.test.exactly-once-input-{}", id);
+    let output_topic = format!("raw.test.exactly-once-output-{}", id);
+    let count = 5;
+
+    let mut builder = RedpandaBuilder::default();
+    builder.set_bootstrap_servers(KAFKA_ADDRESSES);
+    let producer = builder.build_producer().unwrap();
+    for i in 0..count {
+        let m = TestMeasurement::new(&format!("radar-{}", i), chrono::Utc::now());
+        let record = RedpandaRecord::new(&input_topic, None, m.to_bytes(), None);
+        producer
+            .send_result(&record)
+            .map_err(|(e, _)| e)
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let producer = transactional_producer(
+        &mut builder,
+        &format!("exactly-once-{}", id),
+        DEFAULT_TRANSACTION_TIMEOUT,
+    )
+    .await
+    .unwrap();
+
+    let mut builder = RedpandaBuilder::default();
+    builder.set_bootstrap_servers(KAFKA_ADDRESSES);
+    builder.set_group_id(&format!("exactly-once-{}", id));
+    builder.set("enable.auto.commit", "false");
+    builder.set("isolation.level", "read_committed");
+    let consumer = builder.build_consumer().unwrap();
+    consumer.subscribe(&[&input_topic]).unwrap();
+
+    let mut batch = Vec::with_capacity(count);
+    {
+        let mut stream = consumer.stream();
+        while batch.len() < count {
+            let message = tokio::time::timeout(Duration::from_secs(30), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            batch.push(TestMeasurement::from_message(message).unwrap());
+        }
+    }
+    let committed_total = || {
+        consumer
+            .consumer
+            .committed(Duration::from_secs(10))
+            .unwrap()
+            .elements()
+            .iter()
+            .map(|e| match e.offset() {
+                Offset::Offset(offset) => offset,
+                _ => 0,
+            })
+            .sum::<i64>()
+    };
+
+    // A failing write aborts the transaction, leaving the offsets uncommitted
+    let mut failing =
+        ExactlyOnceSink::<TestMeasurement, _>::new(producer.clone(), |_| Err(SinkError::Closed));
+    let result = failing.write_transaction(&consumer, &batch).await;
+    assert!(matches!(result, Err(SinkError::Closed)));
+    assert_eq!(committed_total(), 0);
+
+    // A successful write commits the output and the offsets together
+    let mut sink = ExactlyOnceSink::<TestMeasurement, _>::new(producer, |m| {
+        let key = Some(m.source_id().as_bytes().to_vec());
+        Ok(vec![RedpandaRecord::new(
+            &output_topic,
+            key,
+            m.clone().to_bytes(),
+            None,
+        )])
+    });
+    sink.write_transaction(&consumer, &batch).await.unwrap();
+    assert_eq!(committed_total(), count as i64);
+
+    let mut builder = RedpandaBuilder::default();
+    builder.set_bootstrap_servers(KAFKA_ADDRESSES);
+    builder.set_group_id(&format!("exactly-once-reader-{}", id));
+    builder.set("isolation.level", "read_committed");
+    let reader = builder.build_consumer().unwrap();
+    reader.subscribe(&[&output_topic]).unwrap();
+
+    let mut written = Vec::new();
+    let mut stream = reader.stream();
+    while let Ok(Some(message)) = tokio::time::timeout(Duration::from_secs(10), stream.next()).await
+    {
+        written.push(TestMeasurement::from_message(message.unwrap()).unwrap());
+    }
+    written.sort_by(|a, b| a.source_id.cmp(&b.source_id));
+    batch.sort_by(|a, b| a.source_id.cmp(&b.source_id));
+    assert_eq!(written, batch);
+}
+
+#[tokio::test]
+async fn test_memory_sink() {
+    let mut sink = MemorySink::<TestMeasurement>::new().with_batch_size(2);
+    let written = sink.clone();
+    assert!(written.is_empty());
+    assert_eq!(sink.batch_size(), 2);
+
+    let now = chrono::Utc::now();
+    let batch: Vec<TestMeasurement> = ["a", "b"]
+        .iter()
+        .map(|source_id| TestMeasurement::new(source_id, now))
+        .collect();
+    sink.sink_batch(batch.clone()).await.unwrap();
+    sink.sink_batch(vec![TestMeasurement::new("c", now)])
+        .await
+        .unwrap();
+
+    assert_eq!(written.len(), 3);
+    assert_eq!(written.batches(), 2);
+    assert_eq!(written.measurements()[..2], batch[..]);
+    assert_eq!(written.take()[2].source_id, "c");
+    assert!(sink.is_empty());
+}
+
+#[cfg(feature = "json")]
+mod jsonl {
+    use chrono::{TimeZone, Utc};
+
+    use crate::sink::{error::SinkError, jsonl::JsonlSink};
+    use crate::tests::TestMeasurement;
+    use crate::SensorSink;
+
+    /// Writer that behaves like a pipe whose reader has exited
+    struct ClosedPipe;
+
+    impl std::io::Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_jsonl_sink_write_batch() {
+        let batch = [
+            TestMeasurement::new("a", Utc.timestamp_opt(0, 0).unwrap()),
+            TestMeasurement::new("b", Utc.timestamp_opt(1, 500_000_000).unwrap()),
+        ];
+
+        let mut sink = JsonlSink::new(Vec::new());
+        sink.write_batch(&batch).unwrap();
+        assert_eq!(sink.written(), 2);
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(
+            output,
+            "{\"source_id\":\"a\",\"timestamp\":\"1970-01-01T00:00:00Z\"}\n\
+             {\"source_id\":\"b\",\"timestamp\":\"1970-01-01T00:00:01.500Z\"}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jsonl_sink_broken_pipe() {
+        let batch = vec![TestMeasurement::new("a", Utc.timestamp_opt(0, 0).unwrap())];
+
+        let mut sink = JsonlSink::new(ClosedPipe).with_batch_size(10);
+        assert_eq!(sink.batch_size(), 10);
+        assert!(matches!(sink.write_batch(&batch), Err(SinkError::Closed)));
+        // run_jsonl_sink stops cleanly on the Closed error sink_batch returns from SensorSink::run
+        assert!(matches!(
+            sink.sink_batch(batch).await,
+            Err(SinkError::Closed)
+        ));
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use chrono::{TimeZone, Utc};
+    use rusqlite::types::Value;
+
+    use crate::measurement::{Measurement, RecordMeta};
+    use crate::sink::sqlite::{table_name, SqliteColumn, SqliteRow, SqliteSink};
+    use crate::tests::TestMeasurement;
+    use crate::SensorSink;
+
+    impl SqliteRow for TestMeasurement {
+        fn columns() -> Vec<SqliteColumn> {
+            vec![SqliteColumn::new("timestamp_rfc3339", "TEXT")]
+        }
+
+        fn values(&self) -> Vec<Value> {
+            vec![Value::Text(self.timestamp.to_rfc3339())]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_sink_batch() {
+        let mut sink = SqliteSink::<TestMeasurement>::open_in_memory().unwrap();
+        let batch: Vec<TestMeasurement> = (0..3)
+            .map(|i| {
+                TestMeasurement::new(&format!("radar-{}", i), Utc.timestamp_opt(i, 0).unwrap())
+            })
+            .collect();
+
+        sink.sink_batch(batch.clone()).await.unwrap();
+
+        let connection = sink.connection();
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT source_id, timestamp_ns, timestamp_rfc3339 FROM {} ORDER BY timestamp_ns",
+                table_name::<TestMeasurement>()
+            ))
+            .unwrap();
+        let rows: Vec<(String, i64, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let expected: Vec<(String, i64, String)> = batch
+            .iter()
+            .map(|m| {
+                (
+                    m.source_id.clone(),
+                    m.timestamp.timestamp_nanos(),
+                    m.timestamp.to_rfc3339(),
+                )
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_sink_records_idempotent() {
+        use redpanda::message::Timestamp;
+
+        let mut sink = SqliteSink::<TestMeasurement>::open_in_memory().unwrap();
+        let record = |offset: i64| {
+            let measurement =
+                TestMeasurement::new("radar-1", Utc.timestamp_opt(offset, 0).unwrap());
+            let meta = RecordMeta {
+                topic: TestMeasurement::TOPIC_NAME.to_owned(),
+                partition: 0,
+                offset,
+                timestamp: Timestamp::NotAvailable,
+                key: None,
+            };
+            (measurement, meta)
+        };
+
+        sink.sink_records((0..3).map(record).collect())
+            .await
+            .unwrap();
+        // Replayed after a crash before the offsets were committed, along with a record that wasn't written yet
+        sink.sink_records((0..4).map(record).collect())
+            .await
+            .unwrap();
+        // Measurements written without a record are never ignored
+        let batch = vec![record(0).0];
+        sink.sink_batch(batch.clone()).await.unwrap();
+        sink.sink_batch(batch).await.unwrap();
+
+        let connection = sink.connection();
+        let offsets: Vec<Option<i64>> = connection
+            .prepare(&format!(
+                "SELECT kafka_offset FROM {} ORDER BY rowid",
+                table_name::<TestMeasurement>()
+            ))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(offsets, [Some(0), Some(1), Some(2), Some(3), None, None]);
+    }
+}
+
+#[cfg(feature = "scylla")]
+mod scylla {
+    use std::sync::Arc;
+
+    use chrono::{TimeZone, Utc};
+    use scylla::frame::response::result::CqlValue;
+    use scylla::SessionBuilder;
+
+    use crate::sink::scylla::{ScyllaColumn, ScyllaSink};
+    use crate::tests::TestMeasurement;
+    use crate::SensorSink;
+
+    /// ScyllaDB node from the OpenSensor docker-compose
+    const SCYLLA_ADDRESS: &str = "127.0.0.1:9042";
+
+    #[tokio::test]
+    async fn test_scylla_sink_batch() {
+        let session = SessionBuilder::new()
+            .known_node(SCYLLA_ADDRESS)
+            .build()
+            .await
+            .unwrap();
+        session
+            .query(
+                "CREATE KEYSPACE IF NOT EXISTS opensensor_test WITH REPLICATION = \
+                 {'class': 'SimpleStrategy', 'replication_factor': 1}",
+                &[],
+            )
+            .await
+            .unwrap();
+        session
+            .query("DROP TABLE IF EXISTS opensensor_test.test_measurement", &[])
+            .await
+            .unwrap();
+
+        let mut sink = ScyllaSink::<TestMeasurement>::new(
+            Arc::new(session),
+            "opensensor_test",
+            "test_measurement",
+            &[ScyllaColumn::new("timestamp_rfc3339", "text")],
+            |m| vec![CqlValue::Text(m.timestamp.to_rfc3339())],
+        )
+        .await
+        .unwrap();
+
+        let batch: Vec<TestMeasurement> = (0..3)
+            .map(|i| TestMeasurement::new("radar-1", Utc.timestamp_opt(i, 0).unwrap()))
+            .collect();
+        sink.sink_batch(batch.clone()).await.unwrap();
+
+        let rows: Vec<(String, i64, String)> = sink
+            .session()
+            .query(
+                "SELECT source_id, timestamp_ns, timestamp_rfc3339 FROM opensensor_test.test_measurement \
+                 WHERE source_id = 'radar-1'",
+                &[],
+            )
+            .await
+            .unwrap()
+            .rows_typed::<(String, i64, String)>()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let expected: Vec<(String, i64, String)> = batch
+            .iter()
+            .map(|m| {
+                (
+                    m.source_id.clone(),
+                    m.timestamp.timestamp_nanos(),
+                    m.timestamp.to_rfc3339(),
+                )
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+}
@@ -0,0 +1,251 @@
+//! Stream combinators for measurement streams
+//!
+//! Kafka delivers measurements in arrival order, but time-partitioned sinks want each batch they write to fall in a
+//! single time partition. `rechunk_by_event_time` regroups a measurement stream into batches by
+//! `Measurement::partition_timestamp` window.
+//!
+//! `take_until_timestamp` bounds a stream in time, so replays and exports of a historical window terminate.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_stream::stream;
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use tracing::{event, Level};
+
+use crate::measurement::{nanos_to_date_time, Measurement};
+
+/// What to do with a measurement whose event-time window has already been emitted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LateData {
+    /// Drop late measurements, logging a WARN and counting them
+    Drop,
+    /// Emit late measurements individually as `Rechunked::Late` so the caller can route them elsewhere
+    Redirect,
+}
+
+/// Measurements from one event-time window
+#[derive(Debug, PartialEq, Eq)]
+pub struct EventTimeBatch<M> {
+    /// Inclusive start of the window
+    pub window_start: DateTime<Utc>,
+    /// Measurements in the window, ordered by `partition_timestamp` (ties keep arrival order)
+    pub measurements: Vec<M>,
+}
+
+/// Output of `rechunk_by_event_time`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rechunked<M> {
+    /// Every measurement received for a window that has closed
+    Batch(EventTimeBatch<M>),
+    /// A measurement that arrived after its window closed, only emitted with `LateData::Redirect`
+    Late(M),
+}
+
+/// Groups measurements into fixed, epoch-aligned event-time windows
+///
+/// The watermark trails the latest `partition_timestamp` seen by `allowed_lateness`. A window is emitted once the
+/// watermark passes its end, so measurements can arrive up to `allowed_lateness` out of order and still land in the
+/// right batch. Anything later than that is late data and is handled according to `LateData`.
+///
+/// Memory use is bounded by the number of measurements within `window + allowed_lateness` of the latest event time.
+pub struct EventTimeRechunker<M> {
+    window: i64,
+    allowed_lateness: i64,
+    late_data: LateData,
+    /// Open windows, keyed by window start in nanoseconds since unix epoch
+    windows: BTreeMap<i64, Vec<M>>,
+    max_event_time: Option<i64>,
+    dropped: u64,
+}
+
+impl<M> EventTimeRechunker<M>
+where
+    M: for<'a> Measurement<'a>,
+{
+    /// Create a rechunker with no open windows
+    ///
+    /// `window` is clamped to be at least 1ns
+    pub fn new(window: Duration, allowed_lateness: Duration, late_data: LateData) -> Self {
+        EventTimeRechunker {
+            window: duration_nanos(window).max(1),
+            allowed_lateness: duration_nanos(allowed_lateness),
+            late_data,
+            windows: BTreeMap::new(),
+            max_event_time: None,
+            dropped: 0,
+        }
+    }
+
+    /// Current watermark, None until the first measurement arrives
+    pub fn watermark(&self) -> Option<DateTime<Utc>> {
+        self.watermark_nanos()
+            .and_then(|w| nanos_to_date_time(w).single())
+    }
+
+    /// Number of late measurements dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Number of measurements buffered in open windows
+    pub fn len(&self) -> usize {
+        self.windows.values().map(Vec::len).sum()
+    }
+
+    /// Whether there are no buffered measurements
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Add a measurement, returning any windows the advanced watermark closed, oldest first
+    ///
+    /// A late measurement is returned as `Rechunked::Late` (or dropped) without advancing the watermark.
+    pub fn push(&mut self, measurement: M) -> Vec<Rechunked<M>> {
+        let event_time = measurement.partition_timestamp().timestamp_nanos();
+        let window_start = event_time.div_euclid(self.window) * self.window;
+
+        if let Some(watermark) = self.watermark_nanos() {
+            if window_start.saturating_add(self.window) <= watermark {
+                return match self.late_data {
+                    LateData::Drop => {
+                        self.dropped += 1;
+                        event!(
+                            Level::WARN,
+                            dropped = self.dropped,
+                            "Dropped measurement from {} after its window closed",
+                            measurement.source_id()
+                        );
+                        Vec::new()
+                    }
+                    LateData::Redirect => vec![Rechunked::Late(measurement)],
+                };
+            }
+        }
+
+        self.windows
+            .entry(window_start)
+            .or_default()
+            .push(measurement);
+        self.max_event_time = Some(
+            self.max_event_time
+                .map_or(event_time, |max| max.max(event_time)),
+        );
+
+        let watermark = match self.watermark_nanos() {
+            Some(watermark) => watermark,
+            None => return Vec::new(),
+        };
+        let mut closed = Vec::new();
+        while let Some((&start, _)) = self.windows.iter().next() {
+            if start.saturating_add(self.window) > watermark {
+                break;
+            }
+            if let Some(measurements) = self.windows.remove(&start) {
+                closed.push(batch(start, measurements));
+            }
+        }
+
+        closed
+    }
+
+    /// Emit every open window, oldest first
+    ///
+    /// Call this when the input ends. The watermark is kept, so measurements pushed afterwards for a flushed window
+    /// are still treated as late.
+    pub fn flush(&mut self) -> Vec<Rechunked<M>> {
+        std::mem::take(&mut self.windows)
+            .into_iter()
+            .map(|(start, measurements)| batch(start, measurements))
+            .collect()
+    }
+
+    fn watermark_nanos(&self) -> Option<i64> {
+        self.max_event_time
+            .map(|max| max.saturating_sub(self.allowed_lateness))
+    }
+}
+
+/// Regroup a measurement stream into batches by `partition_timestamp` window
+///
+/// Windows are `window` long and aligned to the unix epoch, so every batch targets exactly one time partition.
+/// Measurements can arrive up to `allowed_lateness` behind the latest event time seen and still be batched
+/// correctly; later ones are dropped or redirected according to `late_data`. Open windows are flushed when the input
+/// stream ends.
+///
+/// See `EventTimeRechunker` for the watermark semantics.
+pub fn rechunk_by_event_time<S, M>(
+    measurements: S,
+    window: Duration,
+    allowed_lateness: Duration,
+    late_data: LateData,
+) -> impl Stream<Item = Rechunked<M>>
+where
+    S: Stream<Item = M>,
+    M: for<'a> Measurement<'a>,
+{
+    stream! {
+        let mut rechunker = EventTimeRechunker::new(window, allowed_lateness, late_data);
+
+        for await measurement in measurements {
+            for rechunked in rechunker.push(measurement) {
+                yield rechunked;
+            }
+        }
+        for rechunked in rechunker.flush() {
+            yield rechunked;
+        }
+    }
+}
+
+/// Yield measurements up to `end`, completing once the stream is past `end`
+///
+/// Measurements with a `timestamp` after `end` are never yielded. Because measurements can arrive out of order, the
+/// stream only completes once a measurement arrives more than `grace` after `end`; until then, later measurements
+/// are skipped and stragglers at or before `end` are still yielded. Use a zero `grace` for streams that are known to
+/// be in order.
+pub fn take_until_timestamp<S, M>(
+    measurements: S,
+    end: DateTime<Utc>,
+    grace: Duration,
+) -> impl Stream<Item = M>
+where
+    S: Stream<Item = M>,
+    M: for<'a> Measurement<'a>,
+{
+    let grace = chrono::Duration::from_std(grace).unwrap_or_else(|_| chrono::Duration::max_value());
+    let cutoff = end
+        .checked_add_signed(grace)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+    stream! {
+        for await measurement in measurements {
+            let timestamp = measurement.timestamp();
+            if timestamp > cutoff {
+                break;
+            }
+            if timestamp <= end {
+                yield measurement;
+            }
+        }
+    }
+}
+
+fn batch<M>(window_start: i64, mut measurements: Vec<M>) -> Rechunked<M>
+where
+    M: for<'a> Measurement<'a>,
+{
+    measurements.sort_by_key(|m| m.partition_timestamp());
+
+    Rechunked::Batch(EventTimeBatch {
+        window_start: nanos_to_date_time(window_start)
+            .single()
+            .unwrap_or(DateTime::<Utc>::MIN_UTC),
+        measurements,
+    })
+}
+
+fn duration_nanos(duration: Duration) -> i64 {
+    i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX)
+}
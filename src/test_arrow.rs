@@ -1,17 +1,46 @@
 use std::borrow::Borrow;
 use std::fs::File;
-use std::sync::Arc;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
 use arrow2::array::*;
 use arrow2::chunk::Chunk;
-use arrow2::datatypes::{Field, Schema};
+use arrow2::datatypes::{DataType, Field, Schema, TimeUnit};
 use arrow2::io::parquet::read;
-use arrow2::io::parquet::write::{
-    CompressionOptions, Encoding, FileWriter, RowGroupIterator, Version, WriteOptions, ZstdLevel,
-};
+use arrow2::io::parquet::write::{CompressionOptions, Encoding, Version, WriteOptions, ZstdLevel};
 use arrow2_convert::deserialize::{arrow_array_deserialize_iterator, TryIntoCollection};
 
 use arrow2_convert::{serialize::TryIntoArrow, ArrowDeserialize, ArrowField, ArrowSerialize};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::StreamExt;
+use parquet2::schema::types::PrimitiveLogicalType;
+
+use crate::arrow::{arrow_chunks, arrow_schema};
+use crate::parquet::leaf_encodings;
+use crate::parquet_io::{
+    read_parquet, read_parquet_filtered, write_parquet, ParquetStreamWriter, TimeRange,
+};
+
+/// Zstd compressed V1 pages with statistics, as the archiver writes them
+fn zstd_options() -> WriteOptions {
+    WriteOptions {
+        write_statistics: true,
+        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
+        version: Version::V1,
+        data_pagesize_limit: None,
+    }
+}
+
+/// Write a batch of structs to parquet bytes
+fn write_bytes<T>(batch: &[T]) -> arrow2::error::Result<Vec<u8>>
+where
+    T: arrow2_convert::field::ArrowField<Type = T>
+        + arrow2_convert::serialize::ArrowSerialize
+        + 'static,
+{
+    let mut buffer = vec![];
+    write_parquet(batch, &mut buffer, zstd_options())?;
+    Ok(buffer)
+}
 
 /// Complex example that uses the following features:
 ///
@@ -27,7 +56,7 @@ pub struct Root {
     a3: Option<Vec<u8>>,
     // date32
     a4: chrono::NaiveDate,
-    // timestamp(ns, None)
+    // timestamp(ns, None), i.e. a local time: use crate::arrow::UtcTimestamp for UTC timestamps
     a5: chrono::NaiveDateTime,
     // timestamp(ns, None)
     a6: Option<chrono::NaiveDateTime>,
@@ -51,7 +80,7 @@ pub struct Root {
     #[arrow_field(type = "arrow2_convert::field::LargeBinary")]
     large_binary: Vec<u8>,
     // fixed size binary
-    // #[arrow_field(type = "arrow2_convert::field::FixedSizeBinary<3>")]
+    #[arrow_field(type = "arrow2_convert::field::FixedSizeBinary<3>")]
     fixed_size_binary: Vec<u8>,
     // large string
     #[arrow_field(type = "arrow2_convert::field::LargeString")]
@@ -60,7 +89,7 @@ pub struct Root {
     #[arrow_field(type = "arrow2_convert::field::LargeVec<i64>")]
     large_vec: Vec<i64>,
     // fixed size vec
-    // #[arrow_field(type = "arrow2_convert::field::FixedSizeVec<i64, 3>")]
+    #[arrow_field(type = "arrow2_convert::field::FixedSizeVec<i64, 3>")]
     fixed_size_vec: Vec<i64>,
 }
 
@@ -245,81 +274,14 @@ fn test_round_trip() -> arrow2::error::Result<()> {
     Ok(())
 }
 
-/// Write arrow2 array to parquet bytes in a buffer
+/// Round trip structs with nested structs, lists, and custom types through parquet bytes
 #[test]
 fn round_trip_parquet() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
-    let original_array = [item(), item(), item()];
-
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "root_custom_struct",
-        <Root as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
+    let original_array = [item(), item2(), item()];
 
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
-
-    let options = WriteOptions {
-        write_statistics: true,
-        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
-        version: Version::V1,
-        data_pagesize_limit: None,
-    };
-
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 25]],
-    )?;
-
-    // anything implementing `std::io::Write` works
-    // let mut buffer = vec![];
-    let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
-
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
-    }
-    let _file_size = writer.end(None)?;
-
-    // // Wrap buffer in a Cursor...this makes the buffer impl Read & Seek (needed for read::read_metadata)
-    // let mut reader = std::io::Cursor::new(buffer);
-
-    // // we can read its metadata:
-    // let metadata = read::read_metadata(&mut reader)?;
-
-    // // and infer a [`Schema`] from the `metadata`.
-    // let schema = read::infer_schema(&metadata)?;
-
-    // println!("Schema: {:?}", &schema);
-
-    // // we can filter the columns we need (here we select all)
-    // let schema = schema.filter(|_index, _field| true);
-
-    // // we can read the statistics of all parquet's row groups (here for each field)
-    // for field in &schema.fields {
-    //     let statistics = read::statistics::deserialize(field, &metadata.row_groups)?;
-    //     println!("{statistics:#?}");
-    // }
-
-    // // Get all the row groups
-    // let row_groups = metadata
-    //     .row_groups;
-
-    // // We can then read the row groups into chunks
-    // let chunks = read::FileReader::new(reader, row_groups, schema, None, None, None);
-
-    // // iterate over chunks and validate each is not empty
-    // for maybe_chunk in chunks {
-    //     println!("{:?}", maybe_chunk);
-    //     let chunk = maybe_chunk?;
-    //     assert!(!chunk.is_empty());
-    // }
+    let buffer = write_bytes(&original_array)?;
+    let read_array: Vec<Root> = read_parquet(Cursor::new(buffer))?;
+    assert_eq!(read_array, original_array);
 
     Ok(())
 }
@@ -370,50 +332,20 @@ fn test_flat_roundtrip() -> arrow2::error::Result<()> {
     Ok(())
 }
 
-/// Write flat struct to parquet bytes
+/// Path in the temp directory to write the parquet file `name` to, so test runs don't leave files in the crate
+fn parquet_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("opensensor-{}", name))
+}
+
+/// Write flat struct to a parquet file
 ///
-/// This resulting parquet file can actually be opened by pyarrow in the parquet.ipynb notebook in the root of this crate
-/// This is the only parquet file generated by arrow2 that doesn't result in an OSError for nesting issues
+/// The resulting parquet file (see `parquet_path`) can be opened by pyarrow in the parquet.ipynb notebook
 #[test]
 fn flat_struct_parquet_file() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
     let original_array = [FlatStruct::default(), FlatStruct::default()];
 
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "flat_struct",
-        <FlatStruct as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
-
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
-
-    let options = WriteOptions {
-        write_statistics: true,
-        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
-        version: Version::V1,
-        data_pagesize_limit: None,
-    };
-
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 3]],
-    )?;
-
-    // anything implementing `std::io::Write` works
-    // let mut buffer = vec![];
-    let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
-
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
-    }
-    let _file_size = writer.end(None)?;
+    let file = File::create(parquet_path("test.parquet")).unwrap();
+    write_parquet(&original_array, file, zstd_options())?;
 
     Ok(())
 }
@@ -421,77 +353,11 @@ fn flat_struct_parquet_file() -> arrow2::error::Result<()> {
 /// Round trip flat struct (no nested structs/arrays) to parquet file bytes and back
 #[test]
 fn flat_struct_round_trip_parquet() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
     let original_array = [FlatStruct::default(), FlatStruct::default()];
 
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "flat_struct",
-        <FlatStruct as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
-
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
-
-    let options = WriteOptions {
-        write_statistics: true,
-        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
-        version: Version::V1,
-        data_pagesize_limit: None,
-    };
-
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 3]],
-    )?;
-
-    // anything implementing `std::io::Write` works
-    let mut buffer = vec![];
-    // let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
-
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
-    }
-    let _file_size = writer.end(None)?;
-
-    // Wrap buffer in a Cursor...this makes the buffer impl Read & Seek (needed for read::read_metadata)
-    let mut reader = std::io::Cursor::new(buffer);
-
-    // we can read its metadata:
-    let metadata = read::read_metadata(&mut reader)?;
-
-    // and infer a [`Schema`] from the `metadata`.
-    let schema = read::infer_schema(&metadata)?;
-
-    println!("Schema: {:?}", &schema);
-
-    // we can filter the columns we need (here we select all)
-    let schema = schema.filter(|_index, _field| true);
-
-    // we can read the statistics of all parquet's row groups (here for each field)
-    for field in &schema.fields {
-        let statistics = read::statistics::deserialize(field, &metadata.row_groups)?;
-        println!("{statistics:#?}");
-    }
-
-    // Get all the row groups
-    let row_groups = metadata.row_groups;
-
-    // We can then read the row groups into chunks
-    let chunks = read::FileReader::new(reader, row_groups, schema, None, None, None);
-
-    // iterate over chunks and validate each is not empty
-    for maybe_chunk in chunks {
-        println!("{:?}", maybe_chunk);
-        let chunk = maybe_chunk?;
-        assert!(!chunk.is_empty());
-    }
+    let buffer = write_bytes(&original_array)?;
+    let read_array: Vec<FlatStruct> = read_parquet(Cursor::new(buffer))?;
+    assert_eq!(read_array, original_array);
 
     Ok(())
 }
@@ -541,51 +407,16 @@ fn test_array_roundtrip() -> arrow2::error::Result<()> {
     Ok(())
 }
 
-/// Round trip serialization of a struct with a nested array to parquet and back
+/// Write a struct with a nested array to a parquet file
 ///
-/// ERROR: even though this test will pass, if you try to read the resulting parquet file with pyarrow
-/// using the parquet.ipynb notebook in the root of this crate, you get an
-/// "OSError: Malformed levels. min: 0 max: 3 out of range.  Max Level: 2"
+/// Open the resulting file (see `parquet_path`) with pyarrow using the parquet.ipynb notebook to check that the
+/// nested lists read back the same as they were written
 #[test]
 fn array_struct_parquet_file() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
     let original_array = [ArrayStruct::default(), ArrayStruct::default()];
 
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "array_struct",
-        <ArrayStruct as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
-
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
-
-    let options = WriteOptions {
-        write_statistics: true,
-        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
-        version: Version::V1,
-        data_pagesize_limit: None,
-    };
-
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 3]],
-    )?;
-
-    // anything implementing `std::io::Write` works
-    // let mut buffer = vec![];
-    let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
-
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
-    }
-    let _file_size = writer.end(None)?;
+    let file = File::create(parquet_path("test.parquet")).unwrap();
+    write_parquet(&original_array, file, zstd_options())?;
 
     Ok(())
 }
@@ -593,77 +424,11 @@ fn array_struct_parquet_file() -> arrow2::error::Result<()> {
 /// Round trip serialization to parquet bytes in a buffer and back
 #[test]
 fn array_struct_round_trip_parquet() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
     let original_array = [ArrayStruct::default(), ArrayStruct::default()];
 
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "flat_struct",
-        <ArrayStruct as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
-
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
-
-    let options = WriteOptions {
-        write_statistics: true,
-        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
-        version: Version::V1,
-        data_pagesize_limit: None,
-    };
-
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 3]],
-    )?;
-
-    // anything implementing `std::io::Write` works
-    let mut buffer = vec![];
-    // let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
-
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
-    }
-    let _file_size = writer.end(None)?;
-
-    // Wrap buffer in a Cursor...this makes the buffer impl Read & Seek (needed for read::read_metadata)
-    let mut reader = std::io::Cursor::new(buffer);
-
-    // we can read its metadata:
-    let metadata = read::read_metadata(&mut reader)?;
-
-    // and infer a [`Schema`] from the `metadata`.
-    let schema = read::infer_schema(&metadata)?;
-
-    println!("Schema: {:?}", &schema);
-
-    // we can filter the columns we need (here we select all)
-    let schema = schema.filter(|_index, _field| true);
-
-    // we can read the statistics of all parquet's row groups (here for each field)
-    for field in &schema.fields {
-        let statistics = read::statistics::deserialize(field, &metadata.row_groups)?;
-        println!("{statistics:#?}");
-    }
-
-    // Get all the row groups
-    let row_groups = metadata.row_groups;
-
-    // We can then read the row groups into chunks
-    let chunks = read::FileReader::new(reader, row_groups, schema, None, None, None);
-
-    // iterate over chunks and validate each is not empty
-    for maybe_chunk in chunks {
-        println!("{:?}", maybe_chunk);
-        let chunk = maybe_chunk?;
-        assert!(!chunk.is_empty());
-    }
+    let buffer = write_bytes(&original_array)?;
+    let read_array: Vec<ArrayStruct> = read_parquet(Cursor::new(buffer))?;
+    assert_eq!(read_array, original_array);
 
     Ok(())
 }
@@ -714,24 +479,170 @@ fn test_nested_array_roundtrip() -> arrow2::error::Result<()> {
     Ok(())
 }
 
-/// Test that you can write a nested array to parquet
-///
-/// ERROR: even though this test will pass, if you try to read the resulting parquet file with pyarrow
-/// using the parquet.ipynb notebook in the root of this crate, you get an
-/// "OSError: Malformed levels. min: 0 max: 3 out of range.  Max Level: 2"
+/// Test that you can write a nested array to a parquet file that pyarrow can read
 #[test]
 fn nested_array_struct_parquet_file() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
     let original_array = [NestedArrayStruct::default(), NestedArrayStruct::default()];
 
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "array_struct",
-        <NestedArrayStruct as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
+    let file = File::create(parquet_path("test.parquet")).unwrap();
+    write_parquet(&original_array, file, zstd_options())?;
+
+    Ok(())
+}
+
+/// Repetition levels, definition levels, max repetition level, and max definition level of leaf column `column` in
+/// the first row group of parquet bytes
+fn read_levels(buffer: &[u8], column: usize) -> (Vec<u32>, Vec<u32>, i16, i16) {
+    use parquet2::encoding::hybrid_rle::HybridRleDecoder;
+    use parquet2::page::{split_buffer, Page};
+    use parquet2::read::levels::get_bit_width;
+
+    let mut reader = Cursor::new(buffer);
+    let metadata = parquet2::read::read_metadata(&mut reader).unwrap();
+    let column = &metadata.row_groups[0].columns()[column];
+    let descriptor = &column.descriptor().descriptor;
+    let decode = |levels: &[u8], max_level: i16, len: usize| -> Vec<u32> {
+        HybridRleDecoder::try_new(levels, get_bit_width(max_level), len)
+            .unwrap()
+            .map(|level| level.unwrap())
+            .collect()
+    };
+
+    let (mut rep, mut def) = (vec![], vec![]);
+    let pages =
+        parquet2::read::get_page_iterator(column, &mut reader, None, vec![], usize::MAX).unwrap();
+    let mut scratch = vec![];
+    for page in pages {
+        if let Page::Data(page) = parquet2::read::decompress(page.unwrap(), &mut scratch).unwrap() {
+            let (rep_levels, def_levels, _) = split_buffer(&page).unwrap();
+            rep.extend(decode(
+                rep_levels,
+                descriptor.max_rep_level,
+                page.num_values(),
+            ));
+            def.extend(decode(
+                def_levels,
+                descriptor.max_def_level,
+                page.num_values(),
+            ));
+        }
+    }
+
+    (rep, def, descriptor.max_rep_level, descriptor.max_def_level)
+}
+
+/// Read back the levels of nested arrays written to parquet, including empty lists, which arrow2 gets wrong
+#[test]
+fn nested_array_parquet_levels() -> arrow2::error::Result<()> {
+    use crate::parquet::read_parquet_tolerant;
+
+    let batch = |rows: Vec<Vec<Vec<u32>>>| -> Vec<NestedArrayStruct> {
+        rows.into_iter()
+            .map(|b| NestedArrayStruct {
+                b,
+                ..Default::default()
+            })
+            .collect()
+    };
+    // Levels of `b`'s values: the struct is nullable and both lists are required, so a value is at definition
+    // level 3, an empty inner list at 2, and an empty outer list at 1
+    let cases = [
+        (
+            vec![NestedArrayStruct::default(), NestedArrayStruct::default()],
+            vec![0, 2, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 2, 1, 2, 2, 2],
+            vec![3; 18],
+        ),
+        (
+            batch(vec![
+                vec![vec![1, 2], vec![3]],
+                vec![vec![], vec![3]],
+                vec![],
+                vec![vec![4]],
+            ]),
+            vec![0, 2, 1, 0, 1, 0, 0],
+            vec![3, 3, 3, 2, 3, 1, 3],
+        ),
+        // No values at all
+        (batch(vec![vec![vec![]], vec![]]), vec![0, 0], vec![2, 1]),
+    ];
+
+    for (original_array, expected_rep, expected_def) in cases {
+        for version in [Version::V1, Version::V2] {
+            let options = WriteOptions {
+                write_statistics: true,
+                compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
+                version,
+                data_pagesize_limit: None,
+            };
+            let mut buffer = vec![];
+            write_parquet(&original_array, &mut buffer, options)?;
+
+            let (rep, def, max_rep, max_def) = read_levels(&buffer, 1);
+            assert_eq!((max_rep, max_def), (2, 3));
+            assert_eq!(rep, expected_rep);
+            assert_eq!(def, expected_def);
+
+            // Every column has one level per row or value
+            let (a_rep, a_def, _, _) = read_levels(&buffer, 0);
+            assert_eq!(a_rep, vec![0; original_array.len()]);
+            assert_eq!(a_def, vec![1; original_array.len()]);
+
+            let read = read_parquet_tolerant::<NestedArrayStruct>(&buffer)?;
+            assert_eq!(read.items, original_array);
+            assert!(read.warnings.is_empty());
+        }
+    }
+
+    Ok(())
+}
 
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
+/// One encoding per parquet leaf column, however deeply the fields are nested
+#[test]
+fn test_leaf_encodings() {
+    let schema = Schema::from(vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new(
+            "root",
+            <Root as arrow2_convert::field::ArrowField>::data_type(),
+            true,
+        ),
+        Field::new(
+            "array_struct",
+            <ArrayStruct as arrow2_convert::field::ArrowField>::data_type(),
+            true,
+        ),
+    ]);
+
+    let encodings = leaf_encodings(&schema, Encoding::Plain);
+    assert_eq!(encodings.len(), 3);
+    assert_eq!(encodings[0], vec![Encoding::Plain]);
+    assert_eq!(encodings[1].len(), 25);
+    assert_eq!(encodings[2].len(), 3);
+}
+
+/// Write a primitive column next to a nested array struct column and read both back
+#[test]
+fn write_parquet_chunk_round_trip() -> arrow2::error::Result<()> {
+    use crate::parquet::write_parquet_chunk;
+
+    let original_array = [
+        ArrayStruct::default(),
+        ArrayStruct {
+            b: vec![vec![], vec![3]],
+            ..Default::default()
+        },
+    ];
+    let ids = UInt32Array::from_slice([1, 2]);
+    let schema = Schema::from(vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new(
+            "array_struct",
+            <ArrayStruct as arrow2_convert::field::ArrowField>::data_type(),
+            true,
+        ),
+    ]);
+    let array: Box<dyn Array> = original_array.try_into_arrow()?;
+    let chunk = Chunk::new(vec![ids.clone().boxed(), array]);
 
     let options = WriteOptions {
         write_statistics: true,
@@ -739,103 +650,753 @@ fn nested_array_struct_parquet_file() -> arrow2::error::Result<()> {
         version: Version::V1,
         data_pagesize_limit: None,
     };
+    let mut buffer = vec![];
+    write_parquet_chunk(&chunk, &schema, &mut buffer, options)?;
 
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 3]],
-    )?;
+    let mut reader = Cursor::new(buffer);
+    let metadata = read::read_metadata(&mut reader)?;
+    let read_schema = read::infer_schema(&metadata)?;
+    let chunks = read::FileReader::new(reader, metadata.row_groups, read_schema, None, None, None)
+        .collect::<arrow2::error::Result<Vec<_>>>()?;
+    assert_eq!(chunks.len(), 1);
+    let read_ids = chunks[0].arrays()[0]
+        .as_any()
+        .downcast_ref::<UInt32Array>()
+        .unwrap();
+    assert_eq!(read_ids, &ids);
+    let read_array: Vec<ArrayStruct> = chunks[0].arrays()[1].as_ref().try_into_collection()?;
+    assert_eq!(read_array, original_array);
 
-    // anything implementing `std::io::Write` works
-    // let mut buffer = vec![];
-    let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
-
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
-    }
-    let _file_size = writer.end(None)?;
+    // A chunk that doesn't match the schema is rejected
+    let ids_only = Chunk::new(vec![ids.boxed()]);
+    assert!(write_parquet_chunk(&ids_only, &schema, vec![], options).is_err());
 
     Ok(())
 }
 
 #[test]
 fn nested_array_struct_round_trip_parquet() -> arrow2::error::Result<()> {
-    // serialize to an arrow array
     let original_array = [NestedArrayStruct::default(), NestedArrayStruct::default()];
 
-    // declare a schema with fields
-    let schema = Schema::from(vec![Field::new(
-        "flat_struct",
-        <NestedArrayStruct as arrow2_convert::field::ArrowField>::data_type(),
-        true,
-    )]);
+    let buffer = write_bytes(&original_array)?;
+    let read_array: Vec<NestedArrayStruct> = read_parquet(Cursor::new(buffer))?;
+    assert_eq!(read_array, original_array);
 
-    let chunk: Chunk<Arc<dyn Array>> = original_array.try_into_arrow()?;
+    Ok(())
+}
 
-    let options = WriteOptions {
-        write_statistics: true,
-        compression: CompressionOptions::Zstd(Some(ZstdLevel::default())),
-        version: Version::V1,
-        data_pagesize_limit: None,
+/// Flat struct mixing required and optional fields, including a nullable custom type
+///
+/// Real measurements have many optional fields, and in a given batch some of them are never set at all
+#[derive(Clone, PartialEq, Debug, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct OptionalStruct {
+    id: u32,
+    // null in every row of the test batches
+    all_null: Option<i64>,
+    // null in some rows
+    partial: Option<String>,
+    partial_f64: Option<f64>,
+    // nullable custom type, null in some rows
+    custom: Option<CustomType>,
+    // nullable custom type, null in every row
+    all_null_custom: Option<CustomType>,
+}
+
+fn optional_batch() -> Vec<OptionalStruct> {
+    (0..4)
+        .map(|i| OptionalStruct {
+            id: i,
+            all_null: None,
+            partial: (i % 2 == 0).then(|| format!("value-{}", i)),
+            partial_f64: (i % 3 == 0).then_some(i as f64 * 0.5),
+            custom: (i % 2 == 1).then_some(CustomType(i as u64)),
+            all_null_custom: None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_optional_roundtrip() -> arrow2::error::Result<()> {
+    let original_array = optional_batch();
+
+    let array: Box<dyn Array> = original_array.try_into_arrow()?;
+    let struct_array = array
+        .as_any()
+        .downcast_ref::<arrow2::array::StructArray>()
+        .unwrap();
+    assert_eq!(struct_array.len(), 4);
+
+    // A field that is null in every row is still a column, just an entirely null one
+    let values = struct_array.values();
+    assert_eq!(values.len(), 6);
+    assert_eq!(values[1].null_count(), 4);
+    assert_eq!(values[2].null_count(), 2);
+    assert_eq!(values[5].null_count(), 4);
+
+    let foo_array: Vec<OptionalStruct> = array.try_into_collection()?;
+    assert_eq!(foo_array, original_array);
+    Ok(())
+}
+
+/// Round trip structs with entirely null, partially null, and nullable custom fields through parquet bytes
+#[test]
+fn optional_struct_round_trip_parquet() -> arrow2::error::Result<()> {
+    let original_array = optional_batch();
+
+    let buffer = write_bytes(&original_array)?;
+    let read_array: Vec<OptionalStruct> = read_parquet(Cursor::new(buffer))?;
+    assert_eq!(read_array, original_array);
+
+    // Batches where every optional field is null, or none are
+    let all_null: Vec<OptionalStruct> = original_array
+        .iter()
+        .map(|s| OptionalStruct {
+            partial: None,
+            partial_f64: None,
+            custom: None,
+            ..s.clone()
+        })
+        .collect();
+    let buffer = write_bytes(&all_null)?;
+    assert_eq!(
+        read_parquet::<OptionalStruct, _>(Cursor::new(buffer))?,
+        all_null
+    );
+
+    let none_null: Vec<OptionalStruct> = original_array
+        .iter()
+        .map(|s| OptionalStruct {
+            all_null: Some(1),
+            partial: Some("set".to_string()),
+            partial_f64: Some(1.5),
+            custom: Some(CustomType(1)),
+            all_null_custom: Some(CustomType(2)),
+            ..s.clone()
+        })
+        .collect();
+    let buffer = write_bytes(&none_null)?;
+    assert_eq!(
+        read_parquet::<OptionalStruct, _>(Cursor::new(buffer))?,
+        none_null
+    );
+
+    Ok(())
+}
+
+/// Write structs with mixed optionality to a parquet file
+///
+/// Open the resulting file (see `parquet_path`) with pyarrow using the parquet.ipynb notebook to check that
+/// the nulls read back the same as they were written
+#[test]
+fn optional_struct_parquet_file() -> arrow2::error::Result<()> {
+    let buffer = write_bytes(&optional_batch())?;
+    std::fs::write(parquet_path("test_optional.parquet"), buffer).unwrap();
+
+    Ok(())
+}
+
+/// Nullable lists of nullable values, i.e. an optional multi-return radar reading where some returns are dropped
+#[derive(Clone, PartialEq, Debug, Default, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct NullableListStruct {
+    id: u32,
+    returns: Option<Vec<Option<String>>>,
+    ranges: Option<Vec<Option<f64>>>,
+}
+
+fn nullable_list_batch() -> Vec<NullableListStruct> {
+    let returns =
+        |values: &[Option<&str>]| Some(values.iter().map(|v| v.map(str::to_owned)).collect());
+    vec![
+        NullableListStruct {
+            id: 0,
+            returns: returns(&[Some("a"), None, Some("b")]),
+            ranges: Some(vec![Some(1.5), None]),
+        },
+        NullableListStruct {
+            id: 1,
+            returns: None,
+            ranges: None,
+        },
+        NullableListStruct {
+            id: 2,
+            returns: Some(vec![]),
+            ranges: Some(vec![]),
+        },
+        NullableListStruct {
+            id: 3,
+            returns: returns(&[None]),
+            ranges: Some(vec![None, None]),
+        },
+        NullableListStruct {
+            id: 4,
+            returns: returns(&[Some("c")]),
+            ranges: None,
+        },
+    ]
+}
+
+/// Round trip `Option<Vec<Option<T>>>` fields through parquet bytes, telling apart null lists, empty lists, and
+/// lists of only nulls
+#[test]
+fn nullable_list_round_trip_parquet() -> arrow2::error::Result<()> {
+    use crate::parquet::read_parquet_tolerant;
+
+    let with_id = |returns, ranges| NullableListStruct {
+        id: 5,
+        returns,
+        ranges,
     };
+    // Levels of `returns`' values: the struct, the list, and its values are nullable, so a value is at definition
+    // level 4, a null value at 3, an empty list at 2, and a null list at 1
+    let cases = [
+        (
+            nullable_list_batch(),
+            vec![0, 1, 1, 0, 0, 0, 0],
+            vec![4, 3, 4, 1, 2, 3, 4],
+        ),
+        // No values at all
+        (
+            vec![with_id(None, None), with_id(Some(vec![]), None)],
+            vec![0, 0],
+            vec![1, 2],
+        ),
+        // Only null values
+        (
+            vec![with_id(Some(vec![None, None]), Some(vec![None]))],
+            vec![0, 1],
+            vec![3, 3],
+        ),
+    ];
+
+    for (original_array, expected_rep, expected_def) in cases {
+        for version in [Version::V1, Version::V2] {
+            let options = WriteOptions {
+                version,
+                ..zstd_options()
+            };
+            let mut buffer = vec![];
+            write_parquet(&original_array, &mut buffer, options)?;
+
+            let (rep, def, max_rep, max_def) = read_levels(&buffer, 1);
+            assert_eq!((max_rep, max_def), (1, 4));
+            assert_eq!(rep, expected_rep);
+            assert_eq!(def, expected_def);
+
+            let read_array: Vec<NullableListStruct> = read_parquet(Cursor::new(buffer.clone()))?;
+            assert_eq!(read_array, original_array);
+            let read = read_parquet_tolerant::<NullableListStruct>(&buffer)?;
+            assert_eq!(read.items, original_array);
+            assert!(read.warnings.is_empty());
+        }
+    }
 
-    // encodings has to be the length of the number of elements in the struct
-    // Maybe dynamically do this the same way that io/parquet/write/pages.rs is checking?
-    let row_groups = RowGroupIterator::try_new(
-        vec![Ok(chunk)].into_iter(),
-        &schema,
-        options,
-        vec![vec![Encoding::Plain; 3]],
-    )?;
+    Ok(())
+}
+
+/// Write nullable lists of nullable values to a parquet file
+///
+/// Open the resulting file (see `parquet_path`) with pyarrow using the parquet.ipynb notebook to check that
+/// null lists, empty lists, and null values read back as they were written
+#[test]
+fn nullable_list_parquet_file() -> arrow2::error::Result<()> {
+    let buffer = write_bytes(&nullable_list_batch())?;
+    std::fs::write(parquet_path("test_nullable_list.parquet"), buffer).unwrap();
+
+    Ok(())
+}
+
+/// Version 1 of a measurement, as written to an old archive
+#[derive(Clone, PartialEq, Debug, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct MeasurementV1 {
+    id: u32,
+    name: String,
+    count: i32,
+    label: String,
+    removed: f64,
+}
+
+/// Version 2 of the same measurement: fields reordered, one removed, one added, and two changed type
+#[derive(Clone, PartialEq, Debug, Default, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct MeasurementV2 {
+    name: String,
+    id: u32,
+    added: Option<f64>,
+    count: i64,
+    label: i64,
+}
+
+#[test]
+fn test_read_parquet_tolerant() -> arrow2::error::Result<()> {
+    use crate::parquet::{read_parquet_tolerant, SchemaWarning};
+
+    let v1: Vec<MeasurementV1> = (0..3)
+        .map(|i| MeasurementV1 {
+            id: i,
+            name: format!("radar-{}", i),
+            count: i as i32 * 10,
+            label: format!("label-{}", i),
+            removed: 1.5,
+        })
+        .collect();
+    let buffer = write_bytes(&v1)?;
+
+    // A strict read of the old file fails
+    assert!(read_parquet::<MeasurementV2, _>(Cursor::new(&buffer)).is_err());
+
+    let read = read_parquet_tolerant::<MeasurementV2>(&buffer)?;
+    let expected: Vec<MeasurementV2> = v1
+        .iter()
+        .map(|m| MeasurementV2 {
+            name: m.name.clone(),
+            id: m.id,
+            added: None,
+            count: m.count as i64,
+            label: 0,
+        })
+        .collect();
+    assert_eq!(read.items, expected);
+    assert_eq!(
+        read.warnings,
+        vec![
+            SchemaWarning::MissingField("added".to_owned()),
+            SchemaWarning::CastField {
+                name: "count".to_owned(),
+                from: DataType::Int32,
+                to: DataType::Int64,
+            },
+            SchemaWarning::IncompatibleField {
+                name: "label".to_owned(),
+                found: DataType::Utf8,
+                expected: DataType::Int64,
+            },
+            SchemaWarning::ExtraColumn("removed".to_owned()),
+        ]
+    );
+
+    // Reading with the same schema it was written with has nothing to warn about
+    let buffer = write_bytes(&expected)?;
+    let read = read_parquet_tolerant::<MeasurementV2>(&buffer)?;
+    assert_eq!(read.items, expected);
+    assert!(read.warnings.is_empty());
+
+    Ok(())
+}
+
+/// Reading written as a float, as an old archive might have
+#[derive(Clone, PartialEq, Debug, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct FloatReading {
+    reading: f64,
+}
+
+/// The same reading, later changed to an integer
+#[derive(Clone, PartialEq, Debug, Default, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct IntReading {
+    reading: i64,
+}
+
+#[test]
+fn test_read_parquet_tolerant_float_to_int() -> arrow2::error::Result<()> {
+    use crate::parquet::{read_parquet_tolerant, SchemaWarning};
+
+    // Whole floats cast to integers exactly
+    let whole: Vec<FloatReading> = [1.0, -2.0, 300.0]
+        .iter()
+        .map(|&reading| FloatReading { reading })
+        .collect();
+    let read = read_parquet_tolerant::<IntReading>(&write_bytes(&whole)?)?;
+    let readings: Vec<i64> = read.items.iter().map(|r| r.reading).collect();
+    assert_eq!(readings, [1, -2, 300]);
+    assert_eq!(
+        read.warnings,
+        vec![SchemaWarning::CastField {
+            name: "reading".to_owned(),
+            from: DataType::Float64,
+            to: DataType::Int64,
+        }]
+    );
+
+    // A fractional value would be truncated, so the column is incompatible and every row gets the default
+    let fractional: Vec<FloatReading> = [1.0, 2.5, 3.0]
+        .iter()
+        .map(|&reading| FloatReading { reading })
+        .collect();
+    let read = read_parquet_tolerant::<IntReading>(&write_bytes(&fractional)?)?;
+    assert_eq!(read.items, vec![IntReading::default(); 3]);
+    assert_eq!(
+        read.warnings,
+        vec![SchemaWarning::IncompatibleField {
+            name: "reading".to_owned(),
+            found: DataType::Float64,
+            expected: DataType::Int64,
+        }]
+    );
+
+    Ok(())
+}
+
+/// A 3-axis accelerometer sample, stored as fixed size columns
+#[derive(Clone, PartialEq, Debug, Default, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct AccelSample {
+    #[arrow_field(type = "arrow2_convert::field::FixedSizeVec<f32, 3>")]
+    xyz: Vec<f32>,
+    #[arrow_field(type = "arrow2_convert::field::FixedSizeBinary<2>")]
+    flags: Vec<u8>,
+}
+
+/// Fixed size fields at the top level, nullable, and inside a list of structs
+#[derive(Clone, PartialEq, Debug, Default, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct FixedSizeStruct {
+    #[arrow_field(type = "arrow2_convert::field::FixedSizeVec<i64, 3>")]
+    fixed_size_vec: Vec<i64>,
+    #[arrow_field(type = "arrow2_convert::field::FixedSizeBinary<4>")]
+    fixed_size_binary: Vec<u8>,
+    #[arrow_field(type = "Option<arrow2_convert::field::FixedSizeVec<f64, 2>>")]
+    nullable_vec: Option<Vec<f64>>,
+    #[arrow_field(type = "Option<arrow2_convert::field::FixedSizeBinary<3>>")]
+    nullable_binary: Option<Vec<u8>>,
+    samples: Vec<AccelSample>,
+}
+
+fn fixed_size_batch() -> Vec<FixedSizeStruct> {
+    let sample = |i: f32| AccelSample {
+        xyz: vec![i, i + 0.5, -i],
+        flags: vec![i as u8, 0xff],
+    };
+    vec![
+        FixedSizeStruct {
+            fixed_size_vec: vec![1, 2, 3],
+            fixed_size_binary: b"abcd".to_vec(),
+            nullable_vec: Some(vec![0.5, 1.5]),
+            nullable_binary: None,
+            samples: vec![sample(1.0), sample(2.0)],
+        },
+        FixedSizeStruct {
+            fixed_size_vec: vec![4, 5, 6],
+            fixed_size_binary: b"efgh".to_vec(),
+            nullable_vec: None,
+            nullable_binary: Some(b"xyz".to_vec()),
+            samples: vec![],
+        },
+        // Values after a null must not pick up the null's (zeroed) items
+        FixedSizeStruct {
+            fixed_size_vec: vec![7, 8, 9],
+            fixed_size_binary: b"ijkl".to_vec(),
+            nullable_vec: Some(vec![2.5, 3.5]),
+            nullable_binary: None,
+            samples: vec![sample(3.0)],
+        },
+    ]
+}
+
+/// Round trip fixed size lists and binary through parquet bytes, with V1 and V2 pages
+#[test]
+fn round_trip_fixed_size_parquet() -> arrow2::error::Result<()> {
+    use crate::parquet::read_parquet_tolerant;
+
+    let original_array = fixed_size_batch();
+    for version in [Version::V1, Version::V2] {
+        let options = WriteOptions {
+            version,
+            ..zstd_options()
+        };
+        let mut buffer = vec![];
+        write_parquet(&original_array, &mut buffer, options)?;
+
+        let read_array: Vec<FixedSizeStruct> = read_parquet(Cursor::new(&buffer))?;
+        assert_eq!(read_array, original_array);
+
+        let read = read_parquet_tolerant::<FixedSizeStruct>(&buffer)?;
+        assert_eq!(read.items, original_array);
+        assert!(read.warnings.is_empty());
+    }
+
+    Ok(())
+}
+
+/// Fixed size values are written as variable size ones, and only read back if they have the fixed size
+#[test]
+fn test_from_parquet_array() -> arrow2::error::Result<()> {
+    use crate::parquet::{from_parquet_array, parquet_data_type, to_parquet_array};
+
+    let fixed = FixedSizeBinaryArray::from([Some(*b"ab"), None, Some(*b"cd")]);
+    let written = to_parquet_array(&fixed)?;
+    assert_eq!(written.data_type(), &DataType::Binary);
+    assert_eq!(parquet_data_type(fixed.data_type()), DataType::Binary);
+    assert_eq!(
+        from_parquet_array(written.as_ref(), fixed.data_type())?.as_ref(),
+        &fixed as &dyn Array
+    );
+
+    let wrong_size = BinaryArray::<i32>::from_slice([b"ab".as_slice(), b"abc".as_slice()]);
+    assert!(from_parquet_array(&wrong_size, fixed.data_type()).is_err());
+
+    Ok(())
+}
+
+/// Stream 100k structs into row groups of 30k and read every row group back
+#[test]
+fn stream_writer_round_trip_parquet() -> arrow2::error::Result<()> {
+    let original_array: Vec<FlatStruct> = (0..100_000)
+        .map(|i| FlatStruct {
+            a: i,
+            b: format!("row {i}"),
+            c: -(i as i32),
+        })
+        .collect();
 
-    // anything implementing `std::io::Write` works
     let mut buffer = vec![];
-    // let mut buffer = File::create("test.parquet").unwrap();
-    let mut writer = FileWriter::try_new(&mut buffer, schema, options)?;
+    let mut writer = ParquetStreamWriter::new(&mut buffer, zstd_options(), 30_000)?;
+    for item in original_array.iter().cloned() {
+        writer.push(item)?;
+    }
+    assert_eq!(writer.rows(), 100_000);
+    writer.finish()?;
+
+    let metadata = read::read_metadata(&mut Cursor::new(&buffer))?;
+    let row_groups: Vec<usize> = metadata.row_groups.iter().map(|g| g.num_rows()).collect();
+    assert_eq!(row_groups, [30_000, 30_000, 30_000, 10_000]);
+
+    let read_array: Vec<FlatStruct> = read_parquet(Cursor::new(buffer))?;
+    assert_eq!(read_array, original_array);
+
+    // Nothing pushed still makes a readable, empty file
+    let mut buffer = vec![];
+    ParquetStreamWriter::<FlatStruct, _>::new(&mut buffer, zstd_options(), 30_000)?.finish()?;
+    let read_array: Vec<FlatStruct> = read_parquet(Cursor::new(buffer))?;
+    assert!(read_array.is_empty());
+
+    assert!(ParquetStreamWriter::<FlatStruct, _>::new(vec![], zstd_options(), 0).is_err());
 
-    // Write to buffer
-    for group in row_groups {
-        writer.write(group?)?;
+    Ok(())
+}
+
+/// Test measurement with a timestamp to filter row groups by
+#[derive(Clone, PartialEq, Debug, Default, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct TimedStruct {
+    timestamp: i64,
+    value: f64,
+    label: String,
+}
+
+/// Reader that records the position of every seek, which is where each column chunk read starts
+struct SeekRecorder<R> {
+    inner: R,
+    seeks: Vec<u64>,
+}
+
+impl<R: Read> Read for SeekRecorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
     }
-    let _file_size = writer.end(None)?;
+}
 
-    // Wrap buffer in a Cursor...this makes the buffer impl Read & Seek (needed for read::read_metadata)
-    let mut reader = std::io::Cursor::new(buffer);
+impl<R: Seek> Seek for SeekRecorder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.seeks.push(position);
+        Ok(position)
+    }
+}
 
-    // we can read its metadata:
-    let metadata = read::read_metadata(&mut reader)?;
+/// Row groups outside the time range and columns that aren't selected are never read
+#[test]
+fn filtered_read_parquet() -> arrow2::error::Result<()> {
+    let original_array: Vec<TimedStruct> = (0..10_000)
+        .map(|i| TimedStruct {
+            timestamp: i * 10,
+            value: i as f64 / 2.0,
+            label: format!("row {i}"),
+        })
+        .collect();
+    let mut buffer = vec![];
+    let mut writer = ParquetStreamWriter::new(&mut buffer, zstd_options(), 1_000)?;
+    for item in original_array.iter().cloned() {
+        writer.push(item)?;
+    }
+    writer.finish()?;
+
+    // Rows 2500 up to 4500 are in row groups 2, 3, and 4
+    let (start, end) = (25_000, 45_000);
+    let range = TimeRange {
+        column: "timestamp".to_owned(),
+        start,
+        end,
+    };
+    let mut reader = SeekRecorder {
+        inner: Cursor::new(&buffer),
+        seeks: vec![],
+    };
+    let in_range = Box::new(move |item: &TimedStruct| (start..end).contains(&item.timestamp));
+    let read_array: Vec<TimedStruct> = read_parquet_filtered(
+        &mut reader,
+        &["timestamp", "value"],
+        Some(&range),
+        Some(in_range),
+    )?;
 
-    // and infer a [`Schema`] from the `metadata`.
-    let schema = read::infer_schema(&metadata)?;
+    let expected: Vec<TimedStruct> = original_array[2500..4500]
+        .iter()
+        .map(|item| TimedStruct {
+            label: String::new(),
+            ..item.clone()
+        })
+        .collect();
+    assert_eq!(read_array, expected);
+
+    let metadata = read::read_metadata(&mut Cursor::new(&buffer))?;
+    assert_eq!(metadata.row_groups.len(), 10);
+    for (i, row_group) in metadata.row_groups.iter().enumerate() {
+        for column in row_group.columns() {
+            let (start, _) = column.byte_range();
+            let was_read = reader.seeks.contains(&start);
+            let selected = column.descriptor().path_in_schema[1] != "label";
+            assert_eq!(was_read, (2..5).contains(&i) && selected);
+        }
+    }
+
+    // Without filters every row is read, and a missing time column is an error
+    let read_array: Vec<TimedStruct> =
+        read_parquet_filtered(Cursor::new(&buffer), &[], None, None)?;
+    assert_eq!(read_array, original_array);
+    let missing = TimeRange {
+        column: "time".to_owned(),
+        ..range
+    };
+    assert!(read_parquet_filtered::<TimedStruct, _>(
+        Cursor::new(&buffer),
+        &[],
+        Some(&missing),
+        None
+    )
+    .is_err());
+    assert!(
+        read_parquet_filtered::<TimedStruct, _>(Cursor::new(&buffer), &["time"], None, None)
+            .is_err()
+    );
+
+    Ok(())
+}
 
-    println!("Schema: {:?}", &schema);
+/// Measurement with UTC timestamps, which parquet should record as UTC rather than as local times
+#[derive(Clone, PartialEq, Debug, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct UtcStruct {
+    #[arrow_field(type = "crate::arrow::UtcTimestamp")]
+    timestamp: DateTime<Utc>,
+    #[arrow_field(type = "Option<crate::arrow::UtcTimestamp>")]
+    calibrated_at: Option<DateTime<Utc>>,
+    #[arrow_field(type = "Vec<crate::arrow::UtcTimestamp>")]
+    events: Vec<DateTime<Utc>>,
+}
 
-    // we can filter the columns we need (here we select all)
-    let schema = schema.filter(|_index, _field| true);
+/// The same timestamp as a `NaiveDateTime`, which arrow2_convert writes without a timezone
+#[derive(Clone, PartialEq, Debug, ArrowField, ArrowSerialize, ArrowDeserialize)]
+struct NaiveStruct {
+    timestamp: chrono::NaiveDateTime,
+}
 
-    // we can read the statistics of all parquet's row groups (here for each field)
-    for field in &schema.fields {
-        let statistics = read::statistics::deserialize(field, &metadata.row_groups)?;
-        println!("{statistics:#?}");
+/// The UTC timezone of `UtcTimestamp` fields survives a round trip through parquet, unlike `NaiveDateTime` fields
+#[test]
+fn utc_timestamp_round_trip_parquet() -> arrow2::error::Result<()> {
+    let utc = DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".to_owned()));
+    let original_array: Vec<UtcStruct> = (0..4)
+        .map(|i| {
+            let timestamp = Utc.timestamp_nanos(1_670_000_000_123_456_789 + i);
+            UtcStruct {
+                timestamp,
+                calibrated_at: (i % 2 == 0).then_some(timestamp - chrono::Duration::days(1)),
+                events: vec![timestamp; i as usize],
+            }
+        })
+        .collect();
+
+    let buffer = write_bytes(&original_array)?;
+    let read_array: Vec<UtcStruct> = read_parquet(Cursor::new(&buffer))?;
+    assert_eq!(read_array, original_array);
+
+    // The timezone is in the schema inferred from the file, not just in the type it's read back as
+    let metadata = read::read_metadata(&mut Cursor::new(&buffer))?;
+    let schema = read::infer_schema(&metadata)?;
+    let fields = match &schema.fields[0].data_type {
+        DataType::Struct(fields) => fields.clone(),
+        data_type => panic!("expected a struct column, not {:?}", data_type),
+    };
+    assert_eq!(fields[0].data_type, utc);
+    assert_eq!(fields[1].data_type, utc);
+    match &fields[2].data_type {
+        DataType::List(item) => assert_eq!(item.data_type, utc),
+        data_type => panic!("expected a list column, not {:?}", data_type),
     }
 
-    // Get all the row groups
-    let row_groups = metadata.row_groups;
+    // Parquet's own logical type marks the column as adjusted to UTC, for readers that ignore the arrow schema
+    let adjusted_to_utc = |buffer: &[u8]| {
+        let metadata = read::read_metadata(&mut Cursor::new(buffer)).unwrap();
+        match metadata.schema().columns()[0]
+            .descriptor
+            .primitive_type
+            .logical_type
+        {
+            Some(PrimitiveLogicalType::Timestamp {
+                is_adjusted_to_utc, ..
+            }) => is_adjusted_to_utc,
+            logical_type => panic!("expected a timestamp column, not {:?}", logical_type),
+        }
+    };
+    assert!(adjusted_to_utc(&buffer));
+    let naive = NaiveStruct {
+        timestamp: original_array[0].timestamp.naive_utc(),
+    };
+    assert!(!adjusted_to_utc(&write_bytes(&[naive])?));
 
-    // We can then read the row groups into chunks
-    let chunks = read::FileReader::new(reader, row_groups, schema, None, None, None);
+    Ok(())
+}
 
-    // iterate over chunks and validate each is not empty
-    for maybe_chunk in chunks {
-        println!("{:?}", maybe_chunk);
-        let chunk = maybe_chunk?;
-        assert!(!chunk.is_empty());
+/// Streams are batched into chunks with a column per field, and the partial batch left at the end is flushed
+#[tokio::test]
+async fn arrow_chunks_flush_partial_batch() -> arrow2::error::Result<()> {
+    let original_array: Vec<UtcStruct> = (0..5)
+        .map(|i| UtcStruct {
+            timestamp: Utc.timestamp_nanos(1_670_000_000_000_000_000 + i),
+            calibrated_at: None,
+            events: vec![],
+        })
+        .collect();
+
+    let schema = arrow_schema::<UtcStruct>();
+    assert_eq!(
+        schema
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["timestamp", "calibrated_at", "events"]
+    );
+
+    let chunks: Vec<_> = arrow_chunks(futures_util::stream::iter(original_array.clone()), 2)
+        .collect()
+        .await;
+    let chunks = chunks
+        .into_iter()
+        .collect::<arrow2::error::Result<Vec<_>>>()?;
+    assert_eq!(
+        chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+        vec![2, 2, 1]
+    );
+
+    let mut read_array: Vec<UtcStruct> = vec![];
+    for chunk in chunks {
+        assert_eq!(chunk.arrays().len(), schema.fields.len());
+        let columns = chunk
+            .arrays()
+            .iter()
+            .map(|column| column.to_boxed())
+            .collect();
+        let array = StructArray::new(DataType::Struct(schema.fields.clone()), columns, None);
+        read_array.extend(arrow_array_deserialize_iterator::<UtcStruct>(&array)?);
     }
+    assert_eq!(read_array, original_array);
+
+    let empty = arrow_chunks(futures_util::stream::iter(Vec::<UtcStruct>::new()), 2);
+    assert_eq!(empty.collect::<Vec<_>>().await.len(), 0);
 
     Ok(())
 }
@@ -2,14 +2,139 @@ use std::fs::File;
 
 use async_stream::stream;
 
-use chrono::Utc;
+use chrono::{DateTime, TimeZone, Utc};
+use flatbuffers::FlatBufferBuilder;
 use futures_core::stream::Stream;
 use futures_util::pin_mut;
 use futures_util::stream::StreamExt;
 
+use crate::batch::{BatchError, MeasurementBatch, MAX_BATCH_BYTES};
 use crate::error::SensorError;
-use crate::measurement;
+use crate::measurement::{
+    self, FlatBufferCodec, FlatBufferMeasurement, Measurement, MeasurementCodec, MeasurementError,
+};
+use crate::mock::{collect_n, MeasurementProducer, MockProducer, MockSensor};
+use crate::reflection::{
+    schema_from_bfbs, validate_against_schema, ReflectionError, SchemaError, LIST_ITEM,
+};
 use crate::reflection_generated::reflection;
+use crate::sensor::{
+    run_resumable, serve_health_on, Acks, DeliveryGuarantee, ProducerSettings, RateLimiter, Sensor,
+    SensorHealth, StateFile,
+};
+use crate::stream_ext::{
+    rechunk_by_event_time, take_until_timestamp, EventTimeRechunker, LateData, Rechunked,
+};
+
+/// Minimal Measurement for testing code that is generic over Measurements
+///
+/// Serialized as a reflection `KeyValue` table (key = source_id, value = RFC 3339 timestamp) so tests don't need a
+/// sensor-specific flatbuffer schema
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct TestMeasurement {
+    pub source_id: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TestMeasurement {
+    pub fn new(source_id: &str, timestamp: DateTime<Utc>) -> Self {
+        TestMeasurement {
+            source_id: source_id.to_owned(),
+            timestamp,
+        }
+    }
+}
+
+/// Error for TestMeasurement
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum TestMeasurementError {
+    #[error("Kafka payload was empty")]
+    EmptyPayloadError,
+    #[error("Expected schema version {expected}, got {found}")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("Timestamp {0} is before the Unix epoch")]
+    BeforeEpochError(DateTime<Utc>),
+    #[error("{0}")]
+    ClockSkew(measurement::ClockSkew),
+    #[error("{0}")]
+    UnknownVariant(#[from] measurement::UnknownVariant),
+    #[error("Invalid flatbuffer {0}")]
+    FlatbufferError(#[from] flatbuffers::InvalidFlatbuffer),
+    #[error("Invalid timestamp {0}")]
+    TimestampError(#[from] chrono::ParseError),
+    #[error("Invalid nanosecond timestamp {0}")]
+    NanosError(#[from] std::num::ParseIntError),
+    #[cfg(feature = "json")]
+    #[error("Invalid JSON {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+impl MeasurementError for TestMeasurementError {
+    fn empty_payload_error() -> Self {
+        TestMeasurementError::EmptyPayloadError
+    }
+
+    fn version_mismatch_error(expected: u32, found: u32) -> Self {
+        TestMeasurementError::VersionMismatch { expected, found }
+    }
+
+    fn clock_skew_error(skew: measurement::ClockSkew) -> Self {
+        TestMeasurementError::ClockSkew(skew)
+    }
+}
+
+impl FlatBufferMeasurement for TestMeasurement {
+    type Error = TestMeasurementError;
+
+    fn serialize_into(self, fbb: &mut FlatBufferBuilder) {
+        let key = fbb.create_string(&self.source_id);
+        let value = fbb.create_string(&self.timestamp.to_rfc3339());
+        let offset = reflection::KeyValue::create(
+            fbb,
+            &reflection::KeyValueArgs {
+                key: Some(key),
+                value: Some(value),
+            },
+        );
+        fbb.finish_minimal(offset);
+    }
+
+    fn from_flatbuffer(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let kv = flatbuffers::root::<reflection::KeyValue>(bytes)?;
+        let timestamp = DateTime::parse_from_rfc3339(kv.value().unwrap_or_default())?;
+
+        Ok(TestMeasurement::new(
+            kv.key(),
+            timestamp.with_timezone(&Utc),
+        ))
+    }
+}
+
+impl<'a> Measurement<'a> for TestMeasurement {
+    type Error = TestMeasurementError;
+    type Codec = FlatBufferCodec;
+
+    const TOPIC_NAME: &'static str = "raw.test.test-measurement";
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) -> bool {
+        self.timestamp = timestamp;
+        true
+    }
+
+    fn source_id(&self) -> &str {
+        &self.source_id
+    }
+
+    /// A TestMeasurement's only state is its source_id
+    fn content_eq(&self, other: &Self) -> bool {
+        self.source_id == other.source_id
+    }
+}
 
 #[test]
 fn test_timestamp_nanos() {
@@ -17,11 +142,1393 @@ fn test_timestamp_nanos() {
     let now_ns = measurement::nanos_to_date_time(now.timestamp_nanos()).unwrap();
     println!("{} {}", now, now_ns);
 
-    assert_eq!(now, now_ns)
+    assert_eq!(now, now_ns)
+}
+
+#[test]
+fn test_measurement_round_trip() {
+    let m = TestMeasurement::new("test-source", Utc::now());
+    let bytes = m.clone().to_bytes();
+
+    assert_eq!(TestMeasurement::from_bytes(&bytes).unwrap(), m);
+    assert!(TestMeasurement::from_bytes(&[0, 1, 2]).is_err());
+}
+
+/// Measurement encoded as "source_id timestamp" text instead of a flatbuffer, with `TextCodec`
+#[derive(Debug, Clone, PartialEq)]
+struct TextMeasurement(TestMeasurement);
+
+/// `MeasurementCodec` for measurements that aren't FlatBuffers
+struct TextCodec;
+
+impl MeasurementCodec<TextMeasurement> for TextCodec {
+    type Error = TestMeasurementError;
+
+    fn encode(m: TextMeasurement) -> Vec<u8> {
+        format!("{} {}", m.0.source_id, m.0.timestamp.to_rfc3339()).into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<TextMeasurement, Self::Error> {
+        let text = String::from_utf8_lossy(bytes).into_owned();
+        let (source_id, timestamp) = text.rsplit_once(' ').unwrap_or(("", text.as_str()));
+        let timestamp = DateTime::parse_from_rfc3339(timestamp)?;
+
+        Ok(TextMeasurement(TestMeasurement::new(
+            source_id,
+            timestamp.with_timezone(&Utc),
+        )))
+    }
+}
+
+impl<'a> Measurement<'a> for TextMeasurement {
+    type Error = TestMeasurementError;
+    type Codec = TextCodec;
+
+    const TOPIC_NAME: &'static str = "raw.test.text-measurement";
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.0.timestamp
+    }
+
+    fn source_id(&self) -> &str {
+        &self.0.source_id
+    }
+}
+
+#[test]
+fn test_measurement_codec() {
+    let now = Utc::now();
+    let m = TextMeasurement(TestMeasurement::new("test-source", now));
+    let bytes = m.clone().to_bytes();
+
+    assert_eq!(
+        bytes,
+        format!("test-source {}", now.to_rfc3339()).into_bytes()
+    );
+    assert_eq!(TextMeasurement::from_bytes(&bytes).unwrap(), m);
+    assert_eq!(measurement::to_bytes_pooled(m.clone()), bytes);
+    assert_eq!(
+        measurement::from_bytes_versioned::<TextMeasurement>(&bytes, None).unwrap(),
+        m
+    );
+    assert!(matches!(
+        TextMeasurement::from_bytes(&[0, 1, 2]),
+        Err(TestMeasurementError::TimestampError(_))
+    ));
+
+    // FlatBuffers measurements encode with FlatBufferCodec
+    let m = TestMeasurement::new("test-source", now);
+    assert_eq!(
+        FlatBufferCodec::encode(m.clone()),
+        measurement::to_bytes_pooled(m.clone())
+    );
+    assert_eq!(
+        <FlatBufferCodec as MeasurementCodec<TestMeasurement>>::decode(&m.clone().to_bytes())
+            .unwrap(),
+        m
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_measurement_json_round_trip() {
+    let m = TestMeasurement::new("test-source", Utc::now());
+    let json = m.to_json().unwrap();
+
+    assert!(json.contains("\"source_id\":\"test-source\""));
+    assert_eq!(TestMeasurement::from_json(&json).unwrap(), m);
+    assert!(matches!(
+        TestMeasurement::from_json("{\"source_id\":1}"),
+        Err(TestMeasurementError::JsonError(_))
+    ));
+}
+
+#[cfg(feature = "schema-registry")]
+#[test]
+fn test_registry_wire_format() {
+    use crate::registry;
+
+    assert_eq!(
+        registry::subject::<TestMeasurement>(),
+        "raw.test.test-measurement-value"
+    );
+
+    let m = TestMeasurement::new("test-source", Utc::now());
+    let record = registry::to_message_registered(m.clone(), 258);
+    let record = redpanda::producer::FutureRecord::from(&record);
+    let bytes = record.payload.unwrap();
+    let (schema_id, payload) = registry::decode(bytes).unwrap();
+    assert_eq!(schema_id, 258);
+    assert_eq!(&bytes[..registry::HEADER_LEN], &[0, 0, 0, 1, 2]);
+    assert_eq!(record.key, Some(&b"test-source".to_vec()));
+    assert_eq!(TestMeasurement::from_bytes(payload).unwrap(), m);
+
+    assert!(registry::decode(&[0, 0, 1]).is_err());
+    assert!(registry::decode(&[1, 0, 0, 0, 1]).is_err());
+}
+
+/// Requires a schema registry that accepts FlatBuffers schemas, e.g. Apicurio's
+/// Confluent-compatible API proxied to `localhost:8081`
+#[cfg(feature = "schema-registry")]
+#[tokio::test]
+#[ignore = "requires a FlatBuffers-capable schema registry"]
+async fn test_registry_round_trip() {
+    use crate::registry::{
+        self, RegistryError, RegistryMeasurement, SchemaRegistry, SchemaRegistryClient, SchemaType,
+    };
+
+    let schema_registry = SchemaRegistry::new(SchemaRegistryClient::new("http://localhost:8081"))
+        .with_schema::<TestMeasurement>(
+            "namespace reflection;\n\ntable KeyValue {\n  key:string (required, key);\n  value:string;\n}\n\nroot_type KeyValue;\n",
+            SchemaType::Flatbuffers,
+        );
+
+    let m = TestMeasurement::new("test-source", Utc::now());
+    let record = m
+        .clone()
+        .to_message_with_registry(&schema_registry)
+        .await
+        .unwrap();
+    let record = redpanda::producer::FutureRecord::from(&record);
+    let bytes = record.payload.unwrap();
+    let (schema_id, _) = registry::decode(bytes).unwrap();
+    assert_eq!(
+        schema_registry
+            .schema_id::<TestMeasurement>()
+            .await
+            .unwrap(),
+        schema_id
+    );
+    assert_eq!(
+        TestMeasurement::from_bytes_with_registry(bytes, &schema_registry)
+            .await
+            .unwrap(),
+        m
+    );
+
+    // Payloads must carry a registered schema ID
+    let unknown = registry::encode(u32::MAX, &m.to_bytes());
+    assert!(matches!(
+        TestMeasurement::from_bytes_with_registry(&unknown, &schema_registry).await,
+        Err(RegistryError::RegistryError { .. })
+    ));
+
+    // and registered under the measurement's subject, not just any subject
+    let other_id = schema_registry
+        .client()
+        .register(
+            "opensensor-test-other-value",
+            "namespace other;\n\ntable Other {\n  id:int;\n}\n\nroot_type Other;\n",
+            SchemaType::Flatbuffers,
+        )
+        .await
+        .unwrap();
+    let other = registry::encode(other_id, &unknown[registry::HEADER_LEN..]);
+    assert!(matches!(
+        TestMeasurement::from_bytes_with_registry(&other, &schema_registry).await,
+        Err(RegistryError::WireFormatError(_))
+    ));
+}
+
+/// Requires the Redpanda schema registry from the OpenSensor docker-compose
+#[cfg(feature = "schema-registry")]
+#[tokio::test]
+async fn test_registry_register_new_version() {
+    use crate::registry::{SchemaRegistryClient, SchemaType};
+
+    let client = SchemaRegistryClient::new("http://localhost:8081");
+    let subject = format!("opensensor-test-{}-value", Utc::now().timestamp_nanos());
+    let v1 = r#"{"type":"record","name":"Reading","fields":[{"name":"key","type":"string"}]}"#;
+    let v2 = r#"{"type":"record","name":"Reading","fields":[{"name":"key","type":"string"},{"name":"value","type":["null","string"],"default":null}]}"#;
+
+    let v1_id = client
+        .register(&subject, v1, SchemaType::Avro)
+        .await
+        .unwrap();
+    let v2_id = client
+        .register(&subject, v2, SchemaType::Avro)
+        .await
+        .unwrap();
+    assert_ne!(v1_id, v2_id);
+    assert_eq!(
+        client
+            .register(&subject, v1, SchemaType::Avro)
+            .await
+            .unwrap(),
+        v1_id
+    );
+    assert_eq!(client.schema(v2_id).await.unwrap(), v2);
+}
+
+/// Flat sensor reading for testing Avro serialization
+#[cfg(feature = "avro")]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct AvroReading {
+    source_id: String,
+    timestamp_ns: i64,
+    range_m: f64,
+    valid: bool,
+}
+
+#[cfg(feature = "avro")]
+impl crate::avro::AvroSerializable for AvroReading {
+    type Error = apache_avro::Error;
+
+    fn avro_serialize(self) -> Vec<u8> {
+        crate::avro::to_datum(&self, &Self::avro_schema()).expect("AvroReading matches its schema")
+    }
+
+    fn avro_deserialize(bytes: &[u8]) -> Result<Self, Self::Error> {
+        crate::avro::from_datum(bytes, &Self::avro_schema())
+    }
+
+    fn avro_schema() -> apache_avro::Schema {
+        apache_avro::Schema::parse_str(
+            r#"{
+                "type": "record",
+                "name": "AvroReading",
+                "fields": [
+                    {"name": "source_id", "type": "string"},
+                    {"name": "timestamp_ns", "type": "long"},
+                    {"name": "range_m", "type": "double"},
+                    {"name": "valid", "type": "boolean"}
+                ]
+            }"#,
+        )
+        .expect("Invalid AvroReading schema")
+    }
+}
+
+#[cfg(feature = "avro")]
+#[test]
+fn test_avro_round_trip() {
+    use crate::avro::AvroSerializable;
+
+    let reading = AvroReading {
+        source_id: "radar-1".to_owned(),
+        timestamp_ns: 1_667_000_000_123_456_789,
+        range_m: 1234.5,
+        valid: true,
+    };
+    let bytes = reading.clone().avro_serialize();
+
+    assert_eq!(AvroReading::avro_deserialize(&bytes).unwrap(), reading);
+    assert!(AvroReading::avro_deserialize(&bytes[..3]).is_err());
+}
+
+/// Second Measurement type for testing multi-message sensors
+///
+/// Serialized as a reflection `KeyValue` table like TestMeasurement, but with the timestamp as nanoseconds, so the
+/// two can't deserialize each other's bytes
+///
+/// Schema version 2. Version 1 stored the timestamp as RFC 3339 like TestMeasurement, and is migrated from.
+/// Events from before the Unix epoch are invalid.
+#[derive(Debug, Clone, PartialEq)]
+struct TestEvent {
+    source_id: String,
+    timestamp: DateTime<Utc>,
+}
+
+impl FlatBufferMeasurement for TestEvent {
+    type Error = TestMeasurementError;
+
+    fn serialize_into(self, fbb: &mut FlatBufferBuilder) {
+        let key = fbb.create_string(&self.source_id);
+        let value = fbb.create_string(&self.timestamp.timestamp_nanos().to_string());
+        let offset = reflection::KeyValue::create(
+            fbb,
+            &reflection::KeyValueArgs {
+                key: Some(key),
+                value: Some(value),
+            },
+        );
+        fbb.finish_minimal(offset);
+    }
+
+    fn from_flatbuffer(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let kv = flatbuffers::root::<reflection::KeyValue>(bytes)?;
+        let nanos: i64 = kv.value().unwrap_or_default().parse()?;
+
+        Ok(TestEvent {
+            source_id: kv.key().to_owned(),
+            timestamp: measurement::nanos_to_date_time(nanos).unwrap(),
+        })
+    }
+}
+
+impl<'a> Measurement<'a> for TestEvent {
+    type Error = TestMeasurementError;
+    type Codec = FlatBufferCodec;
+
+    const TOPIC_NAME: &'static str = "raw.test.test-event";
+
+    const SCHEMA_VERSION: u32 = 2;
+
+    fn migrate(bytes: &[u8], from_version: u32) -> Result<Self, Self::Error> {
+        match from_version {
+            1 => {
+                let m = TestMeasurement::from_bytes(bytes)?;
+                Ok(TestEvent {
+                    source_id: m.source_id,
+                    timestamp: m.timestamp,
+                })
+            }
+            _ => Err(TestMeasurementError::VersionMismatch {
+                expected: Self::SCHEMA_VERSION,
+                found: from_version,
+            }),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        if self.timestamp_nanos() < 0 {
+            return Err(TestMeasurementError::BeforeEpochError(self.timestamp));
+        }
+        Ok(())
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn source_id(&self) -> &str {
+        &self.source_id
+    }
+}
+
+/// Aggregate Measurement for a sensor that produces both test message types
+///
+/// `Cell` has the same layout as `Measurement`, so only the variant tag tells them apart
+#[derive(Debug, Clone, PartialEq)]
+enum TestSensorMeasurement {
+    Measurement(TestMeasurement),
+    Event(TestEvent),
+    Cell(CellPartitionedMeasurement),
+}
+
+crate::impl_measurement_enum!(TestSensorMeasurement, topic = "raw.test", error = TestMeasurementError, {
+    Measurement(TestMeasurement) = 1,
+    Event(TestEvent) = 2,
+    Cell(CellPartitionedMeasurement) = 3,
+});
+
+#[test]
+fn test_measurement_enum() {
+    use crate::measurement::{from_bytes_versioned, UnknownVariant};
+
+    let now = Utc::now();
+    let measurement = TestSensorMeasurement::Measurement(TestMeasurement::new("radar-1", now));
+    let event = TestSensorMeasurement::Event(TestEvent {
+        source_id: "radar-2".to_owned(),
+        timestamp: now,
+    });
+
+    for (m, tag) in [(measurement, 1), (event.clone(), 2)] {
+        let bytes = m.clone().to_bytes();
+        assert_eq!(bytes[0], tag);
+        assert_eq!(TestSensorMeasurement::from_bytes(&bytes).unwrap(), m);
+        assert_eq!(measurement::to_bytes_pooled(m.clone()), bytes);
+        assert_eq!(m.timestamp(), now);
+        assert_eq!(m.message_key(), Some(m.source_id().as_bytes().to_vec()));
+        assert_eq!(m.partition_key(), m.source_id());
+    }
+
+    // Variants with the same layout decode as the variant they were encoded from
+    let inner = TestMeasurement::new("42-a", now);
+    let cell = TestSensorMeasurement::Cell(CellPartitionedMeasurement(inner.clone()));
+    let bytes = cell.clone().to_bytes();
+    assert_eq!(bytes[1..], inner.to_bytes());
+    assert_eq!(TestSensorMeasurement::from_bytes(&bytes).unwrap(), cell);
+
+    assert!(matches!(
+        TestSensorMeasurement::from_bytes(&[9, 1, 2]),
+        Err(TestMeasurementError::UnknownVariant(UnknownVariant {
+            tag: 9,
+            ..
+        }))
+    ));
+    assert!(matches!(
+        TestSensorMeasurement::from_bytes(&[]),
+        Err(TestMeasurementError::EmptyPayloadError)
+    ));
+    assert!(TestSensorMeasurement::from_bytes(&[1, 0, 1, 2]).is_err());
+
+    // Each variant is migrated from its own version
+    assert_eq!(
+        from_bytes_versioned::<TestSensorMeasurement>(&event.clone().to_bytes(), Some(2)).unwrap(),
+        event
+    );
+    let mut v1 = vec![2];
+    v1.extend(TestMeasurement::new("radar-2", now).to_bytes());
+    assert_eq!(
+        from_bytes_versioned::<TestSensorMeasurement>(&v1, Some(1)).unwrap(),
+        event
+    );
+    assert!(matches!(
+        from_bytes_versioned::<TestSensorMeasurement>(&v1, Some(3)),
+        Err(TestMeasurementError::VersionMismatch {
+            expected: 2,
+            found: 3
+        })
+    ));
+}
+
+#[test]
+fn test_measurement_enum_message() {
+    use crate::measurement::SCHEMA_VERSION_HEADER;
+    use redpanda::message::Headers;
+    use redpanda::producer::FutureRecord;
+
+    let event = TestSensorMeasurement::Event(TestEvent {
+        source_id: "radar-2".to_owned(),
+        timestamp: Utc::now(),
+    });
+
+    // Records go to the variant's topic with the variant's schema version
+    for record in [
+        event.clone().to_message(),
+        measurement::to_message_pooled(event.clone()),
+    ] {
+        let record = FutureRecord::from(&record);
+        let headers = record.headers.unwrap();
+        let header = headers.get(0);
+        assert_eq!(header.key, SCHEMA_VERSION_HEADER);
+        assert_eq!(header.value, Some("2".as_bytes()));
+        assert_eq!(
+            TestSensorMeasurement::from_bytes(record.payload.unwrap()).unwrap(),
+            event
+        );
+    }
+    let record = event.to_message();
+    assert_eq!(FutureRecord::from(&record).topic, TestEvent::TOPIC_NAME);
+}
+
+#[test]
+fn test_schema_version() {
+    use crate::measurement::{from_bytes_versioned, SCHEMA_VERSION_HEADER};
+    use redpanda::message::Headers;
+    use redpanda::producer::FutureRecord;
+
+    let event = TestEvent {
+        source_id: "radar-1".to_owned(),
+        timestamp: Utc::now(),
+    };
+
+    // The schema version is recorded in a header
+    for record in [
+        event.clone().to_message(),
+        measurement::to_message_pooled(event.clone()),
+    ] {
+        let record = FutureRecord::from(&record);
+        let headers = record.headers.unwrap();
+        let header = headers.get(0);
+        assert_eq!(header.key, SCHEMA_VERSION_HEADER);
+        assert_eq!(header.value, Some("2".as_bytes()));
+    }
+
+    // The current version, or no version, is read with from_bytes
+    let bytes = event.clone().to_bytes();
+    assert_eq!(
+        from_bytes_versioned::<TestEvent>(&bytes, Some(2)).unwrap(),
+        event
+    );
+    assert_eq!(
+        from_bytes_versioned::<TestEvent>(&bytes, None).unwrap(),
+        event
+    );
+
+    // Other versions are migrated
+    let v1 = TestMeasurement::new(&event.source_id, event.timestamp).to_bytes();
+    assert_eq!(
+        from_bytes_versioned::<TestEvent>(&v1, Some(1)).unwrap(),
+        event
+    );
+    assert!(matches!(
+        from_bytes_versioned::<TestEvent>(&bytes, Some(3)),
+        Err(TestMeasurementError::VersionMismatch {
+            expected: 2,
+            found: 3
+        })
+    ));
+
+    // Measurements that don't migrate return a version mismatch
+    assert!(matches!(
+        from_bytes_versioned::<TestMeasurement>(&v1, Some(2)),
+        Err(TestMeasurementError::VersionMismatch {
+            expected: 1,
+            found: 2
+        })
+    ));
+}
+
+#[test]
+fn test_validate() {
+    use crate::measurement::from_bytes_versioned;
+
+    let before_epoch = Utc.timestamp_opt(-1, 0).unwrap();
+    let event = TestEvent {
+        source_id: "radar-1".to_owned(),
+        timestamp: before_epoch,
+    };
+    let bytes = event.clone().to_bytes();
+
+    // from_bytes validates, and so does the versioned (from_message) path through it
+    assert!(matches!(
+        TestEvent::from_bytes(&bytes),
+        Err(TestMeasurementError::BeforeEpochError(t)) if t == before_epoch
+    ));
+    assert!(matches!(
+        from_bytes_versioned::<TestEvent>(&bytes, None),
+        Err(TestMeasurementError::BeforeEpochError(t)) if t == before_epoch
+    ));
+    assert!(matches!(
+        TestSensorMeasurement::Event(event.clone()).validate(),
+        Err(TestMeasurementError::BeforeEpochError(_))
+    ));
+
+    // Archive reads go through from_bytes too
+    let mut batch = MeasurementBatch::new();
+    batch.push(event).unwrap();
+    let fbb: FlatBufferBuilder = batch.into();
+    assert!(matches!(
+        crate::archiver::chunk::deserialize_chunk::<TestEvent>(fbb.finished_data()),
+        Err(crate::archiver::error::ArchiveError::DeserializeError(_))
+    ));
+
+    // Migrated measurements are validated too
+    let v1 = TestMeasurement::new("radar-1", before_epoch).to_bytes();
+    assert!(matches!(
+        from_bytes_versioned::<TestEvent>(&v1, Some(1)),
+        Err(TestMeasurementError::BeforeEpochError(_))
+    ));
+
+    // Measurements without a validate accept everything
+    let m = TestMeasurement::new("radar-1", before_epoch);
+    assert!(m.validate().is_ok());
+    assert_eq!(
+        from_bytes_versioned::<TestMeasurement>(&m.clone().to_bytes(), None).unwrap(),
+        m
+    );
+}
+
+#[test]
+fn test_to_bytes_pooled() {
+    // Largest first, so a builder that isn't reset properly would leave stale bytes behind
+    for source_id in ["a-much-longer-source-id", "radar-1", ""] {
+        let m = TestMeasurement::new(source_id, Utc::now());
+        let bytes = measurement::to_bytes_pooled(m.clone());
+
+        assert_eq!(bytes, m.clone().to_bytes());
+        assert_eq!(TestMeasurement::from_bytes(&bytes).unwrap(), m);
+    }
+}
+
+#[test]
+fn test_measurement_batch() {
+    let measurements: Vec<TestMeasurement> = (0..100)
+        .map(|i| {
+            TestMeasurement::new(
+                &format!("radar-{}", i % 3),
+                Utc.timestamp_opt(i, 0).unwrap(),
+            )
+        })
+        .collect();
+
+    let mut batch = MeasurementBatch::new();
+    assert!(batch.is_empty());
+    for m in measurements.iter().cloned() {
+        batch.push(m).unwrap();
+    }
+    assert_eq!(batch.len(), 100);
+    let serialized_size = batch.serialized_size();
+    let fbb: FlatBufferBuilder = batch.into();
+    assert!(fbb.finished_data().len() <= serialized_size);
+
+    let read: Vec<TestMeasurement> = MeasurementBatch::from_bytes(fbb.finished_data())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(read, measurements);
+
+    // Batches are archive chunks
+    let chunk: Vec<TestMeasurement> =
+        crate::archiver::chunk::deserialize_chunk(fbb.finished_data()).unwrap();
+    assert_eq!(chunk, measurements);
+
+    let empty: FlatBufferBuilder = MeasurementBatch::<TestMeasurement>::new().into();
+    assert_eq!(
+        MeasurementBatch::<TestMeasurement>::from_bytes(empty.finished_data())
+            .unwrap()
+            .count(),
+        0
+    );
+    assert!(matches!(
+        MeasurementBatch::<TestMeasurement>::from_bytes(b"not a batch"),
+        Err(BatchError::InvalidBatch(_))
+    ));
+}
+
+#[test]
+fn test_measurement_batch_limit() {
+    let mut batch = MeasurementBatch::<TestMeasurement>::new();
+    assert!(batch.fits(1024));
+    assert!(!batch.fits(MAX_BATCH_BYTES));
+    assert!(!batch.fits(usize::MAX));
+
+    // A record's own bytes aren't all it adds, so it can't take up all the space left
+    let remaining = MAX_BATCH_BYTES - batch.serialized_size();
+    assert!(batch.fits(remaining - 64));
+    assert!(!batch.fits(remaining));
+
+    batch
+        .push(TestMeasurement::new("radar-1", Utc::now()))
+        .unwrap();
+    assert!(MAX_BATCH_BYTES - batch.serialized_size() < remaining);
+}
+
+#[test]
+fn test_message_key_default() {
+    let m = TestMeasurement::new("radar-1", Utc::now());
+
+    assert_eq!(m.message_key(), Some(b"radar-1".to_vec()));
+
+    // The produced record is keyed by it, so a source's measurements stay on one partition
+    let record = m.clone().to_message();
+    let record = redpanda::producer::FutureRecord::from(&record);
+    assert_eq!(record.key, Some(&b"radar-1".to_vec()));
+    assert_eq!(record.topic, TestMeasurement::TOPIC_NAME);
+    assert_eq!(
+        TestMeasurement::from_bytes(record.payload.unwrap()).unwrap(),
+        m
+    );
+}
+
+/// TestMeasurement partitioned by the grid cell its source is in, rather than by source
+#[derive(Debug, Clone, PartialEq)]
+struct CellPartitionedMeasurement(TestMeasurement);
+
+impl FlatBufferMeasurement for CellPartitionedMeasurement {
+    type Error = TestMeasurementError;
+
+    fn serialize_into(self, fbb: &mut FlatBufferBuilder) {
+        self.0.serialize_into(fbb);
+    }
+
+    fn from_flatbuffer(bytes: &[u8]) -> Result<Self, Self::Error> {
+        TestMeasurement::from_flatbuffer(bytes).map(CellPartitionedMeasurement)
+    }
+}
+
+impl<'a> Measurement<'a> for CellPartitionedMeasurement {
+    type Error = TestMeasurementError;
+    type Codec = FlatBufferCodec;
+
+    const TOPIC_NAME: &'static str = "raw.test.cell-partitioned";
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.0.timestamp
+    }
+
+    fn source_id(&self) -> &str {
+        &self.0.source_id
+    }
+
+    fn partition_key(&self) -> std::borrow::Cow<'_, str> {
+        // Sources are named "{cell}-{sensor}"
+        let cell = self.0.source_id.split('-').next().unwrap_or_default();
+        std::borrow::Cow::Owned(format!("cell-{}", cell))
+    }
+}
+
+#[test]
+fn test_partition_key() {
+    use redpanda::producer::FutureRecord;
+
+    let m = TestMeasurement::new("radar-1", Utc::now());
+    assert_eq!(m.partition_key(), "radar-1");
+
+    // Both sensors in cell 42 share a key and so a partition, while keeping their own source_id
+    let a = CellPartitionedMeasurement(TestMeasurement::new("42-a", Utc::now()));
+    let b = CellPartitionedMeasurement(TestMeasurement::new("42-b", Utc::now()));
+    assert_eq!(a.partition_key(), "cell-42");
+    assert_eq!(a.partition_key(), b.partition_key());
+    assert_ne!(a.source_id(), b.source_id());
+    assert_eq!(a.message_key(), Some(b"cell-42".to_vec()));
+
+    let record = a.clone().to_message();
+    let record = FutureRecord::from(&record);
+    assert_eq!(record.key, Some(&b"cell-42".to_vec()));
+    assert_eq!(
+        CellPartitionedMeasurement::from_bytes(record.payload.unwrap()).unwrap(),
+        a
+    );
+}
+
+#[test]
+fn test_partition_timestamp_default() {
+    let m = TestMeasurement::new("test-source", Utc::now());
+
+    assert_eq!(m.partition_timestamp(), m.timestamp());
+}
+
+/// field.id: flatbuffer field ID number
+/// field.optional: bool, whether field is optional or not
+#[test]
+fn test_delivery_guarantee_settings_valid() {
+    for guarantee in [
+        DeliveryGuarantee::BestEffort,
+        DeliveryGuarantee::AtLeastOnce,
+        DeliveryGuarantee::Strongest,
+    ] {
+        assert!(guarantee.producer_settings().validate().is_ok());
+    }
+    assert!(
+        DeliveryGuarantee::Strongest
+            .producer_settings()
+            .enable_idempotence
+    );
+}
+
+#[test]
+fn test_producer_settings_invalid() {
+    let idempotent = DeliveryGuarantee::Strongest.producer_settings();
+
+    let leader_acks = ProducerSettings {
+        acks: Acks::Leader,
+        ..idempotent.clone()
+    };
+    assert!(matches!(
+        leader_acks.validate(),
+        Err(SensorError::ConfigError(_))
+    ));
+
+    let no_retries = ProducerSettings {
+        retries: 0,
+        ..idempotent.clone()
+    };
+    assert!(no_retries.validate().is_err());
+
+    let too_many_in_flight = ProducerSettings {
+        max_in_flight: 6,
+        ..idempotent
+    };
+    assert!(too_many_in_flight.validate().is_err());
+}
+
+/// Sensor that remembers the last sequence number it saw
+struct SequenceSensor {
+    last_sequence: u64,
+    expected_sequence: u64,
+}
+
+#[async_trait::async_trait]
+impl Sensor for SequenceSensor {
+    type SensorMeasurement = TestMeasurement;
+
+    async fn next_measurement(&mut self) -> Result<Option<TestMeasurement>, SensorError> {
+        if self.last_sequence == self.expected_sequence {
+            Ok(None)
+        } else {
+            Err(SensorError::StateError(format!(
+                "expected sequence {}, got {}",
+                self.expected_sequence, self.last_sequence
+            )))
+        }
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(self.last_sequence.to_le_bytes().to_vec())
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let Ok(bytes) = state.try_into() {
+            self.last_sequence = u64::from_le_bytes(bytes);
+        }
+    }
+
+    fn produce_measurement(
+        &self,
+        _measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        Err(redpanda::error::KafkaError::Canceled)
+    }
+}
+
+/// Sensor whose producer queue is always full
+struct FullQueueSensor {
+    attempts: std::cell::Cell<usize>,
+}
+
+#[async_trait::async_trait]
+impl Sensor for FullQueueSensor {
+    type SensorMeasurement = TestMeasurement;
+
+    async fn next_measurement(&mut self) -> Result<Option<TestMeasurement>, SensorError> {
+        Ok(None)
+    }
+
+    fn produce_measurement(
+        &self,
+        _measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        self.attempts.set(self.attempts.get() + 1);
+        Err(redpanda::error::KafkaError::Canceled)
+    }
+}
+
+/// Sensor whose run loop never finishes on its own
+struct ForeverSensor;
+
+#[async_trait::async_trait]
+impl Sensor for ForeverSensor {
+    type SensorMeasurement = TestMeasurement;
+
+    async fn next_measurement(&mut self) -> Result<Option<TestMeasurement>, SensorError> {
+        std::future::pending().await
+    }
+
+    fn produce_measurement(
+        &self,
+        _measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        Err(redpanda::error::KafkaError::Canceled)
+    }
+}
+
+#[tokio::test]
+async fn test_run_until_shutdown() {
+    ForeverSensor.run_until(async {}).await.unwrap();
+
+    let sensor = SequenceSensor {
+        last_sequence: 1,
+        expected_sequence: 2,
+    };
+    assert!(sensor
+        .run_until(std::future::pending::<()>())
+        .await
+        .is_err());
+
+    // Sensors that don't implement next_measurement stop straight away
+    let sensor = RunawaySensor {
+        limiter: RateLimiter::new(50).unwrap(),
+        produced: std::cell::Cell::new(0),
+    };
+    sensor.run().await.unwrap();
+}
+
+#[test]
+fn test_produce_measurements_stops_at_first_error() {
+    let sensor = FullQueueSensor {
+        attempts: std::cell::Cell::new(0),
+    };
+    let now = Utc::now();
+    let batch = vec![
+        TestMeasurement::new("a", now),
+        TestMeasurement::new("b", now),
+        TestMeasurement::new("c", now),
+    ];
+
+    assert!(sensor.produce_measurements(batch).is_err());
+    assert_eq!(sensor.attempts.get(), 1);
+    assert!(sensor.produce_measurements(Vec::new()).unwrap().is_empty());
+}
+
+/// Sensor with a runaway read loop, counting the measurements that get past its rate limiter
+struct RunawaySensor {
+    limiter: RateLimiter,
+    produced: std::cell::Cell<usize>,
+}
+
+#[async_trait::async_trait]
+impl Sensor for RunawaySensor {
+    type SensorMeasurement = TestMeasurement;
+
+    fn produce_measurement(
+        &self,
+        _measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        self.produced.set(self.produced.get() + 1);
+        Err(redpanda::error::KafkaError::Canceled)
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        Some(&self.limiter)
+    }
+}
+
+#[test]
+fn test_produce_measurement_throttled() {
+    assert!(matches!(
+        RateLimiter::new(0),
+        Err(SensorError::ConfigError(_))
+    ));
+
+    let sensor = RunawaySensor {
+        limiter: RateLimiter::new(50).unwrap(),
+        produced: std::cell::Cell::new(0),
+    };
+    let start = std::time::Instant::now();
+    let mut rate_limited = 0;
+    while start.elapsed() < std::time::Duration::from_millis(500) {
+        match sensor.produce_measurement_throttled(TestMeasurement::new("a", Utc::now())) {
+            Err(SensorError::RateLimited(retry_after)) => {
+                assert!(retry_after <= std::time::Duration::from_millis(20));
+                rate_limited += 1;
+            }
+            Err(SensorError::KafkaError(_)) => {}
+            other => panic!("Unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    // A full bucket (one second's worth) plus what refilled over the window
+    let cap = 50.0 + 50.0 * start.elapsed().as_secs_f64();
+    assert!(sensor.produced.get() as f64 <= cap);
+    assert!(sensor.produced.get() >= 50);
+    assert!(rate_limited > 0);
+}
+
+#[tokio::test]
+async fn test_rate_limiter_acquire_waits() {
+    let limiter = RateLimiter::new(100).unwrap();
+    let start = tokio::time::Instant::now();
+    // Drain the initial burst, then the next 20 tokens take at least 200ms to refill
+    for _ in 0..120 {
+        limiter.acquire().await;
+    }
+
+    assert!(start.elapsed() >= std::time::Duration::from_millis(190));
+    assert!(limiter.try_acquire().is_err());
+}
+
+/// Sensor that produces `count` measurements with a MockProducer, numbering their source ids
+struct CountingSensor {
+    producer: MockProducer<TestMeasurement>,
+    ids: std::ops::Range<usize>,
+}
+
+impl CountingSensor {
+    fn new(producer: MockProducer<TestMeasurement>, count: usize) -> Self {
+        CountingSensor {
+            producer,
+            ids: 0..count,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sensor for CountingSensor {
+    type SensorMeasurement = TestMeasurement;
+
+    async fn next_measurement(&mut self) -> Result<Option<TestMeasurement>, SensorError> {
+        tokio::task::yield_now().await;
+        Ok(self
+            .ids
+            .next()
+            .map(|i| TestMeasurement::new(&i.to_string(), Utc::now())))
+    }
+
+    fn produce_measurement(
+        &self,
+        measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        self.producer.produce(measurement)
+    }
+
+    fn producer(&self) -> Option<&redpanda::RedpandaProducer> {
+        Some(self.producer.producer())
+    }
+}
+
+impl MockSensor for CountingSensor {
+    fn mock_producer(&self) -> &MockProducer<TestMeasurement> {
+        &self.producer
+    }
+}
+
+#[tokio::test]
+async fn test_collect_n() {
+    let producer = MockProducer::new().unwrap();
+    let sensor = CountingSensor::new(producer.clone(), 100);
+    let measurements = collect_n(sensor, 5).await.unwrap();
+    let source_ids: Vec<&str> = measurements.iter().map(|m| m.source_id.as_str()).collect();
+    assert_eq!(source_ids, ["0", "1", "2", "3", "4"]);
+
+    // A sensor that finishes early returns everything it produced
+    let producer = MockProducer::new().unwrap();
+    let sensor = CountingSensor::new(producer.clone(), 3);
+    assert_eq!(collect_n(sensor, 5).await.unwrap().len(), 3);
+    assert!(producer.is_empty());
+}
+
+#[tokio::test]
+async fn test_run_flushes_producer() {
+    use redpanda::producer::Producer;
+
+    let producer = MockProducer::new().unwrap();
+    let sensor = CountingSensor::new(producer.clone(), 50);
+    sensor.run().await.unwrap();
+
+    // Every queued measurement was delivered before run returned, though their delivery futures were dropped
+    assert_eq!(producer.len(), 50);
+    assert_eq!(producer.producer().producer.in_flight_count(), 0);
+}
+
+#[tokio::test]
+async fn test_run_until_flushes_producer() {
+    use redpanda::producer::Producer;
+
+    let producer = MockProducer::new().unwrap();
+    let sensor = CountingSensor::new(producer.clone(), usize::MAX);
+    sensor.run_until(producer.wait_for(20)).await.unwrap();
+
+    // Shutdown stopped the loop between reads, and what it had queued was still delivered
+    assert!(producer.len() >= 20);
+    assert_eq!(producer.producer().producer.in_flight_count(), 0);
+}
+
+/// Without a producer there's nothing to flush, which is logged at WARN rather than failing `run`
+#[test]
+fn test_flush_without_producer() {
+    let sensor = ForeverSensor;
+    assert!(sensor.producer().is_none());
+    sensor.flush(std::time::Duration::ZERO).unwrap();
+}
+
+#[tokio::test]
+async fn test_mock_producer_delivers() {
+    let producer = MockProducer::new().unwrap();
+    let delivery = producer
+        .produce(TestMeasurement::new("mock", Utc::now()))
+        .unwrap();
+
+    assert_eq!(producer.len(), 1);
+    assert!(delivery.await.unwrap().is_ok());
+    assert_eq!(producer.take()[0].source_id, "mock");
+    assert!(producer.is_empty());
+}
+
+fn test_state_file(name: &str) -> StateFile {
+    let path =
+        std::env::temp_dir().join(format!("opensensor-{}-{}.state", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    StateFile::new(path)
+}
+
+#[test]
+fn test_state_file_round_trip() {
+    let state_file = test_state_file("round-trip");
+    assert_eq!(state_file.load().unwrap(), None);
+
+    let sensor = SequenceSensor {
+        last_sequence: 42,
+        expected_sequence: 42,
+    };
+    assert!(state_file.persist(&sensor).unwrap());
+
+    let mut restarted = SequenceSensor {
+        last_sequence: 0,
+        expected_sequence: 42,
+    };
+    assert!(state_file.restore(&mut restarted).unwrap());
+    assert_eq!(restarted.last_sequence, 42);
+
+    std::fs::remove_file(state_file.path()).unwrap();
+}
+
+#[tokio::test]
+async fn test_run_resumable() {
+    let state_file = test_state_file("run-resumable");
+    state_file.save(&7u64.to_le_bytes()).unwrap();
+
+    let sensor = SequenceSensor {
+        last_sequence: 0,
+        expected_sequence: 7,
+    };
+    run_resumable(
+        sensor,
+        &state_file,
+        std::time::Duration::from_secs(3600),
+        std::future::pending(),
+    )
+    .await
+    .unwrap();
+
+    std::fs::remove_file(state_file.path()).unwrap();
+}
+
+/// Sensor that produces `remaining` more sequence numbers, `pause` apart, then waits to be shut down
+struct PausingSensor {
+    producer: MockProducer<TestMeasurement>,
+    sequence: u64,
+    remaining: u64,
+    pause: std::time::Duration,
+}
+
+impl PausingSensor {
+    fn new(remaining: u64, pause: std::time::Duration) -> Self {
+        PausingSensor {
+            producer: MockProducer::new().unwrap(),
+            sequence: 0,
+            remaining,
+            pause,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sensor for PausingSensor {
+    type SensorMeasurement = TestMeasurement;
+
+    async fn next_measurement(&mut self) -> Result<Option<TestMeasurement>, SensorError> {
+        if self.remaining == 0 {
+            return std::future::pending().await;
+        }
+        tokio::time::sleep(self.pause).await;
+        self.sequence += 1;
+        self.remaining -= 1;
+        Ok(Some(TestMeasurement::new(
+            &self.sequence.to_string(),
+            Utc::now(),
+        )))
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(self.sequence.to_le_bytes().to_vec())
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let Ok(bytes) = state.try_into() {
+            self.sequence = u64::from_le_bytes(bytes);
+        }
+    }
+
+    fn produce_measurement(
+        &self,
+        measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        self.producer.produce(measurement)
+    }
+
+    fn producer(&self) -> Option<&redpanda::RedpandaProducer> {
+        Some(self.producer.producer())
+    }
+}
+
+#[tokio::test]
+async fn test_run_resumable_shutdown_and_resume() {
+    let state_file = test_state_file("shutdown-resume");
+    let hour = std::time::Duration::from_secs(3600);
+
+    let sensor = PausingSensor::new(10, std::time::Duration::ZERO);
+    let shutdown = tokio::time::sleep(std::time::Duration::from_millis(50));
+    run_resumable(sensor, &state_file, hour, shutdown)
+        .await
+        .unwrap();
+    assert_eq!(
+        state_file.load().unwrap(),
+        Some(10u64.to_le_bytes().to_vec())
+    );
+
+    // Picks up from the saved sequence number rather than starting over
+    let resumed = PausingSensor::new(5, std::time::Duration::ZERO);
+    let shutdown = tokio::time::sleep(std::time::Duration::from_millis(50));
+    run_resumable(resumed, &state_file, hour, shutdown)
+        .await
+        .unwrap();
+    assert_eq!(
+        state_file.load().unwrap(),
+        Some(15u64.to_le_bytes().to_vec())
+    );
+
+    std::fs::remove_file(state_file.path()).unwrap();
+}
+
+#[tokio::test]
+async fn test_run_resumable_saves_on_interval() {
+    let state_file = test_state_file("save-interval");
+    let sensor = PausingSensor::new(3, std::time::Duration::from_millis(10));
+    let producer = sensor.producer.clone();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+
+    let run = run_resumable(
+        sensor,
+        &state_file,
+        std::time::Duration::from_millis(10),
+        async {
+            let _ = stopped.await;
+        },
+    );
+    let check = async {
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+        // Saved while still running, before shutdown, without dropping a measurement mid read
+        let saved = state_file.load().unwrap();
+        stop.send(()).unwrap();
+        saved
+    };
+    let (result, saved) = tokio::join!(run, check);
+
+    result.unwrap();
+    assert_eq!(saved, Some(3u64.to_le_bytes().to_vec()));
+    assert_eq!(producer.len(), 3);
+    std::fs::remove_file(state_file.path()).unwrap();
+}
+
+/// Sensor that reports whatever health it's set to
+struct HealthSensor {
+    health: std::sync::Mutex<SensorHealth>,
+    frames: Option<crate::transducer::FrameBuffer>,
+}
+
+#[async_trait::async_trait]
+impl Sensor for HealthSensor {
+    type SensorMeasurement = TestMeasurement;
+
+    async fn next_measurement(&mut self) -> Result<Option<TestMeasurement>, SensorError> {
+        Ok(None)
+    }
+
+    async fn health_check(&self) -> SensorHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    fn debug_frames(&self) -> Option<crate::transducer::FrameBuffer> {
+        self.frames.clone()
+    }
+
+    fn produce_measurement(
+        &self,
+        _measurement: Self::SensorMeasurement,
+    ) -> Result<redpanda::producer::DeliveryFuture, redpanda::error::KafkaError> {
+        Err(redpanda::error::KafkaError::Canceled)
+    }
+}
+
+/// Full response to `GET path`
+async fn get(address: std::net::SocketAddr, path: &str) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Status line of the response to `GET path`
+async fn probe(address: std::net::SocketAddr, path: &str) -> String {
+    get(address, path)
+        .await
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn test_health_check() {
+    use crate::transducer::{ConnectionStatus, TransducerHealth};
+
+    assert_eq!(ForeverSensor.health_check().await, SensorHealth::Healthy);
+
+    let degraded = SensorHealth::Degraded("Redpanda is unreachable".to_owned());
+    assert!(degraded.is_live() && !degraded.is_ready());
+    assert_eq!(SensorHealth::Healthy.and(degraded.clone()), degraded);
+    let transducer = |status| TransducerHealth {
+        status,
+        ..TransducerHealth::default()
+    };
+    assert!(SensorHealth::from_transducer(&transducer(ConnectionStatus::Unknown)).is_ready());
+    assert!(!SensorHealth::from_transducer(&transducer(ConnectionStatus::Disconnected)).is_live());
+    assert_eq!(
+        degraded.and(SensorHealth::from_transducer(&transducer(
+            ConnectionStatus::Disconnected
+        ))),
+        SensorHealth::Unhealthy("Transducer is disconnected".to_owned())
+    );
+
+    let sensor = std::sync::Arc::new(HealthSensor {
+        health: std::sync::Mutex::new(SensorHealth::Healthy),
+        frames: None,
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve_health_on(sensor.clone(), listener));
+
+    assert_eq!(probe(address, "/healthz").await, "HTTP/1.1 200 OK");
+    assert_eq!(probe(address, "/readyz").await, "HTTP/1.1 200 OK");
+    assert_eq!(probe(address, "/metrics").await, "HTTP/1.1 404 Not Found");
+    assert_eq!(
+        probe(address, "/debug/frames").await,
+        "HTTP/1.1 404 Not Found"
+    );
+
+    *sensor.health.lock().unwrap() =
+        SensorHealth::Degraded("Transducer is reconnecting".to_owned());
+    assert_eq!(probe(address, "/healthz").await, "HTTP/1.1 200 OK");
+    assert_eq!(
+        probe(address, "/readyz").await,
+        "HTTP/1.1 503 Service Unavailable"
+    );
+
+    *sensor.health.lock().unwrap() =
+        SensorHealth::Unhealthy("Transducer is disconnected".to_owned());
+    assert_eq!(
+        probe(address, "/healthz").await,
+        "HTTP/1.1 503 Service Unavailable"
+    );
+
+    // A client that never sends a request doesn't hold up other probes, and is disconnected without a response
+    let mut silent = tokio::net::TcpStream::connect(address).await.unwrap();
+    assert_eq!(
+        probe(address, "/healthz").await,
+        "HTTP/1.1 503 Service Unavailable"
+    );
+    let mut response = Vec::new();
+    let read = tokio::io::AsyncReadExt::read_to_end(&mut silent, &mut response);
+    tokio::time::timeout(std::time::Duration::from_secs(10), read)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(response.is_empty());
+}
+
+#[tokio::test]
+async fn test_serve_debug_frames() {
+    let frames = crate::transducer::FrameBuffer::new(2);
+    let sensor = std::sync::Arc::new(HealthSensor {
+        health: std::sync::Mutex::new(SensorHealth::Healthy),
+        frames: Some(frames.clone()),
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve_health_on(sensor, listener));
+
+    for frame in [&b"!AIV"[..], &[0x00, 0xff], &[0x0a]] {
+        frames.record(frame);
+    }
+
+    // Only the 2 most recent frames are kept
+    let response = get(address, "/debug/frames").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    let body: Vec<&str> = response.split("\r\n\r\n").nth(1).unwrap().lines().collect();
+    assert_eq!(body.len(), 2);
+    assert!(body[0].starts_with("1 ") && body[0].ends_with(" 00 ff"));
+    assert!(body[1].starts_with("2 ") && body[1].ends_with(" 0a"));
+
+    let response = get(address, "/debug/frames/2").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with(&format!("{}\n", frames.get(2).unwrap())));
+    assert_eq!(
+        probe(address, "/debug/frames/0").await,
+        "HTTP/1.1 404 Not Found"
+    );
+    assert_eq!(
+        probe(address, "/debug/frames/latest").await,
+        "HTTP/1.1 404 Not Found"
+    );
 }
 
-/// field.id: flatbuffer field ID number
-/// field.optional: bool, whether field is optional or not
 #[test]
 fn test_reflection() {
     use std::io::Read;
@@ -43,6 +1550,253 @@ fn test_reflection() {
     }
 }
 
+#[test]
+fn test_schema_from_bfbs() {
+    use arrow2::datatypes::{DataType, Field};
+
+    let bfbs = std::fs::read("flatbuffers/simple.bfbs").expect("Failed to read file");
+    let schema = schema_from_bfbs(&bfbs).unwrap();
+
+    // Fields are in declaration order, not the name order reflection stores them in
+    let ints = DataType::List(Box::new(Field::new(LIST_ITEM, DataType::Int32, false)));
+    assert_eq!(
+        schema.fields,
+        vec![
+            Field::new("timestamp_utc_nanos", DataType::Int64, false),
+            Field::new("source_id", DataType::Utf8, true),
+            Field::new("input0", ints.clone(), true),
+            Field::new("input1", ints, true),
+        ]
+    );
+
+    assert!(matches!(
+        schema_from_bfbs(b"not a schema"),
+        Err(ReflectionError::InvalidSchema(_))
+    ));
+}
+
+/// A field of a test reflection schema: name, base type, vector element type, object index, and optional flag
+type BfbsField = (
+    &'static str,
+    reflection::BaseType,
+    reflection::BaseType,
+    i32,
+    bool,
+);
+
+/// Build a `.bfbs` reflection schema from (name, is_struct, fields) objects, rooted at the first object
+fn build_bfbs(objects: &[(&str, bool, &[BfbsField])]) -> Vec<u8> {
+    let mut fbb = FlatBufferBuilder::new();
+    let mut offsets = Vec::new();
+    for (name, is_struct, fields) in objects {
+        let mut field_offsets = Vec::new();
+        for (id, (field_name, base_type, element, index, optional)) in fields.iter().enumerate() {
+            let type_ = reflection::Type::create(
+                &mut fbb,
+                &reflection::TypeArgs {
+                    base_type: *base_type,
+                    element: *element,
+                    index: *index,
+                    ..Default::default()
+                },
+            );
+            let field_name = fbb.create_string(field_name);
+            field_offsets.push(reflection::Field::create(
+                &mut fbb,
+                &reflection::FieldArgs {
+                    name: Some(field_name),
+                    type_: Some(type_),
+                    id: id as u16,
+                    optional: *optional,
+                    ..Default::default()
+                },
+            ));
+        }
+        let fields = fbb.create_vector(&field_offsets);
+        let name = fbb.create_string(name);
+        offsets.push(reflection::Object::create(
+            &mut fbb,
+            &reflection::ObjectArgs {
+                name: Some(name),
+                fields: Some(fields),
+                is_struct: *is_struct,
+                ..Default::default()
+            },
+        ));
+    }
+
+    let root = offsets[0];
+    let objects = fbb.create_vector(&offsets);
+    let enums = fbb.create_vector::<flatbuffers::WIPOffset<reflection::Enum>>(&[]);
+    let schema = reflection::Schema::create(
+        &mut fbb,
+        &reflection::SchemaArgs {
+            objects: Some(objects),
+            enums: Some(enums),
+            root_table: Some(root),
+            ..Default::default()
+        },
+    );
+    reflection::finish_schema_buffer(&mut fbb, schema);
+
+    fbb.finished_data().to_vec()
+}
+
+#[test]
+fn test_schema_from_bfbs_nested() {
+    use arrow2::datatypes::{DataType, Field};
+    use reflection::BaseType;
+
+    let bfbs = build_bfbs(&[
+        (
+            "Track",
+            false,
+            &[
+                ("id", BaseType::ULong, BaseType::None, -1, false),
+                ("quality", BaseType::Float, BaseType::None, -1, true),
+                ("label", BaseType::String, BaseType::None, -1, false),
+                ("position", BaseType::Obj, BaseType::None, 1, true),
+                ("history", BaseType::Vector, BaseType::Obj, 1, true),
+            ],
+        ),
+        (
+            "Point",
+            true,
+            &[
+                ("x", BaseType::Double, BaseType::None, -1, true),
+                ("y", BaseType::Double, BaseType::None, -1, true),
+            ],
+        ),
+    ]);
+    let schema = schema_from_bfbs(&bfbs).unwrap();
+
+    // Struct fields are never null, even if marked optional
+    let point = DataType::Struct(vec![
+        Field::new("x", DataType::Float64, false),
+        Field::new("y", DataType::Float64, false),
+    ]);
+    assert_eq!(
+        schema.fields,
+        vec![
+            Field::new("id", DataType::UInt64, false),
+            Field::new("quality", DataType::Float32, true),
+            Field::new("label", DataType::Utf8, false),
+            Field::new("position", point.clone(), true),
+            Field::new(
+                "history",
+                DataType::List(Box::new(Field::new(LIST_ITEM, point, false))),
+                true
+            ),
+        ]
+    );
+
+    let recursive = build_bfbs(&[(
+        "Node",
+        false,
+        &[("children", BaseType::Vector, BaseType::Obj, 0, true)],
+    )]);
+    assert!(matches!(
+        schema_from_bfbs(&recursive),
+        Err(ReflectionError::RecursiveTable(name)) if name == "Node"
+    ));
+
+    let union = build_bfbs(&[(
+        "Message",
+        false,
+        &[("body", BaseType::Union, BaseType::None, 0, true)],
+    )]);
+    assert!(matches!(
+        schema_from_bfbs(&union),
+        Err(ReflectionError::UnsupportedType { field, .. }) if field == "body"
+    ));
+
+    let missing = build_bfbs(&[(
+        "Track",
+        false,
+        &[("position", BaseType::Obj, BaseType::None, 5, true)],
+    )]);
+    assert!(matches!(
+        schema_from_bfbs(&missing),
+        Err(ReflectionError::MissingObject { index: 5, .. })
+    ));
+}
+
+/// A `Reading` table with a timestamp, a source id, and a vector of values
+fn build_reading() -> Vec<u8> {
+    let mut fbb = FlatBufferBuilder::new();
+    let source_id = fbb.create_string("radar-1");
+    let values = fbb.create_vector(&[1.5f64, 2.5]);
+    let start = fbb.start_table();
+    fbb.push_slot_always::<i64>(4, 1_670_000_000_123_456_789);
+    fbb.push_slot_always(6, source_id);
+    fbb.push_slot_always(8, values);
+    let reading = fbb.end_table(start);
+    fbb.finish_minimal(reading);
+
+    fbb.finished_data().to_vec()
+}
+
+#[test]
+fn test_validate_against_schema() {
+    use reflection::BaseType;
+
+    let reading = build_bfbs(&[(
+        "Reading",
+        false,
+        &[
+            ("timestamp", BaseType::Long, BaseType::None, -1, false),
+            ("source_id", BaseType::String, BaseType::None, -1, true),
+            ("values", BaseType::Vector, BaseType::Double, -1, true),
+        ],
+    )]);
+    validate_against_schema(&build_reading(), &reading).unwrap();
+
+    let key_value = build_bfbs(&[(
+        "KeyValue",
+        false,
+        &[
+            ("key", BaseType::String, BaseType::None, -1, false),
+            ("value", BaseType::String, BaseType::None, -1, true),
+        ],
+    )]);
+    let measurement = TestMeasurement::new("a", Utc::now()).to_bytes();
+    validate_against_schema(&measurement, &key_value).unwrap();
+
+    // The timestamp is an integer, not an offset to a string
+    let string_timestamp = build_bfbs(&[(
+        "Reading",
+        false,
+        &[
+            ("timestamp", BaseType::String, BaseType::None, -1, false),
+            ("source_id", BaseType::String, BaseType::None, -1, true),
+            ("values", BaseType::Vector, BaseType::Double, -1, true),
+        ],
+    )]);
+    assert!(matches!(
+        validate_against_schema(&build_reading(), &string_timestamp),
+        Err(SchemaError::FieldMismatch { field, .. }) if field == "Reading.timestamp"
+    ));
+
+    // The buffer has a third field the schema doesn't know about
+    let older = build_bfbs(&[(
+        "Reading",
+        false,
+        &[
+            ("timestamp", BaseType::Long, BaseType::None, -1, false),
+            ("source_id", BaseType::String, BaseType::None, -1, true),
+        ],
+    )]);
+    assert!(matches!(
+        validate_against_schema(&build_reading(), &older),
+        Err(SchemaError::FieldMismatch { field, .. }) if field == "Reading.#2"
+    ));
+
+    assert!(matches!(
+        validate_against_schema(&measurement, b"not a schema"),
+        Err(SchemaError::InvalidSchema(_))
+    ));
+}
+
 /// Sensor that produces a stream of measurements
 #[async_trait::async_trait]
 trait TestSensor<'a> {
@@ -76,6 +1830,832 @@ async fn test_stream() {
     }
 }
 
+fn at_second(source_id: &str, second: i64) -> TestMeasurement {
+    TestMeasurement::new(source_id, Utc.timestamp_opt(second, 0).unwrap())
+}
+
+fn batch_seconds(rechunked: &[Rechunked<TestMeasurement>]) -> Vec<Vec<i64>> {
+    rechunked
+        .iter()
+        .filter_map(|r| match r {
+            Rechunked::Batch(batch) => Some(
+                batch
+                    .measurements
+                    .iter()
+                    .map(|m| m.timestamp.timestamp())
+                    .collect(),
+            ),
+            Rechunked::Late(_) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_rechunk_by_event_time_windows() {
+    let mut rechunker = EventTimeRechunker::new(
+        std::time::Duration::from_secs(10),
+        std::time::Duration::from_secs(5),
+        LateData::Drop,
+    );
+
+    let mut emitted = Vec::new();
+    for second in [1, 12, 3, 14, 9] {
+        emitted.extend(rechunker.push(at_second("a", second)));
+    }
+    // Watermark is 14 - 5 = 9, so [0, 10) is still open
+    assert!(emitted.is_empty());
+
+    emitted.extend(rechunker.push(at_second("a", 21)));
+    assert_eq!(batch_seconds(&emitted), vec![vec![1, 3, 9]]);
+
+    emitted.extend(rechunker.flush());
+    assert_eq!(
+        batch_seconds(&emitted),
+        vec![vec![1, 3, 9], vec![12, 14], vec![21]]
+    );
+    assert!(rechunker.is_empty());
+}
+
+#[test]
+fn test_rechunk_by_event_time_late_data() {
+    let window = std::time::Duration::from_secs(10);
+    let lateness = std::time::Duration::from_secs(0);
+
+    let mut dropping = EventTimeRechunker::new(window, lateness, LateData::Drop);
+    dropping.push(at_second("a", 25));
+    assert!(dropping.push(at_second("a", 5)).is_empty());
+    assert_eq!(dropping.dropped(), 1);
+
+    let mut redirecting = EventTimeRechunker::new(window, lateness, LateData::Redirect);
+    redirecting.push(at_second("a", 25));
+    assert_eq!(
+        redirecting.push(at_second("a", 5)),
+        vec![Rechunked::Late(at_second("a", 5))]
+    );
+    assert_eq!(redirecting.dropped(), 0);
+}
+
+#[tokio::test]
+async fn test_rechunk_by_event_time_stream() {
+    let measurements = stream! {
+        for second in [1, 11, 2, 25] {
+            yield at_second("a", second);
+        }
+    };
+    let rechunked = rechunk_by_event_time(
+        measurements,
+        std::time::Duration::from_secs(10),
+        std::time::Duration::from_secs(10),
+        LateData::Drop,
+    );
+    let rechunked: Vec<_> = rechunked.collect().await;
+
+    assert_eq!(
+        batch_seconds(&rechunked),
+        vec![vec![1, 2], vec![11], vec![25]]
+    );
+}
+
+#[tokio::test]
+async fn test_take_until_timestamp() {
+    let seconds = |seconds: Vec<i64>| {
+        stream! {
+            for second in seconds {
+                yield at_second("a", second);
+            }
+        }
+    };
+    let end = Utc.timestamp_opt(10, 0).unwrap();
+    let taken = |measurements: Vec<TestMeasurement>| -> Vec<i64> {
+        measurements
+            .iter()
+            .map(|m| m.timestamp.timestamp())
+            .collect()
+    };
+
+    // In order, stops at the first measurement past end
+    let in_order =
+        take_until_timestamp(seconds(vec![8, 10, 11, 9]), end, std::time::Duration::ZERO);
+    assert_eq!(taken(in_order.collect().await), vec![8, 10]);
+
+    // Out of order stragglers within the grace period are still yielded
+    let grace = std::time::Duration::from_secs(5);
+    let out_of_order = take_until_timestamp(seconds(vec![8, 12, 9, 14, 7, 16, 6]), end, grace);
+    assert_eq!(taken(out_of_order.collect().await), vec![8, 9, 7]);
+}
+
+#[test]
+fn test_frame_buffer() {
+    use crate::transducer::{DebugFramesArgs, FrameBuffer};
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        debug: DebugFramesArgs,
+    }
+
+    let off = TestCli::parse_from(["test"]);
+    assert!(off.debug.frame_buffer().is_none());
+    let on = TestCli::parse_from(["test", "--debug-frames", "--debug-frames-capacity", "3"]);
+    let frames = on.debug.frame_buffer().unwrap();
+    assert_eq!(frames.capacity(), 3);
+    assert!(frames.is_empty());
+
+    // Clones share frames, and the oldest are dropped once full
+    let recorder: FrameBuffer = frames.clone();
+    let sequences: Vec<u64> = (0u8..5).map(|i| recorder.record(&[i, 0xab])).collect();
+    assert_eq!(sequences, vec![0, 1, 2, 3, 4]);
+    assert_eq!(frames.len(), 3);
+
+    assert!(frames.get(1).is_none());
+    assert_eq!(frames.get(3).unwrap().bytes, vec![3, 0xab]);
+    assert!(frames.get(5).is_none());
+
+    let recent: Vec<u64> = frames.recent(2).iter().map(|f| f.sequence).collect();
+    assert_eq!(recent, vec![3, 4]);
+    assert_eq!(frames.recent(10).len(), 3);
+    assert_eq!(frames.recent(1)[0].hex(), "04 ab");
+}
+
+/// Transducer that reads measurements synchronously, like a serial port, for testing `listen_blocking`
+struct BlockingTestTransducer {
+    readings: Vec<i64>,
+    tx: tokio::sync::mpsc::Sender<TestMeasurement>,
+    rx: Option<tokio::sync::mpsc::Receiver<TestMeasurement>>,
+}
+
+#[async_trait::async_trait]
+impl crate::Transducer for BlockingTestTransducer {
+    type SensorMeasurement = TestMeasurement;
+    type Error = SensorError;
+
+    fn source_id(&self) -> &str {
+        "serial-0"
+    }
+
+    fn rx(&mut self) -> Option<tokio::sync::mpsc::Receiver<TestMeasurement>> {
+        self.rx.take()
+    }
+
+    fn run_blocking(&mut self) -> Result<(), SensorError> {
+        for second in &self.readings {
+            // Stands in for a blocking read from the device
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            if self
+                .tx
+                .blocking_send(at_second(self.source_id(), *second))
+                .is_err()
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn listen(
+        mut self,
+    ) -> Result<tokio::task::JoinHandle<Result<(), SensorError>>, SensorError> {
+        Ok(tokio::task::spawn_blocking(move || self.run_blocking()))
+    }
+}
+
+#[tokio::test]
+async fn test_transducer_listen_blocking() {
+    use crate::Transducer;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let mut transducer = BlockingTestTransducer {
+        readings: vec![0, 1, 2],
+        tx,
+        rx: Some(rx),
+    };
+    let mut rx = transducer.rx().unwrap();
+
+    let handle = transducer.listen_blocking().unwrap();
+    assert_eq!(handle.thread().name(), Some("transducer-serial-0"));
+
+    // The channel holds one measurement, so the thread blocks on every send until it's received here
+    let mut seconds = Vec::new();
+    while let Some(m) = rx.recv().await {
+        seconds.push(m.timestamp().timestamp());
+    }
+    assert_eq!(seconds, vec![0, 1, 2]);
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_transducer_health() {
+    use crate::sensor::health_topic;
+    use crate::transducer::{ConnectionStatus, HealthReporter, TransducerHealth};
+    use crate::Transducer;
+
+    // Transducers that don't report health are Unknown, reported once
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let transducer = BlockingTestTransducer {
+        readings: vec![],
+        tx,
+        rx: Some(rx),
+    };
+    assert_eq!(transducer.health().status, ConnectionStatus::Unknown);
+    let stream = transducer.health_stream();
+    assert_eq!(*stream.borrow(), TransducerHealth::default());
+    assert!(stream.has_changed().is_err());
+
+    let reporter = HealthReporter::new();
+    let mut stream = reporter.subscribe();
+    let recorder = reporter.clone();
+    recorder.record_read();
+    recorder.record_read();
+    recorder.record_error();
+    recorder.record_dropped(3);
+    assert!(stream.has_changed().unwrap());
+    let health = stream.borrow_and_update().clone();
+    assert_eq!(health, reporter.health());
+    assert_eq!(health.status, ConnectionStatus::Connected);
+    assert!(health.last_read.is_some());
+    assert_eq!((health.reads, health.errors, health.dropped), (2, 1, 3));
+
+    // Setting the same status isn't an update
+    recorder.set_status(ConnectionStatus::Connected);
+    assert!(!stream.has_changed().unwrap());
+    recorder.set_status(ConnectionStatus::Reconnecting);
+    assert!(stream.has_changed().unwrap());
+
+    drop(reporter);
+    drop(recorder);
+    assert!(stream.has_changed().is_err());
+    assert_eq!(stream.borrow().status, ConnectionStatus::Reconnecting);
+
+    let health = TransducerHealth {
+        status: ConnectionStatus::Connected,
+        last_read: Some(Utc.timestamp_opt(0, 0).unwrap()),
+        reads: 10,
+        errors: 1,
+        dropped: 0,
+    };
+    assert_eq!(
+        health.to_json(),
+        r#"{"status":"connected","last_read":"1970-01-01T00:00:00+00:00","reads":10,"errors":1,"dropped":0}"#
+    );
+    assert_eq!(
+        TransducerHealth::default().to_json(),
+        r#"{"status":"unknown","last_read":null,"reads":0,"errors":0,"dropped":0}"#
+    );
+
+    assert_eq!(
+        health_topic("raw.surface.ais"),
+        "derived.surface.ais.health"
+    );
+    assert_eq!(
+        health_topic("derived.surface.ship-segmentation.mask-r-cnn"),
+        "derived.surface.ship-segmentation.mask-r-cnn.health"
+    );
+}
+
+/// Transducer whose connection drops between batches of readings, for testing `listen_with_reconnect`
+struct FlakyTestTransducer {
+    /// Readings sent on each connection, the connection resets after every batch but the last
+    connections: Vec<Vec<i64>>,
+    /// Number of `reconnect` calls that fail before one succeeds
+    failed_reconnects: u32,
+    reconnects: u32,
+    health: crate::transducer::HealthReporter,
+    /// Connection status when each `reconnect` was called
+    statuses: std::sync::Arc<std::sync::Mutex<Vec<crate::transducer::ConnectionStatus>>>,
+    tx: tokio::sync::mpsc::Sender<TestMeasurement>,
+    rx: Option<tokio::sync::mpsc::Receiver<TestMeasurement>>,
+}
+
+#[async_trait::async_trait]
+impl crate::Transducer for FlakyTestTransducer {
+    type SensorMeasurement = TestMeasurement;
+    type Error = std::io::Error;
+
+    fn source_id(&self) -> &str {
+        "socket-0"
+    }
+
+    fn rx(&mut self) -> Option<tokio::sync::mpsc::Receiver<TestMeasurement>> {
+        self.rx.take()
+    }
+
+    async fn run(&mut self) -> Result<(), std::io::Error> {
+        let readings = self.connections.remove(0);
+        for second in readings {
+            if self.tx.send(at_second("socket-0", second)).await.is_err() {
+                return Ok(());
+            }
+        }
+        if self.connections.is_empty() {
+            Ok(())
+        } else {
+            Err(std::io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<(), std::io::Error> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .push(self.health.health().status);
+        self.reconnects += 1;
+        if self.reconnects <= self.failed_reconnects {
+            // Like a transducer that reports its own failures, so the next attempt has to report Reconnecting again
+            self.health
+                .set_status(crate::transducer::ConnectionStatus::Disconnected);
+            Err(std::io::ErrorKind::ConnectionRefused.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn health_reporter(&self) -> Option<crate::transducer::HealthReporter> {
+        Some(self.health.clone())
+    }
+
+    fn is_recoverable(&self, error: &std::io::Error) -> bool {
+        matches!(
+            error.kind(),
+            std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionRefused
+        )
+    }
+
+    async fn listen(
+        mut self,
+    ) -> Result<tokio::task::JoinHandle<Result<(), std::io::Error>>, std::io::Error> {
+        Ok(tokio::spawn(async move { self.run().await }))
+    }
+}
+
+#[test]
+fn test_backoff_policy_delay() {
+    use crate::transducer::BackoffPolicy;
+    use std::time::Duration;
+
+    let policy = BackoffPolicy {
+        max_attempts: 5,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(10),
+        jitter: false,
+        reset_after: Duration::from_secs(60),
+    };
+    assert_eq!(policy.delay(0), Duration::from_secs(1));
+    assert_eq!(policy.delay(2), Duration::from_secs(4));
+    assert_eq!(policy.delay(4), Duration::from_secs(10));
+    assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+
+    let jittered = BackoffPolicy {
+        jitter: true,
+        ..policy
+    };
+    for _ in 0..100 {
+        let delay = jittered.delay(2);
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+    }
+}
+
+#[tokio::test]
+async fn test_transducer_listen_with_reconnect() {
+    use crate::transducer::{BackoffPolicy, ConnectionStatus, HealthReporter};
+    use crate::Transducer;
+    use std::time::Duration;
+
+    let policy = BackoffPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(1),
+        jitter: false,
+        reset_after: Duration::ZERO,
+    };
+
+    // The connection drops twice, and the first drop takes two reconnects to recover from
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let mut transducer = FlakyTestTransducer {
+        connections: vec![vec![0, 1], vec![2], vec![3, 4]],
+        failed_reconnects: 1,
+        reconnects: 0,
+        health: HealthReporter::new(),
+        statuses: Default::default(),
+        tx,
+        rx: Some(rx),
+    };
+    let mut rx = transducer.rx().unwrap();
+    let statuses = transducer.statuses.clone();
+    let health = transducer.health.clone();
+    let handle = transducer.listen_with_reconnect(policy).await.unwrap();
+
+    let mut seconds = Vec::new();
+    while let Some(m) = rx.recv().await {
+        seconds.push(m.timestamp().timestamp());
+    }
+    assert_eq!(seconds, vec![0, 1, 2, 3, 4]);
+    handle.await.unwrap().unwrap();
+    // Every reconnect comes after the status is set to Reconnecting
+    assert_eq!(
+        *statuses.lock().unwrap(),
+        vec![ConnectionStatus::Reconnecting; 3]
+    );
+    assert_eq!(health.health().status, ConnectionStatus::Connected);
+
+    // A connection that drops straight after every reconnect doesn't start over, so it still gives up
+    let flapping = |reset_after| {
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let transducer = FlakyTestTransducer {
+            connections: vec![vec![0], vec![1], vec![2], vec![3]],
+            failed_reconnects: 0,
+            reconnects: 0,
+            health: HealthReporter::new(),
+            statuses: Default::default(),
+            tx,
+            rx: Some(rx),
+        };
+        let policy = BackoffPolicy {
+            max_attempts: 2,
+            reset_after,
+            ..policy
+        };
+        (transducer, policy)
+    };
+    let (mut transducer, flapping_policy) = flapping(Duration::from_secs(3600));
+    let mut rx = transducer.rx().unwrap();
+    let handle = transducer
+        .listen_with_reconnect(flapping_policy)
+        .await
+        .unwrap();
+    let mut seconds = Vec::new();
+    while let Some(m) = rx.recv().await {
+        seconds.push(m.timestamp().timestamp());
+    }
+    assert_eq!(seconds, vec![0, 1, 2]);
+    let error = handle.await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+
+    // Connections that stay up long enough start over every time
+    let (mut transducer, flapping_policy) = flapping(Duration::ZERO);
+    let mut rx = transducer.rx().unwrap();
+    let handle = transducer
+        .listen_with_reconnect(flapping_policy)
+        .await
+        .unwrap();
+    let mut seconds = Vec::new();
+    while let Some(m) = rx.recv().await {
+        seconds.push(m.timestamp().timestamp());
+    }
+    assert_eq!(seconds, vec![0, 1, 2, 3]);
+    handle.await.unwrap().unwrap();
+
+    // Gives up after max_attempts failed reconnects in a row
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let mut transducer = FlakyTestTransducer {
+        connections: vec![vec![0], vec![1]],
+        failed_reconnects: u32::MAX,
+        reconnects: 0,
+        health: HealthReporter::new(),
+        statuses: Default::default(),
+        tx,
+        rx: Some(rx),
+    };
+    let mut rx = transducer.rx().unwrap();
+    let health = transducer.health.clone();
+    let handle = transducer.listen_with_reconnect(policy).await.unwrap();
+
+    assert_eq!(rx.recv().await.unwrap().timestamp().timestamp(), 0);
+    let error = handle.await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+    assert!(rx.recv().await.is_none());
+    assert_eq!(health.health().status, ConnectionStatus::Disconnected);
+}
+
+#[tokio::test]
+async fn test_measurement_channel_backpressure() {
+    use crate::transducer::{BackpressureMode, ChannelArgs, DEFAULT_CHANNEL_CAPACITY};
+    use clap::Parser;
+    use std::time::Duration;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        channel: ChannelArgs,
+    }
+
+    let default = TestCli::parse_from(["test"]);
+    assert_eq!(default.channel.channel_capacity, DEFAULT_CHANNEL_CAPACITY);
+    assert_eq!(default.channel.backpressure, BackpressureMode::Block);
+
+    // Block: a full channel holds the read loop until the Sensor receives
+    let cli = TestCli::parse_from(["test", "--channel-capacity", "2"]);
+    let (tx, mut rx) = cli.channel.channel();
+    tx.send(at_second("socket-0", 0)).await.unwrap();
+    tx.send(at_second("socket-0", 1)).await.unwrap();
+    let full = tokio::time::timeout(Duration::from_millis(10), tx.send(at_second("socket-0", 2)));
+    assert!(full.await.is_err());
+    assert_eq!(rx.recv().await.unwrap().timestamp().timestamp(), 0);
+    tx.send(at_second("socket-0", 2)).await.unwrap();
+    drop(tx);
+
+    let mut seconds = Vec::new();
+    while let Some(m) = rx.recv().await {
+        seconds.push(m.timestamp().timestamp());
+    }
+    assert_eq!(seconds, vec![1, 2]);
+
+    // DropOldest: sends never wait, the Sensor gets the most recent measurements
+    let cli = TestCli::parse_from([
+        "test",
+        "--channel-capacity",
+        "2",
+        "--backpressure",
+        "drop-oldest",
+    ]);
+    let (tx, mut rx) = cli.channel.channel();
+    for second in 0..5 {
+        tx.send(at_second("socket-0", second)).await.unwrap();
+    }
+    assert_eq!(tx.dropped(), 3);
+    let sender = tx.clone();
+    drop(tx);
+    assert_eq!(rx.recv().await.unwrap().timestamp().timestamp(), 3);
+    sender.send(at_second("socket-0", 5)).await.unwrap();
+    drop(sender);
+
+    let mut seconds = Vec::new();
+    while let Some(m) = rx.recv().await {
+        seconds.push(m.timestamp().timestamp());
+    }
+    assert_eq!(seconds, vec![4, 5]);
+
+    // Sends fail once the Sensor drops the receiver, in either mode
+    for mode in [BackpressureMode::Block, BackpressureMode::DropOldest] {
+        let (tx, rx) = crate::transducer::measurement_channel(1, mode);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert!(tx.send(at_second("socket-0", 0)).await.is_err());
+    }
+}
+
+#[test]
+fn test_timestamp_source() {
+    use crate::measurement::{
+        stamp_timestamp, to_message_stamped, TimestampClock, TimestampSource,
+        DEFAULT_MAX_CLOCK_SKEW, SCHEMA_VERSION_HEADER, TIMESTAMP_SOURCE_HEADER,
+    };
+    use redpanda::message::Headers;
+    use redpanda::producer::FutureRecord;
+    use std::time::Duration;
+
+    assert_eq!("sensor".parse(), Ok(TimestampSource::Sensor));
+    assert_eq!("producer".parse(), Ok(TimestampSource::Producer));
+    assert_eq!(
+        "producer_if_skewed".parse(),
+        Ok(TimestampSource::ProducerIfSkewed(DEFAULT_MAX_CLOCK_SKEW))
+    );
+    assert_eq!(
+        "producer_if_skewed=500ms".parse(),
+        Ok(TimestampSource::ProducerIfSkewed(Duration::from_millis(
+            500
+        )))
+    );
+    assert!("gps".parse::<TimestampSource>().is_err());
+    assert!("producer=1s".parse::<TimestampSource>().is_err());
+
+    let producer = Utc.timestamp_opt(100, 0).unwrap();
+    let skewed = TimestampSource::ProducerIfSkewed(Duration::from_secs(2));
+    for (sensor_secs, expected) in [
+        (99, TimestampClock::Sensor),
+        (102, TimestampClock::Sensor),
+        (97, TimestampClock::Producer),
+        (103, TimestampClock::Producer),
+    ] {
+        let sensor = Utc.timestamp_opt(sensor_secs, 0).unwrap();
+        assert_eq!(skewed.select(sensor, producer), expected, "{}", sensor_secs);
+    }
+    let sensor = Utc.timestamp_opt(0, 0).unwrap();
+    assert_eq!(
+        TimestampSource::Sensor.select(sensor, producer),
+        TimestampClock::Sensor
+    );
+    assert_eq!(
+        TimestampSource::Producer.select(sensor, producer),
+        TimestampClock::Producer
+    );
+
+    // Stamping with the producer clock replaces the sensor's timestamp
+    let mut measurement = TestMeasurement::new("radar-1", sensor);
+    assert_eq!(
+        stamp_timestamp(&mut measurement, TimestampSource::Sensor),
+        TimestampClock::Sensor
+    );
+    assert_eq!(measurement.timestamp, sensor);
+    assert_eq!(
+        stamp_timestamp(&mut measurement, TimestampSource::Producer),
+        TimestampClock::Producer
+    );
+    assert!(measurement.timestamp > sensor);
+
+    // The clock used is recorded in a header
+    let record = to_message_stamped(
+        TestMeasurement::new("radar-1", sensor),
+        TimestampSource::ProducerIfSkewed(DEFAULT_MAX_CLOCK_SKEW),
+    );
+    let record = FutureRecord::from(&record);
+    let headers = record.headers.unwrap();
+    let header = headers.get(0);
+    assert_eq!(header.key, TIMESTAMP_SOURCE_HEADER);
+    assert_eq!(header.value, Some("producer".as_bytes()));
+    assert_eq!(headers.get(1).key, SCHEMA_VERSION_HEADER);
+    let measurement = TestMeasurement::from_bytes(record.payload.unwrap()).unwrap();
+    assert!(measurement.timestamp > sensor);
+}
+
+/// TestMeasurement whose timestamp may be at most a minute from its record's, rejecting or warning about the rest
+#[derive(Debug, Clone, PartialEq)]
+struct SkewCheckedMeasurement<const WARN: bool>(TestMeasurement);
+
+impl<const WARN: bool> FlatBufferMeasurement for SkewCheckedMeasurement<WARN> {
+    type Error = TestMeasurementError;
+
+    fn serialize_into(self, fbb: &mut FlatBufferBuilder) {
+        self.0.serialize_into(fbb);
+    }
+
+    fn from_flatbuffer(bytes: &[u8]) -> Result<Self, Self::Error> {
+        TestMeasurement::from_flatbuffer(bytes).map(SkewCheckedMeasurement)
+    }
+}
+
+impl<'a, const WARN: bool> Measurement<'a> for SkewCheckedMeasurement<WARN> {
+    type Error = TestMeasurementError;
+    type Codec = FlatBufferCodec;
+
+    const TOPIC_NAME: &'static str = "raw.test.skew-checked";
+    const MAX_CLOCK_SKEW: Option<std::time::Duration> = Some(std::time::Duration::from_secs(60));
+    const CLOCK_SKEW_ACTION: measurement::ClockSkewAction = if WARN {
+        measurement::ClockSkewAction::Warn
+    } else {
+        measurement::ClockSkewAction::Reject
+    };
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.0.timestamp
+    }
+
+    fn source_id(&self) -> &str {
+        &self.0.source_id
+    }
+}
+
+#[test]
+fn test_clock_skew() {
+    use crate::measurement::{check_clock_skew, ClockSkew};
+    use redpanda::message::Timestamp;
+    use std::time::Duration;
+
+    let record = Utc.timestamp_opt(1_000, 0).unwrap();
+    let record_timestamp = Timestamp::LogAppendTime(record.timestamp_millis());
+    let at = |secs: i64| TestMeasurement::new("gps-1", Utc.timestamp_opt(secs, 0).unwrap());
+
+    // In range, either side of the record's timestamp
+    for secs in [940, 1_000, 1_060] {
+        let measurement = SkewCheckedMeasurement::<false>(at(secs));
+        assert!(
+            check_clock_skew(&measurement, record_timestamp).is_ok(),
+            "{}",
+            secs
+        );
+    }
+
+    // Out of range, either side
+    for secs in [939, 1_061, 0] {
+        let measurement = SkewCheckedMeasurement::<false>(at(secs));
+        match check_clock_skew(&measurement, record_timestamp) {
+            Err(TestMeasurementError::ClockSkew(skew)) => {
+                assert_eq!(
+                    skew,
+                    ClockSkew {
+                        measured: measurement.0.timestamp,
+                        record,
+                        max_skew: Duration::from_secs(60),
+                    }
+                );
+                assert_eq!(
+                    skew.skew(),
+                    Duration::from_secs((1_000 - secs).unsigned_abs())
+                );
+            }
+            other => panic!("expected a clock skew error for {}, got {:?}", secs, other),
+        }
+        // Create time is checked the same way, and records without a timestamp can't be
+        let create_time = Timestamp::CreateTime(record.timestamp_millis());
+        assert!(check_clock_skew(&measurement, create_time).is_err());
+        assert!(check_clock_skew(&measurement, Timestamp::NotAvailable).is_ok());
+
+        // Warning keeps the measurement, and measurements without a max skew aren't checked
+        let warned = SkewCheckedMeasurement::<true>(at(secs));
+        assert!(check_clock_skew(&warned, record_timestamp).is_ok());
+        assert!(check_clock_skew(&at(secs), record_timestamp).is_ok());
+    }
+}
+
+#[test]
+fn test_record_meta() {
+    use crate::measurement::RecordMeta;
+    use redpanda::message::{OwnedMessage, Timestamp};
+
+    let produced = Utc.timestamp_millis_opt(1_670_000_000_123).unwrap();
+    let measurement = TestMeasurement::new("gps-1", produced);
+    let message = OwnedMessage::new(
+        Some(measurement.to_bytes()),
+        Some(b"gps-1".to_vec()),
+        "gps".to_owned(),
+        Timestamp::CreateTime(produced.timestamp_millis()),
+        7,
+        1_234,
+        None,
+    );
+
+    let meta = RecordMeta::from_message(&message);
+    assert_eq!(
+        meta,
+        RecordMeta {
+            topic: "gps".to_owned(),
+            partition: 7,
+            offset: 1_234,
+            timestamp: Timestamp::CreateTime(1_670_000_000_123),
+            key: Some(b"gps-1".to_vec()),
+        }
+    );
+    assert_eq!(meta.timestamp_utc(), Some(produced));
+
+    // Broker timestamps convert the same way, and records without one have no UTC timestamp
+    let appended = RecordMeta {
+        timestamp: Timestamp::LogAppendTime(1_670_000_000_123),
+        ..meta.clone()
+    };
+    assert_eq!(appended.timestamp_utc(), Some(produced));
+    let untimed = RecordMeta {
+        timestamp: Timestamp::NotAvailable,
+        ..meta
+    };
+    assert_eq!(untimed.timestamp_utc(), None);
+}
+
+/// `from_message_with_meta` keeps where a consumed measurement came from. Needs the Redpanda cluster from
+/// docker-compose.yaml
+#[tokio::test]
+async fn test_from_message_with_meta() {
+    use redpanda::producer::RedpandaRecord;
+
+    let kafka_addresses = "127.0.0.1:9010";
+    let topic = format!(
+        "record-meta-{}-{}",
+        std::process::id(),
+        Utc::now().timestamp_millis()
+    );
+
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    let producer = builder.build_producer().unwrap();
+    let measurement = TestMeasurement::new("gps-1", Utc::now());
+    let before = Utc::now();
+    let record = RedpandaRecord::new(
+        &topic,
+        Some(b"gps-1".to_vec()),
+        measurement.clone().to_bytes(),
+        None,
+    );
+    let (partition, offset) = producer
+        .send_result(&record)
+        .map_err(|(e, _)| e)
+        .unwrap()
+        .await
+        .unwrap()
+        .unwrap();
+    let after = Utc::now();
+
+    let mut builder = redpanda::RedpandaBuilder::default();
+    builder.set_bootstrap_servers(kafka_addresses);
+    builder.set_group_id(&format!("{}-group", topic));
+    builder.set("auto.offset.reset", "earliest");
+    let consumer = builder.build_consumer().unwrap();
+    consumer.subscribe(&[&topic]).unwrap();
+    let mut stream = consumer.stream();
+    let message = tokio::time::timeout(std::time::Duration::from_secs(30), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    let (consumed, meta) = TestMeasurement::from_message_with_meta(message).unwrap();
+    assert_eq!(consumed, measurement);
+    assert_eq!(meta.topic, topic);
+    assert_eq!(meta.partition, partition);
+    assert_eq!(meta.offset, offset);
+    assert_eq!(meta.key.as_deref(), Some(&b"gps-1"[..]));
+    let timestamp = meta.timestamp_utc().unwrap();
+    assert!(timestamp >= before - chrono::Duration::milliseconds(1) && timestamp <= after);
+}
+
 trait TestConst {
     const SENSOR_NAME: &'static str;
 }
@@ -1,8 +1,19 @@
 //! Generic OpenSensor Transducer for abstracting away hardware-specific sensor implementation details from Sensors
 
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use crate::measurement::Measurement;
 use async_trait::async_trait;
-use tokio::{sync::mpsc::Receiver, task::JoinHandle};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use tokio::sync::mpsc::{self, error::SendError, Receiver, Sender};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
 
 /// Transducer that handles hardware-specific communications (serial port, network socket, etc)
 #[async_trait]
@@ -23,13 +34,744 @@ pub trait Transducer {
     ///
     /// This is an Option because there can only be one copy of an mpsc::Receiver. So attempts to call this
     /// after the single instance of the Receiver has been returned will result in None
+    ///
+    /// The channel is bounded so a fast Transducer can't exhaust memory when the Sensor falls behind. Create it
+    /// with `ChannelArgs::channel` (or `measurement_channel`) so operators can tune the capacity and what happens
+    /// when it's full (see `BackpressureMode`) per deployment.
     fn rx(&mut self) -> Option<Receiver<Self::SensorMeasurement>>;
 
+    /// Buffer of the most recent raw frames the Transducer read, if `--debug-frames` is on
+    ///
+    /// Transducers that support it keep a `FrameBuffer` built from `DebugFramesArgs::frame_buffer` and call
+    /// `FrameBuffer::record` with each raw frame before parsing it. Operators can then see the bytes a suspicious
+    /// measurement came from without a full raw capture, at `/debug/frames` on the Sensor's health server (see
+    /// `Sensor::debug_frames`).
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, the Transducer doesn't keep raw frames
+    fn debug_frames(&self) -> Option<FrameBuffer> {
+        None
+    }
+
+    /// Current health of the Transducer: connection status, when it last read from the hardware, and error counts
+    ///
+    /// Transducers that support it keep a `HealthReporter`, update it from their read loop, and return
+    /// `HealthReporter::health` here.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns `TransducerHealth::default()`, with `ConnectionStatus::Unknown`
+    fn health(&self) -> TransducerHealth {
+        TransducerHealth::default()
+    }
+
+    /// Reporter the Transducer updates its health with, for `listen_with_reconnect` to report reconnects through
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns None, reconnects are only logged
+    fn health_reporter(&self) -> Option<HealthReporter> {
+        None
+    }
+
+    /// Updates to the Transducer's health, for the Sensor to forward to its health topic with
+    /// `sensor::forward_health`
+    ///
+    /// Call this before `listen`, which consumes the Transducer. The receiver keeps working after that, and closes
+    /// once the Transducer (and every clone of its `HealthReporter`) is dropped.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns a receiver holding `health()` that is already closed, so the Sensor reports it once
+    fn health_stream(&self) -> watch::Receiver<TransducerHealth> {
+        watch::channel(self.health()).1
+    }
+
     /// Spawn the main loop of transducer, returning the join handle for the an error if it fails in a way that is unrecoverable
     ///
-    /// Currently, the return type of tokio::runtime::task::JoinHandle indicates that we intend this method to call tokio::spawn()
-    /// on an async inner loop that reads from the physical interface to the sensor/simulator. This might be suboptimal because
-    /// if the loop involves any significant compute, we could end up blocking the tokio async executor. It might be worth
-    /// reimplementing this to spawn a thread or fork a process?
+    /// The loop runs as a task on the tokio executor, so it must only await I/O (network sockets, async serial
+    /// ports). A loop that does synchronous reads or significant compute between awaits blocks the executor thread
+    /// it's on and starves every other task, including the Sensor producing the measurements. Use `listen_blocking`
+    /// for those.
     async fn listen(mut self) -> Result<JoinHandle<Result<(), Self::Error>>, Self::Error>;
+
+    /// Blocking main loop of the transducer, for hardware that is read synchronously (i.e. serial ports, USB)
+    ///
+    /// Read from the hardware and send each measurement to the channel returned by `rx` with
+    /// `Sender::blocking_send`, returning once the device closes, the receiver is dropped, or reading fails in a way
+    /// that is unrecoverable. Only call this through `listen_blocking`: it blocks, so it must not run on the async
+    /// executor.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns Ok(()) immediately, for Transducers that only support `listen`
+    fn run_blocking(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Spawn `run_blocking` on a dedicated OS thread named after the `source_id`, returning its join handle
+    ///
+    /// The alternative to `listen` for transducers that do synchronous reads: the thread can block on the device as
+    /// long as it needs without starving tasks on the async executor. Measurements reach the Sensor through the same
+    /// `rx` channel. Prefer `listen` for async I/O, since a thread per transducer costs more than a task.
+    ///
+    /// Override this to open the device before spawning, so a missing device is reported as an error instead of
+    /// ending the thread.
+    ///
+    /// # Panics
+    ///
+    /// - If the OS fails to spawn the thread, like `std::thread::spawn`
+    fn listen_blocking(
+        mut self,
+    ) -> Result<std::thread::JoinHandle<Result<(), Self::Error>>, Self::Error>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: 'static,
+    {
+        let handle = std::thread::Builder::new()
+            .name(format!("transducer-{}", self.source_id()))
+            .spawn(move || self.run_blocking())
+            .expect("failed to spawn transducer thread");
+
+        Ok(handle)
+    }
+
+    /// Main loop of the transducer for a single connection, for `listen_with_reconnect`
+    ///
+    /// Read from the interface and send each measurement to the channel returned by `rx`, returning Ok once the
+    /// device closes or the receiver is dropped, or the error that ended the connection. The sender must live in
+    /// the transducer rather than in this future, so the Sensor's receiver stays open across reconnects.
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns Ok(()) immediately, for Transducers that only support `listen`
+    async fn run(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Re-establish the interface (reopen the socket or serial port) after `run` failed with a recoverable error
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns Ok(()), for Transducers whose `run` opens the interface itself
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether an error from `run` or `reconnect` is worth reconnecting after, i.e. a dropped socket or an unplugged
+    /// serial adapter, as opposed to bad configuration
+    ///
+    /// ## Default Implementation
+    ///
+    /// Returns false, no errors are recoverable
+    fn is_recoverable(&self, _error: &Self::Error) -> bool {
+        false
+    }
+
+    /// Spawn `run` as a task, reconnecting with backoff when it fails with a recoverable error
+    ///
+    /// After a recoverable error the task waits `policy.delay` and calls `reconnect`, then resumes `run` once it
+    /// succeeds. It gives up with the last error after `policy.max_attempts` reconnects in a row, or straight away on
+    /// an unrecoverable error. Attempts only start over from the first once a connection has stayed up for
+    /// `policy.reset_after`, so a link that drops straight after every reconnect still gives up. The transducer (and
+    /// the sender in it) lives for the whole task, so the Sensor keeps receiving from the same `rx` channel and never
+    /// needs to resubscribe.
+    ///
+    /// Before each wait the status on `health_reporter` is set to `ConnectionStatus::Reconnecting`, to
+    /// `ConnectionStatus::Connected` once `reconnect` succeeds, and to `ConnectionStatus::Disconnected` when it gives
+    /// up, so the Sensor's health topic shows the outage.
+    async fn listen_with_reconnect(
+        mut self,
+        policy: BackoffPolicy,
+    ) -> Result<JoinHandle<Result<(), Self::Error>>, Self::Error>
+    where
+        Self: Sized + Send + 'static,
+        Self::Error: 'static,
+    {
+        let health = self.health_reporter();
+        Ok(tokio::spawn(async move {
+            let mut attempt = 0;
+            loop {
+                let connected = tokio::time::Instant::now();
+                let mut error = match self.run().await {
+                    Ok(()) => return Ok(()),
+                    Err(e) => e,
+                };
+
+                if connected.elapsed() >= policy.reset_after {
+                    attempt = 0;
+                }
+                let first_attempt = attempt;
+                loop {
+                    if !self.is_recoverable(&error) || attempt >= policy.max_attempts {
+                        if let Some(health) = &health {
+                            health.set_status(ConnectionStatus::Disconnected);
+                        }
+                        return Err(error);
+                    }
+                    let delay = policy.delay(attempt);
+                    if let Some(health) = &health {
+                        health.set_status(ConnectionStatus::Reconnecting);
+                    }
+                    event!(
+                        Level::WARN,
+                        source_id = self.source_id(),
+                        attempt = attempt + 1,
+                        "Transducer connection failed ({}), reconnecting in {:?}",
+                        error,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+
+                    match self.reconnect().await {
+                        Ok(()) => break,
+                        Err(e) => error = e,
+                    }
+                }
+                if let Some(health) = &health {
+                    health.set_status(ConnectionStatus::Connected);
+                }
+                event!(
+                    Level::INFO,
+                    source_id = self.source_id(),
+                    "Transducer reconnected after {} attempts",
+                    attempt - first_attempt
+                );
+            }
+        }))
+    }
+}
+
+/// How `Transducer::listen_with_reconnect` backs off between reconnect attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    /// Reconnects in a row before giving up, without a connection staying up for `reset_after` in between
+    pub max_attempts: u32,
+    /// Delay before the first reconnect, doubling for each one after
+    pub base_delay: Duration,
+    /// Longest delay between reconnects
+    pub max_delay: Duration,
+    /// Randomize each delay between half and all of it, so sensors that lost the same link don't reconnect in
+    /// lockstep
+    pub jitter: bool,
+    /// How long a connection has to stay up before the next drop starts over from the first attempt
+    pub reset_after: Duration,
+}
+
+impl Default for BackoffPolicy {
+    /// 10 attempts from 1s up to 60s, with jitter, starting over after a minute connected
+    fn default() -> Self {
+        BackoffPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: true,
+            reset_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// Delay before reconnect number `attempt`, counting from 0
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+}
+
+/// Capacity of a Transducer's measurement channel when it isn't configured
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+/// What a Transducer does when the Sensor falls behind and the measurement channel is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackpressureMode {
+    /// Wait for the Sensor to make room, pausing the read loop
+    ///
+    /// No measurement is lost in the channel, but readings back up into the device or OS buffers, which may drop
+    /// them once full.
+    #[default]
+    Block,
+    /// Drop the oldest measurement in the channel to make room, so the read loop never waits and the Sensor always
+    /// gets the most recent readings
+    DropOldest,
+}
+
+/// Command line options for the Transducer's measurement channel, flatten into a Sensor's CLI with
+/// `#[command(flatten)]`
+#[derive(clap::Args, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelArgs {
+    /// Measurements buffered between the transducer and the sensor
+    #[arg(long, default_value_t = DEFAULT_CHANNEL_CAPACITY)]
+    pub channel_capacity: usize,
+
+    /// What the transducer does when the sensor falls behind and the buffer is full
+    #[arg(long, value_enum, default_value_t = BackpressureMode::Block)]
+    pub backpressure: BackpressureMode,
+}
+
+impl ChannelArgs {
+    /// Measurement channel with the configured capacity and backpressure mode, see `measurement_channel`
+    pub fn channel<M>(&self) -> (MeasurementSender<M>, Receiver<M>)
+    where
+        M: Send + 'static,
+    {
+        measurement_channel(self.channel_capacity, self.backpressure)
+    }
+}
+
+/// Bounded channel from a Transducer to its Sensor, the Transducer keeps the sender and returns the receiver from
+/// `rx`
+///
+/// `capacity` is clamped to at least 1. With `BackpressureMode::Block` this is a plain `mpsc::channel`. With
+/// `BackpressureMode::DropOldest` measurements are buffered in a ring of `capacity` measurements that a background
+/// task moves to the receiver, so one more measurement can be waiting in the channel itself.
+///
+/// # Panics
+///
+/// - With `BackpressureMode::DropOldest`, if called outside a tokio runtime, like `tokio::spawn`
+pub fn measurement_channel<M>(
+    capacity: usize,
+    mode: BackpressureMode,
+) -> (MeasurementSender<M>, Receiver<M>)
+where
+    M: Send + 'static,
+{
+    let capacity = capacity.max(1);
+    match mode {
+        BackpressureMode::Block => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (
+                MeasurementSender {
+                    tx,
+                    ring: None,
+                    dropped: Arc::new(AtomicU64::new(0)),
+                },
+                rx,
+            )
+        }
+        BackpressureMode::DropOldest => {
+            let (tx, rx) = mpsc::channel(1);
+            let ring = Arc::new(Ring {
+                capacity,
+                queue: Mutex::new(VecDeque::with_capacity(capacity)),
+                senders: AtomicUsize::new(1),
+                notify: Notify::new(),
+            });
+            tokio::spawn(forward_ring(ring.clone(), tx.clone()));
+            (
+                MeasurementSender {
+                    tx,
+                    ring: Some(ring),
+                    dropped: Arc::new(AtomicU64::new(0)),
+                },
+                rx,
+            )
+        }
+    }
+}
+
+/// Sending half of a `measurement_channel`, applying its `BackpressureMode` when the channel is full
+///
+/// The channel closes once every clone of the sender is dropped, after the Sensor has received any measurements
+/// still buffered.
+#[derive(Debug)]
+pub struct MeasurementSender<M> {
+    tx: Sender<M>,
+    /// Ring buffer for `BackpressureMode::DropOldest`
+    ring: Option<Arc<Ring<M>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct Ring<M> {
+    capacity: usize,
+    queue: Mutex<VecDeque<M>>,
+    /// Number of live `MeasurementSender`s, the forwarding task exits once it's 0 and the queue is empty
+    senders: AtomicUsize,
+    notify: Notify,
+}
+
+impl<M> MeasurementSender<M> {
+    /// Send a measurement to the Sensor, waiting for room with `BackpressureMode::Block`
+    ///
+    /// # Errors
+    ///
+    /// - SendError: If the Sensor dropped the receiver, returning the measurement
+    pub async fn send(&self, measurement: M) -> Result<(), SendError<M>> {
+        match &self.ring {
+            None => self.tx.send(measurement).await,
+            Some(ring) => self.push(ring, measurement),
+        }
+    }
+
+    /// Send a measurement from a synchronous read loop, i.e. `Transducer::run_blocking`, like `send`
+    ///
+    /// # Panics
+    ///
+    /// - With `BackpressureMode::Block`, if called on the async executor, like `Sender::blocking_send`
+    pub fn blocking_send(&self, measurement: M) -> Result<(), SendError<M>> {
+        match &self.ring {
+            None => self.tx.blocking_send(measurement),
+            Some(ring) => self.push(ring, measurement),
+        }
+    }
+
+    /// Number of measurements dropped to make room with `BackpressureMode::DropOldest`, across every clone
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Whether the Sensor dropped the receiver
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    fn push(&self, ring: &Ring<M>, measurement: M) -> Result<(), SendError<M>> {
+        if self.tx.is_closed() {
+            return Err(SendError(measurement));
+        }
+
+        let mut queue = ring.queue.lock().unwrap();
+        if queue.len() >= ring.capacity {
+            queue.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        queue.push_back(measurement);
+        drop(queue);
+        ring.notify.notify_one();
+
+        Ok(())
+    }
+}
+
+impl<M> Clone for MeasurementSender<M> {
+    fn clone(&self) -> Self {
+        if let Some(ring) = &self.ring {
+            ring.senders.fetch_add(1, Ordering::AcqRel);
+        }
+        MeasurementSender {
+            tx: self.tx.clone(),
+            ring: self.ring.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<M> Drop for MeasurementSender<M> {
+    fn drop(&mut self) {
+        if let Some(ring) = &self.ring {
+            ring.senders.fetch_sub(1, Ordering::AcqRel);
+            ring.notify.notify_one();
+        }
+    }
+}
+
+/// Move measurements from a `BackpressureMode::DropOldest` ring to the channel as the Sensor makes room
+///
+/// Reserving room before taking a measurement from the ring means measurements only leave the ring once the channel
+/// can take them, so the ring keeps dropping the oldest while the Sensor is behind.
+async fn forward_ring<M>(ring: Arc<Ring<M>>, tx: Sender<M>) {
+    loop {
+        let permit = match tx.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => return,
+        };
+        loop {
+            let next = ring.queue.lock().unwrap().pop_front();
+            match next {
+                Some(measurement) => {
+                    permit.send(measurement);
+                    break;
+                }
+                None if ring.senders.load(Ordering::Acquire) == 0 => return,
+                None => ring.notify.notified().await,
+            }
+        }
+    }
+}
+
+/// Command line options for keeping raw frames for debugging, flatten into a Sensor's CLI with `#[command(flatten)]`
+#[derive(clap::Args, Clone, Debug, PartialEq, Eq)]
+pub struct DebugFramesArgs {
+    /// Keep the most recent raw frames the transducer read, to debug suspicious measurements
+    #[arg(long)]
+    pub debug_frames: bool,
+
+    /// How many raw frames to keep with --debug-frames
+    #[arg(long, value_name = "DEBUG_FRAMES_CAPACITY", default_value_t = 1024)]
+    pub debug_frames_capacity: usize,
+}
+
+impl DebugFramesArgs {
+    /// Frame buffer for the Transducer to record to, None if --debug-frames is off
+    pub fn frame_buffer(&self) -> Option<FrameBuffer> {
+        self.debug_frames
+            .then(|| FrameBuffer::new(self.debug_frames_capacity))
+    }
+}
+
+/// A raw frame read by a Transducer, before it was parsed into a measurement
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawFrame {
+    /// Sequence number of the frame, counting every frame recorded to the buffer
+    pub sequence: u64,
+    /// When the frame was recorded
+    pub received: DateTime<Utc>,
+    /// Raw bytes of the frame
+    pub bytes: Vec<u8>,
+}
+
+impl RawFrame {
+    /// Frame bytes as space separated hex, i.e. "21 41 49 56"
+    pub fn hex(&self) -> String {
+        let mut hex = String::with_capacity(self.bytes.len() * 3);
+        for (i, byte) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                hex.push(' ');
+            }
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        hex
+    }
+}
+
+impl std::fmt::Display for RawFrame {
+    /// Sequence number, RFC 3339 receive time, and hex bytes, i.e. "3 2023-03-01T12:00:00+00:00 21 41 49 56"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.sequence,
+            self.received.to_rfc3339(),
+            self.hex()
+        )
+    }
+}
+
+/// Ring buffer of the most recent raw frames read by a Transducer
+///
+/// Cloning is cheap and every clone shares the same frames, so the Transducer can record to one clone while the
+/// health server's `/debug/frames` handler reads from another.
+#[derive(Clone, Debug)]
+pub struct FrameBuffer {
+    capacity: usize,
+    frames: Arc<Mutex<Frames>>,
+}
+
+#[derive(Debug, Default)]
+struct Frames {
+    next_sequence: u64,
+    frames: VecDeque<RawFrame>,
+}
+
+impl FrameBuffer {
+    /// Buffer keeping the most recent `capacity` frames, clamped to at least 1
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        FrameBuffer {
+            capacity,
+            frames: Arc::new(Mutex::new(Frames {
+                next_sequence: 0,
+                frames: VecDeque::with_capacity(capacity),
+            })),
+        }
+    }
+
+    /// Record a raw frame, dropping the oldest frame if the buffer is full, and return its sequence number
+    ///
+    /// Log the sequence number with parse errors so the frame can be looked up later.
+    pub fn record(&self, bytes: &[u8]) -> u64 {
+        let mut frames = self.frames.lock().unwrap();
+        let sequence = frames.next_sequence;
+        frames.next_sequence += 1;
+
+        if frames.frames.len() >= self.capacity {
+            frames.frames.pop_front();
+        }
+        frames.frames.push_back(RawFrame {
+            sequence,
+            received: Utc::now(),
+            bytes: bytes.to_vec(),
+        });
+
+        sequence
+    }
+
+    /// Max number of frames kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of frames currently kept
+    pub fn len(&self) -> usize {
+        self.frames.lock().unwrap().frames.len()
+    }
+
+    /// Whether no frames are kept
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frame with the sequence number returned by `record`, None if it has been dropped
+    pub fn get(&self, sequence: u64) -> Option<RawFrame> {
+        let frames = self.frames.lock().unwrap();
+        let first = frames.frames.front()?.sequence;
+        let i = sequence.checked_sub(first)?;
+        frames.frames.get(i as usize).cloned()
+    }
+
+    /// The most recent `n` frames, oldest first
+    pub fn recent(&self, n: usize) -> Vec<RawFrame> {
+        let frames = self.frames.lock().unwrap();
+        let skip = frames.frames.len().saturating_sub(n);
+        frames.frames.iter().skip(skip).cloned().collect()
+    }
+
+    /// Log the most recent `n` frames as hex at INFO, oldest first
+    pub fn log_recent(&self, n: usize) {
+        for frame in self.recent(n) {
+            event!(
+                Level::INFO,
+                sequence = frame.sequence,
+                received = ?frame.received,
+                "Raw frame {}",
+                frame.hex()
+            );
+        }
+    }
+}
+
+/// Whether a Transducer is connected to its hardware
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// The Transducer doesn't report its connection status
+    #[default]
+    Unknown,
+    /// Connected and reading
+    Connected,
+    /// Lost the connection and trying to re-establish it, i.e. in `listen_with_reconnect`
+    Reconnecting,
+    /// Not connected, and not trying to be
+    Disconnected,
+}
+
+impl ConnectionStatus {
+    /// Lowercase name of the status, i.e. "connected"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Unknown => "unknown",
+            ConnectionStatus::Connected => "connected",
+            ConnectionStatus::Reconnecting => "reconnecting",
+            ConnectionStatus::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// Health of a Transducer, reported separately from its measurements so operators can monitor liveness without
+/// parsing them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransducerHealth {
+    /// Whether the Transducer is connected to its hardware
+    pub status: ConnectionStatus,
+    /// When the Transducer last read from the hardware, None if it never has
+    pub last_read: Option<DateTime<Utc>>,
+    /// Number of successful reads
+    pub reads: u64,
+    /// Number of failed reads, i.e. I/O errors or frames that didn't parse
+    pub errors: u64,
+    /// Number of measurements or frames dropped, i.e. by `BackpressureMode::DropOldest`
+    pub dropped: u64,
+}
+
+impl TransducerHealth {
+    /// Health as a JSON object, the payload `sensor::forward_health` produces
+    ///
+    /// i.e. `{"status":"connected","last_read":"2023-01-01T00:00:00Z","reads":10,"errors":0,"dropped":0}`, with
+    /// `last_read` null if the Transducer never read.
+    pub fn to_json(&self) -> String {
+        let last_read = match self.last_read {
+            Some(last_read) => format!("\"{}\"", last_read.to_rfc3339()),
+            None => "null".to_owned(),
+        };
+        format!(
+            r#"{{"status":"{}","last_read":{},"reads":{},"errors":{},"dropped":{}}}"#,
+            self.status.as_str(),
+            last_read,
+            self.reads,
+            self.errors,
+            self.dropped
+        )
+    }
+}
+
+/// Health of a Transducer, updated by its read loop and watched by its Sensor
+///
+/// Cloning is cheap and every clone shares the same health, so the read loop can update one clone while the
+/// Transducer returns another from `health`, and `subscribe` from `health_stream`.
+#[derive(Clone, Debug)]
+pub struct HealthReporter {
+    health: Arc<watch::Sender<TransducerHealth>>,
+}
+
+impl Default for HealthReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthReporter {
+    /// Reporter starting with `ConnectionStatus::Unknown` and no reads
+    pub fn new() -> Self {
+        HealthReporter {
+            health: Arc::new(watch::channel(TransducerHealth::default()).0),
+        }
+    }
+
+    /// Current health
+    pub fn health(&self) -> TransducerHealth {
+        self.health.borrow().clone()
+    }
+
+    /// Receiver of every update to the health, for `Transducer::health_stream`
+    pub fn subscribe(&self) -> watch::Receiver<TransducerHealth> {
+        self.health.subscribe()
+    }
+
+    /// Set the connection status
+    pub fn set_status(&self, status: ConnectionStatus) {
+        self.health.send_if_modified(|health| {
+            let modified = health.status != status;
+            health.status = status;
+            modified
+        });
+    }
+
+    /// Record a successful read from the hardware, which also means the Transducer is connected
+    pub fn record_read(&self) {
+        self.health.send_modify(|health| {
+            health.status = ConnectionStatus::Connected;
+            health.last_read = Some(Utc::now());
+            health.reads += 1;
+        });
+    }
+
+    /// Record a failed read
+    pub fn record_error(&self) {
+        self.health.send_modify(|health| health.errors += 1);
+    }
+
+    /// Record `count` dropped measurements or frames
+    pub fn record_dropped(&self, count: u64) {
+        self.health.send_modify(|health| health.dropped += count);
+    }
 }